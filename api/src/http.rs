@@ -17,7 +17,11 @@ use serde_json::{Error as SerdeError, Value};
 #[derive(Clone, Deserialize, Debug)]
 pub struct ApiMountCmd {
     /// Path to source of the filesystem.
+    #[serde(default)]
     pub source: String,
+    /// Id of the RAFS metadata blob to fetch from the storage backend, instead of `source`.
+    #[serde(default)]
+    pub bootstrap_blob_id: Option<String>,
     /// Type of filesystem.
     #[serde(default)]
     pub fs_type: String,
@@ -42,6 +46,49 @@ pub struct DaemonConf {
     pub log_level: String,
 }
 
+/// Configuration information for request level tracing.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TraceConfig {
+    /// Whether to trace FUSE requests.
+    pub enable: bool,
+    /// Log traced requests taking longer than the threshold in milliseconds, 0 to log all.
+    #[serde(default)]
+    pub threshold_ms: u64,
+}
+
+/// Runtime configuration of a mounted filesystem, which may be updated on the fly.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MountRuntimeConfig {
+    /// Whether to validate data chunks by digest value, keep unchanged if not specified.
+    #[serde(default)]
+    pub digest_validate: Option<bool>,
+}
+
+/// Access advices from applications about file data, as `posix_fadvise()`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAdvice {
+    /// The data will be accessed in the near future.
+    WillNeed,
+    /// The data will not be accessed in the near future.
+    DontNeed,
+}
+
+/// Access advice about data of a file in a mounted filesystem.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileAdviceCmd {
+    /// Absolute path of the file in the filesystem.
+    pub path: String,
+    /// Start offset of the data range.
+    #[serde(default)]
+    pub offset: u64,
+    /// Size of the data range, to the end of file if zero.
+    #[serde(default)]
+    pub size: u64,
+    /// Access advice for the data range.
+    pub advice: FileAdvice,
+}
+
 /// Configuration information for storage backend.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct BackendConfig {
@@ -49,9 +96,12 @@ pub struct BackendConfig {
     #[serde(rename = "type")]
     pub backend_type: String,
     /// Configuration for storage backend.
-    /// Possible value: `LocalFsConfig`, `RegistryConfig`, `OssConfig`.
+    /// Possible value: `LocalFsConfig`, `RegistryConfig`, `OssConfig`, `S3Config`.
     #[serde(rename = "config")]
     pub backend_config: Value,
+    /// Configuration for storage backend health checking.
+    #[serde(default)]
+    pub health_check: BackendHealthCheckConfig,
 }
 
 /// Errors generated by/related to the API service, sent back through [`ApiResponse`].
@@ -66,6 +116,7 @@ impl BackendConfig {
         Ok(Self {
             backend_type: backend_type.to_string(),
             backend_config,
+            health_check: BackendHealthCheckConfig::default(),
         })
     }
 
@@ -83,10 +134,25 @@ impl BackendConfig {
         Ok(Self {
             backend_type: backend_type.to_string(),
             backend_config,
+            health_check: BackendHealthCheckConfig::default(),
         })
     }
 }
 
+/// Configuration information for storage backend health checking.
+///
+/// The storage backend is probed periodically by querying size of an object. Once the storage
+/// backend recovers from failures, cached failure states of the backend and blob caches are reset
+/// so that IO requests may succeed again without remounting.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct BackendHealthCheckConfig {
+    /// Interval in seconds to probe the storage backend, health checking is disabled if zero.
+    pub interval: u64,
+    /// Object to probe, defaults to the first blob accessed through the storage backend.
+    pub probe_object: String,
+}
+
 /// Configuration information for localfs storage backend.
 #[derive(Clone, Default, Deserialize, Serialize)]
 #[serde(default)]
@@ -139,6 +205,48 @@ pub struct OssConfig {
     pub object_prefix: String,
 }
 
+/// S3 compatible storage configuration information to access blobs.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// Enable HTTP proxy for the read request.
+    pub proxy: ProxyConfig,
+    /// Mirrors are unsupported and ignored, because requests are signed for the S3 endpoint.
+    pub mirrors: Vec<MirrorConfig>,
+    /// Skip SSL certificate validation for HTTPS scheme.
+    pub skip_verify: bool,
+    /// Drop the read request once http request timeout, in seconds.
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u32,
+    /// Drop the read request once http connection timeout, in seconds.
+    #[serde(default = "default_http_timeout")]
+    pub connect_timeout: u32,
+    /// Retry count when read request failed.
+    pub retry_limit: u8,
+    /// S3 endpoint, defaults to `s3.<region>.amazonaws.com` if empty.
+    pub endpoint: String,
+    /// S3 region used for request signing.
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// S3 bucket name.
+    pub bucket_name: String,
+    /// S3 http scheme, either 'http' or 'https'.
+    #[serde(default = "default_http_scheme")]
+    pub scheme: String,
+    /// Use path-style addressing (`<endpoint>/<bucket>/<key>`) instead of virtual-host style
+    /// addressing (`<bucket>.<endpoint>/<key>`), usually required by MinIO.
+    pub path_style: bool,
+    /// Prefix object_prefix to S3 object key.
+    pub object_prefix: String,
+    /// S3 access key, fetch temporary credentials from web identity token or instance metadata
+    /// service if empty.
+    pub access_key_id: String,
+    /// S3 secret key.
+    pub access_key_secret: String,
+    /// Session token for temporary static credentials.
+    pub session_token: String,
+}
+
 /// Container registry configuration information to access blobs.
 #[derive(Clone, Default, Deserialize, Serialize)]
 #[serde(default)]
@@ -168,6 +276,11 @@ pub struct RegistryConfig {
     /// sent to registry auth server to get a bearer token.
     #[serde(default)]
     pub auth: Option<String>,
+    /// Path to a file containing Base64_encoded(username:password), in place of `auth`.
+    ///
+    /// The file is watched and the credential is reloaded on change without restarting nydusd.
+    #[serde(default)]
+    pub auth_file: Option<String>,
     /// The field is a bearer token to be sent to registry
     /// to authorize registry requests.
     #[serde(default)]
@@ -179,18 +292,28 @@ pub struct RegistryConfig {
     /// Redirect blob access to a different host regardless of the one specified in 'host'.
     #[serde(default)]
     pub blob_redirected_host: String,
+    /// Ordered list of mirrors to fail over to when the registry is unavailable.
+    ///
+    /// Different from `mirrors`, which are always tried before the registry, failover mirrors
+    /// are only used when the registry fails or responds with server errors.
+    #[serde(default)]
+    pub failover_mirrors: Vec<MirrorConfig>,
+    /// Interval to fall back to the registry after failing over to mirrors, in seconds.
+    #[serde(default = "default_primary_retry_interval")]
+    pub primary_retry_interval: u64,
 }
 
 /// Configuration information for blob cache manager.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct CacheConfig {
-    /// Type of blob cache: "blobcache", "fscache" or ""
+    /// Type of blob cache: "blobcache", "fscache", "memory" or ""
     #[serde(default, rename = "type")]
     pub cache_type: String,
     /// Whether the data from the cache is compressed, not used anymore.
     #[serde(default, rename = "compressed")]
     pub cache_compressed: bool,
-    /// Blob cache manager specific configuration: FileCacheConfig, FsCacheConfig.
+    /// Blob cache manager specific configuration: FileCacheConfig, FsCacheConfig,
+    /// MemoryCacheConfig.
     #[serde(default, rename = "config")]
     pub cache_config: Value,
     /// Whether to validate data read from the cache.
@@ -199,6 +322,11 @@ pub struct CacheConfig {
     /// Configuration for blob data prefetching.
     #[serde(skip_serializing, skip_deserializing)]
     pub prefetch_config: BlobPrefetchConfig,
+    /// Backend to decompress gzip compressed data: "auto", "builtin", "zlib-ng" or "isal".
+    ///
+    /// The backend is selected once per process, by the first blob cache created.
+    #[serde(default)]
+    pub gzip_backend: String,
 }
 
 /// Configuration information to create blob cache manager.
@@ -224,15 +352,15 @@ pub struct BlobCacheEntryConfig {
     pub backend_type: String,
     /// Configuration for storage backend, corresponding to `FactoryConfig::BackendConfig::backend_config`.
     ///
-    /// Possible value: `LocalFsConfig`, `RegistryConfig`, `OssConfig`.
+    /// Possible value: `LocalFsConfig`, `RegistryConfig`, `OssConfig`, `S3Config`.
     pub backend_config: Value,
     /// Type of blob cache, corresponding to `FactoryConfig::CacheConfig::cache_type`.
     ///
-    /// Possible value: "fscache", "filecache".
+    /// Possible value: "fscache", "filecache", "memory".
     pub cache_type: String,
     /// Configuration for blob cache, corresponding to `FactoryConfig::CacheConfig::cache_config`.
     ///
-    /// Possible value: `FileCacheConfig`, `FsCacheConfig`.
+    /// Possible value: `FileCacheConfig`, `FsCacheConfig`, `MemoryCacheConfig`.
     pub cache_config: Value,
     /// Configuration for data prefetch.
     #[serde(default)]
@@ -285,6 +413,60 @@ pub struct BlobCacheObjectId {
     pub blob_id: String,
 }
 
+/// Compressed data range of a blob.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BlobRange {
+    /// Offset into the compressed blob.
+    pub offset: u64,
+    /// Size of the range.
+    pub size: u64,
+}
+
+/// Information about a data blob to pull into the blob cache by a prefetch job.
+///
+/// Fields other than `blob_id` and `ranges` correspond to the blob table entry of RAFS v6
+/// metadata, which is needed to locate chunks in the blob without loading the metadata.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PrefetchJobBlobConfig {
+    /// Blob id.
+    pub blob_id: String,
+    /// Size of the compressed blob.
+    pub compressed_size: u64,
+    /// Size of the uncompressed blob.
+    pub uncompressed_size: u64,
+    /// Chunk size.
+    pub chunk_size: u32,
+    /// Number of chunks in the blob.
+    pub chunk_count: u32,
+    /// Compression algorithm for chunk data: "none", "lz4_block", "gzip", "zstd" or "snappy".
+    pub compressor: String,
+    /// Digest algorithm for chunk data: "blake3" or "sha256".
+    pub digester: String,
+    /// Feature flags of the chunk information array.
+    #[serde(default)]
+    pub meta_flags: u32,
+    /// Offset of the chunk information array in the blob.
+    pub meta_ci_offset: u64,
+    /// Compressed size of the chunk information array.
+    pub meta_ci_compressed_size: u64,
+    /// Uncompressed size of the chunk information array.
+    pub meta_ci_uncompressed_size: u64,
+    /// Compression algorithm for the chunk information array.
+    pub meta_ci_compressor: String,
+    /// Compressed data ranges to pull, the whole blob if empty.
+    #[serde(default)]
+    pub ranges: Vec<BlobRange>,
+}
+
+/// Configuration information for a job to pull blob data into the blob cache.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PrefetchJobConfig {
+    /// Configuration information of the storage backend and blob cache.
+    pub config: BlobCacheEntryConfig,
+    /// Data blobs to pull.
+    pub blobs: Vec<PrefetchJobBlobConfig>,
+}
+
 /// Configuration information for blob data prefetching.
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Deserialize, Serialize)]
 pub struct BlobPrefetchConfig {
@@ -307,6 +489,9 @@ pub struct FileCacheConfig {
     /// Deprecated: disable index mapping, keep it as false when possible.
     #[serde(default)]
     pub disable_indexed_map: bool,
+    /// Read cache files by io_uring if supported by the running kernel.
+    #[serde(default)]
+    pub enable_io_uring: bool,
 }
 
 impl FileCacheConfig {
@@ -369,6 +554,22 @@ impl FsCacheConfig {
     }
 }
 
+/// Configuration information for in-memory blob cache.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemoryCacheConfig {
+    /// Maximum size of uncompressed chunk data kept in memory, in unit of Bytes.
+    #[serde(default = "default_memory_cache_capacity")]
+    pub capacity: u64,
+}
+
+impl Default for MemoryCacheConfig {
+    fn default() -> Self {
+        MemoryCacheConfig {
+            capacity: default_memory_cache_capacity(),
+        }
+    }
+}
+
 /// Configuration information for network proxy.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
@@ -443,8 +644,16 @@ pub enum ApiRequest {
     ConfigureDaemon(DaemonConf),
     /// Get daemon information.
     GetDaemonInfo,
+    /// Get service information, such as version, features and uptime.
+    GetServiceInfo,
     /// Get daemon global events.
     GetEvents,
+    /// Get audit events with sequence number no less than the specified one.
+    GetAuditEvents(Option<u64>),
+    /// Get request tracing configuration.
+    GetTraceConfig,
+    /// Set request tracing configuration.
+    ConfigureTrace(TraceConfig),
     /// Stop the daemon.
     Exit,
     /// Start the daemon.
@@ -461,11 +670,17 @@ pub enum ApiRequest {
     Remount(String, ApiMountCmd),
     /// Unmount a filesystem.
     Umount(String),
+    /// Update runtime configuration of a mounted filesystem.
+    UpdateMountConfig(String, MountRuntimeConfig),
 
     /// Get storage backend metrics.
     ExportBackendMetrics(Option<String>),
     /// Get blob cache metrics.
     ExportBlobcacheMetrics(Option<String>),
+    /// Get per-blob download metrics.
+    ExportBlobDownloadMetrics(Option<String>),
+    /// Get chunk data decompression metrics.
+    ExportDecompressMetrics,
 
     // Nydus API v1 requests
     /// Get filesystem global metrics.
@@ -474,10 +689,20 @@ pub enum ApiRequest {
     ExportFsAccessPatterns(Option<String>),
     /// Get filesystem backend information.
     ExportFsBackendInfo(String),
+    /// Get access information about data blobs of a filesystem.
+    ExportFsBlobAccess(String),
+    /// Get build provenance of a filesystem.
+    ExportFsProvenance(String),
+    /// List all mounted filesystems.
+    ListFilesystems,
+    /// Advise access pattern about data of a file in a mounted filesystem.
+    AdviseFile(String, FileAdviceCmd),
     /// Get filesystem file metrics.
     ExportFsFilesMetrics(Option<String>, bool),
     /// Get information about filesystem inflight requests.
     ExportFsInflightMetrics,
+    /// List blobs managed by the blob cache manager, optionally filtered by domain id.
+    ListBlobs(Option<String>),
 
     // Nydus API v2
     /// Get daemon information excluding filesystem backends.
@@ -488,6 +713,12 @@ pub enum ApiRequest {
     GetBlobObject(BlobCacheObjectId),
     /// Delete a blob cache entry
     DeleteBlobObject(BlobCacheObjectId),
+    /// Start a job to pull blob data into the blob cache.
+    CreatePrefetchJob(PrefetchJobConfig),
+    /// Get status of all prefetch jobs, or the prefetch job with the specified id.
+    GetPrefetchJobs(Option<u64>),
+    /// Cancel a prefetch job.
+    CancelPrefetchJob(u64),
 }
 
 /// Kinds for daemon related error messages.
@@ -546,12 +777,24 @@ pub enum ApiResponsePayload {
     BackendMetrics(String),
     /// Blobcache metrics.
     BlobcacheMetrics(String),
+    /// Per-blob download metrics.
+    BlobDownloadMetrics(String),
+    /// Chunk data decompression metrics.
+    DecompressMetrics(String),
     /// Daemon version, configuration and status information in json.
     DaemonInfo(String),
+    /// Service version, features and statistics information in json.
+    ServiceInfo(String),
     /// No data is sent on the channel.
     Empty,
     /// Global error events.
     Events(String),
+    /// Audit events.
+    AuditEvents(String),
+    /// Id or status of prefetch jobs.
+    PrefetchJobs(String),
+    /// Request tracing configuration.
+    TraceConfig(String),
 
     /// Filesystem global metrics, v1.
     FsGlobalMetrics(String),
@@ -561,6 +804,12 @@ pub enum ApiResponsePayload {
     FsFilesPatterns(String),
     // Filesystem Backend Information, v1.
     FsBackendInfo(String),
+    // Filesystem data blob access information, v1.
+    FsBlobAccess(String),
+    // Filesystem build provenance, v1.
+    FsProvenance(String),
+    // Mounted filesystems, v1.
+    FsList(String),
     // Filesystem Inflight Requests, v1.
     FsInflightMetrics(String),
 
@@ -584,14 +833,24 @@ pub enum HttpError {
     Configure(ApiError),
     /// Failed to query information about daemon.
     DaemonInfo(ApiError),
+    /// Failed to query information about service.
+    ServiceInfo(ApiError),
     /// Failed to query global events.
     Events(ApiError),
+    /// Failed to query audit events.
+    AuditEvents(ApiError),
+    /// Failed to create, query or cancel prefetch jobs.
+    PrefetchJob(ApiError),
+    /// Failed to query or configure request tracing.
+    Trace(ApiError),
     /// No handler registered for HTTP request URI
     NoRoute,
     /// Failed to parse HTTP request message body
     ParseBody(SerdeError),
     /// Query parameter is missed from the HTTP request.
     QueryString(String),
+    /// The request isn't authorized to change daemon state.
+    Unauthorized(String),
 
     /// Failed to mount filesystem.
     Mount(ApiError),
@@ -603,10 +862,22 @@ pub enum HttpError {
     BackendMetrics(ApiError),
     /// Failed to get blobcache metrics.
     BlobcacheMetrics(ApiError),
+    /// Failed to get per-blob download metrics.
+    BlobDownloadMetrics(ApiError),
+    /// Failed to get decompression metrics.
+    DecompressMetrics(ApiError),
 
     // Filesystem related errors (v1)
     /// Failed to get filesystem backend information
     FsBackendInfo(ApiError),
+    /// Failed to get filesystem data blob access information
+    FsBlobAccess(ApiError),
+    /// Failed to get filesystem build provenance
+    FsProvenance(ApiError),
+    /// Failed to list mounted filesystems
+    FsList(ApiError),
+    /// Failed to advise access pattern about file data
+    FsAdvise(ApiError),
     /// Failed to get filesystem per-file metrics.
     FsFilesMetrics(ApiError),
     /// Failed to get global metrics.
@@ -642,6 +913,10 @@ fn default_http_scheme() -> String {
     "https".to_string()
 }
 
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
 fn default_http_timeout() -> u32 {
     5
 }
@@ -654,10 +929,18 @@ fn default_failure_limit() -> u8 {
     5
 }
 
+fn default_primary_retry_interval() -> u64 {
+    60
+}
+
 fn default_work_dir() -> String {
     ".".to_string()
 }
 
+fn default_memory_cache_capacity() -> u64 {
+    256 * 1024 * 1024
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -715,6 +998,15 @@ mod tests {
         assert!(config.get_work_dir().is_err());
     }
 
+    #[test]
+    fn test_memory_cache_config() {
+        let config: MemoryCacheConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.capacity, 256 * 1024 * 1024);
+
+        let config: MemoryCacheConfig = serde_json::from_str("{\"capacity\":1048576}").unwrap();
+        assert_eq!(config.capacity, 0x100000);
+    }
+
     #[test]
     fn test_blob_cache_entry() {
         let content = r#"{
@@ -801,6 +1093,22 @@ mod tests {
         assert_eq!(config.connect_timeout, 5);
     }
 
+    #[test]
+    fn test_s3_config() {
+        let content = r#"{
+            "endpoint": "localhost:9000",
+            "bucket_name": "nydus",
+            "scheme": "http",
+            "path_style": true
+        }"#;
+        let config: S3Config = serde_json::from_str(content).unwrap();
+        assert_eq!(config.region, "us-east-1");
+        assert!(config.path_style);
+        assert!(config.access_key_id.is_empty());
+        assert_eq!(config.timeout, 5);
+        assert_eq!(config.retry_limit, 0);
+    }
+
     #[test]
     fn test_registry_config() {
         let content = r#"{
@@ -817,6 +1125,25 @@ mod tests {
         assert!(config.skip_verify);
     }
 
+    #[test]
+    fn test_registry_failover_config() {
+        let content = r#"{
+            "host": "my-registry:5000",
+            "repo": "test/repo",
+            "failover_mirrors": [
+                {"host": "http://mirror1:5000"},
+                {"host": "http://mirror2:5000", "failure_limit": 3}
+            ]
+        }"#;
+        let config: RegistryConfig = serde_json::from_str(content).unwrap();
+        assert!(config.mirrors.is_empty());
+        assert_eq!(config.failover_mirrors.len(), 2);
+        assert_eq!(config.failover_mirrors[0].host, "http://mirror1:5000");
+        assert_eq!(config.failover_mirrors[0].failure_limit, 5);
+        assert_eq!(config.failover_mirrors[1].failure_limit, 3);
+        assert_eq!(config.primary_retry_interval, 60);
+    }
+
     #[test]
     fn test_localfs_config() {
         let content = r#"{
@@ -829,4 +1156,20 @@ mod tests {
         assert_eq!(config.dir, "blob_dir");
         assert_eq!(config.alt_dirs, vec!["dir1", "dir2"]);
     }
+
+    #[test]
+    fn test_file_advice_cmd() {
+        let content = r#"{"path": "/bin/bash", "offset": 4096, "advice": "willneed"}"#;
+        let cmd: FileAdviceCmd = serde_json::from_str(content).unwrap();
+        assert_eq!(cmd.path, "/bin/bash");
+        assert_eq!(cmd.offset, 4096);
+        assert_eq!(cmd.size, 0);
+        assert_eq!(cmd.advice, FileAdvice::WillNeed);
+
+        let content = r#"{"path": "/bin/bash", "advice": "dontneed"}"#;
+        let cmd: FileAdviceCmd = serde_json::from_str(content).unwrap();
+        assert_eq!(cmd.advice, FileAdvice::DontNeed);
+        let content = r#"{"path": "/bin/bash", "advice": "random"}"#;
+        assert!(serde_json::from_str::<FileAdviceCmd>(content).is_err());
+    }
 }
@@ -0,0 +1,365 @@
+// Copyright 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Optional authorization of HTTP API requests for multi-tenant deployments.
+//!
+//! Authorization is disabled unless an admin token is configured by [set_admin_token()]. Once
+//! enabled, tokens are passed by the [API_TOKEN_HEADER] HTTP header and:
+//! - a request to mount a filesystem must carry a token, which becomes owner token of the mount.
+//! - requests to remount, umount or update configuration of a filesystem must carry the owner
+//!   token of the filesystem or the admin token.
+//! - all other requests changing daemon state must carry the admin token.
+//! - requests to query information are always allowed, but `GET /api/v1/daemon` only reports
+//!   filesystems owned by the token if a non-admin token is carried.
+//!
+//! Owner tokens are kept in memory only, so filesystems restored by failover or upgrade may only
+//! be managed with the admin token.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use dbs_uhttp::{Body, Method, Request, Response, StatusCode};
+
+use crate::http::HttpError;
+use crate::http_endpoint_v1::HTTP_ROOT_V1;
+use crate::http_handler::extract_query_part;
+
+/// HTTP header to carry the authorization token.
+pub const API_TOKEN_HEADER: &str = "X-Nydus-Token";
+
+/// Compare two byte strings in constant time, to avoid leaking tokens by timing side channels.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = std::cmp::max(a.len(), b.len());
+    let mut diff = a.len() ^ b.len();
+    for idx in 0..len {
+        let x = a.get(idx).copied().unwrap_or(0);
+        let y = b.get(idx).copied().unwrap_or(0);
+        diff |= (x ^ y) as usize;
+    }
+    diff == 0
+}
+
+/// Get the authorization token carried by the HTTP request.
+fn request_token(req: &Request) -> Option<&str> {
+    req.headers
+        .custom_entries()
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(API_TOKEN_HEADER))
+        .map(|(_, v)| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+/// Action to take after the authorized request has been successfully handled.
+#[derive(Debug, PartialEq)]
+pub(crate) enum AuthAction {
+    None,
+    /// Record owner token of the mounted filesystem.
+    Claim(String, String),
+    /// Forget owner token of the umounted filesystem.
+    Release(String),
+    /// Only report filesystems owned by the token.
+    Filter(String),
+}
+
+/// Authorization state of the HTTP API server.
+#[derive(Default)]
+pub(crate) struct ApiAuth {
+    admin_token: Option<String>,
+    // Mountpoint to owner token.
+    owners: HashMap<String, String>,
+}
+
+impl ApiAuth {
+    fn is_admin(&self, token: &str) -> bool {
+        self.admin_token
+            .as_ref()
+            .map(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .unwrap_or(false)
+    }
+
+    fn is_owner(&self, mountpoint: &str, token: &str) -> bool {
+        self.owners
+            .get(mountpoint)
+            .map(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .unwrap_or(false)
+    }
+
+    /// Check whether the request to `path` is allowed.
+    pub(crate) fn authorize(&self, req: &Request, path: &str) -> Result<AuthAction, HttpError> {
+        if self.admin_token.is_none() {
+            return Ok(AuthAction::None);
+        }
+
+        let token = request_token(req);
+        let method = req.method();
+        if method == Method::Get {
+            return match token {
+                Some(t) if path == format!("{}/daemon", HTTP_ROOT_V1) && !self.is_admin(t) => {
+                    Ok(AuthAction::Filter(t.to_string()))
+                }
+                _ => Ok(AuthAction::None),
+            };
+        }
+
+        let token = token.ok_or_else(|| {
+            HttpError::Unauthorized(format!("missing {} header", API_TOKEN_HEADER))
+        })?;
+        if path == format!("{}/mount", HTTP_ROOT_V1)
+            || path == format!("{}/mount/config", HTTP_ROOT_V1)
+        {
+            // Let the handler report the missing mountpoint.
+            let mountpoint = match extract_query_part(req, "mountpoint") {
+                Some(v) => v,
+                None => return Ok(AuthAction::None),
+            };
+            if method == Method::Post {
+                return if self.owners.contains_key(&mountpoint) && !self.is_admin(token) {
+                    Err(HttpError::Unauthorized(format!(
+                        "{} is owned by another token",
+                        mountpoint
+                    )))
+                } else {
+                    Ok(AuthAction::Claim(mountpoint, token.to_string()))
+                };
+            }
+            if !self.is_admin(token) && !self.is_owner(&mountpoint, token) {
+                return Err(HttpError::Unauthorized(format!(
+                    "token doesn't own {}",
+                    mountpoint
+                )));
+            }
+            return if method == Method::Delete && path == format!("{}/mount", HTTP_ROOT_V1) {
+                Ok(AuthAction::Release(mountpoint))
+            } else {
+                Ok(AuthAction::None)
+            };
+        }
+
+        if self.is_admin(token) {
+            Ok(AuthAction::None)
+        } else {
+            Err(HttpError::Unauthorized("admin token required".to_string()))
+        }
+    }
+
+    /// Update authorization state or the response after successfully handling the request.
+    pub(crate) fn complete(&mut self, action: AuthAction, response: &mut Response) {
+        if response.status() != StatusCode::OK && response.status() != StatusCode::NoContent {
+            return;
+        }
+
+        match action {
+            AuthAction::None => {}
+            AuthAction::Claim(mountpoint, token) => {
+                self.owners.insert(mountpoint, token);
+            }
+            AuthAction::Release(mountpoint) => {
+                self.owners.remove(&mountpoint);
+            }
+            AuthAction::Filter(token) => {
+                let body = match response.body() {
+                    Some(b) => b,
+                    None => return,
+                };
+                let mut info: serde_json::Value = match serde_json::from_slice(body.raw()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("api: failed to filter daemon information by owner, {}", e);
+                        return;
+                    }
+                };
+                if let Some(serde_json::Value::Object(fs)) = info.get_mut("backend_collection") {
+                    let others: Vec<String> = fs
+                        .keys()
+                        .filter(|mountpoint| !self.is_owner(mountpoint, &token))
+                        .cloned()
+                        .collect();
+                    for mountpoint in others {
+                        fs.remove(&mountpoint);
+                    }
+                }
+                response.set_body(Body::new(info.to_string()));
+            }
+        }
+    }
+}
+
+lazy_static! {
+    pub(crate) static ref API_AUTH: Mutex<ApiAuth> = Mutex::new(ApiAuth::default());
+}
+
+/// Enable authorization of HTTP API requests with the admin token.
+pub fn set_admin_token(token: String) {
+    API_AUTH.lock().unwrap().admin_token = Some(token);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_handler::HTTP_ROUTES;
+
+    const ADMIN: &str = "admin-token";
+    const TENANT1: &str = "tenant1-token";
+    const TENANT2: &str = "tenant2-token";
+
+    fn request(method: &str, uri: &str, token: Option<&str>) -> Request {
+        let header = token
+            .map(|t| format!("{}: {}\r\n", API_TOKEN_HEADER, t))
+            .unwrap_or_default();
+        let (body, len) = if method == "GET" || method == "DELETE" {
+            ("", String::new())
+        } else {
+            ("{}", "Content-Length: 2\r\n".to_string())
+        };
+        let msg = format!(
+            "{} http://localhost{} HTTP/1.1\r\n{}{}\r\n{}",
+            method, uri, header, len, body
+        );
+        Request::try_from(msg.as_bytes(), None).unwrap()
+    }
+
+    fn authorize(auth: &ApiAuth, method: &str, uri: &str, token: Option<&str>) -> bool {
+        let path = uri.split('?').next().unwrap();
+        auth.authorize(&request(method, uri, token), path).is_ok()
+    }
+
+    fn enabled_auth() -> ApiAuth {
+        ApiAuth {
+            admin_token: Some(ADMIN.to_string()),
+            owners: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token1"));
+        assert!(!constant_time_eq(b"", b"token"));
+    }
+
+    #[test]
+    fn test_request_token() {
+        let req = request("GET", "/api/v1/daemon", Some(TENANT1));
+        assert_eq!(request_token(&req), Some(TENANT1));
+        let req = Request::try_from(
+            b"GET http://localhost/api/v1/daemon HTTP/1.1\r\nx-nydus-token: abc\r\n\r\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(request_token(&req), Some("abc"));
+        let req = request("GET", "/api/v1/daemon", None);
+        assert_eq!(request_token(&req), None);
+    }
+
+    #[test]
+    fn test_auth_disabled() {
+        let auth = ApiAuth::default();
+        for path in HTTP_ROUTES.routes.keys() {
+            for method in ["GET", "PUT", "POST", "PATCH", "DELETE"] {
+                assert!(authorize(&auth, method, path, None));
+            }
+        }
+        let req = request("POST", "/api/v1/mount?mountpoint=/m1", Some(TENANT1));
+        assert_eq!(
+            auth.authorize(&req, "/api/v1/mount").unwrap(),
+            AuthAction::None
+        );
+    }
+
+    #[test]
+    fn test_auth_admin_endpoints() {
+        let auth = enabled_auth();
+        for path in HTTP_ROUTES.routes.keys() {
+            // Queries are always allowed.
+            for token in [None, Some(TENANT1), Some(ADMIN)] {
+                assert!(authorize(&auth, "GET", path, token));
+            }
+            if path == "/api/v1/mount" || path == "/api/v1/mount/config" {
+                continue;
+            }
+            // Other requests changing daemon state need the admin token.
+            for method in ["PUT", "POST", "PATCH", "DELETE"] {
+                assert!(!authorize(&auth, method, path, None));
+                assert!(!authorize(&auth, method, path, Some(TENANT1)));
+                assert!(!authorize(&auth, method, path, Some("admin-token1")));
+                assert!(authorize(&auth, method, path, Some(ADMIN)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_auth_mount_endpoints() {
+        let mut auth = enabled_auth();
+        let m1 = "/api/v1/mount?mountpoint=/m1";
+        let m1_config = "/api/v1/mount/config?mountpoint=/m1";
+
+        // Mounting needs a token, which becomes the owner.
+        assert!(!authorize(&auth, "POST", m1, None));
+        let req = request("POST", m1, Some(TENANT1));
+        let action = auth.authorize(&req, "/api/v1/mount").unwrap();
+        assert_eq!(
+            action,
+            AuthAction::Claim("/m1".to_string(), TENANT1.to_string())
+        );
+        // Failed requests don't change the owner.
+        let mut response = Response::new(dbs_uhttp::Version::Http11, StatusCode::BadRequest);
+        auth.complete(action, &mut response);
+        assert!(auth.owners.is_empty());
+        let action = auth.authorize(&req, "/api/v1/mount").unwrap();
+        let mut response = Response::new(dbs_uhttp::Version::Http11, StatusCode::NoContent);
+        auth.complete(action, &mut response);
+        assert!(auth.is_owner("/m1", TENANT1));
+
+        // Only the owner and admin may change the filesystem.
+        for (method, uri) in [("PUT", m1), ("DELETE", m1), ("PATCH", m1_config)] {
+            assert!(!authorize(&auth, method, uri, None));
+            assert!(!authorize(&auth, method, uri, Some(TENANT2)));
+            assert!(authorize(&auth, method, uri, Some(TENANT1)));
+            assert!(authorize(&auth, method, uri, Some(ADMIN)));
+        }
+        assert!(!authorize(&auth, "POST", m1, Some(TENANT2)));
+        assert!(authorize(&auth, "POST", m1, Some(ADMIN)));
+        // Filesystems without owner may only be changed by admin.
+        let m2 = "/api/v1/mount?mountpoint=/m2";
+        assert!(!authorize(&auth, "DELETE", m2, Some(TENANT1)));
+        assert!(authorize(&auth, "DELETE", m2, Some(ADMIN)));
+
+        // Umount releases the ownership.
+        let req = request("DELETE", m1, Some(TENANT1));
+        let action = auth.authorize(&req, "/api/v1/mount").unwrap();
+        assert_eq!(action, AuthAction::Release("/m1".to_string()));
+        auth.complete(action, &mut response);
+        assert!(auth.owners.is_empty());
+        assert!(authorize(&auth, "POST", m1, Some(TENANT2)));
+    }
+
+    #[test]
+    fn test_auth_filter_daemon_info() {
+        let mut auth = enabled_auth();
+        auth.owners.insert("/m1".to_string(), TENANT1.to_string());
+        auth.owners.insert("/m2".to_string(), TENANT2.to_string());
+        let info = r#"{"id":"d1","backend_collection":{"/m1":{},"/m2":{},"/m3":{}}}"#;
+
+        for (token, expected) in [
+            (None, vec!["/m1", "/m2", "/m3"]),
+            (Some(ADMIN), vec!["/m1", "/m2", "/m3"]),
+            (Some(TENANT1), vec!["/m1"]),
+            (Some(TENANT2), vec!["/m2"]),
+            (Some("tenant3"), vec![]),
+        ] {
+            let req = request("GET", "/api/v1/daemon", token);
+            let action = auth.authorize(&req, "/api/v1/daemon").unwrap();
+            let mut response = Response::new(dbs_uhttp::Version::Http11, StatusCode::OK);
+            response.set_body(Body::new(info));
+            auth.complete(action, &mut response);
+            let body = response.body().unwrap();
+            let v: serde_json::Value = serde_json::from_slice(body.raw()).unwrap();
+            let fs = v["backend_collection"].as_object().unwrap();
+            assert_eq!(fs.keys().collect::<Vec<_>>(), expected);
+            assert_eq!(v["id"], "d1");
+        }
+    }
+}
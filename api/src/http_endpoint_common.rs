@@ -24,8 +24,13 @@ fn convert_to_response<O: FnOnce(ApiError) -> HttpError>(api_resp: ApiResponse,
             match r {
                 Empty => success_response(None),
                 Events(d) => success_response(Some(d)),
+                AuditEvents(d) => success_response(Some(d)),
+                PrefetchJobs(d) => success_response(Some(d)),
+                TraceConfig(d) => success_response(Some(d)),
                 BackendMetrics(d) => success_response(Some(d)),
                 BlobcacheMetrics(d) => success_response(Some(d)),
+                BlobDownloadMetrics(d) => success_response(Some(d)),
+                DecompressMetrics(d) => success_response(Some(d)),
                 _ => panic!("Unexpected response message from API service"),
             }
         }
@@ -90,6 +95,94 @@ impl EndpointHandler for EventsHandler {
     }
 }
 
+/// Get audit events, starting from the sequence number specified by query parameter `since`.
+pub struct AuditEventsHandler {}
+impl EndpointHandler for AuditEventsHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let since = match extract_query_part(req, "since") {
+                    Some(v) => Some(v.parse::<u64>().map_err(|_| {
+                        HttpError::QueryString(format!("invalid sequence number {}", v))
+                    })?),
+                    None => None,
+                };
+                let r = kicker(ApiRequest::GetAuditEvents(since));
+                Ok(convert_to_response(r, HttpError::AuditEvents))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Get or set request tracing configuration.
+pub struct TraceHandler {}
+impl EndpointHandler for TraceHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::GetTraceConfig);
+                Ok(convert_to_response(r, HttpError::Trace))
+            }
+            (Method::Put, Some(body)) => {
+                let conf = parse_body(body)?;
+                let r = kicker(ApiRequest::ConfigureTrace(conf));
+                Ok(convert_to_response(r, HttpError::Trace))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+fn parse_job_id(req: &Request) -> Result<Option<u64>, HttpError> {
+    match extract_query_part(req, "id") {
+        Some(v) => v
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| HttpError::QueryString(format!("invalid prefetch job id {}", v))),
+        None => Ok(None),
+    }
+}
+
+/// Create, query or cancel jobs to pull blob data into the blob cache.
+pub struct PrefetchJobsHandler {}
+impl EndpointHandler for PrefetchJobsHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Post, Some(body)) => {
+                let conf = parse_body(body)?;
+                let r = kicker(ApiRequest::CreatePrefetchJob(conf));
+                Ok(convert_to_response(r, HttpError::PrefetchJob))
+            }
+            (Method::Get, None) => {
+                let id = parse_job_id(req)?;
+                let r = kicker(ApiRequest::GetPrefetchJobs(id));
+                Ok(convert_to_response(r, HttpError::PrefetchJob))
+            }
+            (Method::Delete, None) => {
+                let id = parse_job_id(req)?.ok_or_else(|| {
+                    HttpError::QueryString("'id' should be specified in query string".to_string())
+                })?;
+                let r = kicker(ApiRequest::CancelPrefetchJob(id));
+                Ok(convert_to_response(r, HttpError::PrefetchJob))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 // Metrics related requests.
 /// Get storage backend metrics.
 pub struct MetricsBackendHandler {}
@@ -129,6 +222,43 @@ impl EndpointHandler for MetricsBlobcacheHandler {
     }
 }
 
+/// Get per-blob download metrics.
+pub struct MetricsBlobDownloadHandler {}
+impl EndpointHandler for MetricsBlobDownloadHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let id = extract_query_part(req, "id");
+                let r = kicker(ApiRequest::ExportBlobDownloadMetrics(id));
+                Ok(convert_to_response(r, HttpError::BlobDownloadMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Get chunk data decompression metrics.
+pub struct MetricsDecompressHandler {}
+impl EndpointHandler for MetricsDecompressHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportDecompressMetrics);
+                Ok(convert_to_response(r, HttpError::DecompressMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 /// Mount a filesystem.
 pub struct MountHandler {}
 impl EndpointHandler for MountHandler {
@@ -160,6 +290,28 @@ impl EndpointHandler for MountHandler {
     }
 }
 
+/// Update runtime configuration of a mounted filesystem.
+pub struct MountConfigHandler {}
+impl EndpointHandler for MountConfigHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+            HttpError::QueryString("'mountpoint' should be specified in query string".to_string())
+        })?;
+        match (req.method(), req.body.as_ref()) {
+            (Method::Patch, Some(body)) => {
+                let conf = parse_body(body)?;
+                let r = kicker(ApiRequest::UpdateMountConfig(mountpoint, conf));
+                Ok(convert_to_response(r, HttpError::Mount))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 /// Send fuse fd to new daemon.
 pub struct SendFuseFdHandler {}
 impl EndpointHandler for SendFuseFdHandler {
@@ -29,11 +29,16 @@ fn convert_to_response<O: FnOnce(ApiError) -> HttpError>(api_resp: ApiResponse,
             match r {
                 Empty => success_response(None),
                 DaemonInfo(d) => success_response(Some(d)),
+                ServiceInfo(d) => success_response(Some(d)),
                 FsGlobalMetrics(d) => success_response(Some(d)),
                 FsFilesMetrics(d) => success_response(Some(d)),
                 FsFilesPatterns(d) => success_response(Some(d)),
                 FsBackendInfo(d) => success_response(Some(d)),
+                FsBlobAccess(d) => success_response(Some(d)),
+                FsProvenance(d) => success_response(Some(d)),
+                FsList(d) => success_response(Some(d)),
                 FsInflightMetrics(d) => success_response(Some(d)),
+                BlobObjectList(d) => success_response(Some(d)),
                 _ => panic!("Unexpected response message from API service"),
             }
         }
@@ -67,6 +72,43 @@ impl EndpointHandler for InfoHandler {
     }
 }
 
+/// Get service information, such as version, features and uptime.
+pub struct ServiceInfoHandler {}
+impl EndpointHandler for ServiceInfoHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::GetServiceInfo);
+                Ok(convert_to_response(r, HttpError::ServiceInfo))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// List blobs managed by the blob cache manager.
+pub struct BlobListHandler {}
+impl EndpointHandler for BlobListHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let domain_id = extract_query_part(req, "domain_id");
+                let r = kicker(ApiRequest::ListBlobs(domain_id));
+                Ok(convert_to_response(r, HttpError::GetBlobObjects))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 /// Get filesystem backend information.
 pub struct FsBackendInfo {}
 impl EndpointHandler for FsBackendInfo {
@@ -90,6 +132,94 @@ impl EndpointHandler for FsBackendInfo {
     }
 }
 
+/// Get access information about data blobs of a filesystem.
+pub struct FsBlobAccessHandler {}
+impl EndpointHandler for FsBlobAccessHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let r = kicker(ApiRequest::ExportFsBlobAccess(mountpoint));
+                Ok(convert_to_response(r, HttpError::FsBlobAccess))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Get build provenance of a filesystem.
+pub struct FsProvenanceHandler {}
+impl EndpointHandler for FsProvenanceHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let r = kicker(ApiRequest::ExportFsProvenance(mountpoint));
+                Ok(convert_to_response(r, HttpError::FsProvenance))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// List all mounted filesystems.
+pub struct FsListHandler {}
+impl EndpointHandler for FsListHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ListFilesystems);
+                Ok(convert_to_response(r, HttpError::FsList))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Advise access pattern about data of a file in a mounted filesystem.
+pub struct FsAdviseHandler {}
+impl EndpointHandler for FsAdviseHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, Some(body)) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::AdviseFile(mountpoint, cmd));
+                Ok(convert_to_response(r, HttpError::FsAdvise))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 /// Get filesystem global metrics.
 pub struct MetricsFsGlobalHandler {}
 impl EndpointHandler for MetricsFsGlobalHandler {
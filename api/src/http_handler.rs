@@ -19,13 +19,17 @@ use url::Url;
 use crate::http::{
     ApiError, ApiRequest, ApiResponse, DaemonErrorKind, ErrorMessage, HttpError, MetricsErrorKind,
 };
+use crate::http_auth::API_AUTH;
 use crate::http_endpoint_common::{
-    EventsHandler, ExitHandler, MetricsBackendHandler, MetricsBlobcacheHandler, MountHandler,
-    SendFuseFdHandler, StartHandler, TakeoverFuseFdHandler,
+    AuditEventsHandler, EventsHandler, ExitHandler, MetricsBackendHandler,
+    MetricsBlobDownloadHandler, MetricsBlobcacheHandler, MetricsDecompressHandler,
+    MountConfigHandler, MountHandler, PrefetchJobsHandler, SendFuseFdHandler, StartHandler,
+    TakeoverFuseFdHandler, TraceHandler,
 };
 use crate::http_endpoint_v1::{
-    FsBackendInfo, InfoHandler, MetricsFsAccessPatternHandler, MetricsFsFilesHandler,
-    MetricsFsGlobalHandler, MetricsFsInflightHandler, HTTP_ROOT_V1,
+    BlobListHandler, FsAdviseHandler, FsBackendInfo, FsBlobAccessHandler, FsListHandler,
+    FsProvenanceHandler, InfoHandler, MetricsFsAccessPatternHandler, MetricsFsFilesHandler,
+    MetricsFsGlobalHandler, MetricsFsInflightHandler, ServiceInfoHandler, HTTP_ROOT_V1,
 };
 use crate::http_endpoint_v2::{BlobObjectListHandlerV2, InfoV2Handler, HTTP_ROOT_V2};
 
@@ -146,13 +150,25 @@ lazy_static! {
         r.routes.insert(endpoint_v1!("/daemon/start"), Box::new(StartHandler{}));
         r.routes.insert(endpoint_v1!("/daemon/fuse/sendfd"), Box::new(SendFuseFdHandler{}));
         r.routes.insert(endpoint_v1!("/daemon/fuse/takeover"), Box::new(TakeoverFuseFdHandler{}));
+        r.routes.insert(endpoint_v1!("/daemon/trace"), Box::new(TraceHandler{}));
+        r.routes.insert(endpoint_v1!("/events"), Box::new(AuditEventsHandler{}));
+        r.routes.insert(endpoint_v1!("/prefetch_jobs"), Box::new(PrefetchJobsHandler{}));
         r.routes.insert(endpoint_v1!("/mount"), Box::new(MountHandler{}));
+        r.routes.insert(endpoint_v1!("/mount/config"), Box::new(MountConfigHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/backend"), Box::new(MetricsBackendHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/blobcache"), Box::new(MetricsBlobcacheHandler{}));
+        r.routes.insert(endpoint_v1!("/metrics/blobs"), Box::new(MetricsBlobDownloadHandler{}));
+        r.routes.insert(endpoint_v1!("/metrics/decompress"), Box::new(MetricsDecompressHandler{}));
 
         // Nydus API, v1
         r.routes.insert(endpoint_v1!("/daemon"), Box::new(InfoHandler{}));
         r.routes.insert(endpoint_v1!("/daemon/backend"), Box::new(FsBackendInfo{}));
+        r.routes.insert(endpoint_v1!("/daemon/backend/blobs"), Box::new(FsBlobAccessHandler{}));
+        r.routes.insert(endpoint_v1!("/mount/provenance"), Box::new(FsProvenanceHandler{}));
+        r.routes.insert(endpoint_v1!("/mount/advise"), Box::new(FsAdviseHandler{}));
+        r.routes.insert(endpoint_v1!("/filesystems"), Box::new(FsListHandler{}));
+        r.routes.insert(endpoint_v1!("/info"), Box::new(ServiceInfoHandler{}));
+        r.routes.insert(endpoint_v1!("/blobs"), Box::new(BlobListHandler{}));
         r.routes.insert(endpoint_v1!("/metrics"), Box::new(MetricsFsGlobalHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/files"), Box::new(MetricsFsFilesHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/inflight"), Box::new(MetricsFsInflightHandler{}));
@@ -223,11 +239,21 @@ fn handle_http_request(
     let uri_parsed = request.uri().get_abs_path().parse::<Uri>();
     let mut response = match uri_parsed {
         Ok(uri) => match HTTP_ROUTES.routes.get(uri.path()) {
-            Some(route) => route
-                .handle_request(request, &|r| {
-                    kick_api_server(api_notifier.clone(), to_api, from_api, r)
-                })
-                .unwrap_or_else(|err| error_response(err, StatusCode::BadRequest)),
+            Some(route) => {
+                let action = API_AUTH.lock().unwrap().authorize(request, uri.path());
+                match action {
+                    Ok(action) => {
+                        let mut response = route
+                            .handle_request(request, &|r| {
+                                kick_api_server(api_notifier.clone(), to_api, from_api, r)
+                            })
+                            .unwrap_or_else(|err| error_response(err, StatusCode::BadRequest));
+                        API_AUTH.lock().unwrap().complete(action, &mut response);
+                        response
+                    }
+                    Err(err) => error_response(err, StatusCode::Unauthorized),
+                }
+            }
             None => error_response(HttpError::NoRoute, StatusCode::NotFound),
         },
         Err(e) => {
@@ -347,7 +373,18 @@ mod tests {
     fn test_http_api_routes_v1() {
         assert!(HTTP_ROUTES.routes.get("/api/v1/daemon").is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/daemon/events").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/events").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/blobs").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/prefetch_jobs").is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/daemon/backend").is_some());
+        assert!(HTTP_ROUTES
+            .routes
+            .get("/api/v1/daemon/backend/blobs")
+            .is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/mount/provenance").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/mount/advise").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/filesystems").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/info").is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/daemon/start").is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/daemon/exit").is_some());
         assert!(HTTP_ROUTES
@@ -367,6 +404,11 @@ mod tests {
             .routes
             .get("/api/v1/metrics/blobcache")
             .is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/metrics/blobs").is_some());
+        assert!(HTTP_ROUTES
+            .routes
+            .get("/api/v1/metrics/decompress")
+            .is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/metrics/inflight").is_some());
     }
 
@@ -20,6 +20,8 @@ extern crate nydus_error;
 pub mod http;
 pub use self::http::*;
 
+#[cfg(feature = "handler")]
+pub mod http_auth;
 #[cfg(feature = "handler")]
 pub(crate) mod http_endpoint_common;
 #[cfg(feature = "handler")]
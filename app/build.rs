@@ -49,6 +49,7 @@ fn main() {
         "<Unknown>".to_string()
     };
     let profile = std::env::var("PROFILE").unwrap_or_else(|_| "<Unknown>".to_string());
+    let target = std::env::var("TARGET").unwrap_or_else(|_| "<Unknown>".to_string());
     let build_time = time::OffsetDateTime::now_utc()
         .format(&time::format_description::well_known::Iso8601::DEFAULT)
         .unwrap();
@@ -58,6 +59,7 @@ fn main() {
     println!("cargo:rerun-if-changed=../git/HEAD");
     println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_ver);
     println!("cargo:rustc-env=PROFILE={}", profile);
+    println!("cargo:rustc-env=TARGET={}", target);
     println!("cargo:rustc-env=BUILT_TIME_UTC={}", build_time);
     println!("cargo:rustc-env=GIT_COMMIT_HASH={}", git_commit_hash);
     println!("cargo:rustc-env=GIT_COMMIT_VERSION={}", git_commit_version);
@@ -64,6 +64,7 @@ pub fn log_level_to_verbosity(level: log::LevelFilter) -> usize {
 
 pub mod built_info {
     pub const PROFILE: &str = env!("PROFILE");
+    pub const TARGET: &str = env!("TARGET");
     pub const RUSTC_VERSION: &str = env!("RUSTC_VERSION");
     pub const BUILT_TIME_UTC: &str = env!("BUILT_TIME_UTC");
     pub const GIT_COMMIT_VERSION: &str = env!("GIT_COMMIT_VERSION");
@@ -22,6 +22,7 @@ use fuse_backend_rs::{
 use nydus_error::{einval, eother};
 use nydus_rafs::{
     fs::{Rafs, RafsConfig},
+    metadata::RafsMode,
     RafsIoRead,
 };
 use serde::Deserialize;
@@ -207,7 +208,7 @@ impl BlobFs {
 
         let mut rafs_conf = blob_ondemand_conf.rafs_conf.clone();
         // we must use direct mode to get mmap'd bootstrap.
-        rafs_conf.mode = "direct".to_string();
+        rafs_conf.mode = RafsMode::Direct;
         let mut bootstrap =
             <dyn RafsIoRead>::from_file(path.to_str().unwrap()).map_err(|e| eother!(e))?;
 
@@ -0,0 +1,202 @@
+// Copyright (C) 2022 Nydus Developers. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Incremental build by reusing data chunks of unchanged files from a base bootstrap.
+//!
+//! A regular file in the source directory is considered unchanged if the file with the same path
+//! in the base bootstrap has the same size and modification time. RAFS doesn't record the change
+//! time of files, so it's not checked.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nydus_rafs::metadata::chunk::ChunkWrapper;
+use nydus_rafs::metadata::{RafsInodeExt, RafsMode, RafsSuper, RafsVersion};
+use nydus_utils::digest::{DigestHasher, RafsDigest};
+
+use super::context::{BlobContext, BlobManager, BuildContext};
+use super::node::{ChunkSource, Node, NodeChunk};
+
+/// Verify one of every `REUSE_VERIFY_INTERVAL` reused files when `verify` is enabled.
+const REUSE_VERIFY_INTERVAL: u64 = 16;
+
+struct BaseFile {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    chunks: Vec<ChunkWrapper>,
+}
+
+/// Regular files and data blobs of a base bootstrap to be reused by incremental build.
+pub struct BaseBootstrap {
+    files: HashMap<PathBuf, BaseFile>,
+    verify: bool,
+    reused_files: u64,
+    reused_size: u64,
+}
+
+impl BaseBootstrap {
+    /// Load regular files from the base bootstrap, and append its data blobs to the blob table.
+    ///
+    /// It should be called before building the new data blob, so the new blob comes after all
+    /// blobs of the base bootstrap in the blob table.
+    pub fn load(
+        ctx: &BuildContext,
+        blob_mgr: &mut BlobManager,
+        path: &Path,
+        verify: bool,
+    ) -> Result<Self> {
+        let rs = RafsSuper::load_from_metadata(path, RafsMode::Direct, true)
+            .with_context(|| format!("failed to load base bootstrap {:?}", path))?;
+        let version = RafsVersion::try_from(rs.meta.version)?;
+        if version != ctx.fs_version {
+            bail!(
+                "inconsistent RAFS version with base bootstrap {:?}, current {:?}, base {:?}",
+                path,
+                ctx.fs_version,
+                version
+            );
+        }
+        if rs.meta.get_compressor() != ctx.compressor
+            || rs.meta.get_digest_algorithm() != ctx.digester
+            || rs.meta.chunk_size != ctx.chunk_size
+        {
+            bail!(
+                "inconsistent compressor, digester or chunk size with base bootstrap {:?}",
+                path
+            );
+        }
+
+        let mut blob_idx_map = Vec::new();
+        for blob in rs.superblock.get_blob_infos(true)? {
+            match blob_mgr.get_blob_idx_by_id(blob.blob_id()) {
+                Some(idx) => blob_idx_map.push(idx),
+                None => {
+                    let idx = blob_mgr.alloc_index()?;
+                    blob_mgr.add(BlobContext::from(ctx, &blob, ChunkSource::Parent));
+                    blob_idx_map.push(idx);
+                }
+            }
+        }
+
+        let mut files = HashMap::new();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> Result<()> {
+                if !inode.is_reg() {
+                    return Ok(());
+                }
+                let mut chunks = Vec::with_capacity(inode.get_chunk_count() as usize);
+                for idx in 0..inode.get_chunk_count() {
+                    let mut chunk =
+                        ChunkWrapper::from_chunk_info(inode.get_chunk_info(idx)?.deref());
+                    let blob_index = blob_idx_map
+                        .get(chunk.blob_index() as usize)
+                        .copied()
+                        .ok_or_else(|| anyhow!("invalid blob index {}", chunk.blob_index()))?;
+                    chunk.set_blob_index(blob_index);
+                    chunks.push(chunk);
+                }
+                let attr = inode.get_attr();
+                files.insert(
+                    path.to_path_buf(),
+                    BaseFile {
+                        size: attr.size,
+                        mtime: attr.mtime,
+                        mtime_nsec: attr.mtimensec,
+                        chunks,
+                    },
+                );
+                Ok(())
+            },
+        )?;
+
+        Ok(BaseBootstrap {
+            files,
+            verify,
+            reused_files: 0,
+            reused_size: 0,
+        })
+    }
+
+    /// Try to reuse data chunks from the base bootstrap for a regular file, return true if reused.
+    pub fn try_reuse(&mut self, ctx: &BuildContext, node: &mut Node) -> Result<bool> {
+        if !node.is_reg() || !node.chunks.is_empty() {
+            return Ok(false);
+        }
+        let base = match self.files.get(node.target()) {
+            Some(v)
+                if v.size == node.inode.size()
+                    && v.mtime == node.inode.mtime()
+                    && v.mtime_nsec == node.inode.mtime_nsec()
+                    && v.chunks.len() == node.inode.child_count() as usize =>
+            {
+                v
+            }
+            _ => return Ok(false),
+        };
+
+        if self.verify
+            && self.reused_files % REUSE_VERIFY_INTERVAL == 0
+            && !Self::verify_chunks(ctx, node, &base.chunks)?
+        {
+            warn!(
+                "content of {:?} doesn't match base bootstrap, rebuild it",
+                node.path()
+            );
+            return Ok(false);
+        }
+
+        // RAFS v5 inode digest is calculated from digests of all chunks.
+        if node.inode.is_v5() {
+            let mut hasher = RafsDigest::hasher(ctx.digester);
+            for chunk in base.chunks.iter() {
+                hasher.digest_update(chunk.id().as_ref());
+            }
+            node.inode.set_digest(hasher.digest_finalize());
+        }
+        for chunk in base.chunks.iter() {
+            self.reused_size += chunk.compressed_size() as u64;
+            node.chunks.push(NodeChunk {
+                source: ChunkSource::Parent,
+                inner: chunk.clone(),
+            });
+        }
+        self.reused_files += 1;
+
+        Ok(true)
+    }
+
+    fn verify_chunks(ctx: &BuildContext, node: &Node, chunks: &[ChunkWrapper]) -> Result<bool> {
+        let mut file = File::open(node.path())
+            .with_context(|| format!("failed to open node file {:?}", node.path()))?;
+        let mut buf = Vec::new();
+        for chunk in chunks {
+            buf.resize(chunk.uncompressed_size() as usize, 0);
+            if file.read_exact(&mut buf).is_err()
+                || &RafsDigest::from_buf(&buf, ctx.digester) != chunk.id()
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Get number of regular files whose data chunks are reused.
+    pub fn reused_files(&self) -> u64 {
+        self.reused_files
+    }
+
+    /// Get compressed size of reused data chunks.
+    pub fn reused_size(&self) -> u64 {
+        self.reused_size
+    }
+}
@@ -15,6 +15,7 @@ use super::context::{
 };
 use super::layout::BlobLayout;
 use super::node::Node;
+use super::pipeline::BlobPipeline;
 
 pub struct Blob {}
 
@@ -30,24 +31,43 @@ impl Blob {
             ConversionType::DirectoryToRafs => {
                 let (inodes, prefetch_entries) =
                     BlobLayout::layout_blob_simple(&ctx.prefetch, nodes)?;
-                let mut chunk_data_buf = vec![0u8; RAFS_MAX_CHUNK_SIZE as usize];
-                for (idx, inode) in inodes.iter().enumerate() {
-                    let node = &mut nodes[*inode];
-                    let size = node
-                        .dump_node_data(ctx, blob_mgr, blob_writer, &mut chunk_data_buf)
-                        .context("failed to dump blob chunks")?;
-                    if idx < prefetch_entries {
-                        if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
-                            blob_ctx.blob_prefetch_size += size;
+                if Self::use_pipeline(ctx) {
+                    Self::dump_with_pipeline(
+                        ctx,
+                        nodes,
+                        &inodes,
+                        prefetch_entries,
+                        blob_mgr,
+                        blob_writer,
+                    )?;
+                } else {
+                    let mut chunk_data_buf = vec![0u8; RAFS_MAX_CHUNK_SIZE as usize];
+                    for (idx, inode) in inodes.iter().enumerate() {
+                        let node = &mut nodes[*inode];
+                        if let Some(base) = blob_mgr.base_bootstrap.as_mut() {
+                            if base.try_reuse(ctx, node)? {
+                                ctx.report_file_progress(node.target());
+                                continue;
+                            }
+                        }
+                        let size = node
+                            .dump_node_data(ctx, blob_mgr, blob_writer, &mut chunk_data_buf)
+                            .context("failed to dump blob chunks")?;
+                        if idx < prefetch_entries {
+                            if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
+                                blob_ctx.blob_prefetch_size += size;
+                            }
                         }
                     }
                 }
                 if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
                     Self::dump_meta_data(ctx, blob_ctx, blob_writer)?;
                 }
+                Self::report_reused_data(blob_mgr);
             }
             ConversionType::TarToRafs
             | ConversionType::TargzToRafs
+            | ConversionType::TargzStreamToRafs
             | ConversionType::EStargzToRafs => {
                 if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
                     Self::dump_meta_data(ctx, blob_ctx, blob_writer)?;
@@ -87,6 +107,55 @@ impl Blob {
         Ok(())
     }
 
+    /// Check whether to compress chunk data with the multi-threaded pipeline, which only
+    /// supports fixed-size chunking.
+    fn use_pipeline(ctx: &BuildContext) -> bool {
+        ctx.threads > 1 && !ctx.chunking.is_cdc() && ctx.blob_zran_generator.is_none()
+    }
+
+    fn dump_with_pipeline(
+        ctx: &BuildContext,
+        nodes: &mut [Node],
+        inodes: &[usize],
+        prefetch_entries: usize,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+    ) -> Result<()> {
+        if let Some(base) = blob_mgr.base_bootstrap.as_mut() {
+            for inode in inodes {
+                let node = &mut nodes[*inode];
+                if base.try_reuse(ctx, node)? {
+                    ctx.report_file_progress(node.target());
+                }
+            }
+        }
+
+        let prefetch_size =
+            BlobPipeline::dump(ctx, nodes, inodes, prefetch_entries, blob_mgr, blob_writer)?;
+        if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
+            blob_ctx.blob_prefetch_size += prefetch_size;
+        }
+
+        Ok(())
+    }
+
+    fn report_reused_data(blob_mgr: &mut BlobManager) {
+        let (files, reused) = match blob_mgr.base_bootstrap.as_ref() {
+            Some(base) => (base.reused_files(), base.reused_size()),
+            None => return,
+        };
+        let written = blob_mgr
+            .get_current_blob()
+            .map(|(_, blob_ctx)| blob_ctx.compressed_blob_size)
+            .unwrap_or_default();
+        info!(
+            "reused {} bytes of data for {} files from base bootstrap, wrote {} bytes of new data",
+            reused, files, written
+        );
+        event_tracer!("reused_data_size", +reused);
+        event_tracer!("written_data_size", +written);
+    }
+
     fn dump_meta_data_raw(
         ctx: &BuildContext,
         blob_ctx: &mut BlobContext,
@@ -151,7 +220,7 @@ impl Blob {
         Ok(())
     }
 
-    pub(crate) fn dump_meta_data(
+    pub fn dump_meta_data(
         ctx: &BuildContext,
         blob_ctx: &mut BlobContext,
         blob_writer: &mut Option<ArtifactWriter>,
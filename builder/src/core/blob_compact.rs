@@ -565,7 +565,7 @@ impl BlobCompactor {
             false,
             0,
             rs.meta.get_compressor(),
-            rs.meta.get_digester(),
+            rs.meta.get_digest_algorithm(),
             rs.meta.explicit_uidgid(),
             // useless args
             WhiteoutSpec::Oci,
@@ -580,7 +580,7 @@ impl BlobCompactor {
             BootstrapManager::new(Some(ArtifactStorage::SingleFile(d_bootstrap)), None);
         let mut bootstrap_ctx = bootstrap_mgr.create_ctx(false)?;
         let mut ori_blob_mgr = BlobManager::new();
-        ori_blob_mgr.from_blob_table(&build_ctx, rs.superblock.get_blob_infos());
+        ori_blob_mgr.from_blob_table(&build_ctx, rs.superblock.get_blob_infos(true)?);
         if let Some(dict) = chunk_dict {
             ori_blob_mgr.set_chunk_dict(dict);
             ori_blob_mgr.extend_blob_table_from_chunk_dict(&build_ctx)?;
@@ -0,0 +1,259 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rewrite data chunks of a RAFS filesystem into a single data blob.
+//!
+//! After many incremental builds, an image may reference lots of small data blobs and data of a
+//! file may be scattered across them. The [BlobRewriter] reads all chunks referenced by inodes
+//! from the original data blobs, files in the prefetch table first, and writes them into a new
+//! data blob with the specified compression algorithm. Then a new bootstrap referring to the new
+//! data blob is generated. Chunks not referenced by any inode are dropped, and the original data
+//! blobs are left untouched.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nydus_rafs::metadata::chunk::ChunkWrapper;
+use nydus_rafs::metadata::{RafsMode, RafsSuper, RafsVersion};
+use nydus_rafs::RafsIoReader;
+use nydus_storage::backend::localfs::LocalFs;
+use nydus_storage::backend::BlobBackend;
+use nydus_storage::device::BlobInfo;
+use nydus_storage::meta::{BLOB_META_FEATURE_CHUNK_INFO_V2, BLOB_META_FEATURE_ZRAN};
+use nydus_utils::compress;
+use nydus_utils::digest::RafsDigest;
+use sha2::Digest;
+
+use crate::core::blob::Blob;
+use crate::core::bootstrap::Bootstrap;
+use crate::core::chunk_dict::HashChunkDict;
+use crate::core::context::{
+    ArtifactStorage, ArtifactWriter, BlobManager, BootstrapManager, BuildContext, BuildOutput,
+    ConversionType,
+};
+use crate::core::node::WhiteoutSpec;
+use crate::core::prefetch::Prefetch;
+use crate::core::tree::Tree;
+
+/// Rewrite data chunks of a RAFS filesystem into a single data blob.
+pub struct BlobRewriter {
+    bootstrap: PathBuf,
+    rs: RafsSuper,
+    blobs: Vec<Arc<BlobInfo>>,
+    backend: LocalFs,
+}
+
+impl BlobRewriter {
+    /// Create a rewriter to rewrite data of RAFS filesystem `bootstrap`, whose data blobs are
+    /// stored in `blob_dir`.
+    pub fn new(bootstrap: &Path, blob_dir: &Path) -> Result<Self> {
+        let rs = RafsSuper::load_from_metadata(bootstrap, RafsMode::Direct, true)
+            .with_context(|| format!("failed to load bootstrap {:?}", bootstrap))?;
+        let blobs = rs.superblock.get_blob_infos(true)?;
+        for blob in blobs.iter() {
+            if blob.is_encrypted()
+                || blob.is_foreign_layer()
+                || blob.is_legacy_stargz()
+                || blob.meta_flags() & BLOB_META_FEATURE_ZRAN != 0
+            {
+                bail!(
+                    "can't rewrite data blob {}, which is encrypted or not a nydus data blob",
+                    blob.blob_id()
+                );
+            }
+        }
+
+        let config = serde_json::json!({ "dir": blob_dir });
+        let backend = LocalFs::new(config, Some("rewriter"))
+            .with_context(|| format!("failed to create localfs backend for {:?}", blob_dir))?;
+
+        Ok(BlobRewriter {
+            bootstrap: bootstrap.to_path_buf(),
+            rs,
+            blobs,
+            backend,
+        })
+    }
+
+    /// Rewrite all data chunks into data blob `blob_path`, compressed by `compressor`, and
+    /// generate a new bootstrap `bootstrap_path` referring to the new data blob.
+    ///
+    /// The compression algorithm of the original filesystem is used if `compressor` is None.
+    pub fn rewrite(
+        &self,
+        bootstrap_path: PathBuf,
+        blob_path: PathBuf,
+        compressor: Option<compress::Algorithm>,
+    ) -> Result<BuildOutput> {
+        let rs = &self.rs;
+        let fs_version = RafsVersion::try_from(rs.meta.version)?;
+        let compressor = compressor.unwrap_or_else(|| rs.meta.get_compressor());
+        let prefetch_paths = self.get_prefetch_paths()?;
+        let prefetch = if prefetch_paths.is_empty() {
+            Prefetch::default()
+        } else {
+            Prefetch::from_paths(prefetch_paths)?
+        };
+
+        let mut ctx = BuildContext::new(
+            String::new(),
+            fs_version.is_v6(),
+            0,
+            compressor,
+            rs.meta.get_digest_algorithm(),
+            rs.meta.explicit_uidgid(),
+            WhiteoutSpec::Oci,
+            ConversionType::DirectoryToRafs,
+            PathBuf::new(),
+            prefetch,
+            Some(ArtifactStorage::SingleFile(blob_path.clone())),
+            None,
+            false,
+        );
+        ctx.set_fs_version(fs_version);
+        ctx.set_chunk_size(rs.meta.chunk_size);
+        if fs_version.is_v6() {
+            ctx.blob_meta_features |= BLOB_META_FEATURE_CHUNK_INFO_V2;
+        }
+
+        let mut bootstrap_mgr =
+            BootstrapManager::new(Some(ArtifactStorage::SingleFile(bootstrap_path)), None);
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx(false)?;
+        let mut tree = Tree::from_bootstrap(rs, &mut HashChunkDict::default())?;
+        let mut bootstrap = Bootstrap::new()?;
+        bootstrap.build(&mut ctx, &mut bootstrap_ctx, &mut tree)?;
+
+        // Put data of files in the prefetch table at the head of the data blob.
+        let mut inodes = ctx
+            .prefetch
+            .get_file_indexes()
+            .into_iter()
+            .map(|index| index as usize - 1)
+            .collect::<Vec<_>>();
+        for (index, node) in bootstrap_ctx.nodes.iter().enumerate() {
+            if node.is_reg() && !ctx.prefetch.contains(node) {
+                inodes.push(index);
+            }
+        }
+
+        let mut blob_mgr = BlobManager::new();
+        let mut blob_writer = Some(ArtifactWriter::new(
+            ArtifactStorage::SingleFile(blob_path),
+            false,
+        )?);
+        for index in inodes {
+            let node = &mut bootstrap_ctx.nodes[index];
+            // Chunks will be regenerated when dumping chunk data into the new data blob.
+            let chunks = std::mem::take(&mut node.chunks);
+            for chunk in chunks {
+                let data = self
+                    .read_chunk(&chunk.inner)
+                    .with_context(|| format!("failed to read data of {:?}", node.target()))?;
+                node.dump_chunk(
+                    &ctx,
+                    &mut blob_mgr,
+                    &mut blob_writer,
+                    chunk.inner.file_offset(),
+                    &data,
+                    None,
+                    chunk.inner,
+                    None,
+                )?;
+            }
+        }
+        if let Some((_, blob_ctx)) = blob_mgr.get_current_blob() {
+            Blob::dump_meta_data(&ctx, blob_ctx, &mut blob_writer)?;
+            blob_ctx.blob_id = format!("{:x}", blob_ctx.blob_hash.clone().finalize());
+        }
+
+        crate::dump_bootstrap(
+            &mut ctx,
+            &mut bootstrap_mgr,
+            &mut bootstrap_ctx,
+            &mut bootstrap,
+            &mut blob_mgr,
+            &mut blob_writer,
+        )?;
+
+        BuildOutput::new(&blob_mgr, &bootstrap_mgr.bootstrap_storage)
+    }
+
+    /// Read data of a chunk from the original data blob, and verify it by chunk digest.
+    fn read_chunk(&self, chunk: &ChunkWrapper) -> Result<Vec<u8>> {
+        // No data is stored in the data blob for zeroed chunks.
+        if chunk.is_zeroed() {
+            return Ok(vec![0u8; chunk.uncompressed_size() as usize]);
+        }
+
+        let blob = self
+            .blobs
+            .get(chunk.blob_index() as usize)
+            .ok_or_else(|| anyhow!("invalid blob index {}", chunk.blob_index()))?;
+        let reader = self
+            .backend
+            .get_reader(blob.blob_id())
+            .map_err(|e| anyhow!("failed to open blob {}, {:?}", blob.blob_id(), e))?;
+        let mut c_buf = vec![0u8; chunk.compressed_size() as usize];
+        let size = reader
+            .read(&mut c_buf, chunk.compressed_offset())
+            .map_err(|e| anyhow!("failed to read blob {}, {:?}", blob.blob_id(), e))?;
+        if size != c_buf.len() {
+            bail!(
+                "failed to read chunk at offset {} from blob {}, unexpected EOF",
+                chunk.compressed_offset(),
+                blob.blob_id()
+            );
+        }
+
+        let data = if chunk.is_compressed() {
+            let mut d_buf = vec![0u8; chunk.uncompressed_size() as usize];
+            compress::decompress(&c_buf, &mut d_buf, blob.compressor()).with_context(|| {
+                format!(
+                    "failed to decompress chunk at offset {} from blob {}",
+                    chunk.compressed_offset(),
+                    blob.blob_id()
+                )
+            })?;
+            d_buf
+        } else {
+            c_buf
+        };
+        let digester = self.rs.meta.get_digest_algorithm();
+        if &RafsDigest::from_buf(&data, digester) != chunk.id() {
+            bail!(
+                "data of chunk at offset {} from blob {} doesn't match chunk digest",
+                chunk.compressed_offset(),
+                blob.blob_id()
+            );
+        }
+
+        Ok(data)
+    }
+
+    /// Get paths of files in the prefetch table of the bootstrap.
+    fn get_prefetch_paths(&self) -> Result<Vec<String>> {
+        if self.rs.meta.prefetch_table_entries == 0 {
+            return Ok(Vec::new());
+        }
+
+        let bootstrap = &self.bootstrap;
+        let file = File::open(bootstrap)
+            .with_context(|| format!("failed to open bootstrap {:?}", bootstrap))?;
+        let mut reader = Box::new(file) as RafsIoReader;
+        let mut paths = Vec::new();
+        for ino in self.rs.get_prefetched_inos(&mut reader)? {
+            let path = self.rs.path_from_ino(ino as u64).with_context(|| {
+                format!("invalid prefetch table entry {} in {:?}", ino, bootstrap)
+            })?;
+            // Make sure that the path is absolute, as required by prefetch patterns.
+            let path = Path::new("/").join(path);
+            paths.push(path.to_string_lossy().to_string());
+        }
+
+        Ok(paths)
+    }
+}
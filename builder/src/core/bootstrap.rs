@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::ffi::OsString;
@@ -27,10 +27,10 @@ use super::context::{
 use super::node::{Node, WhiteoutType, OVERLAYFS_WHITEOUT_OPAQUE};
 use super::tree::Tree;
 
-pub(crate) const STARGZ_DEFAULT_BLOCK_SIZE: u32 = 4 << 20;
+pub const STARGZ_DEFAULT_BLOCK_SIZE: u32 = 4 << 20;
 const WRITE_PADDING_DATA: [u8; 4096] = [0u8; 4096];
 
-pub(crate) struct Bootstrap {}
+pub struct Bootstrap {}
 
 impl Bootstrap {
     /// Create a new instance of `Bootstrap`.
@@ -72,6 +72,9 @@ impl Bootstrap {
         if ctx.fs_version.is_v5() {
             tree.node.inode.set_ino(RAFS_V5_ROOT_INODE);
         }
+        if let Some(epoch) = ctx.source_date_epoch {
+            tree.node.clamp_mtime(epoch);
+        }
         ctx.prefetch.insert_if_need(&tree.node);
         nodes.push(tree.node.clone());
 
@@ -140,8 +143,10 @@ impl Bootstrap {
 
         // Maybe the parent is not a directory in multi-layers build scenario, so we check here.
         if parent.is_dir() {
-            // Sort children list by name, so that we can improve performance in fs read_dir using
-            // binary search.
+            // Sort children list by name bytewise, so that we can improve performance in fs read_dir
+            // using binary search. Together with allocating indexes for all children of a directory
+            // before descending into subdirectories, it also makes the inode/nid allocation order
+            // only depend on the directory tree, which is needed for reproducible build.
             tree.children
                 .sort_by_key(|child| child.node.name().to_os_string());
             parent.inode.set_child_index(index);
@@ -165,6 +170,9 @@ impl Bootstrap {
             let index = nodes.len() as u64 + 1;
             child.node.index = index;
             child.node.inode.set_parent(parent_ino);
+            if let Some(epoch) = ctx.source_date_epoch {
+                child.node.clamp_mtime(epoch);
+            }
 
             // Hardlink handle, all hardlink nodes' ino, nlink should be the same,
             // because the real_ino may be conflicted between different layers,
@@ -333,7 +341,7 @@ impl Bootstrap {
 
         // Reuse lower layer blob table,
         // we need to append the blob entry of upper layer to the table
-        blob_mgr.from_blob_table(ctx, rs.superblock.get_blob_infos());
+        blob_mgr.from_blob_table(ctx, rs.superblock.get_blob_infos(true)?);
 
         // Build node tree of lower layer from a bootstrap file, and add chunks
         // of lower node to layered_chunk_dict for chunk deduplication on next.
@@ -369,6 +377,22 @@ impl Bootstrap {
         }
     }
 
+    /// Count extended attribute entries and total size of their names and values.
+    ///
+    /// Hardlinks share the same extended attributes, so they are counted once.
+    fn xattr_stats(&self, nodes: &[Node]) -> (u64, u64) {
+        let mut inos = HashSet::new();
+        let mut entries = 0u64;
+        let mut bytes = 0u64;
+        for node in nodes {
+            if !node.xattrs.is_empty() && inos.insert(node.inode.ino()) {
+                entries += node.xattrs.count() as u64;
+                bytes += node.xattrs.total_bytes() as u64;
+            }
+        }
+        (entries, bytes)
+    }
+
     pub fn dump(
         &mut self,
         ctx: &mut BuildContext,
@@ -454,6 +478,7 @@ impl Bootstrap {
         if ctx.explicit_uidgid {
             super_block.set_explicit_uidgid();
         }
+        super_block.set_builder_version(&ctx.builder_version);
         if ctx.conversion_type == ConversionType::EStargzIndexToRef {
             super_block.set_block_size(STARGZ_DEFAULT_BLOCK_SIZE);
         }
@@ -485,6 +510,14 @@ impl Bootstrap {
         if has_xattr {
             super_block.set_has_xattr();
         }
+        let (xattr_entries, xattr_bytes) = self.xattr_stats(&bootstrap_ctx.nodes);
+        super_block.set_xattr_entry_count(xattr_entries);
+        super_block.set_xattr_total_bytes(xattr_bytes);
+
+        // Build provenance record follows inodes and chunks
+        let provenance = ctx.provenance().to_vec()?;
+        super_block.set_provenance_offset(inode_offset as u64);
+        super_block.set_provenance_size(provenance.len() as u64);
 
         // Dump super block
         super_block
@@ -529,6 +562,12 @@ impl Bootstrap {
             Result<()>
         )?;
 
+        // Dump build provenance record
+        bootstrap_ctx
+            .writer
+            .write_all(&provenance)
+            .context("failed to store provenance record")?;
+
         Ok(())
     }
 
@@ -552,7 +591,7 @@ impl Bootstrap {
 
         // get devt_slotoff
         let mut devtable: Vec<RafsV6Device> = Vec::new();
-        let blobs = blob_table.get_all();
+        let blobs = blob_table.get_all()?;
         let mut block_count = 0u32;
         for entry in blobs.iter() {
             let mut devslot = RafsV6Device::new();
@@ -648,6 +687,10 @@ impl Bootstrap {
         if ctx.explicit_uidgid {
             ext_sb.set_explicit_uidgid();
         }
+        if blobs.iter().any(|blob| blob.is_encrypted()) {
+            ext_sb.set_encrypted();
+        }
+        ext_sb.set_builder_version(&ctx.builder_version);
 
         // dump devtslot
         bootstrap_ctx
@@ -735,11 +778,23 @@ impl Bootstrap {
             chunk_table_offset, chunk_table_size
         );
 
+        // append build provenance record after chunk info table.
+        let provenance = ctx.provenance().to_vec()?;
+        ext_sb.set_provenance_offset(chunk_table_offset + chunk_table_size);
+        ext_sb.set_provenance_size(provenance.len() as u64);
+        bootstrap_ctx
+            .writer
+            .write_all(&provenance)
+            .context("failed to store provenance record")?;
+
         // EROFS does not have inode table, so we lose the chance to decide if this
         // image has xattr. So we have to rewrite extended super block.
         if ctx.has_xattr {
             ext_sb.set_has_xattr();
         }
+        let (xattr_entries, xattr_bytes) = self.xattr_stats(&bootstrap_ctx.nodes);
+        ext_sb.set_xattr_entry_count(xattr_entries);
+        ext_sb.set_xattr_total_bytes(xattr_bytes);
         bootstrap_ctx.writer.seek(SeekFrom::Start(ext_sb_offset))?;
         ext_sb
             .store(bootstrap_ctx.writer.as_mut())
@@ -0,0 +1,530 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use nydus_rafs::metadata::chunk::ChunkWrapper;
+use nydus_rafs::metadata::RafsChunkDict;
+use nydus_storage::backend::registry::Registry;
+use nydus_storage::backend::BlobBackend;
+use nydus_storage::device::BlobInfo;
+use nydus_utils::compress;
+use nydus_utils::digest::{self, RafsDigest};
+use sha2::{Digest, Sha256};
+
+/// Size of buffer to download remote chunk dictionaries.
+const CHUNK_DICT_DOWNLOAD_BUFFER_SIZE: usize = 0x100000;
+
+lazy_static! {
+    // Locks to serialize fetching the same remote chunk dictionary, indexed by digest.
+    static ref CHUNK_DICT_FETCH_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub struct DigestWithBlobIndex(pub RafsDigest, pub u32);
+
+/// Parameters of the RAFS filesystem which a chunk dictionary is built from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkDictParams {
+    pub chunk_size: u32,
+    pub compressor: compress::Algorithm,
+    pub digester: digest::Algorithm,
+}
+
+impl ChunkDictParams {
+    /// Check whether chunks of the chunk dictionary may be reused by a build with `params`.
+    ///
+    /// Chunks are matched by digests, so mismatched digest algorithms are always rejected. Other
+    /// mismatches are rejected unless `force` is true.
+    pub fn check_compatibility(&self, params: &ChunkDictParams, force: bool) -> Result<()> {
+        if self.digester != params.digester {
+            bail!(
+                "chunk dict uses digest algorithm {}, but the build uses {}",
+                self.digester,
+                params.digester
+            );
+        }
+
+        let mut reasons = Vec::new();
+        if self.chunk_size != params.chunk_size {
+            reasons.push(format!(
+                "chunk size 0x{:x} != 0x{:x}",
+                self.chunk_size, params.chunk_size
+            ));
+        }
+        if self.compressor != params.compressor {
+            reasons.push(format!(
+                "compressor {} != {}",
+                self.compressor, params.compressor
+            ));
+        }
+        if !reasons.is_empty() {
+            let reasons = reasons.join(", ");
+            if !force {
+                bail!(
+                    "chunk dict is incompatible with the build: {}, use --force-chunk-dict to use it anyway",
+                    reasons
+                );
+            }
+            warn!("chunk dict is incompatible with the build: {}", reasons);
+        }
+
+        Ok(())
+    }
+}
+
+pub trait ChunkDict: Sync + Send + 'static {
+    fn add_chunk(&mut self, chunk: ChunkWrapper);
+    fn get_chunk(&self, digest: &RafsDigest) -> Option<&ChunkWrapper>;
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>>;
+    fn get_blobs_by_inner_idx(&self, idx: u32) -> Option<&BlobInfo>;
+    fn set_real_blob_idx(&self, inner_idx: u32, out_idx: u32);
+    fn get_real_blob_idx(&self, inner_idx: u32) -> Option<u32>;
+    /// Get parameters of the filesystem the chunk dictionary is built from, if known.
+    fn get_params(&self) -> Option<&ChunkDictParams> {
+        None
+    }
+}
+
+impl ChunkDict for () {
+    fn add_chunk(&mut self, _chunk: ChunkWrapper) {}
+
+    fn get_chunk(&self, _digest: &RafsDigest) -> Option<&ChunkWrapper> {
+        None
+    }
+
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>> {
+        Vec::new()
+    }
+
+    fn get_blobs_by_inner_idx(&self, _idx: u32) -> Option<&BlobInfo> {
+        None
+    }
+
+    fn set_real_blob_idx(&self, _inner_idx: u32, _out_idx: u32) {
+        panic!("()::set_real_blob_idx() should not be invoked");
+    }
+
+    fn get_real_blob_idx(&self, inner_idx: u32) -> Option<u32> {
+        Some(inner_idx)
+    }
+}
+
+#[derive(Default)]
+pub struct HashChunkDict {
+    pub m: HashMap<RafsDigest, (ChunkWrapper, AtomicU32)>,
+    blobs: Vec<Arc<BlobInfo>>,
+    blob_idx_m: Mutex<BTreeMap<u32, u32>>,
+    params: Option<ChunkDictParams>,
+}
+
+impl ChunkDict for HashChunkDict {
+    fn add_chunk(&mut self, chunk: ChunkWrapper) {
+        if let Some(e) = self.m.get(chunk.id()) {
+            e.1.fetch_add(1, Ordering::AcqRel);
+        } else {
+            self.m
+                .insert(chunk.id().to_owned(), (chunk, AtomicU32::new(1)));
+        }
+    }
+
+    fn get_chunk(&self, digest: &RafsDigest) -> Option<&ChunkWrapper> {
+        self.m.get(digest).map(|e| &e.0)
+    }
+
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>> {
+        self.blobs.clone()
+    }
+
+    fn get_blobs_by_inner_idx(&self, idx: u32) -> Option<&BlobInfo> {
+        self.blobs.get(idx as usize).map(|b| b.as_ref())
+    }
+
+    fn set_real_blob_idx(&self, inner_idx: u32, out_idx: u32) {
+        self.blob_idx_m.lock().unwrap().insert(inner_idx, out_idx);
+    }
+
+    fn get_real_blob_idx(&self, inner_idx: u32) -> Option<u32> {
+        self.blob_idx_m.lock().unwrap().get(&inner_idx).copied()
+    }
+
+    fn get_params(&self) -> Option<&ChunkDictParams> {
+        self.params.as_ref()
+    }
+}
+
+impl HashChunkDict {
+    fn from_bootstrap_file(path: &Path) -> Result<Self> {
+        let dict = RafsChunkDict::from_metadata(path)
+            .with_context(|| format!("failed to open bootstrap file {:?}", path))?;
+        let meta = dict.meta();
+        let mut d = HashChunkDict {
+            m: HashMap::with_capacity(dict.chunk_count()),
+            blobs: dict.get_blob_infos()?,
+            blob_idx_m: Mutex::new(BTreeMap::new()),
+            params: Some(ChunkDictParams {
+                chunk_size: meta.chunk_size,
+                compressor: meta.get_compressor(),
+                digester: meta.get_digest_algorithm(),
+            }),
+        };
+
+        for chunk in dict.chunks() {
+            d.add_chunk(ChunkWrapper::from_chunk_info(chunk.as_ref()));
+        }
+
+        Ok(d)
+    }
+}
+
+/// Chunk dictionary bootstrap stored remotely, identified by its sha256 digest.
+#[derive(Debug, PartialEq, Eq)]
+enum RemoteChunkDict {
+    /// Blob `digest` in repository `repo` of registry `host`.
+    Registry {
+        host: String,
+        repo: String,
+        digest: String,
+    },
+    /// File served by a http(s) server.
+    Http { url: String, digest: String },
+}
+
+impl RemoteChunkDict {
+    /// Parse `registry://<host>/<repo>@sha256:<hex>` or `http(s)://<url>@sha256:<hex>`.
+    fn parse(uri: &str) -> Result<Option<Self>> {
+        let (is_registry, location) = if let Some(v) = uri.strip_prefix("registry://") {
+            (true, v)
+        } else if uri.starts_with("http://") || uri.starts_with("https://") {
+            (false, uri)
+        } else {
+            return Ok(None);
+        };
+
+        let (location, digest) = match location.rsplit_once("@sha256:") {
+            Some((l, d)) if !l.is_empty() => (l, d),
+            _ => bail!("chunk dict {} should end with @sha256:<digest>", uri),
+        };
+        if digest.len() != 64 || !digest.bytes().all(|c| c.is_ascii_hexdigit()) {
+            bail!("invalid sha256 digest of chunk dict {}", uri);
+        }
+        let digest = digest.to_ascii_lowercase();
+
+        if is_registry {
+            match location.split_once('/') {
+                Some((host, repo)) if !host.is_empty() && !repo.is_empty() => {
+                    Ok(Some(RemoteChunkDict::Registry {
+                        host: host.to_string(),
+                        repo: repo.to_string(),
+                        digest,
+                    }))
+                }
+                _ => bail!(
+                    "chunk dict {} should be registry://<host>/<repo>@<digest>",
+                    uri
+                ),
+            }
+        } else {
+            Ok(Some(RemoteChunkDict::Http {
+                url: location.to_string(),
+                digest,
+            }))
+        }
+    }
+
+    fn digest(&self) -> &str {
+        match self {
+            RemoteChunkDict::Registry { digest, .. } => digest,
+            RemoteChunkDict::Http { digest, .. } => digest,
+        }
+    }
+
+    /// Fetch the chunk dictionary into `cache_dir`, reusing the cached file if its digest matches.
+    ///
+    /// Concurrent fetches of the same chunk dictionary in a process are serialized, so it's only
+    /// downloaded once.
+    fn fetch(&self, cache_dir: &Path) -> Result<PathBuf> {
+        let lock = CHUNK_DICT_FETCH_LOCKS
+            .lock()
+            .unwrap()
+            .entry(self.digest().to_string())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().unwrap();
+
+        let path = cache_dir.join(self.digest());
+        if path.exists() {
+            match sha256_of_file(&path) {
+                Ok(digest) if digest == self.digest() => {
+                    info!("use cached chunk dict {:?}", path);
+                    return Ok(path);
+                }
+                _ => warn!("cached chunk dict {:?} is corrupted, download again", path),
+            }
+        }
+
+        fs::create_dir_all(cache_dir)
+            .with_context(|| format!("failed to create directory {:?}", cache_dir))?;
+        let tmp_path = cache_dir.join(format!("{}.{}.tmp", self.digest(), std::process::id()));
+        let result = self.download(&tmp_path).and_then(|digest| {
+            if digest != self.digest() {
+                bail!("digest mismatch, expect {}, got {}", self.digest(), digest);
+            }
+            fs::rename(&tmp_path, &path)
+                .with_context(|| format!("failed to rename {:?} to {:?}", tmp_path, path))
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.map(|_| path)
+    }
+
+    // Download the chunk dictionary into `path`, and return sha256 digest of the content.
+    fn download(&self, path: &Path) -> Result<String> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("failed to create file {:?}", path))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; CHUNK_DICT_DOWNLOAD_BUFFER_SIZE];
+
+        match self {
+            RemoteChunkDict::Registry { host, repo, digest } => {
+                let config = serde_json::json!({
+                    "scheme": "https",
+                    "host": host,
+                    "repo": repo,
+                    "retry_limit": 3,
+                });
+                let backend = Registry::new(config, Some("chunk-dict"))?;
+                let reader = backend
+                    .get_reader(digest)
+                    .map_err(|e| anyhow!("failed to get blob reader, {:?}", e))?;
+                let size = reader
+                    .blob_size()
+                    .map_err(|e| anyhow!("failed to get blob size, {:?}", e))?;
+                let mut offset = 0;
+                while offset < size {
+                    let len = std::cmp::min(buf.len() as u64, size - offset) as usize;
+                    let n = reader
+                        .read(&mut buf[..len], offset)
+                        .map_err(|e| anyhow!("failed to read blob, {:?}", e))?;
+                    if n == 0 {
+                        bail!("unexpected end of blob at offset {}", offset);
+                    }
+                    hasher.update(&buf[..n]);
+                    file.write_all(&buf[..n])?;
+                    offset += n as u64;
+                }
+                backend.shutdown();
+            }
+            RemoteChunkDict::Http { url, .. } => {
+                let mut resp = reqwest::blocking::get(url.as_str())?.error_for_status()?;
+                loop {
+                    let n = resp.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                    file.write_all(&buf[..n])?;
+                }
+            }
+        }
+        file.sync_all()?;
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+fn sha256_of_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parse a chunk dictionary argument string.
+///
+/// # Argument
+/// `arg` may be in inform of:
+/// - type=path: type of external source and corresponding path
+/// - path: type default to "bootstrap"
+///
+/// The path of bootstrap type may also be an URI of remote bootstrap with its sha256 digest,
+/// which is downloaded into a local cache file:
+/// - registry://<host>/<repo>@sha256:<hex>: blob in a container image registry
+/// - http(s)://<url>@sha256:<hex>: file on a http(s) server
+///
+/// for example:
+///     bootstrap=image.boot
+///     image.boot
+///     ~/image/image.boot
+///     registry://docker.io/org/dict@sha256:8d5b...
+///     boltdb=/var/db/dict.db (not supported yet)
+pub fn parse_chunk_dict_arg(arg: &str) -> Result<PathBuf> {
+    let (file_type, file_path) = match arg.find('=') {
+        None => ("bootstrap", arg),
+        Some(idx) => (&arg[0..idx], &arg[idx + 1..]),
+    };
+
+    info!("parse chunk dict argument {}={}", file_type, file_path);
+
+    match file_type {
+        "bootstrap" => match RemoteChunkDict::parse(file_path)? {
+            None => Ok(PathBuf::from(file_path)),
+            Some(remote) => {
+                let cache_dir = std::env::temp_dir().join("nydus-chunk-dict");
+                remote
+                    .fetch(&cache_dir)
+                    .with_context(|| format!("failed to fetch chunk dict {}", file_path))
+            }
+        },
+        _ => {
+            bail!("invalid chunk dict type {}", file_type);
+        }
+    }
+}
+
+/// Load a chunk dictionary from external source.
+pub fn import_chunk_dict(arg: &str) -> Result<Arc<dyn ChunkDict>> {
+    let file_path = parse_chunk_dict_arg(arg)?;
+    HashChunkDict::from_bootstrap_file(&file_path)
+        .with_context(|| format!("failed to load chunk dict {}", arg))
+        .map(|d| Arc::new(d) as Arc<dyn ChunkDict>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_rafs::metadata::RafsVersion;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_null_dict() {
+        let mut dict = Box::new(()) as Box<dyn ChunkDict>;
+
+        let chunk = ChunkWrapper::new(RafsVersion::V5);
+        dict.add_chunk(chunk.clone());
+        assert!(dict.get_chunk(chunk.id()).is_none());
+        assert_eq!(dict.get_blobs().len(), 0);
+        assert_eq!(dict.get_real_blob_idx(5).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_chunk_dict() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let mut source_path = PathBuf::from(root_dir);
+        source_path.push("../tests/texture/bootstrap/rafs-v5.boot");
+        let path = source_path.to_str().unwrap();
+        let dict = import_chunk_dict(path).unwrap();
+
+        assert!(dict.get_chunk(&RafsDigest::default()).is_none());
+        assert_eq!(dict.get_blobs().len(), 18);
+        dict.set_real_blob_idx(0, 10);
+        assert_eq!(dict.get_real_blob_idx(0), Some(10));
+        assert_eq!(dict.get_real_blob_idx(1), None);
+        assert!(dict.get_params().is_some());
+    }
+
+    #[test]
+    fn test_chunk_dict_compatibility() {
+        let dict = ChunkDictParams {
+            chunk_size: 0x400000,
+            compressor: compress::Algorithm::Zstd,
+            digester: digest::Algorithm::Sha256,
+        };
+        assert!(dict.check_compatibility(&dict, false).is_ok());
+
+        let mut build = dict.clone();
+        build.chunk_size = 0x100000;
+        build.compressor = compress::Algorithm::Lz4Block;
+        let err = dict.check_compatibility(&build, false).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("chunk size 0x400000 != 0x100000"));
+        assert!(msg.contains("compressor Zstd != Lz4Block"));
+        assert!(dict.check_compatibility(&build, true).is_ok());
+
+        build.digester = digest::Algorithm::Blake3;
+        assert!(dict.check_compatibility(&build, true).is_err());
+    }
+
+    #[test]
+    fn test_parse_remote_chunk_dict() {
+        let digest = "8d5b".repeat(16);
+        assert_eq!(RemoteChunkDict::parse("/path/to/dict.boot").unwrap(), None);
+        assert_eq!(
+            RemoteChunkDict::parse(&format!("registry://docker.io/org/dict@sha256:{}", digest))
+                .unwrap(),
+            Some(RemoteChunkDict::Registry {
+                host: "docker.io".to_string(),
+                repo: "org/dict".to_string(),
+                digest: digest.clone(),
+            })
+        );
+        assert_eq!(
+            RemoteChunkDict::parse(&format!("https://host/dict.boot@sha256:{}", digest)).unwrap(),
+            Some(RemoteChunkDict::Http {
+                url: "https://host/dict.boot".to_string(),
+                digest: digest.clone(),
+            })
+        );
+        assert!(RemoteChunkDict::parse("registry://docker.io/org/dict").is_err());
+        assert!(RemoteChunkDict::parse("registry://docker.io@sha256:1234").is_err());
+        assert!(RemoteChunkDict::parse(&format!("registry://dict@sha256:{}", digest)).is_err());
+        assert!(RemoteChunkDict::parse("http://host/dict@sha256:xyz").is_err());
+    }
+
+    #[test]
+    fn test_fetch_remote_chunk_dict() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let path = PathBuf::from(root_dir).join("../tests/texture/bootstrap/rafs-v5.boot");
+        let content = std::fs::read(&path).unwrap();
+        let digest = sha256_of_file(&path).unwrap();
+
+        // Serve the bootstrap for only one request, so the second fetch must hit the cache.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&content).unwrap();
+        });
+
+        let cache_dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        let remote = RemoteChunkDict::Http {
+            url: format!("http://{}/dict.boot", addr),
+            digest: digest.clone(),
+        };
+        let cached = remote.fetch(cache_dir.as_path()).unwrap();
+        server.join().unwrap();
+        assert_eq!(cached, cache_dir.as_path().join(&digest));
+        assert_eq!(remote.fetch(cache_dir.as_path()).unwrap(), cached);
+        assert!(HashChunkDict::from_bootstrap_file(&cached).is_ok());
+
+        // Corrupted cache files are not used, and download failures are reported.
+        std::fs::write(&cached, b"corrupted").unwrap();
+        assert!(remote.fetch(cache_dir.as_path()).is_err());
+        let remote = RemoteChunkDict::Http {
+            url: format!("http://{}/dict.boot", addr),
+            digest: "0".repeat(64),
+        };
+        assert!(remote.fetch(cache_dir.as_path()).is_err());
+        assert_eq!(std::fs::read_dir(cache_dir.as_path()).unwrap().count(), 1);
+    }
+}
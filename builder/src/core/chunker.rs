@@ -0,0 +1,365 @@
+// Copyright 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Strategies to split file data into chunks.
+//!
+//! Besides the default fixed-size chunking, content-defined chunking (CDC) based on FastCDC is
+//! supported to improve data de-duplication across image versions, because inserting or removing
+//! data in the middle of a file only changes chunks around the modified area. CDC generates chunks
+//! of variable size, so it's only supported by RAFS v5, RAFS v6 assumes uniform chunk size.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result};
+use nydus_rafs::metadata::{RafsVersion, RAFS_MAX_CHUNK_SIZE};
+
+use super::context::ConversionType;
+
+/// Minimum chunk size supported by chunking strategies.
+const MIN_CHUNK_SIZE: u32 = 0x1000;
+
+/// Strategy to split file data into chunks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChunkingStrategy {
+    /// Split file data into chunks of fixed size.
+    Fixed(u32),
+    /// Split file data into chunks of variable size by content-defined chunking.
+    Cdc(FastCdc),
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        Self::Fixed(nydus_rafs::metadata::RAFS_DEFAULT_CHUNK_SIZE as u32)
+    }
+}
+
+impl fmt::Display for ChunkingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(size) => write!(f, "fixed:{}", format_size(*size)),
+            Self::Cdc(cdc) => write!(
+                f,
+                "cdc:{}-{}-{}",
+                format_size(cdc.min_size),
+                format_size(cdc.avg_size),
+                format_size(cdc.max_size)
+            ),
+        }
+    }
+}
+
+impl FromStr for ChunkingStrategy {
+    type Err = Error;
+
+    /// Parse chunking strategy in form of `fixed:<size>` or `cdc:<min>-<avg>-<max>`, with sizes
+    /// like `4096`, `0x1000`, `256K` or `1M`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, param) = match s.split_once(':') {
+            Some(v) => v,
+            None => bail!(
+                "invalid chunking strategy {}, expect fixed:<size> or cdc:<min>-<avg>-<max>",
+                s
+            ),
+        };
+        match kind {
+            "fixed" => {
+                let size = parse_size(param)?;
+                if size < MIN_CHUNK_SIZE
+                    || size as u64 > RAFS_MAX_CHUNK_SIZE
+                    || !size.is_power_of_two()
+                {
+                    bail!(
+                        "invalid chunk size 0x{:x}, must be power of two and between 0x{:x}-0x{:x}",
+                        size,
+                        MIN_CHUNK_SIZE,
+                        RAFS_MAX_CHUNK_SIZE
+                    );
+                }
+                Ok(Self::Fixed(size))
+            }
+            "cdc" => {
+                let sizes = param
+                    .split('-')
+                    .map(parse_size)
+                    .collect::<Result<Vec<u32>>>()?;
+                if sizes.len() != 3 {
+                    bail!("invalid cdc parameters {}, expect <min>-<avg>-<max>", param);
+                }
+                Ok(Self::Cdc(FastCdc::new(sizes[0], sizes[1], sizes[2])?))
+            }
+            _ => bail!("unknown chunking strategy {}, expect fixed or cdc", kind),
+        }
+    }
+}
+
+impl ChunkingStrategy {
+    /// Check whether the strategy generates chunks of variable size.
+    pub fn is_cdc(&self) -> bool {
+        matches!(self, Self::Cdc(_))
+    }
+
+    /// Get maximum size of generated chunks, which is used as chunk size of the filesystem.
+    pub fn max_chunk_size(&self) -> u32 {
+        match self {
+            Self::Fixed(size) => *size,
+            Self::Cdc(cdc) => cdc.max_size,
+        }
+    }
+
+    /// Check whether the strategy is supported by the RAFS version and conversion type.
+    pub fn validate(&self, version: RafsVersion, conversion_type: ConversionType) -> Result<()> {
+        if let Self::Cdc(_) = self {
+            if !version.is_v5() {
+                bail!(
+                    "chunking strategy {} is only supported by RAFS v5, RAFS v6 requires chunks of uniform size",
+                    self
+                );
+            }
+            match conversion_type {
+                ConversionType::DirectoryToRafs
+                | ConversionType::TarToRafs
+                | ConversionType::TargzStreamToRafs => {}
+                _ => bail!(
+                    "chunking strategy {} conflicts with conversion type '{}'",
+                    self,
+                    conversion_type
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Content-defined chunker based on the FastCDC algorithm with normalized chunking.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FastCdc {
+    min_size: u32,
+    avg_size: u32,
+    max_size: u32,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl FastCdc {
+    /// Create a new chunker generating chunks with size in range `[min_size, max_size]`.
+    ///
+    /// `avg_size` must be power of two.
+    pub fn new(min_size: u32, avg_size: u32, max_size: u32) -> Result<Self> {
+        if min_size < MIN_CHUNK_SIZE
+            || min_size >= avg_size
+            || avg_size >= max_size
+            || max_size as u64 > RAFS_MAX_CHUNK_SIZE
+        {
+            bail!(
+                "invalid cdc chunk sizes 0x{:x}-0x{:x}-0x{:x}, expect 0x{:x} <= min < avg < max <= 0x{:x}",
+                min_size,
+                avg_size,
+                max_size,
+                MIN_CHUNK_SIZE,
+                RAFS_MAX_CHUNK_SIZE
+            );
+        }
+        if !avg_size.is_power_of_two() {
+            bail!(
+                "average cdc chunk size 0x{:x} must be power of two",
+                avg_size
+            );
+        }
+
+        // Use a harder mask before reaching the average size and an easier one after it, to
+        // normalize distribution of chunk sizes.
+        let bits = avg_size.trailing_zeros();
+        Ok(FastCdc {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: Self::mask(bits + 2),
+            mask_large: Self::mask(bits - 2),
+        })
+    }
+
+    /// Get minimum size of generated chunks.
+    pub fn min_size(&self) -> u32 {
+        self.min_size
+    }
+
+    /// Get average size of generated chunks.
+    pub fn avg_size(&self) -> u32 {
+        self.avg_size
+    }
+
+    /// Get maximum size of generated chunks.
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    /// Get size of the first chunk in `data`.
+    ///
+    /// The caller should provide at least `max_size` bytes of data unless reaching end of file.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let min_size = self.min_size as usize;
+        if data.len() <= min_size {
+            return data.len();
+        }
+        let end = std::cmp::min(data.len(), self.max_size as usize);
+        let normal = std::cmp::min(end, self.avg_size as usize);
+
+        let mut hash = 0u64;
+        for (idx, v) in data.iter().enumerate().take(normal).skip(min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*v as usize]);
+            if hash & self.mask_small == 0 {
+                return idx + 1;
+            }
+        }
+        for (idx, v) in data.iter().enumerate().take(end).skip(normal) {
+            hash = (hash << 1).wrapping_add(GEAR[*v as usize]);
+            if hash & self.mask_large == 0 {
+                return idx + 1;
+            }
+        }
+
+        end
+    }
+
+    // High bits of the gear hash cover more bytes than low bits, so build the mask from them.
+    fn mask(bits: u32) -> u64 {
+        !0u64 << (64 - bits)
+    }
+}
+
+/// Random values to compute gear hash, generated by splitmix64 so chunk boundaries are stable.
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed = 0x6e79_6475_735f_6364u64;
+    let mut idx = 0;
+    while idx < 256 {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut v = seed;
+        v = (v ^ (v >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        v = (v ^ (v >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[idx] = v ^ (v >> 31);
+        idx += 1;
+    }
+    table
+}
+
+fn parse_size(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let (num, unit) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1u64 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1u64 << 20),
+        _ => (s, 1u64),
+    };
+    let size = if let Some(hex) = num.strip_prefix("0x").or_else(|| num.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        num.parse::<u64>()
+    }
+    .with_context(|| format!("invalid chunk size {}", s))?;
+
+    size.checked_mul(unit)
+        .filter(|v| *v <= u32::MAX as u64)
+        .map(|v| v as u32)
+        .ok_or_else(|| anyhow!("invalid chunk size {}", s))
+}
+
+fn format_size(size: u32) -> String {
+    if size != 0 && size % (1 << 20) == 0 {
+        format!("{}M", size >> 20)
+    } else if size != 0 && size % (1 << 10) == 0 {
+        format!("{}K", size >> 10)
+    } else {
+        size.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chunking_strategy() {
+        let fixed: ChunkingStrategy = "fixed:1M".parse().unwrap();
+        assert_eq!(fixed, ChunkingStrategy::Fixed(0x100000));
+        assert!(!fixed.is_cdc());
+        assert_eq!(fixed.to_string(), "fixed:1M");
+        assert_eq!(
+            "fixed:0x1000".parse::<ChunkingStrategy>().unwrap(),
+            ChunkingStrategy::Fixed(0x1000)
+        );
+        assert!("fixed:3K".parse::<ChunkingStrategy>().is_err());
+        assert!("fixed:32M".parse::<ChunkingStrategy>().is_err());
+
+        let cdc: ChunkingStrategy = "cdc:256K-1M-4M".parse().unwrap();
+        assert!(cdc.is_cdc());
+        assert_eq!(cdc.max_chunk_size(), 0x400000);
+        assert_eq!(cdc.to_string(), "cdc:256K-1M-4M");
+        assert!("cdc:256K-1M".parse::<ChunkingStrategy>().is_err());
+        assert!("cdc:1M-256K-4M".parse::<ChunkingStrategy>().is_err());
+        assert!("cdc:256K-768K-4M".parse::<ChunkingStrategy>().is_err());
+        assert!("cdc:1K-4K-16K".parse::<ChunkingStrategy>().is_err());
+        assert!("rabin:1M".parse::<ChunkingStrategy>().is_err());
+        assert!("1M".parse::<ChunkingStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_validate_chunking_strategy() {
+        let cdc: ChunkingStrategy = "cdc:16K-64K-256K".parse().unwrap();
+        assert!(cdc
+            .validate(RafsVersion::V5, ConversionType::DirectoryToRafs)
+            .is_ok());
+        assert!(cdc
+            .validate(RafsVersion::V6, ConversionType::DirectoryToRafs)
+            .is_err());
+        assert!(cdc
+            .validate(RafsVersion::V5, ConversionType::EStargzToRafs)
+            .is_err());
+        assert!(ChunkingStrategy::default()
+            .validate(RafsVersion::V6, ConversionType::TargzToRef)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_fastcdc_cut() {
+        let cdc = FastCdc::new(0x1000, 0x4000, 0x10000).unwrap();
+        let mut data = vec![0u8; 0x100000];
+        let mut seed = 1u32;
+        for v in data.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *v = (seed >> 16) as u8;
+        }
+
+        let split = |data: &[u8]| {
+            let mut pos = 0;
+            let mut cuts = Vec::new();
+            while pos < data.len() {
+                let size = cdc.cut(&data[pos..]);
+                assert!(size <= 0x10000);
+                assert!(size >= 0x1000 || pos + size == data.len());
+                pos += size;
+                cuts.push(pos);
+            }
+            cuts
+        };
+        let cuts = split(&data);
+        assert_eq!(*cuts.last().unwrap(), data.len());
+        // Average chunk size should be around 16K.
+        assert!(cuts.len() > 0x100000 / 0x10000 * 2);
+        assert!(cuts.len() < 0x100000 / 0x1000);
+
+        // Inserting data at the head only changes boundaries of leading chunks.
+        let mut shifted = vec![0x5au8; 100];
+        shifted.extend_from_slice(&data);
+        let shifted_cuts: Vec<usize> = split(&shifted).iter().map(|v| v - 100).collect();
+        let common = cuts.iter().filter(|v| shifted_cuts.contains(v)).count();
+        assert!(common >= cuts.len() - 2);
+
+        // Small data and zero-filled data.
+        assert_eq!(cdc.cut(&data[..0x800]), 0x800);
+        assert!(cdc.cut(&[0u8; 0x20000]) <= 0x10000);
+    }
+}
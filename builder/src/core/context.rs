@@ -5,14 +5,15 @@
 //! Struct to maintain context information for the image builder.
 
 use std::any::Any;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fs::{remove_file, rename, File, OpenOptions};
-use std::io::{BufWriter, Cursor, Read, Seek, Write};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Display, Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Error, Result};
 use sha2::{Digest, Sha256};
@@ -24,17 +25,20 @@ use nydus_rafs::metadata::layout::v5::RafsV5BlobTable;
 use nydus_rafs::metadata::layout::v6::{RafsV6BlobTable, EROFS_BLOCK_SIZE, EROFS_INODE_SLOT_SIZE};
 use nydus_rafs::metadata::layout::RafsBlobTable;
 use nydus_rafs::metadata::{Inode, RAFS_DEFAULT_CHUNK_SIZE};
-use nydus_rafs::metadata::{RafsSuperFlags, RafsVersion};
+use nydus_rafs::metadata::{RafsProvenance, RafsSuperFlags, RafsVersion};
 use nydus_rafs::{RafsIoReader, RafsIoWrite};
 use nydus_storage::device::{BlobFeatures, BlobInfo};
 use nydus_storage::meta::{
     BlobChunkInfoV2Ondisk, BlobMetaChunkArray, BlobMetaChunkInfo, BlobMetaHeaderOndisk,
     ZranContextGenerator, BLOB_META_FEATURE_4K_ALIGNED, BLOB_META_FEATURE_CHUNK_INFO_V2,
-    BLOB_META_FEATURE_SEPARATE, BLOB_META_FEATURE_ZRAN,
+    BLOB_META_FEATURE_FOREIGN_LAYER, BLOB_META_FEATURE_SEPARATE, BLOB_META_FEATURE_ZRAN,
 };
+use nydus_utils::crypt::{self, CipherKey, KEY_REF_LEN};
 use nydus_utils::{compress, digest, div_round_up, round_down_4k};
 
+use super::base_bootstrap::BaseBootstrap;
 use super::chunk_dict::{ChunkDict, HashChunkDict};
+use super::chunker::ChunkingStrategy;
 use super::node::{ChunkSource, Node, WhiteoutSpec};
 use super::prefetch::{Prefetch, PrefetchPolicy};
 
@@ -52,6 +56,7 @@ pub enum ConversionType {
     TargzToRafs,
     TargzToStargz,
     TargzToRef,
+    TargzStreamToRafs,
     TarToStargz,
     TarToRafs,
 }
@@ -69,12 +74,14 @@ impl FromStr for ConversionType {
             "dir-rafs" => Ok(Self::DirectoryToRafs),
             "dir-stargz" => Ok(Self::DirectoryToStargz),
             "dir-targz" => Ok(Self::DirectoryToTargz),
+            "estargz" => Ok(Self::EStargzIndexToRef),
             "estargz-rafs" => Ok(Self::EStargzToRafs),
             "estargz-ref" => Ok(Self::EStargzToRef),
             "estargztoc-ref" => Ok(Self::EStargzIndexToRef),
             "targz-rafs" => Ok(Self::TargzToRafs),
             "targz-stargz" => Ok(Self::TargzToStargz),
             "targz-ref" => Ok(Self::TargzToRef),
+            "targz-stream" => Ok(Self::TargzStreamToRafs),
             "tar-rafs" => Ok(Self::TarToRafs),
             "tar-stargz" => Ok(Self::TarToStargz),
             // kept for backward compatibility
@@ -97,6 +104,7 @@ impl fmt::Display for ConversionType {
             ConversionType::TargzToRafs => write!(f, "targz-rafs"),
             ConversionType::TargzToStargz => write!(f, "targz-ref"),
             ConversionType::TargzToRef => write!(f, "targz-ref"),
+            ConversionType::TargzStreamToRafs => write!(f, "targz-stream"),
             ConversionType::TarToRafs => write!(f, "tar-rafs"),
             ConversionType::TarToStargz => write!(f, "tar-stargz"),
         }
@@ -109,6 +117,8 @@ pub enum ArtifactStorage {
     SingleFile(PathBuf),
     // Will rename it from tmp file as user didn't specify a name.
     FileDir(PathBuf),
+    // Copy generated data into the buffer when finalizing.
+    Memory(ArtifactBuffer),
 }
 
 impl ArtifactStorage {
@@ -116,10 +126,43 @@ impl ArtifactStorage {
         match self {
             ArtifactStorage::SingleFile(p) => p.display(),
             ArtifactStorage::FileDir(p) => p.display(),
+            ArtifactStorage::Memory(_) => Path::new("<memory>").display(),
         }
     }
 }
 
+/// ArtifactBuffer is an in-memory buffer shared between the builder and its caller, to receive
+/// bootstrap or blob data generated by the builder.
+#[derive(Clone, Default)]
+pub struct ArtifactBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl ArtifactBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of data in the buffer.
+    pub fn data(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Get size of data in the buffer.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Check whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for ArtifactBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArtifactBuffer({} bytes)", self.len())
+    }
+}
+
 impl Default for ArtifactStorage {
     fn default() -> Self {
         Self::SingleFile(PathBuf::new())
@@ -244,11 +287,15 @@ impl ArtifactWriter {
                     tmp_file: None,
                 })
             }
-            ArtifactStorage::FileDir(ref p) => {
+            ArtifactStorage::FileDir(_) | ArtifactStorage::Memory(_) => {
                 // Better we can use open(2) O_TMPFILE, but for compatibility sake, we delay this job.
                 // TODO: Blob dir existence?
-                let tmp = TempFile::new_in(p)
-                    .with_context(|| format!("failed to create temp file in {}", p.display()))?;
+                let tmp = match storage {
+                    ArtifactStorage::FileDir(ref p) => TempFile::new_in(p).with_context(|| {
+                        format!("failed to create temp file in {}", p.display())
+                    })?,
+                    _ => TempFile::new().context("failed to create temp file")?,
+                };
                 let tmp2 = tmp.as_file().try_clone()?;
                 let reader = OpenOptions::new()
                     .read(true)
@@ -297,7 +344,7 @@ impl ArtifactWriter {
     pub fn finalize(&mut self, name: Option<String>) -> Result<()> {
         self.file.flush()?;
 
-        if let Some(n) = name {
+        if let Some(n) = name.as_ref() {
             if let ArtifactStorage::FileDir(s) = &self.storage {
                 let path = Path::new(s).join(n);
                 if !path.exists() {
@@ -320,6 +367,17 @@ impl ArtifactWriter {
             }
         }
 
+        if let ArtifactStorage::Memory(buf) = &self.storage {
+            let mut data = buf.0.lock().unwrap();
+            data.clear();
+            if name.is_some() {
+                self.reader.seek(SeekFrom::Start(0))?;
+                self.reader
+                    .read_to_end(&mut data)
+                    .context("failed to copy data into memory buffer")?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -352,6 +410,13 @@ pub struct BlobContext {
     pub chunk_size: u32,
     /// Whether the blob is from chunk dict.
     pub chunk_source: ChunkSource,
+
+    /// Encryption algorithm for chunks in the blob.
+    pub cipher: crypt::Algorithm,
+    /// Reference to the master key to wrap the data key.
+    pub cipher_key_ref: [u8; KEY_REF_LEN],
+    /// Data key wrapped by the master key.
+    pub cipher_wrapped_key: Vec<u8>,
 }
 
 impl BlobContext {
@@ -378,6 +443,10 @@ impl BlobContext {
             chunk_count: 0,
             chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
             chunk_source: ChunkSource::Build,
+
+            cipher: crypt::Algorithm::None,
+            cipher_key_ref: [0u8; KEY_REF_LEN],
+            cipher_wrapped_key: Vec::new(),
         };
 
         if features & BLOB_META_FEATURE_4K_ALIGNED != 0 {
@@ -392,6 +461,9 @@ impl BlobContext {
         if features & BLOB_META_FEATURE_ZRAN != 0 {
             blob_ctx.blob_meta_header.set_ci_zran(true);
         }
+        if features & BLOB_META_FEATURE_FOREIGN_LAYER != 0 {
+            blob_ctx.blob_meta_header.set_foreign_layer(true);
+        }
 
         blob_ctx
     }
@@ -406,6 +478,11 @@ impl BlobContext {
         blob_ctx.chunk_size = blob.chunk_size();
         blob_ctx.chunk_source = chunk_source;
         blob_ctx.blob_meta_header.set_4k_aligned(ctx.aligned_chunk);
+        blob_ctx.set_cipher_info(
+            blob.cipher(),
+            *blob.cipher_key_ref(),
+            blob.cipher_wrapped_key(),
+        );
 
         if blob.meta_ci_is_valid() {
             blob_ctx
@@ -435,6 +512,17 @@ impl BlobContext {
         self.chunk_size = chunk_size;
     }
 
+    pub fn set_cipher_info(
+        &mut self,
+        cipher: crypt::Algorithm,
+        key_ref: [u8; KEY_REF_LEN],
+        wrapped_key: &[u8],
+    ) {
+        self.cipher = cipher;
+        self.cipher_key_ref = key_ref;
+        self.cipher_wrapped_key = wrapped_key.to_vec();
+    }
+
     // TODO: check the logic to reset prefetch size
     pub fn set_blob_prefetch_size(&mut self, ctx: &BuildContext) {
         if (self.compressed_blob_size > 0
@@ -511,6 +599,44 @@ impl BlobContext {
     }
 }
 
+/// Statistics about data chunks de-duplicated by the chunk dict.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkDedupStats {
+    /// Number of data chunks generated from source files.
+    pub total_chunks: u64,
+    /// Uncompressed size of data chunks generated from source files.
+    pub total_size: u64,
+    /// Number of data chunks found in the chunk dict.
+    pub dict_chunks: u64,
+    /// Uncompressed size of data chunks found in the chunk dict.
+    pub dict_size: u64,
+}
+
+impl ChunkDedupStats {
+    /// Get ratio of data de-duplicated by the chunk dict, in percentage of uncompressed size.
+    pub fn ratio(&self) -> f64 {
+        if self.total_size == 0 {
+            0.0
+        } else {
+            self.dict_size as f64 * 100.0 / self.total_size as f64
+        }
+    }
+}
+
+impl fmt::Display for ChunkDedupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% ({}/{} chunks, 0x{:x}/0x{:x} bytes)",
+            self.ratio(),
+            self.dict_chunks,
+            self.total_chunks,
+            self.dict_size,
+            self.total_size
+        )
+    }
+}
+
 /// BlobManager stores all blob related information during build.
 pub struct BlobManager {
     /// Some layers may not have a blob (only have metadata), so Option
@@ -527,6 +653,10 @@ pub struct BlobManager {
     /// Used for chunk data de-duplication between layers (with `--parent-bootstrap`)
     /// or within layer (with `--inline-bootstrap`).
     pub layered_chunk_dict: HashChunkDict,
+    /// Base bootstrap to reuse data chunks of unchanged files (with `--base-bootstrap`).
+    pub base_bootstrap: Option<BaseBootstrap>,
+    /// Statistics about chunks de-duplicated by the chunk dict.
+    pub dedup_stats: ChunkDedupStats,
 }
 
 impl BlobManager {
@@ -536,6 +666,8 @@ impl BlobManager {
             current_blob_index: None,
             global_chunk_dict: Arc::new(()),
             layered_chunk_dict: HashChunkDict::default(),
+            base_bootstrap: None,
+            dedup_stats: ChunkDedupStats::default(),
         }
     }
 
@@ -544,6 +676,13 @@ impl BlobManager {
             BlobContext::new(ctx.blob_id.clone(), ctx.blob_offset, ctx.blob_meta_features);
         blob_ctx.set_chunk_size(ctx.chunk_size);
         blob_ctx.set_meta_info_enabled(ctx.fs_version == RafsVersion::V6);
+        if let Some(cipher_ctx) = ctx.cipher_ctx.as_ref() {
+            blob_ctx.set_cipher_info(
+                cipher_ctx.cipher,
+                cipher_ctx.key_ref,
+                &cipher_ctx.wrapped_key,
+            );
+        }
 
         Ok(blob_ctx)
     }
@@ -673,6 +812,9 @@ impl BlobManager {
             let mut flags = RafsSuperFlags::empty();
             match &mut blob_table {
                 RafsBlobTable::V5(table) => {
+                    if !ctx.cipher.is_none() {
+                        bail!("Rafs v5 doesn't support encrypted data blobs");
+                    }
                     flags |= RafsSuperFlags::from(build_ctx.compressor);
                     flags |= RafsSuperFlags::from(build_ctx.digester);
                     table.add(
@@ -690,7 +832,7 @@ impl BlobManager {
                 RafsBlobTable::V6(table) => {
                     flags |= RafsSuperFlags::from(build_ctx.compressor);
                     flags |= RafsSuperFlags::from(build_ctx.digester);
-                    table.add(
+                    let blob_index = table.add(
                         blob_id,
                         0,
                         blob_prefetch_size,
@@ -702,6 +844,14 @@ impl BlobManager {
                         flags,
                         ctx.blob_meta_header,
                     );
+                    if !ctx.cipher.is_none() {
+                        table.set_cipher_info(
+                            blob_index,
+                            ctx.cipher,
+                            ctx.cipher_key_ref,
+                            &ctx.cipher_wrapped_key,
+                        )?;
+                    }
                 }
             }
         }
@@ -834,6 +984,8 @@ pub struct BuildContext {
     pub whiteout_spec: WhiteoutSpec,
     /// Chunk slice size.
     pub chunk_size: u32,
+    /// Strategy to split file data into chunks.
+    pub chunking: ChunkingStrategy,
     /// Version number of output metadata and data blob.
     pub fs_version: RafsVersion,
 
@@ -854,6 +1006,21 @@ pub struct BuildContext {
     pub blob_meta_features: u32,
     pub inline_bootstrap: bool,
     pub has_xattr: bool,
+    /// Context to encrypt chunk data, `None` if encryption is disabled.
+    pub cipher_ctx: Option<CipherContext>,
+    /// Version of the builder, recorded into the generated metadata blob.
+    pub builder_version: String,
+    /// Generate byte-identical metadata and data blobs from the same source.
+    pub reproducible: bool,
+    /// Clamp modification time of inodes to the timestamp, in seconds since the Unix epoch.
+    pub source_date_epoch: Option<u64>,
+    /// User annotations, recorded into build provenance of the generated metadata blob.
+    pub annotations: BTreeMap<String, String>,
+    /// Callbacks to report building progress.
+    pub progress: Option<Arc<dyn BuildProgress>>,
+    /// Number of worker threads to compress chunk data, data is dumped by the calling thread if
+    /// it's not bigger than 1.
+    pub threads: usize,
 }
 
 impl BuildContext {
@@ -883,6 +1050,7 @@ impl BuildContext {
             whiteout_spec,
 
             chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            chunking: ChunkingStrategy::default(),
             fs_version: RafsVersion::default(),
 
             conversion_type: source_type,
@@ -895,6 +1063,13 @@ impl BuildContext {
             blob_meta_features: 0,
             inline_bootstrap,
             has_xattr: false,
+            cipher_ctx: None,
+            builder_version: String::new(),
+            reproducible: false,
+            source_date_epoch: None,
+            annotations: BTreeMap::new(),
+            progress: None,
+            threads: 1,
         }
     }
 
@@ -904,9 +1079,67 @@ impl BuildContext {
 
     pub fn set_chunk_size(&mut self, chunk_size: u32) {
         self.chunk_size = chunk_size;
+        self.chunking = ChunkingStrategy::Fixed(chunk_size);
+    }
+
+    /// Set strategy to split file data into chunks, the maximum chunk size is used as chunk size.
+    pub fn set_chunking(&mut self, chunking: ChunkingStrategy) {
+        self.chunk_size = chunking.max_chunk_size();
+        self.chunking = chunking;
+    }
+
+    /// Get build provenance of the generated metadata blob.
+    ///
+    /// The creation time is `source_date_epoch` if specified, to support reproducible builds.
+    pub fn provenance(&self) -> RafsProvenance {
+        let created = self.source_date_epoch.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+        RafsProvenance {
+            builder_version: self.builder_version.clone(),
+            source_type: self.conversion_type.to_string(),
+            compressor: self.compressor.to_string(),
+            chunk_size: self.chunk_size,
+            created,
+            annotations: self.annotations.clone(),
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: Arc<dyn BuildProgress>) {
+        self.progress = Some(progress);
+    }
+
+    /// Report that data of a file has been dumped into or reused for the data blob.
+    pub fn report_file_progress(&self, path: &Path) {
+        if let Some(progress) = self.progress.as_ref() {
+            progress.file_done(path);
+        }
+    }
+
+    /// Report that data blob size grows from `prev_size` to `size`, once per megabyte.
+    pub fn report_blob_progress(&self, prev_size: u64, size: u64) {
+        if let Some(progress) = self.progress.as_ref() {
+            if prev_size >> 20 != size >> 20 {
+                progress.blob_data_written(size);
+            }
+        }
     }
 }
 
+/// Callbacks to report progress of building a RAFS filesystem.
+pub trait BuildProgress: Send + Sync {
+    /// Called after data of a regular file has been dumped into or reused for the data blob,
+    /// with the path of the file in the RAFS filesystem.
+    fn file_done(&self, _path: &Path) {}
+
+    /// Called each time another megabyte of compressed data has been written into the data blob,
+    /// with the total size written so far.
+    fn blob_data_written(&self, _size: u64) {}
+}
+
 impl Default for BuildContext {
     fn default() -> Self {
         Self {
@@ -919,6 +1152,7 @@ impl Default for BuildContext {
             whiteout_spec: WhiteoutSpec::default(),
 
             chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            chunking: ChunkingStrategy::default(),
             fs_version: RafsVersion::default(),
 
             conversion_type: ConversionType::default(),
@@ -931,10 +1165,45 @@ impl Default for BuildContext {
             blob_meta_features: 0,
             has_xattr: true,
             inline_bootstrap: false,
+            cipher_ctx: None,
+            builder_version: String::new(),
+            reproducible: false,
+            source_date_epoch: None,
+            annotations: BTreeMap::new(),
+            progress: None,
+            threads: 1,
         }
     }
 }
 
+/// CipherContext holds the per-image data key to encrypt chunk data.
+#[derive(Clone)]
+pub struct CipherContext {
+    /// Encryption algorithm for chunk data.
+    pub cipher: crypt::Algorithm,
+    /// Data key to encrypt chunk data.
+    pub key: CipherKey,
+    /// Reference to the master key to wrap the data key.
+    pub key_ref: [u8; KEY_REF_LEN],
+    /// Data key wrapped by the master key.
+    pub wrapped_key: Vec<u8>,
+}
+
+impl CipherContext {
+    /// Generate a new data key, and wrap it with the master key identified by `key_id`.
+    pub fn new(key_id: &str, master: &CipherKey) -> Result<Self> {
+        let key = CipherKey::generate().context("failed to generate data key")?;
+        let wrapped_key = crypt::wrap_key(master, &key).context("failed to wrap data key")?;
+
+        Ok(CipherContext {
+            cipher: crypt::Algorithm::Aes256Gcm,
+            key,
+            key_ref: crypt::key_ref(key_id),
+            wrapped_key,
+        })
+    }
+}
+
 /// BuildOutput represents the output in this build.
 #[derive(Default, Debug, Clone)]
 pub struct BuildOutput {
@@ -944,6 +1213,12 @@ pub struct BuildOutput {
     pub blob_size: Option<u64>,
     /// File path for the metadata blob.
     pub bootstrap_path: Option<String>,
+    /// Number of chunks in the output blob.
+    pub chunk_count: u32,
+    /// The uncompressed size of output blob in this build.
+    pub uncompressed_blob_size: Option<u64>,
+    /// Data de-duplication statistics against the chunk dict, `None` if no chunk dict is used.
+    pub dedup_stats: Option<ChunkDedupStats>,
 }
 
 impl fmt::Display for BuildOutput {
@@ -959,6 +1234,9 @@ impl fmt::Display for BuildOutput {
             self.blob_size.unwrap_or_default()
         )?;
         write!(f, "data blobs: {:?}", self.blobs)?;
+        if let Some(stats) = self.dedup_stats.as_ref() {
+            write!(f, "\nchunk dict dedup ratio: {}", stats)?;
+        }
         Ok(())
     }
 }
@@ -970,6 +1248,16 @@ impl BuildOutput {
     ) -> Result<BuildOutput> {
         let blobs = blob_mgr.get_blob_ids();
         let blob_size = blob_mgr.get_last_blob().map(|b| b.compressed_blob_size);
+        let chunk_count = blob_mgr
+            .get_last_blob()
+            .map(|b| b.chunk_count)
+            .unwrap_or_default();
+        let uncompressed_blob_size = blob_mgr.get_last_blob().map(|b| b.uncompressed_blob_size);
+        let dedup_stats = if blob_mgr.global_chunk_dict.get_blobs().is_empty() {
+            None
+        } else {
+            Some(blob_mgr.dedup_stats)
+        };
         let bootstrap_path = if let Some(ArtifactStorage::SingleFile(p)) = bootstrap_storage {
             Some(p.display().to_string())
         } else {
@@ -980,6 +1268,9 @@ impl BuildOutput {
             blobs,
             blob_size,
             bootstrap_path,
+            chunk_count,
+            uncompressed_blob_size,
+            dedup_stats,
         })
     }
 }
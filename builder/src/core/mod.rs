@@ -0,0 +1,17 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod base_bootstrap;
+pub mod blob;
+pub mod blob_compact;
+pub mod blob_rewrite;
+pub mod bootstrap;
+pub mod chunk_dict;
+pub mod chunker;
+pub mod context;
+pub mod layout;
+pub mod node;
+pub mod pipeline;
+pub mod prefetch;
+pub mod tree;
@@ -3,6 +3,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::borrow::Cow;
+use std::cmp;
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display, Formatter, Result as FmtResult};
@@ -33,11 +35,14 @@ use nydus_rafs::metadata::{Inode, RafsStore, RafsVersion};
 use nydus_rafs::RafsIoWrite;
 use nydus_storage::meta::{BlobChunkInfoV2Ondisk, BlobMetaChunkInfo, BLOB_META_FEATURE_ZRAN};
 use nydus_utils::compress;
-use nydus_utils::digest::{DigestHasher, RafsDigest};
+use nydus_utils::digest::{DigestHasher, RafsDigest, RafsDigestHasher};
 use nydus_utils::{div_round_up, round_down_4k, round_up, try_round_up_4k, ByteSize};
 
 use super::chunk_dict::{ChunkDict, DigestWithBlobIndex};
-use super::context::{ArtifactWriter, BlobContext, BlobManager, BootstrapContext, BuildContext};
+use super::chunker::{ChunkingStrategy, FastCdc};
+use super::context::{
+    ArtifactWriter, BlobContext, BlobManager, BootstrapContext, BuildContext, CipherContext,
+};
 use super::tree::Tree;
 
 // Filesystem may have different algorithms to calculate `i_size` for directory entries,
@@ -379,61 +384,187 @@ impl Node {
             None
         };
 
-        // `child_count` of regular file is reused as `chunk_count`.
-        for i in 0..self.inode.child_count() {
-            let chunk_size = ctx.chunk_size;
-            let file_offset = i as u64 * chunk_size as u64;
-            let uncompressed_size = if i == self.inode.child_count() - 1 {
-                (self.inode.size() as u64)
-                    .checked_sub(chunk_size as u64 * i as u64)
-                    .ok_or_else(|| {
-                        anyhow!("the rest chunk size of inode is bigger than chunk_size")
-                    })? as u32
-            } else {
-                chunk_size
-            };
+        if let ChunkingStrategy::Cdc(cdc) = ctx.chunking {
+            blob_size += self.dump_cdc_chunks(
+                ctx,
+                blob_mgr,
+                blob_writer,
+                reader,
+                data_buf,
+                &cdc,
+                inode_hasher.as_mut(),
+            )?;
+        } else {
+            // `child_count` of regular file is reused as `chunk_count`.
+            for i in 0..self.inode.child_count() {
+                let chunk_size = ctx.chunk_size;
+                let file_offset = i as u64 * chunk_size as u64;
+                let uncompressed_size = if i == self.inode.child_count() - 1 {
+                    (self.inode.size() as u64)
+                        .checked_sub(chunk_size as u64 * i as u64)
+                        .ok_or_else(|| {
+                            anyhow!("the rest chunk size of inode is bigger than chunk_size")
+                        })? as u32
+                } else {
+                    chunk_size
+                };
 
-            let chunk_data = &mut data_buf[0..uncompressed_size as usize];
-            let (chunk, chunk_info) = self.read_file_chunk(ctx, reader, chunk_data)?;
+                let chunk_data = &mut data_buf[0..uncompressed_size as usize];
+                let (chunk, chunk_info) = self.read_file_chunk(ctx, reader, chunk_data)?;
+                if let Some(h) = inode_hasher.as_mut() {
+                    h.digest_update(chunk.id().as_ref());
+                }
+                blob_size += self.dump_chunk(
+                    ctx,
+                    blob_mgr,
+                    blob_writer,
+                    file_offset,
+                    chunk_data,
+                    None,
+                    chunk,
+                    chunk_info,
+                )?;
+            }
+        }
+
+        // Finish inode digest calculation
+        if let Some(h) = inode_hasher {
+            self.inode.set_digest(h.digest_finalize());
+        }
+        ctx.report_file_progress(self.target());
+
+        Ok(blob_size)
+    }
+
+    /// Split data from the reader into chunks of variable size by content-defined chunking.
+    #[allow(clippy::too_many_arguments)]
+    fn dump_cdc_chunks<R: Read>(
+        &mut self,
+        ctx: &BuildContext,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+        reader: &mut R,
+        data_buf: &mut [u8],
+        cdc: &FastCdc,
+        mut inode_hasher: Option<&mut RafsDigestHasher>,
+    ) -> Result<u64> {
+        if !self.inode.is_v5() {
+            bail!("content-defined chunking is only supported by RAFS v5");
+        }
+        let max_size = cdc.max_size() as usize;
+        if data_buf.len() < max_size {
+            bail!(
+                "data buffer size 0x{:x} is smaller than max chunk size 0x{:x}",
+                data_buf.len(),
+                max_size
+            );
+        }
+
+        let mut blob_size = 0u64;
+        let mut remaining = self.inode.size();
+        let mut file_offset = 0u64;
+        let mut buf_len = 0usize;
+        loop {
+            // Keep at least `max_size` bytes in the buffer unless reaching end of file.
+            let size = cmp::min((max_size - buf_len) as u64, remaining) as usize;
+            if size > 0 {
+                reader
+                    .read_exact(&mut data_buf[buf_len..buf_len + size])
+                    .with_context(|| format!("failed to read node file {:?}", self.path))?;
+                remaining -= size as u64;
+                buf_len += size;
+            }
+            if buf_len == 0 {
+                break;
+            }
+
+            let chunk_size = cdc.cut(&data_buf[..buf_len]);
+            let chunk_data = &data_buf[..chunk_size];
+            let mut chunk = self.inode.create_chunk();
+            chunk.set_id(RafsDigest::from_buf(chunk_data, ctx.digester));
             if let Some(h) = inode_hasher.as_mut() {
                 h.digest_update(chunk.id().as_ref());
             }
-
-            let mut chunk = match self.find_duplicated_chunk(
+            blob_size += self.dump_chunk(
                 ctx,
                 blob_mgr,
+                blob_writer,
                 file_offset,
-                uncompressed_size,
+                chunk_data,
+                None,
                 chunk,
-            )? {
-                None => continue,
-                Some(c) => c,
-            };
+                None,
+            )?;
 
-            let (blob_index, blob_ctx) = blob_mgr.get_or_create_current_blob(ctx)?;
-            let chunk_index = blob_ctx.alloc_chunk_index()?;
-            chunk.set_blob_index(blob_index);
-            chunk.set_index(chunk_index);
-            chunk.set_file_offset(file_offset);
-            self.dump_file_chunk(ctx, blob_ctx, blob_writer, chunk_data, &mut chunk)?;
-
-            blob_size += chunk.compressed_size() as u64;
-            blob_ctx.add_chunk_meta_info(&chunk, chunk_info)?;
-            blob_mgr.layered_chunk_dict.add_chunk(chunk.clone());
-            self.chunks.push(NodeChunk {
-                source: ChunkSource::Build,
-                inner: chunk,
-            });
+            data_buf.copy_within(chunk_size..buf_len, 0);
+            buf_len -= chunk_size;
+            file_offset += chunk_size as u64;
         }
 
-        // Finish inode digest calculation
-        if let Some(h) = inode_hasher {
-            self.inode.set_digest(h.digest_finalize());
-        }
+        // Chunks are not aligned to the chunk size, so mark the inode to let the runtime locate
+        // chunks by file offset.
+        self.inode.set_child_count(self.chunks.len() as u32);
+        self.inode.set_has_hole(true);
 
         Ok(blob_size)
     }
 
+    /// Dump a chunk into the data blob, or reuse an existing chunk with the same digest.
+    ///
+    /// Data of the chunk will be compressed unless `compressed` is provided. Returns compressed
+    /// size of data written into the data blob.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn dump_chunk(
+        &mut self,
+        ctx: &BuildContext,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+        file_offset: u64,
+        chunk_data: &[u8],
+        compressed: Option<(&[u8], bool)>,
+        chunk: ChunkWrapper,
+        chunk_info: Option<BlobChunkInfoV2Ondisk>,
+    ) -> Result<u64> {
+        let uncompressed_size = chunk_data.len() as u32;
+        blob_mgr.dedup_stats.total_chunks += 1;
+        blob_mgr.dedup_stats.total_size += uncompressed_size as u64;
+
+        let mut chunk = match self.find_duplicated_chunk(
+            ctx,
+            blob_mgr,
+            file_offset,
+            uncompressed_size,
+            chunk,
+        )? {
+            None => return Ok(0),
+            Some(c) => c,
+        };
+
+        let (blob_index, blob_ctx) = blob_mgr.get_or_create_current_blob(ctx)?;
+        let chunk_index = blob_ctx.alloc_chunk_index()?;
+        chunk.set_blob_index(blob_index);
+        chunk.set_index(chunk_index);
+        chunk.set_file_offset(file_offset);
+        self.dump_file_chunk(
+            ctx,
+            blob_ctx,
+            blob_writer,
+            chunk_data,
+            compressed,
+            &mut chunk,
+        )?;
+
+        let compressed_size = chunk.compressed_size() as u64;
+        blob_ctx.add_chunk_meta_info(&chunk, chunk_info)?;
+        blob_mgr.layered_chunk_dict.add_chunk(chunk.clone());
+        self.chunks.push(NodeChunk {
+            source: ChunkSource::Build,
+            inner: chunk,
+        });
+
+        Ok(compressed_size)
+    }
+
     fn build_inode_xattr(&mut self) -> Result<()> {
         let file_xattrs = match xattr::list(&self.path) {
             Ok(x) => x,
@@ -558,12 +689,37 @@ impl Node {
         Ok((chunk, chunk_info))
     }
 
+    /// Compress and encrypt data of a chunk, return the processed data and whether it's compressed.
+    pub fn compress_chunk<'a>(
+        compressor: compress::Algorithm,
+        cipher_ctx: Option<&CipherContext>,
+        data: &'a [u8],
+    ) -> Result<(Cow<'a, [u8]>, bool)> {
+        let (compressed, is_compressed) =
+            compress::compress(data, compressor).context("failed to compress chunk data")?;
+        // Encrypt chunk data after compression, the chunk digest is still calculated
+        // over the uncompressed plaintext.
+        let compressed = match cipher_ctx {
+            Some(cipher_ctx) => Cow::Owned(
+                cipher_ctx
+                    .cipher
+                    .encrypt(&cipher_ctx.key, &compressed)
+                    .context("failed to encrypt chunk data")?,
+            ),
+            None => compressed,
+        };
+
+        Ok((compressed, is_compressed))
+    }
+
+    /// Dump a chunk into the data blob, with data already compressed if `compressed` is provided.
     fn dump_file_chunk(
         &self,
         ctx: &BuildContext,
         blob_ctx: &mut BlobContext,
         blob_writer: &mut Option<ArtifactWriter>,
         chunk_data: &[u8],
+        compressed: Option<(&[u8], bool)>,
         chunk: &mut ChunkWrapper,
     ) -> Result<()> {
         let uncompressed_size = chunk_data.len() as u32;
@@ -582,9 +738,19 @@ impl Node {
 
         let compressed_size = if ctx.blob_meta_features & BLOB_META_FEATURE_ZRAN != 0 {
             chunk.compressed_size()
+        } else if Self::is_zeroed_chunk(ctx, blob_ctx, chunk_data) {
+            // Zeroed chunks are served without accessing the data blob, so skip dumping data.
+            chunk.set_compressed_offset(pre_compressed_offset);
+            chunk.set_compressed_size(0);
+            chunk.set_compressed(false);
+            chunk.set_zeroed(true);
+            0
         } else {
-            let (compressed, is_compressed) = compress::compress(chunk_data, ctx.compressor)
-                .with_context(|| format!("failed to compress node file {:?}", self.path))?;
+            let (compressed, is_compressed) = match compressed {
+                Some((data, is_compressed)) => (Cow::Borrowed(data), is_compressed),
+                None => Self::compress_chunk(ctx.compressor, ctx.cipher_ctx.as_ref(), chunk_data)
+                    .with_context(|| format!("failed to compress node file {:?}", self.path))?,
+            };
             // Dump compressed chunk data to blob
             if let Some(writer) = blob_writer {
                 writer
@@ -596,6 +762,10 @@ impl Node {
             blob_ctx.blob_hash.update(&compressed);
             blob_ctx.compressed_offset += compressed_size as u64;
             blob_ctx.compressed_blob_size += compressed_size as u64;
+            ctx.report_blob_progress(
+                blob_ctx.compressed_blob_size - compressed_size as u64,
+                blob_ctx.compressed_blob_size,
+            );
             chunk.set_compressed_offset(pre_compressed_offset);
             chunk.set_compressed_size(compressed_size);
             chunk.set_compressed(is_compressed);
@@ -608,6 +778,17 @@ impl Node {
         Ok(())
     }
 
+    /// Check whether the chunk should be marked as zeroed instead of being dumped into the blob.
+    ///
+    /// Only RAFS v5 supports zeroed chunks, because RAFS v6 fetches chunk data by the chunk
+    /// information array in the data blob, which has no place to record the flag.
+    fn is_zeroed_chunk(ctx: &BuildContext, blob_ctx: &BlobContext, chunk_data: &[u8]) -> bool {
+        ctx.fs_version.is_v5()
+            && !blob_ctx.blob_meta_info_enabled
+            && !chunk_data.is_empty()
+            && chunk_data.iter().all(|v| *v == 0)
+    }
+
     fn find_duplicated_chunk(
         &mut self,
         ctx: &BuildContext,
@@ -643,6 +824,10 @@ impl Node {
                 // for de-duplication, the blob should not be referenced in the blob table
                 // of final bootstrap, this logic ensure it.
                 if from_dict {
+                    blob_mgr.dedup_stats.dict_chunks += 1;
+                    blob_mgr.dedup_stats.dict_size += uncompressed_size as u64;
+                    event_tracer!("dict_dedup_chunks", +1);
+                    event_tracer!("dict_dedup_uncompressed_size", +uncompressed_size);
                     let blob_index = if let Some(blob_idx) = blob_mgr
                         .global_chunk_dict
                         .get_real_blob_idx(chunk.blob_index())
@@ -820,6 +1005,16 @@ impl Node {
             self.inode.set_has_xattr(false);
         }
     }
+
+    /// Clamp modification time of the inode to `epoch`, for reproducible build.
+    pub fn clamp_mtime(&mut self, epoch: u64) {
+        if self.inode.mtime() > epoch
+            || (self.inode.mtime() == epoch && self.inode.mtime_nsec() > 0)
+        {
+            self.inode.set_mtime(epoch);
+            self.inode.set_mtime_nsec(0);
+        }
+    }
 }
 
 // Rafs v5 dedicated methods
@@ -1595,4 +1790,65 @@ mod tests {
 
         std::fs::remove_file(&pa_pyc).unwrap();
     }
+
+    #[test]
+    fn test_dump_zeroed_chunk() {
+        let pa = TempDir::new().unwrap();
+        let pa_reg = TempFile::new_in(pa.as_path()).unwrap();
+        let node = Node::new(
+            RafsVersion::V5,
+            pa.as_path().to_path_buf(),
+            pa_reg.as_path().to_path_buf(),
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            false,
+        )
+        .unwrap();
+        let ctx = BuildContext {
+            fs_version: RafsVersion::V5,
+            ..Default::default()
+        };
+        let mut blob_ctx = BlobContext::new(String::new(), 0, 0);
+        let mut data = vec![0u8; 0x1000];
+
+        let mut chunk = ChunkWrapper::new(RafsVersion::V5);
+        node.dump_file_chunk(&ctx, &mut blob_ctx, &mut None, &data, None, &mut chunk)
+            .unwrap();
+        assert!(chunk.is_zeroed());
+        assert!(!chunk.is_compressed());
+        assert_eq!(chunk.compressed_offset(), 0);
+        assert_eq!(chunk.compressed_size(), 0);
+        assert_eq!(chunk.uncompressed_size(), 0x1000);
+        assert_eq!(blob_ctx.compressed_offset, 0);
+        assert_eq!(blob_ctx.uncompressed_offset, 0x1000);
+
+        data[0x800] = 1;
+        let mut chunk = ChunkWrapper::new(RafsVersion::V5);
+        node.dump_file_chunk(&ctx, &mut blob_ctx, &mut None, &data, None, &mut chunk)
+            .unwrap();
+        assert!(!chunk.is_zeroed());
+        assert_eq!(chunk.compressed_offset(), 0);
+        assert_ne!(chunk.compressed_size(), 0);
+        assert_eq!(blob_ctx.compressed_offset, chunk.compressed_size() as u64);
+
+        // RAFS v6 has no way to record zeroed chunks in the blob chunk information array.
+        let ctx = BuildContext {
+            fs_version: RafsVersion::V6,
+            ..Default::default()
+        };
+        let mut blob_ctx = BlobContext::new(String::new(), 0, 0);
+        let mut chunk = ChunkWrapper::new(RafsVersion::V6);
+        node.dump_file_chunk(
+            &ctx,
+            &mut blob_ctx,
+            &mut None,
+            &[0u8; 0x1000],
+            None,
+            &mut chunk,
+        )
+        .unwrap();
+        assert!(!chunk.is_zeroed());
+        assert_ne!(chunk.compressed_size(), 0);
+    }
 }
@@ -0,0 +1,331 @@
+// Copyright 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Multi-threaded pipeline to dump file data into the data blob.
+//!
+//! The pipeline consists of:
+//! - a reader thread, which reads file data into chunk buffers in blob layout order.
+//! - a pool of worker threads, which calculate chunk digests and compress chunk data in parallel.
+//! - the calling thread as writer, which de-duplicates chunks, assigns compressed offsets and
+//!   writes chunk data into the data blob strictly in blob layout order.
+//!
+//! Chunk buffers are recycled through a fixed size pool, so memory usage is bounded by
+//! `threads * chunk_size * PIPELINE_QUEUE_DEPTH`. Because all decisions affecting the output are
+//! made by the writer in the same order as the single-threaded path, the generated data blob and
+//! metadata are identical to the single-threaded path.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use nydus_utils::compress;
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+
+use super::context::{ArtifactWriter, BlobManager, BuildContext, CipherContext};
+use super::node::Node;
+
+/// Number of chunk buffers allocated for each worker thread.
+pub const PIPELINE_QUEUE_DEPTH: usize = 4;
+
+struct ChunkJob {
+    seq: u64,
+    buf: Vec<u8>,
+    size: usize,
+}
+
+struct ChunkResult {
+    seq: u64,
+    buf: Vec<u8>,
+    size: usize,
+    digest: RafsDigest,
+    compressed: Vec<u8>,
+    is_compressed: bool,
+}
+
+/// Multi-threaded pipeline to dump data of regular files into the data blob.
+pub struct BlobPipeline {
+    free_tx: Option<Sender<Vec<u8>>>,
+    result_rx: Option<Receiver<Result<ChunkResult>>>,
+    pending: BTreeMap<u64, ChunkResult>,
+    next_seq: u64,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BlobPipeline {
+    /// Dump data of `nodes` into the data blob in order of `inodes`, and return compressed size
+    /// of data dumped for the first `prefetch_entries` inodes.
+    ///
+    /// Data chunks of regular files whose `chunks` are not empty, such as those reused from a
+    /// base bootstrap, won't be dumped again.
+    pub fn dump(
+        ctx: &BuildContext,
+        nodes: &mut [Node],
+        inodes: &[usize],
+        prefetch_entries: usize,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+    ) -> Result<u64> {
+        let files = inodes
+            .iter()
+            .map(|idx| &nodes[*idx])
+            .filter(|node| node.is_reg() && node.chunks.is_empty())
+            .map(|node| (node.path().to_path_buf(), node.inode.size()))
+            .collect::<Vec<_>>();
+        let mut pipeline = Self::start(ctx, files)?;
+        let result = pipeline.write(ctx, nodes, inodes, prefetch_entries, blob_mgr, blob_writer);
+        pipeline.stop();
+
+        result
+    }
+
+    fn start(ctx: &BuildContext, files: Vec<(PathBuf, u64)>) -> Result<Self> {
+        let threads = cmp::max(ctx.threads, 1);
+        let chunk_size = ctx.chunk_size as usize;
+        let buffers = threads * PIPELINE_QUEUE_DEPTH;
+        let (free_tx, free_rx) = channel();
+        for _ in 0..buffers {
+            // Safe to unwrap because the receiver is alive.
+            free_tx.send(vec![0u8; chunk_size]).unwrap();
+        }
+        let (job_tx, job_rx) = sync_channel(buffers);
+        let (result_tx, result_rx) = channel();
+
+        let mut pipeline = BlobPipeline {
+            free_tx: Some(free_tx),
+            result_rx: Some(result_rx),
+            pending: BTreeMap::new(),
+            next_seq: 0,
+            threads: Vec::with_capacity(threads + 1),
+        };
+
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for idx in 0..threads {
+            let job_rx = job_rx.clone();
+            let result_tx = result_tx.clone();
+            let (compressor, digester) = (ctx.compressor, ctx.digester);
+            let cipher_ctx = ctx.cipher_ctx.clone();
+            let handle = thread::Builder::new()
+                .name(format!("blob-worker-{}", idx))
+                .spawn(move || {
+                    Self::run_worker(job_rx, result_tx, compressor, digester, cipher_ctx)
+                })
+                .context("failed to create pipeline worker thread");
+            match handle {
+                Ok(v) => pipeline.threads.push(v),
+                Err(e) => {
+                    pipeline.stop();
+                    return Err(e);
+                }
+            }
+        }
+
+        let handle = thread::Builder::new()
+            .name("blob-reader".to_string())
+            .spawn(move || Self::run_reader(files, chunk_size, free_rx, job_tx, result_tx))
+            .context("failed to create pipeline reader thread");
+        match handle {
+            Ok(v) => pipeline.threads.push(v),
+            Err(e) => {
+                pipeline.stop();
+                return Err(e);
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    fn stop(&mut self) {
+        // Closing the buffer pool and result channel causes the reader thread to exit, which then
+        // closes the job channel and causes worker threads to exit.
+        self.free_tx.take();
+        self.result_rx.take();
+        self.pending.clear();
+        for handle in self.threads.drain(..) {
+            if let Err(e) = handle.join() {
+                error!("failed to join pipeline thread, {:?}", e);
+            }
+        }
+    }
+
+    fn run_reader(
+        files: Vec<(PathBuf, u64)>,
+        chunk_size: usize,
+        free_rx: Receiver<Vec<u8>>,
+        job_tx: SyncSender<ChunkJob>,
+        result_tx: Sender<Result<ChunkResult>>,
+    ) {
+        let mut seq = 0;
+        for (path, size) in files {
+            let mut file = match File::open(&path)
+                .with_context(|| format!("failed to open node file {:?}", path))
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    let _ = result_tx.send(Err(e));
+                    return;
+                }
+            };
+            let mut offset = 0u64;
+            while offset < size {
+                let len = cmp::min(chunk_size as u64, size - offset) as usize;
+                let mut buf = match free_rx.recv() {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                if let Err(e) = file
+                    .read_exact(&mut buf[..len])
+                    .with_context(|| format!("failed to read node file {:?}", path))
+                {
+                    let _ = result_tx.send(Err(e));
+                    return;
+                }
+                if job_tx
+                    .send(ChunkJob {
+                        seq,
+                        buf,
+                        size: len,
+                    })
+                    .is_err()
+                {
+                    return;
+                }
+                seq += 1;
+                offset += len as u64;
+            }
+        }
+    }
+
+    fn run_worker(
+        job_rx: Arc<Mutex<Receiver<ChunkJob>>>,
+        result_tx: Sender<Result<ChunkResult>>,
+        compressor: compress::Algorithm,
+        digester: digest::Algorithm,
+        cipher_ctx: Option<CipherContext>,
+    ) {
+        loop {
+            let job = job_rx.lock().unwrap().recv();
+            let job = match job {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let (digest, result) = {
+                let data = &job.buf[..job.size];
+                let digest = RafsDigest::from_buf(data, digester);
+                let result = Node::compress_chunk(compressor, cipher_ctx.as_ref(), data)
+                    .map(|(compressed, is_compressed)| (compressed.into_owned(), is_compressed));
+                (digest, result)
+            };
+            let result = result.map(|(compressed, is_compressed)| ChunkResult {
+                seq: job.seq,
+                buf: job.buf,
+                size: job.size,
+                digest,
+                compressed,
+                is_compressed,
+            });
+            if result_tx.send(result).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        ctx: &BuildContext,
+        nodes: &mut [Node],
+        inodes: &[usize],
+        prefetch_entries: usize,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+    ) -> Result<u64> {
+        let mut prefetch_size = 0u64;
+        for (idx, inode) in inodes.iter().enumerate() {
+            let node = &mut nodes[*inode];
+            let size = if node.is_reg() && node.chunks.is_empty() {
+                self.write_node(ctx, node, blob_mgr, blob_writer)?
+            } else if !node.is_reg() {
+                node.dump_node_data_with_reader::<File>(ctx, blob_mgr, blob_writer, None, &mut [])
+                    .context("failed to dump blob chunks")?
+            } else {
+                0
+            };
+            if idx < prefetch_entries {
+                prefetch_size += size;
+            }
+        }
+
+        Ok(prefetch_size)
+    }
+
+    fn write_node(
+        &mut self,
+        ctx: &BuildContext,
+        node: &mut Node,
+        blob_mgr: &mut BlobManager,
+        blob_writer: &mut Option<ArtifactWriter>,
+    ) -> Result<u64> {
+        let mut blob_size = 0u64;
+        let mut inode_hasher = if node.inode.is_v5() {
+            Some(RafsDigest::hasher(ctx.digester))
+        } else {
+            None
+        };
+
+        for i in 0..node.inode.child_count() {
+            let result = self.next_result()?;
+            let mut chunk = node.inode.create_chunk();
+            chunk.set_id(result.digest);
+            if let Some(h) = inode_hasher.as_mut() {
+                h.digest_update(chunk.id().as_ref());
+            }
+            blob_size += node
+                .dump_chunk(
+                    ctx,
+                    blob_mgr,
+                    blob_writer,
+                    i as u64 * ctx.chunk_size as u64,
+                    &result.buf[..result.size],
+                    Some((&result.compressed, result.is_compressed)),
+                    chunk,
+                    None,
+                )
+                .context("failed to dump blob chunks")?;
+            // Return the buffer to the pool, the reader may have exited already.
+            if let Some(free_tx) = self.free_tx.as_ref() {
+                let _ = free_tx.send(result.buf);
+            }
+        }
+
+        if let Some(h) = inode_hasher {
+            node.inode.set_digest(h.digest_finalize());
+        }
+        ctx.report_file_progress(node.target());
+
+        Ok(blob_size)
+    }
+
+    fn next_result(&mut self) -> Result<ChunkResult> {
+        let seq = self.next_seq;
+        loop {
+            if let Some(result) = self.pending.remove(&seq) {
+                self.next_seq += 1;
+                return Ok(result);
+            }
+            // Safe to unwrap because the receiver is only taken when stopping the pipeline.
+            match self.result_rx.as_ref().unwrap().recv() {
+                Ok(Ok(v)) => {
+                    self.pending.insert(v.seq, v);
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => bail!("pipeline threads exited unexpectedly"),
+            }
+        }
+    }
+}
@@ -11,7 +11,7 @@ use indexmap::IndexMap;
 use nydus_rafs::metadata::layout::v5::RafsV5PrefetchTable;
 use nydus_rafs::metadata::layout::v6::{calculate_nid, RafsV6PrefetchTable};
 
-use crate::node::Node;
+use crate::core::node::Node;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PrefetchPolicy {
@@ -130,6 +130,16 @@ impl Prefetch {
         })
     }
 
+    /// Create a filesystem level prefetch list from absolute file paths.
+    pub fn from_paths(paths: Vec<String>) -> Result<Self> {
+        Ok(Self {
+            policy: PrefetchPolicy::Fs,
+            disabled: false,
+            patterns: generate_patterns(paths)?,
+            files: BTreeMap::new(),
+        })
+    }
+
     pub fn insert_if_need(&mut self, node: &Node) {
         let path = node.target();
         let index = node.index;
@@ -165,9 +175,10 @@ impl Prefetch {
         self.files.values().copied().collect()
     }
 
+    /// Get number of prefetch patterns matching a filesystem node.
     pub fn len(&self) -> u32 {
         if self.policy == PrefetchPolicy::Fs {
-            self.patterns.values().len() as u32
+            self.patterns.values().filter(|v| v.is_some()).count() as u32
         } else {
             0
         }
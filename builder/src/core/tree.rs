@@ -17,6 +17,7 @@
 
 use std::ffi::OsStr;
 use std::ffi::OsString;
+use std::mem;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
@@ -78,6 +79,23 @@ impl Tree {
         Ok(())
     }
 
+    /// Remove whiteout files and opaque markers from the tree.
+    ///
+    /// It's used for the lowest layer, whose whiteouts have nothing to hide.
+    pub fn remove_whiteouts(&mut self, whiteout_spec: WhiteoutSpec) {
+        self.children.retain_mut(|child| {
+            // Nodes loaded from bootstrap are marked as `Overlay::Lower`, which is never treated
+            // as whiteout, so check them as upper layer nodes.
+            let overlay = mem::replace(&mut child.node.overlay, Overlay::UpperAddition);
+            let is_whiteout = child.node.whiteout_type(whiteout_spec).is_some();
+            child.node.overlay = overlay;
+            !is_whiteout
+        });
+        for child in self.children.iter_mut() {
+            child.remove_whiteouts(whiteout_spec);
+        }
+    }
+
     /// Apply new node (upper layer) to node tree (lower layer).
     ///
     /// Support overlay defined in OCI image layer spec
@@ -320,7 +338,7 @@ impl<'a> MetadataTreeBuilder<'a> {
         let mut xattrs = RafsXAttrs::new();
         for name in inode.get_xattrs()? {
             let name = bytes_to_os_str(&name);
-            let value = inode.get_xattr(name)?;
+            let value = inode.get_xattr(&name)?;
             xattrs.add(name.to_os_string(), value.unwrap_or_default())?;
         }
 
@@ -359,3 +377,82 @@ impl<'a> MetadataTreeBuilder<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_rafs::metadata::{RafsVersion, RAFS_DEFAULT_CHUNK_SIZE};
+    use std::fs::{self, File};
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn new_node(root: &Path, path: &str, overlay: Overlay) -> Node {
+        Node::new(
+            RafsVersion::V6,
+            root.to_path_buf(),
+            root.join(path),
+            overlay,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            false,
+        )
+        .unwrap()
+    }
+
+    // Create files of a lower layer and load them into a tree, directories end with `/`.
+    fn load_lower_layer(root: &Path, paths: &[&str]) -> Tree {
+        let mut tree = Tree::new(new_node(root, "", Overlay::Lower));
+        for path in paths {
+            if path.ends_with('/') {
+                fs::create_dir_all(root.join(path)).unwrap();
+            } else {
+                File::create(root.join(path)).unwrap();
+            }
+            let node = new_node(root, path.trim_end_matches('/'), Overlay::Lower);
+            assert!(tree.apply(&node, false, WhiteoutSpec::Oci).unwrap());
+        }
+        // Nodes are marked as upper layer nodes when applied, mark them as loaded from bootstrap.
+        mark_lower(&mut tree);
+        tree
+    }
+
+    fn mark_lower(tree: &mut Tree) {
+        tree.node.overlay = Overlay::Lower;
+        for child in tree.children.iter_mut() {
+            mark_lower(child);
+        }
+    }
+
+    fn tree_paths(tree: &Tree) -> Vec<String> {
+        let mut paths = Vec::new();
+        tree.iterate(&mut |node: &Node| {
+            paths.push(node.target().to_str().unwrap().to_owned());
+            true
+        })
+        .unwrap();
+        paths
+    }
+
+    #[test]
+    fn test_remove_whiteouts() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = load_lower_layer(
+            dir.as_path(),
+            &[".wh.ghost", "foo", "sub/", "sub/.wh..wh..opq", "sub/bar"],
+        );
+        tree.remove_whiteouts(WhiteoutSpec::Oci);
+        assert_eq!(tree_paths(&tree), vec!["/", "/foo", "/sub", "/sub/bar"]);
+        assert_eq!(tree.children[0].node.overlay, Overlay::Lower);
+    }
+
+    #[test]
+    fn test_apply_whiteout() {
+        let lower = TempDir::new().unwrap();
+        let upper = TempDir::new().unwrap();
+        let mut tree = load_lower_layer(lower.as_path(), &["foo", "sub/", "sub/foo", "sub/bar"]);
+
+        File::create(upper.as_path().join(".wh.foo")).unwrap();
+        let node = new_node(upper.as_path(), ".wh.foo", Overlay::UpperAddition);
+        assert!(tree.apply(&node, true, WhiteoutSpec::Oci).unwrap());
+        assert_eq!(tree_paths(&tree), vec!["/", "/sub", "/sub/foo", "/sub/bar"]);
+    }
+}
@@ -7,13 +7,13 @@ use std::fs::DirEntry;
 
 use anyhow::{Context, Result};
 
-use crate::builder::{build_bootstrap, dump_bootstrap, Builder};
 use crate::core::blob::Blob;
 use crate::core::context::{
     ArtifactWriter, BlobManager, BootstrapContext, BootstrapManager, BuildContext, BuildOutput,
 };
 use crate::core::node::{Node, Overlay};
 use crate::core::tree::Tree;
+use crate::{build_bootstrap, dump_bootstrap, Builder};
 
 struct FilesystemTreeBuilder {}
 
@@ -37,7 +37,10 @@ impl FilesystemTreeBuilder {
 
         let children = fs::read_dir(parent.path())
             .with_context(|| format!("failed to read dir {:?}", parent.path()))?;
-        let children = children.collect::<Result<Vec<DirEntry>, std::io::Error>>()?;
+        let mut children = children.collect::<Result<Vec<DirEntry>, std::io::Error>>()?;
+        if ctx.reproducible {
+            children.sort_by_key(|entry| entry.file_name());
+        }
 
         event_tracer!("load_from_directory", +children.len());
         for child in children {
@@ -73,7 +76,7 @@ impl FilesystemTreeBuilder {
     }
 }
 
-pub(crate) struct DirectoryBuilder {}
+pub struct DirectoryBuilder {}
 
 impl DirectoryBuilder {
     pub fn new() -> Self {
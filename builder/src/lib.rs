@@ -0,0 +1,223 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builder to generate RAFS filesystems from directories, tarballs and stargz images.
+//!
+//! A RAFS filesystem is composed of a metadata blob (bootstrap) and zero or more data blobs.
+//! The builder walks the source to build a filesystem tree, dumps file data into a data blob
+//! and then dumps the filesystem metadata into a bootstrap. The `nydus-image` tool is a command
+//! line interface over this crate.
+//!
+//! There are several core abstractions:
+//! - [BuildContext](core/context/struct.BuildContext.html): options to build the filesystem,
+//!   such as source type, compressor, digester, chunk size and RAFS version.
+//! - [Builder](trait.Builder.html): generates a RAFS filesystem from a type of source, such as
+//!   [DirectoryBuilder](struct.DirectoryBuilder.html).
+//! - [ArtifactStorage](core/context/enum.ArtifactStorage.html): where to store generated
+//!   bootstrap and data blobs, either a file, a directory or an in-memory buffer.
+//! - [BuildProgress](core/context/trait.BuildProgress.html): callbacks to report building
+//!   progress.
+//!
+//! # Examples
+//!
+//! Build a RAFS v6 filesystem from a directory into in-memory buffers:
+//! ```
+//! use std::fs;
+//! use std::path::Path;
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use std::sync::Arc;
+//!
+//! use nydus_builder::{
+//!     ArtifactBuffer, ArtifactStorage, BlobManager, BootstrapManager, BuildContext,
+//!     BuildProgress, Builder, ConversionType, DirectoryBuilder,
+//! };
+//! use nydus_rafs::metadata::{RafsMode, RafsSuper, RafsVersion};
+//! use nydus_storage::meta::BLOB_META_FEATURE_CHUNK_INFO_V2;
+//! use nydus_utils::compress;
+//! use vmm_sys_util::tempdir::TempDir;
+//!
+//! #[derive(Default)]
+//! struct FileCounter(AtomicU64);
+//!
+//! impl BuildProgress for FileCounter {
+//!     fn file_done(&self, _path: &Path) {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let source = TempDir::new().unwrap();
+//! fs::write(source.as_path().join("hello"), b"hello, nydus").unwrap();
+//! fs::create_dir(source.as_path().join("dir")).unwrap();
+//! let data = (0..0x20_0000u32).map(|v| (v % 251) as u8).collect::<Vec<_>>();
+//! fs::write(source.as_path().join("dir/world"), data).unwrap();
+//!
+//! let blob = ArtifactBuffer::new();
+//! let bootstrap = ArtifactBuffer::new();
+//! let counter = Arc::new(FileCounter::default());
+//!
+//! let mut ctx = BuildContext {
+//!     aligned_chunk: true,
+//!     blob_meta_features: BLOB_META_FEATURE_CHUNK_INFO_V2,
+//!     compressor: compress::Algorithm::Lz4Block,
+//!     conversion_type: ConversionType::DirectoryToRafs,
+//!     source_path: source.as_path().to_path_buf(),
+//!     blob_storage: Some(ArtifactStorage::Memory(blob.clone())),
+//!     ..Default::default()
+//! };
+//! ctx.set_fs_version(RafsVersion::V6);
+//! ctx.set_progress(counter.clone());
+//! let mut bootstrap_mgr =
+//!     BootstrapManager::new(Some(ArtifactStorage::Memory(bootstrap.clone())), None);
+//! let mut blob_mgr = BlobManager::new();
+//!
+//! let output = DirectoryBuilder::new()
+//!     .build(&mut ctx, &mut bootstrap_mgr, &mut blob_mgr)
+//!     .unwrap();
+//! assert_eq!(output.blobs.len(), 1);
+//! assert_eq!(output.chunk_count, 3);
+//! assert_eq!(output.uncompressed_blob_size, Some(0x20_1000));
+//! assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+//! assert!(!blob.is_empty());
+//!
+//! let rs = RafsSuper::load_from_slice(&bootstrap.data(), RafsMode::Direct, true).unwrap();
+//! assert!(rs.meta.is_v6());
+//! ```
+
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+
+use std::io::Write;
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::core::bootstrap::Bootstrap;
+use crate::core::context::{ArtifactWriter, BootstrapContext};
+use crate::core::tree::Tree;
+
+pub use self::core::context::{
+    ArtifactBuffer, ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput,
+    BuildProgress, ConversionType,
+};
+pub use self::core::node::WhiteoutSpec;
+pub use self::core::prefetch::{Prefetch, PrefetchPolicy};
+pub use self::directory::DirectoryBuilder;
+pub use self::stargz::StargzBuilder;
+pub use self::tarball::TarballBuilder;
+
+#[macro_use]
+pub mod trace;
+pub mod core;
+
+mod directory;
+mod stargz;
+mod tarball;
+
+/// Trait to generate a RAFS filesystem from the source.
+///
+/// The source is specified by `BuildContext::source_path`, generated data blob is written into
+/// `BuildContext::blob_storage`, and the bootstrap is written into
+/// `BootstrapManager::bootstrap_storage`.
+pub trait Builder {
+    fn build(
+        &mut self,
+        build_ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput>;
+}
+
+const TAR_BLOB_NAME: &str = "image.blob";
+const TAR_BOOTSTRAP_NAME: &str = "image.boot";
+
+fn build_bootstrap(
+    ctx: &mut BuildContext,
+    bootstrap_mgr: &mut BootstrapManager,
+    bootstrap_ctx: &mut BootstrapContext,
+    blob_mgr: &mut BlobManager,
+    mut tree: Tree,
+) -> Result<Bootstrap> {
+    let mut bootstrap = Bootstrap::new()?;
+    // Merge with lower layer if there's one.
+    if bootstrap_ctx.layered {
+        let origin_bootstarp_offset = bootstrap_ctx.offset;
+        // Disable prefetch and bootstrap.apply() will reset the prefetch enable/disable flag.
+        ctx.prefetch.disable();
+        bootstrap.build(ctx, bootstrap_ctx, &mut tree)?;
+        tree = bootstrap.apply(ctx, bootstrap_ctx, bootstrap_mgr, blob_mgr, None)?;
+        bootstrap_ctx.offset = origin_bootstarp_offset;
+        bootstrap_ctx.layered = false;
+    }
+
+    // Convert the hierarchy tree into an array, stored in `bootstrap_ctx.nodes`.
+    timing_tracer!(
+        { bootstrap.build(ctx, bootstrap_ctx, &mut tree) },
+        "build_bootstrap"
+    )?;
+
+    Ok(bootstrap)
+}
+
+fn dump_bootstrap(
+    ctx: &mut BuildContext,
+    bootstrap_mgr: &mut BootstrapManager,
+    bootstrap_ctx: &mut BootstrapContext,
+    bootstrap: &mut Bootstrap,
+    blob_mgr: &mut BlobManager,
+    blob_writer: &mut Option<ArtifactWriter>,
+) -> Result<()> {
+    // Dump bootstrap file
+    let blob_table = blob_mgr.to_blob_table(ctx)?;
+    bootstrap.dump(
+        ctx,
+        &mut bootstrap_mgr.bootstrap_storage,
+        bootstrap_ctx,
+        &blob_table,
+    )?;
+
+    if let Some(blob_writer) = blob_writer.as_mut() {
+        let mut blob_hash = blob_mgr
+            .get_current_blob()
+            .map(|(_, blob_ctx)| blob_ctx.blob_hash.clone())
+            .unwrap_or_else(Sha256::new);
+        if ctx.inline_bootstrap {
+            if blob_mgr.get_current_blob().is_some() {
+                let header = blob_writer.write_tar_header(TAR_BLOB_NAME, blob_writer.pos()?)?;
+                blob_hash.update(header.as_bytes());
+            };
+
+            let reader = bootstrap_ctx.writer.as_reader()?;
+            let mut size = 0;
+            let mut buf = vec![0u8; 16384];
+            loop {
+                let sz = reader.read(&mut buf)?;
+                if sz == 0 {
+                    break;
+                }
+                blob_writer.write_all(&buf[..sz])?;
+                blob_hash.update(&buf[..sz]);
+                size += sz;
+            }
+
+            let header = blob_writer.write_tar_header(TAR_BOOTSTRAP_NAME, size as u64)?;
+            blob_hash.update(header.as_bytes());
+
+            if ctx.blob_id.is_empty() {
+                ctx.blob_id = format!("{:x}", blob_hash.finalize());
+            }
+            blob_writer.finalize(Some(ctx.blob_id.clone()))?;
+        } else {
+            let blob_id = blob_mgr
+                .get_current_blob()
+                .map(|(_, blob_ctx)| blob_ctx.blob_id().unwrap_or_default());
+            blob_writer.finalize(blob_id)?;
+        }
+    }
+
+    Ok(())
+}
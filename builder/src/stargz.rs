@@ -21,18 +21,18 @@ use nydus_rafs::metadata::{Inode, RafsVersion};
 use nydus_storage::device::BlobChunkFlags;
 use nydus_storage::{RAFS_MAX_CHUNKS_PER_BLOB, RAFS_MAX_CHUNK_SIZE};
 use nydus_utils::compact::makedev;
-use nydus_utils::compress::compute_compressed_gzip_size;
+use nydus_utils::compress::{self, compute_compressed_gzip_size};
 use nydus_utils::digest::{self, Algorithm, DigestHasher, RafsDigest};
 use nydus_utils::{try_round_up_4k, ByteSize};
 use serde::{Deserialize, Serialize};
 
-use crate::builder::{build_bootstrap, Builder};
 use crate::core::blob::Blob;
 use crate::core::context::{
     BlobContext, BlobManager, BootstrapContext, BootstrapManager, BuildContext, BuildOutput,
 };
 use crate::core::node::{ChunkSource, Node, NodeChunk, Overlay};
 use crate::core::tree::Tree;
+use crate::{build_bootstrap, Builder};
 
 type RcTocEntry = Rc<RefCell<TocEntry>>;
 
@@ -97,6 +97,12 @@ struct TocEntry {
     #[serde(default)]
     pub offset: u64,
 
+    /// EndOffset, for zstd:chunked layers, is the end offset of the compressed data of the
+    /// entry. It's absent in eStargz TOC, where the compressed size must be figured out from
+    /// the Offset of the next entry.
+    #[serde(default, rename = "endOffset", skip_serializing_if = "is_zero")]
+    pub end_offset: u64,
+
     /// the Offset of the next entry with a non-zero Offset
     #[allow(unused)]
     #[serde(skip)]
@@ -147,6 +153,10 @@ struct TocEntry {
     pub inode: u64,
 }
 
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
 impl TocEntry {
     /// Check whether the `TocEntry` is a directory.
     pub fn is_dir(&self) -> bool {
@@ -329,6 +339,13 @@ impl TocIndex {
 
         Ok(toc_index)
     }
+
+    /// Check whether the index is a zstd:chunked manifest instead of an eStargz TOC.
+    ///
+    /// zstd:chunked manifests record the end offset of compressed data for each entry.
+    fn is_zstd_chunked(&self) -> bool {
+        self.entries.iter().any(|e| e.end_offset != 0)
+    }
 }
 
 struct StargzTreeBuilder {
@@ -349,6 +366,11 @@ impl StargzTreeBuilder {
         } else if toc_index.entries.is_empty() {
             bail!("stargz TOC array is empty");
         }
+        let zstd_chunked = toc_index.is_zstd_chunked();
+        if zstd_chunked {
+            // Chunk data of zstd:chunked layers are independent zstd frames.
+            ctx.compressor = compress::Algorithm::Zstd;
+        }
 
         // Map hardlink path to linked path: HashMap<<hardlink_path>, <linked_path>>
         let mut hardlink_map: HashMap<PathBuf, PathBuf> = HashMap::new();
@@ -369,12 +391,26 @@ impl StargzTreeBuilder {
             let uncompress_size = Self::get_content_size(ctx, entry, &mut last_reg_entry)?;
             if (entry.is_reg() || entry.is_chunk()) && uncompress_size != 0 {
                 let block_id = entry.block_id(&ctx.blob_id)?;
-                // blob_index and compressed_size will be fixed later
+                let compressed_size = if !zstd_chunked {
+                    0
+                } else if entry.end_offset > entry.offset
+                    && entry.end_offset - entry.offset <= RAFS_MAX_CHUNK_SIZE
+                {
+                    (entry.end_offset - entry.offset) as u32
+                } else {
+                    bail!(
+                        "zstd:chunked entry {} has invalid compressed data range [0x{:x}, 0x{:x})",
+                        path.display(),
+                        entry.offset,
+                        entry.end_offset
+                    );
+                };
+                // blob_index will be fixed later, so does compressed_size for eStargz
                 let v5_chunk_info = ChunkWrapper::V5(RafsV5ChunkInfo {
                     block_id,
                     blob_index: 0,
                     flags: BlobChunkFlags::COMPRESSED,
-                    compressed_size: 0,
+                    compressed_size,
                     uncompressed_size: uncompress_size as u32,
                     compressed_offset: entry.offset as u64,
                     uncompressed_offset: uncompress_offset,
@@ -647,7 +683,7 @@ impl StargzTreeBuilder {
     }
 }
 
-pub(crate) struct StargzBuilder {
+pub struct StargzBuilder {
     blob_size: u64,
 }
 
@@ -707,6 +743,14 @@ impl StargzBuilder {
             };
             if curr >= next {
                 bail!("stargz compressed offset is out of order");
+            }
+            // zstd:chunked manifest records the exact compressed size of each chunk.
+            let compressed_size = blob_chunks[idx].inner.compressed_size() as u64;
+            if compressed_size != 0 {
+                if compressed_size > next - curr {
+                    bail!("zstd:chunked compressed data of chunks overlaps");
+                }
+                continue;
             } else if next - curr > RAFS_MAX_CHUNK_SIZE {
                 bail!("stargz compressed size is too big");
             }
@@ -840,3 +884,65 @@ impl Builder for StargzBuilder {
         BuildOutput::new(blob_mgr, &bootstrap_mgr.bootstrap_storage)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_zstd_chunked_tree() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let texture = PathBuf::from(root_dir).join("../tests/texture/stargz");
+        let layer = std::fs::read(texture.join("zstd-chunked.tar.zst")).unwrap();
+        let mut ctx = BuildContext {
+            blob_id: "d0f6cd940a5365e6dd494efd074c0815ae784c04d10fc573185a32cb2a250fc9".to_string(),
+            chunk_size: 0x1000,
+            fs_version: RafsVersion::V6,
+            source_path: texture.join("zstd-chunked.index.json"),
+            ..Default::default()
+        };
+        let tree = StargzTreeBuilder::new().build(&mut ctx, 0).unwrap();
+        assert_eq!(ctx.compressor, compress::Algorithm::Zstd);
+
+        let mut files = HashMap::new();
+        tree.iterate(&mut |node: &Node| {
+            if node.is_reg() {
+                files.insert(node.target().clone(), node.chunks.clone());
+            }
+            true
+        })
+        .unwrap();
+        let data = (0..6000u32)
+            .map(|i| ((i * 7 + i / 251) % 256) as u8)
+            .collect::<Vec<_>>();
+        let expected = [
+            (
+                "/hello.txt",
+                b"hello zstd:chunked\n".to_vec(),
+                vec![(86, 28)],
+            ),
+            ("/dir/data", data, vec![(185, 321), (506, 306)]),
+        ];
+
+        for (path, content, ranges) in expected {
+            let chunks = &files[&PathBuf::from(path)];
+            // Compressed sizes come from `endOffset` of TOC entries.
+            let compressed = chunks
+                .iter()
+                .map(|c| (c.inner.compressed_offset(), c.inner.compressed_size()))
+                .collect::<Vec<_>>();
+            assert_eq!(compressed, ranges);
+
+            // Each chunk could be decompressed independently.
+            for chunk in chunks {
+                let start = chunk.inner.compressed_offset() as usize;
+                let end = start + chunk.inner.compressed_size() as usize;
+                let mut buf = vec![0u8; chunk.inner.uncompressed_size() as usize];
+                compress::decompress(&layer[start..end], &mut buf, compress::Algorithm::Zstd)
+                    .unwrap();
+                let offset = chunk.inner.file_offset() as usize;
+                assert_eq!(buf, content[offset..offset + buf.len()]);
+            }
+        }
+    }
+}
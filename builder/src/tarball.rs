@@ -4,7 +4,8 @@
 
 //! Generate RAFS filesystem from a tarball.
 //!
-//! It support generating RAFS filesystem from a tar/targz/stargz file with or without data blob.
+//! It support generating RAFS filesystem from a tar/targz/stargz file with or without data blob,
+//! or from a plain/gzip/zstd compressed tar stream read from stdin.
 //!
 //! The tarball data is arrange as a sequence of tar headers with associated file data interleaved.
 //! - (tar header) (tar header) (file data) (tar header) (file data) (tar header)
@@ -18,7 +19,7 @@
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::fs::{File, OpenOptions};
-use std::io::Read;
+use std::io::{self, Cursor, Read};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
@@ -32,23 +33,24 @@ use nydus_storage::meta::ZranContextGenerator;
 use nydus_storage::RAFS_MAX_CHUNKS_PER_BLOB;
 use nydus_utils::compact::makedev;
 use nydus_utils::compress::zlib_random::ZranReader;
-use nydus_utils::compress::ZlibDecoder;
+use nydus_utils::compress::{self, Decoder, ZlibDecoder};
 use nydus_utils::digest::RafsDigest;
 use nydus_utils::{div_round_up, ByteSize};
 use tar::{Archive, Entry, EntryType, Header};
 
-use crate::builder::{build_bootstrap, dump_bootstrap, Builder};
 use crate::core::blob::Blob;
 use crate::core::context::{
     ArtifactWriter, BlobManager, BootstrapManager, BuildContext, BuildOutput, ConversionType,
 };
 use crate::core::node::{Node, Overlay};
 use crate::core::tree::Tree;
+use crate::{build_bootstrap, dump_bootstrap, Builder};
 
 enum TarReader {
     File(File),
     TarGz(Box<ZlibDecoder<File>>),
     Zran(ZranReader<File>),
+    Stream(Box<dyn Read>),
 }
 
 impl Read for TarReader {
@@ -57,10 +59,37 @@ impl Read for TarReader {
             TarReader::File(f) => f.read(buf),
             TarReader::TarGz(f) => f.read(buf),
             TarReader::Zran(f) => f.read(buf),
+            TarReader::Stream(f) => f.read(buf),
         }
     }
 }
 
+impl TarReader {
+    /// Create a reader for a plain/gzip/zstd compressed tar stream.
+    ///
+    /// The stream can only be read once, so the magic number used to detect compression algorithm
+    /// is chained back in front of the remaining stream data.
+    fn from_stream(mut reader: Box<dyn Read>) -> Result<Self> {
+        let mut magic = Vec::with_capacity(4);
+        (&mut reader)
+            .take(4)
+            .read_to_end(&mut magic)
+            .context("failed to read magic number from tar stream")?;
+        let algo = if magic.starts_with(&[0x1f, 0x8b]) {
+            compress::Algorithm::GZip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            compress::Algorithm::Zstd
+        } else {
+            compress::Algorithm::None
+        };
+        let reader = Cursor::new(magic).chain(reader);
+        let decoder = Decoder::<'static, _>::new(reader, algo)
+            .context("failed to create decoder for tar stream")?;
+
+        Ok(TarReader::Stream(Box::new(decoder)))
+    }
+}
+
 pub(crate) struct TarballTreeBuilder<'a> {
     ty: ConversionType,
     layer_idx: u16,
@@ -69,6 +98,9 @@ pub(crate) struct TarballTreeBuilder<'a> {
     writer: &'a mut Option<ArtifactWriter>,
     buf: Vec<u8>,
     path_inode_map: HashMap<PathBuf, Inode>,
+    // Map inode number to index into the generated node list, for hardlink handling.
+    inode_node_map: HashMap<Inode, usize>,
+    next_ino: Inode,
 }
 
 impl<'a> TarballTreeBuilder<'a> {
@@ -88,28 +120,18 @@ impl<'a> TarballTreeBuilder<'a> {
             buf: Vec::new(),
             writer,
             path_inode_map: HashMap::new(),
+            inode_node_map: HashMap::new(),
+            next_ino: 0,
         }
     }
 
     fn build_tree(&mut self) -> Result<Tree> {
-        let file = OpenOptions::new()
-            .read(true)
-            .open(self.ctx.source_path.clone())
-            .with_context(|| "can not open source file for conversion")?;
-
-        let reader = match self.ty {
-            ConversionType::TarToRafs => TarReader::File(file),
-            ConversionType::EStargzToRafs | ConversionType::TargzToRafs => {
-                TarReader::TarGz(Box::new(ZlibDecoder::new(file)))
-            }
-            ConversionType::EStargzToRef | ConversionType::TargzToRef => {
-                assert!(self.writer.is_none());
-                let generator = ZranContextGenerator::new(file)?;
-                let reader = generator.reader();
-                self.ctx.blob_zran_generator = Some(Mutex::new(generator));
-                TarReader::Zran(reader)
-            }
-            _ => return Err(anyhow!("unsupported image conversion type")),
+        let reader = if self.ty == ConversionType::TargzStreamToRafs
+            && self.ctx.source_path == Path::new("-")
+        {
+            TarReader::from_stream(Box::new(io::stdin()))?
+        } else {
+            self.open_source()?
         };
         let mut tar = Archive::new(reader);
         tar.set_ignore_zeros(true);
@@ -125,7 +147,7 @@ impl<'a> TarballTreeBuilder<'a> {
         // Generate the root node in advance, it may be overwritten by entries from the tar stream.
         let root = self.create_directory(Path::new("/"))?;
         let mut nodes = Vec::with_capacity(10240);
-        nodes.push(root.clone());
+        self.push_node(&mut nodes, root.clone());
 
         // Generate RAFS node for each tar entry, and optionally adding missing parents.
         let entries = tar
@@ -141,7 +163,7 @@ impl<'a> TarballTreeBuilder<'a> {
             if !self.is_special_files(path) {
                 self.make_lost_dirs(&path, &mut nodes)?;
                 let node = self.parse_entry(&nodes, &mut entry, path)?;
-                nodes.push(node);
+                self.push_node(&mut nodes, node);
             }
         }
 
@@ -159,6 +181,46 @@ impl<'a> TarballTreeBuilder<'a> {
         Ok(tree)
     }
 
+    fn open_source(&mut self) -> Result<TarReader> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(self.ctx.source_path.clone())
+            .with_context(|| "can not open source file for conversion")?;
+
+        let reader = match self.ty {
+            ConversionType::TarToRafs => TarReader::File(file),
+            ConversionType::TargzStreamToRafs => TarReader::from_stream(Box::new(file))?,
+            ConversionType::EStargzToRafs | ConversionType::TargzToRafs => {
+                TarReader::TarGz(Box::new(ZlibDecoder::new(file)))
+            }
+            ConversionType::EStargzToRef | ConversionType::TargzToRef => {
+                assert!(self.writer.is_none());
+                let generator = ZranContextGenerator::new(file)?;
+                let reader = generator.reader();
+                self.ctx.blob_zran_generator = Some(Mutex::new(generator));
+                TarReader::Zran(reader)
+            }
+            _ => return Err(anyhow!("unsupported image conversion type")),
+        };
+
+        Ok(reader)
+    }
+
+    // Entries may be overwritten by later entries with the same path, so allocate inode numbers
+    // by counter instead of by number of known paths to avoid duplicated inode numbers.
+    fn alloc_ino(&mut self) -> Inode {
+        self.next_ino += 1;
+        self.next_ino
+    }
+
+    fn push_node(&mut self, nodes: &mut Vec<Node>, node: Node) {
+        // Hardlink nodes share the inode number of the first node, keep the first one.
+        self.inode_node_map
+            .entry(node.inode.ino())
+            .or_insert(nodes.len());
+        nodes.push(node);
+    }
+
     fn parse_entry<R: Read, P: AsRef<Path>>(
         &mut self,
         nodes: &[Node],
@@ -228,16 +290,16 @@ impl<'a> TarballTreeBuilder<'a> {
         }
 
         // Handle hardlink ino
-        let mut ino = (self.path_inode_map.len() + 1) as Inode;
-        if entry_type.is_hard_link() {
+        let ino = if entry_type.is_hard_link() {
             let link_path = entry
                 .link_name()
                 .with_context(|| "failed to get target path for tar symlink entry")?
                 .ok_or_else(|| anyhow!("failed to get symlink target tor tar entry"))?;
             let link_path = PathBuf::from("/").join(link_path);
             let link_path = link_path.components().as_path();
-            if let Some(_ino) = self.path_inode_map.get(link_path) {
-                ino = *_ino;
+            flags |= RafsV5InodeFlags::HARDLINK;
+            if let Some(ino) = self.path_inode_map.get(link_path) {
+                *ino
             } else {
                 bail!(
                     "unknown target {} for hardlink {}",
@@ -245,27 +307,27 @@ impl<'a> TarballTreeBuilder<'a> {
                     path.as_ref().display()
                 );
             }
-            flags |= RafsV5InodeFlags::HARDLINK;
         } else {
+            let ino = self.alloc_ino();
             self.path_inode_map.insert(path.as_ref().to_path_buf(), ino);
-        }
+            ino
+        };
 
         // Parse xattrs
         let mut xattrs = RafsXAttrs::new();
         if let Some(exts) = entry.pax_extensions()? {
-            let _ = exts
-                .filter_map(|e| e.ok())
-                .filter_map(|e| {
-                    let key = e.key_bytes();
-                    let prefix = b"SCHILY.xattr.";
-                    if key.starts_with(prefix) {
-                        Some((&key[prefix.len()..], e))
-                    } else {
-                        None
-                    }
-                })
-                .map(|(key, e)| (OsStr::from_bytes(key), e.value_bytes()))
-                .map(|(key, value)| xattrs.add(key.to_os_string(), value.to_vec()));
+            let prefix = b"SCHILY.xattr.";
+            for ext in exts {
+                let ext = ext.with_context(|| "failed to parse pax extension from tar entry")?;
+                let key = ext.key_bytes();
+                if key.starts_with(prefix) {
+                    let key = OsStr::from_bytes(&key[prefix.len()..]);
+                    xattrs.add(key.to_os_string(), ext.value_bytes().to_vec())?;
+                }
+            }
+        }
+        if !xattrs.is_empty() {
+            flags |= RafsV5InodeFlags::XATTR;
         }
 
         let v5_inode = RafsV5Inode {
@@ -326,11 +388,15 @@ impl<'a> TarballTreeBuilder<'a> {
         // Tar hardlink header has zero file size and no file data associated, so copy value from
         // the associated regular file.
         if entry_type.is_hard_link() {
-            let idx = (node.inode.ino() - 1) as usize;
-            let n = &nodes[idx];
+            let idx = self
+                .inode_node_map
+                .get(&node.inode.ino())
+                .ok_or_else(|| anyhow!("failed to find target node for hardlink"))?;
+            let n = &nodes[*idx];
             node.inode.set_digest(*n.inode.digest());
             node.inode.set_size(n.inode.size());
             node.inode.set_child_count(n.inode.child_count());
+            node.inode.set_has_hole(n.inode.has_hole());
             node.chunks = n.chunks.clone();
             node.xattrs = n.xattrs.clone();
         } else {
@@ -414,7 +480,7 @@ impl<'a> TarballTreeBuilder<'a> {
             if !self.path_inode_map.contains_key(parent_path) {
                 self.make_lost_dirs(parent_path, nodes)?;
                 let node = self.create_directory(parent_path)?;
-                nodes.push(node);
+                self.push_node(nodes, node);
             }
         }
 
@@ -422,7 +488,7 @@ impl<'a> TarballTreeBuilder<'a> {
     }
 
     fn create_directory(&mut self, path: &Path) -> Result<Node> {
-        let ino = (self.path_inode_map.len() + 1) as Inode;
+        let ino = self.alloc_ino();
         let name = Self::get_file_name(path)?;
         let mut inode = InodeWrapper::new(self.ctx.fs_version);
         inode.set_ino(ino);
@@ -487,7 +553,7 @@ impl<'a> TarballTreeBuilder<'a> {
     }
 }
 
-pub(crate) struct TarballBuilder {
+pub struct TarballBuilder {
     ty: ConversionType,
 }
 
@@ -513,6 +579,7 @@ impl Builder for TarballBuilder {
         match self.ty {
             ConversionType::EStargzToRafs
             | ConversionType::TargzToRafs
+            | ConversionType::TargzStreamToRafs
             | ConversionType::TarToRafs => {
                 if let Some(blob_stor) = ctx.blob_storage.clone() {
                     writer = Some(ArtifactWriter::new(blob_stor, ctx.inline_bootstrap)?);
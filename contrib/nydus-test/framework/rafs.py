@@ -222,6 +222,10 @@ class RafsConf:
         self._configure_rafs("amplify_io", size)
         return self
 
+    def bulk_read_threshold(self, size):
+        self._configure_rafs("bulk_read_threshold", size)
+        return self
+
     def rafs_mem_mode(self, v):
         self._configure_rafs("mode", v)
 
@@ -1,6 +1,7 @@
 import os
 import tempfile
 import time
+import logging
 import random
 import signal
 import stat
@@ -256,6 +257,75 @@ def test_large_file(nydus_anchor, compressor, amplified_size):
     image.clean_up()
 
 
+@pytest.mark.parametrize("bulk_read_threshold", [0, Size(1, Unit.MB).B])
+def test_large_read_throughput(nydus_anchor, bulk_read_threshold):
+    """
+    title: Large read throughput
+    description: Read large files by multi-megabyte requests from a warm blobcache,
+      with the bulk read path disabled or enabled, to compare read throughput.
+    pass_criteria:
+      - Data read from the mount is the same as the source files
+    """
+    _tmp_dir = tempfile.TemporaryDirectory(dir=nydus_anchor.workspace)
+    large_file_dir = _tmp_dir.name
+
+    dist = Distributor(large_file_dir, 1, 1)
+    dist.generate_tree()
+    names = [f"large_file_{i}" for i in range(4)]
+    for name in names:
+        dist.put_single_file(Size(64, Unit.MB), pos=large_file_dir, name=name)
+
+    image = RafsImage(nydus_anchor, large_file_dir, "bs_bulk_read", "blob_bulk_read")
+    image.set_backend(Backend.BACKEND_PROXY).create_image(compressor=Compressor.NONE)
+
+    rafs_conf = (
+        RafsConf(nydus_anchor, image)
+        .enable_rafs_blobcache()
+        .bulk_read_threshold(bulk_read_threshold)
+        .set_rafs_backend(Backend.BACKEND_PROXY, image=image)
+    )
+
+    rafs = NydusDaemon(nydus_anchor, image, rafs_conf)
+    rafs.thread_num(4).mount()
+
+    def read_all(path, buf_size=Size(4, Unit.MB).B):
+        data = []
+        fd = os.open(path, os.O_RDONLY)
+        try:
+            while True:
+                buf = os.read(fd, buf_size)
+                if not buf:
+                    break
+                data.append(buf)
+        finally:
+            os.close(fd)
+        return b"".join(data)
+
+    # The first pass fetches all data into the blobcache.
+    for name in names:
+        read_all(os.path.join(nydus_anchor.mountpoint, name))
+
+    total = 0
+    elapsed = 0
+    for name in names:
+        begin = time.time()
+        data = read_all(os.path.join(nydus_anchor.mountpoint, name))
+        elapsed += time.time() - begin
+        with open(os.path.join(large_file_dir, name), "rb") as f:
+            assert data == f.read()
+        total += len(data)
+    logging.info(
+        "bulk_read_threshold %d: read %d bytes in %.3fs, %.2f MB/s",
+        bulk_read_threshold,
+        total,
+        elapsed,
+        total / elapsed / Size(1, Unit.MB).B,
+    )
+
+    rafs.umount()
+    image.clean_up()
+
+
 def test_hardlink(nydus_anchor: NydusAnchor, nydus_scratch_image, rafs_conf: RafsConf):
     dist = Distributor(nydus_scratch_image.rootfs(), 8, 6)
     dist.generate_tree()
@@ -0,0 +1,15 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nydus_rafs::metadata::fuzz::{parse_superblock, walk_all};
+use nydus_rafs::metadata::RafsVersion;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(RafsVersion::V5) = parse_superblock(data) {
+        let _ = walk_all(data);
+    }
+});
@@ -16,6 +16,9 @@
 
 use std::any::Any;
 use std::cmp;
+#[cfg(feature = "virtio-fs")]
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::ffi::{CStr, OsStr, OsString};
 use std::fmt;
@@ -23,31 +26,194 @@ use std::fs::File;
 use std::io::Result;
 use std::ops::Deref;
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+#[cfg(feature = "virtio-fs")]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use fuse_backend_rs::abi::fuse_abi::Attr;
 use fuse_backend_rs::abi::fuse_abi::{stat64, statvfs64};
+#[cfg(feature = "virtio-fs")]
+use fuse_backend_rs::abi::virtio_fs;
 use fuse_backend_rs::api::filesystem::*;
 use fuse_backend_rs::api::BackendFileSystem;
+#[cfg(feature = "virtio-fs")]
+use fuse_backend_rs::transport::FsCacheReqHandler;
 use nix::unistd::{getegid, geteuid};
-use serde::Deserialize;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
 
-use nydus_api::http::{BlobPrefetchConfig, FactoryConfig};
-use nydus_storage::device::{BlobDevice, BlobIoVec, BlobPrefetchRequest};
+use nydus_api::http::{BlobPrefetchConfig, FactoryConfig, FileAdvice};
+use nydus_storage::device::{
+    BlobAccessInfo, BlobCachedFile, BlobDevice, BlobInfo, BlobInfoSource, BlobIoVec,
+    BlobPrefetchRequest,
+};
 use nydus_storage::{RAFS_DEFAULT_CHUNK_SIZE, RAFS_MAX_CHUNK_SIZE};
+use nydus_utils::audit::{self, EventCategory};
+use nydus_utils::crypt::{self, CipherKey, KEY_REF_LEN};
+use nydus_utils::digest::RafsDigest;
 use nydus_utils::metrics::{self, FopRecorder, StatsFop::*};
+#[cfg(feature = "virtio-fs")]
+use nydus_utils::round_up;
+use nydus_utils::trace::RequestTrace;
 
 use crate::metadata::{
-    Inode, RafsInode, RafsInodeWalkAction, RafsSuper, RafsSuperMeta, DOT, DOTDOT,
+    Inode, RafsInode, RafsInodeWalkAction, RafsMode, RafsSuper, RafsSuperHandle, RafsSuperMeta,
+    DOT, DOTDOT,
 };
 use crate::{RafsError, RafsIoReader, RafsResult};
 
 /// Type of RAFS fuse handle.
 pub type Handle = u64;
 
+/// Identity of a file, to detect inode numbers reused by another file after updating metadata.
+#[derive(Debug, PartialEq)]
+struct FileIdentity {
+    mode: u32,
+    size: u64,
+    mtime: u64,
+    mtimensec: u32,
+    digest: Option<RafsDigest>,
+}
+
+impl FileIdentity {
+    fn new(inode: &dyn RafsInode) -> Self {
+        let attr = inode.get_attr();
+        FileIdentity {
+            mode: attr.mode,
+            size: attr.size,
+            mtime: attr.mtime,
+            mtimensec: attr.mtimensec,
+            digest: if inode.is_reg() {
+                inode.content_hash().ok()
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Policy to amplify user reads with readahead.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadaheadMode {
+    /// Scale the readahead window according to access pattern of each opened file.
+    Adaptive,
+    /// Always readahead with the maximum window, as if all files are accessed sequentially.
+    Sequential,
+    /// Never readahead, as if all files are accessed randomly.
+    Random,
+}
+
+impl Default for ReadaheadMode {
+    fn default() -> Self {
+        ReadaheadMode::Adaptive
+    }
+}
+
+// Score of a file accessed sequentially, a file with a score no less than half of it is classified
+// as sequential.
+const READAHEAD_MAX_SCORE: u32 = 8;
+
+/// Access pattern of an opened file to scale the readahead window.
+struct ReadaheadState {
+    // Offset following the last read.
+    next_offset: u64,
+    score: u32,
+}
+
+impl ReadaheadState {
+    // New opened files are assumed to be accessed sequentially until proven otherwise.
+    fn new() -> Self {
+        ReadaheadState {
+            next_offset: 0,
+            score: READAHEAD_MAX_SCORE,
+        }
+    }
+
+    fn is_sequential(&self) -> bool {
+        self.score * 2 >= READAHEAD_MAX_SCORE
+    }
+
+    // Record a read and return the readahead window scaled from `max_window`. Sequential reads
+    // grow the score slowly, random reads halve it to back off quickly.
+    fn update(&mut self, offset: u64, size: u64, max_window: u32) -> u32 {
+        if offset == self.next_offset {
+            self.score = cmp::min(self.score + 1, READAHEAD_MAX_SCORE);
+        } else {
+            self.score /= 2;
+        }
+        self.next_offset = offset + size;
+        (max_window as u64 * self.score as u64 / READAHEAD_MAX_SCORE as u64) as u32
+    }
+}
+
+/// Maximum bytes of file data to prefetch on advice from applications per second, per filesystem.
+pub const RAFS_DEFAULT_ADVICE_BUDGET: u64 = 64 << 20;
+
+// Budget to prevent applications from flooding the prefetch queue with advices.
+struct AdviceBudget {
+    window_start: Instant,
+    used: u64,
+    limit: u64,
+}
+
+impl AdviceBudget {
+    fn new(limit: u64) -> Self {
+        AdviceBudget {
+            window_start: Instant::now(),
+            used: 0,
+            limit,
+        }
+    }
+
+    // Charge up to `size` bytes against the budget of the current one-second window, return the
+    // number of bytes granted.
+    fn charge(&mut self, size: u64) -> u64 {
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+        let granted = cmp::min(size, self.limit.saturating_sub(self.used));
+        self.used += granted;
+        granted
+    }
+}
+
+/// State of a file opened by fuse `open` request.
+struct OpenedFile {
+    ino: Inode,
+    // The file has been replaced by another file when updating filesystem metadata.
+    stale: bool,
+    cached_file: Option<BlobCachedFile>,
+    readahead: Mutex<ReadaheadState>,
+}
+
+/// Kernel cache invalidation needed after updating filesystem metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RafsInvalidation {
+    /// Invalidate the directory entry `name` of directory `parent`.
+    Entry { parent: Inode, name: OsString },
+    /// Invalidate attributes and cached data of an inode.
+    Inode(Inode),
+}
+
+// Attributes of a directory entry, to detect changed entries across metadata updates.
+#[derive(PartialEq)]
+struct EntryState {
+    ino: Inode,
+    mode: u32,
+    size: u64,
+    mtime: u64,
+    mtimensec: u32,
+}
+
+// Directory entries of a filesystem tree, indexed by parent inode number and name.
+type TreeSnapshot = HashMap<(Inode, OsString), EntryState>;
+
 /// Rafs default attribute timeout value.
 pub const RAFS_DEFAULT_ATTR_TIMEOUT: u64 = 1 << 32;
 /// Rafs default entry timeout value.
@@ -69,8 +235,12 @@ fn default_amplify_io() -> u32 {
     128 * 1024
 }
 
+fn default_bulk_read_threshold() -> u32 {
+    1024 * 1024
+}
+
 /// Configuration information for filesystem data prefetch.
-#[derive(Clone, Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct FsPrefetchControl {
     /// Whether the filesystem layer data prefetch is enabled or not.
     #[serde(default)]
@@ -100,6 +270,20 @@ pub struct FsPrefetchControl {
     pub prefetch_all: bool,
 }
 
+/// Configuration information for virtio-fs DAX window.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct DaxConfig {
+    /// Whether to map cached file data into the virtio-fs DAX window.
+    #[serde(default)]
+    pub enable: bool,
+    /// Size of the DAX window, zero means no limitation.
+    #[serde(default)]
+    pub window_size: u64,
+    /// Maximum number of DAX mappings per inode, zero means no limitation.
+    #[serde(default)]
+    pub max_mappings_per_inode: usize,
+}
+
 impl TryFrom<&RafsConfig> for BlobPrefetchConfig {
     type Error = RafsError;
 
@@ -136,12 +320,12 @@ macro_rules! trim_backend_config {
 }
 
 /// Rafs storage backend configuration information.
-#[derive(Clone, Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct RafsConfig {
     /// Configuration for storage subsystem.
     pub device: FactoryConfig,
     /// Filesystem working mode.
-    pub mode: String,
+    pub mode: RafsMode,
     /// Whether to validate data digest before use.
     #[serde(default)]
     pub digest_validate: bool,
@@ -163,6 +347,63 @@ pub struct RafsConfig {
     // ZERO value means, amplifying user io is not enabled.
     #[serde(default = "default_amplify_io")]
     pub amplify_io: u32,
+    /// Policy to scale the window to amplify user io, up to `amplify_io`.
+    #[serde(default)]
+    pub readahead_mode: ReadaheadMode,
+    /// Master keys to decrypt data keys of encrypted blobs, mapping key id to hex encoded key.
+    #[serde(default)]
+    pub encryption_keys: HashMap<String, String>,
+    /// Serve reads of files fully cached in uncompressed form directly from the cache file.
+    #[serde(default)]
+    pub cached_file_passthrough: bool,
+    /// Minimum size of chunk aligned reads to be served directly from the cache file if all
+    /// requested data is ready in uncompressed form, zero to disable.
+    #[serde(default = "default_bulk_read_threshold")]
+    pub bulk_read_threshold: u32,
+    /// Virtio-fs DAX window configuration.
+    #[serde(default)]
+    pub dax: DaxConfig,
+    /// Recompute nlink of directories recorded as 1 from the number of child directories.
+    #[serde(default)]
+    pub fix_dir_nlink: bool,
+    /// Maximum number of kernel cache invalidations to generate after updating the filesystem
+    /// metadata, zero to disable.
+    #[serde(default)]
+    pub invalidation_budget: usize,
+    /// Override the timeout for the kernel to cache inode attributes, in seconds.
+    #[serde(default, with = "optional_secs")]
+    pub attr_timeout_override: Option<Duration>,
+    /// Override the timeout for the kernel to cache directory entries, in seconds.
+    #[serde(default, with = "optional_secs")]
+    pub entry_timeout_override: Option<Duration>,
+}
+
+// (De)serialize optional durations as number of seconds, such as `1.5`.
+mod optional_secs {
+    use std::time::Duration;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        v: &Option<Duration>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match v {
+            Some(d) => serializer.serialize_some(&d.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Option<Duration>, D::Error> {
+        match Option::<f64>::deserialize(deserializer)? {
+            Some(v) if v.is_finite() && v >= 0.0 => Ok(Some(Duration::from_secs_f64(v))),
+            Some(v) => Err(D::Error::custom(format!("invalid timeout {}", v))),
+            None => Ok(None),
+        }
+    }
 }
 
 impl RafsConfig {
@@ -208,14 +449,26 @@ pub struct Rafs {
     id: String,
     device: BlobDevice,
     ios: Arc<metrics::FsIoStats>,
-    sb: Arc<RafsSuper>,
+    sb: RafsSuperHandle,
 
     initialized: bool,
-    digest_validate: bool,
+    digest_validate: AtomicBool,
     fs_prefetch: bool,
     prefetch_all: bool,
     xattr_enabled: bool,
     amplify_io: u32,
+    readahead_mode: ReadaheadMode,
+    cached_file_passthrough: bool,
+    bulk_read_threshold: u32,
+    fix_dir_nlink: bool,
+    opened_files: RwLock<HashMap<Handle, OpenedFile>>,
+    invalidations: Mutex<Vec<RafsInvalidation>>,
+    next_handle: AtomicU64,
+    advice_budget: Mutex<AdviceBudget>,
+    #[cfg(feature = "virtio-fs")]
+    dax: DaxConfig,
+    #[cfg(feature = "virtio-fs")]
+    dax_mappings: Mutex<DaxMappings>,
 
     // static inode attributes
     i_uid: u32,
@@ -223,6 +476,71 @@ pub struct Rafs {
     i_time: u64,
 }
 
+/// Blob information source which unwraps data keys of encrypted blobs on first access.
+struct CipherKeySource {
+    inner: Arc<dyn BlobInfoSource>,
+    keys: HashMap<String, String>,
+    blobs: Vec<OnceCell<Arc<BlobInfo>>>,
+}
+
+impl CipherKeySource {
+    fn setup_cipher_key(&self, blob_info: Arc<BlobInfo>) -> Result<Arc<BlobInfo>> {
+        if !blob_info.is_encrypted() || blob_info.cipher_key().is_some() {
+            return Ok(blob_info);
+        }
+
+        let (_, master) = self
+            .keys
+            .iter()
+            .find(|(id, _)| &crypt::key_ref(id) == blob_info.cipher_key_ref())
+            .ok_or_else(|| {
+                einval!(format!(
+                    "no encryption key configured for encrypted blob {}",
+                    blob_info.blob_id()
+                ))
+            })?;
+        let key = CipherKey::from_hex(master)
+            .and_then(|master| crypt::unwrap_key(&master, blob_info.cipher_wrapped_key()))
+            .map_err(|e| {
+                einval!(format!(
+                    "failed to unwrap data key for encrypted blob {}, {}",
+                    blob_info.blob_id(),
+                    e
+                ))
+            })?;
+        let mut blob_info = blob_info.as_ref().clone();
+        blob_info.set_cipher_key(Arc::new(key));
+        Ok(Arc::new(blob_info))
+    }
+}
+
+impl BlobInfoSource for CipherKeySource {
+    fn blob_count(&self) -> usize {
+        self.blobs.len()
+    }
+
+    fn blob_id(&self, blob_index: u32) -> Option<String> {
+        self.inner.blob_id(blob_index)
+    }
+
+    fn blob_info(&self, blob_index: u32) -> Result<Arc<BlobInfo>> {
+        let blob = self
+            .blobs
+            .get(blob_index as usize)
+            .ok_or_else(|| einval!(format!("blob index {} is out of range", blob_index)))?;
+        blob.get_or_try_init(|| {
+            self.inner
+                .blob_info(blob_index)
+                .and_then(|blob_info| self.setup_cipher_key(blob_info))
+        })
+        .map(|blob_info| blob_info.clone())
+    }
+
+    fn cipher_key_ref(&self, blob_index: u32) -> Result<Option<[u8; KEY_REF_LEN]>> {
+        self.inner.cipher_key_ref(blob_index)
+    }
+}
+
 impl Rafs {
     /// Create a new instance of `Rafs`.
     pub fn new(conf: RafsConfig, id: &str, r: &mut RafsIoReader) -> RafsResult<Self> {
@@ -230,9 +548,13 @@ impl Rafs {
         let mut sb = RafsSuper::new(&conf).map_err(RafsError::FillSuperblock)?;
         sb.load(r).map_err(RafsError::FillSuperblock)?;
 
-        let blob_infos = sb.superblock.get_blob_infos();
+        let blob_source = sb
+            .superblock
+            .get_blob_source()
+            .map_err(RafsError::FillSuperblock)?;
+        let blob_source = Self::setup_cipher_keys(&conf, blob_source)?;
         let device =
-            BlobDevice::new(&storage_conf, &blob_infos).map_err(RafsError::CreateDevice)?;
+            BlobDevice::with_source(&storage_conf, blob_source).map_err(RafsError::CreateDevice)?;
 
         let rafs = Rafs {
             id: id.to_string(),
@@ -241,11 +563,23 @@ impl Rafs {
             sb: Arc::new(sb),
 
             initialized: false,
-            digest_validate: conf.digest_validate,
+            digest_validate: AtomicBool::new(conf.digest_validate),
             fs_prefetch: conf.fs_prefetch.enable,
             amplify_io: conf.amplify_io,
+            readahead_mode: conf.readahead_mode,
             prefetch_all: conf.fs_prefetch.prefetch_all,
             xattr_enabled: conf.enable_xattr,
+            cached_file_passthrough: conf.cached_file_passthrough,
+            bulk_read_threshold: conf.bulk_read_threshold,
+            fix_dir_nlink: conf.fix_dir_nlink,
+            opened_files: RwLock::new(HashMap::new()),
+            invalidations: Mutex::new(Vec::new()),
+            next_handle: AtomicU64::new(1),
+            advice_budget: Mutex::new(AdviceBudget::new(RAFS_DEFAULT_ADVICE_BUDGET)),
+            #[cfg(feature = "virtio-fs")]
+            dax: conf.dax.clone(),
+            #[cfg(feature = "virtio-fs")]
+            dax_mappings: Mutex::new(DaxMappings::default()),
 
             i_uid: geteuid().into(),
             i_gid: getegid().into(),
@@ -280,6 +614,17 @@ impl Rafs {
 
     /// Update storage backend for blobs.
     pub fn update(&self, r: &mut RafsIoReader, conf: RafsConfig) -> RafsResult<()> {
+        let detail = serde_json::json!({
+            "backend_type": conf.device.backend.backend_type,
+            "cache_type": conf.device.cache.cache_type,
+            "digest_validate": conf.digest_validate,
+        });
+        let result = self.do_update(r, conf);
+        audit::record(EventCategory::Update, Some(&self.id), detail, &result);
+        result
+    }
+
+    fn do_update(&self, r: &mut RafsIoReader, conf: RafsConfig) -> RafsResult<()> {
         info!("update");
         if !self.initialized {
             warn!("Rafs is not yet initialized");
@@ -289,24 +634,69 @@ impl Rafs {
         // TODO: seems no need to do self.sb.update()
         // step 1: update sb.
         // No lock is needed thanks to ArcSwap.
+        let identities = self.get_opened_file_identities();
+        let snapshot = if conf.invalidation_budget > 0 {
+            self.snapshot_tree()
+                .map_err(|e| warn!("failed to snapshot filesystem tree, {}", e))
+                .ok()
+        } else {
+            None
+        };
         self.sb.update(r).map_err(|e| {
             error!("update failed due to {:?}", e);
             e
         })?;
-        info!("update sb is successful");
+        let stale = self.mark_stale_files(&identities);
+        info!(
+            "update sb is successful, generation {}, {} opened files replaced",
+            self.sb.superblock.generation(),
+            stale
+        );
 
         let storage_conf = Self::prepare_storage_conf(&conf)?;
-        let blob_infos = self.sb.superblock.get_blob_infos();
+        let blob_source = self
+            .sb
+            .superblock
+            .get_blob_source()
+            .map_err(RafsError::SwapBackend)?;
+        let blob_source = Self::setup_cipher_keys(&conf, blob_source)?;
 
         // step 2: update device (only localfs is supported)
         self.device
-            .update(&storage_conf, &blob_infos, self.fs_prefetch)
+            .update_with_source(&storage_conf, blob_source, self.fs_prefetch)
             .map_err(RafsError::SwapBackend)?;
+        self.device.set_validation(conf.digest_validate);
+        self.digest_validate
+            .store(conf.digest_validate, Ordering::Relaxed);
         info!("update device is successful");
 
+        if let Some(old) = snapshot {
+            match self.snapshot_tree() {
+                Ok(new) => {
+                    let invalidations = Self::diff_tree(&old, &new, conf.invalidation_budget);
+                    info!("{} kernel cache invalidations pending", invalidations.len());
+                    *self.invalidations.lock().unwrap() = invalidations;
+                }
+                Err(e) => warn!("failed to snapshot filesystem tree, {}", e),
+            }
+        }
+
         Ok(())
     }
 
+    /// Take kernel cache invalidations generated by the last metadata update.
+    ///
+    /// Inode numbers are the filesystem's own inode numbers, the caller is responsible for
+    /// converting them before notifying the kernel.
+    pub fn take_invalidations(&self) -> Vec<RafsInvalidation> {
+        std::mem::take(&mut *self.invalidations.lock().unwrap())
+    }
+
+    /// Record number of kernel cache invalidations sent to the kernel.
+    pub fn record_invalidations(&self, entries: usize, inodes: usize) {
+        self.ios.record_invalidations(entries as u64, inodes as u64);
+    }
+
     /// Import an rafs bootstrap to initialize the filesystem instance.
     pub fn import(
         &mut self,
@@ -354,6 +744,88 @@ impl Rafs {
         &self.sb.meta
     }
 
+    /// Get the working mode of the filesystem metadata.
+    pub fn mode(&self) -> RafsMode {
+        self.sb.mode.clone()
+    }
+
+    /// Get number of data blobs referenced by the filesystem.
+    pub fn blob_count(&self) -> usize {
+        self.sb
+            .superblock
+            .get_blob_source()
+            .map(|source| source.blob_count())
+            .unwrap_or_default()
+    }
+
+    /// Get access information about data blobs, to find out layers actually used by workloads.
+    ///
+    /// Data blobs are registered with the storage backend on first access.
+    pub fn get_blob_access_info(&self) -> Vec<BlobAccessInfo> {
+        self.device.get_blob_access_info()
+    }
+
+    /// Check whether data digest validation is enabled.
+    pub fn digest_validate(&self) -> bool {
+        self.digest_validate.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable data digest validation for the mounted filesystem at runtime.
+    ///
+    /// Data chunks already validated are not validated again when read from the blob cache.
+    pub fn set_digest_validate(&self, enable: bool) -> RafsResult<()> {
+        if enable && self.metadata().is_v6() {
+            return Err(RafsError::Configure(
+                "Rafs v6 doesn't support integrity validation yet".to_string(),
+            ));
+        }
+        self.device.set_validation(enable);
+        self.digest_validate.store(enable, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read data of file `ino` from `offset` into the buffer, return number of bytes read.
+    ///
+    /// It's used by services without zero-copy writers, such as the NFS server, and shares
+    /// metrics with the FUSE read path.
+    pub fn read_to_buf(&self, ino: Inode, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let inode = self.sb.get_inode(ino, false)?;
+        let inode_size = inode.size();
+        let mut recorder = FopRecorder::settle(Read, ino, &self.ios);
+        if buf.is_empty() || offset >= inode_size {
+            recorder.mark_success(0);
+            return Ok(0);
+        }
+
+        let real_size = cmp::min(buf.len() as u64, inode_size - offset) as usize;
+        if let Some(data) = inode.get_inline_data()? {
+            let start = cmp::min(offset as usize, data.len());
+            let end = cmp::min(start + real_size, data.len());
+            buf[..end - start].copy_from_slice(&data[start..end]);
+            recorder.mark_success(end - start);
+            return Ok(end - start);
+        }
+
+        let mut descs = inode.alloc_bio_vecs(&self.device, offset, real_size, true)?;
+        let mut result = 0;
+        let start = self.ios.latency_start();
+        for desc in descs.iter_mut() {
+            let size = desc.size() as usize;
+            if result + size > real_size {
+                return Err(einval!("blob io vectors exceed requested size"));
+            }
+            let r = self.device.read(desc, &mut buf[result..result + size])?;
+            result += r;
+            recorder.mark_success(r);
+            if r != size {
+                break;
+            }
+        }
+        self.ios.latency_end(&start, Read);
+
+        Ok(result)
+    }
+
     fn prepare_storage_conf(conf: &RafsConfig) -> RafsResult<Arc<FactoryConfig>> {
         let mut storage_conf = conf.device.clone();
         storage_conf.cache.cache_validate = conf.digest_validate;
@@ -361,6 +833,39 @@ impl Rafs {
         Ok(Arc::new(storage_conf))
     }
 
+    // Unwrap data keys of encrypted blobs with master keys from the configuration.
+    //
+    // Data keys are unwrapped when the blob is materialized on first access.
+    fn setup_cipher_keys(
+        conf: &RafsConfig,
+        source: Arc<dyn BlobInfoSource>,
+    ) -> RafsResult<Arc<dyn BlobInfoSource>> {
+        // Data keys are unwrapped on first access to blobs, but reject missing keys early.
+        for idx in 0..source.blob_count() as u32 {
+            let key_ref = source
+                .cipher_key_ref(idx)
+                .map_err(|e| RafsError::Configure(e.to_string()))?;
+            if let Some(key_ref) = key_ref {
+                let found = conf.encryption_keys.iter().any(|(id, master)| {
+                    crypt::key_ref(id) == key_ref && CipherKey::from_hex(master).is_ok()
+                });
+                if !found {
+                    return Err(RafsError::Configure(format!(
+                        "no encryption key configured for encrypted blob {}",
+                        source.blob_id(idx).unwrap_or_default()
+                    )));
+                }
+            }
+        }
+
+        let blobs = (0..source.blob_count()).map(|_| OnceCell::new()).collect();
+        Ok(Arc::new(CipherKeySource {
+            inner: source,
+            keys: conf.encryption_keys.clone(),
+            blobs,
+        }))
+    }
+
     fn xattr_supported(&self) -> bool {
         self.xattr_enabled || self.sb.meta.has_xattr()
     }
@@ -370,24 +875,25 @@ impl Rafs {
         ino: Inode,
         size: u32,
         offset: u64,
-        add_entry: &mut dyn FnMut(DirEntry) -> Result<usize>,
+        add_entry: &mut dyn FnMut(DirEntry, Option<Arc<dyn RafsInode>>) -> Result<usize>,
     ) -> Result<()> {
         if size == 0 {
             return Ok(());
         }
 
-        let parent = self.sb.get_inode(ino, self.digest_validate)?;
+        let parent = self.sb.get_inode(ino, self.digest_validate())?;
         if !parent.is_dir() {
             return Err(enotdir!());
         }
 
-        let mut handler = |_inode, name: OsString, ino, offset| {
-            match add_entry(DirEntry {
+        let mut handler = |inode, name: OsString, ino, offset| {
+            let dir_entry = DirEntry {
                 ino,
                 offset,
                 type_: 0,
                 name: name.as_os_str().as_bytes(),
-            }) {
+            };
+            match add_entry(dir_entry, inode) {
                 Ok(0) => {
                     self.ios.new_file_counter(ino);
                     Ok(RafsInodeWalkAction::Break)
@@ -400,9 +906,195 @@ impl Rafs {
             }
         };
 
-        parent.walk_children_inodes(offset, &mut handler)?;
+        // Report corrupted directories as EUCLEAN, like in-kernel filesystems.
+        parent
+            .walk_children_inodes(offset, &mut handler)
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    std::io::Error::from_raw_os_error(libc::EUCLEAN)
+                } else {
+                    e
+                }
+            })
+    }
+
+    // Get the cache file to serve reads of the file, if all of its data is ready in a single
+    // cache file in uncompressed form.
+    fn get_cached_file(&self, ino: Inode) -> Option<BlobCachedFile> {
+        let inode = self.sb.get_inode(ino, false).ok()?;
+        let size = inode.size();
+        if !inode.is_reg() || size == 0 || size > u32::MAX as u64 || inode.get_chunk_count() == 0 {
+            return None;
+        }
 
-        Ok(())
+        let descs = inode
+            .alloc_bio_vecs(&self.device, 0, size as usize, false)
+            .ok()?;
+        if descs.len() != 1 {
+            return None;
+        }
+        self.device.get_cached_file(&descs[0])
+    }
+
+    fn snapshot_tree(&self) -> Result<TreeSnapshot> {
+        let mut snapshot = HashMap::new();
+        let mut dirs = vec![self.root_ino()];
+
+        while let Some(ino) = dirs.pop() {
+            let dir = self.sb.get_inode(ino, false)?;
+            for idx in 0..dir.get_child_count() {
+                let child = dir.get_child_by_index(idx)?;
+                let attr = child.get_attr();
+                if child.is_dir() {
+                    dirs.push(child.ino());
+                }
+                let state = EntryState {
+                    ino: child.ino(),
+                    mode: attr.mode,
+                    size: attr.size,
+                    mtime: attr.mtime,
+                    mtimensec: attr.mtimensec,
+                };
+                snapshot.insert((ino, child.name()), state);
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    // Generate at most `budget` kernel cache invalidations for entries changed between two
+    // snapshots. Directories go first since stale directory entries hide everything below them.
+    fn diff_tree(old: &TreeSnapshot, new: &TreeSnapshot, budget: usize) -> Vec<RafsInvalidation> {
+        let is_dir = |state: &EntryState| state.mode & libc::S_IFMT == libc::S_IFDIR;
+        let mut parents = Vec::new();
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+
+        let mut old_keys = old.keys().collect::<Vec<_>>();
+        old_keys.sort_unstable();
+        for key in old_keys {
+            let state = &old[key];
+            if new.get(key) == Some(state) {
+                continue;
+            }
+            parents.push(key.0);
+            let list = if is_dir(state) { &mut dirs } else { &mut files };
+            list.push(RafsInvalidation::Entry {
+                parent: key.0,
+                name: key.1.clone(),
+            });
+            list.push(RafsInvalidation::Inode(state.ino));
+        }
+        let mut new_keys = new.keys().collect::<Vec<_>>();
+        new_keys.sort_unstable();
+        for key in new_keys {
+            let state = &new[key];
+            // Drop negative directory entries cached by the kernel.
+            if !old.contains_key(key) {
+                parents.push(key.0);
+                let list = if is_dir(state) { &mut dirs } else { &mut files };
+                list.push(RafsInvalidation::Entry {
+                    parent: key.0,
+                    name: key.1.clone(),
+                });
+            }
+        }
+        parents.sort_unstable();
+
+        let mut seen = HashSet::new();
+        parents
+            .into_iter()
+            .map(RafsInvalidation::Inode)
+            .chain(dirs.into_iter())
+            .chain(files.into_iter())
+            .filter(|inval| seen.insert(inval.clone()))
+            .take(budget)
+            .collect()
+    }
+
+    fn get_opened_file_identities(&self) -> HashMap<Inode, FileIdentity> {
+        let mut identities = HashMap::new();
+        for file in self.opened_files.read().unwrap().values() {
+            if !file.stale && !identities.contains_key(&file.ino) {
+                if let Ok(inode) = self.sb.get_inode(file.ino, false) {
+                    identities.insert(file.ino, FileIdentity::new(inode.deref()));
+                }
+            }
+        }
+        identities
+    }
+
+    // Mark opened files whose inode number refers to another file after updating metadata as
+    // stale, so following requests on the file handles get `ESTALE` instead of new content.
+    fn mark_stale_files(&self, identities: &HashMap<Inode, FileIdentity>) -> usize {
+        let changed = identities
+            .iter()
+            .filter(|(ino, identity)| match self.sb.get_inode(**ino, false) {
+                Ok(inode) => &FileIdentity::new(inode.deref()) != *identity,
+                Err(_) => true,
+            })
+            .map(|(ino, _)| *ino)
+            .collect::<HashSet<_>>();
+        if changed.is_empty() {
+            return 0;
+        }
+
+        let mut count = 0;
+        for file in self.opened_files.write().unwrap().values_mut() {
+            if !file.stale && changed.contains(&file.ino) {
+                file.stale = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // Get the cached file associated with an opened file, or `ESTALE` if the file is stale.
+    fn get_opened_cached_file(&self, handle: Handle) -> Result<Option<BlobCachedFile>> {
+        match self.opened_files.read().unwrap().get(&handle) {
+            Some(file) if file.stale => Err(std::io::Error::from_raw_os_error(libc::ESTALE)),
+            Some(file) => Ok(file.cached_file.clone()),
+            None => Ok(None),
+        }
+    }
+
+    // Check whether a read request is large and chunk aligned enough for the bulk read path.
+    fn is_bulk_read(&self, offset: u64, size: u64) -> bool {
+        let chunk_size = self.metadata().chunk_size as u64;
+        self.bulk_read_threshold != 0
+            && size >= self.bulk_read_threshold as u64
+            && chunk_size != 0
+            && offset % chunk_size == 0
+    }
+
+    // Get cache files covering all data of the blob io vectors, or None if any data isn't ready
+    // in the cache file in uncompressed form.
+    fn get_bulk_read_files(&self, descs: &[BlobIoVec]) -> Option<Vec<BlobCachedFile>> {
+        descs
+            .iter()
+            .map(|desc| self.device.get_cached_file(desc))
+            .collect()
+    }
+
+    // Get size of the window to amplify a read request of an opened file.
+    fn get_readahead_window(&self, handle: Handle, offset: u64, size: u64) -> u32 {
+        match self.readahead_mode {
+            ReadaheadMode::Sequential => self.amplify_io,
+            ReadaheadMode::Random => 0,
+            ReadaheadMode::Adaptive => match self.opened_files.read().unwrap().get(&handle) {
+                Some(file) => {
+                    let mut state = file.readahead.lock().unwrap();
+                    let sequential = state.is_sequential();
+                    let window = state.update(offset, size, self.amplify_io);
+                    if sequential != state.is_sequential() {
+                        self.ios.access_pattern_dec(sequential);
+                        self.ios.access_pattern_inc(!sequential);
+                    }
+                    window
+                }
+                None => self.amplify_io,
+            },
+        }
     }
 
     fn negative_entry(&self) -> Entry {
@@ -422,6 +1114,9 @@ impl Rafs {
     fn get_inode_attr(&self, ino: u64) -> Result<Attr> {
         let inode = self.sb.get_inode(ino, false)?;
         let mut attr = inode.get_attr();
+        if let Some(nlink) = self.fixed_dir_nlink(&*inode, attr.nlink) {
+            attr.nlink = nlink;
+        }
 
         // override uid/gid if there is no explicit inode uid/gid
         if !self.sb.meta.explicit_uidgid() {
@@ -448,8 +1143,30 @@ impl Rafs {
         Ok(attr)
     }
 
+    // Images built from layers may record nlink of directories as 1, which breaks tools relying
+    // on nlink to count subdirectories, so recompute it on demand if enabled.
+    fn fixed_dir_nlink(&self, inode: &dyn RafsInode, nlink: u32) -> Option<u32> {
+        if !self.fix_dir_nlink || nlink != 1 || !inode.is_dir() {
+            return None;
+        }
+        match inode.get_subdir_count() {
+            Ok(count) => Some(2 + count),
+            Err(e) => {
+                warn!(
+                    "failed to count subdirectories of inode {}, {}",
+                    inode.ino(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
     fn get_inode_entry<I: Deref<Target = dyn RafsInode>>(&self, inode: I) -> Entry {
         let mut entry = inode.get_entry();
+        if let Some(nlink) = self.fixed_dir_nlink(&*inode, entry.attr.st_nlink as u32) {
+            entry.attr.st_nlink = nlink as _;
+        }
 
         // override uid/gid if there is no explicit inode uid/gid
         if !self.sb.meta.explicit_uidgid() {
@@ -482,18 +1199,78 @@ impl Rafs {
         let device = self.device.clone();
         let prefetch_all = self.prefetch_all;
         let root_ino = self.root_ino();
+        let id = self.id.clone();
 
         let _ = std::thread::spawn(move || {
-            Self::do_prefetch(root_ino, reader, prefetch_files, prefetch_all, sb, device);
+            let detail = serde_json::json!({
+                "files": prefetch_files.as_ref().map(|f| f.len()),
+                "prefetch_all": prefetch_all,
+            });
+            let failures =
+                Self::do_prefetch(root_ino, reader, prefetch_files, prefetch_all, sb, device);
+            let result = if failures.is_empty() {
+                Ok(())
+            } else {
+                Err(failures.join("; "))
+            };
+            audit::record(EventCategory::Prefetch, Some(&id), detail, &result);
         });
     }
 
+    /// Handle an access advice from applications for data of file `ino` in range
+    /// [offset, offset + size).
+    ///
+    /// `FileAdvice::WillNeed` prefetches the range in background as internal IO with low priority,
+    /// subject to the per-filesystem budget. Advices exceeding the remaining budget are clamped to
+    /// it, and silently dropped once the budget is exhausted.
+    /// `FileAdvice::DontNeed` is accepted but ignored because blob caches don't support evicting
+    /// chunks on demand yet.
+    ///
+    /// Note that FUSE doesn't forward `posix_fadvise()` to the filesystem daemon, so advices are
+    /// received from applications by the nydusd `/api/v1/mount/advise` API instead.
+    pub fn advise(&self, ino: Inode, offset: u64, size: u64, advice: FileAdvice) -> Result<()> {
+        let inode = self.sb.get_inode(ino, false)?;
+        if !inode.is_reg() {
+            return Err(einval!("only regular files can be advised"));
+        }
+        if advice == FileAdvice::DontNeed || offset >= inode.size() {
+            return Ok(());
+        }
+
+        let size = cmp::min(size, inode.size() - offset);
+        let granted = self.advice_budget.lock().unwrap().charge(size);
+        if granted == 0 {
+            debug!("drop advice for inode {}, prefetch budget exhausted", ino);
+            self.ios.record_advice(size, true);
+            return Ok(());
+        } else if granted < size {
+            debug!(
+                "clamp advice for inode {} from {} to {} bytes by prefetch budget",
+                ino, size, granted
+            );
+        }
+        let size = granted;
+
+        let descs = inode.alloc_bio_vecs(&self.device, offset, size as usize, false)?;
+        self.device
+            .prefetch_low_priority(&descs.iter().collect::<Vec<_>>())?;
+        self.ios.record_advice(size, false);
+
+        Ok(())
+    }
+
+    /// Get inode number of the file at `path`.
+    pub fn ino_from_path(&self, path: &Path) -> Result<Inode> {
+        self.sb.ino_from_path(path)
+    }
+
     /// for blobfs
     pub fn fetch_range_synchronous(&self, prefetches: &[BlobPrefetchRequest]) -> Result<()> {
         self.device.fetch_range_synchronous(prefetches)
     }
 
-    fn root_ino(&self) -> u64 {
+    /// Get inode number of the filesystem root.
+    pub fn root_ino(&self) -> u64 {
         self.sb.superblock.root_ino()
     }
 
@@ -502,14 +1279,21 @@ impl Rafs {
         mut reader: RafsIoReader,
         prefetch_files: Option<Vec<PathBuf>>,
         prefetch_all: bool,
-        sb: Arc<RafsSuper>,
+        sb: RafsSuperHandle,
         device: BlobDevice,
-    ) {
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+
         // First do range based prefetch for rafs v6.
         if sb.meta.is_v6() {
             let mut prefetches = Vec::new();
 
-            for blob in sb.superblock.get_blob_infos() {
+            let blobs = sb.superblock.get_blob_infos(true).unwrap_or_else(|e| {
+                warn!("Failed to get blobs for prefetch, {:?}", e);
+                errors.push(format!("get blobs: {:?}", e));
+                Vec::new()
+            });
+            for blob in blobs {
                 let sz = blob.prefetch_size();
                 if sz > 0 {
                     let mut offset = 0;
@@ -527,6 +1311,7 @@ impl Rafs {
             if !prefetches.is_empty() {
                 device.prefetch(&[], &prefetches).unwrap_or_else(|e| {
                     warn!("Prefetch error, {:?}", e);
+                    errors.push(format!("prefetch blob ranges: {:?}", e));
                 });
             }
         }
@@ -556,11 +1341,18 @@ impl Rafs {
         // Then do file based prefetch based on:
         // - prefetch listed passed in by user
         // - or file prefetch list in metadata
-        let inodes = prefetch_files.map(|files| Self::convert_file_list(&files, &sb));
-        let res = sb.prefetch_files(&device, &mut reader, root_ino, inodes, &fetcher);
+        let res =
+            sb.prefetch_files_by_path(&device, &mut reader, root_ino, prefetch_files, &fetcher);
         match res {
-            Ok(true) => ignore_prefetch_all = true,
-            Ok(false) => {}
+            Ok((prefetched_all, failures)) => {
+                for (path, e) in failures {
+                    warn!("Skip prefetching file {:?}, {}", path, e);
+                    errors.push(format!("prefetch file {:?}: {}", path, e));
+                }
+                if prefetched_all {
+                    ignore_prefetch_all = true;
+                }
+            }
             Err(e) => info!("No file to be prefetched {:?}", e),
         }
 
@@ -572,24 +1364,62 @@ impl Rafs {
                 info!("No file to be prefetched {:?}", e);
             }
         }
+
+        errors
     }
+}
 
-    fn convert_file_list(files: &[PathBuf], sb: &Arc<RafsSuper>) -> Vec<Inode> {
-        let mut inodes = Vec::<Inode>::with_capacity(files.len());
+/// Ranges of the virtio-fs DAX window mapped to RAFS files.
+///
+/// Mapped ranges keep the underlying cache file open, and cache files are never truncated or
+/// hole-punched when in use, so mapped data stays valid until the range gets removed.
+#[cfg(feature = "virtio-fs")]
+#[derive(Default)]
+struct DaxMappings {
+    // Window offset -> (inode, length) of mapped ranges.
+    ranges: BTreeMap<u64, (Inode, u64)>,
+    // Number of mapped ranges of each inode.
+    inodes: HashMap<Inode, usize>,
+}
 
-        for f in files {
-            if let Ok(inode) = sb.ino_from_path(f.as_path()) {
-                inodes.push(inode);
-            }
+#[cfg(feature = "virtio-fs")]
+impl DaxMappings {
+    fn insert(&mut self, ino: Inode, moffset: u64, len: u64, max_per_inode: usize) -> Result<()> {
+        // The guest may remap a window range without removing it first.
+        self.remove(moffset, len);
+        let count = self.inodes.get(&ino).copied().unwrap_or(0);
+        if max_per_inode != 0 && count >= max_per_inode {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOSPC));
         }
+        self.ranges.insert(moffset, (ino, len));
+        self.inodes.insert(ino, count + 1);
+        Ok(())
+    }
 
-        inodes
+    fn remove(&mut self, moffset: u64, len: u64) {
+        let end = moffset.saturating_add(len);
+        let overlapped = self
+            .ranges
+            .range(..end)
+            .filter(|(start, (_, l))| start.saturating_add(*l) > moffset)
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        for start in overlapped {
+            if let Some((ino, _)) = self.ranges.remove(&start) {
+                if let Some(count) = self.inodes.get_mut(&ino) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.inodes.remove(&ino);
+                    }
+                }
+            }
+        }
     }
 }
 
 impl BackendFileSystem for Rafs {
     fn mount(&self) -> Result<(Entry, u64)> {
-        let root_inode = self.sb.get_inode(self.root_ino(), self.digest_validate)?;
+        let root_inode = self.sb.get_inode(self.root_ino(), self.digest_validate())?;
         self.ios.new_file_counter(root_inode.ino());
         let e = self.get_inode_entry(root_inode);
         Ok((e, self.sb.get_max_ino()))
@@ -626,7 +1456,7 @@ impl FileSystem for Rafs {
     fn lookup(&self, _ctx: &Context, ino: u64, name: &CStr) -> Result<Entry> {
         let mut rec = FopRecorder::settle(Lookup, ino, &self.ios);
         let target = OsStr::from_bytes(name.to_bytes());
-        let parent = self.sb.get_inode(ino, self.digest_validate)?;
+        let parent = self.sb.get_inode(ino, self.digest_validate())?;
         if !parent.is_dir() {
             return Err(enotdir!());
         }
@@ -640,7 +1470,7 @@ impl FileSystem for Rafs {
             let parent = self.sb.get_extended_inode(parent.ino(), false)?;
             Ok(self
                 .sb
-                .get_inode(parent.parent(), self.digest_validate)
+                .get_inode(parent.parent(), self.digest_validate())
                 .map(|i| self.get_inode_entry(i))
                 .unwrap_or_else(|_| self.negative_entry()))
         } else {
@@ -662,13 +1492,11 @@ impl FileSystem for Rafs {
         }
     }
 
-    fn getattr(
-        &self,
-        _ctx: &Context,
-        ino: u64,
-        _handle: Option<u64>,
-    ) -> Result<(stat64, Duration)> {
+    fn getattr(&self, _ctx: &Context, ino: u64, handle: Option<u64>) -> Result<(stat64, Duration)> {
         let mut recorder = FopRecorder::settle(Getattr, ino, &self.ios);
+        if let Some(handle) = handle {
+            self.get_opened_cached_file(handle)?;
+        }
 
         let attr = self.get_inode_attr(ino).map(|r| {
             recorder.mark_success(0);
@@ -680,7 +1508,7 @@ impl FileSystem for Rafs {
 
     fn readlink(&self, _ctx: &Context, ino: u64) -> Result<Vec<u8>> {
         let mut rec = FopRecorder::settle(Readlink, ino, &self.ios);
-        let inode = self.sb.get_inode(ino, self.digest_validate)?;
+        let inode = self.sb.get_inode(ino, self.digest_validate())?;
 
         Ok(inode
             .get_symlink()
@@ -697,7 +1525,7 @@ impl FileSystem for Rafs {
         &self,
         _ctx: &Context,
         ino: u64,
-        _handle: u64,
+        handle: u64,
         w: &mut dyn ZeroCopyWriter,
         size: u32,
         offset: u64,
@@ -708,6 +1536,7 @@ impl FileSystem for Rafs {
             return Err(einval!("offset + size wraps around."));
         }
 
+        let cached_file = self.get_opened_cached_file(handle)?;
         let inode = self.sb.get_inode(ino, false)?;
         let inode_size = inode.size();
         let mut recorder = FopRecorder::settle(Read, ino, &self.ios);
@@ -718,24 +1547,61 @@ impl FileSystem for Rafs {
         }
 
         let real_size = cmp::min(size as u64, inode_size - offset);
+        if let Some(file) = cached_file {
+            // All data of the file is ready in the cache file, bypass the blob cache.
+            let result = file.read_to(w, offset, real_size as usize)?;
+            recorder.mark_success(result);
+            return Ok(result);
+        }
+        if let Some(data) = inode.get_inline_data()? {
+            // Small files may be stored inline in the metadata blob, no need to access data blobs.
+            let start = cmp::min(offset as usize, data.len());
+            let end = cmp::min(start + real_size as usize, data.len());
+            w.write_all(&data[start..end])?;
+            recorder.mark_success(end - start);
+            return Ok(end - start);
+        }
         let mut result = 0;
+        let trace = RequestTrace::start("read", ino);
+        let begin = trace.as_ref().map(|_| Instant::now());
         let mut descs = inode.alloc_bio_vecs(&self.device, offset, real_size as usize, true)?;
         assert!(!descs.is_empty() && !descs[0].is_empty());
+        if let (Some(trace), Some(begin)) = (trace.as_ref(), begin) {
+            trace.record("alloc_bio_vecs", begin);
+        }
+
+        // Fill the reply with data from the cache file without going through the blob cache.
+        if self.is_bulk_read(offset, real_size) {
+            if let Some(files) = self.get_bulk_read_files(&descs) {
+                let start = self.ios.latency_start();
+                for file in files.iter() {
+                    let r = file.read_to(w, 0, file.size() as usize)?;
+                    result += r;
+                    recorder.mark_success(r);
+                    if r as u64 != file.size() {
+                        break;
+                    }
+                }
+                self.ios.latency_end(&start, Read);
+                return Ok(result);
+            }
+        }
 
         // Try to amplify user io for Rafs v5, to improve performance.
-        if self.sb.meta.is_v5() && size < self.amplify_io {
+        let amplify_io = self.get_readahead_window(handle, offset, real_size);
+        if self.sb.meta.is_v5() && size < amplify_io {
             let all_chunks_ready = self.device.all_chunks_ready(&descs);
             if !all_chunks_ready {
                 let chunk_mask = self.metadata().chunk_size as u64 - 1;
                 let next_chunk_base = (offset + (size as u64) + chunk_mask) & !chunk_mask;
                 let window_base = cmp::min(next_chunk_base, inode_size);
                 let actual_size = window_base - (offset & !chunk_mask);
-                if actual_size < self.amplify_io as u64 {
-                    let window_size = self.amplify_io as u64 - actual_size;
+                if actual_size < amplify_io as u64 {
+                    let window_size = amplify_io as u64 - actual_size;
                     let orig_cnt = descs.iter().fold(0, |s, d| s + d.len());
                     self.sb.amplify_io(
                         &self.device,
-                        self.amplify_io,
+                        amplify_io,
                         &mut descs,
                         &inode,
                         window_base,
@@ -755,6 +1621,7 @@ impl FileSystem for Rafs {
         for desc in descs.iter_mut() {
             assert!(!desc.is_empty());
             assert_ne!(desc.size(), 0);
+            desc.set_trace(trace.clone());
 
             // Avoid copying `desc`
             let r = self.device.read_to(w, desc)?;
@@ -772,12 +1639,30 @@ impl FileSystem for Rafs {
     fn open(
         &self,
         _ctx: &Context,
-        _inode: Self::Inode,
+        inode: Self::Inode,
         _flags: u32,
         _fuse_flags: u32,
     ) -> Result<(Option<Self::Handle>, OpenOptions)> {
+        // Fall back to the normal read path if any data of the file isn't ready in cache file.
+        let cached_file = if self.cached_file_passthrough {
+            self.get_cached_file(inode)
+        } else {
+            None
+        };
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        self.opened_files.write().unwrap().insert(
+            handle,
+            OpenedFile {
+                ino: inode,
+                stale: false,
+                cached_file,
+                readahead: Mutex::new(ReadaheadState::new()),
+            },
+        );
+        self.ios.access_pattern_inc(true);
+
         // Keep cache since we are readonly
-        Ok((None, OpenOptions::KEEP_CACHE))
+        Ok((Some(handle), OpenOptions::KEEP_CACHE))
     }
 
     fn release(
@@ -785,11 +1670,15 @@ impl FileSystem for Rafs {
         _ctx: &Context,
         _inode: u64,
         _flags: u32,
-        _handle: u64,
+        handle: u64,
         _flush: bool,
         _flock_release: bool,
         _lock_owner: Option<u64>,
     ) -> Result<()> {
+        if let Some(file) = self.opened_files.write().unwrap().remove(&handle) {
+            self.ios
+                .access_pattern_dec(file.readahead.lock().unwrap().is_sequential());
+        }
         Ok(())
     }
 
@@ -859,6 +1748,14 @@ impl FileSystem for Rafs {
         }
 
         let inode = self.sb.get_inode(inode, false)?;
+        if inode.xattr_count() == 0 {
+            rec.mark_success(0);
+            return match size {
+                0 => Ok(ListxattrReply::Count(0)),
+                _ => Ok(ListxattrReply::Names(Vec::new())),
+            };
+        }
+
         let mut count = 0;
         let mut buf = Vec::new();
         for mut name in inode.get_xattrs()? {
@@ -889,7 +1786,10 @@ impl FileSystem for Rafs {
     ) -> Result<()> {
         let mut rec = FopRecorder::settle(Readdir, inode, &self.ios);
 
-        self.do_readdir(inode, size, offset, add_entry).map(|r| {
+        self.do_readdir(inode, size, offset, &mut |dir_entry, _| {
+            add_entry(dir_entry)
+        })
+        .map(|r| {
             rec.mark_success(0);
             r
         })
@@ -906,8 +1806,14 @@ impl FileSystem for Rafs {
     ) -> Result<()> {
         let mut rec = FopRecorder::settle(Readdirplus, ino, &self.ios);
 
-        self.do_readdir(ino, size, offset, &mut |dir_entry| {
-            let inode = self.sb.get_inode(dir_entry.ino, self.digest_validate)?;
+        // Child inodes are built by `walk_children_inodes()` in the same pass, so only "." and
+        // ".." need an extra lookup. Inodes are immutable and `forget()` is a no-op, so there's
+        // no lookup count to maintain for entries cached by the kernel.
+        self.do_readdir(ino, size, offset, &mut |dir_entry, inode| {
+            let inode = match inode {
+                Some(v) => v,
+                None => self.sb.get_inode(dir_entry.ino, self.digest_validate())?,
+            };
             add_entry(dir_entry, self.get_inode_entry(inode))
         })
         .map(|r| {
@@ -930,6 +1836,101 @@ impl FileSystem for Rafs {
         Ok(())
     }
 
+    #[cfg(feature = "virtio-fs")]
+    fn setupmapping(
+        &self,
+        _ctx: &Context,
+        inode: Inode,
+        _handle: Handle,
+        foffset: u64,
+        len: u64,
+        flags: u64,
+        moffset: u64,
+        vu_req: &mut dyn FsCacheReqHandler,
+    ) -> Result<()> {
+        debug!(
+            "rafs: setupmapping ino {} foffset {} len {} flags {} moffset {}",
+            inode, foffset, len, flags, moffset
+        );
+
+        if !self.dax.enable {
+            return Err(enosys!("virtio-fs DAX is not enabled"));
+        } else if flags & virtio_fs::SetupmappingFlags::WRITE.bits() != 0 {
+            return Err(eacces!("rafs file cannot be written through DAX"));
+        } else if self.dax.window_size != 0
+            && moffset
+                .checked_add(len)
+                .map(|end| end > self.dax.window_size)
+                != Some(false)
+        {
+            return Err(einval!("mapping exceeds the DAX window"));
+        }
+
+        let rafs_inode = self.sb.get_inode(inode, false)?;
+        let size = rafs_inode.size();
+        if !rafs_inode.is_reg() || rafs_inode.get_chunk_count() == 0 {
+            return Err(einval!("inode has no chunk data to map"));
+        } else if foffset >= size || len > u32::MAX as u64 {
+            return Err(einval!("invalid mapping range"));
+        }
+
+        // Translate the file range to ranges of cache files, fetching missing chunks on demand.
+        let real_size = cmp::min(len, size - foffset);
+        let descs = rafs_inode.alloc_bio_vecs(&self.device, foffset, real_size as usize, true)?;
+        let mut files = Vec::new();
+        for desc in descs.iter() {
+            files.append(&mut self.device.fetch_cached_chunks(desc)?);
+        }
+
+        self.dax_mappings.lock().unwrap().insert(
+            inode,
+            moffset,
+            len,
+            self.dax.max_mappings_per_inode,
+        )?;
+        // Safe because sysconf() has no side effects.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let mut offset = 0;
+        for file in files {
+            if file.base_offset() % page_size != 0 || offset % page_size != 0 {
+                self.dax_mappings.lock().unwrap().remove(moffset, len);
+                return Err(einval!("cached chunk data isn't page aligned"));
+            }
+            // Only the last chunk may be partial, round it up to page boundary.
+            let map_len = cmp::min(round_up(file.size(), page_size), len - offset);
+            if let Err(e) = vu_req.map(
+                file.base_offset(),
+                moffset + offset,
+                map_len,
+                flags,
+                file.as_raw_fd(),
+            ) {
+                self.dax_mappings.lock().unwrap().remove(moffset, len);
+                return Err(e);
+            }
+            offset += file.size();
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    fn removemapping(
+        &self,
+        _ctx: &Context,
+        _inode: Inode,
+        requests: Vec<virtio_fs::RemovemappingOne>,
+        vu_req: &mut dyn FsCacheReqHandler,
+    ) -> Result<()> {
+        let mut mappings = self.dax_mappings.lock().unwrap();
+        for req in requests.iter() {
+            mappings.remove(req.moffset, req.len);
+        }
+        drop(mappings);
+
+        vu_req.unmap(requests)
+    }
+
     fn access(&self, ctx: &Context, ino: u64, mask: u32) -> Result<()> {
         let mut rec = FopRecorder::settle(Access, ino, &self.ios);
         let st = self.get_inode_attr(ino)?;
@@ -977,9 +1978,21 @@ impl FileSystem for Rafs {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::metadata::RAFS_DEFAULT_CHUNK_SIZE;
+    use crate::metadata::layout::v6::{
+        RafsV6BlobTable, RafsV6InodeCompact, RafsV6OndiskInode, RafsV6SuperBlock,
+        RafsV6SuperBlockExt, EROFS_BLOCK_SIZE,
+    };
+    use crate::metadata::layout::RAFS_SUPER_VERSION_V6;
+    use crate::metadata::{RafsStore, RafsSuperFlags, RAFS_DEFAULT_CHUNK_SIZE};
+    use crate::mock::{MockChunkInfo, MockInode, MockSuperBlock};
     #[cfg(feature = "backend-oss")]
     use crate::RafsIoRead;
+    use nydus_storage::device::BlobFeatures;
+    use nydus_storage::meta::BlobMetaHeaderOndisk;
+    use nydus_utils::{compress, digest};
+    use std::io::{Seek, SeekFrom, Write};
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
 
     #[cfg(feature = "backend-oss")]
     pub fn new_rafs_backend() -> Box<Rafs> {
@@ -1114,6 +2127,692 @@ pub(crate) mod tests {
         }
     }
 
+    fn new_mock_rafs(sb: Arc<MockSuperBlock>) -> Rafs {
+        Rafs {
+            id: "test".to_string(),
+            device: BlobDevice::default(),
+            ios: metrics::FsIoStats::new("test"),
+            sb: Arc::new(RafsSuper {
+                superblock: sb,
+                ..Default::default()
+            }),
+            initialized: true,
+            digest_validate: AtomicBool::new(false),
+            fs_prefetch: false,
+            prefetch_all: false,
+            xattr_enabled: false,
+            amplify_io: 0,
+            readahead_mode: ReadaheadMode::Adaptive,
+            cached_file_passthrough: false,
+            bulk_read_threshold: 0,
+            fix_dir_nlink: false,
+            opened_files: RwLock::new(HashMap::new()),
+            invalidations: Mutex::new(Vec::new()),
+            next_handle: AtomicU64::new(1),
+            advice_budget: Mutex::new(AdviceBudget::new(RAFS_DEFAULT_ADVICE_BUDGET)),
+            #[cfg(feature = "virtio-fs")]
+            dax: DaxConfig::default(),
+            #[cfg(feature = "virtio-fs")]
+            dax_mappings: Mutex::new(DaxMappings::default()),
+            i_uid: 0,
+            i_gid: 0,
+            i_time: 0,
+        }
+    }
+
+    fn new_v6_blob_table(count: usize) -> RafsV6BlobTable {
+        let chunk_size = EROFS_BLOCK_SIZE as u32;
+        let mut header = BlobMetaHeaderOndisk::default();
+        header.set_4k_aligned(true);
+        let mut blob_table = RafsV6BlobTable::new();
+        for idx in 0..count {
+            blob_table.add(
+                format!("{}", idx).repeat(64),
+                0,
+                0,
+                chunk_size,
+                0,
+                chunk_size as u64,
+                chunk_size as u64,
+                BlobFeatures::empty(),
+                RafsSuperFlags::empty(),
+                header,
+            );
+        }
+        blob_table
+    }
+
+    // Layout: super blocks, blob table, and the root directory inode at block 2.
+    fn new_v6_bootstrap(blob_table: &RafsV6BlobTable) -> TempFile {
+        let file = TempFile::new().unwrap();
+        let mut w = file.as_file().try_clone().unwrap();
+        let mut sb = RafsV6SuperBlock::new();
+        sb.set_inos(1);
+        sb.set_blocks(0);
+        sb.set_meta_addr(2 * EROFS_BLOCK_SIZE);
+        sb.store(&mut w).unwrap();
+        let mut ext_sb = RafsV6SuperBlockExt::new();
+        ext_sb.set_compressor(compress::Algorithm::None);
+        ext_sb.set_digester(digest::Algorithm::Blake3);
+        ext_sb.set_chunk_size(EROFS_BLOCK_SIZE as u32);
+        ext_sb.set_blob_table_offset(EROFS_BLOCK_SIZE);
+        ext_sb.set_blob_table_size(blob_table.size() as u32);
+        ext_sb.store(&mut w).unwrap();
+        blob_table.store(&mut w).unwrap();
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        w.seek(SeekFrom::Start(2 * EROFS_BLOCK_SIZE)).unwrap();
+        w.write_all(inode.as_ref()).unwrap();
+        w.set_len(3 * EROFS_BLOCK_SIZE).unwrap();
+        file
+    }
+
+    fn new_localfs_config(blob_dir: &TempDir, encryption_keys: &str) -> RafsConfig {
+        let config = format!(
+            r#"{{
+                "device": {{
+                    "backend": {{
+                        "type": "localfs",
+                        "config": {{ "dir": "{}" }}
+                    }},
+                    "cache": {{
+                        "type": "blobcache",
+                        "config": {{ "work_dir": "{}" }}
+                    }}
+                }},
+                "mode": "direct",
+                "encryption_keys": {{ {} }}
+            }}"#,
+            blob_dir.as_path().display(),
+            blob_dir.as_path().display(),
+            encryption_keys
+        );
+        RafsConfig::from_str(&config).unwrap()
+    }
+
+    #[test]
+    fn test_lazy_blob_infos() {
+        let file = new_v6_bootstrap(&new_v6_blob_table(3));
+        let blob_dir = TempDir::new().unwrap();
+        let conf = new_localfs_config(&blob_dir, "");
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        let rafs = Rafs::new(conf, "lazy-blob-infos", &mut reader).unwrap();
+
+        // Mounting doesn't materialize any blob information object.
+        assert_eq!(rafs.blob_count(), 3);
+        assert!(rafs.sb.superblock.get_blob_infos(false).unwrap().is_empty());
+        let infos = rafs.get_blob_access_info();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[1].blob_id, "1".repeat(64));
+        assert!(rafs.sb.superblock.get_blob_infos(false).unwrap().is_empty());
+
+        // Touching a blob only materializes its own entry.
+        let _ = rafs.device.create_io_chunk(1, 0);
+        let ready = rafs.sb.superblock.get_blob_infos(false).unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].blob_index(), 1);
+        assert_eq!(rafs.sb.superblock.get_blob_infos(true).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_missing_encryption_key() {
+        let mut blob_table = new_v6_blob_table(2);
+        let wrapped_key = [0u8; crypt::WRAPPED_KEY_LEN];
+        blob_table
+            .set_cipher_info(
+                1,
+                crypt::Algorithm::Aes256Gcm,
+                crypt::key_ref("key1"),
+                &wrapped_key,
+            )
+            .unwrap();
+        let file = new_v6_bootstrap(&blob_table);
+        let blob_dir = TempDir::new().unwrap();
+        let master = "11".repeat(32);
+
+        for keys in [
+            String::new(),
+            format!(r#""key2": "{}""#, master),
+            r#""key1": "invalid""#.to_string(),
+        ] {
+            let conf = new_localfs_config(&blob_dir, &keys);
+            let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+            match Rafs::new(conf, "missing-encryption-key", &mut reader) {
+                Err(RafsError::Configure(msg)) => assert!(msg.contains(&"1".repeat(64))),
+                Err(e) => panic!("unexpected error {:?}", e),
+                Ok(_) => panic!("mounted encrypted blob without encryption key"),
+            }
+        }
+
+        // Checking keys doesn't materialize any blob information object.
+        let conf = new_localfs_config(&blob_dir, &format!(r#""key1": "{}""#, master));
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        let rafs = Rafs::new(conf, "missing-encryption-key", &mut reader).unwrap();
+        assert!(rafs.sb.superblock.get_blob_infos(false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_tree() {
+        let state = |ino: Inode, mode: u32, size: u64| EntryState {
+            ino,
+            mode,
+            size,
+            mtime: 0,
+            mtimensec: 0,
+        };
+        let key = |parent: Inode, name: &str| (parent, OsString::from(name));
+        let entry = |parent: Inode, name: &str| RafsInvalidation::Entry {
+            parent,
+            name: OsString::from(name),
+        };
+
+        let mut old = TreeSnapshot::new();
+        old.insert(key(1, "dir"), state(2, libc::S_IFDIR | 0o755, 0));
+        old.insert(key(1, "same"), state(3, libc::S_IFREG | 0o644, 10));
+        old.insert(key(2, "changed"), state(4, libc::S_IFREG | 0o644, 10));
+        old.insert(key(2, "removed"), state(5, libc::S_IFREG | 0o644, 10));
+        let mut new = TreeSnapshot::new();
+        new.insert(key(1, "dir"), state(2, libc::S_IFDIR | 0o755, 4096));
+        new.insert(key(1, "same"), state(3, libc::S_IFREG | 0o644, 10));
+        new.insert(key(2, "changed"), state(4, libc::S_IFREG | 0o644, 20));
+        new.insert(key(2, "added"), state(6, libc::S_IFREG | 0o644, 10));
+
+        assert!(Rafs::diff_tree(&old, &old, usize::MAX).is_empty());
+        let invals = Rafs::diff_tree(&old, &new, usize::MAX);
+        assert_eq!(
+            invals,
+            vec![
+                RafsInvalidation::Inode(1),
+                RafsInvalidation::Inode(2),
+                entry(1, "dir"),
+                entry(2, "changed"),
+                RafsInvalidation::Inode(4),
+                entry(2, "removed"),
+                RafsInvalidation::Inode(5),
+                entry(2, "added"),
+            ]
+        );
+        assert_eq!(Rafs::diff_tree(&old, &new, 3), invals[..3].to_vec());
+        assert!(Rafs::diff_tree(&old, &new, 0).is_empty());
+    }
+
+    #[test]
+    fn test_stale_file_handle() {
+        let mut file = MockInode::mock_file(2, ROOT_ID, "file");
+        file.set_size(10);
+        let mut sb = MockSuperBlock::new();
+        sb.inodes.insert(2, Arc::new(file));
+        sb.inodes
+            .insert(3, Arc::new(MockInode::mock_file(3, ROOT_ID, "other")));
+        let mut rafs = new_mock_rafs(Arc::new(sb));
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let (handle, _) = rafs.open(ctx, 2, 0, 0).unwrap();
+        let handle = handle.unwrap();
+        let (other, _) = rafs.open(ctx, 3, 0, 0).unwrap();
+        let other = other.unwrap();
+        assert!(rafs.getattr(ctx, 2, Some(handle)).is_ok());
+
+        // Emulate updating the bootstrap, which reuses inode number 2 for a file of another size.
+        let identities = rafs.get_opened_file_identities();
+        let mut file = MockInode::mock_file(2, ROOT_ID, "file");
+        file.set_size(20);
+        let mut sb = MockSuperBlock::new();
+        sb.inodes.insert(2, Arc::new(file));
+        sb.inodes
+            .insert(3, Arc::new(MockInode::mock_file(3, ROOT_ID, "other")));
+        rafs.sb = Arc::new(RafsSuper {
+            superblock: Arc::new(sb),
+            ..Default::default()
+        });
+        assert_eq!(rafs.mark_stale_files(&identities), 1);
+
+        let err = rafs.getattr(ctx, 2, Some(handle)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ESTALE));
+        assert!(rafs.getattr(ctx, 3, Some(other)).is_ok());
+        // Newly opened handles access the new file.
+        let (handle, _) = rafs.open(ctx, 2, 0, 0).unwrap();
+        let (attr, _) = rafs.getattr(ctx, 2, handle).unwrap();
+        assert_eq!(attr.st_size, 20);
+    }
+
+    #[test]
+    fn test_readahead_state() {
+        let mut state = ReadaheadState::new();
+        assert!(state.is_sequential());
+        assert_eq!(state.update(0, 4096, 0x20000), 0x20000);
+        assert_eq!(state.update(4096, 4096, 0x20000), 0x20000);
+
+        // Random reads shrink the window quickly.
+        assert_eq!(state.update(0x100000, 4096, 0x20000), 0x10000);
+        assert!(state.is_sequential());
+        assert_eq!(state.update(0x10, 4096, 0x20000), 0x8000);
+        assert!(!state.is_sequential());
+        assert_eq!(state.update(0x80000, 4096, 0x20000), 0x4000);
+        assert_eq!(state.update(0x40000, 4096, 0x20000), 0);
+        assert_eq!(state.update(0x60000, 4096, 0x20000), 0);
+
+        // Sequential reads grow the window back.
+        assert_eq!(state.update(0x61000, 4096, 0x20000), 0x4000);
+        for idx in 0..3 {
+            state.update(0x62000 + idx * 4096, 4096, 0x20000);
+        }
+        assert!(state.is_sequential());
+    }
+
+    #[test]
+    fn test_bulk_read() {
+        let mut rafs = new_mock_rafs(Arc::new(MockSuperBlock::new()));
+        rafs.sb = Arc::new(RafsSuper {
+            meta: RafsSuperMeta {
+                chunk_size: 0x100000,
+                ..Default::default()
+            },
+            superblock: Arc::new(MockSuperBlock::new()),
+            ..Default::default()
+        });
+        assert!(!rafs.is_bulk_read(0, 0x400000));
+
+        rafs.bulk_read_threshold = 0x200000;
+        assert!(rafs.is_bulk_read(0, 0x400000));
+        assert!(rafs.is_bulk_read(0x100000, 0x200000));
+        assert!(!rafs.is_bulk_read(0x1000, 0x400000));
+        assert!(!rafs.is_bulk_read(0, 0x1fffff));
+        assert_eq!(rafs.get_bulk_read_files(&[]).map(|v| v.len()), Some(0));
+    }
+
+    #[test]
+    fn test_readahead_window() {
+        let mut sb = MockSuperBlock::new();
+        sb.inodes
+            .insert(2, Arc::new(MockInode::mock_file(2, ROOT_ID, "file")));
+        let mut rafs = new_mock_rafs(Arc::new(sb));
+        rafs.amplify_io = 0x20000;
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let (handle, _) = rafs.open(ctx, 2, 0, 0).unwrap();
+        let handle = handle.unwrap();
+        let (other, _) = rafs.open(ctx, 2, 0, 0).unwrap();
+        let other = other.unwrap();
+
+        // Classification state is kept per opened file.
+        assert_eq!(rafs.get_readahead_window(handle, 0x80000, 4096), 0x10000);
+        assert_eq!(rafs.get_readahead_window(handle, 0x10, 4096), 0x8000);
+        assert_eq!(rafs.get_readahead_window(other, 0, 4096), 0x20000);
+        // Unknown handles always get the maximum window.
+        assert_eq!(rafs.get_readahead_window(100, 0x80000, 4096), 0x20000);
+
+        rafs.readahead_mode = ReadaheadMode::Sequential;
+        assert_eq!(rafs.get_readahead_window(handle, 0x10, 4096), 0x20000);
+        rafs.readahead_mode = ReadaheadMode::Random;
+        assert_eq!(rafs.get_readahead_window(other, 4096, 4096), 0);
+
+        rafs.release(ctx, 2, 0, handle, false, false, None).unwrap();
+        rafs.release(ctx, 2, 0, other, false, false, None).unwrap();
+        assert!(rafs.opened_files.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_advise() {
+        let chunks = (0..3)
+            .map(|idx| {
+                Arc::new(MockChunkInfo::mock(
+                    idx * 200,
+                    idx * 100,
+                    100,
+                    idx * 200,
+                    200,
+                ))
+            })
+            .collect();
+        let mut sb = MockSuperBlock::new();
+        sb.inodes
+            .insert(2, Arc::new(MockInode::mock(2, 600, chunks)));
+        sb.inodes
+            .insert(3, Arc::new(MockInode::mock_dir(3, ROOT_ID, "dir", vec![])));
+        let rafs = new_mock_rafs(Arc::new(sb));
+        let advised = |rafs: &Rafs| {
+            let stats = serde_json::to_value(rafs.ios.as_ref()).unwrap();
+            (
+                stats["advised_prefetch_bytes"].as_u64().unwrap(),
+                stats["throttled_advices"].as_u64().unwrap(),
+            )
+        };
+
+        rafs.advise(2, 0, 0x1000, FileAdvice::WillNeed).unwrap();
+        assert_eq!(advised(&rafs), (600, 0));
+        rafs.advise(2, 400, 100, FileAdvice::WillNeed).unwrap();
+        assert_eq!(advised(&rafs), (700, 0));
+        // Out of range and DONTNEED advices are ignored.
+        rafs.advise(2, 600, 100, FileAdvice::WillNeed).unwrap();
+        rafs.advise(2, 0, 600, FileAdvice::DontNeed).unwrap();
+        assert_eq!(advised(&rafs), (700, 0));
+        assert!(rafs.advise(3, 0, 600, FileAdvice::WillNeed).is_err());
+
+        // Advices exceeding the budget are clamped, and dropped once the budget is exhausted.
+        *rafs.advice_budget.lock().unwrap() = AdviceBudget::new(1000);
+        rafs.advise(2, 0, 600, FileAdvice::WillNeed).unwrap();
+        rafs.advise(2, 0, 600, FileAdvice::WillNeed).unwrap();
+        assert_eq!(advised(&rafs), (1700, 0));
+        rafs.advise(2, 0, 600, FileAdvice::WillNeed).unwrap();
+        assert_eq!(advised(&rafs), (1700, 1));
+
+        // Advices larger than the whole budget are clamped instead of dropped.
+        *rafs.advice_budget.lock().unwrap() = AdviceBudget::new(100);
+        rafs.advise(2, 0, 600, FileAdvice::WillNeed).unwrap();
+        assert_eq!(advised(&rafs), (1800, 1));
+    }
+
+    #[test]
+    fn test_fix_dir_nlink() {
+        let sub1 = Arc::new(MockInode::mock_dir(3, 2, "sub1", vec![]));
+        let sub2 = Arc::new(MockInode::mock_dir(4, 2, "sub2", vec![]));
+        let file = Arc::new(MockInode::mock_file(5, 2, "file"));
+        let mut dir = MockInode::mock_dir(2, ROOT_ID, "dir", vec![sub1, sub2, file]);
+        dir.set_nlink(1);
+        let dir = Arc::new(dir);
+        let mut root = MockInode::mock_dir(ROOT_ID, ROOT_ID, "", vec![dir.clone()]);
+        root.set_nlink(3);
+        let mut sb = MockSuperBlock::new();
+        sb.inodes.insert(ROOT_ID, Arc::new(root));
+        sb.inodes.insert(dir.ino(), dir);
+        let sb = Arc::new(sb);
+
+        let mut rafs = new_mock_rafs(sb);
+        assert_eq!(rafs.get_inode_attr(2).unwrap().nlink, 1);
+        rafs.fix_dir_nlink = true;
+        assert_eq!(rafs.get_inode_attr(2).unwrap().nlink, 4);
+        let inode = rafs.sb.get_inode(2, false).unwrap();
+        assert_eq!(rafs.get_inode_entry(inode).attr.st_nlink, 4);
+        // Stored values other than 1 are trusted.
+        assert_eq!(rafs.get_inode_attr(ROOT_ID).unwrap().nlink, 3);
+    }
+
+    #[test]
+    fn test_readdirplus_single_metadata_pass() {
+        const ENTRIES: u64 = 20000;
+        let children = (0..ENTRIES)
+            .map(|idx| {
+                Arc::new(MockInode::mock_file(
+                    idx + 2,
+                    ROOT_ID,
+                    &format!("{:05}", idx),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut sb = MockSuperBlock::new();
+        for child in children.iter() {
+            sb.inodes.insert(child.ino(), child.clone());
+        }
+        let root = Arc::new(MockInode::mock_dir(ROOT_ID, ROOT_ID, "", children));
+        sb.inodes.insert(ROOT_ID, root);
+        let sb = Arc::new(sb);
+        let rafs = new_mock_rafs(sb.clone());
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+
+        // Emulate `ls -la`, which fetches entries in batches and resumes from the last cookie.
+        let mut offset = 0;
+        let mut calls = 0;
+        let mut names = Vec::new();
+        loop {
+            let mut batch = 0;
+            rafs.readdirplus(ctx, ROOT_ID, 0, 4096, offset, &mut |dir_entry, entry| {
+                if batch == 128 {
+                    return Ok(0);
+                }
+                assert_eq!(dir_entry.ino, entry.inode);
+                batch += 1;
+                offset = dir_entry.offset;
+                names.push(OsStr::from_bytes(dir_entry.name).to_os_string());
+                Ok(1)
+            })
+            .unwrap();
+            calls += 1;
+            if batch == 0 {
+                break;
+            }
+        }
+
+        assert_eq!(names.len() as u64, ENTRIES + 2);
+        assert_eq!(names[0], DOT);
+        assert_eq!(names[1], DOTDOT);
+        assert_eq!(names[2], "00000");
+        assert_eq!(names[names.len() - 1], "19999");
+        // One lookup of the directory per call, plus lookups of "." and "..".
+        assert_eq!(sb.lookups.load(Ordering::Relaxed), calls + 2);
+    }
+
+    #[test]
+    fn test_read_to_buf() {
+        let mut sb = MockSuperBlock::new();
+        let file = Arc::new(MockInode::mock_file(2, ROOT_ID, "empty"));
+        sb.inodes.insert(2, file.clone());
+        sb.inodes.insert(
+            ROOT_ID,
+            Arc::new(MockInode::mock_dir(ROOT_ID, ROOT_ID, "", vec![file])),
+        );
+        let rafs = new_mock_rafs(Arc::new(sb));
+
+        let mut buf = vec![0u8; 4096];
+        assert_eq!(rafs.read_to_buf(2, 0, &mut buf).unwrap(), 0);
+        assert_eq!(rafs.read_to_buf(2, 8192, &mut buf).unwrap(), 0);
+        assert_eq!(rafs.read_to_buf(2, 0, &mut []).unwrap(), 0);
+        assert!(rafs.read_to_buf(3, 0, &mut buf).is_err());
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[derive(Default)]
+    struct DummyCacheReq {
+        maps: Vec<(u64, u64, u64)>,
+        unmaps: usize,
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    impl FsCacheReqHandler for DummyCacheReq {
+        fn map(
+            &mut self,
+            foffset: u64,
+            moffset: u64,
+            len: u64,
+            _flags: u64,
+            _fd: std::os::unix::io::RawFd,
+        ) -> Result<()> {
+            self.maps.push((foffset, moffset, len));
+            Ok(())
+        }
+
+        fn unmap(&mut self, requests: Vec<virtio_fs::RemovemappingOne>) -> Result<()> {
+            self.unmaps += requests.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_set_digest_validate() {
+        let mut rafs = new_mock_rafs(Arc::new(MockSuperBlock::new()));
+        assert!(!rafs.digest_validate());
+        rafs.set_digest_validate(true).unwrap();
+        assert!(rafs.digest_validate());
+        rafs.set_digest_validate(false).unwrap();
+        assert!(!rafs.digest_validate());
+
+        Arc::get_mut(&mut rafs.sb).unwrap().meta.version = RAFS_SUPER_VERSION_V6;
+        assert!(rafs.set_digest_validate(true).is_err());
+        assert!(!rafs.digest_validate());
+        rafs.set_digest_validate(false).unwrap();
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_dax_setupmapping() {
+        let mut sb = MockSuperBlock::new();
+        sb.inodes
+            .insert(2, Arc::new(MockInode::mock_file(2, ROOT_ID, "empty")));
+        let mut rafs = new_mock_rafs(Arc::new(sb));
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let mut req = DummyCacheReq::default();
+        let read = virtio_fs::SetupmappingFlags::READ.bits();
+        let write = virtio_fs::SetupmappingFlags::WRITE.bits();
+
+        let err = rafs
+            .setupmapping(ctx, 2, 0, 0, 0x200000, read, 0, &mut req)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSYS));
+
+        rafs.dax = DaxConfig {
+            enable: true,
+            window_size: 0x400000,
+            max_mappings_per_inode: 0,
+        };
+        let err = rafs
+            .setupmapping(ctx, 2, 0, 0, 0x200000, read | write, 0, &mut req)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+        let err = rafs
+            .setupmapping(ctx, 2, 0, 0, 0x200000, read, 0x300000, &mut req)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        // Files without data chunks can't be mapped.
+        let err = rafs
+            .setupmapping(ctx, 2, 0, 0, 0x200000, read, 0, &mut req)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        assert!(req.maps.is_empty());
+
+        let requests = vec![virtio_fs::RemovemappingOne {
+            moffset: 0,
+            len: 0x200000,
+        }];
+        rafs.removemapping(ctx, 2, requests, &mut req).unwrap();
+        assert_eq!(req.unmaps, 1);
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_dax_setupmapping_cached_file() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let texture = PathBuf::from(root_dir).join("../tests/texture");
+        let work_dir = TempDir::new().unwrap();
+        let config = format!(
+            r#"{{
+                "device": {{
+                    "backend": {{
+                        "type": "localfs",
+                        "config": {{ "dir": "{}" }}
+                    }},
+                    "cache": {{
+                        "type": "blobcache",
+                        "config": {{ "work_dir": "{}" }}
+                    }}
+                }},
+                "mode": "direct"
+            }}"#,
+            texture.join("blobs").display(),
+            work_dir.as_path().display()
+        );
+        let conf = RafsConfig::from_str(&config).unwrap();
+        let mut reader =
+            <dyn crate::RafsIoRead>::from_file(texture.join("bootstrap/rafs-v6.boot")).unwrap();
+        let mut rafs = Rafs::new(conf, "dax-setupmapping", &mut reader).unwrap();
+        rafs.dax = DaxConfig {
+            enable: true,
+            window_size: 0x400000,
+            max_mappings_per_inode: 2,
+        };
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let read = virtio_fs::SetupmappingFlags::READ.bits();
+        let file_a = rafs.ino_from_path(Path::new("/file-a")).unwrap();
+        let file_b = rafs.ino_from_path(Path::new("/dir/file-b")).unwrap();
+        let mut req = DummyCacheReq::default();
+
+        // Chunks are fetched into the uncompressed cache file on demand, and partial chunks are
+        // mapped up to page boundary.
+        rafs.setupmapping(ctx, file_a, 0, 0, 0x200000, read, 0, &mut req)
+            .unwrap();
+        rafs.setupmapping(ctx, file_b, 0, 0, 0x200000, read, 0x200000, &mut req)
+            .unwrap();
+        assert_eq!(req.maps, vec![(0, 0, 0x2000), (0x2000, 0x200000, 0x1000)]);
+        for (foffset, moffset, len) in req.maps.iter() {
+            assert_eq!(foffset % 0x1000, 0);
+            assert_eq!(moffset % 0x1000, 0);
+            assert_eq!(len % 0x1000, 0);
+        }
+
+        // Mapped cache file ranges hold the file data.
+        let blob_id = "defa614cc4d1343cfd1715e11fe95bfd151d2b06a08c4ff1587d62284d7b3671";
+        let cache_file = work_dir.as_path().join(format!("{}.blob.data", blob_id));
+        let data = std::fs::read(cache_file).unwrap();
+        assert!(data[..5000].iter().all(|v| *v == b'a'));
+        assert!(data[0x2000..0x2000 + 3000].iter().all(|v| *v == b'b'));
+
+        // Number of mappings per inode is limited, and remapping a window range doesn't count.
+        rafs.setupmapping(ctx, file_a, 0, 0, 0x1000, read, 0x300000, &mut req)
+            .unwrap();
+        rafs.setupmapping(ctx, file_a, 0, 0, 0x1000, read, 0x300000, &mut req)
+            .unwrap();
+        let err = rafs
+            .setupmapping(ctx, file_a, 0, 0, 0x1000, read, 0x301000, &mut req)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+        assert_eq!(req.maps.len(), 4);
+        assert_eq!(req.maps[3], (0, 0x300000, 0x1000));
+
+        let requests = vec![virtio_fs::RemovemappingOne {
+            moffset: 0x300000,
+            len: 0x1000,
+        }];
+        rafs.removemapping(ctx, file_a, requests, &mut req).unwrap();
+        rafs.setupmapping(ctx, file_a, 0, 0, 0x1000, read, 0x301000, &mut req)
+            .unwrap();
+        assert_eq!(req.maps.len(), 5);
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_dax_mappings() {
+        let mut mappings = DaxMappings::default();
+
+        mappings.insert(2, 0, 0x200000, 2).unwrap();
+        mappings.insert(2, 0x200000, 0x200000, 2).unwrap();
+        let err = mappings.insert(2, 0x400000, 0x200000, 2).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+        mappings.insert(3, 0x400000, 0x200000, 2).unwrap();
+        assert_eq!(mappings.ranges.len(), 3);
+        assert_eq!(mappings.inodes[&2], 2);
+
+        // Remapping a window range replaces the old mapping.
+        mappings.insert(3, 0x200000, 0x200000, 2).unwrap();
+        assert_eq!(mappings.ranges[&0x200000], (3, 0x200000));
+        assert_eq!(mappings.inodes[&2], 1);
+        assert_eq!(mappings.inodes[&3], 2);
+
+        mappings.remove(0x100000, 0x200000);
+        assert_eq!(mappings.ranges.len(), 1);
+        assert!(mappings.inodes.get(&2).is_none());
+        mappings.remove(0, u64::MAX);
+        assert!(mappings.ranges.is_empty());
+        assert!(mappings.inodes.is_empty());
+    }
+
     #[test]
     fn test_fsprefetchcontrol_from_rafs_config() {
         let mut config = RafsConfig {
@@ -1141,4 +2840,31 @@ pub(crate) mod tests {
         config.fs_prefetch.prefetch_all = true;
         assert!(BlobPrefetchConfig::try_from(&config).is_ok());
     }
+
+    #[test]
+    fn test_timeout_override() {
+        let mut value = serde_json::to_value(RafsConfig::default()).unwrap();
+        assert!(value["attr_timeout_override"].is_null());
+        value["attr_timeout_override"] = serde_json::json!(1.5);
+        value["entry_timeout_override"] = serde_json::json!(3.0);
+        let config: RafsConfig = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(
+            config.attr_timeout_override,
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(config.entry_timeout_override, Some(Duration::from_secs(3)));
+        assert_eq!(serde_json::to_value(&config).unwrap(), value);
+
+        let sb = RafsSuper::new(&config).unwrap();
+        assert_eq!(sb.meta.attr_timeout, Duration::from_millis(1500));
+        assert_eq!(sb.meta.entry_timeout, Duration::from_secs(3));
+        let sb = RafsSuper::new(&RafsConfig::default()).unwrap();
+        assert_eq!(
+            sb.meta.attr_timeout,
+            Duration::from_secs(RAFS_DEFAULT_ATTR_TIMEOUT)
+        );
+
+        value["attr_timeout_override"] = serde_json::json!(-1);
+        assert!(serde_json::from_value::<RafsConfig>(value).is_err());
+    }
 }
@@ -45,12 +45,13 @@ use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::metadata::{RafsInodeExt, RafsSuper};
+use crate::metadata::{RafsInodeExt, RafsMode, RafsSuper};
 
 pub mod fs;
 pub mod metadata;
 #[cfg(test)]
 pub mod mock;
+pub mod remote_bootstrap;
 
 /// Error codes for rafs related operations.
 #[derive(Debug)]
@@ -228,6 +229,76 @@ impl dyn RafsIoRead {
 
         Ok(Box::new(f))
     }
+
+    /// Create a reader to fetch metadata blob `blob_id` from a storage backend.
+    ///
+    /// The metadata blob is fetched on demand for `RafsMode::Cached`, and downloaded into an
+    /// anonymous file for `RafsMode::Direct`, which needs to mmap it.
+    pub fn from_backend(
+        config: nydus_api::http::BackendConfig,
+        blob_id: &str,
+        mode: RafsMode,
+    ) -> RafsResult<RafsIoReader> {
+        let r = remote_bootstrap::BackendBootstrapReader::new(config, blob_id)
+            .map_err(|e| RafsError::ReadMetadata(e, blob_id.to_string()))?;
+
+        match mode {
+            RafsMode::Direct => {
+                let f = r
+                    .download()
+                    .map_err(|e| RafsError::ReadMetadata(e, blob_id.to_string()))?;
+                Ok(Box::new(f))
+            }
+            RafsMode::Cached => Ok(Box::new(r)),
+        }
+    }
+
+    /// Create a reader from an in-memory buffer.
+    ///
+    /// The data is copied into an anonymous file because `RafsIoReader` must be backed by a file
+    /// descriptor.
+    pub fn from_slice(data: &[u8]) -> Result<RafsIoReader> {
+        let mut f = new_anonymous_file("rafs-bootstrap")?;
+        f.write_all(data)?;
+        f.seek(SeekFrom::Start(0))?;
+
+        Ok(Box::new(f))
+    }
+}
+
+/// Create an anonymous file, which will be released automatically when closed.
+#[cfg(target_os = "linux")]
+pub(crate) fn new_anonymous_file(name: &str) -> Result<File> {
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+    use std::ffi::CString;
+    use std::os::unix::io::FromRawFd;
+
+    let name = CString::new(name).map_err(|e| einval!(e))?;
+    let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)
+        .map_err(|e| eother!(format!("failed to create memfd, {}", e)))?;
+
+    // Safe because we have just created the file descriptor and take the ownership.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Create an anonymous file, which will be released automatically when closed.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn new_anonymous_file(name: &str) -> Result<File> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let path = std::env::temp_dir().join(format!("{}-{}-{}", name, std::process::id(), stamp));
+    let f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+
+    Ok(f)
 }
 
 ///  Iterator to walk all inodes of a Rafs filesystem.
@@ -285,7 +356,6 @@ impl<'a> Iterator for RafsIterator<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::metadata::RafsMode;
     use std::fs::OpenOptions;
     use vmm_sys_util::tempfile::TempFile;
 
@@ -14,8 +14,7 @@ use std::ffi::{OsStr, OsString};
 use std::io::SeekFrom;
 use std::io::{ErrorKind, Read, Result};
 use std::mem::size_of;
-use std::ops::Deref;
-use std::os::unix::ffi::OsStrExt;
+use std::ops::{Deref, Range};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -27,14 +26,16 @@ use nydus_utils::digest::RafsDigest;
 use nydus_utils::ByteSize;
 
 use crate::metadata::layout::v5::{
-    rafsv5_alloc_bio_vecs, rafsv5_validate_inode, RafsV5BlobTable, RafsV5ChunkInfo, RafsV5Inode,
-    RafsV5InodeChunkOps, RafsV5InodeFlags, RafsV5InodeOps, RafsV5XAttrsTable, RAFSV5_ALIGNMENT,
+    rafsv5_alloc_bio_vecs, rafsv5_get_chunk_range, rafsv5_validate_inode, RafsV5BlobTable,
+    RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeChunkOps, RafsV5InodeFlags, RafsV5InodeOps,
+    RafsV5XAttrsTable, RAFSV5_ALIGNMENT,
 };
-use crate::metadata::layout::{bytes_to_os_str, parse_xattr, RAFS_V5_ROOT_INODE};
+use crate::metadata::layout::{bytes_to_os_str, os_str_to_bytes, parse_xattr, RAFS_V5_ROOT_INODE};
 use crate::metadata::{
-    BlobIoVec, Inode, RafsError, RafsInode, RafsInodeExt, RafsInodeWalkAction,
-    RafsInodeWalkHandler, RafsResult, RafsSuperBlock, RafsSuperInodes, RafsSuperMeta, XattrName,
-    XattrValue, DOT, DOTDOT, RAFS_ATTR_BLOCK_SIZE, RAFS_MAX_NAME,
+    calculate_chunk_layout, calculate_content_hash, BlobIoVec, ChunkLocation, Inode, RafsError,
+    RafsInode, RafsInodeExt, RafsInodeWalkAction, RafsInodeWalkHandler, RafsResult, RafsSuperBlock,
+    RafsSuperInodes, RafsSuperMeta, XattrName, XattrValue, DOT, DOTDOT, RAFS_ATTR_BLOCK_SIZE,
+    RAFS_MAX_NAME,
 };
 use crate::RafsIoReader;
 
@@ -191,7 +192,7 @@ impl RafsSuperBlock for CachedSuperBlockV5 {
         self.load_all_inodes(r)?;
 
         // Validate inode digest tree
-        let digester = self.s_meta.get_digester();
+        let digester = self.s_meta.get_digest_algorithm();
         let inode = self.get_extended_inode(RAFS_V5_ROOT_INODE, false)?;
         if self.validate_inode && !rafsv5_validate_inode(inode.deref(), true, digester)? {
             return Err(einval!("invalid inode digest"));
@@ -208,8 +209,8 @@ impl RafsSuperBlock for CachedSuperBlockV5 {
         self.s_inodes.clear();
     }
 
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
-        self.s_blob.entries.clone()
+    fn get_blob_infos(&self, _force: bool) -> Result<Vec<Arc<BlobInfo>>> {
+        Ok(self.s_blob.entries.clone())
     }
 
     fn root_ino(&self) -> u64 {
@@ -400,6 +401,10 @@ impl RafsInode for CachedInodeV5 {
         rafsv5_alloc_bio_vecs(self, offset, size, user_io)
     }
 
+    fn get_chunk_range(&self, offset: u64, len: usize) -> Result<Range<u32>> {
+        rafsv5_get_chunk_range(self, offset, len)
+    }
+
     fn collect_descendants_inodes(
         &self,
         descendants: &mut Vec<Arc<dyn RafsInode>>,
@@ -485,10 +490,15 @@ impl RafsInode for CachedInodeV5 {
         Ok(self
             .i_xattr
             .keys()
-            .map(|k| k.as_bytes().to_vec())
+            .map(|k| os_str_to_bytes(k).into_owned())
             .collect::<Vec<XattrName>>())
     }
 
+    #[inline]
+    fn xattr_count(&self) -> u32 {
+        self.i_xattr.len() as u32
+    }
+
     #[inline]
     fn get_symlink(&self) -> Result<OsString> {
         if !self.is_symlink() {
@@ -521,7 +531,7 @@ impl RafsInode for CachedInodeV5 {
                 cur_offset,
             ) {
                 Ok(RafsInodeWalkAction::Continue) => {}
-                Ok(RafsInodeWalkAction::Break) => return Ok(()),
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => return Ok(()),
                 Err(e) => return Err(e),
             }
         }
@@ -537,19 +547,24 @@ impl RafsInode for CachedInodeV5 {
                 cur_offset,
             ) {
                 Ok(RafsInodeWalkAction::Continue) => {}
-                Ok(RafsInodeWalkAction::Break) => return Ok(()),
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => return Ok(()),
                 Err(e) => return Err(e),
             };
         }
 
         let mut idx = cur_offset - 2;
-        while idx < self.get_child_count() as u64 {
-            assert!(idx <= u32::MAX as u64);
-            let child = self.get_child_by_index(idx as u32)?;
+        while idx < self.i_child.len() as u64 {
+            let child = &self.i_child[idx as usize];
+            let (name, ino) = (child.name(), child.ino());
             cur_offset += 1;
-            match handler(None, child.name(), child.ino(), cur_offset) {
+            match handler(
+                Some(child.clone() as Arc<dyn RafsInode>),
+                name,
+                ino,
+                cur_offset,
+            ) {
                 Ok(RafsInodeWalkAction::Continue) => idx += 1,
-                Ok(RafsInodeWalkAction::Break) => break,
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => break,
                 Err(e) => return Err(e),
             }
         }
@@ -589,6 +604,14 @@ impl RafsInode for CachedInodeV5 {
         self.get_child_count()
     }
 
+    fn content_hash(&self) -> Result<RafsDigest> {
+        calculate_content_hash(self, self.i_meta.get_digest_algorithm())
+    }
+
+    fn chunk_layout(&self) -> Result<Vec<ChunkLocation>> {
+        calculate_chunk_layout(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -724,6 +747,10 @@ impl BlobChunkInfo for CachedChunkInfoV5 {
         self.flags.contains(BlobChunkFlags::COMPRESSED)
     }
 
+    fn is_zeroed(&self) -> bool {
+        self.flags.contains(BlobChunkFlags::ZEROED)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -764,9 +791,10 @@ mod cached_tests {
     use std::sync::Arc;
 
     use nydus_storage::device::{BlobDevice, BlobFeatures};
+    use nydus_utils::digest::{Algorithm, RafsDigest};
     use nydus_utils::ByteSize;
 
-    use crate::metadata::cached_v5::{CachedInodeV5, CachedSuperBlockV5};
+    use crate::metadata::cached_v5::{CachedChunkInfoV5, CachedInodeV5, CachedSuperBlockV5};
     use crate::metadata::layout::v5::{
         rafsv5_align, RafsV5BlobTable, RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeWrapper,
     };
@@ -839,6 +867,7 @@ mod cached_tests {
         assert_eq!(cached_chunk.compressed_offset(), 0);
         assert_eq!(cached_chunk.uncompressed_offset(), 0);
         let c_xattr = cached_inode.get_xattrs().unwrap();
+        assert_eq!(cached_inode.xattr_count(), c_xattr.len() as u32);
         for k in c_xattr.iter() {
             let k = OsStr::from_bytes(k);
             let v = cached_inode.get_xattr(k).unwrap();
@@ -893,6 +922,63 @@ mod cached_tests {
         std::fs::remove_file("/tmp/buf_2").unwrap();
     }
 
+    #[test]
+    fn test_special_file_mode() {
+        let meta = Arc::new(RafsSuperMeta::default());
+        let blob_table = Arc::new(RafsV5BlobTable::new());
+        let mut inode = CachedInodeV5::new(blob_table, meta);
+
+        inode.i_mode = libc::S_IFCHR as u32 | 0o666;
+        assert!(inode.is_char_device());
+        assert!(!inode.is_block_device());
+        assert!(!inode.is_reg());
+
+        inode.i_mode = libc::S_IFBLK as u32 | 0o660;
+        assert!(inode.is_block_device());
+        assert!(!inode.is_char_device());
+
+        inode.i_mode = libc::S_IFIFO as u32 | 0o644;
+        assert!(inode.is_fifo());
+        assert!(!inode.is_socket());
+
+        inode.i_mode = libc::S_IFSOCK as u32 | 0o755;
+        assert!(inode.is_socket());
+        assert!(!inode.is_fifo());
+        assert!(!inode.is_dir());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let meta = Arc::new(RafsSuperMeta::default());
+        let blob_table = Arc::new(RafsV5BlobTable::new());
+        let mut inode = CachedInodeV5::new(blob_table, meta);
+        inode.i_mode = libc::S_IFREG as u32 | 0o644;
+        assert_eq!(
+            inode.content_hash().unwrap(),
+            RafsDigest::from_buf(&[], Algorithm::Blake3)
+        );
+
+        let mut ids = Vec::new();
+        for idx in 0..3u8 {
+            let digest = RafsDigest::from_buf(&[idx; 16], Algorithm::Blake3);
+            let mut chunk = CachedChunkInfoV5::new();
+            chunk.block_id = Arc::new(digest);
+            inode.i_data.push(Arc::new(chunk));
+            inode.i_child_cnt += 1;
+            ids.extend_from_slice(digest.as_ref());
+            if idx == 0 {
+                assert_eq!(inode.content_hash().unwrap(), digest);
+            }
+        }
+        assert_eq!(
+            inode.content_hash().unwrap(),
+            RafsDigest::from_buf(&ids, Algorithm::Blake3)
+        );
+
+        inode.i_mode = libc::S_IFDIR as u32 | 0o755;
+        assert!(inode.content_hash().is_err());
+    }
+
     #[test]
     fn test_alloc_bio_desc() {
         let mut f = OpenOptions::new()
@@ -192,6 +192,22 @@ impl ChunkWrapper {
         }
     }
 
+    /// Check whether the chunk data is all zero.
+    pub fn is_zeroed(&self) -> bool {
+        match self {
+            ChunkWrapper::V5(c) => c.flags.contains(BlobChunkFlags::ZEROED),
+            ChunkWrapper::V6(c) => c.flags.contains(BlobChunkFlags::ZEROED),
+        }
+    }
+
+    /// Set flag for whether chunk data is all zero.
+    pub fn set_zeroed(&mut self, zeroed: bool) {
+        match self {
+            ChunkWrapper::V5(c) => c.flags.set(BlobChunkFlags::ZEROED, zeroed),
+            ChunkWrapper::V6(c) => c.flags.set(BlobChunkFlags::ZEROED, zeroed),
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     /// Set a group of chunk information fields.
     pub fn set_chunk_info(
@@ -18,11 +18,12 @@
 /// before making use of any bootstrap, especially we are using them in memory-mapped mode. The
 /// rule is to call validate() after creating any data structure from the on-disk bootstrap.
 use std::any::Any;
+use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
 use std::io::Result;
 use std::io::SeekFrom;
 use std::mem::{size_of, ManuallyDrop};
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use std::os::unix::io::AsRawFd;
 use std::sync::Arc;
 
@@ -34,18 +35,19 @@ use nydus_utils::digest::RafsDigest;
 use nydus_utils::filemap::{clone_file, FileMapState};
 
 use crate::metadata::layout::v5::{
-    rafsv5_align, rafsv5_alloc_bio_vecs, rafsv5_validate_inode, RafsV5BlobTable, RafsV5ChunkInfo,
-    RafsV5Inode, RafsV5InodeChunkOps, RafsV5InodeOps, RafsV5InodeTable, RafsV5XAttrsTable,
-    RAFSV5_ALIGNMENT, RAFSV5_EXT_BLOB_ENTRY_SIZE, RAFSV5_SUPERBLOCK_SIZE,
+    rafsv5_align, rafsv5_alloc_bio_vecs, rafsv5_get_chunk_range, rafsv5_validate_inode,
+    RafsV5BlobTable, RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeChunkOps, RafsV5InodeOps,
+    RafsV5InodeTable, RafsV5XAttrsTable, RAFSV5_ALIGNMENT, RAFSV5_EXT_BLOB_ENTRY_SIZE,
+    RAFSV5_SUPERBLOCK_SIZE,
 };
 use crate::metadata::layout::{
-    bytes_to_os_str, parse_xattr_names, parse_xattr_value, MetaRange, XattrName, XattrValue,
-    RAFS_V5_ROOT_INODE,
+    bytes_to_os_str, parse_xattr_count, parse_xattr_names, parse_xattr_value, MetaRange, XattrName,
+    XattrValue, RAFS_V5_ROOT_INODE,
 };
 use crate::metadata::{
-    Attr, Entry, Inode, RafsInode, RafsInodeWalkAction, RafsInodeWalkHandler, RafsSuperBlock,
-    RafsSuperInodes, RafsSuperMeta, DOT, DOTDOT, RAFS_ATTR_BLOCK_SIZE, RAFS_MAX_METADATA_SIZE,
-    RAFS_MAX_NAME,
+    calculate_chunk_layout, calculate_content_hash, Attr, ChunkLocation, Entry, Inode, RafsInode,
+    RafsInodeWalkAction, RafsInodeWalkHandler, RafsSuperBlock, RafsSuperInodes, RafsSuperMeta, DOT,
+    DOTDOT, RAFS_ATTR_BLOCK_SIZE, RAFS_MAX_METADATA_SIZE, RAFS_MAX_NAME,
 };
 use crate::{RafsError, RafsInodeExt, RafsIoReader, RafsResult};
 
@@ -100,17 +102,21 @@ struct DirectMappingState {
     file_map: FileMapState,
     mmapped_inode_table: bool,
     validate_inode: bool,
+    // Increased each time a new state is swapped in, used as generation number of inodes.
+    generation: u64,
 }
 
 impl DirectMappingState {
     fn new(meta: &RafsSuperMeta, validate_inode: bool) -> Self {
         DirectMappingState {
-            meta: *meta,
+            meta: meta.clone(),
             inode_table: ManuallyDrop::new(RafsV5InodeTable::default()),
             blob_table: RafsV5BlobTable::default(),
             file_map: FileMapState::default(),
             mmapped_inode_table: false,
             validate_inode,
+
+            generation: 0,
         }
     }
 }
@@ -162,7 +168,7 @@ impl DirectSuperBlockV5 {
         }
 
         if validate_inode {
-            let digester = state.meta.get_digester();
+            let digester = state.meta.get_digest_algorithm();
             if !rafsv5_validate_inode(&wrapper, false, digester)? {
                 return Err(einval!("invalid inode digest"));
             }
@@ -171,7 +177,21 @@ impl DirectSuperBlockV5 {
         Ok(wrapper)
     }
 
+    /// Build a new `DirectMappingState` object from the bootstrap and atomically swap it in.
+    ///
+    /// The current state stays active if any error happens when building the new state.
     fn update_state(&self, r: &mut RafsIoReader) -> Result<()> {
+        let mut state = self.build_state(r)?;
+        state.generation = self.state().generation + 1;
+
+        // Swap new and old DirectMappingState object, the old object will be destroyed when the
+        // reference count reaches zero.
+        self.state.store(Arc::new(state));
+
+        Ok(())
+    }
+
+    fn build_state(&self, r: &mut RafsIoReader) -> Result<DirectMappingState> {
         let old_state = self.state();
 
         // Validate file size
@@ -259,20 +279,15 @@ impl DirectSuperBlockV5 {
 
         let validate_inode = old_state.validate_inode;
 
-        let state = DirectMappingState {
-            meta: old_state.meta,
+        Ok(DirectMappingState {
+            meta: old_state.meta.clone(),
             inode_table: ManuallyDrop::new(inode_table),
             blob_table,
             file_map,
             mmapped_inode_table: true,
             validate_inode,
-        };
-
-        // Swap new and old DirectMappingState object, the old object will be destroyed when the
-        // reference count reaches zero.
-        self.state.store(Arc::new(state));
-
-        Ok(())
+            generation: 0,
+        })
     }
 
     #[inline]
@@ -319,13 +334,17 @@ impl RafsSuperBlock for DirectSuperBlockV5 {
         self.state.store(Arc::new(state));
     }
 
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
-        self.state().blob_table.entries.clone()
+    fn get_blob_infos(&self, _force: bool) -> Result<Vec<Arc<BlobInfo>>> {
+        Ok(self.state().blob_table.entries.clone())
     }
 
     fn root_ino(&self) -> u64 {
         RAFS_V5_ROOT_INODE
     }
+
+    fn generation(&self) -> u64 {
+        self.state().generation
+    }
 }
 
 /// Direct-mapped RAFS v5 inode object.
@@ -353,7 +372,7 @@ impl OndiskInodeWrapper {
     ///
     /// # Safety
     /// It depends on Self::validate() to ensure valid memory layout.
-    fn name_ref<'a>(&self, state: &'a DirectMappingState) -> &'a OsStr {
+    fn name_ref<'a>(&self, state: &'a DirectMappingState) -> Cow<'a, OsStr> {
         let offset = self.offset + size_of::<RafsV5Inode>();
         let size = self.inode(state).i_name_size as usize;
         let name = state.file_map.get_slice(offset, size).unwrap();
@@ -489,6 +508,10 @@ impl RafsInode for OndiskInodeWrapper {
         rafsv5_alloc_bio_vecs(self, offset, size, user_io)
     }
 
+    fn get_chunk_range(&self, offset: u64, len: usize) -> Result<Range<u32>> {
+        rafsv5_get_chunk_range(self, offset, len)
+    }
+
     fn collect_descendants_inodes(
         &self,
         descendants: &mut Vec<Arc<dyn RafsInode>>,
@@ -526,7 +549,7 @@ impl RafsInode for OndiskInodeWrapper {
         Entry {
             attr: self.get_attr().into(),
             inode: inode.i_ino,
-            generation: 0,
+            generation: state.generation,
             attr_flags: 0,
             attr_timeout: state.meta.attr_timeout,
             entry_timeout: state.meta.entry_timeout,
@@ -565,6 +588,13 @@ impl RafsInode for OndiskInodeWrapper {
         parse_xattr_names(xattr_data, xattr_size)
     }
 
+    fn xattr_count(&self) -> u32 {
+        let state = self.state();
+        self.get_xattr_data(&state)
+            .and_then(|(xattr_data, xattr_size)| parse_xattr_count(xattr_data, xattr_size))
+            .unwrap_or(0)
+    }
+
     /// Get symlink target of the inode.
     ///
     /// # Safety
@@ -588,7 +618,7 @@ impl RafsInode for OndiskInodeWrapper {
             // Safe to unwrap since conversion from DOT to os string can't fail.
             match handler(None, OsString::from(DOT), self.ino(), cur_offset) {
                 Ok(RafsInodeWalkAction::Continue) => {}
-                Ok(RafsInodeWalkAction::Break) => return Ok(()),
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => return Ok(()),
                 Err(e) => return Err(e),
             }
         }
@@ -599,19 +629,34 @@ impl RafsInode for OndiskInodeWrapper {
             // Safe to unwrap since conversion from DOTDOT to os string can't fail.
             match handler(None, OsString::from(DOTDOT), parent, cur_offset) {
                 Ok(RafsInodeWalkAction::Continue) => {}
-                Ok(RafsInodeWalkAction::Break) => return Ok(()),
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => return Ok(()),
                 Err(e) => return Err(e),
             };
         }
 
+        let state = self.state();
+        let inode = self.inode(state.deref());
+        let child_count = inode.i_child_count as u64;
+        let child_index = inode.i_child_index as u64;
         let mut idx = cur_offset - 2;
-        while idx < self.get_child_count() as u64 {
-            assert!(idx <= u32::MAX as u64);
-            let child = self.get_child_by_index(idx as u32)?;
+        while idx < child_count {
+            // Build the child inode under the same state guard, so callers like readdirplus
+            // don't need to look it up again.
+            let child = self.mapping.get_inode_wrapper(
+                (idx + child_index) as Inode,
+                state.deref(),
+                state.validate_inode,
+            )?;
+            let (name, ino) = (child.name(), child.ino());
             cur_offset += 1;
-            match handler(None, child.name(), child.ino(), cur_offset) {
+            match handler(
+                Some(Arc::new(child) as Arc<dyn RafsInode>),
+                name,
+                ino,
+                cur_offset,
+            ) {
                 Ok(RafsInodeWalkAction::Continue) => idx += 1,
-                Ok(RafsInodeWalkAction::Break) => break,
+                Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => break,
                 Err(e) => return Err(e),
             }
         }
@@ -702,6 +747,15 @@ impl RafsInode for OndiskInodeWrapper {
         self.get_child_count()
     }
 
+    fn content_hash(&self) -> Result<RafsDigest> {
+        let digester = self.state().meta.get_digest_algorithm();
+        calculate_content_hash(self, digester)
+    }
+
+    fn chunk_layout(&self) -> Result<Vec<ChunkLocation>> {
+        calculate_chunk_layout(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -729,7 +783,7 @@ impl RafsInodeExt for OndiskInodeWrapper {
     /// It depends on Self::validate() to ensure valid memory layout.
     fn name(&self) -> OsString {
         let state = self.state();
-        self.name_ref(state.deref()).to_owned()
+        self.name_ref(state.deref()).into_owned()
     }
 
     fn flags(&self) -> u64 {
@@ -754,6 +808,15 @@ impl RafsInodeExt for OndiskInodeWrapper {
             .map(|v| v as Arc<dyn BlobChunkInfo>)
     }
 
+    fn get_parent_inode(&self) -> Result<Option<Arc<dyn RafsInodeExt>>> {
+        if self.ino() == RAFS_V5_ROOT_INODE {
+            return Ok(None);
+        }
+        self.mapping
+            .get_extended_inode(self.parent(), false)
+            .map(Some)
+    }
+
     impl_inode_getter!(get_name_size, i_name_size, u16);
     impl_inode_getter!(parent, i_parent, u64);
 }
@@ -834,6 +897,12 @@ impl BlobChunkInfo for DirectChunkInfoV5 {
             .contains(BlobChunkFlags::COMPRESSED)
     }
 
+    fn is_zeroed(&self) -> bool {
+        self.chunk(self.state().deref())
+            .flags
+            .contains(BlobChunkFlags::ZEROED)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -18,35 +18,43 @@
 /// before making use of any bootstrap, especially we are using them in memory-mapped mode. The
 /// rule is to call validate() after creating any data structure from the on-disk bootstrap.
 use std::any::Any;
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::io::{Result, SeekFrom};
 use std::mem::size_of;
-use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::ops::Range;
 use std::os::unix::io::AsRawFd;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use arc_swap::{ArcSwap, Guard};
 use nydus_utils::filemap::{clone_file, FileMapState};
 use nydus_utils::{digest::RafsDigest, div_round_up, round_up};
+use once_cell::sync::OnceCell;
 use storage::device::{
-    v5::BlobV5ChunkInfo, BlobChunkFlags, BlobChunkInfo, BlobDevice, BlobInfo, BlobIoDesc, BlobIoVec,
+    v5::BlobV5ChunkInfo, BlobChunkFlags, BlobChunkInfo, BlobDevice, BlobInfo, BlobInfoSource,
+    BlobIoDesc, BlobIoVec,
 };
 use storage::utils::readahead;
 
 use crate::metadata::layout::v5::RafsV5ChunkInfo;
 use crate::metadata::layout::v6::{
-    recover_namespace, RafsV6BlobTable, RafsV6Dirent, RafsV6InodeChunkAddr, RafsV6InodeCompact,
-    RafsV6InodeExtended, RafsV6OndiskInode, RafsV6XattrEntry, RafsV6XattrIbodyHeader,
-    EROFS_BLOCK_SIZE, EROFS_INODE_CHUNK_BASED, EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN,
-    EROFS_INODE_SLOT_SIZE, EROFS_I_DATALAYOUT_BITS, EROFS_I_VERSION_BIT, EROFS_I_VERSION_BITS,
+    erofs_name_hash, recover_namespace, BlobTableError, RafsV6BlobTable, RafsV6Dirent,
+    RafsV6InodeChunkAddr, RafsV6InodeCompact, RafsV6InodeExtended, RafsV6OndiskInode,
+    RafsV6XattrEntry, RafsV6XattrIbodyHeader, EROFS_BLOCK_SIZE, EROFS_INODE_CHUNK_BASED,
+    EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN, EROFS_INODE_SLOT_SIZE,
+    EROFS_I_DATALAYOUT_BITS, EROFS_I_VERSION_BIT, EROFS_I_VERSION_BITS,
+};
+use crate::metadata::layout::{
+    bytes_to_os_str, os_string_to_bytes, MetaRange, XattrName, XattrValue,
 };
-use crate::metadata::layout::{bytes_to_os_str, MetaRange, XattrName, XattrValue};
 use crate::metadata::{
-    Attr, Entry, Inode, RafsInode, RafsInodeWalkAction, RafsInodeWalkHandler, RafsSuperBlock,
-    RafsSuperInodes, RafsSuperMeta, RAFS_ATTR_BLOCK_SIZE, RAFS_MAX_NAME,
+    calculate_chunk_layout, calculate_content_hash, Attr, ChunkLocation, Entry, Inode, RafsInode,
+    RafsInodeWalkAction, RafsInodeWalkHandler, RafsSuperBlock, RafsSuperInodes, RafsSuperMeta,
+    RAFS_ATTR_BLOCK_SIZE, RAFS_MAX_NAME,
 };
 use crate::{MetaType, RafsError, RafsInodeExt, RafsIoReader, RafsResult};
 
@@ -54,6 +62,26 @@ fn err_invalidate_data(rafs_err: RafsError) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::InvalidData, rafs_err)
 }
 
+// Minimal interval in seconds between messages about corrupted directories, to avoid flooding
+// the log when a broken directory is accessed repeatedly.
+const CORRUPTED_DIR_LOG_INTERVAL: u64 = 10;
+static CORRUPTED_DIR_LOG_TIME: AtomicU64 = AtomicU64::new(0);
+
+fn log_corrupted_dir(ino: Inode, err: RafsError) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let last = CORRUPTED_DIR_LOG_TIME.load(AtomicOrdering::Relaxed);
+    if now >= last + CORRUPTED_DIR_LOG_INTERVAL
+        && CORRUPTED_DIR_LOG_TIME
+            .compare_exchange(last, now, AtomicOrdering::Relaxed, AtomicOrdering::Relaxed)
+            .is_ok()
+    {
+        error!("corrupted directory {}, {}", ino, err);
+    }
+}
+
 /// The underlying struct to maintain memory mapped bootstrap for a file system.
 ///
 /// Only the DirectMappingState may store raw pointers.
@@ -62,27 +90,46 @@ fn err_invalidate_data(rafs_err: RafsError) -> std::io::Error {
 /// `DirectMappingState.base + offset`.
 struct DirectMappingState {
     meta: Arc<RafsSuperMeta>,
-    blob_table: RafsV6BlobTable,
+    blob_table: Arc<RafsV6BlobTable>,
     map: FileMapState,
+    // Map from chunk addresses to indexes in the chunk table, built on first use. It lives in the
+    // state because the chunk table may change when a new bootstrap is swapped in.
+    chunk_map: OnceCell<HashMap<RafsV6InodeChunkAddr, usize>>,
+    // Increased each time a new state is swapped in, used as generation number of inodes.
+    generation: u64,
 }
 
 impl DirectMappingState {
     fn new(meta: &RafsSuperMeta) -> Self {
         DirectMappingState {
-            meta: Arc::new(*meta),
-            blob_table: RafsV6BlobTable::default(),
+            meta: Arc::new(meta.clone()),
+            blob_table: Arc::new(RafsV6BlobTable::default()),
             map: FileMapState::default(),
+            chunk_map: OnceCell::new(),
+            generation: 0,
         }
     }
 }
 
+// Maximum number of chunk table scans to resolve chunk layout of files before falling back to
+// build the chunk map.
+const CHUNK_LAYOUT_SCAN_LIMIT: usize = 16;
+
 struct DirectCachedInfo {
     meta_offset: usize,
     root_ino: Inode,
+    // Offset of the root inode, which is accessed by most requests. Only the offset is cached
+    // because `OndiskInodeWrapper` holds a reference to the super block.
+    root_inode_offset: Option<usize>,
+    // Whether directory entries are sorted by name hash instead of name.
+    dirhash: bool,
     chunk_size: u32,
-    chunk_map: Mutex<Option<HashMap<RafsV6InodeChunkAddr, usize>>>,
+    // Number of chunk table scans to resolve chunk layout without the chunk map.
+    chunk_layout_scans: AtomicUsize,
     attr_timeout: Duration,
     entry_timeout: Duration,
+    #[cfg(test)]
+    chunk_map_loads: AtomicUsize,
 }
 
 /// Direct-mapped Rafs v6 super block.
@@ -97,13 +144,20 @@ impl DirectSuperBlockV6 {
     pub fn new(meta: &RafsSuperMeta) -> Self {
         let state = DirectMappingState::new(meta);
         let meta_offset = meta.meta_blkaddr as usize * EROFS_BLOCK_SIZE as usize;
+        let root_inode_offset = (meta.root_nid as usize)
+            .checked_mul(EROFS_INODE_SLOT_SIZE)
+            .and_then(|v| v.checked_add(meta_offset));
         let info = DirectCachedInfo {
             meta_offset,
             root_ino: meta.root_nid as Inode,
+            root_inode_offset,
+            dirhash: meta.dirhash,
             chunk_size: meta.chunk_size,
-            chunk_map: Mutex::new(None),
+            chunk_layout_scans: AtomicUsize::new(0),
             attr_timeout: meta.attr_timeout,
             entry_timeout: meta.entry_timeout,
+            #[cfg(test)]
+            chunk_map_loads: AtomicUsize::new(0),
         };
 
         Self {
@@ -130,7 +184,21 @@ impl DirectSuperBlockV6 {
         state: &Guard<Arc<DirectMappingState>>,
         nid: u64,
     ) -> Result<OndiskInodeWrapper> {
-        let offset = self.info.meta_offset + nid as usize * EROFS_INODE_SLOT_SIZE;
+        // Bootstrap may come from untrusted sources, so validate nid before accessing the inode.
+        let offset = if nid == self.info.root_ino {
+            self.info.root_inode_offset
+        } else {
+            (nid as usize)
+                .checked_mul(EROFS_INODE_SLOT_SIZE)
+                .and_then(|v| v.checked_add(self.info.meta_offset))
+        };
+        let offset = offset
+            .filter(|v| {
+                v.checked_add(size_of::<RafsV6InodeCompact>())
+                    .map(|end| end <= state.map.size())
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| einval!(format!("nid {} out of bounds", nid)))?;
         OndiskInodeWrapper::new(state, self.clone(), offset)
     }
 
@@ -153,11 +221,28 @@ impl DirectSuperBlockV6 {
         })
     }
 
+    /// Build a new `DirectMappingState` object from the bootstrap and atomically swap it in.
+    ///
+    /// The current state stays active if any error happens when building the new state.
     fn update_state(&self, r: &mut RafsIoReader) -> Result<()> {
+        let mut state = self.build_state(r)?;
+        state.generation = self.state().generation + 1;
+
+        // Swap new and old DirectMappingState object,
+        // the old object will be destroyed when the reference count reaches zero.
+        self.state.store(Arc::new(state));
+
+        Ok(())
+    }
+
+    fn build_state(&self, r: &mut RafsIoReader) -> Result<DirectMappingState> {
         // Validate file size
         let file = clone_file(r.as_raw_fd())?;
         let md = file.metadata()?;
         let len = md.len();
+        if len < EROFS_BLOCK_SIZE as u64 {
+            return Err(ebadf!("invalid bootstrap file"));
+        }
         let md_range =
             MetaRange::new(EROFS_BLOCK_SIZE as u64, len - EROFS_BLOCK_SIZE as u64, true)?;
 
@@ -180,24 +265,28 @@ impl DirectSuperBlockV6 {
         blob_table.load(r, meta.blob_table_size, meta.chunk_size, meta.flags)?;
 
         let file_map = FileMapState::new(file, 0, len as usize, false)?;
-        let state = DirectMappingState {
+
+        Ok(DirectMappingState {
             meta: old_state.meta.clone(),
-            blob_table,
+            blob_table: Arc::new(blob_table),
             map: file_map,
-        };
-
-        // Swap new and old DirectMappingState object,
-        // the old object will be destroyed when the reference count reaches zero.
-        self.state.store(Arc::new(state));
-
-        Ok(())
+            chunk_map: OnceCell::new(),
+            generation: 0,
+        })
     }
 
     // For RafsV6, inode doesn't store detailed chunk info, only a simple RafsV6InodeChunkAddr
     // so we need to use the chunk table at the end of the bootstrap to restore the chunk info of an inode
-    fn load_chunk_map(&self) -> Result<HashMap<RafsV6InodeChunkAddr, usize>> {
+    fn load_chunk_map(
+        &self,
+        state: &Guard<Arc<DirectMappingState>>,
+    ) -> Result<HashMap<RafsV6InodeChunkAddr, usize>> {
+        #[cfg(test)]
+        self.info
+            .chunk_map_loads
+            .fetch_add(1, AtomicOrdering::Relaxed);
+
         let mut chunk_map = HashMap::default();
-        let state = self.state.load();
         let size = state.meta.chunk_table_size as usize;
         if size == 0 {
             return Ok(chunk_map);
@@ -209,16 +298,59 @@ impl DirectSuperBlockV6 {
         }
 
         for idx in 0..(size / unit_size) {
-            let chunk = DirectChunkInfoV6::new(&state, self.clone(), idx)?;
-            let mut v6_chunk = RafsV6InodeChunkAddr::new();
-            v6_chunk.set_blob_index(chunk.blob_index());
-            v6_chunk.set_blob_ci_index(chunk.id());
-            v6_chunk.set_block_addr((chunk.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32);
-            chunk_map.insert(v6_chunk, idx);
+            let chunk = DirectChunkInfoV6::new(state, self.clone(), idx)?;
+            chunk_map.insert(chunk.chunk_addr(), idx);
         }
 
         Ok(chunk_map)
     }
+
+    // Resolve chunk addresses by scanning the chunk table, without building the chunk map.
+    fn resolve_chunk_addrs(
+        &self,
+        state: &Guard<Arc<DirectMappingState>>,
+        addrs: &[RafsV6InodeChunkAddr],
+    ) -> Result<Vec<DirectChunkInfoV6>> {
+        let mut pending: HashMap<RafsV6InodeChunkAddr, Vec<usize>> = HashMap::new();
+        for (idx, addr) in addrs.iter().enumerate() {
+            pending.entry(*addr).or_default().push(idx);
+        }
+
+        let mut chunks: Vec<Option<DirectChunkInfoV6>> = Vec::with_capacity(addrs.len());
+        chunks.resize_with(addrs.len(), || None);
+        let count = state.meta.chunk_table_size as usize / size_of::<RafsV5ChunkInfo>();
+        for idx in 0..count {
+            if pending.is_empty() {
+                break;
+            }
+            let chunk = DirectChunkInfoV6::new(state, self.clone(), idx)?;
+            if let Some(indexes) = pending.remove(&chunk.chunk_addr()) {
+                for i in indexes {
+                    chunks[i] = Some(DirectChunkInfoV6::new(state, self.clone(), idx)?);
+                }
+            }
+        }
+
+        chunks
+            .into_iter()
+            .map(|c| c.ok_or_else(|| enoent!("failed to get chunk info")))
+            .collect()
+    }
+
+    // The chunk map is loaded on first use, and shared lock-free afterwards.
+    fn get_chunk_map<'a>(
+        &self,
+        state: &'a Guard<Arc<DirectMappingState>>,
+    ) -> Result<&'a HashMap<RafsV6InodeChunkAddr, usize>> {
+        state
+            .chunk_map
+            .get_or_try_init(|| self.load_chunk_map(state))
+    }
+
+    #[inline]
+    fn state(&self) -> Guard<Arc<DirectMappingState>> {
+        self.state.load()
+    }
 }
 
 impl RafsSuperInodes for DirectSuperBlockV6 {
@@ -271,14 +403,39 @@ impl RafsSuperBlock for DirectSuperBlockV6 {
         self.state.store(Arc::new(state));
     }
 
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
-        self.state.load().blob_table.get_all()
+    fn get_blob_infos(&self, force: bool) -> Result<Vec<Arc<BlobInfo>>> {
+        let state = self.state.load();
+        if force {
+            state.blob_table.get_all()
+        } else {
+            Ok(state.blob_table.get_ready())
+        }
+    }
+
+    fn get_blob_source(&self) -> Result<Arc<dyn BlobInfoSource>> {
+        Ok(self.state.load().blob_table.clone())
     }
 
     fn root_ino(&self) -> u64 {
         self.info.root_ino
     }
 
+    fn meta_blkaddr(&self) -> u32 {
+        self.state().meta.meta_blkaddr
+    }
+
+    fn root_nid(&self) -> u16 {
+        self.state().meta.root_nid
+    }
+
+    fn generation(&self) -> u64 {
+        self.state().generation
+    }
+
+    fn supports_chunk_info(&self) -> bool {
+        true
+    }
+
     fn get_chunk_info(&self, idx: usize) -> Result<Arc<dyn BlobChunkInfo>> {
         let state = self.state.load();
         let chunk = DirectChunkInfoV6::new(&state, self.clone(), idx)?;
@@ -353,59 +510,63 @@ impl OndiskInodeWrapper {
         block_index: usize,
         index: usize,
         max_entries: usize,
-    ) -> RafsResult<&'a OsStr> {
+    ) -> RafsResult<Cow<'a, OsStr>> {
         let offset = self.data_block_offset(inode, block_index)?;
         let de = self.get_entry(state, inode, block_index, index)?;
-        let buf: &[u8] = if index < max_entries - 1 {
+        let illegal_dirent = |msg: String| {
+            error!(
+                "nid {} entry index {} block index {}: {}",
+                self.ino(),
+                index,
+                block_index,
+                msg
+            );
+            RafsError::IllegalMetaStruct(MetaType::Dir, msg)
+        };
+
+        // All blocks of a directory should be fully used except the last one.
+        let blocks_count = div_round_up(self.size(), EROFS_BLOCK_SIZE) as usize;
+        let block_used_bytes = if blocks_count == block_index + 1 {
+            self.size() - block_index as u64 * EROFS_BLOCK_SIZE
+        } else {
+            EROFS_BLOCK_SIZE
+        } as usize;
+
+        // Names are stored after the dirent array and must be within the used part of the block.
+        let name_start = de.e_nameoff as usize;
+        if name_start < size_of::<RafsV6Dirent>() * max_entries || name_start > block_used_bytes {
+            return Err(illegal_dirent(format!(
+                "name offset {} out of range, used bytes {}",
+                name_start, block_used_bytes
+            )));
+        }
+        let name_end = if index + 1 < max_entries {
             let next_de = self.get_entry(state, inode, block_index, index + 1)?;
-            let (next_de_name_off, de_name_off) = (next_de.e_nameoff, de.e_nameoff);
-            let len = next_de.e_nameoff.checked_sub(de.e_nameoff).ok_or_else(|| {
-                error!(
-                        "nid {} entry index {} block index {} next dir entry {:?} current dir entry {:?}",
-                        self.ino(), index, block_index, next_de, de
-                    );
-                RafsError::IllegalMetaStruct(
-                    MetaType::Dir,
-                    format!("cur {} next {}", next_de_name_off, de_name_off),
-                )
-            })?;
-
-            state
-                .map
-                .get_slice(offset + de.e_nameoff as usize, len as usize)
-                .map_err(|_e| RafsError::InvalidImageData)?
+            let next_name_start = next_de.e_nameoff as usize;
+            if next_name_start < name_start || next_name_start > block_used_bytes {
+                return Err(illegal_dirent(format!(
+                    "cur {} next {}",
+                    name_start, next_name_start
+                )));
+            }
+            next_name_start
         } else {
-            let head_de = self.get_entry(state, inode, block_index, 0)?;
-            let s = (de.e_nameoff - head_de.e_nameoff) as u64
-                + (size_of::<RafsV6Dirent>() * max_entries) as u64;
-
-            // The possible maximum len of the last dirent's file name should be calculated
-            // differently depends on whether the dirent is at the last block of the dir file.
-            // Because the other blocks should be fully used, while the last may not.
-            let len = if div_round_up(self.size(), EROFS_BLOCK_SIZE) as usize == block_index + 1 {
-                (self.size() % EROFS_BLOCK_SIZE - s) as usize
-            } else {
-                (EROFS_BLOCK_SIZE - s) as usize
-            };
+            block_used_bytes
+        };
 
-            let buf: &[u8] = state
-                .map
-                .get_slice(offset + de.e_nameoff as usize, len)
-                .map_err(|_e| RafsError::InvalidImageData)?;
-            // Use this trick to temporarily decide entry name's length. Improve this?
-            let mut l: usize = 0;
-            for i in buf {
-                if *i != 0 {
-                    l += 1;
-                    if len == l {
-                        break;
-                    }
-                } else {
-                    break;
-                }
+        let mut buf: &[u8] = state
+            .map
+            .get_slice(offset + name_start, name_end - name_start)
+            .map_err(|_e| RafsError::InvalidImageData)?;
+        // Name of the last dirent in a block may be padded with NUL.
+        if index + 1 == max_entries {
+            if let Some(len) = buf.iter().position(|c| *c == 0) {
+                buf = &buf[..len];
             }
-            &buf[..l]
-        };
+        }
+        if buf.is_empty() || buf.len() > RAFS_MAX_NAME {
+            return Err(illegal_dirent(format!("invalid name length {}", buf.len())));
+        }
 
         Ok(bytes_to_os_str(buf))
     }
@@ -447,6 +608,10 @@ impl OndiskInodeWrapper {
         Ok(r)
     }
 
+    fn is_inline_layout(inode: &dyn RafsV6OndiskInode) -> bool {
+        inode.format() >> EROFS_I_VERSION_BITS == EROFS_INODE_FLAT_INLINE
+    }
+
     fn mode_format_bits(&self) -> u32 {
         let state = self.state();
         let i = self.disk_inode(&state);
@@ -466,10 +631,17 @@ impl OndiskInodeWrapper {
         let chunk_index = chunk_addr.blob_ci_index();
 
         match state.blob_table.get(blob_index) {
-            Err(e) => {
+            Err(BlobTableError::IndexOutOfRange { index, table_size }) => {
                 warn!(
-                    "failed to get blob with index {} for chunk address {:?}, {}",
-                    blob_index, chunk_addr, e
+                    "blob index {} of chunk address {:?} is out of range, blob table size {}",
+                    index, chunk_addr, table_size
+                );
+                None
+            }
+            Err(BlobTableError::InvalidEntry { index }) => {
+                warn!(
+                    "blob table entry {} of chunk address {:?} is invalid",
+                    index, chunk_addr
                 );
                 None
             }
@@ -518,15 +690,20 @@ impl OndiskInodeWrapper {
         );
 
         let total_chunk_addresses = div_round_up(self.size(), self.chunk_size() as u64) as u32;
+        let count = total_chunk_addresses
+            .checked_sub(head_chunk_index)
+            .ok_or(RafsError::InvalidImageData)?;
         let offset = self.offset as usize
             + Self::inode_xattr_size(inode)
             + head_chunk_index as usize * size_of::<RafsV6InodeChunkAddr>();
         state
             .map
-            .get_slice(offset, (total_chunk_addresses - head_chunk_index) as usize)
+            .get_slice(offset, count as usize)
             .map_err(|_e| RafsError::InvalidImageData)
     }
 
+    // Find the directory block which may contain `name`, directory entries are sorted across
+    // blocks in the same order as `compare_names()`.
     fn find_target_block(
         &self,
         state: &Guard<Arc<DirectMappingState>>,
@@ -537,45 +714,63 @@ impl OndiskInodeWrapper {
             return Err(enoent!());
         }
 
-        let blocks_count = div_round_up(inode.size(), EROFS_BLOCK_SIZE);
+        // Search in the half-open range [first, last) to avoid underflow of `last`.
         let mut first = 0usize;
-        let mut last = (blocks_count - 1) as usize;
-        let mut target_block = 0usize;
-        while first <= last {
+        let mut last = div_round_up(inode.size(), EROFS_BLOCK_SIZE) as usize;
+        while first < last {
             let pivot = first + ((last - first) >> 1);
             let head_entry = self
                 .get_entry(state, inode, pivot, 0)
                 .map_err(err_invalidate_data)?;
             let head_name_offset = head_entry.e_nameoff as usize;
             let entries_count = head_name_offset / size_of::<RafsV6Dirent>();
+            if entries_count == 0 {
+                return Err(err_invalidate_data(RafsError::InvalidImageData));
+            }
             let h_name = self
                 .entry_name(state, inode, pivot, 0, entries_count)
                 .map_err(err_invalidate_data)?;
+            if self.compare_names(&h_name, name) == Ordering::Greater {
+                last = pivot;
+                continue;
+            }
             let t_name = self
                 .entry_name(state, inode, pivot, entries_count - 1, entries_count)
                 .map_err(err_invalidate_data)?;
-            if h_name <= name && t_name >= name {
-                target_block = pivot;
-                break;
-            } else if h_name > name {
-                last = pivot - 1;
-            } else {
+            if self.compare_names(&t_name, name) == Ordering::Less {
                 first = pivot + 1;
+            } else {
+                return Ok(pivot);
             }
         }
 
-        Ok(target_block)
+        Err(enoent!())
+    }
+
+    // Compare names of directory entries in the same order as they are sorted on disk.
+    fn compare_names(&self, a: &OsStr, b: &OsStr) -> Ordering {
+        if self.mapping.info.dirhash {
+            erofs_name_hash(a)
+                .cmp(&erofs_name_hash(b))
+                .then_with(|| a.cmp(b))
+        } else {
+            a.cmp(b)
+        }
     }
 
     fn get_parent(&mut self) -> Result<()> {
-        assert!(self.is_dir());
+        if !self.is_dir() {
+            return Err(einval!("inode is not a directory"));
+        }
         let parent = self.get_child_by_name(OsStr::new(".."))?;
         self.parent_inode = Some(parent.ino());
         Ok(())
     }
 
     fn get_name(&mut self, state: &Guard<Arc<DirectMappingState>>) -> Result<()> {
-        assert!(self.is_dir());
+        if !self.is_dir() {
+            return Err(einval!("inode is not a directory"));
+        }
         let cur_ino = self.ino();
         if cur_ino == self.mapping.info.root_ino {
             self.name = Some(OsString::from(""));
@@ -591,7 +786,13 @@ impl OndiskInodeWrapper {
                     Ok(RafsInodeWalkAction::Continue)
                 },
             )?;
-            assert!(self.name.is_some());
+            if self.name.is_none() {
+                return Err(enoent!(format!(
+                    "can't find directory entry for inode {} in parent {}",
+                    cur_ino,
+                    self.parent()
+                )));
+            }
         }
 
         Ok(())
@@ -614,7 +815,12 @@ impl RafsInode for OndiskInodeWrapper {
             )));
         }
 
-        if self.is_reg() {
+        if self.is_reg() && Self::is_inline_layout(inode) {
+            // Only the tail block is stored inline, following the inode and xattrs.
+            let tail = self.size() - (self.blocks_count().max(1) - 1) * EROFS_BLOCK_SIZE;
+            let size = OndiskInodeWrapper::inode_xattr_size(inode) + tail as usize;
+            state.map.validate_range(self.offset, size)?;
+        } else if self.is_reg() {
             if state.meta.is_chunk_dict() {
                 // chunk-dict doesn't support chunk_count check
                 return Err(std::io::Error::from_raw_os_error(libc::EOPNOTSUPP));
@@ -636,6 +842,23 @@ impl RafsInode for OndiskInodeWrapper {
         Ok(())
     }
 
+    fn get_chunk_range(&self, offset: u64, len: usize) -> Result<Range<u32>> {
+        let end = offset
+            .checked_add(len as u64)
+            .ok_or_else(|| einval!("invalid read size"))?;
+        let end = std::cmp::min(end, self.size());
+        let chunk_cnt = self.get_chunk_count();
+        if offset >= end || chunk_cnt == 0 {
+            return Ok(0..0);
+        }
+
+        let chunk_size = self.chunk_size() as u64;
+        let start = std::cmp::min(offset / chunk_size, chunk_cnt as u64) as u32;
+        let end = std::cmp::min((end - 1) / chunk_size + 1, chunk_cnt as u64) as u32;
+
+        Ok(start..std::cmp::max(start, end))
+    }
+
     fn alloc_bio_vecs(
         &self,
         device: &BlobDevice,
@@ -643,19 +866,35 @@ impl RafsInode for OndiskInodeWrapper {
         size: usize,
         user_io: bool,
     ) -> Result<Vec<BlobIoVec>> {
+        // The kernel may send reads beyond EOF, for example racing with truncation.
+        if size == 0 || offset >= self.size() {
+            return Ok(Vec::new());
+        }
         let state = self.state();
+        if Self::is_inline_layout(self.disk_inode(&state)) {
+            // File content is stored in the metadata blob, refer to `get_inline_data()`.
+            return Ok(Vec::new());
+        }
         let chunk_size = self.chunk_size();
         let head_chunk_index = offset / chunk_size as u64;
         let mut vec: Vec<BlobIoVec> = Vec::new();
         let chunks = self
             .chunk_addresses(&state, head_chunk_index as u32)
             .map_err(err_invalidate_data)?;
-        if chunks.is_empty() {
-            return Ok(vec);
-        }
 
         let content_offset = (offset % chunk_size as u64) as u32;
         let mut left = std::cmp::min(self.size() - offset, size as u64) as u32;
+        let chunks_needed = div_round_up(content_offset as u64 + left as u64, chunk_size as u64);
+        if (chunks.len() as u64) < chunks_needed {
+            error!(
+                "inode {} has {} chunks from index {}, but {} chunks are needed",
+                self.ino(),
+                chunks.len(),
+                head_chunk_index,
+                chunks_needed
+            );
+            return Err(std::io::Error::from_raw_os_error(libc::EUCLEAN));
+        }
         let mut content_len = std::cmp::min(chunk_size - content_offset, left);
         let desc = self
             .make_chunk_io(
@@ -692,7 +931,15 @@ impl RafsInode for OndiskInodeWrapper {
         if !descs.is_empty() {
             vec.push(descs)
         }
-        assert_eq!(left, 0);
+        if left != 0 {
+            error!(
+                "inode {} has no chunk for {} bytes at offset {}",
+                self.ino(),
+                left,
+                offset + size as u64 - left as u64
+            );
+            return Err(std::io::Error::from_raw_os_error(libc::EUCLEAN));
+        }
 
         Ok(vec)
     }
@@ -713,6 +960,12 @@ impl RafsInode for OndiskInodeWrapper {
                     if name != "." && name != ".." {
                         child_dirs.push(child_inode);
                     }
+                } else if child_inode.is_char_device()
+                    || child_inode.is_block_device()
+                    || child_inode.is_fifo()
+                    || child_inode.is_socket()
+                {
+                    // Special files have no data chunks, so never issue chunk IO for them.
                 } else if !child_inode.is_empty_size() && child_inode.is_reg() {
                     descendants.push(child_inode);
                 }
@@ -734,7 +987,7 @@ impl RafsInode for OndiskInodeWrapper {
         Entry {
             attr: self.get_attr().into(),
             inode: self.ino(),
-            generation: 0,
+            generation: self.state().generation,
             attr_timeout: self.mapping.info.attr_timeout,
             entry_timeout: self.mapping.info.entry_timeout,
             ..Default::default()
@@ -821,7 +1074,7 @@ impl RafsInode for OndiskInodeWrapper {
                 offset + size_of::<RafsV6XattrEntry>(),
                 e.name_len() as usize,
             )?;
-            xa_name.push(OsStr::from_bytes(suffix));
+            xa_name.push(bytes_to_os_str(suffix));
             if xa_name == name {
                 let data: &[u8] = state.map.get_slice(
                     offset + size_of::<RafsV6XattrEntry>() + e.name_len() as usize,
@@ -832,6 +1085,9 @@ impl RafsInode for OndiskInodeWrapper {
 
             let mut s = e.name_len() + e.value_size() + size_of::<RafsV6XattrEntry>() as u32;
             s = round_up(s as u64, size_of::<RafsV6XattrEntry>() as u64) as u32;
+            if s as usize > remaining {
+                return Err(einval!("invalid xattr entry size"));
+            }
             remaining -= s as usize;
             offset += s as usize;
         }
@@ -858,12 +1114,15 @@ impl RafsInode for OndiskInodeWrapper {
                 e.name_len() as usize,
             )?;
             let ns = recover_namespace(e.name_index())?;
-            let mut xa = ns.into_vec();
+            let mut xa = os_string_to_bytes(ns);
             xa.extend_from_slice(name);
             xattrs.push(xa);
 
             let mut s = e.name_len() + e.value_size() + size_of::<RafsV6XattrEntry>() as u32;
             s = round_up(s as u64, size_of::<RafsV6XattrEntry>() as u64) as u32;
+            if s as usize > remaining {
+                return Err(einval!("invalid xattr entry size"));
+            }
             offset += s as usize;
             remaining -= s as usize;
         }
@@ -871,6 +1130,41 @@ impl RafsInode for OndiskInodeWrapper {
         Ok(xattrs)
     }
 
+    /// Get number of extended attributes.
+    ///
+    /// `xattr_inline_count()` is the size of the inline xattr area in units of
+    /// `RafsV6XattrEntry` instead of the number of xattr entries, so walk through the entry
+    /// headers to count entries without copying out xattr names.
+    fn xattr_count(&self) -> u32 {
+        let state = self.state();
+        let inode = self.disk_inode(&state);
+        let total = inode.xattr_inline_count();
+        if total == 0 {
+            return 0;
+        }
+
+        let mut count = 0;
+        let mut offset =
+            self.offset + Self::inode_size(inode) + size_of::<RafsV6XattrIbodyHeader>();
+        let mut remaining = (total - 1) as usize * size_of::<RafsV6XattrEntry>();
+        while remaining > 0 {
+            let e: &RafsV6XattrEntry = match state.map.get_ref(offset) {
+                Ok(v) => v,
+                Err(_) => return 0,
+            };
+            let mut s = e.name_len() + e.value_size() + size_of::<RafsV6XattrEntry>() as u32;
+            s = round_up(s as u64, size_of::<RafsV6XattrEntry>() as u64) as u32;
+            if s as usize > remaining {
+                return 0;
+            }
+            count += 1;
+            offset += s as usize;
+            remaining -= s as usize;
+        }
+
+        count
+    }
+
     /// Get symlink target of the inode.
     ///
     /// # Safety
@@ -894,8 +1188,9 @@ impl RafsInode for OndiskInodeWrapper {
     fn walk_children_inodes(&self, entry_offset: u64, handler: RafsInodeWalkHandler) -> Result<()> {
         let state = self.state();
         let inode = self.disk_inode(&state);
+        // An empty directory has no dirent blocks at all.
         if inode.size() == 0 {
-            return Err(enoent!());
+            return Ok(());
         }
 
         let blocks_count = div_round_up(inode.size(), EROFS_BLOCK_SIZE);
@@ -936,14 +1231,16 @@ impl RafsInode for OndiskInodeWrapper {
                     &state,
                     nid,
                     self.ino(),
-                    OsString::from(name),
+                    name.to_os_string(),
                 )?) as Arc<dyn RafsInode>;
                 cur_offset += 1;
                 match handler(Some(inode), name.to_os_string(), nid, cur_offset) {
                     // Break returned by handler indicates that there is not enough buffer of readdir for entries inreaddir,
                     // such that it has to return. because this is a nested loop,
                     // using break can only jump out of the internal loop, there is no way to jump out of the whole loop.
-                    Ok(RafsInodeWalkAction::Break) => return Ok(()),
+                    Ok(RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_)) => {
+                        return Ok(())
+                    }
                     Ok(RafsInodeWalkAction::Continue) => continue,
                     Err(e) => return Err(e),
                 };
@@ -960,38 +1257,39 @@ impl RafsInode for OndiskInodeWrapper {
     fn get_child_by_name(&self, name: &OsStr) -> Result<Arc<dyn RafsInodeExt>> {
         let state = self.state();
         let inode = self.disk_inode(&state);
-        if let Ok(target_block) = self.find_target_block(&state, name) {
-            let head_entry = self
-                .get_entry(&state, inode, target_block, 0)
-                .map_err(err_invalidate_data)?;
-            let head_name_offset = head_entry.e_nameoff as usize;
-            let entries_count = head_name_offset / size_of::<RafsV6Dirent>();
+        let target_block = self.find_target_block(&state, name)?;
+        let head_entry = self
+            .get_entry(&state, inode, target_block, 0)
+            .map_err(err_invalidate_data)?;
+        let head_name_offset = head_entry.e_nameoff as usize;
+        let entries_count = head_name_offset / size_of::<RafsV6Dirent>();
 
-            let mut first = 0;
-            let mut last = entries_count - 1;
-            while first <= last {
-                let pivot = first + ((last - first) >> 1);
-                let de = self
-                    .get_entry(&state, inode, target_block, pivot)
-                    .map_err(err_invalidate_data)?;
-                let d_name = self
-                    .entry_name(&state, inode, target_block, pivot, entries_count)
-                    .map_err(err_invalidate_data)?;
-                match d_name.cmp(name) {
-                    Ordering::Equal => {
-                        let inode = self.mapping.inode_wrapper_with_info(
-                            &state,
-                            de.e_nid,
-                            self.ino(),
-                            OsString::from(name),
-                        )?;
-                        return Ok(Arc::new(inode));
-                    }
-                    Ordering::Less => first = pivot + 1,
-                    Ordering::Greater => last = pivot - 1,
+        // Search in the half-open range [first, last) to avoid underflow of `last`.
+        let mut first = 0usize;
+        let mut last = entries_count;
+        while first < last {
+            let pivot = first + ((last - first) >> 1);
+            let de = self
+                .get_entry(&state, inode, target_block, pivot)
+                .map_err(err_invalidate_data)?;
+            let d_name = self
+                .entry_name(&state, inode, target_block, pivot, entries_count)
+                .map_err(err_invalidate_data)?;
+            match self.compare_names(&d_name, name) {
+                Ordering::Equal => {
+                    let inode = self.mapping.inode_wrapper_with_info(
+                        &state,
+                        de.e_nid,
+                        self.ino(),
+                        OsString::from(name),
+                    )?;
+                    return Ok(Arc::new(inode));
                 }
+                Ordering::Less => first = pivot + 1,
+                Ordering::Greater => last = pivot,
             }
         }
+
         Err(enoent!())
     }
 
@@ -1013,8 +1311,7 @@ impl RafsInode for OndiskInodeWrapper {
         for i in 0..blocks_count as usize {
             let head_entry = self
                 .get_entry(&state, inode, i, 0)
-                .map_err(err_invalidate_data)
-                .unwrap();
+                .map_err(err_invalidate_data)?;
             let name_offset = head_entry.e_nameoff;
             let entries_count = name_offset as usize / size_of::<RafsV6Dirent>();
 
@@ -1025,7 +1322,7 @@ impl RafsInode for OndiskInodeWrapper {
                 let name = self
                     .entry_name(&state, inode, i, j, entries_count)
                     .map_err(err_invalidate_data)?;
-                if name == "." || name == ".." {
+                if name == OsStr::new(".") || name == OsStr::new("..") {
                     continue;
                 }
                 if cur_idx == idx {
@@ -1056,17 +1353,21 @@ impl RafsInode for OndiskInodeWrapper {
         let inode = self.disk_inode(&state);
         let blocks_count = div_round_up(self.size(), EROFS_BLOCK_SIZE);
         for i in 0..blocks_count as usize {
-            let head_entry = self
-                .get_entry(&state, inode, i, 0)
-                .map_err(err_invalidate_data)
-                .unwrap();
+            let head_entry = match self.get_entry(&state, inode, i, 0) {
+                Ok(v) => v,
+                Err(e) => {
+                    // The trait method can't report errors, so treat it as an empty directory.
+                    log_corrupted_dir(self.ino(), e);
+                    return 0;
+                }
+            };
             let name_offset = head_entry.e_nameoff;
             let entries_count = name_offset / size_of::<RafsV6Dirent>() as u16;
 
             child_cnt += entries_count as u32;
         }
-        // Skip DOT and DOTDOT
-        child_cnt - 2
+        // Skip DOT and DOTDOT, empty directories may have no dirent at all.
+        child_cnt.saturating_sub(2)
     }
 
     fn get_child_index(&self) -> Result<u32> {
@@ -1082,9 +1383,81 @@ impl RafsInode for OndiskInodeWrapper {
 
     #[inline]
     fn get_chunk_count(&self) -> u32 {
+        let state = self.state();
+        if self.is_reg() && Self::is_inline_layout(self.disk_inode(&state)) {
+            // File content stored in the metadata blob has no data chunks.
+            return 0;
+        }
         self.get_child_count()
     }
 
+    /// Get file content stored with the `EROFS_INODE_FLAT_INLINE` layout.
+    ///
+    /// # Safety
+    /// It depends on Self::validate() to ensure valid memory layout.
+    fn get_inline_data(&self) -> Result<Option<Vec<u8>>> {
+        let state = self.state();
+        let inode = self.disk_inode(&state);
+        if !self.is_reg() || !Self::is_inline_layout(inode) {
+            return Ok(None);
+        }
+
+        let size = inode.size() as usize;
+        let mut data = Vec::with_capacity(size);
+        for idx in 0..self.blocks_count() as usize {
+            let offset = self
+                .data_block_offset(inode, idx)
+                .map_err(err_invalidate_data)?;
+            let len = std::cmp::min(size - data.len(), EROFS_BLOCK_SIZE as usize);
+            let buf: &[u8] = state.map.get_slice(offset, len)?;
+            data.extend_from_slice(buf);
+        }
+
+        Ok(Some(data))
+    }
+
+    fn content_hash(&self) -> Result<RafsDigest> {
+        let digester = self.state().meta.get_digest_algorithm();
+        calculate_content_hash(self, digester)
+    }
+
+    fn chunk_layout(&self) -> Result<Vec<ChunkLocation>> {
+        let info = &self.mapping.info;
+        if self.state().chunk_map.get().is_some()
+            || info
+                .chunk_layout_scans
+                .fetch_add(1, AtomicOrdering::Relaxed)
+                >= CHUNK_LAYOUT_SCAN_LIMIT
+        {
+            return calculate_chunk_layout(self);
+        }
+        if !self.is_reg() {
+            return Err(einval!("chunk layout is only available for regular files"));
+        }
+
+        // Building the chunk map needs to scan the whole chunk table, which is too expensive
+        // when only a few files are queried, so resolve chunk addresses of the file directly.
+        let state = self.state();
+        let inode = self.disk_inode(&state);
+        let base = self.offset + OndiskInodeWrapper::inode_xattr_size(inode);
+        let addrs = (0..self.get_chunk_count() as usize)
+            .map(|idx| {
+                let offset = base + idx * size_of::<RafsV6InodeChunkAddr>();
+                state
+                    .map
+                    .get_ref::<RafsV6InodeChunkAddr>(offset)
+                    .map(|v| *v)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let chunks = self.mapping.resolve_chunk_addrs(&state, &addrs)?;
+
+        Ok(chunks
+            .iter()
+            .enumerate()
+            .map(|(idx, chunk)| ChunkLocation::new(idx as u32, chunk))
+            .collect())
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -1139,16 +1512,22 @@ impl RafsInodeExt for OndiskInodeWrapper {
             + OndiskInodeWrapper::inode_xattr_size(inode)
             + (idx as usize * size_of::<RafsV6InodeChunkAddr>());
         let chunk_addr = state.map.get_ref::<RafsV6InodeChunkAddr>(offset)?;
-        let mut chunk_map = self.mapping.info.chunk_map.lock().unwrap();
-        if chunk_map.is_none() {
-            *chunk_map = Some(self.mapping.load_chunk_map()?);
-        }
-        match chunk_map.as_ref().unwrap().get(chunk_addr) {
+        match self.mapping.get_chunk_map(&state)?.get(chunk_addr) {
             None => Err(enoent!("failed to get chunk info")),
             Some(idx) => DirectChunkInfoV6::new(&state, self.mapping.clone(), *idx)
                 .map(|v| Arc::new(v) as Arc<dyn BlobChunkInfo>),
         }
     }
+
+    fn get_parent_inode(&self) -> Result<Option<Arc<dyn RafsInodeExt>>> {
+        // The parent of the root directory is itself.
+        if self.ino() == self.parent() {
+            return Ok(None);
+        }
+        self.mapping
+            .get_extended_inode(self.parent(), false)
+            .map(Some)
+    }
 }
 
 /// Impl get accessor for chunkinfo object.
@@ -1206,6 +1585,15 @@ impl DirectChunkInfoV6 {
         // Safe to unwrap() because we have validated the offset in DirectChunkInfoV6::new().
         state.map.get_ref::<RafsV5ChunkInfo>(self.offset).unwrap()
     }
+
+    // Get the RAFS v6 chunk address referring to the chunk.
+    fn chunk_addr(&self) -> RafsV6InodeChunkAddr {
+        let mut addr = RafsV6InodeChunkAddr::new();
+        addr.set_blob_index(self.blob_index());
+        addr.set_blob_ci_index(self.id());
+        addr.set_block_addr((self.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32);
+        addr
+    }
 }
 
 impl BlobChunkInfo for DirectChunkInfoV6 {
@@ -1219,9 +1607,14 @@ impl BlobChunkInfo for DirectChunkInfoV6 {
 
     fn is_compressed(&self) -> bool {
         let state = self.state();
-        self.v5_chunk(&state)
-            .flags
-            .contains(BlobChunkFlags::COMPRESSED)
+        let flags = self.v5_chunk(&state).flags;
+        // No data is stored in the data blob for zeroed chunks.
+        flags.contains(BlobChunkFlags::COMPRESSED) && !flags.contains(BlobChunkFlags::ZEROED)
+    }
+
+    fn is_zeroed(&self) -> bool {
+        let state = self.state();
+        self.v5_chunk(&state).flags.contains(BlobChunkFlags::ZEROED)
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -1244,3 +1637,476 @@ impl BlobV5ChunkInfo for DirectChunkInfoV6 {
     impl_chunkinfo_getter!(file_offset, u64);
     impl_chunkinfo_getter!(flags, BlobChunkFlags);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn store_inode(buf: &mut [u8], nid: usize, inode: &RafsV6InodeCompact, data: &[u8]) {
+        let offset = EROFS_BLOCK_SIZE as usize + nid * EROFS_INODE_SLOT_SIZE;
+        let data_offset = offset + size_of::<RafsV6InodeCompact>();
+        buf[offset..data_offset].copy_from_slice(inode.as_ref());
+        buf[data_offset..data_offset + data.len()].copy_from_slice(data);
+    }
+
+    #[test]
+    fn test_load_chunk_map_once() {
+        let sb = DirectSuperBlockV6::new(&RafsSuperMeta::default());
+        let barrier = Arc::new(std::sync::Barrier::new(16));
+        let threads = (0..16)
+            .map(|_| {
+                let sb = sb.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    let state = sb.state();
+                    let map = sb.get_chunk_map(&state).unwrap();
+                    assert!(map.is_empty());
+                    map as *const HashMap<RafsV6InodeChunkAddr, usize> as usize
+                })
+            })
+            .collect::<Vec<_>>();
+        let maps = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(maps.iter().all(|v| *v == maps[0]));
+        assert_eq!(sb.info.chunk_map_loads.load(AtomicOrdering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_get_inline_data() {
+        let block_size = EROFS_BLOCK_SIZE as usize;
+        let tail = b"inline data";
+        let mut buf = vec![0u8; block_size * 3];
+
+        // Small file with all content stored inline, nid 0.
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_FLAT_INLINE);
+        inode.set_mode(libc::S_IFREG as u16 | 0o644);
+        inode.set_nlink(1);
+        inode.set_size(tail.len() as u64);
+        store_inode(&mut buf, 0, &inode, tail);
+
+        // File with a full data block at block 2 and the tail stored inline, nid 2.
+        inode.set_size((block_size + tail.len()) as u64);
+        inode.set_u(2);
+        store_inode(&mut buf, 2, &inode, tail);
+        buf[block_size * 2..].fill(0x5a);
+
+        // Chunk based file, nid 4.
+        inode.set_data_layout(EROFS_INODE_CHUNK_BASED);
+        inode.set_size(block_size as u64);
+        inode.set_u(0);
+        store_inode(&mut buf, 4, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let inode = sb.get_inode(0, false).unwrap();
+        assert_eq!(inode.get_inline_data().unwrap().unwrap(), tail);
+        assert_eq!(inode.get_chunk_count(), 0);
+
+        let inode = sb.get_inode(2, false).unwrap();
+        let data = inode.get_inline_data().unwrap().unwrap();
+        assert_eq!(data.len(), block_size + tail.len());
+        assert!(data[..block_size].iter().all(|v| *v == 0x5a));
+        assert_eq!(&data[block_size..], tail);
+
+        let inode = sb.get_inode(4, false).unwrap();
+        assert!(inode.get_inline_data().unwrap().is_none());
+        assert_eq!(inode.get_chunk_count(), 1);
+    }
+
+    #[test]
+    fn test_get_inode_out_of_bounds() {
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_mode(libc::S_IFREG as u16 | 0o644);
+        inode.set_nlink(1);
+        let last_nid = EROFS_BLOCK_SIZE as usize / EROFS_INODE_SLOT_SIZE - 1;
+        store_inode(&mut buf, last_nid, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        assert!(sb.get_inode(last_nid as u64, false).unwrap().is_reg());
+        // Inode slot past the end of the metadata region.
+        let e = sb.get_inode(last_nid as u64 + 1, false).err().unwrap();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+        // `nid * EROFS_INODE_SLOT_SIZE` overflows.
+        let e = sb.get_inode(u64::MAX, false).err().unwrap();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+        // Adding the metadata offset overflows.
+        let nid = (usize::MAX / EROFS_INODE_SLOT_SIZE) as u64;
+        let e = sb.get_extended_inode(nid, false).err().unwrap();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_walk_empty_directory() {
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        inode.set_size(0);
+        store_inode(&mut buf, 0, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let inode = sb.get_inode(0, false).unwrap();
+        assert!(inode.is_dir());
+        assert_eq!(inode.get_child_count(), 0);
+        let mut count = 0;
+        inode
+            .walk_children_inodes(0, &mut |_, _, _, _| {
+                count += 1;
+                Ok(RafsInodeWalkAction::Continue)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_get_root_inode() {
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        store_inode(&mut buf, 2, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            root_nid: 2,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let offset = EROFS_BLOCK_SIZE as usize + 2 * EROFS_INODE_SLOT_SIZE;
+        assert_eq!(sb.info.root_inode_offset, Some(offset));
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+        assert_eq!(sb.meta_blkaddr(), 1);
+        assert_eq!(sb.root_nid(), 2);
+        assert_eq!(
+            sb.meta_blkaddr() as usize * EROFS_BLOCK_SIZE as usize
+                + sb.root_nid() as usize * EROFS_INODE_SLOT_SIZE,
+            offset
+        );
+
+        let state = sb.state.load();
+        assert_eq!(sb.inode_wrapper(&state, 2).unwrap().offset, offset);
+        let inode = sb.get_inode(2, false).unwrap();
+        assert!(inode.is_dir());
+        assert_eq!(inode.ino(), 2);
+        let inode = sb.get_extended_inode(2, false).unwrap();
+        assert_eq!(inode.name(), "/");
+
+        // The cached offset is validated against the current mapping.
+        drop(state);
+        sb.destroy();
+        assert!(sb.get_inode(2, false).is_err());
+    }
+
+    fn check_get_child_by_name(dirhash: bool) {
+        let names = ["foo", "bar", "a", "b", "zzz", "hello.txt", "c"];
+        let mut dirents = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (OsStr::new(*name), 16 + idx as u64))
+            .collect::<Vec<_>>();
+        if dirhash {
+            dirents.sort_by_key(|(name, _)| (erofs_name_hash(name), *name));
+        } else {
+            dirents.sort();
+        }
+
+        let mut data = Vec::new();
+        let mut name_data = Vec::new();
+        let file_type = RafsV6Dirent::file_type(libc::S_IFREG as u32);
+        for (name, nid) in dirents.iter() {
+            let nameoff = dirents.len() * size_of::<RafsV6Dirent>() + name_data.len();
+            let dirent = RafsV6Dirent::new(*nid, nameoff as u16, file_type);
+            data.extend_from_slice(dirent.as_ref());
+            name_data.extend_from_slice(name.as_bytes());
+        }
+        data.extend_from_slice(&name_data);
+
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_FLAT_INLINE);
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        inode.set_size(data.len() as u64);
+        store_inode(&mut buf, 0, &inode, &data);
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_mode(libc::S_IFREG as u16 | 0o644);
+        inode.set_nlink(1);
+        for (_, nid) in dirents.iter() {
+            store_inode(&mut buf, *nid as usize, &inode, &[]);
+        }
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            dirhash,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let dir = sb.get_inode(0, false).unwrap();
+        for (idx, name) in names.iter().enumerate() {
+            let child = dir.get_child_by_name(OsStr::new(name)).unwrap();
+            assert_eq!(child.ino(), 16 + idx as u64);
+            assert_eq!(child.name(), *name);
+        }
+        // Names before the first entry, after the last entry and in between.
+        for name in ["", "0", "missing", "zzzz", "~"] {
+            let err = dir.get_child_by_name(OsStr::new(name)).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_get_child_by_name_sorted_by_name() {
+        check_get_child_by_name(false);
+    }
+
+    #[test]
+    fn test_get_child_by_name_sorted_by_hash() {
+        check_get_child_by_name(true);
+    }
+
+    #[test]
+    fn test_get_child_by_name_empty_dir() {
+        let mut data = Vec::new();
+        let file_type = RafsV6Dirent::file_type(libc::S_IFDIR as u32);
+        let nameoff = 2 * size_of::<RafsV6Dirent>();
+        data.extend_from_slice(RafsV6Dirent::new(0, nameoff as u16, file_type).as_ref());
+        data.extend_from_slice(RafsV6Dirent::new(0, nameoff as u16 + 1, file_type).as_ref());
+        data.extend_from_slice(b"...");
+
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_FLAT_INLINE);
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        inode.set_size(data.len() as u64);
+        store_inode(&mut buf, 0, &inode, &data);
+        // A directory with non-zero size but without any directory entry.
+        inode.set_size(size_of::<RafsV6Dirent>() as u64);
+        store_inode(&mut buf, 4, &inode, &[0u8; size_of::<RafsV6Dirent>()]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let dir = sb.get_inode(0, false).unwrap();
+        assert_eq!(dir.get_child_count(), 0);
+        assert_eq!(dir.get_child_by_name(OsStr::new("..")).unwrap().ino(), 0);
+        for name in ["", "-", "a", "~"] {
+            let err = dir.get_child_by_name(OsStr::new(name)).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        }
+
+        let dir = sb.get_inode(4, false).unwrap();
+        let err = dir.get_child_by_name(OsStr::new("a")).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    fn check_corrupted_dir(dirents: &[(u16, &[u8])], size: usize) {
+        let mut data = Vec::new();
+        let file_type = RafsV6Dirent::file_type(libc::S_IFREG as u32);
+        for (nameoff, _) in dirents.iter() {
+            data.extend_from_slice(RafsV6Dirent::new(4, *nameoff, file_type).as_ref());
+        }
+        for (_, name) in dirents.iter() {
+            data.extend_from_slice(name);
+        }
+        data.resize(std::cmp::max(data.len(), size), 0);
+
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_FLAT_INLINE);
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        inode.set_size(size as u64);
+        store_inode(&mut buf, 0, &inode, &data);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let dir = sb.get_inode(0, false).unwrap();
+        let err = dir
+            .walk_children_inodes(0, &mut |_, _, _, _| Ok(RafsInodeWalkAction::Continue))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = dir.get_child_by_index(0).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = dir.get_child_by_name(OsStr::new("a")).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        dir.get_child_count();
+    }
+
+    #[test]
+    fn test_corrupted_dirent_name() {
+        // Name offset of the last dirent beyond the directory size.
+        check_corrupted_dir(&[(200, b"a")], 13);
+        // Name offset inside the dirent array.
+        check_corrupted_dir(&[(24, b"a"), (4, b"b")], 26);
+        // Name offset of the next dirent smaller than the current one.
+        check_corrupted_dir(&[(25, b"a"), (24, b"b")], 26);
+        // Name offset of the next dirent beyond the directory size.
+        check_corrupted_dir(&[(24, b"a"), (100, b"b")], 26);
+        // Empty name.
+        check_corrupted_dir(&[(12, b"")], 12);
+        check_corrupted_dir(&[(12, &[0u8; 4])], 16);
+        // Name longer than RAFS_MAX_NAME.
+        check_corrupted_dir(&[(12, &[b'a'; RAFS_MAX_NAME + 1])], 12 + RAFS_MAX_NAME + 1);
+    }
+
+    #[test]
+    fn test_truncated_directory() {
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_FLAT_PLAIN);
+        inode.set_mode(libc::S_IFDIR as u16 | 0o755);
+        inode.set_nlink(2);
+        // Directory data blocks beyond end of the metadata file.
+        inode.set_u(2);
+        inode.set_size(EROFS_BLOCK_SIZE * 4);
+        store_inode(&mut buf, 0, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: 0x10_0000,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+
+        let dir = sb.get_inode(0, false).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(dir.get_child_count(), 0);
+        let err = dir.get_child_by_index(0).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = dir
+            .walk_children_inodes(0, &mut |_, _, _, _| Ok(RafsInodeWalkAction::Continue))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(dir.get_child_by_name(OsStr::new("a")).is_err());
+    }
+
+    #[test]
+    fn test_alloc_bio_vecs_out_of_range() {
+        let chunk_size = 0x10_0000u64;
+        let mut buf = vec![0u8; EROFS_BLOCK_SIZE as usize * 2];
+        let mut inode = RafsV6InodeCompact::new();
+        inode.set_data_layout(EROFS_INODE_CHUNK_BASED);
+        inode.set_mode(libc::S_IFREG as u16 | 0o644);
+        inode.set_nlink(1);
+        inode.set_size(chunk_size * 2);
+        store_inode(&mut buf, 0, &inode, &[]);
+        // The chunk address array of the last inode slot has room for four chunks only.
+        inode.set_size(chunk_size * 5);
+        store_inode(&mut buf, 126, &inode, &[]);
+
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &buf).unwrap();
+        let meta = RafsSuperMeta {
+            meta_blkaddr: 1,
+            blob_table_offset: EROFS_BLOCK_SIZE,
+            chunk_size: chunk_size as u32,
+            ..Default::default()
+        };
+        let mut sb = DirectSuperBlockV6::new(&meta);
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        sb.load(&mut reader).unwrap();
+        let device = BlobDevice::default();
+
+        let inode = sb.get_inode(0, false).unwrap();
+        assert!(inode
+            .alloc_bio_vecs(&device, 0, 0, true)
+            .unwrap()
+            .is_empty());
+        let eof = chunk_size * 2;
+        assert!(inode
+            .alloc_bio_vecs(&device, eof, 4096, true)
+            .unwrap()
+            .is_empty());
+        let past_eof = chunk_size * 3 + 1;
+        assert!(inode
+            .alloc_bio_vecs(&device, past_eof, 4096, true)
+            .unwrap()
+            .is_empty());
+
+        let inode = sb.get_inode(126, false).unwrap();
+        assert_eq!(inode.get_chunk_count(), 5);
+        assert!(inode
+            .alloc_bio_vecs(&device, chunk_size * 4 + 10, 100, true)
+            .is_err());
+        assert!(inode
+            .alloc_bio_vecs(&device, 0, (chunk_size * 5) as usize, true)
+            .is_err());
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Entry points for fuzzing the RAFS bootstrap parsers.
+//!
+//! Functions in this module take untrusted, in-memory bootstrap images and exercise the metadata
+//! parsers. They should only ever return errors for malformed input, any panic found by a fuzzer
+//! indicates a bug in the parsers.
+
+use std::collections::HashSet;
+use std::io::Result;
+
+use super::layout::bytes_to_os_str;
+use super::layout::v5::RafsV5SuperBlock;
+use super::layout::v6::{RafsV6SuperBlock, RafsV6SuperBlockExt};
+use super::{RafsMode, RafsSuper, RafsVersion};
+use crate::RafsIoRead;
+
+/// Parse and validate the super block of an in-memory bootstrap image.
+///
+/// Return the detected RAFS version on success.
+pub fn parse_superblock(data: &[u8]) -> Result<RafsVersion> {
+    let size = data.len() as u64;
+    let mut r = <dyn RafsIoRead>::from_slice(data)?;
+
+    let mut sb = RafsV5SuperBlock::new();
+    if r.read_exact(sb.as_mut()).is_ok() && sb.is_rafs_v5() {
+        sb.validate(size)?;
+        return Ok(RafsVersion::V5);
+    }
+
+    r.seek_to_offset(0)?;
+    let mut sb = RafsV6SuperBlock::new();
+    sb.load(&mut r)?;
+    if !sb.is_rafs_v6() {
+        return Err(einval!("invalid RAFS super block magic"));
+    }
+    sb.validate(size)?;
+    let mut ext_sb = RafsV6SuperBlockExt::new();
+    ext_sb.load(&mut r)?;
+    ext_sb.validate(size)?;
+
+    Ok(RafsVersion::V6)
+}
+
+/// Load an in-memory bootstrap image in direct mode and walk all reachable inodes.
+///
+/// Every inode is validated, and its xattrs, symlink target, chunks and children are accessed.
+pub fn walk_all(data: &[u8]) -> Result<()> {
+    let rs = RafsSuper::load_from_slice(data, RafsMode::Direct, false)?;
+    let max_inode = rs.meta.inodes_count;
+    let chunk_size = rs.meta.chunk_size as u64;
+    let mut visited = HashSet::new();
+    let mut pending = vec![rs.superblock.root_ino()];
+
+    while let Some(ino) = pending.pop() {
+        // A corrupted image may contain directory loops.
+        if !visited.insert(ino) {
+            continue;
+        }
+
+        let inode = rs.get_extended_inode(ino, false)?;
+        inode.validate(max_inode, chunk_size)?;
+        let _ = inode.name();
+        for name in inode.get_xattrs()? {
+            inode.get_xattr(&bytes_to_os_str(&name))?;
+        }
+        if inode.is_symlink() {
+            inode.get_symlink()?;
+        } else if inode.is_reg() {
+            for idx in 0..inode.get_chunk_count() {
+                inode.get_chunk_info(idx)?;
+            }
+        } else if inode.is_dir() {
+            for idx in 0..inode.get_child_count() {
+                let child = inode.get_child_by_index(idx)?;
+                // Lookup may legitimately fail for images with unsorted entries.
+                let _ = inode.get_child_by_name(&child.name());
+                pending.push(child.ino());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn load_texture() -> Vec<u8> {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let mut path = PathBuf::from(root_dir);
+        path.push("../tests/texture/bootstrap/rafs-v5.boot");
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn test_fuzz_valid_bootstrap() {
+        let data = load_texture();
+        assert_eq!(parse_superblock(&data).unwrap(), RafsVersion::V5);
+        walk_all(&data).unwrap();
+    }
+
+    #[test]
+    fn test_fuzz_corrupted_bootstrap() {
+        let data = load_texture();
+
+        assert!(parse_superblock(&[]).is_err());
+        assert!(walk_all(&[]).is_err());
+        assert!(parse_superblock(&[0u8; 8192]).is_err());
+
+        for len in [1usize, 512, 4096, 8192, data.len() / 2, data.len() - 1] {
+            if len < data.len() {
+                let _ = parse_superblock(&data[..len]);
+                let _ = walk_all(&data[..len]);
+            }
+        }
+
+        let mut buf = data.clone();
+        let mut pos = 0usize;
+        while pos < buf.len() {
+            buf[pos] = !buf[pos];
+            let _ = walk_all(&buf);
+            buf[pos] = data[pos];
+            pos += 4093;
+        }
+    }
+}
@@ -185,6 +185,34 @@ impl InodeWrapper {
         }
     }
 
+    /// Check whether chunks of the inode have holes or are not aligned to the chunk size.
+    pub fn has_hole(&self) -> bool {
+        match self {
+            InodeWrapper::V5(i) => i.has_hole(),
+            InodeWrapper::V6(i) => i.has_hole(),
+        }
+    }
+
+    /// Set whether chunks of the inode have holes or are not aligned to the chunk size.
+    pub fn set_has_hole(&mut self, enable: bool) {
+        match self {
+            InodeWrapper::V5(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::HAS_HOLE;
+                }
+            }
+            InodeWrapper::V6(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::HAS_HOLE;
+                }
+            }
+        }
+    }
+
     /// Get inode number.
     pub fn ino(&self) -> Inode {
         match self {
@@ -507,7 +535,7 @@ pub fn new_v6_inode(
     assert!(inode.ino() <= i32::MAX as Inode);
     i.set_ino(inode.ino() as u32);
     i.set_size(inode.size());
-    i.set_uidgid(inode.uid(), inode.gid());
+    i.set_ugid(inode.uid(), inode.gid());
     i.set_mtime(inode.mtime(), inode.mtime_nsec());
     i.set_nlink(inode.nlink());
     i.set_mode(inode.mode() as u16);
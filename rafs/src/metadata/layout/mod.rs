@@ -5,12 +5,16 @@
 
 //! Rafs filesystem metadata layout and data structures.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display};
 use std::io::Result;
 use std::mem::size_of;
-use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 
 use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
 use nydus_utils::ByteSize;
@@ -110,6 +114,31 @@ macro_rules! impl_pub_getter_setter {
     };
 }
 
+/// Size of the area to store version of the builder in superblocks.
+pub const RAFS_BUILDER_VERSION_SIZE: usize = 64;
+
+/// Parse version of the builder from a NUL padded byte slice.
+///
+/// Returns `None` if the area is empty or contains invalid data, for metadata blobs generated by
+/// old builders.
+pub(crate) fn parse_builder_version(buf: &[u8]) -> Option<String> {
+    let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    match std::str::from_utf8(&buf[..len]) {
+        Ok(v) if !v.is_empty() => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// Fill version of the builder into a NUL padded byte slice, truncating it if it's too long.
+pub(crate) fn fill_builder_version(buf: &mut [u8], version: &str) {
+    let mut len = std::cmp::min(version.len(), buf.len());
+    while !version.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.fill(0);
+    buf[..len].copy_from_slice(&version.as_bytes()[..len]);
+}
+
 /// Parse a utf8 byte slice into two strings.
 pub fn parse_string(buf: &[u8]) -> Result<(&str, &str)> {
     std::str::from_utf8(buf)
@@ -124,9 +153,163 @@ pub fn parse_string(buf: &[u8]) -> Result<(&str, &str)> {
         .map_err(|e| einval!(format!("failed in parsing string, {:?}", e)))
 }
 
+// Helpers to convert file names between raw bytes in RAFS metadata and platform strings, so name
+// handling doesn't depend on unix-only APIs. Other parts of the crate still require unix.
+
 /// Convert a byte slice into OsStr.
-pub fn bytes_to_os_str(buf: &[u8]) -> &OsStr {
-    OsStr::from_bytes(buf)
+#[cfg(unix)]
+pub fn bytes_to_os_str(buf: &[u8]) -> Cow<OsStr> {
+    Cow::Borrowed(OsStr::from_bytes(buf))
+}
+
+/// Convert a byte slice into OsStr.
+///
+/// Names are expected to be UTF-8 encoded on non-unix platforms, invalid UTF-8 sequences are
+/// replaced with `U+FFFD`.
+#[cfg(not(unix))]
+pub fn bytes_to_os_str(buf: &[u8]) -> Cow<OsStr> {
+    match String::from_utf8_lossy(buf) {
+        Cow::Borrowed(v) => Cow::Borrowed(OsStr::new(v)),
+        Cow::Owned(v) => Cow::Owned(OsString::from(v)),
+    }
+}
+
+/// Convert a byte vector into OsString.
+#[cfg(unix)]
+pub fn bytes_to_os_string(buf: Vec<u8>) -> OsString {
+    OsString::from_vec(buf)
+}
+
+/// Convert a byte vector into OsString, invalid UTF-8 sequences are replaced with `U+FFFD`.
+#[cfg(not(unix))]
+pub fn bytes_to_os_string(buf: Vec<u8>) -> OsString {
+    match String::from_utf8(buf) {
+        Ok(s) => OsString::from(s),
+        Err(e) => OsString::from(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+/// Convert an OsStr into a byte slice.
+#[cfg(unix)]
+pub fn os_str_to_bytes(s: &OsStr) -> Cow<[u8]> {
+    Cow::Borrowed(s.as_bytes())
+}
+
+/// Convert an OsStr into a byte slice, invalid unicode sequences are replaced with `U+FFFD`.
+#[cfg(not(unix))]
+pub fn os_str_to_bytes(s: &OsStr) -> Cow<[u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(v) => Cow::Borrowed(v.as_bytes()),
+        Cow::Owned(v) => Cow::Owned(v.into_bytes()),
+    }
+}
+
+/// Convert an OsString into a byte vector.
+#[cfg(unix)]
+pub fn os_string_to_bytes(s: OsString) -> Vec<u8> {
+    s.into_vec()
+}
+
+/// Convert an OsString into a byte vector, invalid unicode sequences are replaced with `U+FFFD`.
+#[cfg(not(unix))]
+pub fn os_string_to_bytes(s: OsString) -> Vec<u8> {
+    match s.into_string() {
+        Ok(v) => v.into_bytes(),
+        Err(v) => v.to_string_lossy().into_owned().into_bytes(),
+    }
+}
+
+/// Portable path of files in RAFS filesystems.
+///
+/// Paths are byte strings separated by `/` in RAFS metadata, independent of the platform where
+/// the metadata is analyzed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RafsPath(Vec<u8>);
+
+impl RafsPath {
+    /// Path separator of RAFS filesystems.
+    pub const SEPARATOR: u8 = b'/';
+
+    /// Create a `RafsPath` object from raw bytes.
+    pub fn from_bytes<T: Into<Vec<u8>>>(buf: T) -> Self {
+        RafsPath(buf.into())
+    }
+
+    /// Create a `RafsPath` object from a local path, `\` separators are converted on Windows.
+    pub fn from_path(path: &Path) -> Self {
+        let buf = os_str_to_bytes(path.as_os_str()).into_owned();
+        #[cfg(windows)]
+        let buf = buf
+            .into_iter()
+            .map(|c| if c == b'\\' { Self::SEPARATOR } else { c })
+            .collect();
+        RafsPath(buf)
+    }
+
+    /// Get raw bytes of the path.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Convert into raw bytes of the path.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Check whether it's an absolute path.
+    pub fn is_absolute(&self) -> bool {
+        self.0.first() == Some(&Self::SEPARATOR)
+    }
+
+    /// Get an iterator over non-empty components of the path.
+    pub fn components(&self) -> impl Iterator<Item = &[u8]> {
+        self.0
+            .split(|c| *c == Self::SEPARATOR)
+            .filter(|c| !c.is_empty())
+    }
+
+    /// Get the last component of the path.
+    pub fn file_name(&self) -> Option<Cow<OsStr>> {
+        self.components().last().map(bytes_to_os_str)
+    }
+
+    /// Create a new path by appending `name` to the path.
+    pub fn join(&self, name: &[u8]) -> Self {
+        let mut buf = self.0.clone();
+        if buf.last() != Some(&Self::SEPARATOR) {
+            buf.push(Self::SEPARATOR);
+        }
+        buf.extend_from_slice(name);
+        RafsPath(buf)
+    }
+
+    /// Convert to an OsString.
+    pub fn to_os_string(&self) -> OsString {
+        bytes_to_os_string(self.0.clone())
+    }
+
+    /// Convert to a local path.
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf::from(self.to_os_string())
+    }
+}
+
+impl From<&OsStr> for RafsPath {
+    fn from(s: &OsStr) -> Self {
+        RafsPath(os_str_to_bytes(s).into_owned())
+    }
+}
+
+impl From<&Path> for RafsPath {
+    fn from(path: &Path) -> Self {
+        Self::from_path(path)
+    }
+}
+
+impl Display for RafsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
 }
 
 /// Parse a byte slice into xattr pairs and invoke the callback for each xattr pair.
@@ -135,6 +318,16 @@ pub fn bytes_to_os_str(buf: &[u8]) -> &OsStr {
 pub fn parse_xattr<F>(data: &[u8], size: usize, mut cb: F) -> Result<()>
 where
     F: FnMut(&OsStr, XattrValue) -> bool,
+{
+    parse_xattr_pairs(data, size, |name, value| {
+        cb(&bytes_to_os_str(name), value.to_vec())
+    })
+}
+
+// Parse a byte slice into xattr pairs without copying, and invoke the callback for each pair.
+fn parse_xattr_pairs<F>(data: &[u8], size: usize, mut cb: F) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8]) -> bool,
 {
     if data.len() < size {
         return Err(einval!("invalid xattr content size"));
@@ -167,9 +360,7 @@ where
         let (pair, rest) = rest.split_at(pair_size);
         if let Some(pos) = pair.iter().position(|&c| c == 0) {
             let (name, value) = pair.split_at(pos);
-            let name = OsStr::from_bytes(name);
-            let value = value[1..].to_vec();
-            if !cb(name, value) {
+            if !cb(name, &value[1..]) {
                 break;
             }
         }
@@ -186,13 +377,25 @@ pub fn parse_xattr_names(data: &[u8], size: usize) -> Result<Vec<XattrName>> {
     let mut result = Vec::new();
 
     parse_xattr(data, size, |name, _| {
-        result.push(name.as_bytes().to_vec());
+        result.push(os_str_to_bytes(name).into_owned());
         true
     })?;
 
     Ok(result)
 }
 
+/// Count xattr pairs in a byte slice without allocating memory.
+pub fn parse_xattr_count(data: &[u8], size: usize) -> Result<u32> {
+    let mut count = 0;
+
+    parse_xattr_pairs(data, size, |_, _| {
+        count += 1;
+        true
+    })?;
+
+    Ok(count)
+}
+
 /// Parse a 'buf' to xattr value by xattr name.
 pub fn parse_xattr_value(data: &[u8], size: usize, name: &OsStr) -> Result<Option<XattrValue>> {
     let mut value = None;
@@ -222,17 +425,18 @@ pub const RAFS_XATTR_PREFIXES: [&str; 5] = [
 
 /// Rafs inode extended attributes.
 ///
-/// An extended attribute is a (String, String) pair associated with a inode.
+/// An extended attribute is a (String, String) pair associated with a inode. Pairs are kept in
+/// bytewise order of keys, so they are always stored in the same order.
 #[derive(Clone, Default)]
 pub struct RafsXAttrs {
-    pairs: HashMap<OsString, XattrValue>,
+    pairs: BTreeMap<OsString, XattrValue>,
 }
 
 impl RafsXAttrs {
     /// Create a new instance of `RafsXattrs`.
     pub fn new() -> Self {
         Self {
-            pairs: HashMap::new(),
+            pairs: BTreeMap::new(),
         }
     }
 
@@ -255,7 +459,7 @@ impl RafsXAttrs {
 
     /// Add or update an extended attribute.
     pub fn add(&mut self, name: OsString, value: XattrValue) -> Result<()> {
-        let buf = name.as_bytes();
+        let buf = os_str_to_bytes(&name);
         if buf.len() > 255 || value.len() > 0x10000 {
             return Err(einval!("xattr key/value is too big"));
         }
@@ -277,6 +481,19 @@ impl RafsXAttrs {
     pub fn is_empty(&self) -> bool {
         self.pairs.is_empty()
     }
+
+    /// Get number of extended attributes.
+    pub fn count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Get total size of names and values of the extended attributes.
+    pub fn total_bytes(&self) -> usize {
+        self.pairs
+            .iter()
+            .map(|(key, value)| key.byte_size() + value.len())
+            .sum()
+    }
 }
 
 pub(crate) struct MetaRange {
@@ -376,6 +593,33 @@ mod tests {
         parse_string(&[0xffu8, 0xffu8, 0xffu8, 0xffu8, 0xffu8]).unwrap_err();
     }
 
+    #[test]
+    fn test_builder_version() {
+        let mut buf = [0u8; RAFS_BUILDER_VERSION_SIZE];
+        assert_eq!(parse_builder_version(&buf), None);
+
+        fill_builder_version(&mut buf, "v2.1.0-rc.1");
+        assert_eq!(parse_builder_version(&buf).unwrap(), "v2.1.0-rc.1");
+
+        let long = "v".repeat(RAFS_BUILDER_VERSION_SIZE + 10);
+        fill_builder_version(&mut buf, &long);
+        assert_eq!(
+            parse_builder_version(&buf).unwrap(),
+            long[..RAFS_BUILDER_VERSION_SIZE]
+        );
+
+        // Never split a multi-byte character.
+        let long = format!("{}\u{e9}", "v".repeat(RAFS_BUILDER_VERSION_SIZE - 1));
+        fill_builder_version(&mut buf, &long);
+        assert_eq!(
+            parse_builder_version(&buf).unwrap(),
+            long[..RAFS_BUILDER_VERSION_SIZE - 1]
+        );
+
+        buf[0] = 0xff;
+        assert_eq!(parse_builder_version(&buf), None);
+    }
+
     #[test]
     fn test_parse_xattrs() {
         let buf = [0x4u8, 0x0, 0x0, 0x0, b'a', 0, b'b'];
@@ -387,11 +631,46 @@ mod tests {
         let names = parse_xattr_names(&buf, 7).unwrap();
         assert_eq!(names.len(), 1);
         assert_eq!(names[0], &[b'a']);
+        assert_eq!(parse_xattr_count(&buf, 7).unwrap(), 1);
+        assert_eq!(parse_xattr_count(&buf, 0).unwrap(), 0);
+        parse_xattr_count(&buf, 8).unwrap_err();
 
         let value = parse_xattr_value(&buf, 7, &OsString::from("a")).unwrap();
         assert_eq!(value, Some(vec![b'b']));
     }
 
+    #[test]
+    fn test_rafs_path() {
+        let path = RafsPath::from_path(Path::new("/a/b//c"));
+        assert!(path.is_absolute());
+        assert_eq!(path.as_bytes(), b"/a/b//c");
+        assert_eq!(
+            path.components().collect::<Vec<_>>(),
+            vec![&b"a"[..], &b"b"[..], &b"c"[..]]
+        );
+        assert_eq!(path.file_name().as_deref(), Some(OsStr::new("c")));
+        assert_eq!(path.join(b"d").to_string(), "/a/b//c/d");
+        assert_eq!(RafsPath::from_bytes("/").join(b"d").as_bytes(), b"/d");
+        assert_eq!(path.to_path_buf(), PathBuf::from("/a/b//c"));
+        assert_eq!(RafsPath::from(OsStr::new("x")).into_bytes(), b"x".to_vec());
+
+        let path = RafsPath::from_bytes(vec![b'a', 0xff]);
+        assert!(!path.is_absolute());
+        assert_eq!(path.to_string(), "a\u{fffd}");
+        assert_eq!(
+            os_string_to_bytes(bytes_to_os_string(b"abc".to_vec())),
+            b"abc"
+        );
+        assert_eq!(os_str_to_bytes(&bytes_to_os_str(b"abc")).as_ref(), b"abc");
+
+        // Only invalid sequences are replaced on non-unix platforms.
+        let name = bytes_to_os_str(&[b'a', 0xff, b'b']);
+        #[cfg(unix)]
+        assert_eq!(os_str_to_bytes(&name).as_ref(), &[b'a', 0xff, b'b']);
+        #[cfg(not(unix))]
+        assert_eq!(name, OsStr::new("a\u{fffd}b"));
+    }
+
     #[test]
     fn test_meta_range() {
         assert!(MetaRange::new(u64::MAX, 1, true).is_err());
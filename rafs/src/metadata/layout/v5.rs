@@ -39,8 +39,7 @@ use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::io::{Read, Result};
 use std::mem::size_of;
-use std::ops::Deref;
-use std::os::unix::ffi::OsStrExt;
+use std::ops::{Deref, Range};
 use std::sync::Arc;
 
 use nydus_utils::digest::{self, DigestHasher, RafsDigest};
@@ -54,10 +53,14 @@ use nydus_storage::device::{
     BlobChunkFlags, BlobChunkInfo, BlobFeatures, BlobInfo, BlobIoDesc, BlobIoVec,
 };
 
-use crate::metadata::layout::{bytes_to_os_str, MetaRange, RafsXAttrs, RAFS_SUPER_VERSION_V5};
+use crate::metadata::layout::{
+    bytes_to_os_str, fill_builder_version, os_str_to_bytes, os_string_to_bytes,
+    parse_builder_version, MetaRange, RafsXAttrs, RAFS_BUILDER_VERSION_SIZE, RAFS_SUPER_VERSION_V5,
+};
 use crate::metadata::md_v5::V5IoChunk;
 use crate::metadata::{
     Inode, RafsInode, RafsStore, RafsSuperFlags, RAFS_DEFAULT_CHUNK_SIZE, RAFS_MAX_CHUNK_SIZE,
+    RAFS_MAX_PROVENANCE_SIZE,
 };
 use crate::{
     impl_bootstrap_converter, impl_pub_getter_setter, RafsInodeExt, RafsIoReader, RafsIoWrite,
@@ -68,7 +71,8 @@ pub(crate) const RAFSV5_SUPERBLOCK_SIZE: usize = 8192;
 pub(crate) const RAFSV5_EXT_BLOB_ENTRY_SIZE: usize = 64;
 
 const RAFSV5_SUPER_MAGIC: u32 = 0x5241_4653;
-const RAFSV5_SUPERBLOCK_RESERVED_SIZE: usize = RAFSV5_SUPERBLOCK_SIZE - 80;
+const RAFSV5_SUPERBLOCK_RESERVED_SIZE: usize =
+    RAFSV5_SUPERBLOCK_SIZE - 112 - RAFS_BUILDER_VERSION_SIZE;
 const RAFSV5_EXT_BLOB_RESERVED_SIZE: usize = RAFSV5_EXT_BLOB_ENTRY_SIZE - 24;
 
 /// Trait to get information about a Rafs v5 inode.
@@ -121,6 +125,16 @@ pub struct RafsV5SuperBlock {
     s_extended_blob_table_entries: u32, // 72 bytes
     /// Extended Blob Table
     s_extended_blob_table_offset: u64, // 80 bytes --- reduce me from `RAFS_SUPERBLOCK_RESERVED_SIZE`
+    /// Version of the builder, NUL padded.
+    s_builder_version: [u8; RAFS_BUILDER_VERSION_SIZE], // 144 bytes
+    /// Number of extended attribute entries, zero if not recorded by the builder.
+    s_xattr_entry_count: u64,
+    /// Total size of extended attribute names and values.
+    s_xattr_total_bytes: u64, // 160 bytes
+    /// Offset of the build provenance record, zero if not recorded by the builder.
+    s_provenance_offset: u64,
+    /// Size of the build provenance record.
+    s_provenance_size: u64, // 176 bytes
     /// Unused area
     s_reserved: [u8; RAFSV5_SUPERBLOCK_RESERVED_SIZE],
 }
@@ -210,6 +224,19 @@ impl RafsV5SuperBlock {
             return Err(einval!("invalid prefetch table offset or size."));
         }
 
+        let provenance_size = self.provenance_size();
+        if provenance_size != 0 {
+            let provenance_range =
+                MetaRange::new(self.provenance_offset(), provenance_size, false)?;
+            if provenance_size > RAFS_MAX_PROVENANCE_SIZE
+                || !provenance_range.is_subrange_of(&meta_range)
+                || provenance_range.intersect_with(&inode_table_range)
+                || provenance_range.intersect_with(&blob_table_range)
+            {
+                return Err(einval!("invalid provenance record offset or size."));
+            }
+        }
+
         Ok(())
     }
 
@@ -249,8 +276,37 @@ impl RafsV5SuperBlock {
         self.s_flags |= RafsSuperFlags::HAS_XATTR.bits();
     }
 
+    /// Get version of the builder which generated the metadata blob.
+    pub fn builder_version(&self) -> Option<String> {
+        parse_builder_version(&self.s_builder_version)
+    }
+
+    /// Set version of the builder which generates the metadata blob.
+    pub fn set_builder_version(&mut self, version: &str) {
+        fill_builder_version(&mut self.s_builder_version, version);
+    }
+
     impl_pub_getter_setter!(magic, set_magic, s_magic, u32);
     impl_pub_getter_setter!(version, set_version, s_fs_version, u32);
+    impl_pub_getter_setter!(
+        xattr_entry_count,
+        set_xattr_entry_count,
+        s_xattr_entry_count,
+        u64
+    );
+    impl_pub_getter_setter!(
+        xattr_total_bytes,
+        set_xattr_total_bytes,
+        s_xattr_total_bytes,
+        u64
+    );
+    impl_pub_getter_setter!(
+        provenance_offset,
+        set_provenance_offset,
+        s_provenance_offset,
+        u64
+    );
+    impl_pub_getter_setter!(provenance_size, set_provenance_size, s_provenance_size, u64);
     impl_pub_getter_setter!(sb_size, set_sb_size, s_sb_size, u32);
     impl_pub_getter_setter!(block_size, set_block_size, s_block_size, u32);
     impl_pub_getter_setter!(flags, set_flags, s_flags, u64);
@@ -340,6 +396,11 @@ impl Default for RafsV5SuperBlock {
             s_blob_table_offset: u64::to_le(0),
             s_extended_blob_table_offset: u64::to_le(0),
             s_extended_blob_table_entries: u32::to_le(0),
+            s_builder_version: [0u8; RAFS_BUILDER_VERSION_SIZE],
+            s_xattr_entry_count: u64::to_le(0),
+            s_xattr_total_bytes: u64::to_le(0),
+            s_provenance_offset: u64::to_le(0),
+            s_provenance_size: u64::to_le(0),
             s_reserved: [0u8; RAFSV5_SUPERBLOCK_RESERVED_SIZE],
         }
     }
@@ -850,7 +911,7 @@ bitflags! {
         const HARDLINK = 0x0000_0002;
         /// Inode has extended attributes.
         const XATTR = 0x0000_0004;
-        /// Inode chunks has holes.
+        /// Inode chunks has holes, or are not aligned to the chunk size.
         const HAS_HOLE = 0x0000_0008;
    }
 }
@@ -1032,16 +1093,16 @@ impl<'a> RafsStore for RafsV5InodeWrapper<'a> {
         w.write_all(inode_data)?;
         size += inode_data.len();
 
-        let name = self.name.as_bytes();
-        w.write_all(name)?;
+        let name = os_str_to_bytes(self.name);
+        w.write_all(&name)?;
         size += name.len();
         let padding = rafsv5_align(self.inode.i_name_size as usize) - name.len();
         w.write_padding(padding)?;
         size += padding;
 
         if let Some(symlink) = self.symlink {
-            let symlink_path = symlink.as_bytes();
-            w.write_all(symlink_path)?;
+            let symlink_path = os_str_to_bytes(symlink);
+            w.write_all(&symlink_path)?;
             size += symlink_path.len();
             let padding = rafsv5_align(self.inode.i_symlink_size as usize) - symlink_path.len();
             w.write_padding(padding)?;
@@ -1171,8 +1232,8 @@ impl RafsXAttrs {
                 w.write_all(&pair_size_data)?;
                 size += pair_size_data.len();
 
-                let key_data = key.as_bytes();
-                w.write_all(key_data)?;
+                let key_data = os_str_to_bytes(key);
+                w.write_all(&key_data)?;
                 w.write_all(&[0u8])?;
                 size += key_data.len() + 1;
 
@@ -1202,13 +1263,16 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
     let end = offset
         .checked_add(size as u64)
         .ok_or_else(|| einval!("invalid read size"))?;
-    let (index_start, index_end) = calculate_bio_chunk_index(
-        offset,
-        end,
-        inode.get_chunk_size() as u64,
-        inode.get_child_count(),
-        inode.has_hole(),
-    );
+    let (index_start, index_end) = if inode.has_hole() {
+        locate_bio_chunk_index(inode, offset, end, inode.get_child_count())?
+    } else {
+        calculate_bio_chunk_index(
+            offset,
+            end,
+            inode.get_chunk_size() as u64,
+            inode.get_child_count(),
+        )
+    };
     trace!(
         "alloc bio desc offset {} size {} i_size {} index_start {} index_end {} i_child_count {}",
         offset,
@@ -1218,7 +1282,7 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
         index_end,
         inode.get_child_count()
     );
-    if size == 0 || index_start >= inode.get_chunk_count() {
+    if size == 0 || index_start >= inode.get_chunk_count() || index_start >= index_end {
         return Ok(vec![]);
     }
 
@@ -1246,6 +1310,31 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
     Ok(descs)
 }
 
+/// Get indexes of chunks covering the IO range [offset, offset + size).
+pub(crate) fn rafsv5_get_chunk_range<I: RafsInode + RafsV5InodeChunkOps + RafsV5InodeOps>(
+    inode: &I,
+    offset: u64,
+    size: usize,
+) -> Result<Range<u32>> {
+    let end = offset
+        .checked_add(size as u64)
+        .ok_or_else(|| einval!("invalid read size"))?;
+    let end = cmp::min(end, inode.size());
+    let chunk_cnt = inode.get_chunk_count();
+    if offset >= end || chunk_cnt == 0 {
+        return Ok(0..0);
+    }
+
+    let (index_start, index_end) = if inode.has_hole() {
+        locate_bio_chunk_index(inode, offset, end, chunk_cnt)?
+    } else {
+        calculate_bio_chunk_index(offset, end, inode.get_chunk_size() as u64, chunk_cnt)
+    };
+    let index_start = cmp::min(index_start, chunk_cnt);
+
+    Ok(index_start..cmp::max(index_start, index_end))
+}
+
 /// Add a new bio covering the IO range into the provided bio desc.
 ///
 /// Returns true if caller should continue checking more chunks.
@@ -1315,28 +1404,57 @@ fn add_chunk_to_bio_desc(
 /// - end: IO end to the file start, exclusive.
 /// - chunk_size: chunk size.
 /// - chunk_cnt: maximum number of chunks
-/// - has_hole: whether a file has holes in it.
-fn calculate_bio_chunk_index(
+fn calculate_bio_chunk_index(offset: u64, end: u64, chunk_size: u64, chunk_cnt: u32) -> (u32, u32) {
+    debug_assert!(offset < end);
+
+    let index_start = (offset / chunk_size) as u32;
+    let index_end = cmp::min(((end - 1) / chunk_size) as u32 + 1, chunk_cnt);
+
+    (index_start, index_end)
+}
+
+/// Locate chunks overlapping with the provided IO range for inodes with irregular chunk layout.
+///
+/// Chunks of files with holes or chunked by content-defined chunking may not be aligned to the
+/// chunk size, so binary search chunks by file offset instead.
+///
+/// # Parameters
+/// - offset: IO offset to the file start, inclusive.
+/// - end: IO end to the file start, exclusive.
+/// - chunk_cnt: number of chunks of the inode.
+fn locate_bio_chunk_index<I: RafsV5InodeChunkOps + ?Sized>(
+    inode: &I,
     offset: u64,
     end: u64,
-    chunk_size: u64,
     chunk_cnt: u32,
-    has_hole: bool,
-) -> (u32, u32) {
+) -> Result<(u32, u32)> {
     debug_assert!(offset < end);
 
-    let index_start = if !has_hole {
-        (offset / chunk_size) as u32
-    } else {
-        0
-    };
-    let index_end = if !has_hole {
-        cmp::min(((end - 1) / chunk_size) as u32 + 1, chunk_cnt)
-    } else {
-        chunk_cnt
-    };
+    // Find the first chunk ending after `offset`.
+    let (mut start, mut high) = (0, chunk_cnt);
+    while start < high {
+        let mid = start + (high - start) / 2;
+        let chunk = inode.get_chunk_info_v5(mid)?;
+        if chunk.file_offset() + chunk.uncompressed_size() as u64 <= offset {
+            start = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
 
-    (index_start, index_end)
+    // Find the first chunk starting at or after `end`.
+    let (mut index_end, mut high) = (start, chunk_cnt);
+    while index_end < high {
+        let mid = index_end + (high - index_end) / 2;
+        let chunk = inode.get_chunk_info_v5(mid)?;
+        if chunk.file_offset() < end {
+            index_end = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok((start, index_end))
 }
 
 pub(crate) fn rafsv5_align(size: usize) -> usize {
@@ -1361,7 +1479,7 @@ pub(crate) fn rafsv5_validate_inode(
     let mut hasher = RafsDigest::hasher(digester);
 
     if inode.is_symlink() {
-        hasher.digest_update(inode.get_symlink()?.as_bytes());
+        hasher.digest_update(&os_string_to_bytes(inode.get_symlink()?));
     } else if inode.is_reg() {
         for idx in 0..child_count {
             let chunk = inode.get_chunk_info(idx)?;
@@ -1705,7 +1823,6 @@ pub mod tests {
                 *io_start + *io_size,
                 blksize,
                 chunk_cnt as u32,
-                false,
             );
 
             assert_eq!(start, *expected_start);
@@ -1713,6 +1830,47 @@ pub mod tests {
         }
     }
 
+    struct MockChunks(Vec<MockChunkInfo>);
+
+    impl RafsV5InodeChunkOps for MockChunks {
+        fn get_chunk_info_v5(&self, idx: u32) -> Result<Arc<dyn BlobV5ChunkInfo>> {
+            Ok(Arc::new(self.0[idx as usize]))
+        }
+    }
+
+    #[test]
+    fn test_locate_bio_chunk_index() {
+        // Chunks [0, 100), [100, 400), hole [400, 1000), [1000, 1050)
+        let mut chunks = Vec::new();
+        for (file_offset, size) in [(0u64, 100u32), (100, 300), (1000, 50)].iter() {
+            let mut chunk = MockChunkInfo::new();
+            chunk.file_offset = *file_offset;
+            chunk.uncompress_size = *size;
+            chunks.push(chunk);
+        }
+        let inode = MockChunks(chunks);
+
+        let io_range: Vec<(u64, u64, u32, u32)> = vec![
+            (0, 1, 0, 1),
+            (0, 100, 0, 1),
+            (0, 101, 0, 2),
+            (99, 102, 0, 2),
+            (100, 400, 1, 2),
+            (399, 1001, 1, 3),
+            (400, 1000, 2, 2),
+            (500, 600, 2, 2),
+            (1000, 1050, 2, 3),
+            (1049, 2000, 2, 3),
+            (1050, 2000, 3, 3),
+        ];
+
+        for (io_start, io_end, expected_start, expected_end) in io_range.iter() {
+            let (start, end) = locate_bio_chunk_index(&inode, *io_start, *io_end, 3).unwrap();
+            assert_eq!(start, *expected_start);
+            assert_eq!(end, *expected_end);
+        }
+    }
+
     #[test]
     fn test_rafsv5_align() {
         assert_eq!(rafsv5_align(0), 0);
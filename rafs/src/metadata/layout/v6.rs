@@ -5,26 +5,30 @@
 
 use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
-use std::fmt::Debug;
-use std::io::{Read, Result};
+use std::fmt::{self, Debug};
+use std::io::{Error, Read, Result};
 use std::mem::size_of;
-use std::os::unix::ffi::OsStrExt;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use lazy_static::lazy_static;
-use nydus_storage::device::{BlobFeatures, BlobInfo};
+use nydus_storage::device::{BlobFeatures, BlobInfo, BlobInfoSource};
 use nydus_storage::meta::{
     BlobChunkInfoV1Ondisk, BlobChunkInfoV2Ondisk, BlobMetaHeaderOndisk, ZranInflateContext,
     BLOB_META_FEATURE_4K_ALIGNED, BLOB_META_FEATURE_CHUNK_INFO_V2, BLOB_META_FEATURE_MASK,
     BLOB_META_FEATURE_SEPARATE, BLOB_META_FEATURE_ZRAN,
 };
 use nydus_storage::{RAFS_MAX_CHUNKS_PER_BLOB, RAFS_MAX_CHUNK_SIZE};
+use nydus_utils::crypt::{self, KEY_REF_LEN, WRAPPED_KEY_LEN};
 use nydus_utils::{compress, digest, round_up, ByteSize};
+use once_cell::sync::OnceCell;
 
 use crate::metadata::layout::v5::RafsV5ChunkInfo;
-use crate::metadata::layout::MetaRange;
-use crate::metadata::{layout::RafsXAttrs, RafsStore, RafsSuperFlags};
+use crate::metadata::layout::{
+    fill_builder_version, os_str_to_bytes, parse_builder_version, MetaRange,
+    RAFS_BUILDER_VERSION_SIZE,
+};
+use crate::metadata::{layout::RafsXAttrs, RafsStore, RafsSuperFlags, RAFS_MAX_PROVENANCE_SIZE};
 use crate::{impl_bootstrap_converter, impl_pub_getter_setter, RafsIoReader, RafsIoWrite};
 
 /// EROFS metadata slot size.
@@ -74,6 +78,8 @@ const EROFS_FEATURE_COMPAT_RAFS_V6: u32 = 0x4000_0000;
 const EROFS_FEATURE_INCOMPAT_CHUNKED_FILE: u32 = 0x0000_0004;
 /// Multi-devices, incompatible with EROFS versions prior to Linux kernel 5.16.
 const EROFS_FEATURE_INCOMPAT_DEVICE_TABLE: u32 = 0x0000_0008;
+/// Directory entries are sorted by name hash instead of name, see [erofs_name_hash].
+pub const EROFS_FEATURE_INCOMPAT_DIRHASH: u32 = 0x0000_0080;
 /// Size of SHA256 digest string.
 const BLOB_SHA256_LEN: usize = 64;
 const BLOB_MAX_SIZE_UNCOMPRESSED: u64 = 1u64 << 44;
@@ -222,7 +228,7 @@ impl RafsV6SuperBlock {
         //     return Err(einval!("invalid build time in Rafsv6 superblock"));
         // }
 
-        if u32::from_le(self.s_feature_incompat)
+        if u32::from_le(self.s_feature_incompat) & !EROFS_FEATURE_INCOMPAT_DIRHASH
             != EROFS_FEATURE_INCOMPAT_CHUNKED_FILE | EROFS_FEATURE_INCOMPAT_DEVICE_TABLE
         {
             return Err(einval!(
@@ -277,6 +283,22 @@ impl RafsV6SuperBlock {
         self.s_extra_devices = count.to_le();
     }
 
+    /// Check whether directory entries are sorted by name hash.
+    pub fn has_dirhash(&self) -> bool {
+        u32::from_le(self.s_feature_incompat) & EROFS_FEATURE_INCOMPAT_DIRHASH != 0
+    }
+
+    /// Enable or disable ordering directory entries by name hash.
+    pub fn set_dirhash(&mut self, enable: bool) {
+        let mut features = u32::from_le(self.s_feature_incompat);
+        if enable {
+            features |= EROFS_FEATURE_INCOMPAT_DIRHASH;
+        } else {
+            features &= !EROFS_FEATURE_INCOMPAT_DIRHASH;
+        }
+        self.s_feature_incompat = features.to_le();
+    }
+
     impl_pub_getter_setter!(magic, set_magic, s_magic, u32);
 }
 
@@ -344,8 +366,18 @@ pub struct RafsV6SuperBlockExt {
     s_prefetch_table_offset: u64,
     s_prefetch_table_size: u32,
     s_padding: u32,
+    /// Version of the builder, NUL padded.
+    s_builder_version: [u8; RAFS_BUILDER_VERSION_SIZE],
+    /// Number of extended attribute entries, zero if not recorded by the builder.
+    s_xattr_entry_count: u64,
+    /// Total size of extended attribute names and values.
+    s_xattr_total_bytes: u64,
+    /// Offset of the build provenance record, zero if not recorded by the builder.
+    s_provenance_offset: u64,
+    /// Size of the build provenance record.
+    s_provenance_size: u64,
     /// Reserved
-    s_reserved: [u8; 200],
+    s_reserved: [u8; 104],
 }
 
 impl_bootstrap_converter!(RafsV6SuperBlockExt);
@@ -457,6 +489,20 @@ impl RafsV6SuperBlockExt {
             }
         }
 
+        let provenance_offset = self.provenance_offset();
+        let provenance_size = self.provenance_size();
+        if provenance_size > 0
+            && (provenance_offset < EROFS_BLOCK_SIZE
+                || provenance_size > RAFS_MAX_PROVENANCE_SIZE
+                || provenance_offset.checked_add(provenance_size).is_none()
+                || provenance_offset + provenance_size > meta_size)
+        {
+            return Err(einval!(format!(
+                "invalid provenance offset 0x{:x}/size 0x{:x} in Rafs v6 extended superblock",
+                provenance_offset, provenance_size
+            )));
+        }
+
         Ok(())
     }
 
@@ -476,11 +522,26 @@ impl RafsV6SuperBlockExt {
         self.s_flags |= RafsSuperFlags::HAS_XATTR.bits();
     }
 
+    /// Set the `encrypted` flag for the RAFS filesystem.
+    pub fn set_encrypted(&mut self) {
+        self.s_flags |= RafsSuperFlags::ENCRYPTION_AES256_GCM.bits();
+    }
+
     /// Enable explicit Uid/Gid feature.
     pub fn set_explicit_uidgid(&mut self) {
         self.s_flags |= RafsSuperFlags::EXPLICIT_UID_GID.bits();
     }
 
+    /// Get version of the builder which generated the metadata blob.
+    pub fn builder_version(&self) -> Option<String> {
+        parse_builder_version(&self.s_builder_version)
+    }
+
+    /// Set version of the builder which generates the metadata blob.
+    pub fn set_builder_version(&mut self, version: &str) {
+        fill_builder_version(&mut self.s_builder_version, version);
+    }
+
     /// Set message digest algorithm to handle chunk of the Rafs filesystem.
     pub fn set_digester(&mut self, digester: digest::Algorithm) {
         let c: RafsSuperFlags = digester.into();
@@ -510,6 +571,25 @@ impl RafsV6SuperBlockExt {
     );
     impl_pub_getter_setter!(chunk_size, set_chunk_size, s_chunk_size, u32);
     impl_pub_getter_setter!(flags, set_flags, s_flags, u64);
+    impl_pub_getter_setter!(
+        xattr_entry_count,
+        set_xattr_entry_count,
+        s_xattr_entry_count,
+        u64
+    );
+    impl_pub_getter_setter!(
+        xattr_total_bytes,
+        set_xattr_total_bytes,
+        s_xattr_total_bytes,
+        u64
+    );
+    impl_pub_getter_setter!(
+        provenance_offset,
+        set_provenance_offset,
+        s_provenance_offset,
+        u64
+    );
+    impl_pub_getter_setter!(provenance_size, set_provenance_size, s_provenance_size, u64);
     impl_pub_getter_setter!(
         blob_table_offset,
         set_blob_table_offset,
@@ -553,7 +633,12 @@ impl Default for RafsV6SuperBlockExt {
             s_prefetch_table_offset: 0,
             s_prefetch_table_size: 0,
             s_padding: u32::to_le(0),
-            s_reserved: [0u8; 200],
+            s_builder_version: [0u8; RAFS_BUILDER_VERSION_SIZE],
+            s_xattr_entry_count: 0,
+            s_xattr_total_bytes: 0,
+            s_provenance_offset: 0,
+            s_provenance_size: 0,
+            s_reserved: [0u8; 104],
         }
     }
 }
@@ -593,7 +678,7 @@ pub trait RafsV6OndiskInode: RafsStore {
     fn set_nlink(&mut self, nlinks: u32);
     fn set_mode(&mut self, mode: u16);
     fn set_u(&mut self, u: u32);
-    fn set_uidgid(&mut self, uid: u32, gid: u32);
+    fn set_ugid(&mut self, uid: u32, gid: u32);
     fn set_mtime(&mut self, _sec: u64, _nsec: u32);
     fn set_rdev(&mut self, rdev: u32);
     fn set_xattr_inline_count(&mut self, count: u16);
@@ -711,7 +796,7 @@ impl RafsV6OndiskInode for RafsV6InodeCompact {
     }
 
     /// Set uid and gid for the inode.
-    fn set_uidgid(&mut self, uid: u32, gid: u32) {
+    fn set_ugid(&mut self, uid: u32, gid: u32) {
         self.i_uid = u16::to_le(uid as u16);
         self.i_gid = u16::to_le(gid as u16);
     }
@@ -871,7 +956,7 @@ impl RafsV6OndiskInode for RafsV6InodeExtended {
     }
 
     /// Set uid and gid for the inode.
-    fn set_uidgid(&mut self, uid: u32, gid: u32) {
+    fn set_ugid(&mut self, uid: u32, gid: u32) {
         self.i_uid = u32::to_le(uid);
         self.i_gid = u32::to_le(gid);
     }
@@ -1246,6 +1331,18 @@ pub fn calculate_nid(offset: u64, meta_size: u64) -> u64 {
     (offset - meta_size) >> EROFS_INODE_SLOT_BITS
 }
 
+/// Calculate hash of a directory entry name, which is the 32-bit FNV-1a hash of the name.
+///
+/// When `EROFS_FEATURE_INCOMPAT_DIRHASH` is set, directory entries are sorted by the name hash,
+/// and entries with the same hash value are sorted by name.
+pub fn erofs_name_hash(name: &OsStr) -> u32 {
+    os_str_to_bytes(name)
+        .iter()
+        .fold(0x811c_9dc5u32, |hash, c| {
+            (hash ^ *c as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct RafsV6Blob {
@@ -1268,7 +1365,8 @@ struct RafsV6Blob {
     // Size of the uncompressed blob, not including CI array and header.
     uncompressed_size: u64,
 
-    reserved1: u32,
+    // Encryption algorithm for chunks in the blob.
+    cipher_algo: u32,
     // Compression algorithm for the compression information array.
     ci_compressor: u32,
     // Offset into the compressed blob for the compression information array.
@@ -1287,7 +1385,10 @@ struct RafsV6Blob {
     // Number of the ZRan context information entries.
     ci_zran_count: u32,
 
-    reserved2: [u8; 68],
+    // Reference to the master key to wrap the data key.
+    cipher_key_ref: [u8; KEY_REF_LEN],
+    // Data key for chunks in the blob, wrapped by the master key.
+    cipher_wrapped_key: [u8; WRAPPED_KEY_LEN],
 }
 
 impl Default for RafsV6Blob {
@@ -1302,7 +1403,7 @@ impl Default for RafsV6Blob {
             meta_features: 0u32,
             compressed_size: 0u64,
             uncompressed_size: 0u64,
-            reserved1: 0u32,
+            cipher_algo: (crypt::Algorithm::None as u32).to_le(),
             ci_compressor: (compress::Algorithm::None as u32).to_le(),
             ci_offset: 0u64,
             ci_compressed_size: 0u64,
@@ -1311,7 +1412,8 @@ impl Default for RafsV6Blob {
             ci_zran_offset: 0,
             ci_zran_size: 0,
             ci_zran_count: 0,
-            reserved2: [0u8; 68],
+            cipher_key_ref: [0u8; KEY_REF_LEN],
+            cipher_wrapped_key: [0u8; WRAPPED_KEY_LEN],
         }
     }
 }
@@ -1356,6 +1458,11 @@ impl RafsV6Blob {
                 u64::from_le(self.ci_zran_size),
             );
         }
+        let cipher = crypt::Algorithm::try_from(u32::from_le(self.cipher_algo))
+            .map_err(|_| einval!("invalid encryption algorithm in Rafs v6 blob entry"))?;
+        if !cipher.is_none() {
+            blob_info.set_cipher_info(cipher, self.cipher_key_ref, &self.cipher_wrapped_key);
+        }
 
         Ok(blob_info)
     }
@@ -1369,6 +1476,14 @@ impl RafsV6Blob {
         let mut blob_id = [0u8; BLOB_SHA256_LEN];
         blob_id.copy_from_slice(blob_info.blob_id().as_bytes());
 
+        let mut cipher_wrapped_key = [0u8; WRAPPED_KEY_LEN];
+        if blob_info.is_encrypted() {
+            if blob_info.cipher_wrapped_key().len() != WRAPPED_KEY_LEN {
+                return Err(einval!("invalid wrapped data key in blob info"));
+            }
+            cipher_wrapped_key.copy_from_slice(blob_info.cipher_wrapped_key());
+        }
+
         Ok(RafsV6Blob {
             blob_id,
             blob_index: blob_info.blob_index().to_le(),
@@ -1376,7 +1491,7 @@ impl RafsV6Blob {
             chunk_count: blob_info.chunk_count().to_le(),
             compression_algo: (blob_info.compressor() as u32).to_le(),
             digest_algo: (blob_info.digester() as u32).to_le(),
-            reserved1: 0u32.to_le(),
+            cipher_algo: (blob_info.cipher() as u32).to_le(),
             compressed_size: blob_info.compressed_size().to_le(),
             uncompressed_size: blob_info.uncompressed_size().to_le(),
             meta_features: blob_info.meta_flags().to_le(),
@@ -1388,7 +1503,8 @@ impl RafsV6Blob {
             ci_zran_count: u32::to_le(blob_info.meta_ci_zran_count()),
             ci_zran_offset: u64::to_le(blob_info.meta_ci_zran_offset()),
             ci_zran_size: u64::to_le(blob_info.meta_ci_zran_size()),
-            reserved2: [0u8; 68],
+            cipher_key_ref: *blob_info.cipher_key_ref(),
+            cipher_wrapped_key,
         })
     }
 
@@ -1455,6 +1571,14 @@ impl RafsV6Blob {
             return false;
         }
 
+        if crypt::Algorithm::try_from(u32::from_le(self.cipher_algo)).is_err() {
+            error!(
+                "RafsV6Blob: idx {} invalid cipher_algo {}",
+                blob_index, self.cipher_algo
+            );
+            return false;
+        }
+
         let uncompressed_blob_size = u64::from_le(self.uncompressed_size);
         let compressed_blob_size = u64::from_le(self.compressed_size);
         if uncompressed_blob_size > BLOB_MAX_SIZE_UNCOMPRESSED {
@@ -1583,11 +1707,108 @@ impl RafsV6Blob {
     }
 }
 
+/// Errors to access the Rafs v6 blob description table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobTableError {
+    /// The blob index is beyond the end of the blob table.
+    IndexOutOfRange { index: u32, table_size: u32 },
+    /// The blob table entry can't be converted into blob information.
+    InvalidEntry { index: u32 },
+}
+
+impl fmt::Display for BlobTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlobTableError::IndexOutOfRange { index, table_size } => write!(
+                f,
+                "blob index {} is out of range, blob table size {}",
+                index, table_size
+            ),
+            BlobTableError::InvalidEntry { index } => {
+                write!(f, "blob table entry {} is invalid", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlobTableError {}
+
+impl From<BlobTableError> for Error {
+    fn from(e: BlobTableError) -> Self {
+        enoent!(e.to_string())
+    }
+}
+
+/// Entry of the Rafs v6 blob description table.
+///
+/// Entries loaded from the on-disk blob table are converted into `BlobInfo` objects on demand.
+#[derive(Clone, Debug)]
+struct RafsV6BlobEntry {
+    ondisk: Option<RafsV6Blob>,
+    info: OnceCell<Arc<BlobInfo>>,
+}
+
+impl RafsV6BlobEntry {
+    fn new(blob_info: BlobInfo) -> Self {
+        let info = OnceCell::new();
+        let _ = info.set(Arc::new(blob_info));
+        RafsV6BlobEntry { ondisk: None, info }
+    }
+
+    fn from_ondisk(blob: RafsV6Blob) -> Self {
+        RafsV6BlobEntry {
+            ondisk: Some(blob),
+            info: OnceCell::new(),
+        }
+    }
+
+    fn get(&self) -> Result<Arc<BlobInfo>> {
+        self.info
+            .get_or_try_init(|| match self.ondisk.as_ref() {
+                Some(blob) => blob.to_blob_info().map(Arc::new),
+                None => Err(einval!("blob table entry has no blob information")),
+            })
+            .map(|v| v.clone())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.info.get().is_some()
+    }
+
+    fn cipher_key_ref(&self) -> Result<Option<[u8; KEY_REF_LEN]>> {
+        match (self.info.get(), self.ondisk.as_ref()) {
+            (Some(info), _) if info.is_encrypted() => Ok(Some(*info.cipher_key_ref())),
+            (Some(_), _) => Ok(None),
+            (None, Some(blob)) => {
+                let cipher = crypt::Algorithm::try_from(u32::from_le(blob.cipher_algo))
+                    .map_err(|_| einval!("invalid encryption algorithm in Rafs v6 blob entry"))?;
+                if cipher.is_none() {
+                    Ok(None)
+                } else {
+                    Ok(Some(blob.cipher_key_ref))
+                }
+            }
+            (None, None) => Err(einval!("blob table entry has no blob information")),
+        }
+    }
+
+    fn blob_id(&self) -> Option<&str> {
+        match (self.info.get(), self.ondisk.as_ref()) {
+            (Some(info), _) => Some(info.blob_id()),
+            (None, Some(blob)) => std::str::from_utf8(&blob.blob_id).ok(),
+            (None, None) => None,
+        }
+    }
+}
+
 /// Rafs v6 blob description table.
+///
+/// Blob information objects are materialized lazily, so blobs never accessed by a filesystem
+/// instance don't cost anything except the on-disk entry.
 #[derive(Clone, Debug, Default)]
 pub struct RafsV6BlobTable {
     /// Base blob information array.
-    entries: Vec<Arc<BlobInfo>>,
+    entries: Vec<RafsV6BlobEntry>,
 }
 
 impl RafsV6BlobTable {
@@ -1605,17 +1826,48 @@ impl RafsV6BlobTable {
 
     /// Get base information for a blob.
     #[inline]
-    pub fn get(&self, blob_index: u32) -> Result<Arc<BlobInfo>> {
+    pub fn get(&self, blob_index: u32) -> std::result::Result<Arc<BlobInfo>, BlobTableError> {
         if blob_index >= self.entries.len() as u32 {
-            Err(enoent!("blob not found"))
+            Err(BlobTableError::IndexOutOfRange {
+                index: blob_index,
+                table_size: self.entries.len() as u32,
+            })
         } else {
-            Ok(self.entries[blob_index as usize].clone())
+            self.entries[blob_index as usize]
+                .get()
+                .map_err(|_e| BlobTableError::InvalidEntry { index: blob_index })
         }
     }
 
-    /// Get the base blob information array.
-    pub fn get_all(&self) -> Vec<Arc<BlobInfo>> {
-        self.entries.clone()
+    /// Get the base blob information array, materializing all blob information objects.
+    pub fn get_all(&self) -> Result<Vec<Arc<BlobInfo>>> {
+        self.entries.iter().map(|entry| entry.get()).collect()
+    }
+
+    /// Get blob information objects which have already been materialized.
+    pub fn get_ready(&self) -> Vec<Arc<BlobInfo>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.info.get().cloned())
+            .collect()
+    }
+
+    /// Check whether the blob information object for a blob has been materialized.
+    pub fn is_ready(&self, blob_index: u32) -> bool {
+        self.entries
+            .get(blob_index as usize)
+            .map(|entry| entry.is_ready())
+            .unwrap_or(false)
+    }
+
+    /// Find base information for a blob by blob id.
+    ///
+    /// Blob tables are small, so just search the table linearly.
+    pub fn find_by_id(&self, blob_id: &str) -> Option<Arc<BlobInfo>> {
+        self.entries
+            .iter()
+            .find(|entry| entry.blob_id() == Some(blob_id))
+            .and_then(|entry| entry.get().ok())
     }
 
     /// Add information for new blob into the blob information table.
@@ -1662,11 +1914,29 @@ impl RafsV6BlobTable {
             );
         }
 
-        self.entries.push(Arc::new(blob_info));
+        self.entries.push(RafsV6BlobEntry::new(blob_info));
 
         blob_index
     }
 
+    /// Set encryption information for a blob in the blob information table.
+    pub fn set_cipher_info(
+        &mut self,
+        blob_index: u32,
+        cipher: crypt::Algorithm,
+        key_ref: [u8; KEY_REF_LEN],
+        wrapped_key: &[u8],
+    ) -> Result<()> {
+        let entry = self
+            .entries
+            .get_mut(blob_index as usize)
+            .ok_or_else(|| enoent!("blob not found"))?;
+        let mut blob_info = entry.get()?.as_ref().clone();
+        blob_info.set_cipher_info(cipher, key_ref, wrapped_key);
+        *entry = RafsV6BlobEntry::new(blob_info);
+        Ok(())
+    }
+
     /// Load blob information table from a reader.
     pub fn load(
         &mut self,
@@ -1678,29 +1948,57 @@ impl RafsV6BlobTable {
         if blob_table_size == 0 {
             return Ok(());
         }
-        if blob_table_size as usize % size_of::<RafsV6Blob>() != 0 {
-            let msg = format!("invalid Rafs v6 blob table size {}", blob_table_size);
-            return Err(einval!(msg));
+        // A truncated or corrupted blob table would otherwise silently drop the last entry.
+        let entry_size = size_of::<RafsV6Blob>();
+        if blob_table_size as usize % entry_size != 0 {
+            return Err(einval!(format!(
+                "blob table size {} not aligned to entry size {}",
+                blob_table_size, entry_size
+            )));
         }
 
-        for idx in 0..(blob_table_size as usize / size_of::<RafsV6Blob>()) {
+        for idx in 0..(blob_table_size as usize / entry_size) {
             let mut blob = RafsV6Blob::default();
             r.read_exact(blob.as_mut())?;
             if !blob.validate(idx as u32, chunk_size, flags) {
                 return Err(einval!("invalid Rafs v6 blob entry"));
             }
-            let blob_info = blob.to_blob_info()?;
-            self.entries.push(Arc::new(blob_info));
+            self.entries.push(RafsV6BlobEntry::from_ondisk(blob));
         }
 
         Ok(())
     }
 }
 
+impl BlobInfoSource for RafsV6BlobTable {
+    fn blob_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn blob_id(&self, blob_index: u32) -> Option<String> {
+        self.entries
+            .get(blob_index as usize)
+            .and_then(|entry| entry.blob_id())
+            .map(|id| id.to_string())
+    }
+
+    fn blob_info(&self, blob_index: u32) -> Result<Arc<BlobInfo>> {
+        self.get(blob_index).map_err(|e| e.into())
+    }
+
+    fn cipher_key_ref(&self, blob_index: u32) -> Result<Option<[u8; KEY_REF_LEN]>> {
+        self.entries
+            .get(blob_index as usize)
+            .ok_or_else(|| einval!(format!("blob index {} is out of range", blob_index)))?
+            .cipher_key_ref()
+    }
+}
+
 impl RafsStore for RafsV6BlobTable {
     fn store(&self, w: &mut dyn RafsIoWrite) -> Result<usize> {
-        for blob_info in self.entries.iter() {
-            let blob: RafsV6Blob = RafsV6Blob::from_blob_info(blob_info)?;
+        for entry in self.entries.iter() {
+            let blob_info = entry.get()?;
+            let blob: RafsV6Blob = RafsV6Blob::from_blob_info(&blob_info)?;
             trace!(
                 "blob_info index {}, chunk_count {} blob_id {:?}",
                 blob_info.blob_index(),
@@ -1909,7 +2207,7 @@ impl RafsXAttrs {
                 entry.set_value_size(value.len() as u16);
 
                 w.write_all(entry.as_ref())?;
-                w.write_all(&key.as_bytes()[prefix_len..])?;
+                w.write_all(&os_str_to_bytes(key)[prefix_len..])?;
                 w.write_all(value.as_ref())?;
 
                 let size =
@@ -2005,7 +2303,7 @@ impl RafsV6PrefetchTable {
 mod tests {
     use super::*;
     use crate::{BufWriter, RafsIoRead};
-    use std::fs::OpenOptions;
+    use std::fs::{File, OpenOptions};
     use std::io::Write;
     use vmm_sys_util::tempfile::TempFile;
 
@@ -2043,6 +2341,18 @@ mod tests {
             sb2.s_feature_incompat,
             (EROFS_FEATURE_INCOMPAT_CHUNKED_FILE | EROFS_FEATURE_INCOMPAT_DEVICE_TABLE).to_le()
         );
+        assert!(!sb2.has_dirhash());
+        sb2.set_dirhash(true);
+        assert!(sb2.has_dirhash());
+        sb2.set_dirhash(false);
+        assert!(!sb2.has_dirhash());
+    }
+
+    #[test]
+    fn test_erofs_name_hash() {
+        assert_eq!(erofs_name_hash(OsStr::new("")), 0x811c_9dc5);
+        assert_eq!(erofs_name_hash(OsStr::new("a")), 0xe40c_292c);
+        assert_eq!(erofs_name_hash(OsStr::new("foobar")), 0xbf9c_f968);
     }
 
     #[test]
@@ -2086,7 +2396,7 @@ mod tests {
             inode.i_format,
             u16::to_le(EROFS_INODE_LAYOUT_EXTENDED | (EROFS_INODE_CHUNK_BASED << 1))
         );
-        inode.set_uidgid(1, 2);
+        inode.set_ugid(1, 2);
         inode.set_mtime(3, 4);
         inode.store(&mut writer).unwrap();
         writer.flush().unwrap();
@@ -2103,6 +2413,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rafs_v6_inode_setters() {
+        let mut compact = RafsV6InodeCompact::new();
+        let mut extended = RafsV6InodeExtended::new();
+        let inodes: [&mut dyn RafsV6OndiskInode; 2] = [&mut compact, &mut extended];
+
+        for inode in inodes {
+            inode.set_size(0x1000);
+            inode.set_mode(0o100644);
+            inode.set_ugid(1, 2);
+            inode.set_mtime(3, 4);
+            inode.set_nlink(5);
+            inode.set_xattr_inline_count(6);
+            assert_eq!(inode.size(), 0x1000);
+            assert_eq!(inode.mode(), 0o100644);
+            assert_eq!(inode.ugid(), (1, 2));
+            assert_eq!(inode.nlink(), 5);
+            assert_eq!(inode.xattr_inline_count(), 6);
+        }
+
+        // Compact inodes have no room for modification time.
+        assert_eq!(compact.mtime_s_ns(), (0, 0));
+        assert_eq!(extended.mtime_s_ns(), (3, 4));
+    }
+
     #[test]
     fn test_rafs_v6_chunk_header() {
         let chunk_size: u32 = 1024 * 1024;
@@ -2171,6 +2506,150 @@ mod tests {
         assert_eq!(device.blob_id(), &id);
     }
 
+    #[test]
+    fn test_rafs_v6_blob_cipher() {
+        let blob_id = "a".repeat(BLOB_SHA256_LEN);
+        let mut blob_info = BlobInfo::new(
+            0,
+            blob_id,
+            0x1000,
+            0x1000 + crypt::Algorithm::Aes256Gcm.overhead() as u64,
+            0x1000,
+            1,
+            BlobFeatures::empty(),
+        );
+        let key_ref = crypt::key_ref("kms://key1");
+        let wrapped_key = [0x5au8; WRAPPED_KEY_LEN];
+        blob_info.set_cipher_info(crypt::Algorithm::Aes256Gcm, key_ref, &wrapped_key);
+
+        let blob = RafsV6Blob::from_blob_info(&blob_info).unwrap();
+        let blob_info2 = blob.to_blob_info().unwrap();
+        assert!(blob_info2.is_encrypted());
+        assert_eq!(blob_info2.cipher(), crypt::Algorithm::Aes256Gcm);
+        assert_eq!(blob_info2.cipher_key_ref(), &key_ref);
+        assert_eq!(blob_info2.cipher_wrapped_key(), &wrapped_key);
+        assert!(blob_info2.cipher_key().is_none());
+
+        let mut blob = RafsV6Blob::from_blob_info(&blob_info).unwrap();
+        blob.cipher_algo = 0x10u32.to_le();
+        assert!(!blob.validate(0, 0x1000, RafsSuperFlags::empty()));
+
+        blob_info.set_cipher_info(crypt::Algorithm::Aes256Gcm, key_ref, &wrapped_key[1..]);
+        assert!(RafsV6Blob::from_blob_info(&blob_info).is_err());
+    }
+
+    #[test]
+    fn test_rafs_v6_blob_table_load_unaligned() {
+        let mut header = BlobMetaHeaderOndisk::default();
+        header.set_4k_aligned(true);
+        header.set_ci_uncompressed_size(size_of::<BlobChunkInfoV1Ondisk>() as u64);
+        let mut table = RafsV6BlobTable::new();
+        table.add(
+            "0".repeat(BLOB_SHA256_LEN),
+            0,
+            0,
+            0x1000,
+            1,
+            0x1000,
+            0x1000,
+            BlobFeatures::empty(),
+            RafsSuperFlags::empty(),
+            header,
+        );
+
+        let temp = TempFile::new().unwrap();
+        let w = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp.as_path())
+            .unwrap();
+        let mut writer = BufWriter::new(w);
+        table.store(&mut writer).unwrap();
+        // Pad the blob table with one byte.
+        writer.write_all(&[0u8]).unwrap();
+        writer.flush().unwrap();
+        let size = table.size() as u32;
+
+        let mut reader: Box<dyn RafsIoRead> = Box::new(File::open(temp.as_path()).unwrap());
+        let mut table2 = RafsV6BlobTable::new();
+        let err = table2
+            .load(&mut reader, size + 1, 0x1000, RafsSuperFlags::empty())
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        assert!(table2.get_all().unwrap().is_empty());
+
+        let mut reader: Box<dyn RafsIoRead> = Box::new(File::open(temp.as_path()).unwrap());
+        table2
+            .load(&mut reader, size, 0x1000, RafsSuperFlags::empty())
+            .unwrap();
+        assert!(!table2.is_ready(0));
+        assert!(table2.get_ready().is_empty());
+        assert_eq!(table2.get_all().unwrap().len(), 1);
+        assert!(table2.is_ready(0));
+        assert_eq!(table2.get_ready().len(), 1);
+    }
+
+    #[test]
+    fn test_rafs_v6_blob_table_get() {
+        let mut table = RafsV6BlobTable::new();
+        for idx in 0..2 {
+            table.add(
+                format!("{}", idx).repeat(BLOB_SHA256_LEN),
+                0,
+                0,
+                0x1000,
+                1,
+                0x1000,
+                0x1000,
+                BlobFeatures::empty(),
+                RafsSuperFlags::empty(),
+                BlobMetaHeaderOndisk::default(),
+            );
+        }
+
+        assert_eq!(table.get(0).unwrap().blob_index(), 0);
+        assert_eq!(table.get(1).unwrap().blob_index(), 1);
+        let err = table.get(2).unwrap_err();
+        assert_eq!(
+            err,
+            BlobTableError::IndexOutOfRange {
+                index: 2,
+                table_size: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "blob index 2 is out of range, blob table size 2"
+        );
+        assert_eq!(Error::from(err).raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn test_rafs_v6_blob_table_find_by_id() {
+        let mut table = RafsV6BlobTable::new();
+        assert!(table.find_by_id(&"0".repeat(BLOB_SHA256_LEN)).is_none());
+        for idx in 0..2 {
+            table.add(
+                format!("{}", idx).repeat(BLOB_SHA256_LEN),
+                0,
+                0,
+                0x1000,
+                1,
+                0x1000,
+                0x1000,
+                BlobFeatures::empty(),
+                RafsSuperFlags::empty(),
+                BlobMetaHeaderOndisk::default(),
+            );
+        }
+
+        let blob = table.find_by_id(&"1".repeat(BLOB_SHA256_LEN)).unwrap();
+        assert_eq!(blob.blob_index(), 1);
+        assert_eq!(blob.blob_id(), "1".repeat(BLOB_SHA256_LEN));
+        assert!(table.find_by_id(&"2".repeat(BLOB_SHA256_LEN)).is_none());
+        assert!(table.find_by_id("").is_none());
+    }
+
     #[test]
     fn test_rafs_xattr_count_v6() {
         let mut xattrs = RafsXAttrs::new();
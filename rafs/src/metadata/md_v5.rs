@@ -39,6 +39,13 @@ impl RafsSuper {
         self.meta.extended_blob_table_entries = sb.extended_blob_table_entries();
         self.meta.prefetch_table_entries = sb.prefetch_table_entries();
         self.meta.prefetch_table_offset = sb.prefetch_table_offset();
+        self.meta.builder_version = sb.builder_version();
+        self.meta.xattr_entry_count = sb.xattr_entry_count();
+        self.meta.xattr_total_bytes = sb.xattr_total_bytes();
+        if sb.provenance_size() != 0 {
+            let provenance = RafsProvenance::load(r, sb.provenance_offset(), sb.provenance_size())?;
+            self.meta.provenance = Some(provenance);
+        }
 
         match self.mode {
             RafsMode::Direct => {
@@ -47,7 +54,7 @@ impl RafsSuper {
                 self.superblock = Arc::new(inodes);
             }
             RafsMode::Cached => {
-                let mut inodes = CachedSuperBlockV5::new(self.meta, self.validate_digest);
+                let mut inodes = CachedSuperBlockV5::new(self.meta.clone(), self.validate_digest);
                 inodes.load(r)?;
                 self.superblock = Arc::new(inodes);
             }
@@ -241,6 +248,10 @@ impl BlobChunkInfo for V5IoChunk {
         self.flags.contains(BlobChunkFlags::COMPRESSED)
     }
 
+    fn is_zeroed(&self) -> bool {
+        self.flags.contains(BlobChunkFlags::ZEROED)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
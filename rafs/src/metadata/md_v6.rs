@@ -3,11 +3,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::HashSet;
+use std::fs::OpenOptions;
 use std::io::Result;
 use std::mem::size_of;
+use std::path::Path;
 use std::sync::Arc;
 
 use super::direct_v6::DirectSuperBlockV6;
+use super::layout::v5::RafsV5ChunkInfo;
 use super::layout::v6::{RafsV6PrefetchTable, RafsV6SuperBlock, RafsV6SuperBlockExt};
 use super::layout::RAFS_SUPER_VERSION_V6;
 use super::*;
@@ -33,6 +36,7 @@ impl RafsSuper {
         self.meta.magic = sb.magic();
         self.meta.meta_blkaddr = sb.s_meta_blkaddr;
         self.meta.root_nid = sb.s_root_nid;
+        self.meta.dirhash = sb.has_dirhash();
 
         let mut ext_sb = RafsV6SuperBlockExt::new();
         ext_sb.load(r)?;
@@ -43,6 +47,14 @@ impl RafsSuper {
         self.meta.chunk_table_offset = ext_sb.chunk_table_offset();
         self.meta.chunk_table_size = ext_sb.chunk_table_size();
         self.meta.inodes_count = sb.inodes_count();
+        self.meta.builder_version = ext_sb.builder_version();
+        self.meta.xattr_entry_count = ext_sb.xattr_entry_count();
+        self.meta.xattr_total_bytes = ext_sb.xattr_total_bytes();
+        if ext_sb.provenance_size() != 0 {
+            let provenance =
+                RafsProvenance::load(r, ext_sb.provenance_offset(), ext_sb.provenance_size())?;
+            self.meta.provenance = Some(provenance);
+        }
 
         self.meta.flags = RafsSuperFlags::from_bits(ext_sb.flags())
             .ok_or_else(|| einval!(format!("invalid super flags {:x}", ext_sb.flags())))?;
@@ -67,6 +79,33 @@ impl RafsSuper {
         }
     }
 
+    /// Load RAFS v6 super block from a metadata file for a chunk dictionary.
+    ///
+    /// Chunks of RAFS v6 chunk dictionaries are enumerated through the chunk table instead of
+    /// inodes, so the chunk table is validated too.
+    pub fn load_chunk_dict_v6(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        let mut rs = RafsSuper {
+            mode: RafsMode::Direct,
+            validate_digest: true,
+            ..Default::default()
+        };
+        let mut reader = Box::new(file) as RafsIoReader;
+
+        rs.meta.is_chunk_dict = true;
+        if !rs.try_load_v6(&mut reader)? {
+            return Err(einval!(format!("{:?} is not a RAFS v6 filesystem", path)));
+        }
+        if rs.meta.chunk_table_size % size_of::<RafsV5ChunkInfo>() as u64 != 0 {
+            return Err(einval!(format!(
+                "invalid RAFS v6 chunk table size {}",
+                rs.meta.chunk_table_size
+            )));
+        }
+
+        Ok(rs)
+    }
+
     pub(crate) fn prefetch_data_v6<F>(
         &self,
         device: &BlobDevice,
@@ -6,33 +6,35 @@
 //! Enums, Structs and Traits to access and manage Rafs filesystem metadata.
 
 use std::any::Any;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::fs::OpenOptions;
 use std::io::{Error, Result};
-use std::ops::Deref;
-use std::os::unix::ffi::OsStrExt;
+use std::mem::size_of;
+use std::ops::{Deref, Range};
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::bail;
 use fuse_backend_rs::abi::fuse_abi::Attr;
 use fuse_backend_rs::api::filesystem::Entry;
-use nydus_storage::device::{BlobChunkInfo, BlobDevice, BlobInfo, BlobIoMerge, BlobIoVec};
+use nydus_storage::device::{
+    BlobChunkInfo, BlobDevice, BlobInfo, BlobInfoSource, BlobIoMerge, BlobIoVec,
+};
 use nydus_utils::compress;
 use nydus_utils::digest::{self, RafsDigest};
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use self::layout::v5::RafsV5PrefetchTable;
-use self::layout::v6::RafsV6PrefetchTable;
+use self::layout::v5::{RafsV5ChunkInfo, RafsV5PrefetchTable, RafsV5SuperBlock};
+use self::layout::v6::{RafsV6PrefetchTable, RafsV6SuperBlock};
 use self::layout::{XattrName, XattrValue, RAFS_SUPER_VERSION_V5, RAFS_SUPER_VERSION_V6};
 use self::noop::NoopSuperBlock;
 use crate::fs::{RafsConfig, RAFS_DEFAULT_ATTR_TIMEOUT, RAFS_DEFAULT_ENTRY_TIMEOUT};
-use crate::{RafsError, RafsIoReader, RafsIoWrite, RafsResult};
+use crate::{RafsError, RafsIoRead, RafsIoReader, RafsIoWrite, RafsResult};
 
 mod md_v5;
 mod md_v6;
@@ -42,6 +44,8 @@ pub mod cached_v5;
 pub mod chunk;
 pub mod direct_v5;
 pub mod direct_v6;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 pub mod inode;
 pub mod layout;
 
@@ -56,10 +60,14 @@ pub const RAFS_ATTR_BLOCK_SIZE: u32 = 4096;
 pub const RAFS_MAX_NAME: usize = 255;
 /// Maximum size of RAFS filesystem metadata blobs.
 pub const RAFS_MAX_METADATA_SIZE: usize = 0x8000_0000;
+/// Maximum size of the build provenance record in RAFS metadata blobs.
+pub const RAFS_MAX_PROVENANCE_SIZE: u64 = 0x10000;
 /// File name for Unix current directory.
 pub const DOT: &str = ".";
 /// File name for Unix parent directory.
 pub const DOTDOT: &str = "..";
+/// Maximum number of symlinks to follow when resolving a path, same as Linux `MAXSYMLINKS`.
+const MAX_SYMLINK_HOPS: u32 = 40;
 
 /// Type for RAFS filesystem inode number.
 pub type Inode = u64;
@@ -88,33 +96,77 @@ pub trait RafsSuperBlock: RafsSuperInodes + Send + Sync {
     /// Destroy the RAFS filesystem super block object.
     fn destroy(&mut self);
 
-    /// Get all blob objects referenced by the RAFS filesystem.
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>>;
+    /// Get blob objects referenced by the RAFS filesystem.
+    ///
+    /// Blob objects may be materialized lazily. All blob objects are returned if `force` is true,
+    /// otherwise only those already materialized are returned.
+    fn get_blob_infos(&self, force: bool) -> Result<Vec<Arc<BlobInfo>>>;
+
+    /// Get a source of blob objects referenced by the RAFS filesystem.
+    ///
+    /// Blob objects are materialized by the source on demand, if the RAFS filesystem supports
+    /// lazily loading blob objects.
+    fn get_blob_source(&self) -> Result<Arc<dyn BlobInfoSource>> {
+        Ok(Arc::new(self.get_blob_infos(true)?))
+    }
 
     /// Get the inode number of the RAFS filesystem root.
     fn root_ino(&self) -> u64;
 
+    /// Get the block address of the metadata area, in unit of `EROFS_BLOCK_SIZE`.
+    ///
+    /// Together with [RafsSuperBlock::root_nid()], it locates the on-disk root inode:
+    /// `offset = meta_blkaddr * EROFS_BLOCK_SIZE + nid * EROFS_INODE_SLOT_SIZE`.
+    /// It's always `0` for RAFS v5.
+    fn meta_blkaddr(&self) -> u32 {
+        0
+    }
+
+    /// Get the nid of the root inode, in unit of `EROFS_INODE_SLOT_SIZE` relative to the metadata
+    /// area. It's always `0` for RAFS v5.
+    fn root_nid(&self) -> u16 {
+        0
+    }
+
+    /// Get the generation number of the filesystem metadata, increased on every successful update.
+    ///
+    /// It's returned as generation number of inodes, so inode numbers reused by a new bootstrap
+    /// won't be confused with inodes of the previous bootstrap.
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    /// Check whether chunks may be accessed by chunk index through `get_chunk_info()`.
+    fn supports_chunk_info(&self) -> bool {
+        false
+    }
+
     /// Get the `BlobChunkInfo` object by a chunk index, used by RAFS v6.
     fn get_chunk_info(&self, _idx: usize) -> Result<Arc<dyn BlobChunkInfo>> {
-        unimplemented!()
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "get_chunk_info not supported by this superblock version",
+        ))
     }
 }
 
 /// Result codes for `RafsInodeWalkHandler`.
-pub enum RafsInodeWalkAction {
+pub enum RafsInodeWalkAction<T = ()> {
     /// Indicates the need to continue iterating
     Continue,
     /// Indicates that it is necessary to stop continuing to iterate
     Break,
+    /// Indicates that it is necessary to stop iterating and return the value to the caller
+    Return(T),
 }
 
 /// Callback handler for RafsInode::walk_children_inodes().
-pub type RafsInodeWalkHandler<'a> = &'a mut dyn FnMut(
+pub type RafsInodeWalkHandler<'a, T = ()> = &'a mut dyn FnMut(
     Option<Arc<dyn RafsInode>>,
     OsString,
     u64,
     u64,
-) -> Result<RafsInodeWalkAction>;
+) -> Result<RafsInodeWalkAction<T>>;
 
 /// Trait to provide readonly accessors for RAFS filesystem inode.
 ///
@@ -137,6 +189,12 @@ pub trait RafsInode: Any {
         user_io: bool,
     ) -> Result<Vec<BlobIoVec>>;
 
+    /// RAFS: get indexes of data chunks covering file data in range [offset, offset + len).
+    ///
+    /// It's cheaper than `alloc_bio_vecs()`, so callers may check whether a range is covered by
+    /// a single chunk first. The returned range is empty if no chunk covers the file range.
+    fn get_chunk_range(&self, offset: u64, len: usize) -> Result<Range<u32>>;
+
     /// RAFS: collect all descendants of the inode for image building.
     fn collect_descendants_inodes(
         &self,
@@ -170,6 +228,41 @@ pub trait RafsInode: Any {
     /// Mode: check whether the inode is a hardlink.
     fn is_hardlink(&self) -> bool;
 
+    /// Mode: check whether the inode is a character device.
+    fn is_char_device(&self) -> bool {
+        self.get_attr().mode & libc::S_IFMT as u32 == libc::S_IFCHR as u32
+    }
+
+    /// Mode: check whether the inode is a block device.
+    fn is_block_device(&self) -> bool {
+        self.get_attr().mode & libc::S_IFMT as u32 == libc::S_IFBLK as u32
+    }
+
+    /// Mode: check whether the inode is a FIFO.
+    fn is_fifo(&self) -> bool {
+        self.get_attr().mode & libc::S_IFMT as u32 == libc::S_IFIFO as u32
+    }
+
+    /// Mode: check whether the inode is a socket.
+    fn is_socket(&self) -> bool {
+        self.get_attr().mode & libc::S_IFMT as u32 == libc::S_IFSOCK as u32
+    }
+
+    /// Mode: check whether the set-user-ID bit is set.
+    fn is_setuid(&self) -> bool {
+        self.get_attr().mode & libc::S_ISUID as u32 != 0
+    }
+
+    /// Mode: check whether the set-group-ID bit is set.
+    fn is_setgid(&self) -> bool {
+        self.get_attr().mode & libc::S_ISGID as u32 != 0
+    }
+
+    /// Mode: check whether the sticky bit is set.
+    fn is_sticky(&self) -> bool {
+        self.get_attr().mode & libc::S_ISVTX as u32 != 0
+    }
+
     /// Xattr: check whether the inode has extended attributes.
     fn has_xattr(&self) -> bool;
 
@@ -179,6 +272,13 @@ pub trait RafsInode: Any {
     /// Xattr: get all xattr keys.
     fn get_xattrs(&self) -> Result<Vec<XattrName>>;
 
+    /// Xattr: get number of extended attributes, without allocating memory for xattr keys.
+    ///
+    /// Zero is returned if the extended attributes are corrupted.
+    fn xattr_count(&self) -> u32 {
+        self.get_xattrs().map(|v| v.len() as u32).unwrap_or(0)
+    }
+
     /// Symlink: get the symlink target.
     fn get_symlink(&self) -> Result<OsString>;
 
@@ -211,9 +311,65 @@ pub trait RafsInode: Any {
     /// Regular: get number of data chunks.
     fn get_chunk_count(&self) -> u32;
 
+    /// Regular: get file content stored inline in the metadata blob.
+    ///
+    /// Return `None` if file content is stored in data blobs instead of the metadata blob.
+    fn get_inline_data(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Regular: get digest of the entire file content, refer to [calculate_content_hash()] for
+    /// the algorithm.
+    fn content_hash(&self) -> Result<RafsDigest>;
+
+    /// Regular: get locations of data chunks in data blobs, in file offset order.
+    fn chunk_layout(&self) -> Result<Vec<ChunkLocation>>;
+
+    /// Regular: get total compressed size of data chunks, refer to
+    /// [calculate_compressed_size()] for details.
+    fn total_chunk_compressed_size(&self) -> Result<u64> {
+        Ok(calculate_compressed_size(&self.chunk_layout()?))
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
+impl dyn RafsInode + '_ {
+    /// Walk children of a directory inode, returning the value of the first
+    /// `RafsInodeWalkAction::Return` from `handler`.
+    ///
+    /// Return `None` if the walk completes or is stopped by `RafsInodeWalkAction::Break`.
+    pub fn walk_children_inodes_with_result<T>(
+        &self,
+        entry_offset: u64,
+        handler: RafsInodeWalkHandler<T>,
+    ) -> Result<Option<T>> {
+        let mut result = None;
+        self.walk_children_inodes(entry_offset, &mut |inode, name, ino, offset| match handler(
+            inode, name, ino, offset,
+        )? {
+            RafsInodeWalkAction::Continue => Ok(RafsInodeWalkAction::Continue),
+            RafsInodeWalkAction::Break => Ok(RafsInodeWalkAction::Break),
+            RafsInodeWalkAction::Return(v) => {
+                result = Some(v);
+                Ok(RafsInodeWalkAction::Break)
+            }
+        })?;
+        Ok(result)
+    }
+
+    /// Directory: get number of child directories.
+    pub fn get_subdir_count(&self) -> Result<u32> {
+        let mut count = 0;
+        for idx in 0..self.get_child_count() {
+            if self.get_child_by_index(idx)?.is_dir() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
 /// Extended inode information for builder and directory walker.
 pub trait RafsInodeExt: RafsInode {
     /// Convert to the base type `RafsInode`.
@@ -236,6 +392,183 @@ pub trait RafsInodeExt: RafsInode {
 
     /// RAFS v5: get chunk info object by chunk index, chunk index starts from 0.
     fn get_chunk_info(&self, idx: u32) -> Result<Arc<dyn BlobChunkInfo>>;
+
+    /// Get the parent inode object, or `None` for the root inode.
+    ///
+    /// Only supported by inodes in direct mode, which may look up the parent inode by themselves.
+    fn get_parent_inode(&self) -> Result<Option<Arc<dyn RafsInodeExt>>> {
+        Err(enosys!("parent inode lookup is not supported"))
+    }
+
+    /// Compute path of the inode by walking the chain of parent inodes.
+    ///
+    /// Different from `RafsSuper::path_from_ino()`, it doesn't need to access the super block.
+    fn compute_path(&self) -> Result<PathBuf> {
+        let mut path = PathBuf::from(self.name());
+        let mut parent = self.get_parent_inode()?;
+        while let Some(inode) = parent {
+            path = PathBuf::from(inode.name()).join(path);
+            parent = inode.get_parent_inode()?;
+        }
+
+        Ok(path)
+    }
+
+    /// Check whether the inode has the same content as `other`, which may come from another
+    /// RAFS filesystem, without reading any file data.
+    ///
+    /// Regular files are compared by digests of their data chunks, so files from filesystems with
+    /// different chunk sizes or digest algorithms are never equal. Symlinks are compared by their
+    /// targets, and special files by their device numbers. Directories are never equal, comparing
+    /// directory content is the caller's responsibility.
+    fn compare_content(&self, other: &dyn RafsInodeExt) -> Result<bool> {
+        let fmt = self.get_attr().mode & libc::S_IFMT as u32;
+        if self.is_dir() || fmt != other.get_attr().mode & libc::S_IFMT as u32 {
+            return Ok(false);
+        }
+        if self.size() != other.size() {
+            return Ok(false);
+        }
+
+        if self.is_symlink() {
+            Ok(self.get_symlink()? == other.get_symlink()?)
+        } else if self.is_reg() {
+            match (self.get_inline_data()?, other.get_inline_data()?) {
+                (Some(data), Some(other_data)) => return Ok(data == other_data),
+                (None, None) => {}
+                _ => return Ok(false),
+            }
+            let count = self.get_chunk_count();
+            if count != other.get_chunk_count() {
+                return Ok(false);
+            }
+            for idx in 0..count {
+                if self.get_chunk_info(idx)?.chunk_id() != other.get_chunk_info(idx)?.chunk_id() {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        } else {
+            Ok(self.rdev() == other.rdev())
+        }
+    }
+}
+
+/// Calculate digest of the entire content of a regular file from digests of its data chunks.
+///
+/// - for a file with exactly one chunk, it's the chunk digest itself.
+/// - otherwise it's the digest, calculated with `algorithm`, of all chunk digests concatenated in
+///   file offset order, with each chunk digest taking 32 bytes. So it's the digest of an empty
+///   buffer for an empty file.
+///
+/// The `algorithm` should be the digest algorithm of the RAFS filesystem, so the content hash of
+/// a single chunk file matches digest of the file content.
+pub fn calculate_content_hash(
+    inode: &dyn RafsInodeExt,
+    algorithm: digest::Algorithm,
+) -> Result<RafsDigest> {
+    if !inode.is_reg() {
+        return Err(einval!("content hash is only available for regular files"));
+    }
+
+    let count = inode.get_chunk_count();
+    if count == 1 {
+        return Ok(*inode.get_chunk_info(0)?.chunk_id());
+    }
+    let mut buf = Vec::with_capacity(count as usize * digest::RAFS_DIGEST_LENGTH);
+    for idx in 0..count {
+        buf.extend_from_slice(inode.get_chunk_info(idx)?.chunk_id().as_ref());
+    }
+
+    Ok(RafsDigest::from_buf(&buf, algorithm))
+}
+
+/// Location of a data chunk of a regular file.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ChunkLocation {
+    /// Index of the chunk in the file.
+    pub index: u32,
+    /// Index of the data blob containing the chunk.
+    pub blob_index: u32,
+    /// Offset of the compressed chunk data in the data blob.
+    pub compressed_offset: u64,
+    /// Size of the compressed chunk data.
+    pub compressed_size: u32,
+}
+
+impl ChunkLocation {
+    pub(crate) fn new(index: u32, chunk: &dyn BlobChunkInfo) -> Self {
+        ChunkLocation {
+            index,
+            blob_index: chunk.blob_index(),
+            compressed_offset: chunk.compressed_offset(),
+            compressed_size: chunk.compressed_size(),
+        }
+    }
+}
+
+/// Get locations of data chunks of a regular file from its chunk information objects.
+pub fn calculate_chunk_layout(inode: &dyn RafsInodeExt) -> Result<Vec<ChunkLocation>> {
+    if !inode.is_reg() {
+        return Err(einval!("chunk layout is only available for regular files"));
+    }
+
+    (0..inode.get_chunk_count())
+        .map(|idx| Ok(ChunkLocation::new(idx, inode.get_chunk_info(idx)?.as_ref())))
+        .collect()
+}
+
+/// Count extended attribute entries and total size of their names and values by walking the
+/// filesystem tree of `sb`.
+///
+/// Inodes reachable through multiple hardlinks are counted once.
+pub fn compute_xattr_stats(sb: &dyn RafsSuperBlock) -> Result<(u64, u64)> {
+    let mut entries = 0u64;
+    let mut bytes = 0u64;
+    let mut visited = HashSet::new();
+    let mut pending = vec![sb.root_ino()];
+
+    while let Some(ino) = pending.pop() {
+        if !visited.insert(ino) {
+            continue;
+        }
+        let inode = sb.get_inode(ino, false)?;
+        if inode.has_xattr() {
+            for name in inode.get_xattrs()? {
+                let value = inode.get_xattr(&layout::bytes_to_os_str(&name))?;
+                entries += 1;
+                bytes += (name.len() + value.map(|v| v.len()).unwrap_or_default()) as u64;
+            }
+        }
+        if inode.is_dir() {
+            for idx in 0..inode.get_child_count() {
+                pending.push(inode.get_child_by_index(idx)?.ino());
+            }
+        }
+    }
+
+    Ok((entries, bytes))
+}
+
+/// Calculate total compressed size of data chunks, which is the amount of data to download from
+/// storage backends.
+///
+/// If all chunks are stored contiguously in the same data blob, the size of the compressed range
+/// is returned directly, otherwise compressed sizes of all chunks are summed up.
+pub fn calculate_compressed_size(chunks: &[ChunkLocation]) -> u64 {
+    let (first, last) = match (chunks.first(), chunks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 0,
+    };
+    let contiguous = chunks.windows(2).all(|w| {
+        w[0].blob_index == w[1].blob_index
+            && w[0].compressed_offset + w[0].compressed_size as u64 == w[1].compressed_offset
+    });
+    if contiguous {
+        last.compressed_offset + last.compressed_size as u64 - first.compressed_offset
+    } else {
+        chunks.iter().map(|c| c.compressed_size as u64).sum()
+    }
 }
 
 /// Trait to write out RAFS filesystem meta objects into the metadata blob.
@@ -266,6 +599,10 @@ bitflags! {
         const COMPRESSION_GZIP = 0x0000_0040;
         // Data chunks are compressed with zstd
         const COMPRESSION_ZSTD = 0x0000_0080;
+        /// Data chunks are encrypted with aes256-gcm.
+        const ENCRYPTION_AES256_GCM = 0x0000_0100;
+        /// Data chunks are compressed with raw snappy.
+        const COMPRESSION_SNAPPY = 0x0000_0200;
     }
 }
 
@@ -308,6 +645,7 @@ impl From<RafsSuperFlags> for compress::Algorithm {
             x if x.contains(RafsSuperFlags::COMPRESSION_LZ4) => compress::Algorithm::Lz4Block,
             x if x.contains(RafsSuperFlags::COMPRESSION_GZIP) => compress::Algorithm::GZip,
             x if x.contains(RafsSuperFlags::COMPRESSION_ZSTD) => compress::Algorithm::Zstd,
+            x if x.contains(RafsSuperFlags::COMPRESSION_SNAPPY) => compress::Algorithm::Snappy,
             _ => compress::Algorithm::Lz4Block,
         }
     }
@@ -320,12 +658,72 @@ impl From<compress::Algorithm> for RafsSuperFlags {
             compress::Algorithm::Lz4Block => RafsSuperFlags::COMPRESSION_LZ4,
             compress::Algorithm::GZip => RafsSuperFlags::COMPRESSION_GZIP,
             compress::Algorithm::Zstd => RafsSuperFlags::COMPRESSION_ZSTD,
+            compress::Algorithm::Snappy => RafsSuperFlags::COMPRESSION_SNAPPY,
         }
     }
 }
 
+/// Build provenance of a RAFS filesystem, recorded by the builder as a JSON object.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RafsProvenance {
+    /// Version of the builder.
+    pub builder_version: String,
+    /// Type of the source the filesystem is built from.
+    pub source_type: String,
+    /// Compression algorithm for data chunks.
+    pub compressor: String,
+    /// Size of data chunks.
+    pub chunk_size: u32,
+    /// Time when the filesystem was built, in seconds since the UNIX epoch.
+    pub created: u64,
+    /// User annotations.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl RafsProvenance {
+    /// Serialize the provenance record, which must not be bigger than `RAFS_MAX_PROVENANCE_SIZE`.
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        let buf = serde_json::to_vec(self).map_err(|e| einval!(e))?;
+        if buf.len() as u64 > RAFS_MAX_PROVENANCE_SIZE {
+            return Err(einval!(format!(
+                "provenance record size {} exceeds limit {}",
+                buf.len(),
+                RAFS_MAX_PROVENANCE_SIZE
+            )));
+        }
+        Ok(buf)
+    }
+
+    /// Parse a provenance record from a byte slice.
+    pub fn from_slice(buf: &[u8]) -> Result<Self> {
+        if buf.is_empty() || buf.len() as u64 > RAFS_MAX_PROVENANCE_SIZE {
+            return Err(einval!(format!(
+                "invalid provenance record size {}",
+                buf.len()
+            )));
+        }
+        serde_json::from_slice(buf)
+            .map_err(|e| einval!(format!("invalid provenance record, {}", e)))
+    }
+
+    // Load the provenance record at `offset`, the position of the reader is kept unchanged.
+    fn load(r: &mut RafsIoReader, offset: u64, size: u64) -> Result<Self> {
+        if size > RAFS_MAX_PROVENANCE_SIZE {
+            return Err(einval!(format!("invalid provenance record size {}", size)));
+        }
+        let pos = r.seek_plus_offset(0)?;
+        r.seek_to_offset(offset)?;
+        let mut buf = vec![0u8; size as usize];
+        let ret = r.read_exact(&mut buf);
+        r.seek_to_offset(pos)?;
+        ret?;
+        Self::from_slice(&buf)
+    }
+}
+
 /// Rafs filesystem meta-data cached from on disk RAFS super block.
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct RafsSuperMeta {
     /// Filesystem magic number.
     pub magic: u32,
@@ -367,10 +765,20 @@ pub struct RafsSuperMeta {
     pub meta_blkaddr: u32,
     /// Root nid for RAFS v6.
     pub root_nid: u16,
+    /// Whether directory entries are sorted by name hash for RAFS v6.
+    pub dirhash: bool,
     /// Offset of the chunk table for RAFS v6.
     pub chunk_table_offset: u64,
     /// Size  of the chunk table for RAFS v6.
     pub chunk_table_size: u64,
+    /// Version of the builder which generated the metadata blob, `None` for old builders.
+    pub builder_version: Option<String>,
+    /// Number of extended attribute entries in the filesystem.
+    pub xattr_entry_count: u64,
+    /// Total size of extended attribute names and values in the filesystem.
+    pub xattr_total_bytes: u64,
+    /// Build provenance of the filesystem, `None` for old builders.
+    pub provenance: Option<RafsProvenance>,
 }
 
 impl RafsSuperMeta {
@@ -399,6 +807,19 @@ impl RafsSuperMeta {
         self.flags.contains(RafsSuperFlags::HAS_XATTR)
     }
 
+    /// Check whether xattr statistics are available from the super block.
+    ///
+    /// Old builders don't record xattr statistics, so zero entries for a filesystem with the
+    /// `HAS_XATTR` flag means the statistics are unknown.
+    pub fn has_xattr_stats(&self) -> bool {
+        !self.has_xattr() || self.xattr_entry_count != 0
+    }
+
+    /// Check whether data chunks of the filesystem are encrypted or not.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags.contains(RafsSuperFlags::ENCRYPTION_AES256_GCM)
+    }
+
     /// Get compression algorithm to handle chunk data for the filesystem.
     pub fn get_compressor(&self) -> compress::Algorithm {
         if self.is_v5() || self.is_v6() {
@@ -409,13 +830,60 @@ impl RafsSuperMeta {
     }
 
     /// V5: get message digest algorithm to validate chunk data for the filesystem.
-    pub fn get_digester(&self) -> digest::Algorithm {
+    pub fn get_digest_algorithm(&self) -> digest::Algorithm {
         if self.is_v5() || self.is_v6() {
             self.flags.into()
         } else {
             digest::Algorithm::Blake3
         }
     }
+
+    /// Get message digest provider to validate chunk data for the filesystem.
+    pub fn get_digester(&self) -> &'static dyn digest::DigestProvider {
+        self.get_digest_algorithm().provider()
+    }
+
+    /// Check whether the filesystem could be stacked with filesystem `other`.
+    ///
+    /// All layers of a stacked image must use the same chunk size, compression algorithm and
+    /// message digest algorithm.
+    pub fn is_compatible_with(&self, other: &RafsSuperMeta) -> bool {
+        self.incompatibility_reasons(other).is_empty()
+    }
+
+    /// Get reasons why the filesystem couldn't be stacked with filesystem `other`.
+    pub fn incompatibility_reasons(&self, other: &RafsSuperMeta) -> Vec<String> {
+        let compression_mask = RafsSuperFlags::COMPRESSION_NONE
+            | RafsSuperFlags::COMPRESSION_LZ4
+            | RafsSuperFlags::COMPRESSION_GZIP
+            | RafsSuperFlags::COMPRESSION_ZSTD
+            | RafsSuperFlags::COMPRESSION_SNAPPY;
+        let hash_mask = RafsSuperFlags::HASH_BLAKE3 | RafsSuperFlags::HASH_SHA256;
+        let mut reasons = Vec::new();
+
+        if self.chunk_size != other.chunk_size {
+            reasons.push(format!(
+                "chunk size 0x{:x} vs 0x{:x}",
+                self.chunk_size, other.chunk_size
+            ));
+        }
+        if self.flags & compression_mask != other.flags & compression_mask {
+            reasons.push(format!(
+                "compression flags {:?} vs {:?}",
+                self.flags & compression_mask,
+                other.flags & compression_mask
+            ));
+        }
+        if self.flags & hash_mask != other.flags & hash_mask {
+            reasons.push(format!(
+                "digest flags {:?} vs {:?}",
+                self.flags & hash_mask,
+                other.flags & hash_mask
+            ));
+        }
+
+        reasons
+    }
 }
 
 impl Default for RafsSuperMeta {
@@ -440,15 +908,20 @@ impl Default for RafsSuperMeta {
             entry_timeout: Duration::from_secs(RAFS_DEFAULT_ENTRY_TIMEOUT),
             meta_blkaddr: 0,
             root_nid: 0,
+            dirhash: false,
             is_chunk_dict: false,
             chunk_table_offset: 0,
             chunk_table_size: 0,
+            builder_version: None,
+            xattr_entry_count: 0,
+            xattr_total_bytes: 0,
+            provenance: None,
         }
     }
 }
 
 /// RAFS filesystem versions.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 pub enum RafsVersion {
     /// RAFS v5
     V5,
@@ -487,6 +960,15 @@ impl RafsVersion {
     }
 }
 
+/// Estimated time in milliseconds to load 10MB of metadata in `RafsMode::Direct` mode.
+pub const RAFS_LOAD_MS_PER_10MB_DIRECT: u64 = 100;
+/// Estimated time in milliseconds to load 10MB of metadata in `RafsMode::Cached` mode.
+pub const RAFS_LOAD_MS_PER_10MB_CACHED: u64 = 500;
+/// Environment variable to override [RAFS_LOAD_MS_PER_10MB_DIRECT].
+pub const RAFS_LOAD_MS_PER_10MB_DIRECT_ENV: &str = "NYDUS_RAFS_LOAD_MS_PER_10MB_DIRECT";
+/// Environment variable to override [RAFS_LOAD_MS_PER_10MB_CACHED].
+pub const RAFS_LOAD_MS_PER_10MB_CACHED_ENV: &str = "NYDUS_RAFS_LOAD_MS_PER_10MB_CACHED";
+
 /// Rafs metadata working mode.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RafsMode {
@@ -523,7 +1005,24 @@ impl Display for RafsMode {
     }
 }
 
+impl Serialize for RafsMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RafsMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        RafsMode::from_str(&s)
+            .map_err(|e| serde::de::Error::custom(format!("invalid rafs mode \"{}\", {}", s, e)))
+    }
+}
+
 /// Cached Rafs super block and inode information.
+///
+/// `RafsSuper` is `Send + Sync`, methods to access inodes only take `&self` so the same object
+/// may be shared among threads by [RafsSuperHandle] without extra locking.
 pub struct RafsSuper {
     /// Rafs metadata working mode.
     pub mode: RafsMode,
@@ -533,8 +1032,22 @@ pub struct RafsSuper {
     pub meta: RafsSuperMeta,
     /// Rafs filesystem super block.
     pub superblock: Arc<dyn RafsSuperBlock>,
+    /// Files to prefetch by default, set by [RafsSuper::with_prefetch_hint()].
+    ///
+    /// The paths are resolved and consumed by the first [RafsSuper::prefetch_files()] call
+    /// without an explicit file list.
+    pub prefetch_hint: Mutex<Option<Vec<PathBuf>>>,
 }
 
+/// Reference counted handle to share a [RafsSuper] object among threads.
+pub type RafsSuperHandle = Arc<RafsSuper>;
+
+// Inode lookups rely on `RafsSuper` being shared by reference, so make sure it stays thread safe.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RafsSuper>();
+};
+
 impl Default for RafsSuper {
     fn default() -> Self {
         Self {
@@ -542,18 +1055,57 @@ impl Default for RafsSuper {
             validate_digest: false,
             meta: RafsSuperMeta::default(),
             superblock: Arc::new(NoopSuperBlock::new()),
+            prefetch_hint: Mutex::new(None),
+        }
+    }
+}
+
+// Only print summary information, the superblock may contain millions of inodes.
+impl Debug for RafsSuper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let mut d = f.debug_struct("RafsSuper");
+        if self.meta.is_v5() {
+            d.field("version", &RafsVersion::V5);
+        } else if self.meta.is_v6() {
+            d.field("version", &RafsVersion::V6);
+        } else {
+            d.field("version", &format_args!("{:#x}", self.meta.version));
         }
+        d.field("mode", &self.mode)
+            .field("validate_digest", &self.validate_digest)
+            .field("inodes_count", &self.meta.inodes_count)
+            .field("chunk_size", &self.meta.chunk_size)
+            .field("compressor", &self.meta.get_compressor())
+            .field("digester", &self.meta.get_digest_algorithm())
+            .field(
+                "blob_count",
+                &self
+                    .superblock
+                    .get_blob_source()
+                    .map(|s| s.blob_count())
+                    .unwrap_or_default(),
+            )
+            .finish()
     }
 }
 
 impl RafsSuper {
     /// Create a new `RafsSuper` instance from a `RafsConfig` object.
     pub fn new(conf: &RafsConfig) -> Result<Self> {
-        Ok(Self {
-            mode: RafsMode::from_str(conf.mode.as_str())?,
+        let mut rs = Self {
+            mode: conf.mode.clone(),
             validate_digest: conf.digest_validate,
             ..Default::default()
-        })
+        };
+        // Timeouts are kept by the super block across loading metadata.
+        if let Some(timeout) = conf.attr_timeout_override {
+            rs.meta.attr_timeout = timeout;
+        }
+        if let Some(timeout) = conf.entry_timeout_override {
+            rs.meta.entry_timeout = timeout;
+        }
+
+        Ok(rs)
     }
 
     /// Destroy the filesystem super block.
@@ -586,6 +1138,75 @@ impl RafsSuper {
         Ok(rs)
     }
 
+    /// Load RAFS metadata from an in-memory bootstrap image.
+    ///
+    /// RAFS readers must be backed by a file descriptor, so the data is copied into an anonymous
+    /// file before loading. This works for both `RafsMode::Direct` and `RafsMode::Cached`.
+    pub fn load_from_slice(data: &[u8], mode: RafsMode, validate_digest: bool) -> Result<Self> {
+        let mut rs = RafsSuper {
+            mode,
+            validate_digest,
+            ..Default::default()
+        };
+        let mut reader = <dyn RafsIoRead>::from_slice(data)?;
+
+        rs.load(&mut reader)?;
+
+        Ok(rs)
+    }
+
+    /// Estimate time needed to load a RAFS metadata file in `mode`.
+    ///
+    /// Only the super block is read to detect the RAFS version, and the estimation is based on
+    /// size of the metadata file and empirical loading speed on typical NVMe devices, which may be
+    /// tuned by environment variables [RAFS_LOAD_MS_PER_10MB_DIRECT_ENV] and
+    /// [RAFS_LOAD_MS_PER_10MB_CACHED_ENV]. It's a heuristic instead of a guarantee.
+    pub fn estimate_load_time(path: &Path, mode: RafsMode) -> Result<Duration> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        let mut reader = Box::new(file) as RafsIoReader;
+
+        let mut sb = RafsV5SuperBlock::new();
+        let is_v5 = reader.read_exact(sb.as_mut()).is_ok() && sb.is_rafs_v5();
+        if is_v5 {
+            sb.validate(size)?;
+        } else {
+            reader.seek_to_offset(0)?;
+            let mut sb = RafsV6SuperBlock::new();
+            if sb.load(&mut reader).is_err() || !sb.is_rafs_v6() {
+                return Err(einval!(format!("{:?} is not a RAFS filesystem", path)));
+            }
+            sb.validate(size)?;
+            if mode == RafsMode::Cached {
+                return Err(enosys!("Rafs v6 does not support cached mode"));
+            }
+        }
+
+        let (env, default) = match mode {
+            RafsMode::Direct => (
+                RAFS_LOAD_MS_PER_10MB_DIRECT_ENV,
+                RAFS_LOAD_MS_PER_10MB_DIRECT,
+            ),
+            RafsMode::Cached => (
+                RAFS_LOAD_MS_PER_10MB_CACHED_ENV,
+                RAFS_LOAD_MS_PER_10MB_CACHED,
+            ),
+        };
+        let ms_per_10mb = match std::env::var(env) {
+            Ok(v) => v.trim().parse::<u64>().unwrap_or_else(|_| {
+                warn!(
+                    "invalid value \"{}\" for {}, use default {}",
+                    v, env, default
+                );
+                default
+            }),
+            Err(_) => default,
+        };
+        let us = size as u128 * ms_per_10mb as u128 * 1000 / (10 << 20);
+
+        Ok(Duration::from_micros(us as u64))
+    }
+
     /// Load RAFS metadata and optionally cache inodes.
     pub fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         // Try to load the filesystem as Rafs v5
@@ -601,6 +1222,9 @@ impl RafsSuper {
     }
 
     /// Update the filesystem metadata and storage backend.
+    ///
+    /// Inode objects returned by previous lookups may refer to the old metadata, so the caller
+    /// must make sure no concurrent lookups are in flight when updating a shared [RafsSuperHandle].
     pub fn update(&self, r: &mut RafsIoReader) -> RafsResult<()> {
         if self.meta.is_v5() {
             self.skip_v5_superblock(r)
@@ -631,9 +1255,24 @@ impl RafsSuper {
 
     /// Convert a file path to an inode number.
     pub fn ino_from_path(&self, f: &Path) -> Result<Inode> {
+        self.lookup_ext(f).map(|inode| inode.ino())
+    }
+
+    /// Get the `RafsInode` object corresponding to an absolute file path.
+    pub fn lookup<P: AsRef<Path>>(&self, path: P) -> Result<Arc<dyn RafsInode>> {
+        let ino = self.lookup_ext(path)?.ino();
+        self.get_inode(ino, self.validate_digest)
+    }
+
+    /// Get the `RafsInodeExt` object corresponding to an absolute file path.
+    ///
+    /// The path is resolved component by component from the root directory, and the inode
+    /// object of the last component is returned directly.
+    pub fn lookup_ext<P: AsRef<Path>>(&self, path: P) -> Result<Arc<dyn RafsInodeExt>> {
+        let f = path.as_ref();
         let root_ino = self.superblock.root_ino();
         if f == Path::new("/") {
-            return Ok(root_ino);
+            return self.get_extended_inode(root_ino, self.validate_digest);
         } else if !f.starts_with("/") {
             return Err(einval!());
         }
@@ -643,8 +1282,8 @@ impl RafsSuper {
             .filter(|comp| *comp != Component::RootDir)
             .map(|comp| match comp {
                 Component::Normal(name) => Some(name),
-                Component::ParentDir => Some(OsStr::from_bytes(DOTDOT.as_bytes())),
-                Component::CurDir => Some(OsStr::from_bytes(DOT.as_bytes())),
+                Component::ParentDir => Some(OsStr::new(DOTDOT)),
+                Component::CurDir => Some(OsStr::new(DOT)),
                 _ => None,
             })
             .collect::<Vec<_>>();
@@ -669,7 +1308,7 @@ impl RafsSuper {
             }
         }
 
-        Ok(parent.ino())
+        Ok(parent)
     }
 
     /// Prefetch filesystem and file data to improve performance.
@@ -694,6 +1333,7 @@ impl RafsSuper {
         files: Option<Vec<Inode>>,
         fetcher: &dyn Fn(&mut BlobIoVec, bool),
     ) -> RafsResult<bool> {
+        let files = files.or_else(|| self.take_prefetch_hint());
         // Try to prefetch files according to the list specified by the `--prefetch-files` option.
         if let Some(files) = files {
             // Avoid prefetching multiple times for hardlinks to the same file.
@@ -719,6 +1359,64 @@ impl RafsSuper {
         }
     }
 
+    /// Prefetch filesystem and file data specified by file paths.
+    ///
+    /// It's the same as [RafsSuper::prefetch_files()], except that each path in `paths` is
+    /// resolved to an inode by [RafsSuper::ino_from_path()] first. Paths which fail to be resolved
+    /// are skipped and returned together with the error, instead of stopping the whole prefetch.
+    #[allow(clippy::type_complexity)]
+    pub fn prefetch_files_by_path(
+        &self,
+        device: &BlobDevice,
+        r: &mut RafsIoReader,
+        root_ino: Inode,
+        paths: Option<Vec<PathBuf>>,
+        fetcher: &dyn Fn(&mut BlobIoVec, bool),
+    ) -> RafsResult<(bool, Vec<(PathBuf, Error)>)> {
+        let mut failures = Vec::new();
+        let files = paths.map(|paths| {
+            let mut inodes = Vec::with_capacity(paths.len());
+            for path in paths {
+                match self.ino_from_path(&path) {
+                    Ok(ino) => inodes.push(ino),
+                    Err(e) => failures.push((path, e)),
+                }
+            }
+            inodes
+        });
+
+        self.prefetch_files(device, r, root_ino, files, fetcher)
+            .map(|v| (v, failures))
+    }
+
+    /// Attach a list of files to prefetch by default.
+    ///
+    /// The hint overrides the prefetch table stored in the bootstrap for the first call to
+    /// [RafsSuper::prefetch_files()] with `files` being `None`. Paths are resolved lazily when
+    /// the hint is applied, and those which fail to be resolved are skipped.
+    pub fn with_prefetch_hint(self, hints: Vec<PathBuf>) -> Self {
+        *self.prefetch_hint.lock().unwrap() = Some(hints);
+        self
+    }
+
+    fn take_prefetch_hint(&self) -> Option<Vec<Inode>> {
+        let paths = self.prefetch_hint.lock().unwrap().take()?;
+        let mut inodes = Vec::with_capacity(paths.len());
+        for path in paths {
+            match self.ino_from_path(&path) {
+                Ok(ino) => inodes.push(ino),
+                Err(e) => warn!("skip prefetch hint {}, {}", path.display(), e),
+            }
+        }
+
+        // Fall back to the prefetch table if nothing in the hint could be resolved.
+        if inodes.is_empty() {
+            None
+        } else {
+            Some(inodes)
+        }
+    }
+
     #[inline]
     fn prefetch_inode(
         device: &BlobDevice,
@@ -844,6 +1542,23 @@ impl RafsSuper {
         }
     }
 
+    /// Get build provenance of the filesystem, `None` if it's not recorded by the builder.
+    pub fn provenance(&self) -> Option<&RafsProvenance> {
+        self.meta.provenance.as_ref()
+    }
+
+    /// Get number of extended attribute entries and total size of their names and values.
+    ///
+    /// The statistics are computed on first access if they are not recorded in the super block.
+    pub fn xattr_stats(&mut self) -> Result<(u64, u64)> {
+        if !self.meta.has_xattr_stats() {
+            let (entries, bytes) = compute_xattr_stats(self.superblock.as_ref())?;
+            self.meta.xattr_entry_count = entries;
+            self.meta.xattr_total_bytes = bytes;
+        }
+        Ok((self.meta.xattr_entry_count, self.meta.xattr_total_bytes))
+    }
+
     /// Walk through the file tree rooted at ino, calling cb for each file or directory
     /// in the tree by DFS order, including ino, please ensure ino is a directory.
     pub fn walk_directory<P: AsRef<Path>>(
@@ -859,6 +1574,34 @@ impl RafsSuper {
         self.do_walk_directory(inode.deref(), parent, cb)
     }
 
+    /// Walk a directory like [walk_directory()](Self::walk_directory), and collect statistics
+    /// about the directory tree.
+    pub fn walk_directory_with_stats<P: AsRef<Path>>(
+        &self,
+        ino: Inode,
+        parent: Option<P>,
+        cb: &mut dyn FnMut(&dyn RafsInodeExt, &Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<DirectoryStats> {
+        let mut stats = DirectoryStats::default();
+        let mut visited = HashSet::new();
+        self.walk_directory(ino, parent, &mut |inode: &dyn RafsInodeExt,
+                                               path: &Path|
+         -> anyhow::Result<()> {
+            if inode.is_dir() {
+                stats.dir_count += 1;
+            } else {
+                stats.file_count += 1;
+                if inode.is_reg() && visited.insert(inode.ino()) {
+                    stats.total_logical_bytes += inode.size();
+                    stats.total_compressed_bytes += inode.total_chunk_compressed_size()?;
+                }
+            }
+            cb(inode, path)
+        })?;
+
+        Ok(stats)
+    }
+
     fn do_walk_directory<P: AsRef<Path>>(
         &self,
         inode: &dyn RafsInodeExt,
@@ -881,10 +1624,316 @@ impl RafsSuper {
     }
 }
 
+/// Statistics about a directory tree in a RAFS filesystem.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct DirectoryStats {
+    /// Number of non-directory entries.
+    pub file_count: u64,
+    /// Number of directories, including the walked directory itself.
+    pub dir_count: u64,
+    /// Total size of regular files, hardlinks are counted once.
+    pub total_logical_bytes: u64,
+    /// Total compressed size of data chunks of regular files, hardlinks are counted once.
+    pub total_compressed_bytes: u64,
+}
+
+/// Data locality of a regular file whose chunks span multiple data blobs.
+#[derive(Debug, Serialize)]
+pub struct FileLocality {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Number of data chunks of the file.
+    pub chunks: u32,
+    /// Sorted indexes of data blobs containing chunks of the file.
+    pub blobs: Vec<u32>,
+}
+
+impl RafsSuper {
+    /// List regular files whose data chunks span more than `threshold` data blobs.
+    ///
+    /// Files are sorted by number of blobs spanned in descending order, then by path.
+    pub fn locality_report(&self, threshold: usize) -> anyhow::Result<Vec<FileLocality>> {
+        let mut files = Vec::new();
+        self.walk_directory::<PathBuf>(
+            self.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> anyhow::Result<()> {
+                if inode.is_reg() {
+                    let layout = inode.chunk_layout()?;
+                    let mut blobs = layout.iter().map(|c| c.blob_index).collect::<Vec<_>>();
+                    blobs.sort_unstable();
+                    blobs.dedup();
+                    if blobs.len() > threshold {
+                        files.push(FileLocality {
+                            path: path.to_path_buf(),
+                            chunks: layout.len() as u32,
+                            blobs,
+                        });
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        files.sort_by(|a, b| b.blobs.len().cmp(&a.blobs.len()).then(a.path.cmp(&b.path)));
+
+        Ok(files)
+    }
+}
+
+/// Symlinks found in a RAFS filesystem.
+#[derive(Debug, Default, Serialize)]
+pub struct SymlinkReport {
+    /// Map of symlink path to its target.
+    pub symlinks: HashMap<PathBuf, PathBuf>,
+    /// Sorted paths of symlinks whose target doesn't exist in the filesystem.
+    pub dangling: Vec<PathBuf>,
+}
+
+impl RafsSuper {
+    /// Find all symlinks in the filesystem and detect dangling ones.
+    ///
+    /// Targets are resolved inside the filesystem, so absolute targets are relative to the root
+    /// of the filesystem and intermediate symlinks are followed.
+    pub fn find_all_symlinks(&self) -> anyhow::Result<SymlinkReport> {
+        let mut report = SymlinkReport::default();
+        self.walk_directory::<PathBuf>(
+            self.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> anyhow::Result<()> {
+                if inode.is_symlink() {
+                    let target = inode.get_symlink()?;
+                    if !self.resolve_symlink(path, &target)? {
+                        report.dangling.push(path.to_path_buf());
+                    }
+                    report
+                        .symlinks
+                        .insert(path.to_path_buf(), PathBuf::from(target));
+                }
+                Ok(())
+            },
+        )?;
+        report.dangling.sort();
+
+        Ok(report)
+    }
+
+    /// Find all regular files with the set-user-ID or set-group-ID bit set, sorted by path.
+    pub fn find_setuid_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        self.walk_directory::<PathBuf>(
+            self.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> anyhow::Result<()> {
+                if inode.is_reg() && (inode.is_setuid() || inode.is_setgid()) {
+                    files.push(path.to_path_buf());
+                }
+                Ok(())
+            },
+        )?;
+        files.sort();
+
+        Ok(files)
+    }
+
+    // Check whether `target` of the symlink at `path` resolves to an inode.
+    fn resolve_symlink(&self, path: &Path, target: &OsStr) -> Result<bool> {
+        let root = self.get_extended_inode(self.superblock.root_ino(), false)?;
+        let mut stack = vec![root];
+        // The walked path contains no symlinks, so the parent directory always exists.
+        if let Some(parent) = path.parent() {
+            for comp in parent.components() {
+                if let Component::Normal(name) = comp {
+                    let child = stack[stack.len() - 1].get_child_by_name(name)?;
+                    stack.push(child);
+                }
+            }
+        }
+
+        let mut pending = Path::new(target)
+            .components()
+            .map(|c| c.as_os_str().to_os_string())
+            .collect::<Vec<_>>();
+        pending.reverse();
+        let mut hops = 0;
+        while let Some(name) = pending.pop() {
+            let name = Path::new(&name);
+            match name.components().next() {
+                Some(Component::RootDir) => stack.truncate(1),
+                Some(Component::ParentDir) => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                Some(Component::Normal(name)) => {
+                    let parent = &stack[stack.len() - 1];
+                    if !parent.is_dir() {
+                        return Ok(false);
+                    }
+                    let child = match parent.get_child_by_name(name) {
+                        Ok(v) => v,
+                        Err(_) => return Ok(false),
+                    };
+                    if child.is_symlink() {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Ok(false);
+                        }
+                        let target = child.get_symlink()?;
+                        let mut comps = Path::new(&target)
+                            .components()
+                            .map(|c| c.as_os_str().to_os_string())
+                            .collect::<Vec<_>>();
+                        comps.reverse();
+                        pending.append(&mut comps);
+                    } else {
+                        stack.push(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Number of inodes of each file type in a RAFS filesystem.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct InodeTypeCounts {
+    pub regular: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub char_devices: u64,
+    pub block_devices: u64,
+    pub fifos: u64,
+    pub sockets: u64,
+}
+
+impl RafsSuper {
+    /// Count inodes in the filesystem by file type, hardlinks to the same inode are counted once.
+    pub fn count_inodes_by_type(&self) -> anyhow::Result<InodeTypeCounts> {
+        let mut counts = InodeTypeCounts::default();
+        let mut visited = HashSet::new();
+        self.walk_directory::<PathBuf>(
+            self.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, _path: &Path| -> anyhow::Result<()> {
+                if !visited.insert(inode.ino()) {
+                    return Ok(());
+                }
+                if inode.is_reg() {
+                    counts.regular += 1;
+                } else if inode.is_dir() {
+                    counts.directories += 1;
+                } else if inode.is_symlink() {
+                    counts.symlinks += 1;
+                } else if inode.is_char_device() {
+                    counts.char_devices += 1;
+                } else if inode.is_block_device() {
+                    counts.block_devices += 1;
+                } else if inode.is_fifo() {
+                    counts.fifos += 1;
+                } else if inode.is_socket() {
+                    counts.sockets += 1;
+                }
+                Ok(())
+            },
+        )?;
+
+        Ok(counts)
+    }
+}
+
+/// A RAFS filesystem loaded as chunk dictionary for chunk deduplication.
+///
+/// Chunk dictionaries are loaded with `validate_digest` and `is_chunk_dict` enabled, which skips
+/// some inode validations. So the `RafsSuper` object is wrapped to prevent it from being used as a
+/// regular filesystem, and chunks may only be accessed by their digests.
+pub struct RafsChunkDict(RafsSuper, HashMap<RafsDigest, Arc<dyn BlobChunkInfo>>);
+
+impl RafsChunkDict {
+    /// Load a chunk dictionary from a RAFS v5 or v6 metadata file.
+    pub fn from_metadata(path: &Path) -> Result<Self> {
+        let rs = if Self::is_rafs_v6(path)? {
+            RafsSuper::load_chunk_dict_v6(path)?
+        } else {
+            RafsSuper::load_chunk_dict_from_metadata(path)?
+        };
+        let mut chunks = HashMap::new();
+
+        if !rs.superblock.supports_chunk_info() {
+            let root_ino = rs.superblock.root_ino();
+            rs.walk_directory::<PathBuf>(root_ino, None, &mut |inode, _path| {
+                if inode.is_reg() {
+                    for idx in 0..inode.get_chunk_count() {
+                        let chunk = inode.get_chunk_info(idx)?;
+                        chunks.insert(*chunk.chunk_id(), chunk);
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e| einval!(format!("failed to load chunks from {:?}, {}", path, e)))?;
+        } else {
+            let count = rs.meta.chunk_table_size as usize / size_of::<RafsV5ChunkInfo>();
+            for idx in 0..count {
+                let chunk = rs.superblock.get_chunk_info(idx)?;
+                chunks.insert(*chunk.chunk_id(), chunk);
+            }
+        }
+
+        Ok(RafsChunkDict(rs, chunks))
+    }
+
+    /// Get the chunk with `digest` from the chunk dictionary.
+    pub fn lookup_chunk(&self, digest: &RafsDigest) -> Result<Option<Arc<dyn BlobChunkInfo>>> {
+        Ok(self.1.get(digest).cloned())
+    }
+
+    /// Get number of unique chunks in the chunk dictionary.
+    pub fn chunk_count(&self) -> usize {
+        self.1.len()
+    }
+
+    /// Get all chunks in the chunk dictionary.
+    pub fn chunks(&self) -> impl Iterator<Item = &Arc<dyn BlobChunkInfo>> {
+        self.1.values()
+    }
+
+    /// Get information about blobs referenced by the chunk dictionary.
+    pub fn get_blob_infos(&self) -> Result<Vec<Arc<BlobInfo>>> {
+        self.0.superblock.get_blob_infos(true)
+    }
+
+    /// Get metadata of the RAFS filesystem the chunk dictionary is loaded from.
+    pub fn meta(&self) -> &RafsSuperMeta {
+        &self.0.meta
+    }
+
+    fn is_rafs_v6(path: &Path) -> Result<bool> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        let mut reader = Box::new(file) as RafsIoReader;
+        let mut sb = RafsV6SuperBlock::new();
+
+        Ok(sb.load(&mut reader).is_ok() && sb.is_rafs_v6())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn texture_bootstrap_path(name: &str) -> PathBuf {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        PathBuf::from(root_dir)
+            .join("../tests/texture/bootstrap")
+            .join(name)
+    }
+
+    fn load_texture_bootstrap(name: &str) -> RafsSuper {
+        RafsSuper::load_from_metadata(texture_bootstrap_path(name), RafsMode::Direct, false)
+            .unwrap()
+    }
+
     #[test]
     fn test_rafs_mode() {
         assert!(RafsMode::from_str("").is_err());
@@ -897,6 +1946,702 @@ mod tests {
         assert_eq!(&format!("{}", RafsMode::Cached), "cached");
     }
 
+    #[test]
+    fn test_rafs_super_debug() {
+        let mut rs = RafsSuper::default();
+        rs.meta.version = RAFS_SUPER_VERSION_V6;
+        rs.meta.inodes_count = 10;
+        rs.meta.chunk_size = 0x10_0000;
+        let s = format!("{:?}", rs);
+        assert!(s.starts_with("RafsSuper {"));
+        assert!(s.contains("version: V6"));
+        assert!(s.contains("mode: Direct"));
+        assert!(s.contains("inodes_count: 10"));
+        assert!(s.contains("chunk_size: 1048576"));
+        assert!(s.contains("blob_count: 0"));
+
+        rs.meta.version = 0x700;
+        assert!(format!("{:?}", rs).contains("version: 0x700"));
+    }
+
+    #[test]
+    fn test_rafs_meta_compatibility() {
+        let compressions = [
+            RafsSuperFlags::COMPRESSION_NONE,
+            RafsSuperFlags::COMPRESSION_LZ4,
+            RafsSuperFlags::COMPRESSION_GZIP,
+            RafsSuperFlags::COMPRESSION_ZSTD,
+            RafsSuperFlags::COMPRESSION_SNAPPY,
+        ];
+        let hashes = [RafsSuperFlags::HASH_BLAKE3, RafsSuperFlags::HASH_SHA256];
+        let chunk_sizes = [0x10_0000u32, 0x20_0000u32];
+
+        let mut metas = Vec::new();
+        for compression in compressions {
+            for hash in hashes {
+                for chunk_size in chunk_sizes {
+                    let meta = RafsSuperMeta {
+                        version: RAFS_SUPER_VERSION_V5,
+                        chunk_size,
+                        flags: compression | hash | RafsSuperFlags::HAS_XATTR,
+                        ..Default::default()
+                    };
+                    metas.push(meta);
+                }
+            }
+        }
+
+        for m1 in metas.iter() {
+            for m2 in metas.iter() {
+                let reasons = m1.incompatibility_reasons(m2);
+                let mut expected = 0;
+                if m1.chunk_size != m2.chunk_size {
+                    expected += 1;
+                    assert!(reasons.iter().any(|r| r.starts_with("chunk size")));
+                }
+                if m1.get_compressor() != m2.get_compressor() {
+                    expected += 1;
+                    assert!(reasons.iter().any(|r| r.starts_with("compression")));
+                }
+                if m1.get_digest_algorithm() != m2.get_digest_algorithm() {
+                    expected += 1;
+                    assert!(reasons.iter().any(|r| r.starts_with("digest")));
+                }
+                assert_eq!(reasons.len(), expected);
+                assert_eq!(m1.is_compatible_with(m2), expected == 0);
+                assert_eq!(m1.is_compatible_with(m2), m2.is_compatible_with(m1));
+            }
+        }
+
+        // Flags other than compression and digest algorithms don't matter.
+        let mut m1 = metas[0].clone();
+        m1.flags |= RafsSuperFlags::EXPLICIT_UID_GID;
+        m1.flags.remove(RafsSuperFlags::HAS_XATTR);
+        assert!(m1.is_compatible_with(&metas[0]));
+    }
+
+    #[test]
+    fn test_rafs_mode_serde() {
+        for mode in [RafsMode::Direct, RafsMode::Cached] {
+            let s = serde_json::to_string(&mode).unwrap();
+            assert_eq!(s, format!("\"{}\"", mode));
+            assert_eq!(serde_json::from_str::<RafsMode>(&s).unwrap(), mode);
+        }
+        assert!(serde_json::from_str::<RafsMode>("\"Direct\"").is_err());
+        let err = serde_json::from_str::<RafsMode>("\"mmap\"").unwrap_err();
+        assert!(err.to_string().contains("invalid rafs mode \"mmap\""));
+    }
+
+    #[test]
+    fn test_rafs_load_from_slice() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let data = std::fs::read(&path).unwrap();
+
+        let expected = load_texture_bootstrap("rafs-v5.boot");
+        for mode in [RafsMode::Direct, RafsMode::Cached] {
+            let rs = RafsSuper::load_from_slice(&data, mode, false).unwrap();
+            assert!(rs.meta.is_v5());
+            assert_eq!(rs.meta.inodes_count, expected.meta.inodes_count);
+            assert_eq!(rs.superblock.root_ino(), expected.superblock.root_ino());
+            assert_eq!(
+                rs.ino_from_path(Path::new("/bin")).unwrap(),
+                expected.ino_from_path(Path::new("/bin")).unwrap()
+            );
+        }
+
+        assert!(RafsSuper::load_from_slice(&data[..16], RafsMode::Direct, false).is_err());
+        assert!(RafsSuper::load_from_slice(&[], RafsMode::Cached, false).is_err());
+    }
+
+    #[test]
+    fn test_rafs_provenance() {
+        let mut provenance = RafsProvenance {
+            builder_version: "v2.2.0".to_string(),
+            source_type: "directory".to_string(),
+            compressor: "zstd".to_string(),
+            chunk_size: 0x10_0000,
+            created: 1665987200,
+            annotations: BTreeMap::new(),
+        };
+        provenance.annotations.insert(
+            "org.opencontainers.image.source".to_string(),
+            "https://github.com/dragonflyoss/image-service".to_string(),
+        );
+        let buf = provenance.to_vec().unwrap();
+        assert_eq!(RafsProvenance::from_slice(&buf).unwrap(), provenance);
+        assert!(RafsProvenance::from_slice(&[]).is_err());
+        assert!(RafsProvenance::from_slice(b"{").is_err());
+
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        let mut data = vec![0u8; 16];
+        data.extend_from_slice(&buf);
+        std::fs::write(file.as_path(), &data).unwrap();
+        let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+        reader.seek_to_offset(8).unwrap();
+        let loaded = RafsProvenance::load(&mut reader, 16, buf.len() as u64).unwrap();
+        assert_eq!(loaded, provenance);
+        assert_eq!(reader.seek_plus_offset(0).unwrap(), 8);
+        assert!(RafsProvenance::load(&mut reader, 16, buf.len() as u64 + 1).is_err());
+        assert!(RafsProvenance::load(&mut reader, 16, RAFS_MAX_PROVENANCE_SIZE + 1).is_err());
+
+        let mut large = provenance.clone();
+        large.annotations.insert(
+            "large".to_string(),
+            "x".repeat(RAFS_MAX_PROVENANCE_SIZE as usize),
+        );
+        assert!(large.to_vec().is_err());
+
+        // Metadata blobs generated by old builders have no provenance record.
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        assert!(rs.provenance().is_none());
+    }
+
+    #[test]
+    fn test_rafs_xattr_stats() {
+        let mut meta = RafsSuperMeta::default();
+        assert!(meta.has_xattr_stats());
+        meta.flags |= RafsSuperFlags::HAS_XATTR;
+        assert!(!meta.has_xattr_stats());
+        meta.xattr_entry_count = 2;
+        meta.xattr_total_bytes = 30;
+        assert!(meta.has_xattr_stats());
+
+        let mut rs = load_texture_bootstrap("rafs-v5.boot");
+        let expected = compute_xattr_stats(rs.superblock.as_ref()).unwrap();
+        rs.meta.flags |= RafsSuperFlags::HAS_XATTR;
+        rs.meta.xattr_entry_count = 0;
+        assert_eq!(rs.xattr_stats().unwrap(), expected);
+
+        rs.meta.xattr_entry_count = 2;
+        rs.meta.xattr_total_bytes = 30;
+        assert_eq!(rs.xattr_stats().unwrap(), (2, 30));
+    }
+
+    #[test]
+    fn test_walk_children_inodes_with_result() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        let bin = rs.ino_from_path(Path::new("/bin")).unwrap();
+        let root = rs.get_inode(rs.superblock.root_ino(), false).unwrap();
+
+        let mut visited = 0;
+        let ino = root
+            .walk_children_inodes_with_result(0, &mut |_inode, name, ino, _offset| {
+                visited += 1;
+                if name == "bin" {
+                    Ok(RafsInodeWalkAction::Return(ino))
+                } else {
+                    Ok(RafsInodeWalkAction::Continue)
+                }
+            })
+            .unwrap();
+        assert_eq!(ino, Some(bin));
+        assert!(visited > 2);
+
+        let ino = root
+            .walk_children_inodes_with_result(0, &mut |_inode, _name, _ino, _offset| {
+                Ok(RafsInodeWalkAction::<u64>::Break)
+            })
+            .unwrap();
+        assert!(ino.is_none());
+        let ino = root
+            .walk_children_inodes_with_result(0, &mut |_inode, _name, _ino, _offset| {
+                Ok(RafsInodeWalkAction::<u64>::Continue)
+            })
+            .unwrap();
+        assert!(ino.is_none());
+    }
+
+    #[test]
+    fn test_calculate_compressed_size() {
+        let chunk = |index, blob_index, compressed_offset, compressed_size| ChunkLocation {
+            index,
+            blob_index,
+            compressed_offset,
+            compressed_size,
+        };
+
+        assert_eq!(calculate_compressed_size(&[]), 0);
+        assert_eq!(
+            calculate_compressed_size(&[chunk(0, 1, 0x1000, 0x100)]),
+            0x100
+        );
+        let contiguous = [
+            chunk(0, 1, 0x1000, 0x100),
+            chunk(1, 1, 0x1100, 0x200),
+            chunk(2, 1, 0x1300, 0x300),
+        ];
+        assert_eq!(calculate_compressed_size(&contiguous), 0x600);
+        // Chunks deduplicated within the file.
+        let dedup = [chunk(0, 1, 0x1000, 0x100), chunk(1, 1, 0x1000, 0x100)];
+        assert_eq!(calculate_compressed_size(&dedup), 0x200);
+        let blobs = [chunk(0, 1, 0x1000, 0x100), chunk(1, 2, 0x1100, 0x100)];
+        assert_eq!(calculate_compressed_size(&blobs), 0x200);
+
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        rs.walk_directory::<PathBuf>(rs.superblock.root_ino(), None, &mut |inode, _path| {
+            if inode.is_reg() {
+                let mut size = 0;
+                for idx in 0..inode.get_chunk_count() {
+                    size += inode.get_chunk_info(idx)?.compressed_size() as u64;
+                }
+                assert_eq!(inode.total_chunk_compressed_size()?, size);
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rafs_generation() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        let generation = rs.superblock.generation();
+        let bin = rs.lookup("/bin").unwrap();
+        assert_eq!(bin.get_entry().generation, generation);
+
+        let mut reader = <dyn RafsIoRead>::from_file(&path).unwrap();
+        rs.update(&mut reader).unwrap();
+        assert_eq!(rs.superblock.generation(), generation + 1);
+        assert_eq!(
+            rs.lookup("/bin").unwrap().get_entry().generation,
+            generation + 1
+        );
+
+        // Chunks are resolved by the chunk table of the new bootstrap after updating.
+        let path = texture_bootstrap_path("rafs-v6.boot");
+        let rs = load_texture_bootstrap("rafs-v6.boot");
+        let chunk_offsets = |rs: &RafsSuper| -> Vec<u64> {
+            ["/file-a", "/dir/file-b", "/dir/file-c"]
+                .iter()
+                .map(|p| {
+                    let inode = rs.lookup_ext(p).unwrap();
+                    inode.get_chunk_info(0).unwrap().compressed_offset()
+                })
+                .collect()
+        };
+        let offsets = chunk_offsets(&rs);
+        let generation = rs.superblock.generation();
+
+        // Swap the first two entries of the chunk table, so chunk addresses map to other indexes.
+        let mut buf = std::fs::read(&path).unwrap();
+        let start = rs.meta.chunk_table_offset as usize;
+        let unit = size_of::<RafsV5ChunkInfo>();
+        assert!(rs.meta.chunk_table_size as usize >= 2 * unit);
+        let (first, second) = buf[start..start + 2 * unit].split_at_mut(unit);
+        first.swap_with_slice(second);
+        let tmp_file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(tmp_file.as_path(), &buf).unwrap();
+
+        let mut reader = <dyn RafsIoRead>::from_file(tmp_file.as_path()).unwrap();
+        rs.update(&mut reader).unwrap();
+        assert_eq!(rs.superblock.generation(), generation + 1);
+        assert_eq!(chunk_offsets(&rs), offsets);
+    }
+
+    #[test]
+    fn test_rafs_super_handle() {
+        let rs: RafsSuperHandle = Arc::new(load_texture_bootstrap("rafs-v5.boot"));
+        let expected = rs.ino_from_path(Path::new("/bin")).unwrap();
+
+        let threads = (0..4)
+            .map(|_| {
+                let rs = rs.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(rs.lookup("/bin").unwrap().ino(), expected);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rafs_lookup() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let root = rs.lookup("/").unwrap();
+        assert_eq!(root.ino(), rs.superblock.root_ino());
+        let bin = rs.lookup_ext("/bin").unwrap();
+        assert_eq!(bin.ino(), rs.ino_from_path(Path::new("/bin")).unwrap());
+        assert_eq!(bin.name(), "bin");
+        assert!(rs.lookup("bin").is_err());
+        assert!(rs.lookup_ext("/no-such-file").is_err());
+    }
+
+    #[test]
+    fn test_rafs_estimate_load_time() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        let direct = RafsSuper::estimate_load_time(&path, RafsMode::Direct).unwrap();
+        let cached = RafsSuper::estimate_load_time(&path, RafsMode::Cached).unwrap();
+        assert!(cached > direct);
+        assert_eq!(
+            direct.as_micros(),
+            size as u128 * RAFS_LOAD_MS_PER_10MB_DIRECT as u128 * 1000 / (10 << 20)
+        );
+
+        std::env::set_var(RAFS_LOAD_MS_PER_10MB_CACHED_ENV, "1000000");
+        let tuned = RafsSuper::estimate_load_time(&path, RafsMode::Cached).unwrap();
+        assert_eq!(tuned.as_micros(), size as u128 * 1_000_000_000 / (10 << 20));
+        std::env::set_var(RAFS_LOAD_MS_PER_10MB_CACHED_ENV, "fast");
+        let fallback = RafsSuper::estimate_load_time(&path, RafsMode::Cached).unwrap();
+        assert_eq!(fallback, cached);
+        std::env::remove_var(RAFS_LOAD_MS_PER_10MB_CACHED_ENV);
+
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(file.as_path(), &[0u8; 4096]).unwrap();
+        assert!(RafsSuper::estimate_load_time(file.as_path(), RafsMode::Direct).is_err());
+        assert!(
+            RafsSuper::estimate_load_time(Path::new("/no-such-file"), RafsMode::Direct).is_err()
+        );
+    }
+
+    #[test]
+    fn test_rafs_update_truncated_bootstrap() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let data = std::fs::read(&path).unwrap();
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        let ino = rs.ino_from_path(Path::new("/bin")).unwrap();
+        let blobs = rs.superblock.get_blob_infos(true).unwrap().len();
+
+        let blob_table_end = (rs.meta.blob_table_offset + rs.meta.blob_table_size as u64) as usize;
+        for size in [16, blob_table_end - 8] {
+            let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+            std::fs::write(file.as_path(), &data[..size]).unwrap();
+            let mut reader = Box::new(file.as_file().try_clone().unwrap()) as RafsIoReader;
+            assert!(rs.update(&mut reader).is_err());
+
+            // The filesystem should still work with the pre-update state.
+            assert_eq!(rs.ino_from_path(Path::new("/bin")).unwrap(), ino);
+            assert_eq!(rs.superblock.get_blob_infos(true).unwrap().len(), blobs);
+            assert_eq!(rs.get_inode(ino, false).unwrap().ino(), ino);
+        }
+
+        let mut reader = Box::new(std::fs::File::open(&path).unwrap()) as RafsIoReader;
+        rs.update(&mut reader).unwrap();
+        assert_eq!(rs.ino_from_path(Path::new("/bin")).unwrap(), ino);
+    }
+
+    #[test]
+    fn test_rafs_count_inodes_by_type() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let counts = rs.count_inodes_by_type().unwrap();
+        let mut inodes = HashMap::new();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, _path: &Path| -> anyhow::Result<()> {
+                inodes.insert(inode.ino(), inode.get_attr().mode & libc::S_IFMT);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let count = |fmt: u32| inodes.values().filter(|v| **v == fmt).count() as u64;
+        assert!(counts.directories > 0);
+        assert!(counts.regular > 0);
+        assert!(counts.symlinks > 0);
+        assert_eq!(counts.regular, count(libc::S_IFREG));
+        assert_eq!(counts.directories, count(libc::S_IFDIR));
+        assert_eq!(counts.symlinks, count(libc::S_IFLNK));
+        assert_eq!(counts.char_devices, count(libc::S_IFCHR));
+        assert_eq!(counts.block_devices, count(libc::S_IFBLK));
+        assert_eq!(counts.fifos, count(libc::S_IFIFO));
+        assert_eq!(counts.sockets, count(libc::S_IFSOCK));
+    }
+
+    #[test]
+    fn test_rafs_walk_directory_with_stats() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let mut entries = 0;
+        let stats = rs
+            .walk_directory_with_stats::<PathBuf>(
+                rs.superblock.root_ino(),
+                None,
+                &mut |_inode: &dyn RafsInodeExt, _path: &Path| -> anyhow::Result<()> {
+                    entries += 1;
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(stats.file_count + stats.dir_count, entries);
+
+        let counts = rs.count_inodes_by_type().unwrap();
+        assert_eq!(stats.dir_count, counts.directories);
+        assert!(stats.file_count >= counts.regular);
+        assert!(stats.total_logical_bytes > 0);
+        assert!(stats.total_compressed_bytes > 0);
+
+        let mut sizes = HashMap::new();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, _path: &Path| -> anyhow::Result<()> {
+                if inode.is_reg() {
+                    sizes.insert(inode.ino(), inode.size());
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.total_logical_bytes, sizes.values().sum::<u64>());
+    }
+
+    #[test]
+    fn test_rafs_compare_content() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        let rs2 = RafsSuper::load_from_metadata(&path, RafsMode::Cached, false).unwrap();
+
+        let mut files = Vec::new();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> anyhow::Result<()> {
+                let other = rs2.get_extended_inode(rs2.ino_from_path(path)?, false)?;
+                let equal = inode.compare_content(other.deref())?;
+                assert_eq!(equal, !inode.is_dir(), "{}", path.display());
+                if inode.is_reg() && inode.size() > 0 {
+                    files.push(path.to_path_buf());
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let inodes = files
+            .iter()
+            .map(|p| {
+                rs.get_extended_inode(rs.ino_from_path(p).unwrap(), false)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut checked = false;
+        for inode in inodes.iter() {
+            if inode.size() != inodes[0].size() {
+                assert!(!inode.compare_content(inodes[0].deref()).unwrap());
+                assert!(!inodes[0].compare_content(inode.deref()).unwrap());
+                checked = true;
+                break;
+            }
+        }
+        assert!(checked);
+    }
+
+    #[test]
+    fn test_rafs_find_setuid_files() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let files = rs.find_setuid_files().unwrap();
+        let mut expected = Vec::new();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> anyhow::Result<()> {
+                let mode = inode.get_attr().mode;
+                assert_eq!(inode.is_setuid(), mode & libc::S_ISUID != 0);
+                assert_eq!(inode.is_setgid(), mode & libc::S_ISGID != 0);
+                assert_eq!(inode.is_sticky(), mode & libc::S_ISVTX != 0);
+                if inode.is_reg() && mode & (libc::S_ISUID | libc::S_ISGID) != 0 {
+                    expected.push(path.to_path_buf());
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn test_rafs_find_all_symlinks() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let report = rs.find_all_symlinks().unwrap();
+        for (link, target) in report.symlinks.iter() {
+            let inode = rs.lookup(link).unwrap();
+            assert!(inode.is_symlink());
+            assert_eq!(&PathBuf::from(inode.get_symlink().unwrap()), target);
+        }
+        for link in report.dangling.iter() {
+            assert!(report.symlinks.contains_key(link));
+        }
+    }
+
+    #[test]
+    fn test_rafs_prefetch_files_by_path() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+        let device = BlobDevice::default();
+        let mut reader = Box::new(std::fs::File::open(&path).unwrap()) as RafsIoReader;
+        let root_ino = rs.superblock.root_ino();
+        let fetcher = |desc: &mut BlobIoVec, _last: bool| desc.reset();
+
+        let paths = vec![
+            PathBuf::from("/bin"),
+            PathBuf::from("/no-such-file"),
+            PathBuf::from("bin"),
+        ];
+        let (prefetch_all, failures) = rs
+            .prefetch_files_by_path(&device, &mut reader, root_ino, Some(paths), &fetcher)
+            .unwrap();
+        assert!(!prefetch_all);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].0, PathBuf::from("/no-such-file"));
+        assert_eq!(failures[0].1.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(failures[1].0, PathBuf::from("bin"));
+        assert_eq!(failures[1].1.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_rafs_with_prefetch_hint() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let rs = load_texture_bootstrap("rafs-v5.boot")
+            .with_prefetch_hint(vec![PathBuf::from("/bin"), PathBuf::from("/no-such-file")]);
+        let device = BlobDevice::default();
+        let mut reader = Box::new(std::fs::File::open(&path).unwrap()) as RafsIoReader;
+        let root_ino = rs.superblock.root_ino();
+        let fetcher = |desc: &mut BlobIoVec, _last: bool| desc.reset();
+
+        assert!(rs.prefetch_hint.lock().unwrap().is_some());
+        let prefetch_all = rs
+            .prefetch_files(&device, &mut reader, root_ino, None, &fetcher)
+            .unwrap();
+        assert!(!prefetch_all);
+        // The hint is only applied once.
+        assert!(rs.prefetch_hint.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rafs_locality_report() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        // All data chunks are stored in a single blob.
+        assert!(rs.locality_report(1).unwrap().is_empty());
+        let files = rs.locality_report(0).unwrap();
+        assert!(!files.is_empty());
+        for file in files.iter() {
+            assert_eq!(file.blobs.len(), 1);
+            let inode = rs.lookup_ext(&file.path).unwrap();
+            let layout = inode.chunk_layout().unwrap();
+            assert_eq!(layout.len() as u32, file.chunks);
+            for (idx, chunk) in layout.iter().enumerate() {
+                assert_eq!(chunk.index, idx as u32);
+                assert_eq!(chunk.blob_index, file.blobs[0]);
+            }
+        }
+
+        let root = rs
+            .get_extended_inode(rs.superblock.root_ino(), false)
+            .unwrap();
+        let err = root.chunk_layout().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_rafs_inode_compute_path() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        let root = rs
+            .get_extended_inode(rs.superblock.root_ino(), false)
+            .unwrap();
+        assert!(root.get_parent_inode().unwrap().is_none());
+        assert_eq!(root.compute_path().unwrap(), PathBuf::from("/"));
+
+        let ino = rs.ino_from_path(Path::new("/bin")).unwrap();
+        let inode = rs.get_extended_inode(ino, false).unwrap();
+        let parent = inode.get_parent_inode().unwrap().unwrap();
+        assert_eq!(parent.ino(), root.ino());
+        assert_eq!(inode.compute_path().unwrap(), PathBuf::from("/bin"));
+
+        for file in rs.locality_report(0).unwrap().iter() {
+            let ino = rs.ino_from_path(&file.path).unwrap();
+            let inode = rs.get_extended_inode(ino, false).unwrap();
+            assert_eq!(
+                inode.compute_path().unwrap(),
+                rs.path_from_ino(ino).unwrap()
+            );
+        }
+
+        let rs = RafsSuper::load_from_metadata(&path, RafsMode::Cached, false).unwrap();
+        let inode = rs.get_extended_inode(ino, false).unwrap();
+        let err = inode.compute_path().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSYS));
+    }
+
+    #[test]
+    fn test_rafs_get_chunk_range() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, _path: &Path| -> anyhow::Result<()> {
+                let count = inode.get_chunk_count();
+                if !inode.is_reg() || count == 0 {
+                    return Ok(());
+                }
+                let size = inode.size();
+                assert_eq!(inode.get_chunk_range(0, size as usize).unwrap(), 0..count);
+                assert_eq!(inode.get_chunk_range(0, usize::MAX).unwrap(), 0..count);
+                assert!(inode.get_chunk_range(0, 0).unwrap().is_empty());
+                assert!(inode.get_chunk_range(size, 1).unwrap().is_empty());
+                assert!(inode.get_chunk_range(u64::MAX, 1).is_err());
+                let chunk_info = |idx: u32| {
+                    let chunk = inode.get_chunk_info(idx).unwrap();
+                    crate::metadata::chunk::ChunkWrapper::from_chunk_info(chunk.as_ref())
+                };
+                for idx in 0..count {
+                    let chunk = chunk_info(idx);
+                    let offset = chunk.file_offset();
+                    assert_eq!(inode.get_chunk_range(offset, 1).unwrap(), idx..idx + 1);
+                    let len = chunk.uncompressed_size() as usize;
+                    assert_eq!(inode.get_chunk_range(offset, len).unwrap(), idx..idx + 1);
+                }
+                if count > 1 {
+                    let chunk = chunk_info(1);
+                    let range = inode.get_chunk_range(chunk.file_offset() - 1, 2).unwrap();
+                    assert_eq!(range, 0..2);
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rafs_v5_get_chunk_info_unsupported() {
+        let rs = load_texture_bootstrap("rafs-v5.boot");
+
+        assert!(!rs.superblock.supports_chunk_info());
+        let err = rs.superblock.get_chunk_info(0).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_rafs_chunk_dict() {
+        let path = texture_bootstrap_path("rafs-v5.boot");
+
+        let dict = RafsChunkDict::from_metadata(&path).unwrap();
+        assert_eq!(dict.get_blob_infos().unwrap().len(), 18);
+        assert!(dict.chunk_count() > 0);
+        assert_eq!(dict.chunks().count(), dict.chunk_count());
+        for chunk in dict.chunks() {
+            let found = dict.lookup_chunk(chunk.chunk_id()).unwrap().unwrap();
+            assert_eq!(found.chunk_id(), chunk.chunk_id());
+        }
+        assert!(dict.lookup_chunk(&RafsDigest::default()).unwrap().is_none());
+
+        assert!(RafsSuper::load_chunk_dict_v6(&path).is_err());
+        assert!(RafsChunkDict::from_metadata(Path::new("/non-existent")).is_err());
+    }
+
     #[test]
     fn test_rafs_compressor() {
         assert_eq!(
@@ -915,6 +2660,14 @@ mod tests {
             compress::Algorithm::from(RafsSuperFlags::COMPRESSION_ZSTD),
             compress::Algorithm::Zstd
         );
+        assert_eq!(
+            compress::Algorithm::from(RafsSuperFlags::COMPRESSION_SNAPPY),
+            compress::Algorithm::Snappy
+        );
+        assert_eq!(
+            RafsSuperFlags::from(compress::Algorithm::Snappy),
+            RafsSuperFlags::COMPRESSION_SNAPPY
+        );
         assert_eq!(
             compress::Algorithm::from(
                 RafsSuperFlags::COMPRESSION_ZSTD | RafsSuperFlags::COMPRESSION_LZ4,
@@ -50,8 +50,8 @@ impl RafsSuperBlock for NoopSuperBlock {
 
     fn destroy(&mut self) {}
 
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
-        Vec::new()
+    fn get_blob_infos(&self, _force: bool) -> Result<Vec<Arc<BlobInfo>>> {
+        Ok(Vec::new())
     }
 
     fn root_ino(&self) -> u64 {
@@ -54,6 +54,10 @@ impl BlobChunkInfo for MockChunkInfo {
         self.c_flags.contains(BlobChunkFlags::COMPRESSED)
     }
 
+    fn is_zeroed(&self) -> bool {
+        self.c_flags.contains(BlobChunkFlags::ZEROED)
+    }
+
     fn chunk_id(&self) -> &RafsDigest {
         &self.c_block_id
     }
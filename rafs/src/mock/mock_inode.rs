@@ -7,6 +7,7 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::io::Result;
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
 use std::sync::Arc;
 
@@ -14,16 +15,22 @@ use fuse_backend_rs::abi::fuse_abi;
 use fuse_backend_rs::api::filesystem::Entry;
 use nydus_storage::device::v5::BlobV5ChunkInfo;
 use nydus_storage::device::{BlobChunkInfo, BlobDevice, BlobInfo, BlobIoVec};
-use nydus_utils::{digest::RafsDigest, ByteSize};
+use nydus_utils::{
+    digest::{Algorithm, RafsDigest},
+    ByteSize,
+};
 
 use super::mock_chunk::MockChunkInfo;
 use super::mock_super::CHUNK_SIZE;
 use crate::metadata::layout::v5::{
-    rafsv5_alloc_bio_vecs, RafsV5BlobTable, RafsV5InodeChunkOps, RafsV5InodeFlags, RafsV5InodeOps,
+    rafsv5_alloc_bio_vecs, rafsv5_get_chunk_range, RafsV5BlobTable, RafsV5InodeChunkOps,
+    RafsV5InodeFlags, RafsV5InodeOps,
 };
 use crate::metadata::{
+    calculate_chunk_layout, calculate_content_hash,
     layout::{XattrName, XattrValue},
-    Inode, RafsInode, RafsInodeWalkHandler, RafsSuperMeta, RAFS_ATTR_BLOCK_SIZE,
+    ChunkLocation, Inode, RafsInode, RafsInodeWalkAction, RafsInodeWalkHandler, RafsSuperMeta, DOT,
+    DOTDOT, RAFS_ATTR_BLOCK_SIZE,
 };
 use crate::RafsInodeExt;
 
@@ -71,6 +78,40 @@ impl MockInode {
             ..Default::default()
         }
     }
+
+    pub fn mock_dir(ino: Inode, parent: Inode, name: &str, children: Vec<Arc<MockInode>>) -> Self {
+        Self {
+            i_ino: ino,
+            i_parent: parent,
+            i_name: OsString::from(name),
+            i_mode: libc::S_IFDIR as u32 | 0o755,
+            i_nlink: 2,
+            i_child_cnt: children.len() as u32,
+            i_child: children,
+            i_blksize: CHUNK_SIZE,
+            ..Default::default()
+        }
+    }
+
+    pub fn mock_file(ino: Inode, parent: Inode, name: &str) -> Self {
+        Self {
+            i_ino: ino,
+            i_parent: parent,
+            i_name: OsString::from(name),
+            i_mode: libc::S_IFREG as u32 | 0o644,
+            i_nlink: 1,
+            i_blksize: CHUNK_SIZE,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_nlink(&mut self, nlink: u32) {
+        self.i_nlink = nlink;
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.i_size = size;
+    }
 }
 
 impl RafsInode for MockInode {
@@ -107,12 +148,42 @@ impl RafsInode for MockInode {
         }
     }
 
-    fn walk_children_inodes(
-        &self,
-        _entry_offset: u64,
-        _handler: RafsInodeWalkHandler,
-    ) -> Result<()> {
-        todo!()
+    fn walk_children_inodes(&self, entry_offset: u64, handler: RafsInodeWalkHandler) -> Result<()> {
+        // offset 0 and 1 is for "." and ".." respectively.
+        let mut cur_offset = entry_offset;
+
+        if cur_offset == 0 {
+            cur_offset += 1;
+            match handler(None, OsString::from(DOT), self.i_ino, cur_offset)? {
+                RafsInodeWalkAction::Continue => {}
+                RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_) => return Ok(()),
+            }
+        }
+
+        if cur_offset == 1 {
+            cur_offset += 1;
+            match handler(None, OsString::from(DOTDOT), self.i_parent, cur_offset)? {
+                RafsInodeWalkAction::Continue => {}
+                RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_) => return Ok(()),
+            }
+        }
+
+        let mut idx = cur_offset - 2;
+        while idx < self.i_child.len() as u64 {
+            let child = &self.i_child[idx as usize];
+            cur_offset += 1;
+            match handler(
+                Some(child.clone() as Arc<dyn RafsInode>),
+                child.i_name.clone(),
+                child.i_ino,
+                cur_offset,
+            )? {
+                RafsInodeWalkAction::Continue => idx += 1,
+                RafsInodeWalkAction::Break | RafsInodeWalkAction::Return(_) => break,
+            }
+        }
+
+        Ok(())
     }
 
     fn get_symlink(&self) -> Result<OsString> {
@@ -157,6 +228,14 @@ impl RafsInode for MockInode {
         self.get_child_count()
     }
 
+    fn content_hash(&self) -> Result<RafsDigest> {
+        calculate_content_hash(self, Algorithm::Blake3)
+    }
+
+    fn chunk_layout(&self) -> Result<Vec<ChunkLocation>> {
+        calculate_chunk_layout(self)
+    }
+
     fn has_xattr(&self) -> bool {
         self.i_flags.contains(RafsV5InodeFlags::XATTR)
     }
@@ -174,6 +253,11 @@ impl RafsInode for MockInode {
             .collect::<Vec<XattrName>>())
     }
 
+    #[inline]
+    fn xattr_count(&self) -> u32 {
+        self.i_xattr.len() as u32
+    }
+
     fn is_dir(&self) -> bool {
         self.i_mode & libc::S_IFMT as u32 == libc::S_IFDIR as u32
     }
@@ -229,6 +313,10 @@ impl RafsInode for MockInode {
         rafsv5_alloc_bio_vecs(self, offset, size, user_io)
     }
 
+    fn get_chunk_range(&self, offset: u64, len: usize) -> Result<Range<u32>> {
+        rafsv5_get_chunk_range(self, offset, len)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -5,10 +5,12 @@
 
 use std::collections::HashMap;
 use std::io::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use nydus_storage::device::BlobInfo;
 
+use crate::metadata::layout::RAFS_V5_ROOT_INODE;
 use crate::metadata::{Inode, RafsInode, RafsSuperBlock, RafsSuperInodes};
 use crate::mock::MockInode;
 use crate::{RafsInodeExt, RafsIoReader, RafsResult};
@@ -16,6 +18,8 @@ use crate::{RafsInodeExt, RafsIoReader, RafsResult};
 #[derive(Default)]
 pub struct MockSuperBlock {
     pub inodes: HashMap<Inode, Arc<MockInode>>,
+    /// Number of inode lookups served by the super block.
+    pub lookups: AtomicUsize,
 }
 
 pub const CHUNK_SIZE: u32 = 200;
@@ -24,6 +28,7 @@ impl MockSuperBlock {
     pub fn new() -> Self {
         Self {
             inodes: HashMap::new(),
+            lookups: AtomicUsize::new(0),
         }
     }
 }
@@ -34,6 +39,7 @@ impl RafsSuperInodes for MockSuperBlock {
     }
 
     fn get_inode(&self, ino: Inode, _validate_inode: bool) -> Result<Arc<dyn RafsInode>> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
         self.inodes
             .get(&ino)
             .map_or(Err(enoent!()), |i| Ok(i.clone()))
@@ -44,6 +50,7 @@ impl RafsSuperInodes for MockSuperBlock {
         ino: Inode,
         _validate_inode: bool,
     ) -> Result<Arc<dyn RafsInodeExt>> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
         self.inodes
             .get(&ino)
             .map_or(Err(enoent!()), |i| Ok(i.clone()))
@@ -58,11 +65,11 @@ impl RafsSuperBlock for MockSuperBlock {
         unimplemented!()
     }
     fn destroy(&mut self) {}
-    fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
+    fn get_blob_infos(&self, _force: bool) -> Result<Vec<Arc<BlobInfo>>> {
         unimplemented!()
     }
 
     fn root_ino(&self) -> u64 {
-        unimplemented!()
+        RAFS_V5_ROOT_INODE
     }
 }
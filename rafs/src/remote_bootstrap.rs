@@ -0,0 +1,315 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access RAFS metadata blobs stored on storage backends.
+//!
+//! The [BackendBootstrapReader](struct.BackendBootstrapReader.html) implements `RafsIoRead` over
+//! a storage backend, so the bootstrap may be fetched lazily from the same registry/OSS backend
+//! as data blobs instead of from a local file. Data is read through a small in-memory page cache,
+//! which is enough for `RafsMode::Cached` to load the filesystem. `RafsMode::Direct` needs a
+//! file descriptor to mmap, so the whole metadata blob should be downloaded into an anonymous
+//! file by [BackendBootstrapReader::download()](struct.BackendBootstrapReader.html#method.download)
+//! before loading the filesystem.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use nydus_api::http::BackendConfig;
+use storage::backend::{BackendResult, BlobBackend, BlobReader};
+use storage::factory::BlobFactory;
+
+use crate::{new_anonymous_file, RafsIoRead};
+
+/// Size of pages cached in memory.
+const BOOTSTRAP_PAGE_SIZE: u64 = 0x10000;
+/// Maximum number of pages cached in memory.
+const BOOTSTRAP_PAGE_CACHE_CAPACITY: usize = 256;
+/// Maximum number of retries on backend errors.
+const BOOTSTRAP_RETRY_LIMIT: u32 = 5;
+/// Delay before the first retry, doubled for each following retry.
+const BOOTSTRAP_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A `RafsIoReader` to read RAFS metadata blobs from storage backends.
+pub struct BackendBootstrapReader {
+    blob_id: String,
+    backend: Arc<dyn BlobBackend + Send + Sync>,
+    reader: Arc<dyn BlobReader>,
+    size: u64,
+    pos: u64,
+    pages: HashMap<u64, Arc<Vec<u8>>>,
+    lru: VecDeque<u64>,
+}
+
+impl BackendBootstrapReader {
+    /// Create a new instance of `BackendBootstrapReader` to access metadata blob `blob_id`.
+    pub fn new(config: BackendConfig, blob_id: &str) -> Result<Self> {
+        let backend = BlobFactory::new_backend(config, blob_id)?;
+        let reader = backend.get_reader(blob_id).map_err(|e| {
+            eio!(format!(
+                "failed to get reader for blob {}, {:?}",
+                blob_id, e
+            ))
+        })?;
+        let size = retry_backend_op(blob_id, || reader.blob_size())?;
+
+        Ok(BackendBootstrapReader {
+            blob_id: blob_id.to_string(),
+            backend,
+            reader,
+            size,
+            pos: 0,
+            pages: HashMap::new(),
+            lru: VecDeque::new(),
+        })
+    }
+
+    /// Get size of the metadata blob.
+    pub fn blob_size(&self) -> u64 {
+        self.size
+    }
+
+    fn get_page(&mut self, index: u64) -> Result<Arc<Vec<u8>>> {
+        if let Some(page) = self.pages.get(&index) {
+            // Move the page to the most recently used end.
+            if let Some(pos) = self.lru.iter().position(|v| *v == index) {
+                self.lru.remove(pos);
+            }
+            self.lru.push_back(index);
+            return Ok(page.clone());
+        }
+
+        let offset = index * BOOTSTRAP_PAGE_SIZE;
+        let len = cmp::min(BOOTSTRAP_PAGE_SIZE, self.size - offset) as usize;
+        let mut buf = vec![0u8; len];
+        self.read_exact_at(&mut buf, offset)?;
+
+        let page = Arc::new(buf);
+        if self.lru.len() >= BOOTSTRAP_PAGE_CACHE_CAPACITY {
+            if let Some(victim) = self.lru.pop_front() {
+                self.pages.remove(&victim);
+            }
+        }
+        self.pages.insert(index, page.clone());
+        self.lru.push_back(index);
+
+        Ok(page)
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let sz = retry_backend_op(&self.blob_id, || {
+                self.reader.read(&mut buf[pos..], offset + pos as u64)
+            })?;
+            if sz == 0 {
+                return Err(eio!(format!(
+                    "unexpected EOF when reading metadata blob {} at offset {}",
+                    self.blob_id,
+                    offset + pos as u64
+                )));
+            }
+            pos += sz;
+        }
+
+        Ok(())
+    }
+
+    /// Download the whole metadata blob into an anonymous file.
+    pub fn download(&self) -> Result<File> {
+        let mut file = new_anonymous_file(&self.blob_id)?;
+        let mut buf = vec![0u8; BOOTSTRAP_PAGE_SIZE as usize];
+        let mut offset = 0;
+
+        while offset < self.size {
+            let len = cmp::min(BOOTSTRAP_PAGE_SIZE, self.size - offset) as usize;
+            self.read_exact_at(&mut buf[..len], offset)?;
+            file.write_all(&buf[..len])?;
+            offset += len as u64;
+        }
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(file)
+    }
+}
+
+impl Read for BackendBootstrapReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() || self.pos >= self.size {
+            return Ok(0);
+        }
+
+        let index = self.pos / BOOTSTRAP_PAGE_SIZE;
+        let page = self.get_page(index)?;
+        let offset = (self.pos - index * BOOTSTRAP_PAGE_SIZE) as usize;
+        let len = cmp::min(buf.len(), page.len() - offset);
+        buf[..len].copy_from_slice(&page[offset..offset + len]);
+        self.pos += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl Seek for BackendBootstrapReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add_signed(self.size, offset),
+            SeekFrom::Current(offset) => checked_add_signed(self.pos, offset),
+        };
+
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(einval!(
+                "invalid seek to a negative or overflowing position"
+            )),
+        }
+    }
+}
+
+impl AsRawFd for BackendBootstrapReader {
+    /// The reader has no local file, so an invalid file descriptor is returned and mmap based
+    /// `RafsMode::Direct` fails to load from it. Use `download()` to get a local copy instead.
+    fn as_raw_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+impl RafsIoRead for BackendBootstrapReader {}
+
+impl Drop for BackendBootstrapReader {
+    fn drop(&mut self) {
+        self.backend.shutdown();
+    }
+}
+
+fn checked_add_signed(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
+
+fn retry_backend_op<T, F>(blob_id: &str, mut op: F) -> Result<T>
+where
+    F: FnMut() -> BackendResult<T>,
+{
+    let mut delay = BOOTSTRAP_RETRY_DELAY;
+    let mut retry = 0;
+
+    loop {
+        match op() {
+            Ok(v) => return Ok(v),
+            Err(e) if retry < BOOTSTRAP_RETRY_LIMIT => {
+                retry += 1;
+                warn!(
+                    "failed to access metadata blob {}, {:?}, retry {} after {:?}",
+                    blob_id, e, retry, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(e) => {
+                return Err(eio!(format!(
+                    "failed to access metadata blob {}, {:?}",
+                    blob_id, e
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{RafsMode, RafsSuper};
+    use crate::RafsIoReader;
+    use std::path::{Path, PathBuf};
+
+    fn localfs_config() -> BackendConfig {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let dir = PathBuf::from(root_dir).join("../tests/texture/bootstrap");
+        BackendConfig {
+            backend_type: "localfs".to_string(),
+            backend_config: serde_json::json!({ "dir": dir.to_str().unwrap() }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backend_bootstrap_reader() {
+        let mut reader = BackendBootstrapReader::new(localfs_config(), "rafs-v5.boot").unwrap();
+        let size = reader.blob_size();
+        assert!(size > BOOTSTRAP_PAGE_SIZE);
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len() as u64, size);
+
+        assert_eq!(reader.seek(SeekFrom::End(-8)).unwrap(), size - 8);
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+        assert_eq!(&buf[..8], &data[data.len() - 8..]);
+        assert!(reader.seek(SeekFrom::Current(-(size as i64) - 1)).is_err());
+
+        let mut file = reader.download().unwrap();
+        let mut local = Vec::new();
+        file.read_to_end(&mut local).unwrap();
+        assert_eq!(local, data);
+
+        assert!(BackendBootstrapReader::new(localfs_config(), "non-existent").is_err());
+    }
+
+    #[test]
+    fn test_page_cache_lru() {
+        let mut reader = BackendBootstrapReader::new(localfs_config(), "rafs-v5.boot").unwrap();
+        let page0 = reader.get_page(0).unwrap();
+        reader.get_page(1).unwrap();
+        assert_eq!(reader.lru, [0, 1]);
+
+        // Cache hits move pages to the most recently used end.
+        assert!(Arc::ptr_eq(&reader.get_page(0).unwrap(), &page0));
+        assert_eq!(reader.lru, [1, 0]);
+        assert_eq!(reader.pages.len(), 2);
+    }
+
+    #[test]
+    fn test_load_rafs_from_backend() {
+        for mode in [RafsMode::Direct, RafsMode::Cached] {
+            let mut reader =
+                <dyn RafsIoRead>::from_backend(localfs_config(), "rafs-v5.boot", mode.clone())
+                    .unwrap();
+            let mut rs = RafsSuper {
+                mode,
+                validate_digest: false,
+                ..Default::default()
+            };
+            rs.load(&mut reader).unwrap();
+            assert!(rs.meta.is_v5());
+            assert!(rs.ino_from_path(Path::new("/bin")).is_ok());
+        }
+
+        // Direct mode can't mmap the metadata blob without a local copy.
+        let reader = BackendBootstrapReader::new(localfs_config(), "rafs-v5.boot").unwrap();
+        let mut rs = RafsSuper {
+            mode: RafsMode::Direct,
+            validate_digest: false,
+            ..Default::default()
+        };
+        assert!(rs.load(&mut (Box::new(reader) as RafsIoReader)).is_err());
+        assert!(
+            <dyn RafsIoRead>::from_backend(localfs_config(), "non-existent", RafsMode::Direct)
+                .is_err()
+        );
+    }
+}
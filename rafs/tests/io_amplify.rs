@@ -6,13 +6,13 @@ extern crate assert_matches;
 use std::sync::Arc;
 
 use rafs::fs::RafsConfig;
-use rafs::metadata::RafsSuper;
+use rafs::metadata::{RafsMode, RafsSuper};
 use rafs::mock::{MockChunkInfo, MockInode, MockSuperBlock, CHUNK_SIZE};
 
 #[test]
 fn test_user_io_amplification_lack_chunks_small_expected() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -58,7 +58,7 @@ fn test_user_io_amplification_lack_chunks_small_expected() {
 #[test]
 fn test_user_io_amplification_lack_chunks_normal_expected() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -84,7 +84,7 @@ fn test_user_io_amplification_lack_chunks_normal_expected() {
 #[test]
 fn test_user_io_amplification_large_boundary() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -172,7 +172,7 @@ fn test_user_io_amplification_large_boundary() {
 #[test]
 fn test_user_io_amplification_sparse_inodes() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -228,7 +228,7 @@ fn test_user_io_amplification_sparse_inodes() {
 #[test]
 fn test_user_io_amplification_2_inodes_4_chunks_3_amplified() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -287,7 +287,7 @@ fn test_user_io_amplification_2_inodes_4_chunks_3_amplified() {
 #[test]
 fn test_user_io_amplification_huge_expected() {
     let mut rafs_config = RafsConfig::new();
-    rafs_config.mode = "cached".to_string();
+    rafs_config.mode = RafsMode::Cached;
     let mut super_sb = RafsSuper::new(&rafs_config).unwrap();
     let mut rafs_super_block = MockSuperBlock::new();
 
@@ -1,13 +0,0 @@
-// Copyright 2020 Ant Group. All rights reserved.
-//
-// SPDX-License-Identifier: Apache-2.0
-
-pub(crate) mod blob;
-pub(crate) mod blob_compact;
-pub(crate) mod bootstrap;
-pub(crate) mod chunk_dict;
-pub(crate) mod context;
-pub(crate) mod layout;
-pub(crate) mod node;
-pub(crate) mod prefetch;
-pub(crate) mod tree;
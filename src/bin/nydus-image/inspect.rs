@@ -13,13 +13,15 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use anyhow::Context;
 use nydus_rafs::metadata::{RafsInode, RafsInodeExt, RafsInodeWalkAction, RafsSuper};
 use nydus_rafs::{RafsIoRead, RafsIoReader};
 use nydus_storage::device::BlobChunkInfo;
 use serde_json::Value;
 
 pub(crate) struct RafsInspector {
-    request_mode: bool,
+    // Output results in JSON instead of human readable text
+    json_output: bool,
     // Rafs Meta Data
     rafs_meta: RafsSuper,
     // Bootstrap
@@ -34,7 +36,7 @@ pub(crate) struct RafsInspector {
 
 impl RafsInspector {
     // create the RafsInspector
-    pub fn new(bootstrap_path: &Path, request_mode: bool) -> Result<Self, anyhow::Error> {
+    pub fn new(bootstrap_path: &Path, json_output: bool) -> Result<Self, anyhow::Error> {
         // Load Bootstrap
         let mut f = <dyn RafsIoRead>::from_file(bootstrap_path)
             .map_err(|e| anyhow!("Can't find bootstrap, path={:?}, {:?}", bootstrap_path, e))?;
@@ -49,7 +51,7 @@ impl RafsInspector {
         let root_ino = rafs_meta.superblock.root_ino();
 
         Ok(RafsInspector {
-            request_mode,
+            json_output,
             rafs_meta,
             bootstrap: Arc::new(Mutex::new(f)),
             cur_dir_ino: root_ino,
@@ -84,21 +86,46 @@ impl RafsInspector {
     // Implement command "stats""
     // Print information of "RafsSuperMeta"
     fn cmd_stats(&mut self) -> Result<Option<Value>, anyhow::Error> {
-        let o = if self.request_mode {
-            Some(json!({"inodes_count": self.rafs_meta.meta.inodes_count}))
+        let (xattr_entry_count, xattr_total_bytes) = self.rafs_meta.xattr_stats()?;
+        let o = if self.json_output {
+            Some(json!({
+                "inodes_count": self.rafs_meta.meta.inodes_count,
+                "builder_version": self.rafs_meta.meta.builder_version,
+                "chunk_size": self.rafs_meta.meta.chunk_size,
+                "compressor": self.rafs_meta.meta.get_compressor().to_string(),
+                "digester": self.rafs_meta.meta.get_digest_algorithm().to_string(),
+                "xattr_entry_count": xattr_entry_count,
+                "xattr_total_bytes": xattr_total_bytes,
+                "provenance": self.rafs_meta.provenance(),
+            }))
         } else {
             println!(
                 r#"
     Version:            {version}
     Inodes Count:       {inodes_count}
     Chunk Size:         {chunk_size}KB
+    Compressor:         {compressor}
+    Digester:           {digester}
     Root Inode:         {root_inode}
-    Flags:              {flags}"#,
+    Flags:              {flags}
+    Builder Version:    {builder_version}
+    Xattr Entries:      {xattr_entry_count}
+    Xattr Size:         {xattr_total_bytes}"#,
                 version = self.rafs_meta.meta.version >> 8,
                 inodes_count = self.rafs_meta.meta.inodes_count,
                 chunk_size = self.rafs_meta.meta.chunk_size / 1024,
+                compressor = self.rafs_meta.meta.get_compressor(),
+                digester = self.rafs_meta.meta.get_digest_algorithm(),
                 flags = self.rafs_meta.meta.flags,
                 root_inode = self.rafs_meta.superblock.root_ino(),
+                builder_version = self
+                    .rafs_meta
+                    .meta
+                    .builder_version
+                    .as_deref()
+                    .unwrap_or("<unknown>"),
+                xattr_entry_count = xattr_entry_count,
+                xattr_total_bytes = xattr_total_bytes,
             );
             None
         };
@@ -107,8 +134,19 @@ impl RafsInspector {
 
     // Implement command "ls"
     // Walk_children_inodes with handler defined
-    fn cmd_list_dir(&mut self) -> Result<Option<Value>, anyhow::Error> {
-        let dir_inode = self.rafs_meta.get_inode(self.cur_dir_ino, false)?;
+    fn cmd_list_dir(&mut self, dir_name: Option<&str>) -> Result<Option<Value>, anyhow::Error> {
+        let dir_ino = match dir_name {
+            None => self.cur_dir_ino,
+            Some(name) => {
+                let (_, inode) = self.lookup(name)?;
+                if !inode.is_dir() {
+                    bail!("{} is not a directory", name);
+                }
+                inode.ino()
+            }
+        };
+        let dir_inode = self.rafs_meta.get_inode(dir_ino, false)?;
+        let mut value = json!([]);
 
         // Entry_offset: 0, and skip 0
         dir_inode.walk_children_inodes(0, &mut |_inode, f, ino, _offset| {
@@ -129,16 +167,25 @@ impl RafsInspector {
                 " "
             };
 
-            println!(
-                r#"{}    {inode_number:<8} {name:?}"#,
-                sign,
-                name = f,
-                inode_number = ino,
-            );
+            if self.json_output {
+                let v = json!({"name": f.to_string_lossy(), "inode": ino, "type": sign});
+                value.as_array_mut().unwrap().push(v);
+            } else {
+                println!(
+                    r#"{}    {inode_number:<8} {name:?}"#,
+                    sign,
+                    name = f,
+                    inode_number = ino,
+                );
+            }
 
             Ok(RafsInodeWalkAction::Continue)
         })?;
 
+        if self.json_output {
+            return Ok(Some(value));
+        }
+
         Ok(None)
     }
 
@@ -159,22 +206,26 @@ impl RafsInspector {
         }
 
         // Walk through children inodes of current directory
-        let mut new_dir_ino = None;
         let mut err = "";
         let dir_inodes = self.rafs_meta.get_inode(self.cur_dir_ino, false)?;
-        dir_inodes.walk_children_inodes(0, &mut |_inode, child_name, child_ino, _offset| {
-            let child_inode = self.rafs_meta.get_inode(child_ino, false)?;
-            if child_name != dir_name {
-                Ok(RafsInodeWalkAction::Continue)
-            } else {
-                if child_inode.is_dir() {
-                    new_dir_ino = Some(child_ino);
+        let child_ino = dir_inodes.walk_children_inodes_with_result(
+            0,
+            &mut |_inode, child_name, child_ino, _offset| {
+                if child_name != dir_name {
+                    Ok(RafsInodeWalkAction::Continue)
                 } else {
-                    err = "not a directory";
+                    Ok(RafsInodeWalkAction::Return(child_ino))
                 }
-                Ok(RafsInodeWalkAction::Break)
+            },
+        )?;
+        let new_dir_ino = match child_ino {
+            Some(ino) if self.rafs_meta.get_inode(ino, false)?.is_dir() => Some(ino),
+            Some(_) => {
+                err = "not a directory";
+                None
             }
-        })?;
+            None => None,
+        };
 
         if let Some(n) = new_dir_ino {
             self.parent_inodes.push(self.cur_dir_ino);
@@ -188,6 +239,12 @@ impl RafsInspector {
 
     // Implement command "stat"
     fn cmd_stat_file(&self, file_name: &str) -> Result<Option<Value>, anyhow::Error> {
+        // Stat file by absolute path
+        if file_name.starts_with('/') {
+            let (parent, inode) = self.lookup(file_name)?;
+            return self.stat_single_file(Some(parent.as_ref()), inode.as_inode());
+        }
+
         // Stat current directory
         if file_name == "." {
             let inode = self.rafs_meta.get_extended_inode(self.cur_dir_ino, false)?;
@@ -197,18 +254,17 @@ impl RafsInspector {
 
         // Walk through children inodes to find the file
         // Print its basic information and all chunk infomation
+        let mut output = None;
         let dir_inode = self.rafs_meta.get_extended_inode(self.cur_dir_ino, false)?;
         dir_inode.walk_children_inodes(0, &mut |_inode, child_name, child_ino, _offset| {
             if child_name == file_name {
                 // Print file information
                 let child_inode = self.rafs_meta.get_inode(child_ino, false)?;
-                if let Err(e) =
-                    self.stat_single_file(Some(dir_inode.as_ref()), child_inode.as_ref())
-                {
-                    return Err(Error::new(ErrorKind::Other, e));
-                }
+                output = self
+                    .stat_single_file(Some(dir_inode.as_ref()), child_inode.as_ref())
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
 
-                if self.rafs_meta.meta.is_v5() {
+                if self.rafs_meta.meta.is_v5() && !self.json_output {
                     let child_inode = self.rafs_meta.get_extended_inode(child_ino, false)?;
                     let mut chunks = Vec::<Arc<dyn BlobChunkInfo>>::new();
 
@@ -264,16 +320,75 @@ impl RafsInspector {
             }
         })?;
 
+        Ok(output)
+    }
+
+    // Implement command "chunks"
+    fn cmd_list_chunks(&self, file_name: &str) -> Result<Option<Value>, anyhow::Error> {
+        let (_, inode) = self.lookup(file_name)?;
+        if !inode.is_reg() {
+            bail!("{} is not a regular file", file_name);
+        }
+
+        let chunk_count = inode.get_chunk_count();
+        let mut value = json!([]);
+        let mut file_offset = 0u64;
+        if !self.json_output {
+            println!("Chunk Count: {}", chunk_count);
+        }
+        for idx in 0..chunk_count {
+            // For RAFS v6, chunk information is looked up from the chunk table in the bootstrap.
+            let c = inode.get_chunk_info(idx)?;
+            let blob_id = self.get_blob_id_by_index(c.blob_index())?;
+            if self.json_output {
+                let v = json!({
+                    "index": idx,
+                    "file_offset": file_offset,
+                    "blob_id": blob_id,
+                    "chunk_id": c.chunk_id().to_string(),
+                    "compressed_offset": c.compressed_offset(),
+                    "compressed_size": c.compressed_size(),
+                    "decompressed_offset": c.uncompressed_offset(),
+                    "decompressed_size": c.uncompressed_size(),
+                    "compressed": c.is_compressed(),
+                });
+                value.as_array_mut().unwrap().push(v);
+            } else {
+                println!(
+                    r#"
+Index:               {index}
+File Offset:         {file_offset}
+Blob ID:             {blob_id}
+Chunk ID:            {chunk_id}
+Compressed Offset:   {compressed_offset}, Compressed Size: {compressed_size}
+Decompressed Offset: {decompressed_offset}, Decompressed Size: {decompressed_size}"#,
+                    index = idx,
+                    file_offset = file_offset,
+                    blob_id = blob_id,
+                    chunk_id = c.chunk_id(),
+                    compressed_offset = c.compressed_offset(),
+                    compressed_size = c.compressed_size(),
+                    decompressed_offset = c.uncompressed_offset(),
+                    decompressed_size = c.uncompressed_size(),
+                );
+            }
+            file_offset += c.uncompressed_size() as u64;
+        }
+
+        if self.json_output {
+            return Ok(Some(value));
+        }
+
         Ok(None)
     }
 
     // Implement command "blobs"
     fn cmd_list_blobs(&self) -> Result<Option<Value>, anyhow::Error> {
-        let blob_infos = self.rafs_meta.superblock.get_blob_infos();
+        let blob_infos = self.rafs_meta.superblock.get_blob_infos(true)?;
 
         let mut value = json!([]);
         for (_i, blob_info) in blob_infos.iter().enumerate() {
-            if self.request_mode {
+            if self.json_output {
                 let v = json!({"blob_id": blob_info.blob_id(), 
                                     "readahead_offset": blob_info.prefetch_offset(),
                                     "readahead_size": blob_info.prefetch_size(),
@@ -298,7 +413,7 @@ Compressed Size:    {compressed_size}
             }
         }
 
-        if self.request_mode {
+        if self.json_output {
             return Ok(Some(value));
         }
 
@@ -356,7 +471,7 @@ Compressed Size:    {compressed_size}
         let prefetch_inos = self.rafs_meta.get_prefetched_inos(bootstrap)?;
         drop(guard);
 
-        let o = if self.request_mode {
+        let o = if self.json_output {
             let mut value = json!([]);
             for ino in prefetch_inos {
                 let path = self.path_from_ino(ino as u64)?;
@@ -530,9 +645,146 @@ Blob ID: {}
 
         Ok(None)
     }
+
+    // Implement command "symlinks"
+    fn cmd_list_symlinks(&self) -> Result<Option<Value>, anyhow::Error> {
+        let report = self.rafs_meta.find_all_symlinks()?;
+
+        let o = if self.json_output {
+            Some(serde_json::to_value(&report)?)
+        } else {
+            let mut symlinks = report.symlinks.iter().collect::<Vec<_>>();
+            symlinks.sort();
+            println!("Total Symlinks: {}", symlinks.len());
+            for (path, target) in symlinks {
+                println!(r#"{:?} -> {:?}"#, path, target);
+            }
+            println!("Dangling Symlinks: {}", report.dangling.len());
+            for path in report.dangling.iter() {
+                println!(r#"{:?}"#, path);
+            }
+            None
+        };
+
+        Ok(o)
+    }
+
+    // Implement command "setuid"
+    fn cmd_list_setuid_files(&self) -> Result<Option<Value>, anyhow::Error> {
+        let files = self.rafs_meta.find_setuid_files()?;
+
+        let o = if self.json_output {
+            Some(serde_json::to_value(&files)?)
+        } else {
+            println!("Total Setuid/Setgid Files: {}", files.len());
+            for path in files.iter() {
+                println!(r#"{:?}"#, path);
+            }
+            None
+        };
+
+        Ok(o)
+    }
+
+    // Implement command "types"
+    fn cmd_count_inode_types(&self) -> Result<Option<Value>, anyhow::Error> {
+        let counts = self.rafs_meta.count_inodes_by_type()?;
+
+        let o = if self.json_output {
+            Some(serde_json::to_value(&counts)?)
+        } else {
+            println!(
+                r#"
+Regular Files:      {}
+Directories:        {}
+Symlinks:           {}
+Character Devices:  {}
+Block Devices:      {}
+FIFOs:              {}
+Sockets:            {}"#,
+                counts.regular,
+                counts.directories,
+                counts.symlinks,
+                counts.char_devices,
+                counts.block_devices,
+                counts.fifos,
+                counts.sockets
+            );
+            None
+        };
+
+        Ok(o)
+    }
+
+    // Implement command "locality"
+    fn cmd_show_locality(&self, threshold: Option<&str>) -> Result<Option<Value>, anyhow::Error> {
+        let threshold = match threshold {
+            None => 1,
+            Some(v) => v
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid blob count threshold {}", v))?,
+        };
+        let files = self.rafs_meta.locality_report(threshold)?;
+
+        let o = if self.json_output {
+            Some(serde_json::to_value(&files)?)
+        } else {
+            println!(
+                "Files Spanning More Than {} Blobs: {}",
+                threshold,
+                files.len()
+            );
+            for file in files.iter() {
+                println!(
+                    r#"{:?}: {} chunks in {} blobs {:?}"#,
+                    file.path,
+                    file.chunks,
+                    file.blobs.len(),
+                    file.blobs
+                );
+            }
+            None
+        };
+
+        Ok(o)
+    }
 }
 
 impl RafsInspector {
+    /// Look up a file by absolute path, or by name in the current directory.
+    ///
+    /// Return the parent directory together with the file, the root directory is its own parent.
+    fn lookup(
+        &self,
+        file_name: &str,
+    ) -> Result<(Arc<dyn RafsInodeExt>, Arc<dyn RafsInodeExt>), anyhow::Error> {
+        let path = Path::new(file_name);
+        let parent_ino = if !path.is_absolute() {
+            self.cur_dir_ino
+        } else if let Some(parent) = path.parent() {
+            self.rafs_meta
+                .ino_from_path(parent)
+                .with_context(|| format!("failed to look up {:?}", parent))?
+        } else {
+            let root = self
+                .rafs_meta
+                .get_extended_inode(self.rafs_meta.superblock.root_ino(), false)?;
+            return Ok((root.clone(), root));
+        };
+
+        let parent = self.rafs_meta.get_extended_inode(parent_ino, false)?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("invalid file name {}", file_name))?;
+        // Inodes got from the parent directory carry name information, which is needed by
+        // RAFS v6 to get chunk information of regular files.
+        let inode = parent
+            .get_child_by_name(name)
+            .with_context(|| format!("failed to look up {}", file_name))?;
+
+        Ok((parent, inode))
+    }
+
     /// Get file name of the inode, the rafs v6 file is handled separately.
     fn get_file_name(&self, parent_inode: &dyn RafsInodeExt, inode: &dyn RafsInode) -> OsString {
         let mut filename = OsString::from("");
@@ -569,6 +821,21 @@ impl RafsInspector {
         let inode_attr = inode.get_attr();
 
         if let Some(parent) = parent_inode {
+            if self.json_output {
+                return Ok(Some(json!({
+                    "inode": inode.ino(),
+                    "name": self.get_file_name(parent, inode).to_string_lossy(),
+                    "size": inode.size(),
+                    "parent": parent.ino(),
+                    "mode": inode_attr.mode,
+                    "nlink": inode_attr.nlink,
+                    "uid": inode_attr.uid,
+                    "gid": inode_attr.gid,
+                    "mtime": inode_attr.mtime,
+                    "mtime_nsec": inode_attr.mtimensec,
+                    "blocks": inode_attr.blocks,
+                })));
+            }
             println!(
                 r#"
 Inode Number:       {inode_number}
@@ -603,7 +870,7 @@ Blocks:             {blocks}"#,
 
     // Match blobinfo by using blob index
     fn get_blob_id_by_index(&self, blob_index: u32) -> Result<String, anyhow::Error> {
-        let blob_infos = self.rafs_meta.superblock.get_blob_infos();
+        let blob_infos = self.rafs_meta.superblock.get_blob_infos(true)?;
         for (_i, b) in blob_infos.iter().enumerate() {
             if b.blob_index() == blob_index {
                 return Ok(b.blob_id().to_owned());
@@ -648,11 +915,16 @@ impl Executor {
             }
             ("exit", _) | ("q", _) => return Err(ExecuteError::Exit),
             ("stats", None) => inspector.cmd_stats(),
-            ("ls", None) => inspector.cmd_list_dir(),
+            ("ls", dir) => inspector.cmd_list_dir(dir),
             ("cd", Some(dir)) => inspector.cmd_change_dir(dir),
             ("stat", Some(file_name)) => inspector.cmd_stat_file(file_name),
+            ("chunks", Some(file_name)) => inspector.cmd_list_chunks(file_name),
             ("blobs", None) => inspector.cmd_list_blobs(),
             ("prefetch", None) => inspector.cmd_list_prefetch(),
+            ("symlinks", None) => inspector.cmd_list_symlinks(),
+            ("setuid", None) => inspector.cmd_list_setuid_files(),
+            ("types", None) => inspector.cmd_count_inode_types(),
+            ("locality", threshold) => inspector.cmd_show_locality(threshold),
             ("chunk", Some(argument)) => {
                 let offset: u64 = argument.parse().unwrap();
                 inspector.cmd_show_chunk(offset)
@@ -681,11 +953,16 @@ impl Executor {
         println!(
             r#"
     stats:              Display global rafs metadata
-    ls:                 Show files in current directory
+    ls [DIR]:           Show files in current directory or DIR
     cd DIR:             Change current directory
-    stat FILE_NAME:     Show particular information of rafs inode
+    stat FILE_NAME:     Show particular information of rafs inode, FILE_NAME may be an absolute path
+    chunks FILE_NAME:   Show data chunks of a regular file, FILE_NAME may be an absolute path
     blobs:              Show blobs table
     prefetch:           Show prefetch table
+    symlinks:           Show all symlinks and dangling ones
+    setuid:             Show all regular files with setuid or setgid bit set
+    types:              Count inodes by file type
+    locality [N]:       Show regular files whose data chunks span more than N (default 1) blobs
     chunk OFFSET:       List basic info of a single chunk together with a list of files that share it
     icheck INODE:       Show path of the inode and basic information
         "#
@@ -715,6 +992,7 @@ impl Prompt {
                 Ok(Some(o)) => {
                     serde_json::to_writer(std::io::stdout(), &o)
                         .unwrap_or_else(|e| error!("Failed to serialize, {:?}", e));
+                    println!();
                 }
                 _ => continue,
             }
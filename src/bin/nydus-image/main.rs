@@ -14,7 +14,11 @@ extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate nydus_builder;
 
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::{self, metadata, DirEntry, File, OpenOptions};
 use std::path::{Path, PathBuf};
 
@@ -27,39 +31,63 @@ use nydus_rafs::metadata::RafsVersion;
 use nydus_rafs::RafsIoReader;
 use nydus_storage::factory::BlobFactory;
 use nydus_storage::meta::{
-    format_blob_meta_features, BLOB_META_FEATURE_CHUNK_INFO_V2, BLOB_META_FEATURE_SEPARATE,
-    BLOB_META_FEATURE_ZRAN,
+    format_blob_meta_features, BLOB_META_FEATURE_CHUNK_INFO_V2, BLOB_META_FEATURE_FOREIGN_LAYER,
+    BLOB_META_FEATURE_SEPARATE, BLOB_META_FEATURE_ZRAN,
 };
 use nydus_storage::{RAFS_DEFAULT_CHUNK_SIZE, RAFS_MAX_CHUNK_SIZE};
+use nydus_utils::crypt::CipherKey;
 use nydus_utils::{compress, digest};
 use serde::{Deserialize, Serialize};
 
-use crate::builder::{Builder, DirectoryBuilder, StargzBuilder, TarballBuilder};
-use crate::core::blob_compact::BlobCompactor;
-use crate::core::chunk_dict::{import_chunk_dict, parse_chunk_dict_arg};
-use crate::core::context::{
-    ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput, ConversionType,
+use nydus_builder::core::base_bootstrap::BaseBootstrap;
+use nydus_builder::core::blob_compact::BlobCompactor;
+use nydus_builder::core::blob_rewrite::BlobRewriter;
+use nydus_builder::core::chunk_dict::{import_chunk_dict, parse_chunk_dict_arg, ChunkDictParams};
+use nydus_builder::core::chunker::ChunkingStrategy;
+use nydus_builder::core::context::{ChunkDedupStats, CipherContext};
+use nydus_builder::trace::{EventTracerClass, TimingTracerClass, TraceClass};
+use nydus_builder::{
+    ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput, Builder,
+    ConversionType, DirectoryBuilder, Prefetch, PrefetchPolicy, StargzBuilder, TarballBuilder,
+    WhiteoutSpec,
 };
-use crate::core::node::{self, WhiteoutSpec};
-use crate::core::prefetch::{Prefetch, PrefetchPolicy};
-use crate::core::tree;
+
 use crate::merge::Merger;
-use crate::trace::{EventTracerClass, TimingTracerClass, TraceClass};
+use crate::oci::OciLayout;
 use crate::unpack::{OCIUnpacker, Unpacker};
-use crate::validator::Validator;
+use crate::validator::{Difference, Validator};
 
-#[macro_use]
-mod trace;
-mod builder;
-mod core;
 mod inspect;
+mod manifest;
 mod merge;
+mod oci;
 mod stat;
 mod unpack;
 mod validator;
 
 const BLOB_ID_MAXIMUM_LENGTH: usize = 255;
 
+/// Data de-duplication statistics against the chunk dictionary.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ChunkDictDedup {
+    /// Number of chunks de-duplicated against the chunk dictionary.
+    deduplicated_chunks: u64,
+    /// Uncompressed size of chunks de-duplicated against the chunk dictionary.
+    deduplicated_size: u64,
+    /// Percentage of uncompressed data saved by the chunk dictionary.
+    saved_percent: f64,
+}
+
+impl From<&ChunkDedupStats> for ChunkDictDedup {
+    fn from(stats: &ChunkDedupStats) -> Self {
+        ChunkDictDedup {
+            deduplicated_chunks: stats.dict_chunks,
+            deduplicated_size: stats.dict_size,
+            saved_percent: stats.ratio(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default)]
 pub struct OutputSerializer {
     /// The binary version of builder (nydus-image).
@@ -72,6 +100,12 @@ pub struct OutputSerializer {
     blobs: Vec<String>,
     /// Performance trace info for current build.
     trace: serde_json::Map<String, serde_json::Value>,
+    /// Differences between RAFS filesystem and source directory, for `check --compare`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    differences: Vec<Difference>,
+    /// Data de-duplication statistics against the chunk dictionary, for `create --chunk-dict`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunk_dict: Option<ChunkDictDedup>,
 }
 
 impl OutputSerializer {
@@ -98,6 +132,8 @@ impl OutputSerializer {
                 bootstrap: build_output.bootstrap_path.unwrap_or_default(),
                 blobs: build_output.blobs,
                 trace,
+                differences: Vec::new(),
+                chunk_dict: build_output.dedup_stats.as_ref().map(|v| v.into()),
             };
 
             serde_json::to_writer_pretty(w, &output)
@@ -112,6 +148,7 @@ impl OutputSerializer {
         build_info: &BuildTimeInfo,
         blob_ids: Vec<String>,
         bootstrap: &Path,
+        differences: Vec<Difference>,
     ) -> Result<()> {
         let output_json: Option<PathBuf> = matches
             .get_one::<String>("output-json")
@@ -131,6 +168,8 @@ impl OutputSerializer {
                 bootstrap: bootstrap.display().to_string(),
                 blobs: blob_ids,
                 trace,
+                differences,
+                chunk_dict: None,
             };
 
             serde_json::to_writer(w, &output).context("failed to write result to output file")?;
@@ -178,12 +217,14 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .value_parser([
                             "directory",
                             "dir-rafs",
+                            "estargz",
                             "estargz-rafs",
                             "estargz-ref",
                             "estargztoc-ref",
                             "tar-rafs",
                             "targz-rafs",
                             "targz-ref",
+                            "targz-stream",
                             "stargz_index",
                         ])
                 )
@@ -218,7 +259,7 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .arg(
                     Arg::new("blob-id")
                         .long("blob-id")
-                        .required_if_eq_any([("type", "estargztoc-ref"), ("type", "stargz_index")])
+                        .required_if_eq_any([("type", "estargz"), ("type", "estargztoc-ref"), ("type", "stargz_index")])
                         .help("Specify RAFS data blob id (as object id in backend/oss)")
                 )
                 .arg(
@@ -236,7 +277,7 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .arg(
                     Arg::new("blob-data-size")
                         .long("blob-data-size")
-                        .help("Set data blob size for 'estargztoc-ref' conversion"),
+                        .help("Set data blob size for 'estargz' or 'estargztoc-ref' conversion"),
                 )
                 .arg(
                     Arg::new("chunk-size")
@@ -244,13 +285,26 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .help("Set size of data chunk, must be power of two and between 0x1000-0x1000000:")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("chunking")
+                        .long("chunking")
+                        .help("Set strategy to split file data into chunks, 'fixed:<size>' or 'cdc:<min>-<avg>-<max>' (RAFS v5 only), such as 'fixed:1M' or 'cdc:256K-1M-4M'")
+                        .conflicts_with("chunk-size")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .help("Set number of worker threads to compress and digest chunks, defaults to the number of available CPUs")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("compressor")
                         .long("compressor")
                         .help("Set algorithm to compress chunks:")
                         .required(false)
                         .default_value("zstd")
-                        .value_parser(["none", "lz4_block", "gzip", "zstd"]),
+                        .value_parser(["none", "lz4_block", "gzip", "zstd", "snappy"]),
                 )
                 .arg(
                     Arg::new("digester")
@@ -260,6 +314,20 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .default_value("sha256")
                         .value_parser(["blake3", "sha256"]),
                 )
+                .arg(
+                    Arg::new("encrypt-key-id")
+                        .long("encrypt-key-id")
+                        .help("Encrypt chunks with AES256-GCM, using a data key wrapped by the master key with the specified id, apply to RAFS V6 only")
+                        .requires("encrypt-key-file")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("encrypt-key-file")
+                        .long("encrypt-key-file")
+                        .help("File containing the hex encoded master key to wrap the data key")
+                        .requires("encrypt-key-id")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("fs-version")
                         .long("fs-version")
@@ -271,12 +339,35 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .arg(
                     arg_chunk_dict.clone(),
                 )
+                .arg(
+                    Arg::new("force-chunk-dict")
+                        .long("force-chunk-dict")
+                        .help("Use the chunk dictionary even if its chunk size or compressor differs from the build")
+                        .action(ArgAction::SetTrue)
+                        .requires("chunk-dict")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("parent-bootstrap")
                         .long("parent-bootstrap")
                         .help("Path to parent/referenced RAFS filesystem metadata blob (optional)")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("base-bootstrap")
+                        .long("base-bootstrap")
+                        .help("Path to RAFS metadata built from a previous version of the source directory, to reuse data of unchanged files")
+                        .conflicts_with_all(["parent-bootstrap", "reproducible"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("verify-reused")
+                        .long("verify-reused")
+                        .help("Verify digests of a sample of files reused from the base bootstrap")
+                        .action(ArgAction::SetTrue)
+                        .requires("base-bootstrap")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("aligned-chunk")
                         .long("aligned-chunk")
@@ -290,6 +381,14 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .action(ArgAction::SetTrue)
                         .required(false),
                 )
+                .arg(
+                    Arg::new("reproducible")
+                        .long("reproducible")
+                        .help("Generate byte-identical RAFS metadata and data blobs from the same source, with modification time clamped to $SOURCE_DATE_EPOCH or 0, and a build manifest saved as <BOOTSTRAP>.manifest.json")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["blob-id", "encrypt-key-id"])
+                        .required(false),
+                )
                 .arg(
                     Arg::new("disable-check")
                         .long("disable-check")
@@ -304,6 +403,13 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .default_value("oci")
                         .value_parser(["oci", "overlayfs", "none"])
                 )
+                .arg(
+                    Arg::new("annotation")
+                        .long("annotation")
+                        .help("Annotation in form of KEY=VALUE, recorded into build provenance of the RAFS metadata")
+                        .action(ArgAction::Append)
+                        .required(false),
+                )
                 .arg(
                     arg_prefetch_policy.clone(),
                 )
@@ -318,9 +424,25 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                     Arg::new("bootstrap")
                         .long("bootstrap")
                         .short('B')
+                        .visible_alias("output")
                         .help("output path of nydus overlaid bootstrap")
                         .required(true),
                 )
+                .arg(
+                    Arg::new("parent")
+                        .long("parent")
+                        .help("per layer bootstrap paths, in order of lower to upper layers")
+                        .num_args(1..)
+                        .conflicts_with("SOURCE"),
+                )
+                .arg(
+                    Arg::new("layer-bootstraps")
+                        .long("layer-bootstraps")
+                        .help("comma separated per layer bootstrap paths, in order of lower to upper layers")
+                        .value_delimiter(',')
+                        .num_args(1..)
+                        .conflicts_with_all(["parent", "SOURCE"]),
+                )
                 .arg(
                     arg_chunk_dict,
                 )
@@ -333,7 +455,7 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .arg(
                     Arg::new("SOURCE")
                         .help("bootstrap paths (allow one or more)")
-                        .required(true)
+                        .required_unless_present_any(["parent", "layer-bootstraps"])
                         .num_args(1..),
                 )
         )
@@ -353,6 +475,29 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .action(ArgAction::SetTrue)
                         .required(false),
                 )
+                .arg(
+                    Arg::new("compare")
+                        .long("compare")
+                        .help("Compare RAFS filesystem with the source directory it's built from")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("deep")
+                        .long("deep")
+                        .help(
+                            "Verify chunk digests against data of source files and directory nlink when comparing",
+                        )
+                        .action(ArgAction::SetTrue)
+                        .requires("compare")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("blob-dir")
+                        .long("blob-dir")
+                        .help("Directory containing data blobs to verify chunk data when comparing")
+                        .requires("deep")
+                        .required(false),
+                )
                 .arg(
                     arg_output_json.clone(),
                 )
@@ -363,15 +508,49 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .arg(
                     Arg::new("bootstrap")
                         .help("Path to RAFS metadata file")
-                        .required(true),
+                        .required_unless_present("bootstrap-file"),
+                )
+                .arg(
+                    Arg::new("bootstrap-file")
+                        .long("bootstrap")
+                        .short('B')
+                        .help("Path to RAFS metadata file")
+                        .conflicts_with("bootstrap"),
                 )
                 .arg(
                     Arg::new("request")
                         .long("request")
                         .short('R')
-                        .help("Inspect RAFS filesystem metadata in request mode")
+                        .help("Inspect RAFS filesystem metadata in request mode, e.g. \"stat /etc/passwd\", \"ls /usr/bin\", \"chunks /bin/bash\", \"blobs\" or \"prefetch\"")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output results in JSON, always enabled in request mode")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("symlinks")
+                        .long("symlinks")
+                        .help("List all symlinks and dangling ones in JSON")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("request"),
+                )
+                .arg(
+                    Arg::new("setuid")
+                        .long("setuid")
+                        .help("List all regular files with setuid or setgid bit set in JSON")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["request", "symlinks"]),
+                )
+                .arg(
+                    Arg::new("type-counts")
+                        .long("type-counts")
+                        .help("Count inodes by file type in JSON")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["request", "symlinks", "setuid"]),
+                )
         )
         .subcommand(
             App::new("stat")
@@ -416,19 +595,40 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                         .long("config")
                         .short('C')
                         .help("config to compactor")
-                        .required(true),
+                        .required_unless_present("output-blob"),
                 )
                 .arg(
                     Arg::new("backend-type")
                         .long("backend-type")
                         .help("type of backend")
-                        .required(true),
+                        .required_unless_present("output-blob"),
                 )
                 .arg(
                     Arg::new("backend-config-file")
                         .long("backend-config-file")
                         .help("config file of backend")
-                        .required(true),
+                        .required_unless_present("output-blob"),
+                )
+                .arg(
+                    Arg::new("blob-dir")
+                        .long("blob-dir")
+                        .short('D')
+                        .help("Directory containing data blobs referenced by the bootstrap, to rewrite data into a single blob")
+                        .requires("output-blob"),
+                )
+                .arg(
+                    Arg::new("output-blob")
+                        .long("output-blob")
+                        .help("Rewrite data of all files into a single data blob at the path, chunks unreachable from any inode are dropped")
+                        .requires("blob-dir")
+                        .conflicts_with_all(["config", "chunk-dict"]),
+                )
+                .arg(
+                    Arg::new("compressor")
+                        .long("compressor")
+                        .help("Algorithm to compress chunks of the rewritten data blob, default to the one of the source bootstrap")
+                        .requires("output-blob")
+                        .value_parser(["none", "lz4_block", "gzip", "zstd", "snappy"]),
                 )
                 .arg(
                     Arg::new("chunk-dict")
@@ -445,15 +645,39 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                     arg_output_json,
                 )
         )
+        .subcommand(
+            App::new("pack")
+                .about("Pack a RAFS bootstrap and data blobs referenced by it into an OCI image layout")
+                .arg(
+                    Arg::new("bootstrap")
+                        .long("bootstrap")
+                        .short('B')
+                        .help("File path of RAFS metadata")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("blob-dir")
+                        .long("blob-dir")
+                        .short('D')
+                        .help("Directory containing data blobs referenced by the RAFS metadata")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("oci-layout")
+                        .long("oci-layout")
+                        .help("Directory to store the generated OCI image layout")
+                        .required(true),
+                )
+        )
         .subcommand(
             App::new("unpack")
-            .about("Unpack a RAFS filesystem to a tar file")
+            .about("Unpack a RAFS filesystem to a tar file, or extract RAFS bootstrap and data blobs from an OCI image layout")
             .arg(
                 Arg::new("bootstrap")
                 .long("bootstrap")
                 .short('B')
                 .help("path to RAFS bootstrap file")
-                .required(true)
+                .required_unless_present("oci-layout")
                 )
             .arg(
                 Arg::new("blob")
@@ -462,10 +686,17 @@ fn prepare_cmd_args(bti_string: &'static str) -> App {
                 .help("path to RAFS data blob file")
                 .required(false),
                 )
+            .arg(
+                Arg::new("oci-layout")
+                .long("oci-layout")
+                .help("path to OCI image layout generated by 'nydus-image pack'")
+                .conflicts_with_all(["bootstrap", "blob"])
+                .required(false),
+                )
             .arg(
                 Arg::new("output")
                 .long("output")
-                .help("path for output tar file")
+                .help("path for output tar file, or output directory for bootstrap and data blobs with '--oci-layout'")
                 .required(true),
                 )
         )
@@ -534,6 +765,8 @@ fn main() -> Result<()> {
         Command::stat(matches)
     } else if let Some(matches) = cmd.subcommand_matches("compact") {
         Command::compact(matches, &build_info)
+    } else if let Some(matches) = cmd.subcommand_matches("pack") {
+        Command::pack(matches)
     } else if let Some(matches) = cmd.subcommand_matches("unpack") {
         Command::unpack(matches)
     } else {
@@ -595,8 +828,15 @@ impl Command {
             }
             ConversionType::EStargzToRafs
             | ConversionType::TargzToRafs
+            | ConversionType::TargzStreamToRafs
             | ConversionType::TarToRafs => {
-                Self::ensure_file(&source_path)?;
+                // The tar stream may be read from stdin or a FIFO.
+                if conversion_type != ConversionType::TargzStreamToRafs {
+                    Self::ensure_file(&source_path)?;
+                } else if source_path != Path::new("-") {
+                    metadata(&source_path)
+                        .context(format!("failed to access path {:?}", source_path))?;
+                }
                 if blob_stor.is_none() {
                     bail!("both --blob and --blob-dir are not provided");
                 } else if blob_meta_stor.is_some() {
@@ -644,7 +884,7 @@ impl Command {
                     );
                 }
                 if conversion_type == ConversionType::EStargzIndexToRef && blob_id.trim() == "" {
-                    bail!("'--blob-id' is missing for '--type stargz_index'");
+                    bail!("'--blob-id' is missing for '--type {}'", conversion_type);
                 }
             }
             ConversionType::DirectoryToStargz
@@ -674,13 +914,47 @@ impl Command {
         );
         build_ctx.set_fs_version(version);
         build_ctx.set_chunk_size(chunk_size);
+        if let Some(chunking) = Self::get_chunking(matches, version, conversion_type)? {
+            build_ctx.set_chunking(chunking);
+        }
+        build_ctx.cipher_ctx = Self::get_cipher_context(matches, version, conversion_type)?;
+        build_ctx.threads = Self::get_threads(matches)?;
+        build_ctx.annotations = Self::get_annotations(matches)?;
+        build_ctx.builder_version = build_info.package_ver.clone();
+        if matches.get_flag("reproducible") {
+            build_ctx.reproducible = true;
+            // Avoid build-time information in the generated metadata.
+            build_ctx.source_date_epoch = Some(Self::get_source_date_epoch()?.unwrap_or(0));
+            build_ctx.builder_version = String::new();
+        }
 
         let mut blob_mgr = BlobManager::new();
         if let Some(chunk_dict_arg) = matches.get_one::<String>("chunk-dict") {
-            blob_mgr.set_chunk_dict(timing_tracer!(
-                { import_chunk_dict(chunk_dict_arg) },
-                "import_chunk_dict"
-            )?);
+            let chunk_dict =
+                timing_tracer!({ import_chunk_dict(chunk_dict_arg) }, "import_chunk_dict")?;
+            if let Some(params) = chunk_dict.get_params() {
+                let build_params = ChunkDictParams {
+                    chunk_size: build_ctx.chunk_size,
+                    compressor: build_ctx.compressor,
+                    digester: build_ctx.digester,
+                };
+                params
+                    .check_compatibility(&build_params, matches.get_flag("force-chunk-dict"))
+                    .with_context(|| format!("invalid chunk dict {}", chunk_dict_arg))?;
+            }
+            blob_mgr.set_chunk_dict(chunk_dict);
+        }
+
+        if let Some(base) = matches.get_one::<String>("base-bootstrap") {
+            if conversion_type != ConversionType::DirectoryToRafs {
+                bail!(
+                    "conversion type '{}' conflicts with '--base-bootstrap'",
+                    conversion_type
+                );
+            }
+            let verify = matches.get_flag("verify-reused");
+            let base = BaseBootstrap::load(&build_ctx, &mut blob_mgr, Path::new(base), verify)?;
+            blob_mgr.base_bootstrap = Some(base);
         }
 
         let mut bootstrap_mgr = if inline_bootstrap {
@@ -707,6 +981,7 @@ impl Command {
                     build_ctx.blob_meta_features |= BLOB_META_FEATURE_CHUNK_INFO_V2;
                      */
                     build_ctx.blob_meta_features |= BLOB_META_FEATURE_SEPARATE;
+                    build_ctx.blob_meta_features |= BLOB_META_FEATURE_FOREIGN_LAYER;
                 }
                 Box::new(StargzBuilder::new(blob_data_size))
             }
@@ -719,6 +994,7 @@ impl Command {
                 }
                 Box::new(TarballBuilder::new(conversion_type))
             }
+            ConversionType::TargzStreamToRafs => Box::new(TarballBuilder::new(conversion_type)),
             ConversionType::TarToRafs => Box::new(TarballBuilder::new(conversion_type)),
             ConversionType::TarToStargz => unimplemented!(),
         };
@@ -740,16 +1016,31 @@ impl Command {
         if !inline_bootstrap {
             if let Some(ArtifactStorage::SingleFile(p)) = &bootstrap_mgr.bootstrap_storage {
                 Self::validate_image(matches, p).context("failed to validate bootstrap")?;
+                if build_ctx.reproducible {
+                    let manifest = manifest::dump(&build_ctx, p)
+                        .context("failed to generate build manifest")?;
+                    info!("build manifest saved to {}", manifest.display());
+                }
             }
         }
 
         info!("successfully built RAFS filesystem: \n{}", build_output);
+        if let Some(stats) = build_output.dedup_stats.as_ref() {
+            println!(
+                "Deduplicated {} chunks ({} bytes) from dictionary, saved {:.2}%",
+                stats.dict_chunks,
+                stats.dict_size,
+                stats.ratio()
+            );
+        }
         OutputSerializer::dump(matches, build_output, build_info)
     }
 
     fn merge(matches: &clap::ArgMatches, build_info: &BuildTimeInfo) -> Result<()> {
         let source_bootstrap_paths: Vec<PathBuf> = matches
-            .get_many::<String>("SOURCE")
+            .get_many::<String>("parent")
+            .or_else(|| matches.get_many::<String>("layer-bootstraps"))
+            .or_else(|| matches.get_many::<String>("SOURCE"))
             .map(|paths| paths.map(PathBuf::from).collect())
             .unwrap();
         let target_bootstrap_path = Self::get_bootstrap_storage(matches)?;
@@ -760,6 +1051,7 @@ impl Command {
         };
         let mut ctx = BuildContext {
             prefetch: Self::get_prefetch(matches)?,
+            builder_version: build_info.package_ver.clone(),
             ..Default::default()
         };
         let output = Merger::merge(
@@ -778,6 +1070,19 @@ impl Command {
             Some(s) => PathBuf::from(s),
         };
 
+        if let Some(dst_blob) = matches.get_one::<String>("output-blob") {
+            // Safe to unwrap because `--blob-dir` is required by `--output-blob`.
+            let blob_dir = Path::new(matches.get_one::<String>("blob-dir").unwrap());
+            let compressor = matches
+                .get_one::<String>("compressor")
+                .map(|s| s.parse::<compress::Algorithm>())
+                .transpose()?;
+            let build_output = BlobRewriter::new(&bootstrap_path, blob_dir)?
+                .rewrite(dst_bootstrap, PathBuf::from(dst_blob), compressor)
+                .with_context(|| format!("failed to rewrite data blobs of {:?}", bootstrap_path))?;
+            return OutputSerializer::dump(matches, build_output, build_info);
+        }
+
         let chunk_dict = match matches.get_one::<String>("chunk-dict") {
             None => None,
             Some(args) => Some(import_chunk_dict(args)?),
@@ -808,7 +1113,26 @@ impl Command {
         Ok(())
     }
 
+    fn pack(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments.
+        let blob_dir = Path::new(matches.get_one::<String>("blob-dir").unwrap());
+        let layout = matches.get_one::<String>("oci-layout").unwrap();
+
+        OciLayout::new(layout)
+            .pack(bootstrap, blob_dir)
+            .with_context(|| format!("failed to pack bootstrap {:?} into {}", bootstrap, layout))
+    }
+
     fn unpack(args: &clap::ArgMatches) -> Result<()> {
+        if let Some(layout) = args.get_one::<String>("oci-layout") {
+            // Safe to unwrap because it's a required argument.
+            let output = args.get_one::<String>("output").unwrap();
+            return OciLayout::new(layout)
+                .unpack(Path::new(output))
+                .with_context(|| format!("failed to unpack OCI image layout {}", layout));
+        }
+
         let bootstrap = args
             .get_one::<String>("bootstrap")
             .expect("pass in bootstrap");
@@ -850,22 +1174,65 @@ impl Command {
             blob_ids.push(blob.blob_id().to_string());
         }
 
-        OutputSerializer::dump_with_check(matches, build_info, blob_ids, bootstrap_path)?;
+        let mut differences = Vec::new();
+        if let Some(source) = matches.get_one::<String>("compare") {
+            let deep = matches.get_flag("deep");
+            let blob_dir = matches.get_one::<String>("blob-dir").map(Path::new);
+            differences = validator
+                .compare(Path::new(source), deep, blob_dir)
+                .with_context(|| {
+                    format!(
+                        "failed to compare bootstrap {:?} with {}",
+                        bootstrap_path, source
+                    )
+                })?;
+            serde_json::to_writer_pretty(std::io::stdout(), &differences)
+                .context("failed to write differences")?;
+            println!();
+        }
+        let count = differences.len();
+
+        OutputSerializer::dump_with_check(
+            matches,
+            build_info,
+            blob_ids,
+            bootstrap_path,
+            differences,
+        )?;
+        if count > 0 {
+            bail!(
+                "found {} differences between RAFS filesystem and source directory",
+                count
+            );
+        }
 
         Ok(())
     }
 
     fn inspect(matches: &clap::ArgMatches) -> Result<()> {
-        let bootstrap_path = Self::get_bootstrap(matches)?;
-        let cmd = matches.get_one::<String>("request");
+        let bootstrap_path = match matches.get_one::<String>("bootstrap-file") {
+            Some(v) => Path::new(v),
+            None => Self::get_bootstrap(matches)?,
+        };
+        let cmd = if matches.get_flag("symlinks") {
+            Some("symlinks".to_string())
+        } else if matches.get_flag("setuid") {
+            Some("setuid".to_string())
+        } else if matches.get_flag("type-counts") {
+            Some("types".to_string())
+        } else {
+            matches.get_one::<String>("request").cloned()
+        };
+        let json_output = cmd.is_some() || matches.get_flag("json");
         let mut inspector =
-            inspect::RafsInspector::new(bootstrap_path, cmd.is_some()).map_err(|e| {
+            inspect::RafsInspector::new(bootstrap_path, json_output).map_err(|e| {
                 error!("failed to create inspector, {:?}", e);
                 e
             })?;
 
         if let Some(c) = cmd {
-            let o = inspect::Executor::execute(&mut inspector, c.to_string()).unwrap();
+            let o = inspect::Executor::execute(&mut inspector, c)
+                .map_err(|e| anyhow!("failed to execute inspect request, {:?}", e))?;
             serde_json::to_writer(std::io::stdout(), &o)
                 .unwrap_or_else(|e| error!("Failed to serialize result, {:?}", e));
         } else {
@@ -1018,6 +1385,19 @@ impl Command {
         Ok(None)
     }
 
+    fn get_source_date_epoch() -> Result<Option<u64>> {
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(v) if !v.trim().is_empty() => {
+                let epoch = v
+                    .trim()
+                    .parse::<u64>()
+                    .with_context(|| format!("invalid SOURCE_DATE_EPOCH {}", v))?;
+                Ok(Some(epoch))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn get_parent_bootstrap(matches: &clap::ArgMatches) -> Result<Option<RafsIoReader>> {
         let mut parent_bootstrap_path = Path::new("");
         if let Some(_parent_bootstrap_path) = matches.get_one::<String>("parent-bootstrap") {
@@ -1110,6 +1490,85 @@ impl Command {
         }
     }
 
+    fn get_chunking(
+        matches: &clap::ArgMatches,
+        version: RafsVersion,
+        ty: ConversionType,
+    ) -> Result<Option<ChunkingStrategy>> {
+        match matches.get_one::<String>("chunking") {
+            None => Ok(None),
+            Some(v) => {
+                let chunking: ChunkingStrategy = v.parse()?;
+                chunking.validate(version, ty)?;
+                Ok(Some(chunking))
+            }
+        }
+    }
+
+    fn get_annotations(matches: &clap::ArgMatches) -> Result<BTreeMap<String, String>> {
+        let mut annotations = BTreeMap::new();
+        if let Some(values) = matches.get_many::<String>("annotation") {
+            for v in values {
+                match v.split_once('=') {
+                    Some((key, value)) if !key.is_empty() => {
+                        annotations.insert(key.to_string(), value.to_string());
+                    }
+                    _ => bail!("invalid annotation {}, should be in form of KEY=VALUE", v),
+                }
+            }
+        }
+        Ok(annotations)
+    }
+
+    fn get_threads(matches: &clap::ArgMatches) -> Result<usize> {
+        match matches.get_one::<String>("threads") {
+            None => Ok(std::thread::available_parallelism()
+                .map(|v| v.get())
+                .unwrap_or(1)),
+            Some(v) => {
+                let threads: usize = v
+                    .parse()
+                    .with_context(|| format!("invalid number of threads: {}", v))?;
+                if threads == 0 {
+                    bail!("invalid number of threads: {}", v);
+                }
+                Ok(threads)
+            }
+        }
+    }
+
+    fn get_cipher_context(
+        matches: &clap::ArgMatches,
+        version: RafsVersion,
+        ty: ConversionType,
+    ) -> Result<Option<CipherContext>> {
+        let key_id = match matches.get_one::<String>("encrypt-key-id") {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+        if !version.is_v6() {
+            bail!("'--encrypt-key-id' is only supported by RAFS V6");
+        }
+        match ty {
+            ConversionType::DirectoryToRafs
+            | ConversionType::EStargzToRafs
+            | ConversionType::TargzToRafs
+            | ConversionType::TargzStreamToRafs
+            | ConversionType::TarToRafs => {}
+            _ => bail!("conversion type '{}' conflicts with '--encrypt-key-id'", ty),
+        }
+
+        // Safe to unwrap because `encrypt-key-file` is required by `encrypt-key-id`.
+        let key_file = matches.get_one::<String>("encrypt-key-file").unwrap();
+        let key = fs::read_to_string(key_file)
+            .with_context(|| format!("failed to read master key from {}", key_file))?;
+        let master = CipherKey::from_hex(&key)
+            .with_context(|| format!("invalid master key in {}", key_file))?;
+        let cipher_ctx = CipherContext::new(key_id, &master)?;
+
+        Ok(Some(cipher_ctx))
+    }
+
     fn get_prefetch(matches: &clap::ArgMatches) -> Result<Prefetch> {
         let prefetch_policy = matches
             .get_one::<String>("prefetch-policy")
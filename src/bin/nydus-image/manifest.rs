@@ -0,0 +1,94 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Build manifest for reproducible builds.
+//!
+//! The manifest lists files in the generated RAFS filesystem with their data chunk digests,
+//! together with configuration used to build the filesystem, so two reproducible builds may be
+//! compared without parsing the bootstrap.
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nydus_builder::BuildContext;
+use nydus_rafs::metadata::{RafsMode, RafsSuper};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ManifestConfig {
+    fs_version: u32,
+    compressor: String,
+    digester: String,
+    chunk_size: u32,
+    explicit_uidgid: bool,
+    aligned_chunk: bool,
+    source_date_epoch: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    path: PathBuf,
+    mode: u32,
+    size: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    chunks: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    config: ManifestConfig,
+    files: Vec<ManifestFile>,
+}
+
+/// Get path of the manifest file for a bootstrap, `<bootstrap>.manifest.json`.
+pub fn manifest_path(bootstrap: &Path) -> PathBuf {
+    let mut name = bootstrap.as_os_str().to_os_string();
+    name.push(".manifest.json");
+    PathBuf::from(name)
+}
+
+/// Generate the build manifest for `bootstrap` and save it alongside the bootstrap.
+pub fn dump(ctx: &BuildContext, bootstrap: &Path) -> Result<PathBuf> {
+    let rs = RafsSuper::load_from_metadata(bootstrap, RafsMode::Direct, false)
+        .with_context(|| format!("failed to load bootstrap {}", bootstrap.display()))?;
+    let config = ManifestConfig {
+        fs_version: rs.meta.version >> 8,
+        compressor: ctx.compressor.to_string(),
+        digester: ctx.digester.to_string(),
+        chunk_size: ctx.chunk_size,
+        explicit_uidgid: ctx.explicit_uidgid,
+        aligned_chunk: ctx.aligned_chunk,
+        source_date_epoch: ctx.source_date_epoch,
+    };
+
+    let mut files = Vec::new();
+    rs.walk_directory::<PathBuf>(rs.superblock.root_ino(), None, &mut |inode, path| {
+        let mut chunks = Vec::new();
+        if inode.is_reg() {
+            for idx in 0..inode.get_chunk_count() {
+                chunks.push(inode.get_chunk_info(idx)?.chunk_id().to_string());
+            }
+        }
+        files.push(ManifestFile {
+            path: path.to_path_buf(),
+            mode: inode.get_attr().mode,
+            size: inode.size(),
+            chunks,
+        });
+        Ok(())
+    })?;
+
+    let path = manifest_path(bootstrap);
+    let w = OpenOptions::new()
+        .truncate(true)
+        .create(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("can not open manifest file {}", path.display()))?;
+    serde_json::to_writer_pretty(w, &Manifest { config, files })
+        .context("failed to write build manifest")?;
+
+    Ok(path)
+}
@@ -2,23 +2,28 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fs::File;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use nydus_rafs::metadata::{RafsInodeExt, RafsMode, RafsSuper, RafsSuperMeta, RafsVersion};
+use nydus_rafs::metadata::{
+    RafsChunkDict, RafsInodeExt, RafsMode, RafsSuper, RafsSuperMeta, RafsVersion,
+};
+use nydus_rafs::RafsIoReader;
 use nydus_utils::compress;
 use nydus_utils::digest;
 
-use crate::core::bootstrap::Bootstrap;
-use crate::core::chunk_dict::HashChunkDict;
-use crate::core::context::{
+use nydus_builder::core::bootstrap::Bootstrap;
+use nydus_builder::core::chunk_dict::{ChunkDict, HashChunkDict};
+use nydus_builder::core::context::{
     ArtifactStorage, BlobContext, BlobManager, BootstrapContext, BuildContext, BuildOutput,
 };
-use crate::core::node::{ChunkSource, Overlay, WhiteoutSpec};
-use crate::core::tree::{MetadataTreeBuilder, Tree};
+use nydus_builder::core::node::{ChunkSource, Overlay, WhiteoutSpec};
+use nydus_builder::core::prefetch::{Prefetch, PrefetchPolicy};
+use nydus_builder::core::tree::{MetadataTreeBuilder, Tree};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct Flags {
@@ -31,7 +36,7 @@ impl Flags {
     fn from_meta(meta: &RafsSuperMeta) -> Self {
         Self {
             compressor: meta.get_compressor(),
-            digester: meta.get_digester(),
+            digester: meta.get_digest_algorithm(),
             explicit_uidgid: meta.explicit_uidgid(),
         }
     }
@@ -62,8 +67,56 @@ impl Merger {
         Ok(blob_hash.to_string())
     }
 
+    /// Get paths of files in the prefetch table of a bootstrap.
+    fn get_prefetch_paths(rs: &RafsSuper, bootstrap_path: &Path) -> Result<Vec<String>> {
+        if rs.meta.prefetch_table_entries == 0 {
+            return Ok(Vec::new());
+        }
+
+        let file = File::open(bootstrap_path)
+            .with_context(|| format!("failed to open bootstrap {:?}", bootstrap_path))?;
+        let mut reader = Box::new(file) as RafsIoReader;
+        let inos = rs.get_prefetched_inos(&mut reader)?;
+        // RAFS v6 only records parents of directories, so resolve paths by walking the tree.
+        let mut found: HashMap<u64, Option<PathBuf>> =
+            inos.iter().map(|v| (*v as u64, None)).collect();
+        rs.walk_directory::<PathBuf>(
+            rs.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> Result<()> {
+                if let Some(v) = found.get_mut(&inode.ino()) {
+                    v.get_or_insert_with(|| path.to_path_buf());
+                }
+                Ok(())
+            },
+        )?;
+
+        let mut paths = Vec::new();
+        for ino in inos {
+            let path = found
+                .get(&(ino as u64))
+                .and_then(|v| v.as_ref())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "invalid prefetch table entry {} in {:?}",
+                        ino,
+                        bootstrap_path
+                    )
+                })?;
+            paths.push(path.to_string_lossy().to_string());
+        }
+
+        Ok(paths)
+    }
+
     /// Generate the merged RAFS bootstrap for an image from per layer RAFS bootstraps.
     ///
+    /// Only metadata of the per layer bootstraps is accessed, data blobs are never read. Chunks
+    /// with the same digest are deduplicated across layers, so they always refer to the data
+    /// blob of the lowest layer containing them. When no prefetch policy is specified, entries
+    /// from the prefetch table of the uppermost layer are kept if they still exist in the merged
+    /// filesystem.
+    ///
     /// # Arguments
     /// - sources: contains one or more per layer bootstraps in order of lower to higher.
     /// - chunk_dict: contain the chunk dictionary used to build per layer boostrap, or None.
@@ -80,17 +133,20 @@ impl Merger {
         // Get the blobs come from chunk dict bootstrap.
         let mut chunk_dict_blobs = HashSet::new();
         if let Some(chunk_dict_path) = &chunk_dict {
-            let rs = RafsSuper::load_from_metadata(chunk_dict_path, RafsMode::Direct, true)
+            let dict = RafsChunkDict::from_metadata(chunk_dict_path)
                 .context(format!("load chunk dict bootstrap {:?}", chunk_dict_path))?;
-            for blob in rs.superblock.get_blob_infos() {
+            for blob in dict.get_blob_infos()? {
                 chunk_dict_blobs.insert(blob.blob_id().to_string());
             }
         }
 
         let mut fs_version = None;
+        let mut first_meta: Option<RafsSuperMeta> = None;
         let mut flags: Option<Flags> = None;
         let mut chunk_size = None;
         let mut tree: Option<Tree> = None;
+        let mut chunk_dict = HashChunkDict::default();
+        let mut prefetch_paths = Vec::new();
         let mut blob_mgr = BlobManager::new();
         for (layer_idx, bootstrap_path) in sources.iter().enumerate() {
             let rs = RafsSuper::load_from_metadata(bootstrap_path, RafsMode::Direct, true)
@@ -109,6 +165,20 @@ impl Merger {
                 }
             }
 
+            // All layers must use the same chunk size, compression and digest algorithms.
+            match &first_meta {
+                None => first_meta = Some(rs.meta.clone()),
+                Some(meta) => {
+                    if !meta.is_compatible_with(&rs.meta) {
+                        bail!(
+                            "can not merge bootstraps with incompatible layer {:?}: {}",
+                            bootstrap_path,
+                            meta.incompatibility_reasons(&rs.meta).join(", ")
+                        );
+                    }
+                }
+            }
+
             let current_flags = Flags::from_meta(&rs.meta);
             if let Some(flags) = &flags {
                 if flags != &current_flags {
@@ -128,7 +198,7 @@ impl Merger {
             let blob_hash = Self::get_blob_hash(bootstrap_path)?;
             let mut blob_idx_map = Vec::new();
             let mut parent_blob_added = false;
-            for blob in rs.superblock.get_blob_infos() {
+            for blob in rs.superblock.get_blob_infos(true)? {
                 let mut blob_ctx = BlobContext::from(ctx, &blob, ChunkSource::Parent);
                 if chunk_dict_blobs.get(blob.blob_id()).is_none() {
                     // It is assumed that the `nydus-image create` at each layer and `nydus-image merge` commands
@@ -167,6 +237,10 @@ impl Merger {
                 }
             }
 
+            if layer_idx == sources.len() - 1 && ctx.prefetch.policy == PrefetchPolicy::None {
+                prefetch_paths = Self::get_prefetch_paths(&rs, bootstrap_path)?;
+            }
+
             if let Some(tree) = &mut tree {
                 let mut nodes = Vec::new();
                 rs.walk_directory::<PathBuf>(
@@ -183,6 +257,16 @@ impl Merger {
                             let origin_blob_index = chunk.inner.blob_index() as usize;
                             // Set the blob index of chunk to real index in blob table of final bootstrap.
                             chunk.inner.set_blob_index(blob_idx_map[origin_blob_index]);
+                            // Reuse the chunk from lower layers if it has the same digest.
+                            match chunk_dict.get_chunk(chunk.inner.id()) {
+                                Some(c) if c.blob_index() != chunk.inner.blob_index() => {
+                                    let file_offset = chunk.inner.file_offset();
+                                    chunk.inner.copy_from(c);
+                                    chunk.inner.set_file_offset(file_offset);
+                                }
+                                Some(_) => {}
+                                None => chunk_dict.add_chunk(chunk.inner.clone()),
+                            }
                         }
                         // Set node's layer index to distinguish same inode number (from bootstrap)
                         // between different layers.
@@ -205,8 +289,9 @@ impl Merger {
                     tree.apply(node, true, WhiteoutSpec::Oci)?;
                 }
             } else {
-                let mut dict = HashChunkDict::default();
-                tree = Some(Tree::from_bootstrap(&rs, &mut dict)?);
+                let mut lowest = Tree::from_bootstrap(&rs, &mut chunk_dict)?;
+                lowest.remove_whiteouts(WhiteoutSpec::Oci);
+                tree = Some(lowest);
             }
         }
 
@@ -217,6 +302,9 @@ impl Merger {
         if let Some(chunk_size) = chunk_size {
             ctx.chunk_size = chunk_size;
         }
+        if !prefetch_paths.is_empty() {
+            ctx.prefetch = Prefetch::from_paths(prefetch_paths)?;
+        }
 
         let mut bootstrap_ctx = BootstrapContext::new(Some(target.clone()), false, false)?;
         let mut bootstrap = Bootstrap::new()?;
@@ -0,0 +1,396 @@
+// Copyright 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pack RAFS bootstrap and data blobs into an OCI image layout, and the inverse.
+//!
+//! The generated image follows the conventions expected by nydus snapshotter:
+//! - each data blob is a layer of media type `application/vnd.oci.image.layer.nydus.blob.v1`,
+//!   with annotation `containerd.io/snapshot/nydus-blob: true`, and the digest of the layer must
+//!   be the same as the blob id.
+//! - the bootstrap is the last layer, which is a gzip compressed tar file containing the bootstrap
+//!   as `image/image.boot`, with annotation `containerd.io/snapshot/nydus-bootstrap: true`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nydus_rafs::metadata::{RafsMode, RafsSuper};
+use nydus_utils::compress;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+const OCI_SCHEMA_VERSION: u32 = 2;
+
+const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const MEDIA_TYPE_OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const MEDIA_TYPE_OCI_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+/// Media type of nydus data blob layers.
+const MEDIA_TYPE_NYDUS_BLOB: &str = "application/vnd.oci.image.layer.nydus.blob.v1";
+
+/// Annotation to mark a layer as nydus data blob.
+const ANNOTATION_NYDUS_BLOB: &str = "containerd.io/snapshot/nydus-blob";
+/// Annotation to mark a layer as nydus bootstrap.
+const ANNOTATION_NYDUS_BOOTSTRAP: &str = "containerd.io/snapshot/nydus-bootstrap";
+/// Annotation of RAFS version of the bootstrap.
+const ANNOTATION_NYDUS_FS_VERSION: &str = "containerd.io/snapshot/nydus-fs-version";
+/// Annotation of ids of data blobs referenced by the bootstrap, in JSON array format.
+const ANNOTATION_NYDUS_BLOB_IDS: &str = "containerd.io/snapshot/nydus-blob-ids";
+/// Annotation of chunk size of the RAFS filesystem.
+const ANNOTATION_NYDUS_CHUNK_SIZE: &str = "containerd.io/snapshot/nydus-chunk-size";
+/// Annotation of compression algorithm of data blobs.
+const ANNOTATION_NYDUS_COMPRESSOR: &str = "containerd.io/snapshot/nydus-compressor";
+
+/// Path of the bootstrap inside the bootstrap layer.
+const BOOTSTRAP_ENTRY: &str = "image/image.boot";
+/// Name of the bootstrap file extracted by `OciLayout::unpack()`.
+const BOOTSTRAP_NAME: &str = "image.boot";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+impl Descriptor {
+    fn annotation_enabled(&self, key: &str) -> bool {
+        self.annotations.get(key).map(|v| v.as_str()) == Some("true")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: String,
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: String,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Serialize)]
+struct RootFs {
+    #[serde(rename = "type")]
+    ty: String,
+    diff_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ImageConfig {
+    architecture: String,
+    os: String,
+    config: BTreeMap<String, String>,
+    rootfs: RootFs,
+}
+
+/// Helper to pack/unpack RAFS images into/from an OCI image layout directory.
+pub struct OciLayout {
+    dir: PathBuf,
+}
+
+impl OciLayout {
+    /// Create an `OciLayout` object to access the OCI image layout at `dir`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        OciLayout {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Pack the bootstrap and data blobs referenced by it into the OCI image layout.
+    ///
+    /// Data blobs are looked up in `blob_dir` by blob id.
+    pub fn pack(&self, bootstrap: &Path, blob_dir: &Path) -> Result<()> {
+        let rs = RafsSuper::load_from_metadata(bootstrap, RafsMode::Direct, false)
+            .with_context(|| format!("failed to load bootstrap {:?}", bootstrap))?;
+        fs::create_dir_all(self.blob_dir())
+            .with_context(|| format!("failed to create OCI image layout {:?}", self.dir))?;
+
+        let mut layers = Vec::new();
+        let mut diff_ids = Vec::new();
+        let mut blob_ids = Vec::new();
+        for blob in rs.superblock.get_blob_infos(true)? {
+            let path = blob_dir.join(blob.blob_id());
+            let file = File::open(&path)
+                .with_context(|| format!("failed to open data blob {:?}", path))?;
+            let (digest, size) = self.write_blob(file)?;
+            if digest != format!("sha256:{}", blob.blob_id()) {
+                bail!(
+                    "digest {} of data blob {:?} doesn't match blob id {}",
+                    digest,
+                    path,
+                    blob.blob_id()
+                );
+            }
+            let mut annotations = BTreeMap::new();
+            annotations.insert(ANNOTATION_NYDUS_BLOB.to_string(), "true".to_string());
+            layers.push(Descriptor {
+                media_type: MEDIA_TYPE_NYDUS_BLOB.to_string(),
+                digest: digest.clone(),
+                size,
+                annotations,
+            });
+            diff_ids.push(digest);
+            blob_ids.push(blob.blob_id().to_string());
+        }
+
+        let (desc, diff_id) = self
+            .write_bootstrap_layer(bootstrap, &rs, &blob_ids)
+            .with_context(|| format!("failed to pack bootstrap {:?}", bootstrap))?;
+        layers.push(desc);
+        diff_ids.push(diff_id);
+
+        let config = ImageConfig {
+            architecture: Self::architecture().to_string(),
+            os: std::env::consts::OS.to_string(),
+            config: BTreeMap::new(),
+            rootfs: RootFs {
+                ty: "layers".to_string(),
+                diff_ids,
+            },
+        };
+        let config = serde_json::to_vec(&config).context("failed to serialize image config")?;
+        let (digest, size) = self.write_blob(config.as_slice())?;
+        let manifest = Manifest {
+            schema_version: OCI_SCHEMA_VERSION,
+            media_type: MEDIA_TYPE_OCI_MANIFEST.to_string(),
+            config: Descriptor {
+                media_type: MEDIA_TYPE_OCI_CONFIG.to_string(),
+                digest,
+                size,
+                annotations: BTreeMap::new(),
+            },
+            layers,
+        };
+        let manifest = serde_json::to_vec(&manifest).context("failed to serialize manifest")?;
+        let (digest, size) = self.write_blob(manifest.as_slice())?;
+
+        let index = Index {
+            schema_version: OCI_SCHEMA_VERSION,
+            media_type: MEDIA_TYPE_OCI_INDEX.to_string(),
+            manifests: vec![Descriptor {
+                media_type: MEDIA_TYPE_OCI_MANIFEST.to_string(),
+                digest,
+                size,
+                annotations: BTreeMap::new(),
+            }],
+        };
+        let index = serde_json::to_vec(&index).context("failed to serialize image index")?;
+        fs::write(self.dir.join("index.json"), index).context("failed to write image index")?;
+        let layout = json!({ "imageLayoutVersion": OCI_LAYOUT_VERSION }).to_string();
+        fs::write(self.dir.join("oci-layout"), layout).context("failed to write oci-layout")?;
+
+        Ok(())
+    }
+
+    /// Extract the bootstrap and data blobs from the OCI image layout into `output`.
+    ///
+    /// The bootstrap is saved as `output/image.boot` and data blobs are saved as `output/<blob id>`,
+    /// so `output` can be used as blob directory of the localfs backend to mount the image.
+    pub fn unpack(&self, output: &Path) -> Result<()> {
+        let index = fs::read(self.dir.join("index.json"))
+            .with_context(|| format!("failed to read image index from {:?}", self.dir))?;
+        let index: Index = serde_json::from_slice(&index).context("invalid image index")?;
+        let desc = index
+            .manifests
+            .iter()
+            .find(|d| d.media_type == MEDIA_TYPE_OCI_MANIFEST)
+            .ok_or_else(|| anyhow!("no image manifest found in image index"))?;
+        let manifest = self.read_blob(desc)?;
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).context("invalid image manifest")?;
+
+        fs::create_dir_all(output)
+            .with_context(|| format!("failed to create output directory {:?}", output))?;
+        let mut bootstrap_found = false;
+        for layer in manifest.layers.iter() {
+            if layer.annotation_enabled(ANNOTATION_NYDUS_BOOTSTRAP) {
+                self.extract_bootstrap(layer, &output.join(BOOTSTRAP_NAME))?;
+                bootstrap_found = true;
+            } else if layer.annotation_enabled(ANNOTATION_NYDUS_BLOB) {
+                let path = self.verify_blob(layer)?;
+                // Safe to unwrap because the digest has been validated.
+                let blob_id = layer.digest.strip_prefix("sha256:").unwrap();
+                fs::copy(path, output.join(blob_id))
+                    .with_context(|| format!("failed to extract data blob {}", layer.digest))?;
+            } else {
+                warn!("skip non-nydus layer {}", layer.digest);
+            }
+        }
+        if !bootstrap_found {
+            bail!("no nydus bootstrap layer found in image manifest");
+        }
+
+        Ok(())
+    }
+
+    fn write_bootstrap_layer(
+        &self,
+        bootstrap: &Path,
+        rs: &RafsSuper,
+        blob_ids: &[String],
+    ) -> Result<(Descriptor, String)> {
+        let data = fs::read(bootstrap)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_path(BOOTSTRAP_ENTRY)?;
+        header.set_size(data.len() as u64);
+        header.set_mode(0o444);
+        header.set_mtime(0);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, data.as_slice())?;
+        let tarball = builder.into_inner()?;
+        let diff_id = format!("sha256:{:x}", Sha256::digest(&tarball));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tarball)?;
+        let (digest, size) = self.write_blob(encoder.finish()?.as_slice())?;
+
+        let fs_version = if rs.meta.is_v5() { "5" } else { "6" };
+        let compressor = match rs.meta.get_compressor() {
+            compress::Algorithm::None => "none",
+            compress::Algorithm::Lz4Block => "lz4_block",
+            compress::Algorithm::GZip => "gzip",
+            compress::Algorithm::Zstd => "zstd",
+            compress::Algorithm::Snappy => "snappy",
+        };
+        let mut annotations = BTreeMap::new();
+        annotations.insert(ANNOTATION_NYDUS_BOOTSTRAP.to_string(), "true".to_string());
+        annotations.insert(
+            ANNOTATION_NYDUS_FS_VERSION.to_string(),
+            fs_version.to_string(),
+        );
+        annotations.insert(
+            ANNOTATION_NYDUS_BLOB_IDS.to_string(),
+            serde_json::to_string(blob_ids)?,
+        );
+        annotations.insert(
+            ANNOTATION_NYDUS_CHUNK_SIZE.to_string(),
+            rs.meta.chunk_size.to_string(),
+        );
+        annotations.insert(
+            ANNOTATION_NYDUS_COMPRESSOR.to_string(),
+            compressor.to_string(),
+        );
+
+        let desc = Descriptor {
+            media_type: MEDIA_TYPE_OCI_LAYER_GZIP.to_string(),
+            digest,
+            size,
+            annotations,
+        };
+
+        Ok((desc, diff_id))
+    }
+
+    fn extract_bootstrap(&self, layer: &Descriptor, path: &Path) -> Result<()> {
+        let blob_path = self.verify_blob(layer)?;
+        let file = File::open(blob_path)
+            .with_context(|| format!("failed to open bootstrap layer {}", layer.digest))?;
+        let decoder = compress::Decoder::new(file, compress::Algorithm::GZip)?;
+        let mut archive = tar::Archive::new(decoder);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() == Path::new(BOOTSTRAP_ENTRY) {
+                let mut output = File::create(path)
+                    .with_context(|| format!("failed to create bootstrap {:?}", path))?;
+                io::copy(&mut entry, &mut output)
+                    .with_context(|| format!("failed to extract bootstrap {:?}", path))?;
+                return Ok(());
+            }
+        }
+
+        bail!(
+            "no {} found in bootstrap layer {}",
+            BOOTSTRAP_ENTRY,
+            layer.digest
+        )
+    }
+
+    /// Write content from `reader` into the blob store, return its digest and size.
+    fn write_blob<R: Read>(&self, mut reader: R) -> Result<(String, u64)> {
+        let tmp_path = self.blob_dir().join(".tmp");
+        let mut file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create blob file {:?}", tmp_path))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 0x10_0000];
+        let mut size = 0u64;
+        loop {
+            let count = reader.read(&mut buf).context("failed to read blob data")?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buf[..count]);
+            file.write_all(&buf[..count])
+                .with_context(|| format!("failed to write blob file {:?}", tmp_path))?;
+            size += count as u64;
+        }
+
+        let digest = format!("sha256:{:x}", hasher.finalize());
+        fs::rename(&tmp_path, self.blob_path(&digest)?)
+            .with_context(|| format!("failed to rename blob file {:?}", tmp_path))?;
+
+        Ok((digest, size))
+    }
+
+    fn read_blob(&self, desc: &Descriptor) -> Result<Vec<u8>> {
+        let path = self.verify_blob(desc)?;
+        fs::read(path).with_context(|| format!("failed to read blob {}", desc.digest))
+    }
+
+    /// Verify content of the blob against its descriptor, return path of the blob file.
+    fn verify_blob(&self, desc: &Descriptor) -> Result<PathBuf> {
+        let path = self.blob_path(&desc.digest)?;
+        let mut file =
+            File::open(&path).with_context(|| format!("failed to open blob {:?}", path))?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)
+            .with_context(|| format!("failed to read blob {:?}", path))?;
+        if size != desc.size || format!("sha256:{:x}", hasher.finalize()) != desc.digest {
+            bail!("content of blob {:?} doesn't match its descriptor", path);
+        }
+
+        Ok(path)
+    }
+
+    fn blob_dir(&self) -> PathBuf {
+        self.dir.join("blobs").join("sha256")
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        match digest.strip_prefix("sha256:") {
+            Some(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Ok(self.blob_dir().join(hex))
+            }
+            _ => bail!("invalid or unsupported digest {}", digest),
+        }
+    }
+
+    /// Get the architecture name defined by OCI image spec for current platform.
+    fn architecture() -> &'static str {
+        match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "powerpc64" => "ppc64le",
+            v => v,
+        }
+    }
+}
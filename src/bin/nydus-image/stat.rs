@@ -8,12 +8,11 @@ use std::path::Path;
 use std::sync::atomic::Ordering;
 
 use anyhow::{Context, Result};
+use nydus_builder::core::chunk_dict::{ChunkDict, HashChunkDict};
+use nydus_builder::core::tree::Tree;
 use nydus_rafs::metadata::{RafsMode, RafsSuper};
 use serde::Serialize;
 
-use crate::core::chunk_dict::{ChunkDict, HashChunkDict};
-use crate::core::tree::Tree;
-
 #[derive(Copy, Clone, Default, Serialize)]
 struct DedupInfo {
     raw_chunks: u64,
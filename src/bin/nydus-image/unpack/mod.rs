@@ -128,7 +128,7 @@ impl OCITarBuilderFactory {
     ) -> Result<Box<dyn TarBuilder>> {
         let writer = self.create_writer(output_path)?;
 
-        let blob = meta.superblock.get_blob_infos().pop();
+        let blob = meta.superblock.get_blob_infos(true)?.pop();
         let builders = self.create_builders(blob, blob_path)?;
 
         let builder = OCITarBuilder::new(builders, writer);
@@ -723,6 +723,11 @@ impl ChunkReader {
     }
 
     fn load_chunk(&mut self, chunk: &dyn BlobChunkInfo) -> Result<()> {
+        if chunk.is_zeroed() {
+            self.chunk = Cursor::new(vec![0u8; chunk.uncompressed_size() as usize]);
+            return Ok(());
+        }
+
         let mut buf = alloc_buf(chunk.compressed_size() as usize);
         self.reader
             .read(buf.as_mut_slice(), chunk.compressed_offset())
@@ -4,14 +4,68 @@
 
 //! Validator for RAFS format
 
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use nydus_rafs::metadata::{RafsMode, RafsSuper};
-use nydus_storage::device::BlobInfo;
+use nydus_api::http::LocalFsConfig;
+use nydus_builder::core::tree::Tree;
+use nydus_rafs::metadata::{RafsInodeExt, RafsMode, RafsSuper};
+use nydus_storage::backend::{localfs::LocalFs, BlobBackend, BlobReader};
+use nydus_storage::device::{BlobChunkInfo, BlobInfo};
+use nydus_storage::meta::BLOB_META_FEATURE_ZRAN;
+use nydus_utils::compress;
+use nydus_utils::digest::RafsDigest;
+use serde::{Deserialize, Serialize};
 
-use crate::tree::Tree;
+/// Category of differences between a RAFS filesystem and its source directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffKind {
+    /// The file exists in the source directory but not in the RAFS filesystem.
+    Missing,
+    /// The file exists in the RAFS filesystem but not in the source directory.
+    Extra,
+    /// The file has different mode, size, symlink target, xattrs or hardlinks.
+    MetadataMismatch,
+    /// The file has different data content.
+    ContentMismatch,
+}
+
+/// A difference between a RAFS filesystem and its source directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Difference {
+    pub kind: DiffKind,
+    pub path: PathBuf,
+    pub detail: String,
+}
+
+impl Difference {
+    fn new(kind: DiffKind, path: &Path, detail: String) -> Self {
+        Difference {
+            kind,
+            path: path.to_path_buf(),
+            detail,
+        }
+    }
+}
+
+/// Information about a RAFS inode to compare with the source file.
+struct RafsEntry {
+    ino: u64,
+    mode: u32,
+    size: u64,
+    nlink: u32,
+    symlink: Option<OsString>,
+    xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
+    chunks: Vec<Arc<dyn BlobChunkInfo>>,
+}
 
 pub struct Validator {
     sb: RafsSuper,
@@ -38,6 +92,453 @@ impl Validator {
             true
         })?;
 
-        Ok(self.sb.superblock.get_blob_infos())
+        Ok(self.sb.superblock.get_blob_infos(true)?)
+    }
+
+    /// Compare the RAFS filesystem with the source directory it's built from.
+    ///
+    /// File names, modes, sizes, symlink targets, xattrs and hardlinks are always compared. With
+    /// `deep` enabled, chunk digests are also compared with digests recomputed from source files,
+    /// nlink of directories is verified against the number of child directories, and if
+    /// `blob_dir` is given, chunk data in data blobs are verified against chunk digests.
+    /// Files removed from the source directory while comparing are ignored.
+    pub fn compare(
+        &self,
+        source: &Path,
+        deep: bool,
+        blob_dir: Option<&Path>,
+    ) -> Result<Vec<Difference>> {
+        let entries = self.collect_rafs_entries(deep)?;
+        let mut sources = BTreeMap::new();
+        let root_meta = fs::symlink_metadata(source)
+            .with_context(|| format!("failed to stat source directory {:?}", source))?;
+        if !root_meta.is_dir() {
+            bail!("source {:?} is not a directory", source);
+        }
+        sources.insert(PathBuf::from("/"), root_meta);
+        Self::collect_source_entries(source, Path::new("/"), &mut sources)?;
+
+        let mut diffs = Vec::new();
+        let mut blob_readers = HashMap::new();
+        for (path, meta) in sources.iter() {
+            match entries.get(path) {
+                None => diffs.push(Difference::new(
+                    DiffKind::Missing,
+                    path,
+                    "not found in RAFS filesystem".to_string(),
+                )),
+                Some(entry) => {
+                    let source_path = source.join(path.strip_prefix("/").unwrap_or(path));
+                    self.compare_entry(&source_path, path, meta, entry, &mut diffs)?;
+                    if deep && meta.is_file() && meta.size() == entry.size {
+                        self.compare_content(&source_path, path, entry, &mut diffs)?;
+                    }
+                    if let Some(dir) = blob_dir {
+                        self.verify_blob_data(dir, path, entry, &mut blob_readers, &mut diffs)?;
+                    }
+                }
+            }
+        }
+        for path in entries.keys() {
+            if !sources.contains_key(path) {
+                diffs.push(Difference::new(
+                    DiffKind::Extra,
+                    path,
+                    "not found in source directory".to_string(),
+                ));
+            }
+        }
+        Self::compare_hardlinks(&sources, &entries, &mut diffs);
+        if deep {
+            Self::check_dir_nlink(&entries, &mut diffs);
+        }
+
+        Ok(diffs)
+    }
+
+    fn collect_rafs_entries(&self, deep: bool) -> Result<BTreeMap<PathBuf, RafsEntry>> {
+        let mut entries = BTreeMap::new();
+
+        self.sb.walk_directory::<PathBuf>(
+            self.sb.superblock.root_ino(),
+            None,
+            &mut |inode: &dyn RafsInodeExt, path: &Path| -> Result<()> {
+                let attr = inode.get_attr();
+                let symlink = if inode.is_symlink() {
+                    Some(inode.get_symlink()?)
+                } else {
+                    None
+                };
+                let mut xattrs = BTreeMap::new();
+                if inode.has_xattr() {
+                    for name in inode.get_xattrs()? {
+                        let value = inode.get_xattr(OsStr::from_bytes(&name))?;
+                        xattrs.insert(name, value.unwrap_or_default());
+                    }
+                }
+                let mut chunks = Vec::new();
+                if deep && inode.is_reg() {
+                    for idx in 0..inode.get_chunk_count() {
+                        chunks.push(inode.get_chunk_info(idx)?);
+                    }
+                }
+                entries.insert(
+                    path.to_path_buf(),
+                    RafsEntry {
+                        ino: inode.ino(),
+                        mode: attr.mode,
+                        size: attr.size,
+                        nlink: attr.nlink,
+                        symlink,
+                        xattrs,
+                        chunks,
+                    },
+                );
+                Ok(())
+            },
+        )?;
+
+        Ok(entries)
+    }
+
+    fn collect_source_entries(
+        dir: &Path,
+        path: &Path,
+        sources: &mut BTreeMap<PathBuf, fs::Metadata>,
+    ) -> Result<()> {
+        let children = match fs::read_dir(dir) {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!("directory {:?} has been removed while comparing", dir);
+                return Ok(());
+            }
+            Err(e) => return Err(e).context(format!("failed to read directory {:?}", dir)),
+        };
+
+        for child in children {
+            let child = child.with_context(|| format!("failed to read directory {:?}", dir))?;
+            let meta = match fs::symlink_metadata(child.path()) {
+                Ok(v) => v,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    warn!("file {:?} has been removed while comparing", child.path());
+                    continue;
+                }
+                Err(e) => return Err(e).context(format!("failed to stat {:?}", child.path())),
+            };
+            let child_path = path.join(child.file_name());
+            let is_dir = meta.is_dir();
+            sources.insert(child_path.clone(), meta);
+            if is_dir {
+                Self::collect_source_entries(&child.path(), &child_path, sources)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compare_entry(
+        &self,
+        source_path: &Path,
+        path: &Path,
+        meta: &fs::Metadata,
+        entry: &RafsEntry,
+        diffs: &mut Vec<Difference>,
+    ) -> Result<()> {
+        if meta.mode() != entry.mode {
+            diffs.push(Difference::new(
+                DiffKind::MetadataMismatch,
+                path,
+                format!("mode 0o{:o} != 0o{:o}", entry.mode, meta.mode()),
+            ));
+            // Skip other checks if file types are different.
+            if meta.mode() & libc::S_IFMT != entry.mode & libc::S_IFMT {
+                return Ok(());
+            }
+        }
+
+        let file_type = meta.file_type();
+        if file_type.is_file() && meta.size() != entry.size {
+            diffs.push(Difference::new(
+                DiffKind::MetadataMismatch,
+                path,
+                format!("size 0x{:x} != 0x{:x}", entry.size, meta.size()),
+            ));
+        } else if file_type.is_symlink() {
+            match fs::read_link(source_path) {
+                Ok(target) => {
+                    if entry.symlink.as_deref() != Some(target.as_os_str()) {
+                        diffs.push(Difference::new(
+                            DiffKind::MetadataMismatch,
+                            path,
+                            format!("symlink target {:?} != {:?}", entry.symlink, target),
+                        ));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => {
+                    return Err(e).context(format!("failed to read symlink {:?}", source_path))
+                }
+            }
+        }
+
+        let mut xattrs = BTreeMap::new();
+        match xattr::list(source_path) {
+            Ok(names) => {
+                for name in names {
+                    let value = xattr::get(source_path, &name)
+                        .with_context(|| format!("failed to get xattr of {:?}", source_path))?;
+                    xattrs.insert(name.as_bytes().to_vec(), value.unwrap_or_default());
+                }
+            }
+            Err(e)
+                if e.raw_os_error() == Some(libc::EOPNOTSUPP)
+                    || e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("failed to list xattr of {:?}", source_path)),
+        }
+        if xattrs != entry.xattrs {
+            diffs.push(Difference::new(
+                DiffKind::MetadataMismatch,
+                path,
+                format!(
+                    "xattrs {:?} != {:?}",
+                    Self::format_xattr_names(&entry.xattrs),
+                    Self::format_xattr_names(&xattrs)
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn format_xattr_names(xattrs: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<String> {
+        xattrs
+            .keys()
+            .map(|k| String::from_utf8_lossy(k).to_string())
+            .collect()
+    }
+
+    /// Compare chunk digests with digests recomputed from the source file.
+    fn compare_content(
+        &self,
+        source_path: &Path,
+        path: &Path,
+        entry: &RafsEntry,
+        diffs: &mut Vec<Difference>,
+    ) -> Result<()> {
+        let mut file = match File::open(source_path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context(format!("failed to open {:?}", source_path)),
+        };
+        let digester = self.sb.meta.get_digest_algorithm();
+        let mut buf = Vec::new();
+
+        for (idx, chunk) in entry.chunks.iter().enumerate() {
+            buf.resize(chunk.uncompressed_size() as usize, 0);
+            match file.read_exact(&mut buf) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    diffs.push(Difference::new(
+                        DiffKind::ContentMismatch,
+                        path,
+                        "source file has been truncated".to_string(),
+                    ));
+                    return Ok(());
+                }
+                Err(e) => return Err(e).context(format!("failed to read {:?}", source_path)),
+            }
+            if &RafsDigest::from_buf(&buf, digester) != chunk.chunk_id() {
+                diffs.push(Difference::new(
+                    DiffKind::ContentMismatch,
+                    path,
+                    format!("digest of chunk {} doesn't match source data", idx),
+                ));
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify chunk data in data blobs against chunk digests.
+    fn verify_blob_data(
+        &self,
+        blob_dir: &Path,
+        path: &Path,
+        entry: &RafsEntry,
+        readers: &mut HashMap<u32, Option<(Arc<BlobInfo>, Arc<dyn BlobReader>)>>,
+        diffs: &mut Vec<Difference>,
+    ) -> Result<()> {
+        let digester = self.sb.meta.get_digest_algorithm();
+
+        for (idx, chunk) in entry.chunks.iter().enumerate() {
+            let blob_index = chunk.blob_index();
+            if !readers.contains_key(&blob_index) {
+                let reader = self.create_blob_reader(blob_dir, blob_index)?;
+                readers.insert(blob_index, reader);
+            }
+            let (blob, reader) = match readers.get(&blob_index) {
+                Some(Some((blob, reader))) => (blob, reader),
+                _ => continue,
+            };
+
+            // No data is stored in the data blob for zeroed chunks.
+            let data = if chunk.is_zeroed() {
+                Some(vec![0u8; chunk.uncompressed_size() as usize])
+            } else {
+                let mut c_buf = vec![0u8; chunk.compressed_size() as usize];
+                let size = reader
+                    .read(&mut c_buf, chunk.compressed_offset())
+                    .map_err(|e| anyhow!("failed to read blob {}, {:?}", blob.blob_id(), e))?;
+                if size != c_buf.len() {
+                    None
+                } else if chunk.is_compressed() {
+                    let mut d_buf = vec![0u8; chunk.uncompressed_size() as usize];
+                    compress::decompress(&c_buf, &mut d_buf, blob.compressor())
+                        .ok()
+                        .map(|_| d_buf)
+                } else {
+                    Some(c_buf)
+                }
+            };
+            match data {
+                Some(d) if &RafsDigest::from_buf(&d, digester) == chunk.chunk_id() => {}
+                _ => {
+                    diffs.push(Difference::new(
+                        DiffKind::ContentMismatch,
+                        path,
+                        format!(
+                            "data of chunk {} in blob {} doesn't match chunk digest",
+                            idx,
+                            blob.blob_id()
+                        ),
+                    ));
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_blob_reader(
+        &self,
+        blob_dir: &Path,
+        blob_index: u32,
+    ) -> Result<Option<(Arc<BlobInfo>, Arc<dyn BlobReader>)>> {
+        let blob = self
+            .sb
+            .superblock
+            .get_blob_infos(true)?
+            .get(blob_index as usize)
+            .cloned()
+            .ok_or_else(|| anyhow!("invalid blob index {}", blob_index))?;
+        if blob.is_encrypted()
+            || blob.is_foreign_layer()
+            || blob.meta_flags() & BLOB_META_FEATURE_ZRAN != 0
+        {
+            warn!(
+                "skip verifying data of blob {}, which is encrypted or not a nydus data blob",
+                blob.blob_id()
+            );
+            return Ok(None);
+        }
+
+        let blob_path = blob_dir.join(blob.blob_id());
+        let config = LocalFsConfig {
+            blob_file: blob_path.display().to_string(),
+            dir: Default::default(),
+            alt_dirs: Default::default(),
+        };
+        let config = serde_json::to_value(config)
+            .with_context(|| format!("failed to create localfs config for {:?}", blob_path))?;
+        let backend = LocalFs::new(config, Some("validator"))
+            .with_context(|| format!("failed to create localfs backend for {:?}", blob_path))?;
+        let reader = backend
+            .get_reader(blob.blob_id())
+            .map_err(|e| anyhow!("failed to open blob {:?}, {:?}", blob_path, e))?;
+
+        Ok(Some((blob, reader)))
+    }
+
+    /// Check that nlink of each directory is 2 plus the number of its child directories.
+    ///
+    /// Source directories aren't used as reference because some filesystems always report 1.
+    fn check_dir_nlink(entries: &BTreeMap<PathBuf, RafsEntry>, diffs: &mut Vec<Difference>) {
+        let is_dir = |entry: &RafsEntry| entry.mode & libc::S_IFMT == libc::S_IFDIR;
+        let mut subdirs: HashMap<&Path, u32> = HashMap::new();
+        for (path, entry) in entries.iter() {
+            if is_dir(entry) {
+                subdirs.entry(path).or_default();
+                if let Some(parent) = path.parent() {
+                    *subdirs.entry(parent).or_default() += 1;
+                }
+            }
+        }
+
+        for (path, entry) in entries.iter() {
+            if is_dir(entry) {
+                let expected = 2 + subdirs.get(path.as_path()).copied().unwrap_or_default();
+                if entry.nlink != expected {
+                    diffs.push(Difference::new(
+                        DiffKind::MetadataMismatch,
+                        path,
+                        format!("directory nlink {} != {}", entry.nlink, expected),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Compare hardlink groups of the source directory and the RAFS filesystem.
+    fn compare_hardlinks(
+        sources: &BTreeMap<PathBuf, fs::Metadata>,
+        entries: &BTreeMap<PathBuf, RafsEntry>,
+        diffs: &mut Vec<Difference>,
+    ) {
+        let mut source_groups: HashMap<(u64, u64), Vec<&PathBuf>> = HashMap::new();
+        for (path, meta) in sources.iter() {
+            if !meta.is_dir() && meta.nlink() > 1 && entries.contains_key(path) {
+                source_groups
+                    .entry((meta.dev(), meta.ino()))
+                    .or_default()
+                    .push(path);
+            }
+        }
+        let mut rafs_groups: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+        for (path, entry) in entries.iter() {
+            if entry.mode & libc::S_IFMT != libc::S_IFDIR
+                && entry.nlink > 1
+                && sources.contains_key(path)
+            {
+                rafs_groups.entry(entry.ino).or_default().push(path);
+            }
+        }
+
+        for (path, entry) in entries.iter() {
+            let meta = match sources.get(path) {
+                Some(v) => v,
+                None => continue,
+            };
+            let source_group = source_groups.get(&(meta.dev(), meta.ino()));
+            let rafs_group = rafs_groups.get(&entry.ino);
+            let is_same = match (source_group, rafs_group) {
+                (Some(s), Some(r)) => s == r,
+                (None, None) => true,
+                // Single link within the compared files.
+                (Some(g), None) | (None, Some(g)) => g.len() == 1,
+            };
+            if !is_same {
+                diffs.push(Difference::new(
+                    DiffKind::MetadataMismatch,
+                    path,
+                    format!(
+                        "hardlinks {:?} != {:?}",
+                        rafs_group.cloned().unwrap_or_default(),
+                        source_group.cloned().unwrap_or_default()
+                    ),
+                ));
+            }
+        }
     }
 }
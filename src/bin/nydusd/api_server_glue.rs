@@ -3,6 +3,7 @@
 //
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::io::Result;
 use std::str::FromStr;
@@ -17,11 +18,18 @@ use nix::unistd::Pid;
 use nydus::{FsBackendType, NydusError};
 use nydus_api::{
     start_http_thread, ApiError, ApiMountCmd, ApiRequest, ApiResponse, ApiResponsePayload,
-    ApiResult, BlobCacheEntry, BlobCacheObjectId, DaemonConf, DaemonErrorKind, MetricsErrorKind,
+    ApiResult, BlobCacheEntry, BlobCacheObjectId, DaemonConf, DaemonErrorKind, FileAdviceCmd,
+    MetricsErrorKind, MountRuntimeConfig, PrefetchJobConfig, TraceConfig,
+};
+use nydus_app::{built_info, BuildTimeInfo};
+use nydus_error::error::MetricsError;
+use nydus_utils::audit::{self, EventCategory};
+use nydus_utils::{metrics, trace};
+use storage::prefetch_job::{
+    prefetch_job_factory_config, PrefetchJobBlob, PrefetchJobStatus, PREFETCH_JOB_MGR,
 };
-use nydus_utils::metrics;
 
-use crate::daemon::{DaemonError, NydusDaemon};
+use crate::daemon::{DaemonError, NydusDaemon, ServiceInfo};
 use crate::fs_service::{FsBackendMountCmd, FsBackendUmountCmd, FsService};
 use crate::DAEMON_CONTROLLER;
 
@@ -62,7 +70,11 @@ impl ApiServer {
             // Common (v1/v2)
             ApiRequest::ConfigureDaemon(conf) => self.configure_daemon(conf),
             ApiRequest::GetDaemonInfo => self.daemon_info(true),
+            ApiRequest::GetServiceInfo => self.service_info(),
             ApiRequest::GetEvents => Self::events(),
+            ApiRequest::GetAuditEvents(since) => Self::audit_events(since),
+            ApiRequest::GetTraceConfig => Self::trace_config(),
+            ApiRequest::ConfigureTrace(conf) => Self::configure_trace(conf),
             ApiRequest::Exit => self.do_exit(),
             ApiRequest::Start => self.do_start(),
             ApiRequest::SendFuseFd => self.send_fuse_fd(),
@@ -70,8 +82,14 @@ impl ApiServer {
             ApiRequest::Mount(mountpoint, info) => self.do_mount(mountpoint, info),
             ApiRequest::Remount(mountpoint, info) => self.do_remount(mountpoint, info),
             ApiRequest::Umount(mountpoint) => self.do_umount(mountpoint),
+            ApiRequest::UpdateMountConfig(mountpoint, conf) => {
+                self.do_update_mount_config(mountpoint, conf)
+            }
+            ApiRequest::AdviseFile(mountpoint, cmd) => self.advise_file(&mountpoint, &cmd),
             ApiRequest::ExportBackendMetrics(id) => Self::export_backend_metrics(id),
             ApiRequest::ExportBlobcacheMetrics(id) => Self::export_blobcache_metrics(id),
+            ApiRequest::ExportBlobDownloadMetrics(id) => Self::export_blob_download_metrics(id),
+            ApiRequest::ExportDecompressMetrics => Self::export_decompress_metrics(),
 
             // Nydus API v1
             ApiRequest::ExportFsGlobalMetrics(id) => Self::export_global_metrics(id),
@@ -80,13 +98,20 @@ impl ApiServer {
             }
             ApiRequest::ExportFsAccessPatterns(id) => Self::export_access_patterns(id),
             ApiRequest::ExportFsBackendInfo(mountpoint) => self.backend_info(&mountpoint),
+            ApiRequest::ExportFsBlobAccess(mountpoint) => self.blob_access(&mountpoint),
+            ApiRequest::ExportFsProvenance(mountpoint) => self.provenance(&mountpoint),
+            ApiRequest::ListFilesystems => self.list_filesystems(),
             ApiRequest::ExportFsInflightMetrics => self.export_inflight_metrics(),
+            ApiRequest::ListBlobs(domain_id) => self.list_blobs(domain_id),
 
             // Nydus API v2
             ApiRequest::GetDaemonInfoV2 => self.daemon_info(false),
             ApiRequest::GetBlobObject(_param) => todo!(),
             ApiRequest::CreateBlobObject(entry) => self.create_blob_cache_entry(&entry),
             ApiRequest::DeleteBlobObject(param) => self.remove_blob_cache_entry(&param),
+            ApiRequest::CreatePrefetchJob(conf) => Self::create_prefetch_job(conf),
+            ApiRequest::GetPrefetchJobs(id) => Self::prefetch_jobs(id),
+            ApiRequest::CancelPrefetchJob(id) => Self::cancel_prefetch_job(id),
         };
 
         self.respond(resp);
@@ -101,7 +126,8 @@ impl ApiServer {
     }
 
     fn configure_daemon(&self, conf: DaemonConf) -> ApiResponse {
-        conf.log_level
+        let resp = conf
+            .log_level
             .parse::<log::LevelFilter>()
             .map_err(|e| {
                 error!("Invalid log level passed, {}", e);
@@ -110,7 +136,14 @@ impl ApiServer {
             .map(|v| {
                 log::set_max_level(v);
                 ApiResponsePayload::Empty
-            })
+            });
+        audit::record(
+            EventCategory::ConfigChange,
+            None,
+            serde_json::json!({ "log_level": conf.log_level }),
+            &resp,
+        );
+        resp
     }
 
     fn daemon_info(&self, include_fs_info: bool) -> ApiResponse {
@@ -120,6 +153,51 @@ impl ApiServer {
             .map(ApiResponsePayload::DaemonInfo)
     }
 
+    /// Export service information, which is available even before the daemon gets started.
+    fn service_info(&self) -> ApiResponse {
+        let fs_service = DAEMON_CONTROLLER
+            .try_get_daemon()
+            .and_then(|d| d.get_default_fs_service())
+            .or_else(|| DAEMON_CONTROLLER.get_fs_service());
+        let mounted_filesystems = fs_service
+            .as_ref()
+            .map(|s| s.backend_collection().len())
+            .unwrap_or_default();
+        let builder_versions = fs_service.map(|s| s.builder_versions()).unwrap_or_default();
+        let blob_objects = DAEMON_CONTROLLER
+            .get_blob_cache_mgr()
+            .map(|mgr| mgr.get_blob_objects_num())
+            .unwrap_or_default();
+
+        let mut features = Vec::new();
+        if cfg!(feature = "virtiofs") {
+            features.push("virtiofs".to_string());
+        } else {
+            features.push("fusedev".to_string());
+        }
+        if cfg!(target_os = "linux") {
+            features.push("fscache".to_string());
+        }
+        features.push("prefetch".to_string());
+        for algo in ["lz4_block", "gzip", "zstd", "snappy"] {
+            features.push(format!("compressor-{}", algo));
+        }
+
+        let info = ServiceInfo {
+            version: BuildTimeInfo::dump().1,
+            target: built_info::TARGET.to_string(),
+            features,
+            uptime_secs: DAEMON_CONTROLLER.uptime().as_secs(),
+            blob_objects,
+            mounted_filesystems,
+            builder_versions,
+        };
+
+        serde_json::to_string(&info)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(DaemonErrorKind::Serde(e))))
+            .map(ApiResponsePayload::ServiceInfo)
+    }
+
     /// External supervisor wants this instance to exit. But it can't just die leave
     /// some pending or in-flight fuse messages un-handled. So this method guarantees
     /// all fuse messages read from kernel are handled and replies are sent back.
@@ -159,6 +237,40 @@ impl ApiServer {
         Ok(ApiResponsePayload::Events(events))
     }
 
+    fn trace_config() -> ApiResponse {
+        let (enable, threshold_ms) = trace::config();
+        let conf = TraceConfig {
+            enable,
+            threshold_ms,
+        };
+        serde_json::to_string(&conf)
+            .map(ApiResponsePayload::TraceConfig)
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Serde(e)))
+    }
+
+    fn configure_trace(conf: TraceConfig) -> ApiResponse {
+        info!(
+            "{} request tracing, latency threshold {}ms",
+            if conf.enable { "enable" } else { "disable" },
+            conf.threshold_ms
+        );
+        trace::configure(conf.enable, conf.threshold_ms);
+        audit::record::<_, ()>(
+            EventCategory::ConfigChange,
+            None,
+            serde_json::json!({ "trace": conf }),
+            &Ok(()),
+        );
+        Ok(ApiResponsePayload::Empty)
+    }
+
+    fn audit_events(since: Option<u64>) -> ApiResponse {
+        let page = audit::query(since.unwrap_or_default());
+        serde_json::to_string(&page)
+            .map(ApiResponsePayload::AuditEvents)
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Serde(e)))
+    }
+
     fn export_global_metrics(id: Option<String>) -> ApiResponse {
         metrics::export_global_stats(&id)
             .map(ApiResponsePayload::FsGlobalMetrics)
@@ -190,6 +302,37 @@ impl ApiServer {
             .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
     }
 
+    fn export_decompress_metrics() -> ApiResponse {
+        metrics::export_decompress_metrics()
+            .map(ApiResponsePayload::DecompressMetrics)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
+    fn export_blob_download_metrics(id: Option<String>) -> ApiResponse {
+        let mgr = DAEMON_CONTROLLER
+            .get_blob_cache_mgr()
+            .ok_or(ApiError::DaemonAbnormal(DaemonErrorKind::Unsupported))?;
+        let stats = match id {
+            Some(id) => {
+                let stats = mgr.get_blob_stats(&id).ok_or_else(|| {
+                    ApiError::Metrics(MetricsErrorKind::Stats(MetricsError::NoCounter))
+                })?;
+                serde_json::to_string(&stats)
+            }
+            None => {
+                let stats = mgr
+                    .get_all_blob_stats()
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>();
+                serde_json::to_string(&stats)
+            }
+        };
+
+        stats
+            .map(ApiResponsePayload::BlobDownloadMetrics)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(DaemonErrorKind::Serde(e))))
+    }
+
     #[inline]
     fn get_daemon_object(&self) -> std::result::Result<Arc<dyn NydusDaemon>, ApiError> {
         Ok(DAEMON_CONTROLLER.get_daemon())
@@ -203,6 +346,29 @@ impl ApiServer {
         Ok(ApiResponsePayload::FsBackendInfo(info))
     }
 
+    fn blob_access(&self, mountpoint: &str) -> ApiResponse {
+        let info = self
+            .get_default_fs_service()?
+            .export_blob_access(mountpoint)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(e.into())))?;
+        Ok(ApiResponsePayload::FsBlobAccess(info))
+    }
+
+    fn provenance(&self, mountpoint: &str) -> ApiResponse {
+        let info = self
+            .get_default_fs_service()?
+            .export_provenance(mountpoint)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(e.into())))?;
+        Ok(ApiResponsePayload::FsProvenance(info))
+    }
+
+    fn list_filesystems(&self) -> ApiResponse {
+        let filesystems = self.get_default_fs_service()?.get_mounted_filesystems();
+        serde_json::to_string(&filesystems)
+            .map(ApiResponsePayload::FsList)
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Serde(e)))
+    }
+
     /// Detect if there is fop being hang.
     /// `ApiResponsePayload::Empty` will be converted to http status code 204, which means
     /// there is no requests being processed right now.
@@ -243,6 +409,13 @@ impl ApiServer {
     }
 
     fn do_mount(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
+        let detail = mount_event_detail(&cmd);
+        let resp = self.mount(mountpoint.clone(), cmd);
+        audit::record(EventCategory::Mount, Some(&mountpoint), detail, &resp);
+        resp
+    }
+
+    fn mount(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
         let fs_type = FsBackendType::from_str(&cmd.fs_type)
             .map_err(|e| ApiError::MountFilesystem(DaemonError::from(e).into()))?;
         let fs = self.get_default_fs_service()?;
@@ -251,6 +424,7 @@ impl ApiServer {
             mountpoint,
             config: cmd.config,
             source: cmd.source,
+            bootstrap_blob_id: cmd.bootstrap_blob_id,
             prefetch_files: cmd.prefetch_files,
         })
         .map(|_| ApiResponsePayload::Empty)
@@ -258,6 +432,13 @@ impl ApiServer {
     }
 
     fn do_remount(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
+        let detail = mount_event_detail(&cmd);
+        let resp = self.remount(mountpoint.clone(), cmd);
+        audit::record(EventCategory::Remount, Some(&mountpoint), detail, &resp);
+        resp
+    }
+
+    fn remount(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
         let fs_type = FsBackendType::from_str(&cmd.fs_type)
             .map_err(|e| ApiError::MountFilesystem(DaemonError::from(e).into()))?;
         self.get_default_fs_service()?
@@ -266,6 +447,7 @@ impl ApiServer {
                 mountpoint,
                 config: cmd.config,
                 source: cmd.source,
+                bootstrap_blob_id: cmd.bootstrap_blob_id,
                 prefetch_files: cmd.prefetch_files,
             })
             .map(|_| ApiResponsePayload::Empty)
@@ -273,10 +455,46 @@ impl ApiServer {
     }
 
     fn do_umount(&self, mountpoint: String) -> ApiResponse {
+        let resp = self
+            .get_default_fs_service()
+            .and_then(|fs| {
+                fs.umount(FsBackendUmountCmd {
+                    mountpoint: mountpoint.clone(),
+                })
+                .map_err(|e| ApiError::MountFilesystem(e.into()))
+            })
+            .map(|_| ApiResponsePayload::Empty);
+        audit::record(
+            EventCategory::Umount,
+            Some(&mountpoint),
+            serde_json::Value::Null,
+            &resp,
+        );
+        resp
+    }
+
+    fn do_update_mount_config(&self, mountpoint: String, conf: MountRuntimeConfig) -> ApiResponse {
+        let resp = self
+            .get_default_fs_service()
+            .and_then(|fs| {
+                fs.update_mount_config(&mountpoint, &conf)
+                    .map_err(|e| ApiError::MountFilesystem(e.into()))
+            })
+            .map(|_| ApiResponsePayload::Empty);
+        audit::record(
+            EventCategory::ConfigChange,
+            Some(&mountpoint),
+            serde_json::json!({ "mount_config": conf }),
+            &resp,
+        );
+        resp
+    }
+
+    fn advise_file(&self, mountpoint: &str, cmd: &FileAdviceCmd) -> ApiResponse {
         self.get_default_fs_service()?
-            .umount(FsBackendUmountCmd { mountpoint })
-            .map(|_| ApiResponsePayload::Empty)
-            .map_err(|e| ApiError::MountFilesystem(e.into()))
+            .advise_file(mountpoint, cmd)
+            .map_err(|e| ApiError::MountFilesystem(e.into()))?;
+        Ok(ApiResponsePayload::Empty)
     }
 
     fn send_fuse_fd(&self) -> ApiResponse {
@@ -310,6 +528,16 @@ impl ApiServer {
         }
     }
 
+    fn list_blobs(&self, domain_id: Option<String>) -> ApiResponse {
+        let mgr = DAEMON_CONTROLLER
+            .get_blob_cache_mgr()
+            .ok_or(ApiError::DaemonAbnormal(DaemonErrorKind::Unsupported))?;
+        let blobs = mgr.list_blobs(domain_id.as_deref());
+        serde_json::to_string(&blobs)
+            .map(ApiResponsePayload::BlobObjectList)
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Serde(e)))
+    }
+
     fn remove_blob_cache_entry(&self, param: &BlobCacheObjectId) -> ApiResponse {
         match DAEMON_CONTROLLER.get_blob_cache_mgr() {
             None => Err(ApiError::DaemonAbnormal(DaemonErrorKind::Unsupported)),
@@ -326,6 +554,67 @@ impl ApiServer {
         }
     }
 
+    fn create_prefetch_job(conf: PrefetchJobConfig) -> ApiResponse {
+        let blob_ids = conf
+            .blobs
+            .iter()
+            .map(|b| b.blob_id.as_str())
+            .collect::<Vec<_>>();
+        let mut detail = serde_json::json!({ "blobs": blob_ids });
+        let result = conf
+            .blobs
+            .iter()
+            .map(PrefetchJobBlob::from_config)
+            .collect::<Result<Vec<_>>>()
+            .and_then(|blobs| {
+                let config = Arc::new(prefetch_job_factory_config(&conf.config));
+                PREFETCH_JOB_MGR.start(config, blobs)
+            });
+        if let Ok(id) = result.as_ref() {
+            detail["job_id"] = serde_json::json!(id);
+        }
+        audit::record(EventCategory::Prefetch, None, detail, &result);
+
+        let id = result.map_err(|e| {
+            ApiError::DaemonAbnormal(DaemonErrorKind::Other(format!(
+                "failed to start prefetch job, {}",
+                e
+            )))
+        })?;
+        Ok(ApiResponsePayload::PrefetchJobs(
+            serde_json::json!({ "id": id }).to_string(),
+        ))
+    }
+
+    fn prefetch_jobs(id: Option<u64>) -> ApiResponse {
+        let status = match id {
+            Some(id) => {
+                let status = PREFETCH_JOB_MGR.get_job_status(id).ok_or_else(|| {
+                    ApiError::DaemonAbnormal(DaemonErrorKind::Other(format!(
+                        "prefetch job {} not found",
+                        id
+                    )))
+                })?;
+                prefetch_job_status_to_json(&status)
+            }
+            None => serde_json::Value::Array(
+                PREFETCH_JOB_MGR
+                    .get_all_job_status()
+                    .iter()
+                    .map(prefetch_job_status_to_json)
+                    .collect(),
+            ),
+        };
+        Ok(ApiResponsePayload::PrefetchJobs(status.to_string()))
+    }
+
+    fn cancel_prefetch_job(id: u64) -> ApiResponse {
+        PREFETCH_JOB_MGR
+            .cancel(id)
+            .map(|_| ApiResponsePayload::Empty)
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Other(e.to_string())))
+    }
+
     fn do_start(&self) -> ApiResponse {
         let d = self.get_daemon_object()?;
         d.trigger_start()
@@ -334,6 +623,26 @@ impl ApiServer {
     }
 }
 
+fn prefetch_job_status_to_json(status: &PrefetchJobStatus) -> serde_json::Value {
+    serde_json::json!({
+        "id": status.id,
+        "state": status.state.to_string(),
+        "total_bytes": status.total_bytes,
+        "fetched_bytes": status.fetched_bytes,
+        "error": status.error,
+    })
+}
+
+// Filesystem configuration is excluded because it may contain credentials.
+fn mount_event_detail(cmd: &ApiMountCmd) -> serde_json::Value {
+    serde_json::json!({
+        "fs_type": cmd.fs_type,
+        "source": cmd.source,
+        "bootstrap_blob_id": cmd.bootstrap_blob_id,
+        "prefetch_files": cmd.prefetch_files.as_ref().map(|v| v.len()),
+    })
+}
+
 struct ApiServerHandler {
     server: ApiServer,
     api_receiver: Receiver<Option<ApiRequest>>,
@@ -402,6 +711,7 @@ impl ApiServerController {
         let api_handler = ApiServerHandler::new(api_server, from_router)?;
         let (router_thread, waker) = start_http_thread(apisock, None, to_handler, from_handler)?;
         let daemon_waker = DAEMON_CONTROLLER.waker.clone();
+        let worker = DAEMON_CONTROLLER.register_worker();
 
         info!("HTTP API server running at {}", apisock);
         let handler_thread = std::thread::Builder::new()
@@ -410,6 +720,7 @@ impl ApiServerController {
                 api_handler.handle_requests_from_router();
                 info!("HTTP api-server handler thread exits");
                 let _ = daemon_waker.wake();
+                drop(worker);
                 Ok(())
             })
             .map_err(|_e| einval!("Failed to start work thread for HTTP handler"))?;
@@ -448,3 +759,162 @@ impl ApiServerController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    use fuse_backend_rs::api::{Vfs, VfsOptions};
+    use vmm_sys_util::tempdir::TempDir;
+
+    use super::*;
+    use crate::daemon::DaemonResult;
+    use crate::fs_service::FsBackendCollection;
+    use crate::upgrade::UpgradeManager;
+
+    struct TestFsService {
+        vfs: Vfs,
+        backend_collection: Mutex<FsBackendCollection>,
+    }
+
+    impl FsService for TestFsService {
+        fn get_vfs(&self) -> &Vfs {
+            &self.vfs
+        }
+
+        fn upgrade_mgr(&self) -> Option<MutexGuard<UpgradeManager>> {
+            None
+        }
+
+        fn backend_collection(&self) -> MutexGuard<FsBackendCollection> {
+            self.backend_collection.lock().unwrap()
+        }
+
+        fn export_inflight_ops(&self) -> DaemonResult<Option<String>> {
+            Err(DaemonError::Unsupported)
+        }
+    }
+
+    /// Send a HTTP request to the API server and return the status code and body of the response.
+    fn http_request(sock: &str, method: &str, uri: &str, body: &str) -> (u32, String) {
+        let mut stream = UnixStream::connect(sock).unwrap();
+        let req = format!(
+            "{} {} HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            uri,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).unwrap();
+
+        let mut resp = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let sz = stream.read(&mut buf).unwrap();
+            assert_ne!(sz, 0, "connection closed before end of response");
+            resp.extend_from_slice(&buf[..sz]);
+            let text = String::from_utf8_lossy(&resp).to_string();
+            if let Some(pos) = text.find("\r\n\r\n") {
+                let (head, body) = (&text[..pos], &text[pos + 4..]);
+                let len = head
+                    .lines()
+                    .find_map(|l| {
+                        let (k, v) = l.split_once(':')?;
+                        k.eq_ignore_ascii_case("content-length")
+                            .then(|| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= len {
+                    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+                    return (status, body.to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_advise_file_api() {
+        let service = Arc::new(TestFsService {
+            vfs: Vfs::new(VfsOptions::default()),
+            backend_collection: Default::default(),
+        });
+        DAEMON_CONTROLLER.set_fs_service(service);
+
+        let tmpdir = TempDir::new().unwrap();
+        let sock = tmpdir.as_path().join("api.sock");
+        let sock = sock.to_str().unwrap();
+        let mut api_controller = ApiServerController::new(Some(sock));
+        api_controller.start().unwrap();
+
+        let root_dir = std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let texture = PathBuf::from(root_dir).join("tests/texture");
+        let bootstrap = texture.join("bootstrap/rafs-v6.boot");
+        let config = serde_json::json!({
+            "device": {
+                "backend": {
+                    "type": "localfs",
+                    "config": { "dir": texture.join("blobs") }
+                },
+                "cache": {
+                    "type": "blobcache",
+                    "config": { "work_dir": tmpdir.as_path() }
+                }
+            },
+            "mode": "direct"
+        });
+        let mount = serde_json::json!({
+            "source": bootstrap,
+            "fs_type": "rafs",
+            "config": config.to_string(),
+        });
+        let mountpoint = "/advise";
+        let (status, _) = http_request(
+            sock,
+            "POST",
+            "/api/v1/mount?mountpoint=/advise",
+            &mount.to_string(),
+        );
+        assert_eq!(status, 204);
+
+        let advised = || {
+            let (status, body) = http_request(
+                sock,
+                "GET",
+                &format!("/api/v1/metrics?id={}", mountpoint),
+                "",
+            );
+            assert_eq!(status, 200);
+            let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+            (
+                stats["advised_prefetch_bytes"].as_u64().unwrap(),
+                stats["throttled_advices"].as_u64().unwrap(),
+            )
+        };
+        assert_eq!(advised(), (0, 0));
+
+        // Size 0 advises up to the end of the 5000 bytes file.
+        let advice = r#"{"path": "/file-a", "offset": 1000, "size": 0, "advice": "willneed"}"#;
+        let uri = format!("/api/v1/mount/advise?mountpoint={}", mountpoint);
+        let (status, body) = http_request(sock, "PUT", &uri, advice);
+        assert_eq!(status, 204, "{}", body);
+        assert_eq!(advised(), (4000, 0));
+
+        let advice = r#"{"path": "/dir/file-c", "advice": "dontneed"}"#;
+        let (status, _) = http_request(sock, "PUT", &uri, advice);
+        assert_eq!(status, 204);
+        assert_eq!(advised(), (4000, 0));
+
+        let advice = r#"{"path": "/non-existent", "advice": "willneed"}"#;
+        let (status, _) = http_request(sock, "PUT", &uri, advice);
+        assert_ne!(status, 204);
+        let advice = r#"{"path": "/dir", "advice": "willneed"}"#;
+        let (status, _) = http_request(sock, "PUT", &uri, advice);
+        assert_ne!(status, 204);
+        assert_eq!(advised(), (4000, 0));
+
+        api_controller.stop();
+    }
+}
@@ -8,12 +8,14 @@ use std::io::{Error, ErrorKind, Result};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use nydus_api::http::{BackendConfig, CacheConfig, FactoryConfig};
 use nydus_api::http::{
     BlobCacheEntry, BlobCacheList, BlobCacheObjectId, FsCacheConfig, BLOB_CACHE_TYPE_BOOTSTRAP,
 };
 use rafs::metadata::{RafsMode, RafsSuper};
+use serde::Serialize;
 use storage::device::BlobInfo;
 
 const ID_SPLITTER: &str = "/";
@@ -29,6 +31,7 @@ pub fn generate_blob_key(domain_id: &str, blob_id: &str) -> String {
 
 /// Configuration information for cached bootstrap blob objects.
 pub struct BlobCacheConfigBootstrap {
+    domain_id: String,
     blob_id: String,
     scoped_blob_id: String,
     path: PathBuf,
@@ -49,6 +52,7 @@ impl BlobCacheConfigBootstrap {
 
 /// Configuration information for cached data blob objects.
 pub struct BlobCacheConfigDataBlob {
+    domain_id: String,
     blob_info: Arc<BlobInfo>,
     scoped_blob_id: String,
     factory_config: Arc<FactoryConfig>,
@@ -85,6 +89,7 @@ impl BlobCacheObjectConfig {
         let scoped_blob_id = generate_blob_key(&domain_id, blob_info.blob_id());
 
         BlobCacheObjectConfig::DataBlob(Arc::new(BlobCacheConfigDataBlob {
+            domain_id,
             blob_info,
             scoped_blob_id,
             factory_config,
@@ -101,6 +106,7 @@ impl BlobCacheObjectConfig {
         let scoped_blob_id = generate_blob_key(&domain_id, &blob_id);
 
         BlobCacheObjectConfig::Bootstrap(Arc::new(BlobCacheConfigBootstrap {
+            domain_id,
             blob_id,
             scoped_blob_id,
             path,
@@ -109,6 +115,21 @@ impl BlobCacheObjectConfig {
         }))
     }
 
+    fn get_blob_id(&self) -> BlobId {
+        match self {
+            BlobCacheObjectConfig::Bootstrap(o) => BlobId {
+                domain: o.domain_id.clone(),
+                blob_id: o.blob_id.clone(),
+                ref_count: 1,
+            },
+            BlobCacheObjectConfig::DataBlob(o) => BlobId {
+                domain: o.domain_id.clone(),
+                blob_id: o.blob_info.blob_id().to_string(),
+                ref_count: o.ref_count.load(Ordering::Acquire) as usize,
+            },
+        }
+    }
+
     fn get_key(&self) -> &str {
         match self {
             BlobCacheObjectConfig::Bootstrap(o) => &o.scoped_blob_id,
@@ -124,6 +145,17 @@ impl BlobCacheObjectConfig {
     }
 }
 
+/// Identifier and reference count of a blob managed by [BlobCacheMgr].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlobId {
+    /// Domain the blob is associated with.
+    pub domain: String,
+    /// Id of the bootstrap or data blob.
+    pub blob_id: String,
+    /// Number of bootstrap blobs referencing the data blob, always 1 for bootstrap blobs.
+    pub ref_count: usize,
+}
+
 #[derive(Default)]
 struct BlobCacheState {
     id_to_config_map: HashMap<String, BlobCacheObjectConfig>,
@@ -206,6 +238,21 @@ impl BlobCacheState {
         self.id_to_config_map.get(key).cloned()
     }
 
+    fn len(&self) -> usize {
+        self.id_to_config_map.len()
+    }
+
+    fn list_blobs(&self, domain_id: Option<&str>) -> Vec<BlobId> {
+        let mut blobs = self
+            .id_to_config_map
+            .values()
+            .map(|v| v.get_blob_id())
+            .filter(|b| domain_id.map(|d| b.domain == d).unwrap_or(true))
+            .collect::<Vec<_>>();
+        blobs.sort_by(|a, b| (&a.domain, &a.blob_id).cmp(&(&b.domain, &b.blob_id)));
+        blobs
+    }
+
     /// get DataBlob number for a domain_id
     fn get_blobs_num(&self, domain_id: &str) -> usize {
         let scoped_blob_prefix = format!("{}{}", domain_id, ID_SPLITTER);
@@ -223,10 +270,24 @@ impl BlobCacheState {
     }
 }
 
+/// Statistics information about data downloaded for a blob.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BlobDownloadStats {
+    /// Total bytes of data downloaded.
+    pub total_bytes: u64,
+    /// Total time spent on downloading data, in milliseconds.
+    pub total_duration_ms: u64,
+    /// Number of download requests.
+    pub request_count: u64,
+}
+
 /// Manager for cached file objects.
 #[derive(Default)]
 pub struct BlobCacheMgr {
     state: Mutex<BlobCacheState>,
+    download_stats: Mutex<HashMap<String, BlobDownloadStats>>,
+    // Raw blob cache entries added, used to save and restore state on live upgrade.
+    entries: Mutex<HashMap<String, serde_json::Value>>,
 }
 
 impl BlobCacheMgr {
@@ -234,6 +295,8 @@ impl BlobCacheMgr {
     pub fn new() -> Self {
         BlobCacheMgr {
             state: Mutex::new(BlobCacheState::new()),
+            download_stats: Mutex::new(HashMap::new()),
+            entries: Mutex::new(HashMap::new()),
         }
     }
 
@@ -256,7 +319,13 @@ impl BlobCacheMgr {
                         entry
                     );
                     e
-                })
+                })?;
+            let value = serde_json::to_value(entry).map_err(|e| eother!(e))?;
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(generate_blob_key(&entry.domain_id, &entry.blob_id), value);
+            Ok(())
         } else {
             warn!("blob_cache: invalid blob cache entry: {:?}", entry);
             Err(einval!("blob_cache: invalid blob cache entry"))
@@ -274,7 +343,35 @@ impl BlobCacheMgr {
 
     /// Remove a blob object from the cache manager.
     pub fn remove_blob_entry(&self, param: &BlobCacheObjectId) -> Result<()> {
-        self.get_state().remove(param)
+        self.get_state().remove(param)?;
+
+        let mut entries = self.entries.lock().unwrap();
+        if param.blob_id.is_empty() && !param.domain_id.is_empty() {
+            let scoped_blob_prefix = format!("{}{}", param.domain_id, ID_SPLITTER);
+            entries.retain(|k, _v| !k.starts_with(&scoped_blob_prefix));
+        } else {
+            entries.remove(&generate_blob_key(&param.domain_id, &param.blob_id));
+        }
+
+        Ok(())
+    }
+
+    /// Export all blob cache entries added to the cache manager, in format of `BlobCacheList`.
+    ///
+    /// The exported entries may be imported by [BlobCacheMgr::import_entries()] to restore state
+    /// of the cache manager after live upgrade or failover.
+    pub fn export_entries(&self) -> Result<Vec<u8>> {
+        let entries = self.entries.lock().unwrap();
+        let mut keys = entries.keys().collect::<Vec<_>>();
+        keys.sort();
+        let blobs = keys.iter().map(|k| entries[*k].clone()).collect::<Vec<_>>();
+        serde_json::to_vec(&serde_json::json!({ "blobs": blobs })).map_err(|e| eother!(e))
+    }
+
+    /// Import blob cache entries exported by [BlobCacheMgr::export_entries()].
+    pub fn import_entries(&self, data: &[u8]) -> Result<()> {
+        let list: BlobCacheList = serde_json::from_slice(data).map_err(|e| einval!(e))?;
+        self.add_blob_list(&list)
     }
 
     /// Get configuration information for the blob with `key`.
@@ -286,6 +383,44 @@ impl BlobCacheMgr {
         self.get_state().get_blobs_num(domain_id)
     }
 
+    /// List bootstrap and data blobs associated with domain `domain_id`, or blobs of all domains
+    /// if `domain_id` is `None`, sorted by domain and blob id.
+    pub fn list_blobs(&self, domain_id: Option<&str>) -> Vec<BlobId> {
+        self.get_state().list_blobs(domain_id)
+    }
+
+    /// Get number of bootstrap and data blob objects managed by the cache manager.
+    pub fn get_blob_objects_num(&self) -> usize {
+        self.get_state().len()
+    }
+
+    /// Account `bytes` of data downloaded for blob `blob_id` in `duration`.
+    pub fn record_download(&self, blob_id: &str, bytes: u64, duration: Duration) {
+        let mut stats = self.download_stats.lock().unwrap();
+        let entry = stats.entry(blob_id.to_string()).or_default();
+        entry.total_bytes += bytes;
+        entry.total_duration_ms += duration.as_millis() as u64;
+        entry.request_count += 1;
+    }
+
+    /// Get download statistics information for blob `blob_id`.
+    pub fn get_blob_stats(&self, blob_id: &str) -> Option<BlobDownloadStats> {
+        self.download_stats.lock().unwrap().get(blob_id).copied()
+    }
+
+    /// Get download statistics information for all blobs, sorted by blob id.
+    pub fn get_all_blob_stats(&self) -> Vec<(String, BlobDownloadStats)> {
+        let mut stats = self
+            .download_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
     #[inline]
     fn get_state(&self) -> MutexGuard<BlobCacheState> {
         self.state.lock().unwrap()
@@ -347,6 +482,7 @@ impl BlobCacheMgr {
             backend: BackendConfig {
                 backend_type: entry.blob_config.backend_type.clone(),
                 backend_config: entry.blob_config.backend_config.clone(),
+                ..Default::default()
             },
             cache: CacheConfig {
                 cache_type: entry.blob_config.cache_type.clone(),
@@ -354,6 +490,7 @@ impl BlobCacheMgr {
                 cache_config: entry.blob_config.cache_config.clone(),
                 cache_validate: false,
                 prefetch_config,
+                gzip_backend: String::new(),
             },
         });
 
@@ -381,7 +518,7 @@ impl BlobCacheMgr {
         let bs_obj = bootstrap.bootstrap_config().unwrap();
 
         // Try to add the referenced data blob object if it doesn't exist yet.
-        for bi in rs.superblock.get_blob_infos() {
+        for bi in rs.superblock.get_blob_infos(true)? {
             debug!(
                 "blob_cache: add data blob {} to domain {}",
                 &bi.blob_id(),
@@ -471,6 +608,7 @@ mod tests {
         assert_eq!(&factory_config.cache.cache_type, "fscache");
 
         let blob = BlobCacheConfigBootstrap {
+            domain_id: "domain1".to_string(),
             blob_id: "123456789-123".to_string(),
             scoped_blob_id: "domain1".to_string(),
             path: path.clone(),
@@ -480,6 +618,30 @@ mod tests {
         assert_eq!(blob.path(), &path);
     }
 
+    #[test]
+    fn test_blob_download_stats() {
+        let mgr = BlobCacheMgr::new();
+        assert!(mgr.get_blob_stats("blob1").is_none());
+        assert!(mgr.get_all_blob_stats().is_empty());
+
+        mgr.record_download("blob2", 0x1000, Duration::from_millis(5));
+        mgr.record_download("blob1", 0x2000, Duration::from_millis(10));
+        mgr.record_download("blob1", 0x3000, Duration::from_millis(20));
+        assert_eq!(
+            mgr.get_blob_stats("blob1").unwrap(),
+            BlobDownloadStats {
+                total_bytes: 0x5000,
+                total_duration_ms: 30,
+                request_count: 2,
+            }
+        );
+        let stats = mgr.get_all_blob_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].0, "blob1");
+        assert_eq!(stats[1].0, "blob2");
+        assert_eq!(stats[1].1.request_count, 1);
+    }
+
     #[test]
     fn test_invalid_blob_id() {
         let tmpdir = TempDir::new().unwrap();
@@ -596,6 +758,31 @@ mod tests {
         assert!(mgr.get_config(&blob_id).is_some());
         assert!(mgr.get_config(&blob_id_cloned).is_some());
 
+        let blobs = mgr.list_blobs(Some(entry.domain_id.as_str()));
+        assert_eq!(blobs.len(), 20);
+        assert_eq!(blobs, mgr.list_blobs(None));
+        assert!(mgr.list_blobs(Some("userid2")).is_empty());
+        let blob = blobs
+            .iter()
+            .find(|b| b.blob_id == "rafs-v5-cloned")
+            .unwrap();
+        assert_eq!(blob.domain, entry.domain_id);
+        assert_eq!(blob.ref_count, 1);
+        let blob = blobs
+            .iter()
+            .find(|b| {
+                b.blob_id == "7fe907a0c9c7f35538f23f40baae5f2e8d148a3a6186f0f443f62d04b5e2d731"
+            })
+            .unwrap();
+        assert_eq!(blob.ref_count, 2);
+
+        // Restore state of the cache manager from exported entries.
+        let data = mgr.export_entries().unwrap();
+        let mgr2 = BlobCacheMgr::new();
+        mgr2.import_entries(&data).unwrap();
+        assert_eq!(mgr2.list_blobs(None), mgr.list_blobs(None));
+        mgr2.import_entries(b"invalid").unwrap_err();
+
         mgr.remove_blob_entry(&BlobCacheObjectId {
             domain_id: entry.domain_id.clone(),
             blob_id: "rafs-v5".to_string(),
@@ -604,6 +791,7 @@ mod tests {
         assert_eq!(mgr.get_state().id_to_config_map.len(), 19);
         assert!(mgr.get_config(&blob_id).is_none());
         assert!(mgr.get_config(&blob_id_cloned).is_some());
+        assert_eq!(mgr.entries.lock().unwrap().len(), 1);
 
         mgr.remove_blob_entry(&BlobCacheObjectId {
             domain_id: entry.domain_id,
@@ -0,0 +1,282 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Unified configuration for nydusd.
+//!
+//! Historically nydusd accepts several configuration file formats: the RAFS filesystem
+//! configuration for `fuse` and `virtiofs` mode and the blob cache list for `singleton` mode.
+//! [ConfigV2] unifies them into a versioned format with strict validation, unknown fields are
+//! reported as errors instead of being silently ignored. Legacy formats are still accepted and
+//! migrated to the new format, with warnings about fields moved.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use nydus_api::http::{BlobCacheEntry, BlobCacheList};
+use rafs::fs::RafsConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Version number of the unified configuration format.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Unified configuration for nydusd, version 2.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigV2 {
+    /// Version of the configuration format, must be [CONFIG_VERSION].
+    pub version: u32,
+    /// Configuration information for RAFS filesystems mounted by `fuse` and `virtiofs` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rafs: Option<RafsConfig>,
+    /// Blob cache objects to create on startup by `singleton` mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blobs: Vec<BlobCacheEntry>,
+}
+
+impl ConfigV2 {
+    /// Load configuration from file `path`, migrating legacy formats if needed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("failed to read configuration file {:?}, {}", path, e),
+            )
+        })?;
+        Self::from_content(&content)
+            .map_err(|e| Error::new(e.kind(), format!("invalid configuration {:?}, {}", path, e)))
+    }
+
+    /// Parse configuration from a JSON string, migrating legacy formats if needed.
+    pub fn from_content(content: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(content).map_err(invalid_data)?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| invalid_data("configuration should be a JSON object"))?;
+
+        if object.contains_key("version") {
+            let config: ConfigV2 = serde_json::from_str(content).map_err(invalid_data)?;
+            if config.version != CONFIG_VERSION {
+                return Err(invalid_data(format!(
+                    "unsupported configuration version {}, expected {}",
+                    config.version, CONFIG_VERSION
+                )));
+            }
+            let unknown = unknown_fields(&value, &config)?;
+            if !unknown.is_empty() {
+                return Err(invalid_data(format!(
+                    "unknown fields: {}",
+                    unknown.join(", ")
+                )));
+            }
+            Ok(config)
+        } else if object.contains_key("device") {
+            let rafs: RafsConfig = serde_json::from_value(value.clone()).map_err(invalid_data)?;
+            let moved = object
+                .keys()
+                .map(|k| format!("{} -> rafs.{}", k, k))
+                .collect::<Vec<_>>();
+            warn!(
+                "legacy RAFS configuration format is deprecated, please migrate to version {}, fields moved: {}",
+                CONFIG_VERSION,
+                moved.join(", ")
+            );
+            let config = ConfigV2 {
+                version: CONFIG_VERSION,
+                rafs: Some(rafs),
+                blobs: Vec::new(),
+            };
+            warn_unknown_fields(&value, &config)?;
+            Ok(config)
+        } else if object.contains_key("blobs") {
+            let list: BlobCacheList =
+                serde_json::from_value(value.clone()).map_err(invalid_data)?;
+            warn!(
+                "legacy blob cache configuration format is deprecated, please migrate to version {} by adding field \"version\"",
+                CONFIG_VERSION
+            );
+            let config = ConfigV2 {
+                version: CONFIG_VERSION,
+                rafs: None,
+                blobs: list.blobs,
+            };
+            warn_unknown_fields(&value, &config)?;
+            Ok(config)
+        } else {
+            Err(invalid_data(
+                "unknown configuration format, field \"version\" is missing",
+            ))
+        }
+    }
+
+    /// Get the RAFS filesystem configuration.
+    pub fn rafs_config(&self) -> Result<&RafsConfig> {
+        self.rafs
+            .as_ref()
+            .ok_or_else(|| invalid_data("field \"rafs\" is missing"))
+    }
+}
+
+fn invalid_data<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+// Find fields in `input` which are ignored when deserializing into `config`.
+//
+// All known fields are serialized, so fields missing in the serialized configuration are unknown
+// fields, which may be typos.
+fn unknown_fields(input: &Value, config: &ConfigV2) -> Result<Vec<String>> {
+    let known = serde_json::to_value(config).map_err(invalid_data)?;
+    let mut fields = Vec::new();
+    collect_unknown_fields(input, &known, "", &mut fields);
+    Ok(fields)
+}
+
+// Legacy formats are migrated to the top level of `ConfigV2` before comparing.
+fn warn_unknown_fields(input: &Value, config: &ConfigV2) -> Result<()> {
+    let known = match config.rafs.as_ref() {
+        Some(rafs) => serde_json::to_value(rafs).map_err(invalid_data)?,
+        None => serde_json::to_value(config).map_err(invalid_data)?,
+    };
+    let mut fields = Vec::new();
+    collect_unknown_fields(input, &known, "", &mut fields);
+    if !fields.is_empty() {
+        warn!(
+            "unknown configuration fields ignored: {}",
+            fields.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn collect_unknown_fields(input: &Value, known: &Value, prefix: &str, fields: &mut Vec<String>) {
+    match (input, known) {
+        (Value::Object(input), Value::Object(known)) => {
+            for (key, value) in input.iter() {
+                let path = if prefix.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                match known.get(key) {
+                    Some(v) => collect_unknown_fields(value, v, &path, fields),
+                    None => fields.push(path),
+                }
+            }
+        }
+        (Value::Array(input), Value::Array(known)) => {
+            for (idx, (value, v)) in input.iter().zip(known.iter()).enumerate() {
+                let path = format!("{}[{}]", prefix, idx);
+                collect_unknown_fields(value, v, &path, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rafs::metadata::RafsMode;
+
+    const LEGACY_RAFS_CONFIG: &str = r#"
+    {
+        "device": {
+            "backend": {
+                "type": "localfs",
+                "config": {
+                    "dir": "/tmp/blobs"
+                }
+            },
+            "cache": {
+                "type": "blobcache",
+                "config": {
+                    "work_dir": "/tmp/cache"
+                }
+            }
+        },
+        "mode": "direct",
+        "digest_validate": true
+    }"#;
+
+    const BLOB_ENTRIES: &str = r#"
+    [
+        {
+            "type": "bootstrap",
+            "id": "rafs-v6",
+            "config": {
+                "id": "factory1",
+                "backend_type": "localfs",
+                "backend_config": {
+                    "dir": "/tmp/blobs"
+                },
+                "cache_type": "fscache",
+                "cache_config": {
+                    "work_dir": "/tmp/cache"
+                },
+                "metadata_path": "/tmp/bootstrap"
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn test_migrate_legacy_config() {
+        let config = ConfigV2::from_content(LEGACY_RAFS_CONFIG).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.blobs.is_empty());
+        let rafs = config.rafs_config().unwrap();
+        assert_eq!(rafs.mode, RafsMode::Direct);
+        assert!(rafs.digest_validate);
+        assert_eq!(rafs.device.backend.backend_type, "localfs");
+
+        let content = format!(r#"{{"blobs": {}}}"#, BLOB_ENTRIES);
+        let config = ConfigV2::from_content(&content).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.rafs.is_none());
+        assert!(config.rafs_config().is_err());
+        assert_eq!(config.blobs.len(), 1);
+        assert_eq!(config.blobs[0].blob_id, "rafs-v6");
+
+        // Unknown fields in legacy formats are ignored for compatibility.
+        let content = LEGACY_RAFS_CONFIG.replace("\"digest_validate\"", "\"digest_validdate\"");
+        let config = ConfigV2::from_content(&content).unwrap();
+        assert!(!config.rafs_config().unwrap().digest_validate);
+
+        assert!(ConfigV2::from_content("{}").is_err());
+        assert!(ConfigV2::from_content("[]").is_err());
+    }
+
+    #[test]
+    fn test_load_config_v2() {
+        let content = format!(
+            r#"{{"version": 2, "rafs": {}, "blobs": {}}}"#,
+            LEGACY_RAFS_CONFIG, BLOB_ENTRIES
+        );
+        let config = ConfigV2::from_content(&content).unwrap();
+        assert!(config.rafs.is_some());
+        assert_eq!(config.blobs.len(), 1);
+
+        // Normalized configuration should be accepted again.
+        let normalized = serde_json::to_string_pretty(&config).unwrap();
+        let config = ConfigV2::from_content(&normalized).unwrap();
+        assert!(config.rafs_config().unwrap().digest_validate);
+
+        let err = ConfigV2::from_content(r#"{"version": 3}"#).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = ConfigV2::from_content("{\"version\": 2,\n\"mode\": \"direct\"}")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("unknown field `mode`"));
+        assert!(err.to_string().contains("line 2"));
+
+        let content = format!(
+            r#"{{"version": 2, "rafs": {}}}"#,
+            LEGACY_RAFS_CONFIG.replace("\"digest_validate\"", "\"digest_validdate\"")
+        );
+        let err = ConfigV2::from_content(&content).err().unwrap();
+        assert!(err.to_string().contains("rafs.digest_validdate"));
+    }
+}
@@ -6,14 +6,16 @@
 
 use std::any::Any;
 use std::cmp::PartialEq;
+use std::collections::HashMap;
 use std::convert::From;
 use std::fmt::{Display, Formatter};
 use std::io::Result;
 use std::ops::Deref;
 use std::process::id;
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use std::{error, fmt, io};
 
 use fuse_backend_rs::api::vfs::VfsError;
@@ -166,6 +168,75 @@ pub struct DaemonInfo {
     pub backend_collection: Option<FsBackendCollection>,
 }
 
+/// Used to export service version, features and statistics information.
+#[derive(Serialize)]
+pub struct ServiceInfo {
+    pub version: BuildTimeInfo,
+    pub target: String,
+    pub features: Vec<String>,
+    pub uptime_secs: u64,
+    pub blob_objects: usize,
+    pub mounted_filesystems: usize,
+    pub builder_versions: HashMap<String, Option<String>>,
+}
+
+/// Track working threads of the daemon, so the daemon can wait until all of them have exited.
+#[derive(Default)]
+pub struct WorkerTracker {
+    count: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl WorkerTracker {
+    /// Register a working thread, which is unregistered when the returned guard is dropped.
+    ///
+    /// Should be called before spawning the working thread, so that waiters won't miss it.
+    pub fn register(self: &Arc<Self>) -> WorkerGuard {
+        *self.count.lock().unwrap() += 1;
+        WorkerGuard(self.clone())
+    }
+
+    /// Get number of active working threads.
+    pub fn active_workers(&self) -> usize {
+        *self.count.lock().unwrap()
+    }
+
+    /// Wait until all working threads have exited, return false on timeout.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let guard = self.count.lock().unwrap();
+        match timeout {
+            None => {
+                let _guard = self.cond.wait_while(guard, |count| *count > 0).unwrap();
+                true
+            }
+            Some(dur) => {
+                let (_guard, result) = self
+                    .cond
+                    .wait_timeout_while(guard, dur, |count| *count > 0)
+                    .unwrap();
+                !result.timed_out()
+            }
+        }
+    }
+
+    fn unregister(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.cond.notify_all();
+        }
+    }
+}
+
+/// Guard object for a registered working thread.
+pub struct WorkerGuard(Arc<WorkerTracker>);
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        self.0.unregister();
+    }
+}
+
 pub trait NydusDaemon: DaemonStateMachineSubscriber + Send + Sync {
     fn as_any(&self) -> &dyn Any;
     fn id(&self) -> Option<String>;
@@ -431,4 +502,24 @@ mod tests {
 
         assert!("xxxxxxxxxxxxx".parse::<FsBackendType>().is_err());
     }
+
+    #[test]
+    fn test_worker_tracker() {
+        let tracker = Arc::new(WorkerTracker::default());
+        assert!(tracker.wait(None));
+
+        let guard = tracker.register();
+        let guard2 = tracker.register();
+        assert_eq!(tracker.active_workers(), 2);
+        assert!(!tracker.wait(Some(Duration::from_millis(10))));
+
+        drop(guard);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard2);
+        });
+        assert!(tracker.wait(Some(Duration::from_secs(10))));
+        assert_eq!(tracker.active_workers(), 0);
+        handle.join().unwrap();
+    }
 }
@@ -16,7 +16,8 @@ use std::ptr::read_unaligned;
 use std::string::String;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier, Mutex, MutexGuard, RwLock};
-use std::{thread, time};
+use std::thread::JoinHandle;
+use std::{mem, thread, time};
 
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token, Waker};
@@ -237,18 +238,27 @@ struct FsCacheState {
     blob_cache_mgr: Arc<BlobCacheMgr>,
 }
 
+/// Event poller of a fscache working thread.
+struct FsCacheWorker {
+    poller: Mutex<Poll>,
+    waker: Waker,
+}
+
 /// Handler to cooperate with Linux fscache driver to manage cached blob objects.
 ///
 /// The `FsCacheHandler` create a communication channel with the Linux fscache driver, configure
 /// the communication session and serves all requests from the fscache driver.
+///
+/// Requests are served by multiple working threads. Opening `/dev/cachefiles` again creates
+/// another cache session, so all working threads share the same fd, and each of them polls the
+/// fd with its own poller.
 pub struct FsCacheHandler {
     active: AtomicBool,
     barrier: Barrier,
-    threads: usize,
     file: File,
     state: Arc<Mutex<FsCacheState>>,
-    poller: Mutex<Poll>,
-    waker: Arc<Waker>,
+    workers: Vec<FsCacheWorker>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl FsCacheHandler {
@@ -276,18 +286,25 @@ impl FsCacheHandler {
                 e
             })?;
 
-        let poller =
-            Poll::new().map_err(|_e| eother!("fscache: failed to create poller for service"))?;
-        let waker = Waker::new(poller.registry(), Token(TOKEN_EVENT_WAKER))
-            .map_err(|_e| eother!("fscache: failed to create waker for service"))?;
-        poller
-            .registry()
-            .register(
-                &mut SourceFd(&file.as_raw_fd()),
-                Token(TOKEN_EVENT_FSCACHE),
-                Interest::READABLE,
-            )
-            .map_err(|_e| eother!("fscache: failed to register fd for service"))?;
+        let mut workers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let poller = Poll::new()
+                .map_err(|_e| eother!("fscache: failed to create poller for service"))?;
+            let waker = Waker::new(poller.registry(), Token(TOKEN_EVENT_WAKER))
+                .map_err(|_e| eother!("fscache: failed to create waker for service"))?;
+            poller
+                .registry()
+                .register(
+                    &mut SourceFd(&file.as_raw_fd()),
+                    Token(TOKEN_EVENT_FSCACHE),
+                    Interest::READABLE,
+                )
+                .map_err(|_e| eother!("fscache: failed to register fd for service"))?;
+            workers.push(FsCacheWorker {
+                poller: Mutex::new(poller),
+                waker,
+            });
+        }
 
         // Initialize the fscache session
         file.write_all(format!("dir {}", dir).as_bytes())?;
@@ -308,38 +325,76 @@ impl FsCacheHandler {
         Ok(FsCacheHandler {
             active: AtomicBool::new(true),
             barrier: Barrier::new(threads + 1),
-            threads,
             file,
             state: Arc::new(Mutex::new(state)),
-            poller: Mutex::new(poller),
-            waker: Arc::new(waker),
+            workers,
+            handles: Mutex::new(Vec::new()),
         })
     }
 
     /// Get number of working threads to service fscache requests.
     pub fn working_threads(&self) -> usize {
-        self.threads
+        self.workers.len()
     }
 
-    /// Stop worker threads for the fscache service.
+    /// Spawn working threads to serve requests from the fscache driver.
+    ///
+    /// `on_start` is called before spawning each working thread, and the returned closure is
+    /// invoked by the working thread when it exits. Return after all working threads are ready.
+    pub fn start<F, G>(self: &Arc<Self>, on_start: F) -> Result<()>
+    where
+        F: Fn() -> G,
+        G: FnOnce() + Send + 'static,
+    {
+        let mut handles = self.handles.lock().unwrap();
+        for idx in 0..self.workers.len() {
+            let handler = self.clone();
+            let on_exit = on_start();
+            let handle = thread::Builder::new()
+                .name(format!("fscache_worker_{}", idx))
+                .spawn(move || {
+                    handler.barrier.wait();
+                    if let Err(e) = handler.run_loop(idx) {
+                        error!(
+                            "fscache: failed to run service loop of worker {}, {}",
+                            idx, e
+                        );
+                    }
+                    on_exit();
+                })?;
+            handles.push(handle);
+        }
+        drop(handles);
+        self.barrier.wait();
+
+        Ok(())
+    }
+
+    /// Stop worker threads for the fscache service and wait for them to exit.
     pub fn stop(&self) {
         self.active.store(false, Ordering::Release);
-        if let Err(e) = self.waker.wake() {
-            error!("fscache: failed to signal worker thread to exit, {}", e);
+        for worker in self.workers.iter() {
+            if let Err(e) = worker.waker.wake() {
+                error!("fscache: failed to signal worker thread to exit, {}", e);
+            }
+        }
+
+        let handles = mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            if handle.join().is_err() {
+                error!("fscache: failed to join worker thread");
+            }
         }
-        self.barrier.wait();
     }
 
-    /// Run the event loop to handle all requests from kernel fscache driver.
-    ///
-    /// This method should only be invoked by a single thread, which will poll the fscache fd
-    /// and dispatch requests from fscache fd to other working threads.
-    pub fn run_loop(&self) -> Result<()> {
+    /// Run the event loop of working thread `idx` to handle requests from kernel fscache driver.
+    fn run_loop(&self, idx: usize) -> Result<()> {
         let mut events = Events::with_capacity(64);
         let mut buf = vec![0u8; MIN_DATA_BUF_SIZE];
+        let mut poller = self.workers[idx].poller.lock().unwrap();
 
         loop {
-            match self.poller.lock().unwrap().poll(&mut events, None) {
+            match poller.poll(&mut events, None) {
                 Ok(_) => {}
                 Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(e) => {
@@ -361,9 +416,6 @@ impl FsCacheHandler {
                     && event.token() == Token(TOKEN_EVENT_WAKER)
                     && !self.active.load(Ordering::Acquire)
                 {
-                    // Notify next worker to exit.
-                    let _ = self.waker.wake();
-                    self.barrier.wait();
                     return Ok(());
                 }
             }
@@ -474,7 +526,9 @@ impl FsCacheHandler {
     }
 
     fn init_blobcache(fsblob: Arc<RwLock<FsCacheBlobCache>>, barrier: Arc<Barrier>) {
+        let worker = crate::DAEMON_CONTROLLER.register_worker();
         thread::spawn(move || {
+            let _worker = worker;
             let mut guard = fsblob.write().unwrap();
             barrier.wait();
             //for now FsCacheBlobCache only init once, should not have blobcache associated with it
@@ -691,20 +745,27 @@ impl FsCacheHandler {
                 fd = u;
                 let guard = fsblob.read().unwrap();
                 match guard.get_blobcache() {
-                    Some(blob) => {
-                        match blob.get_blob_object() {
-                            None => {
-                                warn!("fscache: internal error: cached object is not BlobCache objects");
-                            }
-                            Some(obj) => match obj.fetch_range_uncompressed(msg.off, msg.len) {
-                                Ok(_) => {}
+                    Some(blob) => match blob.get_blob_object() {
+                        None => {
+                            warn!(
+                                "fscache: internal error: cached object is not BlobCache objects"
+                            );
+                        }
+                        Some(obj) => {
+                            let start = time::Instant::now();
+                            match obj.fetch_range_uncompressed(msg.off, msg.len) {
+                                Ok(_) => self.get_state().blob_cache_mgr.record_download(
+                                    blob.blob_id(),
+                                    msg.len,
+                                    start.elapsed(),
+                                ),
                                 Err(e) => error!(
                                     "{}",
                                     format!("fscache: failed to read data from blob object: {}", e,)
                                 ),
-                            },
+                            }
                         }
-                    }
+                    },
                     _ => {
                         warn!("fscache: blob object not ready")
                         //TODO: maybe we should retry init blob object here
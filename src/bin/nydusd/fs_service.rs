@@ -6,16 +6,19 @@
 
 use std::collections::HashMap;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, MutexGuard};
+use std::time::Duration;
 
 use fuse_backend_rs::api::{BackFileSystem, Vfs};
 #[cfg(target_os = "linux")]
 use fuse_backend_rs::passthrough::{Config, PassthroughFs};
 use nydus::{FsBackendDesc, FsBackendType};
+use nydus_api::http::{FileAdviceCmd, MountRuntimeConfig};
 use rafs::fs::{Rafs, RafsConfig};
-use rafs::{trim_backend_config, RafsError, RafsIoRead};
+use rafs::metadata::{RafsMode, RafsVersion};
+use rafs::{trim_backend_config, RafsError, RafsIoRead, RafsIoReader};
 use serde::{self, Deserialize, Serialize};
 use storage::factory::BLOB_FACTORY;
 
@@ -24,10 +27,11 @@ use crate::upgrade::{self, UpgradeManager};
 use crate::DaemonError;
 
 /// Command to mount a filesystem.
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct FsBackendMountCmd {
     pub fs_type: FsBackendType,
     pub source: String,
+    pub bootstrap_blob_id: Option<String>,
     pub config: String,
     pub mountpoint: String,
     pub prefetch_files: Option<Vec<String>>,
@@ -39,12 +43,29 @@ pub struct FsBackendUmountCmd {
     pub mountpoint: String,
 }
 
+/// Information about a mounted RAFS filesystem.
+#[derive(Clone, Debug, Serialize)]
+pub struct MountedFsInfo {
+    pub mount_id: String,
+    pub path: PathBuf,
+    pub version: RafsVersion,
+    pub mode: RafsMode,
+    pub blob_count: usize,
+    pub uptime: Duration,
+}
+
 /// List of filesystem backend information.
 #[derive(Default, Serialize, Clone)]
 pub struct FsBackendCollection(HashMap<String, FsBackendDesc>);
 
 impl FsBackendCollection {
-    pub fn add(&mut self, id: &str, cmd: &FsBackendMountCmd) -> DaemonResult<()> {
+    /// Add or update a filesystem backend, `vfs_index` of an existing backend is kept if `None`.
+    pub fn add(
+        &mut self,
+        id: &str,
+        cmd: &FsBackendMountCmd,
+        vfs_index: Option<u8>,
+    ) -> DaemonResult<()> {
         // We only wash Rafs backend now.
         let fs_config = match cmd.fs_type {
             FsBackendType::Rafs => {
@@ -57,6 +78,10 @@ impl FsBackendCollection {
                     "auth",
                     "token"
                 );
+                // Never export master keys to decrypt encrypted blobs.
+                if let serde_json::Value::Object(ref mut m) = config {
+                    m.remove("encryption_keys");
+                }
                 Some(config)
             }
             FsBackendType::PassthroughFs => {
@@ -70,6 +95,7 @@ impl FsBackendCollection {
             mountpoint: cmd.mountpoint.clone(),
             mounted_time: time::OffsetDateTime::now_utc(),
             config: fs_config,
+            vfs_index: vfs_index.or_else(|| self.vfs_index(id)),
         };
 
         self.0.insert(id.to_string(), desc);
@@ -80,6 +106,25 @@ impl FsBackendCollection {
     pub fn del(&mut self, id: &str) {
         self.0.remove(id);
     }
+
+    /// Get number of mounted filesystem backends.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get index of a filesystem backend in the virtual filesystem.
+    pub fn vfs_index(&self, id: &str) -> Option<u8> {
+        self.0.get(id).and_then(|desc| desc.vfs_index)
+    }
+
+    /// Get mountpoints of all mounted filesystem backends.
+    pub fn mountpoints(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
 }
 
 /// Define services provided by a filesystem provider.
@@ -97,7 +142,8 @@ pub trait FsService: Send + Sync {
         let backend = fs_backend_factory(&cmd)?;
         let index = self.get_vfs().mount(backend, &cmd.mountpoint)?;
         info!("{} filesystem mounted at {}", &cmd.fs_type, &cmd.mountpoint);
-        self.backend_collection().add(&cmd.mountpoint, &cmd)?;
+        self.backend_collection()
+            .add(&cmd.mountpoint, &cmd, Some(index))?;
 
         // Add mounts opaque to UpgradeManager
         if let Some(mut mgr_guard) = self.upgrade_mgr() {
@@ -107,12 +153,40 @@ pub trait FsService: Send + Sync {
         Ok(())
     }
 
+    /// Mount a filesystem saved by the previous daemon at the same vfs index on live upgrade.
+    ///
+    /// The vfs allocates indexes in increasing order, so mounts must be restored by
+    /// [upgrade::restore_mounts] to get their saved vfs indexes.
+    fn restore_mount(&self, cmd: FsBackendMountCmd, vfs_index: u8) -> DaemonResult<()> {
+        let backend = fs_backend_factory(&cmd)?;
+        let index = self.get_vfs().mount(backend, &cmd.mountpoint)?;
+        if index != vfs_index {
+            self.get_vfs().umount(&cmd.mountpoint)?;
+            return Err(DaemonError::Common(format!(
+                "filesystem at {} restored at vfs index {}, expect {}",
+                &cmd.mountpoint, index, vfs_index
+            )));
+        }
+        info!(
+            "{} filesystem restored at {}, vfs index {}",
+            &cmd.fs_type, &cmd.mountpoint, vfs_index
+        );
+        self.backend_collection()
+            .add(&cmd.mountpoint, &cmd, Some(vfs_index))?;
+
+        if let Some(mut mgr_guard) = self.upgrade_mgr() {
+            upgrade::add_mounts_state(&mut mgr_guard, cmd, vfs_index)?;
+        }
+
+        Ok(())
+    }
+
     fn remount(&self, cmd: FsBackendMountCmd) -> DaemonResult<()> {
         let rootfs = self
             .backend_from_mountpoint(&cmd.mountpoint)?
             .ok_or(DaemonError::NotFound)?;
         let rafs_config = RafsConfig::from_str(&cmd.config)?;
-        let mut bootstrap = <dyn RafsIoRead>::from_file(&&cmd.source)?;
+        let mut bootstrap = open_rafs_bootstrap(&cmd, &rafs_config)?;
         let any_fs = rootfs.deref().as_any();
         let rafs = any_fs
             .downcast_ref::<Rafs>()
@@ -125,7 +199,8 @@ pub trait FsService: Send + Sync {
             })?;
 
         // To update mounted time and backend configurations.
-        self.backend_collection().add(&cmd.mountpoint, &cmd)?;
+        self.backend_collection().add(&cmd.mountpoint, &cmd, None)?;
+        self.invalidate_kernel_cache(&cmd.mountpoint, rafs);
 
         // Update mounts opaque from UpgradeManager
         if let Some(mut mgr_guard) = self.upgrade_mgr() {
@@ -153,6 +228,60 @@ pub trait FsService: Send + Sync {
         Ok(())
     }
 
+    /// Notify the kernel to invalidate cached directory entries and inodes changed by updating a
+    /// RAFS filesystem.
+    ///
+    /// It's a no-op for services without kernel notification support.
+    fn invalidate_kernel_cache(&self, _mountpoint: &str, rafs: &Rafs) {
+        rafs.take_invalidations();
+    }
+
+    /// Update runtime configuration of a mounted RAFS filesystem.
+    ///
+    /// The change is not persisted, so it's lost after restarting or upgrading the daemon.
+    fn update_mount_config(&self, mountpoint: &str, conf: &MountRuntimeConfig) -> DaemonResult<()> {
+        let rootfs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let rafs = rootfs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+
+        if let Some(enable) = conf.digest_validate {
+            rafs.set_digest_validate(enable)
+                .map_err(DaemonError::Rafs)?;
+            info!(
+                "{} digest validation for filesystem mounted at {}",
+                if enable { "enable" } else { "disable" },
+                mountpoint
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Apply access advice on a file of the RAFS filesystem mounted at `mountpoint`.
+    fn advise_file(&self, mountpoint: &str, cmd: &FileAdviceCmd) -> DaemonResult<()> {
+        let rootfs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let rafs = rootfs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        let ino = rafs
+            .ino_from_path(Path::new(&cmd.path))
+            .map_err(|e| DaemonError::InvalidArguments(format!("{}: {}", cmd.path, e)))?;
+        // Size 0 means up to the end of the file, which `Rafs::advise()` clamps to.
+        let size = if cmd.size == 0 { u64::MAX } else { cmd.size };
+
+        rafs.advise(ino, cmd.offset, size, cmd.advice)
+            .map_err(|e| DaemonError::Common(e.to_string()))
+    }
+
     fn backend_from_mountpoint(&self, mp: &str) -> DaemonResult<Option<Arc<BackFileSystem>>> {
         self.get_vfs().get_rootfs(mp).map_err(|e| e.into())
     }
@@ -168,6 +297,92 @@ pub trait FsService: Send + Sync {
         let resp = serde_json::to_string(rafs.metadata()).map_err(DaemonError::Serde)?;
         Ok(resp)
     }
+
+    /// Get access information about data blobs of the RAFS filesystem mounted at `mountpoint`.
+    fn export_blob_access(&self, mountpoint: &str) -> DaemonResult<String> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let rafs = fs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        let blobs = rafs
+            .get_blob_access_info()
+            .into_iter()
+            .map(|info| {
+                serde_json::json!({
+                    "blob_id": info.blob_id,
+                    "ready": info.ready,
+                    "first_access": info.first_access,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string(&blobs).map_err(DaemonError::Serde)
+    }
+
+    /// Get build provenance of the RAFS filesystem mounted at `mountpoint`.
+    ///
+    /// `null` is returned for filesystems generated by old builders without provenance records.
+    fn export_provenance(&self, mountpoint: &str) -> DaemonResult<String> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let rafs = fs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        serde_json::to_string(&rafs.metadata().provenance).map_err(DaemonError::Serde)
+    }
+
+    /// Get versions of the builder generating mounted RAFS filesystems, indexed by mountpoint.
+    fn builder_versions(&self) -> HashMap<String, Option<String>> {
+        let mut versions = HashMap::new();
+        let mountpoints = self.backend_collection().mountpoints();
+        for mp in mountpoints {
+            if let Ok(Some(fs)) = self.backend_from_mountpoint(&mp) {
+                if let Some(rafs) = fs.deref().as_any().downcast_ref::<Rafs>() {
+                    versions.insert(mp, rafs.metadata().builder_version.clone());
+                }
+            }
+        }
+        versions
+    }
+
+    /// Get information about all mounted RAFS filesystems, sorted by mount id.
+    ///
+    /// Filesystems are registered into the backend collection on mount and deregistered on
+    /// unmount, other types of filesystem backends are skipped.
+    fn get_mounted_filesystems(&self) -> Vec<MountedFsInfo> {
+        let now = time::OffsetDateTime::now_utc();
+        let descs = self.backend_collection().0.clone();
+        let mut filesystems = Vec::new();
+        for (id, desc) in descs {
+            if let Ok(Some(fs)) = self.backend_from_mountpoint(&desc.mountpoint) {
+                if let Some(rafs) = fs.deref().as_any().downcast_ref::<Rafs>() {
+                    let version = if rafs.metadata().is_v6() {
+                        RafsVersion::V6
+                    } else {
+                        RafsVersion::V5
+                    };
+                    let uptime = (now - desc.mounted_time).whole_seconds().max(0) as u64;
+                    filesystems.push(MountedFsInfo {
+                        mount_id: id,
+                        path: PathBuf::from(&desc.mountpoint),
+                        version,
+                        mode: rafs.mode(),
+                        blob_count: rafs.blob_count(),
+                        uptime: Duration::from_secs(uptime),
+                    });
+                }
+            }
+        }
+        filesystems.sort_by(|a, b| a.mount_id.cmp(&b.mount_id));
+        filesystems
+    }
+
     fn export_inflight_ops(&self) -> DaemonResult<Option<String>>;
 }
 
@@ -191,13 +406,31 @@ fn validate_prefetch_file_list(input: &Option<Vec<String>>) -> DaemonResult<Opti
     }
 }
 
+/// Open the RAFS bootstrap from a local file or from the storage backend by blob id.
+fn open_rafs_bootstrap(
+    cmd: &FsBackendMountCmd,
+    rafs_config: &RafsConfig,
+) -> DaemonResult<RafsIoReader> {
+    match cmd.bootstrap_blob_id.as_ref() {
+        Some(blob_id) if !blob_id.is_empty() => Ok(<dyn RafsIoRead>::from_backend(
+            rafs_config.device.backend.clone(),
+            blob_id,
+            rafs_config.mode.clone(),
+        )?),
+        _ if cmd.source.is_empty() => Err(DaemonError::InvalidArguments(
+            "either bootstrap source or bootstrap_blob_id is required".to_string(),
+        )),
+        _ => Ok(<dyn RafsIoRead>::from_file(&cmd.source)?),
+    }
+}
+
 fn fs_backend_factory(cmd: &FsBackendMountCmd) -> DaemonResult<BackFileSystem> {
     let prefetch_files = validate_prefetch_file_list(&cmd.prefetch_files)?;
 
     match cmd.fs_type {
         FsBackendType::Rafs => {
             let rafs_config = RafsConfig::from_str(cmd.config.as_str())?;
-            let mut bootstrap = <dyn RafsIoRead>::from_file(&cmd.source)?;
+            let mut bootstrap = open_rafs_bootstrap(cmd, &rafs_config)?;
             let mut rafs = Rafs::new(rafs_config, &cmd.mountpoint, &mut bootstrap)?;
             rafs.import(bootstrap, prefetch_files)?;
             info!("RAFS filesystem imported");
@@ -249,12 +482,15 @@ mod tests {
                 config: "{\"config\": \"test\"}".to_string(),
                 mountpoint: "testmonutount".to_string(),
                 source: "testsource".to_string(),
+                bootstrap_blob_id: None,
                 prefetch_files: Some(vec!["testfile".to_string()]),
             },
+            Some(1),
         );
         assert!(r.is_ok(), "failed to add backend collection");
 
         assert_eq!(col.0.len(), 1);
+        assert_eq!(col.vfs_index("test"), Some(1));
 
         col.del("test");
         assert_eq!(col.0.len(), 0);
@@ -305,6 +541,7 @@ mod tests {
             config: config.to_string(),
             mountpoint: "testmountpoint".to_string(),
             source: bootstrap.to_string(),
+            bootstrap_blob_id: None,
             prefetch_files: Some(vec!["/testfile".to_string()]),
         })
         .unwrap()
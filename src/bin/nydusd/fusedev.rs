@@ -4,13 +4,12 @@
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
 use std::any::Any;
-use std::ffi::{CStr, CString};
-use std::fs::metadata;
-use std::io::{Error, ErrorKind, Result};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::{metadata, File};
+use std::io::{Error, ErrorKind, Result, Write};
 use std::ops::Deref;
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
-#[cfg(target_os = "linux")]
 use std::os::unix::ffi::OsStrExt;
 #[cfg(target_os = "macos")]
 use std::os::unix::fs::MetadataExt;
@@ -24,14 +23,15 @@ use std::sync::{
 use std::thread::{self, JoinHandle};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use fuse_backend_rs::abi::fuse_abi::{InHeader, OutHeader};
+use fuse_backend_rs::abi::fuse_abi::{InHeader, OutHeader, ROOT_ID};
 use fuse_backend_rs::api::server::{MetricsHook, Server};
-use fuse_backend_rs::api::Vfs;
+use fuse_backend_rs::api::{Vfs, VFS_MAX_INO};
 use fuse_backend_rs::transport::{FuseChannel, FuseSession};
 use mio::Waker;
 #[cfg(target_os = "linux")]
 use nix::sys::stat::{major, minor};
 use nydus_app::BuildTimeInfo;
+use rafs::fs::{Rafs, RafsInvalidation};
 use serde::Serialize;
 
 use crate::daemon::{
@@ -143,6 +143,60 @@ impl FuseServer {
     }
 }
 
+/// Notification code to invalidate cached attributes and data of an inode.
+const FUSE_NOTIFY_INVAL_INODE: i32 = 2;
+/// Notification code to invalidate a cached directory entry.
+const FUSE_NOTIFY_INVAL_ENTRY: i32 = 3;
+/// Bits of inode number reserved by `Vfs`, the higher bits are for the filesystem index.
+const VFS_INDEX_SHIFT: u32 = 64 - VFS_MAX_INO.leading_zeros();
+
+fn encode_notify(code: i32, body: &[u8]) -> Vec<u8> {
+    let len = std::mem::size_of::<OutHeader>() + body.len();
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(&(len as u32).to_ne_bytes());
+    // Notifications are identified by a zero `unique` and the notify code in `error`.
+    buf.extend_from_slice(&code.to_ne_bytes());
+    buf.extend_from_slice(&0u64.to_ne_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn encode_notify_inval_inode(ino: u64) -> Vec<u8> {
+    let mut body = Vec::with_capacity(24);
+    body.extend_from_slice(&ino.to_ne_bytes());
+    // Offset 0 and length 0 invalidate the whole page cache of the inode.
+    body.extend_from_slice(&0i64.to_ne_bytes());
+    body.extend_from_slice(&0i64.to_ne_bytes());
+    encode_notify(FUSE_NOTIFY_INVAL_INODE, &body)
+}
+
+fn encode_notify_inval_entry(parent: u64, name: &OsStr) -> Vec<u8> {
+    let name = name.as_bytes();
+    let mut body = Vec::with_capacity(16 + name.len() + 1);
+    body.extend_from_slice(&parent.to_ne_bytes());
+    body.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+    body.extend_from_slice(&0u32.to_ne_bytes());
+    body.extend_from_slice(name);
+    body.push(0);
+    encode_notify(FUSE_NOTIFY_INVAL_ENTRY, &body)
+}
+
+/// Write a notification message to the fuse device, the whole message must be written at once.
+///
+/// Return `Ok(false)` if the kernel has no cache for the object to invalidate.
+fn send_notify(mut file: &File, msg: &[u8]) -> Result<bool> {
+    match file.write(msg) {
+        Ok(n) if n == msg.len() => Ok(true),
+        Ok(n) => Err(eio!(format!(
+            "short write of fuse notification, {} of {} bytes",
+            n,
+            msg.len()
+        ))),
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
 pub struct FusedevFsService {
     /// Fuse connection ID which usually equals to `st_dev`
     pub conn: AtomicU64,
@@ -219,6 +273,61 @@ impl FsService for FusedevFsService {
         self.backend_collection.lock().unwrap()
     }
 
+    fn invalidate_kernel_cache(&self, mountpoint: &str, rafs: &Rafs) {
+        let invalidations = rafs.take_invalidations();
+        if invalidations.is_empty() {
+            return;
+        }
+        let vfs_index = match self.backend_collection().vfs_index(mountpoint) {
+            Some(idx) => idx as u64,
+            None => return,
+        };
+        // Translate RAFS inode numbers into inode numbers known by the kernel. The RAFS root is
+        // represented by the pseudo inode of the mountpoint, which is only known for "/".
+        let root_ino = rafs.root_ino();
+        let kernel_ino = |ino: u64| -> Option<u64> {
+            if ino == root_ino {
+                if mountpoint == "/" {
+                    Some(ROOT_ID)
+                } else {
+                    None
+                }
+            } else {
+                Some((vfs_index << VFS_INDEX_SHIFT) | ino)
+            }
+        };
+
+        let mut session = self.session.lock().unwrap();
+        let file = match session.get_fuse_file() {
+            Some(f) => f,
+            None => return,
+        };
+        let (mut entries, mut inodes) = (0, 0);
+        for inval in invalidations.iter() {
+            let result = match inval {
+                RafsInvalidation::Entry { parent, name } => match kernel_ino(*parent) {
+                    Some(p) => send_notify(file, &encode_notify_inval_entry(p, name))
+                        .map(|sent| entries += sent as usize),
+                    None => Ok(()),
+                },
+                RafsInvalidation::Inode(ino) => match kernel_ino(*ino) {
+                    Some(i) => send_notify(file, &encode_notify_inval_inode(i))
+                        .map(|sent| inodes += sent as usize),
+                    None => Ok(()),
+                },
+            };
+            // Kernels without notification support reject with ENOSYS, no need to go on.
+            if let Err(e) = result {
+                warn!(
+                    "failed to invalidate kernel cache for {}, {}",
+                    mountpoint, e
+                );
+                break;
+            }
+        }
+        rafs.record_invalidations(entries, inodes);
+    }
+
     fn export_inflight_ops(&self) -> DaemonResult<Option<String>> {
         let ops = self.inflight_ops.lock().unwrap();
 
@@ -242,7 +351,7 @@ pub struct FusedevDaemon {
     id: Option<String>,
     request_sender: Arc<Mutex<Sender<DaemonStateMachineInput>>>,
     result_receiver: Mutex<Receiver<DaemonResult<()>>>,
-    service: Arc<FusedevFsService>,
+    pub(crate) service: Arc<FusedevFsService>,
     state: AtomicI32,
     supervisor: Option<String>,
     threads_cnt: u32,
@@ -573,3 +682,38 @@ pub fn create_fuse_daemon(
 
     Ok(daemon)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_encode_notify() {
+        assert_eq!(VFS_INDEX_SHIFT, 56);
+
+        let msg = encode_notify_inval_inode(0x1234);
+        assert_eq!(msg.len(), 40);
+        assert_eq!(&msg[0..4], &40u32.to_ne_bytes());
+        assert_eq!(&msg[4..8], &FUSE_NOTIFY_INVAL_INODE.to_ne_bytes());
+        assert_eq!(&msg[8..16], &0u64.to_ne_bytes());
+        assert_eq!(&msg[16..24], &0x1234u64.to_ne_bytes());
+
+        let msg = encode_notify_inval_entry(1, OsStr::new("foo"));
+        assert_eq!(msg.len(), 16 + 16 + 4);
+        assert_eq!(&msg[0..4], &36u32.to_ne_bytes());
+        assert_eq!(&msg[4..8], &FUSE_NOTIFY_INVAL_ENTRY.to_ne_bytes());
+        assert_eq!(&msg[16..24], &1u64.to_ne_bytes());
+        assert_eq!(&msg[24..28], &3u32.to_ne_bytes());
+        assert_eq!(&msg[32..], b"foo\0");
+
+        let tmp = TempFile::new().unwrap();
+        let mut file = tmp.as_file();
+        assert!(send_notify(file, &msg).unwrap());
+        let mut buf = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, msg);
+    }
+}
@@ -20,8 +20,11 @@ extern crate nydus_storage as storage;
 
 use std::convert::TryInto;
 use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use clap::parser::ValuesRef;
 use clap::{Arg, ArgAction, ArgMatches, Command};
@@ -30,21 +33,26 @@ use nix::sys::signal;
 use rlimit::Resource;
 
 use nydus_app::{dump_program_info, setup_logging, BuildTimeInfo};
+use nydus_utils::audit::{self, AuditFileConfig};
+use rafs::fs::RafsConfig;
 
 use crate::api_server_glue::ApiServerController;
 use crate::blob_cache::BlobCacheMgr;
-use crate::daemon::{DaemonError, NydusDaemon};
+use crate::config::ConfigV2;
+use crate::daemon::{DaemonError, NydusDaemon, WorkerGuard, WorkerTracker};
 use crate::fs_service::{FsBackendMountCmd, FsService};
 use crate::service_controller::create_daemon;
 
 use nydus::ensure_threads;
 
 mod fusedev;
+mod nfs;
 #[cfg(feature = "virtiofs")]
 mod virtiofs;
 
 mod api_server_glue;
 mod blob_cache;
+mod config;
 mod daemon;
 #[cfg(target_os = "linux")]
 mod fs_cache;
@@ -71,6 +79,8 @@ pub struct DaemonController {
     fs_service: Mutex<Option<Arc<dyn FsService>>>,
     waker: Arc<Waker>,
     poller: Mutex<Poll>,
+    start_time: SystemTime,
+    workers: Arc<WorkerTracker>,
 }
 
 impl DaemonController {
@@ -87,6 +97,8 @@ impl DaemonController {
             fs_service: Mutex::new(None),
             waker: Arc::new(waker),
             poller: Mutex::new(poller),
+            start_time: SystemTime::now(),
+            workers: Arc::new(WorkerTracker::default()),
         }
     }
 
@@ -117,6 +129,16 @@ impl DaemonController {
         self.daemon.lock().unwrap().clone().unwrap()
     }
 
+    /// Get the daemon service object if it has been set.
+    pub fn try_get_daemon(&self) -> Option<Arc<dyn NydusDaemon>> {
+        self.daemon.lock().unwrap().clone()
+    }
+
+    /// Get elapsed time since the service controller has been created.
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed().unwrap_or_default()
+    }
+
     /// Get the optional blob cache manager.
     pub fn get_blob_cache_mgr(&self) -> Option<Arc<BlobCacheMgr>> {
         self.blob_cache_mgr.lock().unwrap().clone()
@@ -137,6 +159,16 @@ impl DaemonController {
         self.fs_service.lock().unwrap().clone()
     }
 
+    /// Register a working thread, which should hold the returned guard until it exits.
+    pub fn register_worker(&self) -> WorkerGuard {
+        self.workers.register()
+    }
+
+    /// Wait until all registered working threads have exited, return false on timeout.
+    pub fn wait_workers(&self, timeout: Option<Duration>) -> bool {
+        self.workers.wait(timeout)
+    }
+
     fn shutdown(&self) {
         // Marking exiting state.
         self.active.store(false, Ordering::Release);
@@ -193,7 +225,9 @@ impl DaemonController {
 }
 
 extern "C" fn sig_exit(_sig: std::os::raw::c_int) {
-    DAEMON_CONTROLLER.shutdown();
+    // Only signal the main event loop to exit, the main thread will then stop the HTTP server and
+    // shutdown the daemon, which may wait for all working threads including the HTTP server.
+    let _ = DAEMON_CONTROLLER.waker.wake();
 }
 
 const SHARED_DIR_HELP_MESSAGE: &str = "Local directory to share via passthroughfs FUSE driver";
@@ -202,6 +236,16 @@ pub fn thread_validator(v: &str) -> std::result::Result<String, String> {
     ensure_threads(v).map(|s| s.to_string())
 }
 
+fn timeout_validator(v: &str) -> std::result::Result<String, String> {
+    match v.parse::<f64>() {
+        Ok(t) if t.is_finite() && t >= 0.0 => Ok(v.to_string()),
+        _ => Err(format!(
+            "invalid timeout {}, should be a non-negative number",
+            v
+        )),
+    }
+}
+
 fn append_fs_options(app: Command) -> Command {
     app.arg(
         Arg::new("bootstrap")
@@ -242,6 +286,22 @@ fn append_fs_options(app: Command) -> Command {
             .default_value("/")
             .required(false),
     )
+    .arg(
+        Arg::new("attr-timeout")
+            .long("attr-timeout")
+            .help("Timeout in seconds for the kernel to cache inode attributes, overriding the configuration")
+            .value_parser(timeout_validator)
+            .requires("bootstrap")
+            .required(false),
+    )
+    .arg(
+        Arg::new("entry-timeout")
+            .long("entry-timeout")
+            .help("Timeout in seconds for the kernel to cache directory entries, overriding the configuration")
+            .value_parser(timeout_validator)
+            .requires("bootstrap")
+            .required(false),
+    )
 }
 
 fn append_fuse_options(app: Command) -> Command {
@@ -308,6 +368,22 @@ fn append_virtiofs_subcmd_options(cmd: Command) -> Command {
     cmd.subcommand(subcmd)
 }
 
+fn append_nfs_subcmd_options(cmd: Command) -> Command {
+    let subcmd = Command::new("nfs")
+        .about("Run as an experimental read-only NFSv3 server")
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .help(
+                    "Address for the NFS server to listen on, serving both MOUNT and NFS programs",
+                )
+                .default_value("127.0.0.1:2049")
+                .required(false),
+        );
+    let subcmd = append_fs_options(subcmd);
+    cmd.subcommand(subcmd)
+}
+
 fn append_fscache_options(app: Command) -> Command {
     app.arg(
         Arg::new("fscache-tag")
@@ -318,6 +394,7 @@ fn append_fscache_options(app: Command) -> Command {
     .arg(
         Arg::new("fscache-threads")
             .long("fscache-threads")
+            .visible_alias("fscache-workers")
             .default_value("4")
             .help("Number of working threads to serve fscache requests")
             .required(false)
@@ -362,6 +439,13 @@ fn prepare_commandline_options() -> Command {
                 .required(false)
                 .global(true),
         )
+        .arg(
+            Arg::new("api-token-file")
+                .long("api-token-file")
+                .help("File containing the admin token to enable authorization of administration API requests")
+                .required(false)
+                .global(true),
+        )
         .arg(
             Arg::new("config")
                 .long("config")
@@ -404,6 +488,37 @@ fn prepare_commandline_options() -> Command {
                 .required(false)
                 .global(true),
         )
+        .arg(
+            Arg::new("event-log-capacity")
+                .long("event-log-capacity")
+                .help("Number of audit events kept in memory, 0 to disable")
+                .default_value("1024")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::new("event-log-file")
+                .long("event-log-file")
+                .help("Mirror audit events to the file in JSON-lines format")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::new("event-log-rotation-size")
+                .long("event-log-rotation-size")
+                .help("Specify audit event log file rotation size(MB), 0 to disable")
+                .default_value("0")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::new("event-log-rotation-count")
+                .long("event-log-rotation-count")
+                .help("Number of rotated audit event log files to keep")
+                .default_value("5")
+                .required(false)
+                .global(true),
+        )
         .arg(
             Arg::new("rlimit-nofile")
                 .long("rlimit-nofile")
@@ -427,6 +542,14 @@ fn prepare_commandline_options() -> Command {
                 .action(ArgAction::SetTrue)
                 .required(false)
                 .global(true),
+        )
+        .arg(
+            Arg::new("validate-config")
+                .long("validate-config")
+                .help("Validate the configuration file, print the normalized configuration and exit")
+                .value_name("path")
+                .required(false)
+                .global(true),
         );
     let cmdline = append_fuse_options(cmdline);
     let cmdline = append_fs_options(cmdline);
@@ -434,6 +557,7 @@ fn prepare_commandline_options() -> Command {
     let cmdline = append_fuse_subcmd_options(cmdline);
     #[cfg(feature = "virtiofs")]
     let cmdline = append_virtiofs_subcmd_options(cmdline);
+    let cmdline = append_nfs_subcmd_options(cmdline);
     append_services_subcmd_options(cmdline)
 }
 
@@ -543,11 +667,20 @@ impl<'a> SubCmdArgs<'a> {
     }
 }
 
+/// Type of services to export filesystems.
+#[derive(PartialEq)]
+enum FsServiceType {
+    Fuse,
+    #[cfg_attr(not(feature = "virtiofs"), allow(dead_code))]
+    Virtiofs,
+    Nfs,
+}
+
 fn process_fs_service(
     args: SubCmdArgs,
     bti: BuildTimeInfo,
     apisock: Option<&str>,
-    is_fuse: bool,
+    service_type: FsServiceType,
 ) -> Result<()> {
     // shared-dir means fs passthrough
     let shared_dir = args.value_of("shared-dir");
@@ -561,6 +694,7 @@ fn process_fs_service(
         let cmd = FsBackendMountCmd {
             fs_type: nydus::FsBackendType::PassthroughFs,
             source: shared_dir.to_string(),
+            bootstrap_blob_id: None,
             config: "".to_string(),
             mountpoint: virtual_mnt.to_string(),
             prefetch_files: None,
@@ -573,9 +707,9 @@ fn process_fs_service(
 
         Some(cmd)
     } else if let Some(b) = bootstrap {
-        let config = match args.value_of("localfs-dir") {
+        let mut config = match args.value_of("localfs-dir") {
             Some(v) => {
-                format!(
+                let content = format!(
                     r###"
         {{
             "device": {{
@@ -600,10 +734,11 @@ fn process_fs_service(
         }}
         "###,
                     v, v
-                )
+                );
+                RafsConfig::from_str(&content).map_err(|e| eother!(e))?
             }
             None => match args.value_of("config") {
-                Some(v) => std::fs::read_to_string(v)?,
+                Some(v) => ConfigV2::load(v)?.rafs_config()?.clone(),
                 None => {
                     let e = DaemonError::InvalidArguments(
                         "both --config and --localfs-dir are missing".to_string(),
@@ -613,6 +748,15 @@ fn process_fs_service(
             },
         };
 
+        // Safe to unwrap because timeouts have been validated.
+        if let Some(v) = args.value_of("attr-timeout") {
+            config.attr_timeout_override = Some(Duration::from_secs_f64(v.parse().unwrap()));
+        }
+        if let Some(v) = args.value_of("entry-timeout") {
+            config.entry_timeout_override = Some(Duration::from_secs_f64(v.parse().unwrap()));
+        }
+        let config = serde_json::to_string(&config).map_err(|e| eother!(e))?;
+
         let prefetch_files: Option<Vec<String>> = args
             .values_of("prefetch-files")
             .map(|files| files.map(|s| s.to_string()).collect());
@@ -620,6 +764,7 @@ fn process_fs_service(
         let cmd = FsBackendMountCmd {
             fs_type: nydus::FsBackendType::Rafs,
             source: b.to_string(),
+            bootstrap_blob_id: None,
             config,
             mountpoint: virtual_mnt.to_string(),
             prefetch_files,
@@ -634,7 +779,7 @@ fn process_fs_service(
     };
 
     // Enable all options required by passthroughfs
-    if !is_fuse && args.is_present("hybrid-mode") {
+    if service_type == FsServiceType::Virtiofs && args.is_present("hybrid-mode") {
         opts.no_open = false;
         opts.no_opendir = false;
         opts.killpriv_v2 = true;
@@ -646,7 +791,7 @@ fn process_fs_service(
     let daemon_id = args.value_of("id").map(|id| id.to_string());
     let supervisor = args.value_of("supervisor").map(|s| s.to_string());
 
-    if is_fuse {
+    if service_type == FsServiceType::Fuse {
         // threads means number of fuse service threads
         let threads: u32 = args
             .value_of("threads")
@@ -693,6 +838,22 @@ fn process_fs_service(
             })?
         };
         DAEMON_CONTROLLER.set_daemon(daemon);
+    } else if service_type == FsServiceType::Nfs {
+        let mount_cmd = match mount_cmd {
+            Some(cmd) if cmd.fs_type == nydus::FsBackendType::Rafs => cmd,
+            _ => {
+                let e = DaemonError::InvalidArguments(
+                    "--bootstrap must be provided for NFS server!".to_string(),
+                );
+                return Err(e.into());
+            }
+        };
+        // Safe to unwrap because it has default value.
+        let listen = args.value_of("listen").unwrap();
+        let _ = apisock.as_ref();
+        DAEMON_CONTROLLER.set_daemon(nfs::create_nfs_daemon(
+            daemon_id, supervisor, listen, vfs, mount_cmd, bti,
+        )?);
     } else {
         #[cfg(feature = "virtiofs")]
         {
@@ -729,6 +890,37 @@ lazy_static! {
     static ref BTI: BuildTimeInfo = BuildTimeInfo::dump().1;
 }
 
+fn handle_event_log_options(args: &ArgMatches) -> Result<()> {
+    // Safe to unwrap because these options have default values.
+    let capacity = args
+        .get_one::<String>("event-log-capacity")
+        .unwrap()
+        .parse::<usize>()
+        .map_err(|e| einval!(format!("Invalid event log capacity: {}", e)))?;
+    let rotation_size = args
+        .get_one::<String>("event-log-rotation-size")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|e| einval!(format!("Invalid event log rotation size: {}", e)))?;
+    let rotation_count = args
+        .get_one::<String>("event-log-rotation-count")
+        .unwrap()
+        .parse::<usize>()
+        .map_err(|e| einval!(format!("Invalid event log rotation count: {}", e)))?;
+    let file = args
+        .get_one::<String>("event-log-file")
+        .map(|path| AuditFileConfig {
+            path: PathBuf::from(path),
+            rotation_size: rotation_size * 1024 * 1024,
+            rotation_count,
+        });
+
+    audit::configure(capacity, file).map_err(|e| {
+        error!("Failed to open audit event log file, {}", e);
+        e
+    })
+}
+
 fn main() -> Result<()> {
     let bti = BTI.to_owned();
     let cmd_options = prepare_commandline_options().version(BTI_STRING.as_str());
@@ -749,8 +941,19 @@ fn main() -> Result<()> {
 
     setup_logging(logging_file, level, rotation_size)?;
 
+    if let Some(path) = args.get_one::<String>("validate-config") {
+        let config = ConfigV2::load(path).map_err(|e| {
+            error!("{}", e);
+            e
+        })?;
+        let content = serde_json::to_string_pretty(&config).map_err(|e| eother!(e))?;
+        println!("{}", content);
+        return Ok(());
+    }
+
     dump_program_info();
     handle_rlimit_nofile_option(&args, "rlimit-nofile")?;
+    handle_event_log_options(&args)?;
 
     match args.subcommand_name() {
         Some("singleton") => {
@@ -763,17 +966,23 @@ fn main() -> Result<()> {
             // Safe to unwrap because the subcommand is `fuse`.
             let subargs = args.subcommand_matches("fuse").unwrap();
             let subargs = SubCmdArgs::new(&args, subargs);
-            process_fs_service(subargs, bti, apisock, true)?;
+            process_fs_service(subargs, bti, apisock, FsServiceType::Fuse)?;
         }
         Some("virtiofs") => {
             // Safe to unwrap because the subcommand is `virtiofs`.
             let subargs = args.subcommand_matches("virtiofs").unwrap();
             let subargs = SubCmdArgs::new(&args, subargs);
-            process_fs_service(subargs, bti, apisock, false)?;
+            process_fs_service(subargs, bti, apisock, FsServiceType::Virtiofs)?;
+        }
+        Some("nfs") => {
+            // Safe to unwrap because the subcommand is `nfs`.
+            let subargs = args.subcommand_matches("nfs").unwrap();
+            let subargs = SubCmdArgs::new(&args, subargs);
+            process_fs_service(subargs, bti, apisock, FsServiceType::Nfs)?;
         }
         _ => {
             let subargs = SubCmdArgs::new(&args, &args);
-            process_fs_service(subargs, bti, apisock, true)?;
+            process_fs_service(subargs, bti, apisock, FsServiceType::Fuse)?;
         }
     }
 
@@ -782,6 +991,18 @@ fn main() -> Result<()> {
         DAEMON_CONTROLLER.set_fs_service(fs);
     }
 
+    if let Some(path) = args.get_one::<String>("api-token-file") {
+        let token = std::fs::read_to_string(path).map_err(|e| {
+            error!("Failed to read API admin token from {}, {}", path, e);
+            e
+        })?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(einval!(format!("API admin token file {} is empty", path)));
+        }
+        nydus_api::http_auth::set_admin_token(token.to_string());
+    }
+
     // Start the HTTP Administration API server
     let mut api_controller = ApiServerController::new(apisock);
     api_controller.start()?;
@@ -0,0 +1,1257 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Experimental NFSv3 server to export RAFS filesystems in read-only mode.
+//!
+//! It's useful on hosts without FUSE or fscache support. Both the MOUNT and NFS programs are
+//! served on the same TCP port without registering to the portmapper, so clients need to specify
+//! the ports explicitly:
+//! `mount -t nfs -o vers=3,proto=tcp,port=2049,mountport=2049,mountproto=tcp,nolock 127.0.0.1:/ /mnt`
+
+use std::any::Any;
+use std::cmp;
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use fuse_backend_rs::abi::fuse_abi::stat64;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+use fuse_backend_rs::api::Vfs;
+#[cfg(target_os = "linux")]
+use nix::sys::stat::{major, minor};
+use nydus_app::BuildTimeInfo;
+use rafs::fs::Rafs;
+
+use crate::daemon::{
+    DaemonError, DaemonResult, DaemonState, DaemonStateMachineContext, DaemonStateMachineInput,
+    DaemonStateMachineSubscriber, NydusDaemon,
+};
+use crate::fs_service::{FsBackendCollection, FsBackendMountCmd, FsService};
+use crate::upgrade::UpgradeManager;
+use crate::DAEMON_CONTROLLER;
+
+const RPC_VERSION: u32 = 2;
+const RPC_CALL: u32 = 0;
+const RPC_REPLY: u32 = 1;
+const MSG_ACCEPTED: u32 = 0;
+const MSG_DENIED: u32 = 1;
+const RPC_MISMATCH: u32 = 0;
+const AUTH_NONE: u32 = 0;
+const AUTH_UNIX: u32 = 1;
+const ACCEPT_SUCCESS: u32 = 0;
+const ACCEPT_PROG_UNAVAIL: u32 = 1;
+const ACCEPT_PROG_MISMATCH: u32 = 2;
+const ACCEPT_PROC_UNAVAIL: u32 = 3;
+const ACCEPT_GARBAGE_ARGS: u32 = 4;
+const RPC_MAX_AUTH_SIZE: usize = 400;
+const RPC_LAST_FRAGMENT: u32 = 0x8000_0000;
+// Calls are small because writing is not supported, so limit size of RPC records.
+const RPC_MAX_RECORD_SIZE: usize = 0x10000;
+
+const MOUNT_PROGRAM: u32 = 100005;
+const MOUNT_V3: u32 = 3;
+const MOUNTPROC3_NULL: u32 = 0;
+const MOUNTPROC3_MNT: u32 = 1;
+const MOUNTPROC3_DUMP: u32 = 2;
+const MOUNTPROC3_UMNT: u32 = 3;
+const MOUNTPROC3_UMNTALL: u32 = 4;
+const MOUNTPROC3_EXPORT: u32 = 5;
+const MNT3_OK: u32 = 0;
+const MNT3ERR_NOENT: u32 = 2;
+const MNT3ERR_SERVERFAULT: u32 = 10006;
+const MNT_PATH_LEN: usize = 1024;
+
+const NFS_PROGRAM: u32 = 100003;
+const NFS_V3: u32 = 3;
+const NFSPROC3_NULL: u32 = 0;
+const NFSPROC3_GETATTR: u32 = 1;
+const NFSPROC3_SETATTR: u32 = 2;
+const NFSPROC3_LOOKUP: u32 = 3;
+const NFSPROC3_ACCESS: u32 = 4;
+const NFSPROC3_READLINK: u32 = 5;
+const NFSPROC3_READ: u32 = 6;
+const NFSPROC3_WRITE: u32 = 7;
+const NFSPROC3_CREATE: u32 = 8;
+const NFSPROC3_MKDIR: u32 = 9;
+const NFSPROC3_SYMLINK: u32 = 10;
+const NFSPROC3_MKNOD: u32 = 11;
+const NFSPROC3_REMOVE: u32 = 12;
+const NFSPROC3_RMDIR: u32 = 13;
+const NFSPROC3_RENAME: u32 = 14;
+const NFSPROC3_LINK: u32 = 15;
+const NFSPROC3_READDIR: u32 = 16;
+const NFSPROC3_READDIRPLUS: u32 = 17;
+const NFSPROC3_FSSTAT: u32 = 18;
+const NFSPROC3_FSINFO: u32 = 19;
+const NFSPROC3_PATHCONF: u32 = 20;
+const NFSPROC3_COMMIT: u32 = 21;
+
+const NFS3_OK: u32 = 0;
+const NFS3ERR_NOENT: u32 = 2;
+const NFS3ERR_IO: u32 = 5;
+const NFS3ERR_ACCES: u32 = 13;
+const NFS3ERR_NOTDIR: u32 = 20;
+const NFS3ERR_ISDIR: u32 = 21;
+const NFS3ERR_INVAL: u32 = 22;
+const NFS3ERR_ROFS: u32 = 30;
+const NFS3ERR_NAMETOOLONG: u32 = 63;
+const NFS3ERR_STALE: u32 = 70;
+const NFS3ERR_BADHANDLE: u32 = 10001;
+const NFS3ERR_TOOSMALL: u32 = 10005;
+const NFS3ERR_NOTSUPP: u32 = 10004;
+
+const NF3REG: u32 = 1;
+const NF3DIR: u32 = 2;
+const NF3BLK: u32 = 3;
+const NF3CHR: u32 = 4;
+const NF3LNK: u32 = 5;
+const NF3SOCK: u32 = 6;
+const NF3FIFO: u32 = 7;
+
+const ACCESS3_READ: u32 = 0x1;
+const ACCESS3_LOOKUP: u32 = 0x2;
+const ACCESS3_EXECUTE: u32 = 0x20;
+const FSF3_LINK: u32 = 0x1;
+const FSF3_SYMLINK: u32 = 0x2;
+const FSF3_HOMOGENEOUS: u32 = 0x8;
+
+const NFS3_FHSIZE: usize = 64;
+const NFS3_MAX_NAME_LEN: usize = 255;
+const NFS3_COOKIEVERF_SIZE: usize = 8;
+const NFS_MAX_READ_SIZE: u32 = 0x100000;
+const NFS_MAX_READDIR_SIZE: u32 = 0x10000;
+// Size of fields in READDIR/READDIRPLUS replies other than directory entries.
+const NFS_READDIR_REPLY_OVERHEAD: usize = 128;
+// There's only one filesystem exported, so use a fixed filesystem id.
+const NFS_FSID: u64 = 0x6e79_6475;
+
+/// Status code defined by the NFSv3 protocol.
+type NfsResult<T> = std::result::Result<T, u32>;
+
+fn garbage_args() -> Error {
+    Error::new(ErrorKind::InvalidData, "nfs: invalid XDR encoded arguments")
+}
+
+/// Decoder for XDR encoded data, as defined by RFC 4506.
+struct XdrReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> XdrReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        XdrReader { buf, pos: 0 }
+    }
+
+    fn fixed(&mut self, len: usize) -> Result<&'a [u8]> {
+        let padded = len.checked_add(3).ok_or_else(garbage_args)? & !3;
+        if padded > self.buf.len() - self.pos {
+            return Err(garbage_args());
+        }
+        let data = &self.buf[self.pos..self.pos + len];
+        self.pos += padded;
+        Ok(data)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let data = self.fixed(4)?;
+        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let high = self.u32()? as u64;
+        let low = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    fn opaque(&mut self, max_len: usize) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if len > max_len {
+            return Err(garbage_args());
+        }
+        self.fixed(len)
+    }
+}
+
+/// Encoder for XDR encoded data, as defined by RFC 4506.
+#[derive(Default)]
+struct XdrWriter {
+    buf: Vec<u8>,
+}
+
+impl XdrWriter {
+    fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn bool(&mut self, v: bool) {
+        self.u32(v as u32);
+    }
+
+    fn fixed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        let pad = (4 - data.len() % 4) % 4;
+        self.buf.extend_from_slice(&[0u8; 3][..pad]);
+    }
+
+    fn opaque(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.fixed(data);
+    }
+
+    fn append(&mut self, other: &XdrWriter) {
+        self.buf.extend_from_slice(&other.buf);
+    }
+}
+
+/// Read a RPC record, which may consist of multiple fragments, return `None` on EOF.
+fn read_record<R: Read>(r: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+
+    loop {
+        let mut header = [0u8; 4];
+        match r.read_exact(&mut header) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && record.is_empty() => return Ok(None),
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+        let header = u32::from_be_bytes(header);
+        let len = (header & !RPC_LAST_FRAGMENT) as usize;
+        if record.len() + len > RPC_MAX_RECORD_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("nfs: RPC record is too big, size {}", record.len() + len),
+            ));
+        }
+        let pos = record.len();
+        record.resize(pos + len, 0);
+        r.read_exact(&mut record[pos..])?;
+        if header & RPC_LAST_FRAGMENT != 0 {
+            return Ok(Some(record));
+        }
+    }
+}
+
+/// Write `data` as a RPC record with a single fragment.
+fn write_record<W: Write>(w: &mut W, data: &[u8]) -> Result<()> {
+    let mut buf = Vec::with_capacity(data.len() + 4);
+    buf.extend_from_slice(&(data.len() as u32 | RPC_LAST_FRAGMENT).to_be_bytes());
+    buf.extend_from_slice(data);
+    w.write_all(&buf)
+}
+
+fn nfs_status(e: &Error) -> u32 {
+    match e.raw_os_error() {
+        Some(libc::ENOENT) => NFS3ERR_NOENT,
+        Some(libc::EACCES) => NFS3ERR_ACCES,
+        Some(libc::ENOTDIR) => NFS3ERR_NOTDIR,
+        Some(libc::EISDIR) => NFS3ERR_ISDIR,
+        Some(libc::EINVAL) => NFS3ERR_INVAL,
+        Some(libc::EROFS) => NFS3ERR_ROFS,
+        Some(libc::ENAMETOOLONG) => NFS3ERR_NAMETOOLONG,
+        Some(libc::ESTALE) => NFS3ERR_STALE,
+        _ => NFS3ERR_IO,
+    }
+}
+
+fn file_type(st: &stat64) -> u32 {
+    match st.st_mode as u32 & libc::S_IFMT as u32 {
+        x if x == libc::S_IFDIR as u32 => NF3DIR,
+        x if x == libc::S_IFBLK as u32 => NF3BLK,
+        x if x == libc::S_IFCHR as u32 => NF3CHR,
+        x if x == libc::S_IFLNK as u32 => NF3LNK,
+        x if x == libc::S_IFSOCK as u32 => NF3SOCK,
+        x if x == libc::S_IFIFO as u32 => NF3FIFO,
+        _ => NF3REG,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn device_numbers(dev: u64) -> (u32, u32) {
+    (major(dev) as u32, minor(dev) as u32)
+}
+
+#[cfg(target_os = "macos")]
+fn device_numbers(dev: u64) -> (u32, u32) {
+    ((dev >> 24) as u32 & 0xff, dev as u32 & 0xff_ffff)
+}
+
+/// Encode a `fattr3` object from file attributes.
+fn encode_fattr(w: &mut XdrWriter, st: &stat64) {
+    let (major, minor) = device_numbers(st.st_rdev as u64);
+
+    w.u32(file_type(st));
+    w.u32(st.st_mode as u32 & 0o7777);
+    w.u32(st.st_nlink as u32);
+    w.u32(st.st_uid);
+    w.u32(st.st_gid);
+    w.u64(st.st_size as u64);
+    w.u64(st.st_blocks as u64 * 512);
+    w.u32(major);
+    w.u32(minor);
+    w.u64(NFS_FSID);
+    w.u64(st.st_ino as u64);
+    w.u32(st.st_atime as u32);
+    w.u32(st.st_atime_nsec as u32);
+    w.u32(st.st_mtime as u32);
+    w.u32(st.st_mtime_nsec as u32);
+    w.u32(st.st_ctime as u32);
+    w.u32(st.st_ctime_nsec as u32);
+}
+
+/// Encode an empty `wcc_data` object, the filesystem is never changed.
+fn encode_empty_wcc(w: &mut XdrWriter) {
+    w.bool(false);
+    w.bool(false);
+}
+
+/// File handles are big endian encoded inode numbers of the RAFS filesystem.
+fn inode_from_handle(fh: &[u8]) -> NfsResult<u64> {
+    if fh.len() != 8 {
+        return Err(NFS3ERR_BADHANDLE);
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(fh);
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn request_context() -> Context {
+    Context {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    }
+}
+
+/// Server to handle MOUNT and NFS requests for a RAFS filesystem.
+pub struct NfsServer {
+    service: Arc<NfsFsService>,
+    export: String,
+    listener: TcpListener,
+    stopped: AtomicBool,
+    conns: Mutex<Vec<TcpStream>>,
+    conn_threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl NfsServer {
+    fn new(service: Arc<NfsFsService>, listener: TcpListener, export: &str) -> Self {
+        NfsServer {
+            service,
+            export: export.to_string(),
+            listener,
+            stopped: AtomicBool::new(false),
+            conns: Mutex::new(Vec::new()),
+            conn_threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Accept connections from NFS clients until the server gets stopped.
+    fn run(self: &Arc<Self>) -> Result<()> {
+        for stream in self.listener.incoming() {
+            if self.stopped.load(Ordering::Acquire) {
+                break;
+            }
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("nfs: failed to accept connection, {}", e);
+                    continue;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+            self.conns.lock().unwrap().push(stream.try_clone()?);
+
+            let server = self.clone();
+            let handle = thread::Builder::new()
+                .name("nfs_conn".to_string())
+                .spawn(move || {
+                    if let Err(e) = server.serve(stream) {
+                        if !server.stopped.load(Ordering::Acquire) {
+                            warn!("nfs: connection closed, {}", e);
+                        }
+                    }
+                })?;
+            self.conn_threads.lock().unwrap().push(handle);
+        }
+
+        Ok(())
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> Result<()> {
+        while let Some(call) = read_record(&mut stream)? {
+            match self.handle_call(&call) {
+                Ok(reply) => write_record(&mut stream, &reply)?,
+                Err(e) => warn!("nfs: drop invalid RPC message, {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop the server and shutdown all connections.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        for conn in self.conns.lock().unwrap().drain(..) {
+            let _ = conn.shutdown(Shutdown::Both);
+        }
+        // Wake up the thread blocking on `accept()`.
+        if let Ok(addr) = self.listener.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+    }
+
+    fn wait(&self) {
+        loop {
+            let handle = self.conn_threads.lock().unwrap().pop();
+            match handle {
+                Some(handle) => {
+                    let _ = handle.join();
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Handle a RPC call message and generate the reply message.
+    fn handle_call(&self, call: &[u8]) -> Result<Vec<u8>> {
+        let mut r = XdrReader::new(call);
+        let xid = r.u32()?;
+        if r.u32()? != RPC_CALL {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "nfs: RPC message is not a call",
+            ));
+        }
+
+        let mut w = XdrWriter::default();
+        w.u32(xid);
+        w.u32(RPC_REPLY);
+        if r.u32()? != RPC_VERSION {
+            w.u32(MSG_DENIED);
+            w.u32(RPC_MISMATCH);
+            w.u32(RPC_VERSION);
+            w.u32(RPC_VERSION);
+            return Ok(w.buf);
+        }
+
+        let prog = r.u32()?;
+        let vers = r.u32()?;
+        let procedure = r.u32()?;
+        // Credentials are ignored because the filesystem is exported in read-only mode.
+        r.u32()?;
+        r.opaque(RPC_MAX_AUTH_SIZE)?;
+        r.u32()?;
+        r.opaque(RPC_MAX_AUTH_SIZE)?;
+
+        w.u32(MSG_ACCEPTED);
+        w.u32(AUTH_NONE);
+        w.u32(0);
+        let pos = w.len();
+        w.u32(ACCEPT_SUCCESS);
+
+        let result = match (prog, vers) {
+            (NFS_PROGRAM, NFS_V3) => self.handle_nfs(procedure, &mut r, &mut w),
+            (MOUNT_PROGRAM, MOUNT_V3) => self.handle_mount(procedure, &mut r, &mut w),
+            (NFS_PROGRAM, _) | (MOUNT_PROGRAM, _) => {
+                w.buf.truncate(pos);
+                w.u32(ACCEPT_PROG_MISMATCH);
+                w.u32(3);
+                w.u32(3);
+                Ok(())
+            }
+            _ => {
+                w.buf.truncate(pos);
+                w.u32(ACCEPT_PROG_UNAVAIL);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            w.buf.truncate(pos);
+            if e.kind() == ErrorKind::Unsupported {
+                w.u32(ACCEPT_PROC_UNAVAIL);
+            } else {
+                w.u32(ACCEPT_GARBAGE_ARGS);
+            }
+        }
+
+        Ok(w.buf)
+    }
+
+    fn handle_mount(&self, procedure: u32, r: &mut XdrReader, w: &mut XdrWriter) -> Result<()> {
+        match procedure {
+            MOUNTPROC3_NULL | MOUNTPROC3_UMNTALL => {}
+            MOUNTPROC3_MNT => {
+                let path = r.opaque(MNT_PATH_LEN)?;
+                if path != self.export.as_bytes() {
+                    w.u32(MNT3ERR_NOENT);
+                    return Ok(());
+                }
+                match self.with_rafs(|fs| Ok(fs.metadata().root_inode)) {
+                    Ok(ino) => {
+                        w.u32(MNT3_OK);
+                        w.opaque(&ino.to_be_bytes());
+                        w.u32(2);
+                        w.u32(AUTH_UNIX);
+                        w.u32(AUTH_NONE);
+                    }
+                    Err(_) => w.u32(MNT3ERR_SERVERFAULT),
+                }
+            }
+            MOUNTPROC3_DUMP => w.bool(false),
+            MOUNTPROC3_UMNT => {
+                r.opaque(MNT_PATH_LEN)?;
+            }
+            MOUNTPROC3_EXPORT => {
+                w.bool(true);
+                w.opaque(self.export.as_bytes());
+                w.bool(false);
+                w.bool(false);
+            }
+            _ => return Err(Error::from(ErrorKind::Unsupported)),
+        }
+
+        Ok(())
+    }
+
+    fn handle_nfs(&self, procedure: u32, r: &mut XdrReader, w: &mut XdrWriter) -> Result<()> {
+        match procedure {
+            NFSPROC3_NULL => {}
+            NFSPROC3_GETATTR => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                match inode_from_handle(fh).and_then(|ino| self.getattr(ino)) {
+                    Ok(st) => {
+                        w.u32(NFS3_OK);
+                        encode_fattr(w, &st);
+                    }
+                    Err(s) => w.u32(s),
+                }
+            }
+            NFSPROC3_LOOKUP => {
+                let dir = r.opaque(NFS3_FHSIZE)?;
+                let name = r.opaque(NFS3_MAX_NAME_LEN)?;
+                let parent = inode_from_handle(dir).ok();
+                match self.lookup(dir, name) {
+                    Ok(ino) => {
+                        w.u32(NFS3_OK);
+                        w.opaque(&ino.to_be_bytes());
+                        self.encode_post_op_attr(w, Some(ino));
+                        self.encode_post_op_attr(w, parent);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        self.encode_post_op_attr(w, parent);
+                    }
+                }
+            }
+            NFSPROC3_ACCESS => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let access = r.u32()?;
+                match inode_from_handle(fh).and_then(|ino| self.getattr(ino)) {
+                    Ok(st) => {
+                        w.u32(NFS3_OK);
+                        w.bool(true);
+                        encode_fattr(w, &st);
+                        w.u32(access & (ACCESS3_READ | ACCESS3_LOOKUP | ACCESS3_EXECUTE));
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        w.bool(false);
+                    }
+                }
+            }
+            NFSPROC3_READLINK => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let ino = inode_from_handle(fh);
+                let ctx = request_context();
+                match ino.and_then(|ino| self.with_rafs(|fs| fs.readlink(&ctx, ino))) {
+                    Ok(target) => {
+                        w.u32(NFS3_OK);
+                        self.encode_post_op_attr(w, ino.ok());
+                        w.opaque(&target);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        self.encode_post_op_attr(w, ino.ok());
+                    }
+                }
+            }
+            NFSPROC3_READ => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let offset = r.u64()?;
+                let count = r.u32()?;
+                match self.read(fh, offset, count) {
+                    Ok((st, data, eof)) => {
+                        w.u32(NFS3_OK);
+                        w.bool(true);
+                        encode_fattr(w, &st);
+                        w.u32(data.len() as u32);
+                        w.bool(eof);
+                        w.opaque(&data);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        w.bool(false);
+                    }
+                }
+            }
+            NFSPROC3_READDIR => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let cookie = r.u64()?;
+                r.fixed(NFS3_COOKIEVERF_SIZE)?;
+                let count = r.u32()?;
+                self.readdir(w, fh, cookie, count, false);
+            }
+            NFSPROC3_READDIRPLUS => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let cookie = r.u64()?;
+                r.fixed(NFS3_COOKIEVERF_SIZE)?;
+                // The `dircount` field, only `maxcount` matters.
+                r.u32()?;
+                let count = r.u32()?;
+                self.readdir(w, fh, cookie, count, true);
+            }
+            NFSPROC3_FSSTAT => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                let ino = inode_from_handle(fh);
+                let ctx = request_context();
+                match ino.and_then(|ino| self.with_rafs(|fs| fs.statfs(&ctx, ino))) {
+                    Ok(st) => {
+                        w.u32(NFS3_OK);
+                        self.encode_post_op_attr(w, ino.ok());
+                        w.u64(st.f_blocks as u64 * st.f_bsize as u64);
+                        w.u64(0);
+                        w.u64(0);
+                        w.u64(st.f_files as u64);
+                        w.u64(0);
+                        w.u64(0);
+                        w.u32(0);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        w.bool(false);
+                    }
+                }
+            }
+            NFSPROC3_FSINFO => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                match inode_from_handle(fh).and_then(|ino| self.getattr(ino)) {
+                    Ok(st) => {
+                        w.u32(NFS3_OK);
+                        w.bool(true);
+                        encode_fattr(w, &st);
+                        w.u32(NFS_MAX_READ_SIZE);
+                        w.u32(NFS_MAX_READ_SIZE);
+                        w.u32(4096);
+                        w.u32(0);
+                        w.u32(0);
+                        w.u32(4096);
+                        w.u32(NFS_MAX_READDIR_SIZE);
+                        w.u64(u64::MAX);
+                        w.u32(0);
+                        w.u32(1);
+                        w.u32(FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        w.bool(false);
+                    }
+                }
+            }
+            NFSPROC3_PATHCONF => {
+                let fh = r.opaque(NFS3_FHSIZE)?;
+                match inode_from_handle(fh).and_then(|ino| self.getattr(ino)) {
+                    Ok(st) => {
+                        w.u32(NFS3_OK);
+                        w.bool(true);
+                        encode_fattr(w, &st);
+                        w.u32(u32::MAX);
+                        w.u32(NFS3_MAX_NAME_LEN as u32);
+                        w.bool(true);
+                        w.bool(true);
+                        w.bool(false);
+                        w.bool(true);
+                    }
+                    Err(s) => {
+                        w.u32(s);
+                        w.bool(false);
+                    }
+                }
+            }
+            NFSPROC3_SETATTR | NFSPROC3_WRITE | NFSPROC3_CREATE | NFSPROC3_MKDIR
+            | NFSPROC3_SYMLINK | NFSPROC3_MKNOD | NFSPROC3_REMOVE | NFSPROC3_RMDIR
+            | NFSPROC3_COMMIT => {
+                w.u32(NFS3ERR_ROFS);
+                encode_empty_wcc(w);
+            }
+            NFSPROC3_RENAME => {
+                w.u32(NFS3ERR_ROFS);
+                encode_empty_wcc(w);
+                encode_empty_wcc(w);
+            }
+            NFSPROC3_LINK => {
+                w.u32(NFS3ERR_ROFS);
+                w.bool(false);
+                encode_empty_wcc(w);
+            }
+            _ => return Err(Error::from(ErrorKind::Unsupported)),
+        }
+
+        Ok(())
+    }
+
+    fn with_rafs<T, F: FnOnce(&Rafs) -> Result<T>>(&self, f: F) -> NfsResult<T> {
+        let fs = self
+            .service
+            .backend_from_mountpoint(&self.export)
+            .map_err(|_| NFS3ERR_IO)?
+            .ok_or(NFS3ERR_STALE)?;
+        let rafs = fs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or(NFS3ERR_NOTSUPP)?;
+        f(rafs).map_err(|e| nfs_status(&e))
+    }
+
+    fn getattr(&self, ino: u64) -> NfsResult<stat64> {
+        let ctx = request_context();
+        self.with_rafs(|fs| fs.getattr(&ctx, ino, None).map(|(st, _)| st))
+    }
+
+    fn encode_post_op_attr(&self, w: &mut XdrWriter, ino: Option<u64>) {
+        match ino.and_then(|ino| self.getattr(ino).ok()) {
+            Some(st) => {
+                w.bool(true);
+                encode_fattr(w, &st);
+            }
+            None => w.bool(false),
+        }
+    }
+
+    fn lookup(&self, dir: &[u8], name: &[u8]) -> NfsResult<u64> {
+        let parent = inode_from_handle(dir)?;
+        let name = CString::new(name).map_err(|_| NFS3ERR_INVAL)?;
+        let ctx = request_context();
+        let entry = self.with_rafs(|fs| fs.lookup(&ctx, parent, &name))?;
+        // Inode number 0 means a negative entry.
+        if entry.inode == 0 {
+            Err(NFS3ERR_NOENT)
+        } else {
+            Ok(entry.inode)
+        }
+    }
+
+    fn read(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<(stat64, Vec<u8>, bool)> {
+        let ino = inode_from_handle(fh)?;
+        let st = self.getattr(ino)?;
+        match file_type(&st) {
+            NF3REG => {}
+            NF3DIR => return Err(NFS3ERR_ISDIR),
+            _ => return Err(NFS3ERR_INVAL),
+        }
+
+        let mut buf = vec![0u8; cmp::min(count, NFS_MAX_READ_SIZE) as usize];
+        let size = self.with_rafs(|fs| fs.read_to_buf(ino, offset, &mut buf))?;
+        buf.truncate(size);
+        let eof = offset.saturating_add(size as u64) >= st.st_size as u64;
+
+        Ok((st, buf, eof))
+    }
+
+    /// Handle READDIR and READDIRPLUS requests, cookies are offsets of directory entries.
+    fn readdir(&self, w: &mut XdrWriter, fh: &[u8], cookie: u64, count: u32, plus: bool) {
+        let ino = match inode_from_handle(fh) {
+            Ok(ino) => ino,
+            Err(s) => {
+                w.u32(s);
+                w.bool(false);
+                return;
+            }
+        };
+        let max_size = (cmp::min(count, NFS_MAX_READDIR_SIZE) as usize)
+            .saturating_sub(NFS_READDIR_REPLY_OVERHEAD);
+        let mut entries = XdrWriter::default();
+        let mut eof = true;
+        let mut add_entry = |ino: u64, name: &[u8], offset: u64, st: Option<&stat64>| {
+            let mut e = XdrWriter::default();
+            e.bool(true);
+            e.u64(ino);
+            e.opaque(name);
+            e.u64(offset);
+            if plus {
+                match st {
+                    Some(st) => {
+                        e.bool(true);
+                        encode_fattr(&mut e, st);
+                        e.bool(true);
+                        e.opaque(&ino.to_be_bytes());
+                    }
+                    None => {
+                        e.bool(false);
+                        e.bool(false);
+                    }
+                }
+            }
+            if entries.len() + e.len() > max_size {
+                eof = false;
+                0
+            } else {
+                entries.append(&e);
+                1
+            }
+        };
+
+        let ctx = request_context();
+        let size = cmp::max(count, 1);
+        let result = self.with_rafs(|fs| {
+            if plus {
+                fs.readdirplus(&ctx, ino, 0, size, cookie, &mut |de, entry| {
+                    Ok(add_entry(de.ino, de.name, de.offset, Some(&entry.attr)))
+                })
+            } else {
+                fs.readdir(&ctx, ino, 0, size, cookie, &mut |de| {
+                    Ok(add_entry(de.ino, de.name, de.offset, None))
+                })
+            }
+        });
+
+        match result {
+            Ok(()) if !eof && entries.len() == 0 => {
+                w.u32(NFS3ERR_TOOSMALL);
+                self.encode_post_op_attr(w, Some(ino));
+            }
+            Ok(()) => {
+                w.u32(NFS3_OK);
+                self.encode_post_op_attr(w, Some(ino));
+                w.fixed(&[0u8; NFS3_COOKIEVERF_SIZE]);
+                w.append(&entries);
+                w.bool(false);
+                w.bool(eof);
+            }
+            Err(s) => {
+                w.u32(s);
+                self.encode_post_op_attr(w, Some(ino));
+            }
+        }
+    }
+}
+
+pub struct NfsFsService {
+    vfs: Arc<Vfs>,
+    upgrade_mgr: Option<Mutex<UpgradeManager>>,
+    backend_collection: Mutex<FsBackendCollection>,
+}
+
+impl NfsFsService {
+    fn new(vfs: Arc<Vfs>) -> Self {
+        NfsFsService {
+            vfs,
+            upgrade_mgr: None,
+            backend_collection: Default::default(),
+        }
+    }
+}
+
+impl FsService for NfsFsService {
+    fn get_vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    fn upgrade_mgr(&self) -> Option<MutexGuard<UpgradeManager>> {
+        self.upgrade_mgr.as_ref().map(|mgr| mgr.lock().unwrap())
+    }
+
+    fn backend_collection(&self) -> MutexGuard<FsBackendCollection> {
+        self.backend_collection.lock().unwrap()
+    }
+
+    fn export_inflight_ops(&self) -> DaemonResult<Option<String>> {
+        Err(DaemonError::Unsupported)
+    }
+}
+
+struct NfsDaemon {
+    bti: BuildTimeInfo,
+    id: Option<String>,
+    request_sender: Arc<Mutex<Sender<DaemonStateMachineInput>>>,
+    result_receiver: Mutex<Receiver<DaemonResult<()>>>,
+    service: Arc<NfsFsService>,
+    state: AtomicI32,
+    supervisor: Option<String>,
+
+    server: Arc<NfsServer>,
+    state_machine_thread: Mutex<Option<JoinHandle<Result<()>>>>,
+    nfs_service_threads: Mutex<Vec<JoinHandle<Result<()>>>>,
+}
+
+impl NydusDaemon for NfsDaemon {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    fn get_state(&self) -> DaemonState {
+        self.state.load(Ordering::Relaxed).into()
+    }
+
+    fn set_state(&self, state: DaemonState) {
+        self.state.store(state as i32, Ordering::Relaxed);
+    }
+
+    fn version(&self) -> BuildTimeInfo {
+        self.bti.clone()
+    }
+
+    fn start(&self) -> DaemonResult<()> {
+        let server = self.server.clone();
+        let waker = DAEMON_CONTROLLER.alloc_waker();
+        server.stopped.store(false, Ordering::Release);
+        let thread = thread::Builder::new()
+            .name("nfs_server".to_string())
+            .spawn(move || {
+                let result = server.run();
+                if let Err(e) = &result {
+                    error!("nfs: server exited with error, {}", e);
+                    if let Err(err) = waker.wake() {
+                        error!("fail to exit daemon, error: {:?}", err);
+                    }
+                }
+                result
+            })
+            .map_err(DaemonError::ThreadSpawn)?;
+        self.nfs_service_threads.lock().unwrap().push(thread);
+
+        Ok(())
+    }
+
+    fn disconnect(&self) -> DaemonResult<()> {
+        self.server.stop();
+        Ok(())
+    }
+
+    fn interrupt(&self) {
+        self.server.stop();
+    }
+
+    fn wait(&self) -> DaemonResult<()> {
+        self.wait_state_machine()?;
+        self.wait_service()
+    }
+
+    fn wait_state_machine(&self) -> DaemonResult<()> {
+        let mut guard = self.state_machine_thread.lock().unwrap();
+        if guard.is_some() {
+            guard
+                .take()
+                .unwrap()
+                .join()
+                .map_err(|e| {
+                    DaemonError::WaitDaemon(
+                        *e.downcast::<Error>()
+                            .unwrap_or_else(|e| Box::new(eother!(e))),
+                    )
+                })?
+                .map_err(DaemonError::WaitDaemon)?;
+        }
+
+        Ok(())
+    }
+
+    fn wait_service(&self) -> DaemonResult<()> {
+        loop {
+            let handle = self.nfs_service_threads.lock().unwrap().pop();
+            match handle {
+                Some(handle) => {
+                    handle
+                        .join()
+                        .map_err(|e| {
+                            DaemonError::WaitDaemon(
+                                *e.downcast::<Error>()
+                                    .unwrap_or_else(|e| Box::new(eother!(e))),
+                            )
+                        })?
+                        .map_err(DaemonError::WaitDaemon)?;
+                }
+                None => break,
+            }
+        }
+        self.server.wait();
+
+        Ok(())
+    }
+
+    fn supervisor(&self) -> Option<String> {
+        self.supervisor.clone()
+    }
+
+    fn save(&self) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
+
+    fn restore(&self) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
+
+    fn get_default_fs_service(&self) -> Option<Arc<dyn FsService>> {
+        Some(self.service.clone())
+    }
+}
+
+impl DaemonStateMachineSubscriber for NfsDaemon {
+    fn on_event(&self, event: DaemonStateMachineInput) -> DaemonResult<()> {
+        self.request_sender
+            .lock()
+            .unwrap()
+            .send(event)
+            .map_err(|e| DaemonError::Channel(format!("send {:?}", e)))?;
+
+        self.result_receiver
+            .lock()
+            .expect("Not expect poisoned lock!")
+            .recv()
+            .map_err(|e| DaemonError::Channel(format!("recv {:?}", e)))?
+    }
+}
+
+/// Create a daemon to export the RAFS filesystem by NFSv3 at address `listen`.
+pub fn create_nfs_daemon(
+    id: Option<String>,
+    supervisor: Option<String>,
+    listen: &str,
+    vfs: Arc<Vfs>,
+    mount_cmd: FsBackendMountCmd,
+    bti: BuildTimeInfo,
+) -> Result<Arc<dyn NydusDaemon>> {
+    if mount_cmd.fs_type != nydus::FsBackendType::Rafs {
+        return Err(DaemonError::InvalidArguments(
+            "only RAFS filesystems may be exported by NFS".to_string(),
+        )
+        .into());
+    }
+
+    let listener = TcpListener::bind(listen)?;
+    info!("nfs server listening on {}", listener.local_addr()?);
+    let (trigger, events_rx) = channel::<DaemonStateMachineInput>();
+    let (result_sender, result_receiver) = channel::<DaemonResult<()>>();
+    let service = Arc::new(NfsFsService::new(vfs));
+    let server = Arc::new(NfsServer::new(
+        service.clone(),
+        listener,
+        &mount_cmd.mountpoint,
+    ));
+    let daemon = Arc::new(NfsDaemon {
+        bti,
+        id,
+        request_sender: Arc::new(Mutex::new(trigger)),
+        result_receiver: Mutex::new(result_receiver),
+        service,
+        state: AtomicI32::new(DaemonState::INIT as i32),
+        supervisor,
+
+        server,
+        state_machine_thread: Mutex::new(None),
+        nfs_service_threads: Mutex::new(Vec::new()),
+    });
+    let machine = DaemonStateMachineContext::new(daemon.clone(), events_rx, result_sender);
+    let machine_thread = machine.kick_state_machine()?;
+    *daemon.state_machine_thread.lock().unwrap() = Some(machine_thread);
+
+    daemon.service.mount(mount_cmd)?;
+    daemon
+        .on_event(DaemonStateMachineInput::Mount)
+        .map_err(|e| eother!(e))?;
+    daemon
+        .on_event(DaemonStateMachineInput::Start)
+        .map_err(|e| eother!(e))?;
+
+    Ok(daemon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fuse_backend_rs::api::VfsOptions;
+
+    fn create_server() -> NfsServer {
+        let vfs = Arc::new(Vfs::new(VfsOptions::default()));
+        let service = Arc::new(NfsFsService::new(vfs));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        NfsServer::new(service, listener, "/")
+    }
+
+    fn rpc_call(prog: u32, vers: u32, procedure: u32, args: &[u8]) -> Vec<u8> {
+        let mut w = XdrWriter::default();
+        w.u32(0x1234);
+        w.u32(RPC_CALL);
+        w.u32(RPC_VERSION);
+        w.u32(prog);
+        w.u32(vers);
+        w.u32(procedure);
+        w.u32(AUTH_UNIX);
+        w.opaque(&[0u8; 20]);
+        w.u32(AUTH_NONE);
+        w.opaque(&[]);
+        w.buf.extend_from_slice(args);
+        w.buf
+    }
+
+    /// Check the reply header and return the accept status and results.
+    fn parse_reply(reply: &[u8]) -> (u32, Vec<u8>) {
+        let mut r = XdrReader::new(reply);
+        assert_eq!(r.u32().unwrap(), 0x1234);
+        assert_eq!(r.u32().unwrap(), RPC_REPLY);
+        assert_eq!(r.u32().unwrap(), MSG_ACCEPTED);
+        assert_eq!(r.u32().unwrap(), AUTH_NONE);
+        assert_eq!(r.opaque(RPC_MAX_AUTH_SIZE).unwrap().len(), 0);
+        let status = r.u32().unwrap();
+        (status, reply[r.pos..].to_vec())
+    }
+
+    #[test]
+    fn test_xdr_encode_decode() {
+        let mut w = XdrWriter::default();
+        w.u32(1);
+        w.u64(0x1_0000_0002);
+        w.bool(true);
+        w.opaque(b"hello");
+        w.opaque(&[]);
+        assert_eq!(w.len(), 4 + 8 + 4 + 12 + 4);
+
+        let mut r = XdrReader::new(&w.buf);
+        assert_eq!(r.u32().unwrap(), 1);
+        assert_eq!(r.u64().unwrap(), 0x1_0000_0002);
+        assert_eq!(r.u32().unwrap(), 1);
+        assert!(r.opaque(4).is_err());
+        let mut r = XdrReader::new(&w.buf[16..]);
+        assert_eq!(r.opaque(16).unwrap(), b"hello");
+        assert_eq!(r.opaque(16).unwrap(), b"");
+        assert!(r.u32().is_err());
+
+        let mut r = XdrReader::new(&[0, 0, 0, 8, 1, 2, 3, 4]);
+        assert!(r.opaque(16).is_err());
+    }
+
+    #[test]
+    fn test_rpc_record() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&3u32.to_be_bytes());
+        buf.extend_from_slice(b"abc");
+        write_record(&mut buf, b"defg").unwrap();
+        write_record(&mut buf, b"").unwrap();
+
+        let mut r = buf.as_slice();
+        assert_eq!(read_record(&mut r).unwrap().unwrap(), b"abcdefg");
+        assert_eq!(read_record(&mut r).unwrap().unwrap(), b"");
+        assert!(read_record(&mut r).unwrap().is_none());
+
+        let header = (RPC_MAX_RECORD_SIZE as u32 + 1) | RPC_LAST_FRAGMENT;
+        let mut r: &[u8] = &header.to_be_bytes();
+        assert!(read_record(&mut r).is_err());
+        let mut r: &[u8] = &[0x80, 0, 0, 8, 1];
+        assert!(read_record(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_rpc_dispatch() {
+        let server = create_server();
+
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_NULL, &[]))
+            .unwrap();
+        assert_eq!(parse_reply(&reply), (ACCEPT_SUCCESS, vec![]));
+        let reply = server.handle_call(&rpc_call(100000, 2, 0, &[])).unwrap();
+        assert_eq!(parse_reply(&reply).0, ACCEPT_PROG_UNAVAIL);
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, 4, NFSPROC3_NULL, &[]))
+            .unwrap();
+        assert_eq!(parse_reply(&reply).0, ACCEPT_PROG_MISMATCH);
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, 22, &[]))
+            .unwrap();
+        assert_eq!(parse_reply(&reply).0, ACCEPT_PROC_UNAVAIL);
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_GETATTR, &[]))
+            .unwrap();
+        assert_eq!(parse_reply(&reply).0, ACCEPT_GARBAGE_ARGS);
+
+        let mut call = rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_NULL, &[]);
+        call[8..12].copy_from_slice(&3u32.to_be_bytes());
+        let reply = server.handle_call(&call).unwrap();
+        assert_eq!(&reply[8..12], &MSG_DENIED.to_be_bytes());
+        call[4..8].copy_from_slice(&RPC_REPLY.to_be_bytes());
+        assert!(server.handle_call(&call).is_err());
+    }
+
+    #[test]
+    fn test_nfs_without_filesystem() {
+        let server = create_server();
+
+        let mut args = XdrWriter::default();
+        args.opaque(&[0u8; 4]);
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_GETATTR, &args.buf))
+            .unwrap();
+        assert_eq!(
+            parse_reply(&reply),
+            (ACCEPT_SUCCESS, NFS3ERR_BADHANDLE.to_be_bytes().to_vec())
+        );
+
+        let mut args = XdrWriter::default();
+        args.opaque(&1u64.to_be_bytes());
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_GETATTR, &args.buf))
+            .unwrap();
+        assert_eq!(parse_reply(&reply).1, NFS3ERR_STALE.to_be_bytes().to_vec());
+
+        let reply = server
+            .handle_call(&rpc_call(NFS_PROGRAM, NFS_V3, NFSPROC3_WRITE, &[]))
+            .unwrap();
+        let (status, result) = parse_reply(&reply);
+        assert_eq!(status, ACCEPT_SUCCESS);
+        assert_eq!(&result[..4], &NFS3ERR_ROFS.to_be_bytes());
+        assert_eq!(result.len(), 12);
+
+        let mut args = XdrWriter::default();
+        args.opaque(b"/");
+        let reply = server
+            .handle_call(&rpc_call(
+                MOUNT_PROGRAM,
+                MOUNT_V3,
+                MOUNTPROC3_MNT,
+                &args.buf,
+            ))
+            .unwrap();
+        assert_eq!(
+            parse_reply(&reply).1,
+            MNT3ERR_SERVERFAULT.to_be_bytes().to_vec()
+        );
+        let mut args = XdrWriter::default();
+        args.opaque(b"/other");
+        let reply = server
+            .handle_call(&rpc_call(
+                MOUNT_PROGRAM,
+                MOUNT_V3,
+                MOUNTPROC3_MNT,
+                &args.buf,
+            ))
+            .unwrap();
+        assert_eq!(parse_reply(&reply).1, MNT3ERR_NOENT.to_be_bytes().to_vec());
+    }
+}
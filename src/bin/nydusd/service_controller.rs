@@ -9,15 +9,18 @@ use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use nydus_api::http::BlobCacheList;
 use nydus_app::BuildTimeInfo;
 
 use crate::blob_cache::BlobCacheMgr;
+use crate::config::ConfigV2;
 use crate::daemon::{
     DaemonError, DaemonResult, DaemonState, DaemonStateMachineContext, DaemonStateMachineInput,
     DaemonStateMachineSubscriber,
 };
+use crate::upgrade::{UpgradeManager, UpgradeMgrError};
 use crate::{FsService, NydusDaemon, SubCmdArgs, DAEMON_CONTROLLER};
 #[cfg(target_os = "linux")]
 use nydus::ensure_threads;
@@ -45,18 +48,16 @@ impl ServiceController {
         #[cfg(target_os = "linux")]
         if self.fscache_enabled.load(Ordering::Acquire) {
             if let Some(fscache) = self.fscache.lock().unwrap().clone() {
-                for _ in 0..fscache.working_threads() {
-                    let fscache2 = fscache.clone();
-                    std::thread::spawn(move || {
-                        if let Err(e) = fscache2.run_loop() {
-                            error!("Failed to run fscache service loop, {}", e);
-                        }
+                fscache.start(|| {
+                    let worker = DAEMON_CONTROLLER.register_worker();
+                    move || {
                         // Notify the global service controller that one working thread is exiting.
                         if let Err(e) = crate::DAEMON_CONTROLLER.waker.wake() {
                             error!("Failed to notify the global service controller, {}", e);
                         }
-                    });
-                }
+                        drop(worker);
+                    }
+                })?;
             }
         }
 
@@ -75,19 +76,23 @@ impl ServiceController {
         }
     }
 
-    fn initialize_blob_cache(&self, config: &Option<serde_json::Value>) -> Result<()> {
+    /// Wait until all working threads have exited, return false on timeout.
+    pub fn wait_with_timeout(&self, dur: Duration) -> DaemonResult<bool> {
+        Ok(DAEMON_CONTROLLER.wait_workers(Some(dur)))
+    }
+
+    fn initialize_blob_cache(&self, config: Option<ConfigV2>) -> Result<()> {
         DAEMON_CONTROLLER.set_blob_cache_mgr(self.blob_cache_mgr.clone());
 
         // Create blob cache objects configured by the configuration file.
         if let Some(config) = config {
-            if let Some(config1) = config.as_object() {
-                if config1.contains_key("blobs") {
-                    if let Ok(v) = serde_json::from_value::<BlobCacheList>(config.clone()) {
-                        if let Err(e) = self.blob_cache_mgr.add_blob_list(&v) {
-                            error!("Failed to add blob list: {}", e);
-                            return Err(e);
-                        }
-                    }
+            if !config.blobs.is_empty() {
+                let list = BlobCacheList {
+                    blobs: config.blobs,
+                };
+                if let Err(e) = self.blob_cache_mgr.add_blob_list(&list) {
+                    error!("Failed to add blob list: {}", e);
+                    return Err(e);
                 }
             }
         }
@@ -179,7 +184,10 @@ impl NydusDaemon for ServiceController {
         Ok(())
     }
 
+    /// Block until all working threads, such as fscache service loops, blob prefetch threads and
+    /// the HTTP server, have exited.
     fn wait(&self) -> DaemonResult<()> {
+        DAEMON_CONTROLLER.wait_workers(None);
         Ok(())
     }
 
@@ -187,12 +195,37 @@ impl NydusDaemon for ServiceController {
         self.supervisor.clone()
     }
 
+    /// Save blob cache entries to the supervisor.
+    ///
+    /// State of the fscache service can't be handed over yet, so it's unsupported to save state
+    /// when the fscache service is enabled.
     fn save(&self) -> DaemonResult<()> {
-        Err(DaemonError::Unsupported)
+        let supervisor = self.supervisor.as_ref().ok_or(DaemonError::Unsupported)?;
+        if self.fscache_enabled.load(Ordering::Acquire) {
+            return Err(DaemonError::Unsupported);
+        }
+
+        let state = self
+            .blob_cache_mgr
+            .export_entries()
+            .map_err(|e| DaemonError::Common(e.to_string()))?;
+        UpgradeManager::new(supervisor.into()).save(state, &[])?;
+        info!("saved blob cache entries to supervisor");
+
+        Ok(())
     }
 
     fn restore(&self) -> DaemonResult<()> {
-        Err(DaemonError::Unsupported)
+        let supervisor = self.supervisor.as_ref().ok_or(DaemonError::Unsupported)?;
+        let mut channel = UpgradeManager::new(supervisor.into()).connect()?;
+        let (state, _) = channel.restore(0).map_err(UpgradeMgrError::Supervisor)?;
+        self.blob_cache_mgr
+            .import_entries(&state)
+            .map_err(|e| UpgradeMgrError::InvalidState(e.to_string()))?;
+        channel.confirm().map_err(UpgradeMgrError::Supervisor)?;
+        info!("restored blob cache entries from supervisor");
+
+        Ok(())
     }
 
     fn get_default_fs_service(&self) -> Option<Arc<dyn FsService>> {
@@ -220,12 +253,7 @@ pub fn create_daemon(subargs: &SubCmdArgs, bti: BuildTimeInfo) -> Result<Arc<dyn
     let supervisor = subargs.value_of("supervisor").map(|s| s.to_string());
     let config = match subargs.value_of("config") {
         None => None,
-        Some(path) => {
-            let config = std::fs::read_to_string(path)?;
-            let config: serde_json::Value = serde_json::from_str(&config)
-                .map_err(|_e| einval!("invalid configuration file"))?;
-            Some(config)
-        }
+        Some(path) => Some(ConfigV2::load(path)?),
     };
 
     let (to_sm, from_client) = channel::<DaemonStateMachineInput>();
@@ -245,7 +273,9 @@ pub fn create_daemon(subargs: &SubCmdArgs, bti: BuildTimeInfo) -> Result<Arc<dyn
         fscache: Mutex::new(None),
     };
 
-    service_controller.initialize_blob_cache(&config)?;
+    // In upgrade mode, blob cache entries are restored from the supervisor on takeover.
+    let upgrade = subargs.is_present("upgrade");
+    service_controller.initialize_blob_cache(if upgrade { None } else { config })?;
     #[cfg(target_os = "linux")]
     if let Some(path) = subargs.value_of("fscache") {
         service_controller.initialize_fscache_service(subargs, path)?;
@@ -254,12 +284,15 @@ pub fn create_daemon(subargs: &SubCmdArgs, bti: BuildTimeInfo) -> Result<Arc<dyn
     let daemon = Arc::new(service_controller);
     let machine = DaemonStateMachineContext::new(daemon.clone(), from_client, to_client);
     machine.kick_state_machine()?;
-    daemon
-        .on_event(DaemonStateMachineInput::Mount)
-        .map_err(|e| eother!(e))?;
-    daemon
-        .on_event(DaemonStateMachineInput::Start)
-        .map_err(|e| eother!(e))?;
+    // In upgrade mode, wait for the supervisor to trigger takeover and start by API.
+    if !upgrade {
+        daemon
+            .on_event(DaemonStateMachineInput::Mount)
+            .map_err(|e| eother!(e))?;
+        daemon
+            .on_event(DaemonStateMachineInput::Start)
+            .map_err(|e| eother!(e))?;
+    }
 
     Ok(daemon)
 }
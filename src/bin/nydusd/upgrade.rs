@@ -1,17 +1,95 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Save and restore daemon state through the supervisor for live upgrade and failover.
+//!
+//! The wire protocol is implemented by [nydus::upgrade], this module decides what to save.
+
+use std::any::Any;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::io;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
-use crate::daemon::DaemonResult;
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Entry, FileSystem};
+use fuse_backend_rs::api::{BackendFileSystem, Vfs};
+use nydus::upgrade::UpgradeChannel;
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::{DaemonError, DaemonResult};
 use crate::fs_service::FsBackendUmountCmd;
 use crate::FsBackendMountCmd;
 
 #[derive(Debug)]
-pub enum UpgradeMgrError {}
-pub struct UpgradeManager {}
+pub enum UpgradeMgrError {
+    /// Failed to communicate with the supervisor.
+    Supervisor(io::Error),
+    /// Failed to serialize/deserialize daemon state.
+    Serde(serde_json::Error),
+    /// Daemon state received from the supervisor is invalid.
+    InvalidState(String),
+}
+
+impl Display for UpgradeMgrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Supervisor(e) => write!(f, "failed to communicate with supervisor, {}", e),
+            Self::Serde(e) => write!(f, "failed to serialize/deserialize daemon state, {}", e),
+            Self::InvalidState(s) => write!(f, "invalid daemon state, {}", s),
+        }
+    }
+}
+
+impl From<UpgradeMgrError> for DaemonError {
+    fn from(e: UpgradeMgrError) -> Self {
+        error!("upgrade manager: {}", e);
+        DaemonError::UpgradeManager(e)
+    }
+}
+
+/// State of a mounted filesystem, used to mount it again at the same vfs index after upgrade.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MountState {
+    pub cmd: FsBackendMountCmd,
+    pub vfs_index: u8,
+}
+
+/// Manager to save and restore daemon state through the supervisor.
+pub struct UpgradeManager {
+    supervisor: PathBuf,
+    mounts: HashMap<String, MountState>,
+}
 
 impl UpgradeManager {
-    pub fn new(_: PathBuf) -> Self {
-        UpgradeManager {}
+    pub fn new(supervisor: PathBuf) -> Self {
+        UpgradeManager {
+            supervisor,
+            mounts: HashMap::new(),
+        }
+    }
+
+    /// Get states of mounted filesystems, sorted by vfs index.
+    pub fn mounts(&self) -> Vec<MountState> {
+        let mut mounts = self.mounts.values().cloned().collect::<Vec<_>>();
+        mounts.sort_by_key(|m| m.vfs_index);
+        mounts
+    }
+
+    /// Connect to the supervisor.
+    pub fn connect(&self) -> DaemonResult<UpgradeChannel> {
+        UpgradeChannel::connect(&self.supervisor).map_err(|e| UpgradeMgrError::Supervisor(e).into())
+    }
+
+    /// Save daemon `state` and file descriptors `fds` to the supervisor.
+    pub fn save(&self, state: Vec<u8>, fds: &[RawFd]) -> DaemonResult<()> {
+        let mut channel = self.connect()?;
+        channel
+            .save(state, fds)
+            .map_err(|e| UpgradeMgrError::Supervisor(e).into())
     }
 }
 
@@ -46,32 +124,216 @@ impl TryFrom<&String> for FailoverPolicy {
 }
 
 pub fn add_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendMountCmd,
-    _vfs_index: u8,
+    mgr: &mut UpgradeManager,
+    cmd: FsBackendMountCmd,
+    vfs_index: u8,
 ) -> DaemonResult<()> {
+    mgr.mounts
+        .insert(cmd.mountpoint.clone(), MountState { cmd, vfs_index });
     Ok(())
 }
 
-pub fn update_mounts_state(_mgr: &mut UpgradeManager, _cmd: FsBackendMountCmd) -> DaemonResult<()> {
+pub fn update_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendMountCmd) -> DaemonResult<()> {
+    match mgr.mounts.get_mut(&cmd.mountpoint) {
+        Some(state) => {
+            state.cmd = cmd;
+            Ok(())
+        }
+        None => Err(DaemonError::NotFound),
+    }
+}
+
+pub fn remove_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendUmountCmd) -> DaemonResult<()> {
+    mgr.mounts.remove(&cmd.mountpoint);
     Ok(())
 }
 
-pub fn remove_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendUmountCmd,
-) -> DaemonResult<()> {
+// The vfs reserves index 0 for its pseudo filesystem, so mounted filesystems start from index 1.
+const FIRST_VFS_INDEX: u8 = 1;
+
+// Filesystem mounted temporarily to skip a vfs index when restoring mounts.
+struct PlaceholderFs;
+
+impl FileSystem for PlaceholderFs {
+    type Inode = u64;
+    type Handle = u64;
+}
+
+impl BackendFileSystem for PlaceholderFs {
+    fn mount(&self) -> io::Result<(Entry, u64)> {
+        let entry = Entry {
+            inode: ROOT_ID,
+            ..Default::default()
+        };
+        Ok((entry, ROOT_ID))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Mount filesystems saved by the previous daemon at their original vfs indexes by `restore`.
+///
+/// The vfs of a new daemon allocates indexes in increasing order, so filesystems are restored in
+/// the order of their vfs indexes. Indexes of filesystems umounted before upgrading are skipped
+/// by mounting and umounting a placeholder filesystem at the next mountpoint to restore.
+pub fn restore_mounts<F>(vfs: &Vfs, mut mounts: Vec<MountState>, mut restore: F) -> DaemonResult<()>
+where
+    F: FnMut(MountState) -> DaemonResult<()>,
+{
+    mounts.sort_by_key(|mount| mount.vfs_index);
+    let mut next_index = FIRST_VFS_INDEX;
+    for mount in mounts {
+        while next_index < mount.vfs_index {
+            let index = vfs.mount(Box::new(PlaceholderFs), &mount.cmd.mountpoint)?;
+            vfs.umount(&mount.cmd.mountpoint)?;
+            next_index = index + 1;
+        }
+        next_index = mount.vfs_index + 1;
+        restore(mount)?;
+    }
+
     Ok(())
 }
 
 pub mod fusedev_upgrade {
-    use crate::daemon::DaemonResult;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::Ordering;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{MountState, UpgradeMgrError};
+    use crate::daemon::{DaemonError, DaemonResult};
+    use crate::fs_service::FsService;
     use crate::fusedev::FusedevDaemon;
-    pub fn save(_daemon: &FusedevDaemon) -> DaemonResult<()> {
+
+    /// State of the fuse service saved to the supervisor, the `/dev/fuse` fd is passed along.
+    #[derive(Deserialize, Serialize)]
+    struct FusedevState {
+        mounts: Vec<MountState>,
+        fuse_conn: u64,
+    }
+
+    pub fn save(daemon: &FusedevDaemon) -> DaemonResult<()> {
+        let svc = &daemon.service;
+        let mgr = svc.upgrade_mgr().ok_or(DaemonError::Unsupported)?;
+        let state = FusedevState {
+            mounts: mgr.mounts(),
+            fuse_conn: svc.conn.load(Ordering::Acquire),
+        };
+        let state = serde_json::to_vec(&state).map_err(UpgradeMgrError::Serde)?;
+        let mut session = svc.session.lock().unwrap();
+        let file = session
+            .get_fuse_file()
+            .ok_or_else(|| DaemonError::Common("fuse session is not mounted".to_string()))?;
+
+        mgr.save(state, &[file.as_raw_fd()])?;
+        info!("saved fuse service state to supervisor");
+
         Ok(())
     }
 
-    pub fn restore(_daemon: &FusedevDaemon) -> DaemonResult<()> {
+    pub fn restore(daemon: &FusedevDaemon) -> DaemonResult<()> {
+        let svc = &daemon.service;
+        // Release the lock, restoring mounts will add their states to the upgrade manager.
+        let mut channel = svc
+            .upgrade_mgr()
+            .ok_or(DaemonError::Unsupported)?
+            .connect()?;
+        let (state, mut files) = channel.restore(1).map_err(UpgradeMgrError::Supervisor)?;
+        let state: FusedevState = serde_json::from_slice(&state).map_err(UpgradeMgrError::Serde)?;
+        let file = files
+            .pop()
+            .ok_or_else(|| UpgradeMgrError::InvalidState("no fuse fd received".to_string()))?;
+
+        super::restore_mounts(svc.get_vfs(), state.mounts, |mount| {
+            svc.restore_mount(mount.cmd, mount.vfs_index)
+        })?;
+        svc.session.lock().unwrap().set_fuse_file(file);
+        svc.conn.store(state.fuse_conn, Ordering::Release);
+        channel.confirm().map_err(UpgradeMgrError::Supervisor)?;
+        info!("restored fuse service state from supervisor");
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus::FsBackendType;
+
+    fn mount_cmd(mountpoint: &str, config: &str) -> FsBackendMountCmd {
+        FsBackendMountCmd {
+            fs_type: FsBackendType::Rafs,
+            source: "/tmp/bootstrap".to_string(),
+            bootstrap_blob_id: None,
+            config: config.to_string(),
+            mountpoint: mountpoint.to_string(),
+            prefetch_files: None,
+        }
+    }
+
+    #[test]
+    fn test_upgrade_manager_mounts_state() {
+        let mut mgr = UpgradeManager::new(PathBuf::from("/tmp/supervisor.sock"));
+
+        add_mounts_state(&mut mgr, mount_cmd("/sub2", "{}"), 2).unwrap();
+        add_mounts_state(&mut mgr, mount_cmd("/sub1", "{}"), 1).unwrap();
+        update_mounts_state(&mut mgr, mount_cmd("/sub2", "{\"a\":1}")).unwrap();
+        update_mounts_state(&mut mgr, mount_cmd("/sub3", "{}")).unwrap_err();
+
+        let mounts = mgr.mounts();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].cmd.mountpoint, "/sub1");
+        assert_eq!(mounts[1].cmd.mountpoint, "/sub2");
+        assert_eq!(mounts[1].cmd.config, "{\"a\":1}");
+        assert_eq!(mounts[1].vfs_index, 2);
+
+        let cmd = FsBackendUmountCmd {
+            mountpoint: "/sub1".to_string(),
+        };
+        remove_mounts_state(&mut mgr, cmd).unwrap();
+        assert_eq!(mgr.mounts().len(), 1);
+
+        assert!(mgr.save(Vec::new(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_restore_mounts_with_holes() {
+        let vfs = Vfs::default();
+        // Filesystems at vfs index 2, 4 and 5 were umounted before upgrading.
+        let mounts = vec![
+            MountState {
+                cmd: mount_cmd("/sub6", "{}"),
+                vfs_index: 6,
+            },
+            MountState {
+                cmd: mount_cmd("/sub1", "{}"),
+                vfs_index: 1,
+            },
+            MountState {
+                cmd: mount_cmd("/sub3", "{}"),
+                vfs_index: 3,
+            },
+        ];
+
+        let mut restored = Vec::new();
+        restore_mounts(&vfs, mounts, |mount| {
+            let index = vfs.mount(Box::new(PlaceholderFs), &mount.cmd.mountpoint)?;
+            assert_eq!(index, mount.vfs_index);
+            restored.push(mount.vfs_index);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(restored, vec![1, 3, 6]);
+        for path in ["/sub1", "/sub3", "/sub6"] {
+            assert!(vfs.get_rootfs(path).unwrap().is_some());
+        }
+
+        // A filesystem mounted after restoring gets a new index.
+        let index = vfs.mount(Box::new(PlaceholderFs), "/sub7").unwrap();
+        assert_eq!(index, 7);
+    }
+}
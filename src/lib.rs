@@ -9,6 +9,8 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+pub mod upgrade;
+
 /// Error code related to Nydus library.
 #[derive(Debug)]
 pub enum NydusError {
@@ -57,6 +59,9 @@ pub struct FsBackendDesc {
     pub mountpoint: String,
     pub mounted_time: time::OffsetDateTime,
     pub config: Option<serde_json::Value>,
+    /// Index of the filesystem in the virtual filesystem, to convert inode numbers.
+    #[serde(skip)]
+    pub vfs_index: Option<u8>,
 }
 
 pub fn ensure_threads<V: AsRef<str>>(v: V) -> std::result::Result<usize, String> {
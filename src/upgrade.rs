@@ -0,0 +1,481 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Protocol to hand over daemon state and file descriptors through a supervisor.
+//!
+//! To live upgrade or fail over a nydusd instance, the old daemon saves its state and file
+//! descriptors to a supervisor through the unix domain socket specified by `--supervisor`, and
+//! the new daemon started with `--upgrade` fetches them back from the supervisor:
+//! - the old daemon connects to the supervisor, sends [UpgradeMessage::SaveState] and
+//!   [UpgradeMessage::Fds], then waits for [UpgradeMessage::Confirm].
+//! - the new daemon connects to the supervisor, receives [UpgradeMessage::SaveState] and
+//!   [UpgradeMessage::Fds], restores the state and then sends [UpgradeMessage::Confirm].
+//!
+//! Each message starts with a header containing a magic number, the protocol version, the
+//! message type and the payload size, all encoded in little endian. File descriptors are passed
+//! as `SCM_RIGHTS` ancillary data of the `Fds` message. Messages of all protocol versions in
+//! range [UPGRADE_PROTOCOL_MIN_VERSION, UPGRADE_PROTOCOL_VERSION] are accepted, and the channel
+//! replies with the lowest version seen from the peer, so daemons of different versions may
+//! negotiate a common version through the supervisor.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Magic number of upgrade protocol messages, "NYUP".
+pub const UPGRADE_MAGIC: u32 = 0x4e59_5550;
+/// Current version of the upgrade protocol.
+pub const UPGRADE_PROTOCOL_VERSION: u16 = 1;
+/// Minimum version of the upgrade protocol supported.
+pub const UPGRADE_PROTOCOL_MIN_VERSION: u16 = 1;
+/// Maximum number of file descriptors carried by a `Fds` message.
+pub const MAX_UPGRADE_FDS: usize = 16;
+/// Maximum size of message payload.
+pub const MAX_UPGRADE_PAYLOAD: usize = 64 << 20;
+
+const HEADER_SIZE: usize = 12;
+const MSG_SAVE_STATE: u16 = 1;
+const MSG_FDS: u16 = 2;
+const MSG_CONFIRM: u16 = 3;
+
+/// Messages of the upgrade protocol.
+#[derive(Debug, PartialEq)]
+pub enum UpgradeMessage {
+    /// Opaque state of the daemon, interpreted by the daemon only.
+    SaveState(Vec<u8>),
+    /// File descriptors to hand over, the receiver takes ownership of them.
+    Fds(Vec<RawFd>),
+    /// Acknowledge that the state has been saved or restored.
+    Confirm,
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn close_fds(fds: &[RawFd]) {
+    for fd in fds {
+        // Safe because we own the received file descriptors.
+        drop(unsafe { File::from_raw_fd(*fd) });
+    }
+}
+
+/// A connection between a daemon and the supervisor to transfer upgrade protocol messages.
+pub struct UpgradeChannel {
+    stream: UnixStream,
+    version: u16,
+}
+
+impl UpgradeChannel {
+    /// Create an upgrade channel from a connected unix domain socket.
+    pub fn new(stream: UnixStream) -> Self {
+        UpgradeChannel {
+            stream,
+            version: UPGRADE_PROTOCOL_VERSION,
+        }
+    }
+
+    /// Connect to the supervisor listening on `path`.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        UnixStream::connect(path).map(Self::new)
+    }
+
+    /// Get the protocol version used to send messages.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Send a message to the peer.
+    pub fn send(&mut self, msg: &UpgradeMessage) -> Result<()> {
+        let (kind, payload, fds) = match msg {
+            UpgradeMessage::SaveState(state) => (MSG_SAVE_STATE, state.clone(), &[][..]),
+            UpgradeMessage::Fds(fds) => {
+                if fds.is_empty() || fds.len() > MAX_UPGRADE_FDS {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid number of file descriptors {}", fds.len()),
+                    ));
+                }
+                let count = (fds.len() as u32).to_le_bytes().to_vec();
+                (MSG_FDS, count, fds.as_slice())
+            }
+            UpgradeMessage::Confirm => (MSG_CONFIRM, Vec::new(), &[][..]),
+        };
+        if payload.len() > MAX_UPGRADE_PAYLOAD {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("message payload is too big, {} bytes", payload.len()),
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len());
+        buf.extend_from_slice(&UPGRADE_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&payload);
+
+        let sent = if fds.is_empty() {
+            0
+        } else {
+            self.send_with_fds(&buf, fds)?
+        };
+        self.stream.write_all(&buf[sent..])
+    }
+
+    /// Receive a message from the peer.
+    pub fn recv(&mut self) -> Result<UpgradeMessage> {
+        let mut header = [0u8; HEADER_SIZE];
+        let (size, fds) = self.recv_with_fds(&mut header)?;
+        if size == 0 {
+            close_fds(&fds);
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "upgrade channel closed by peer",
+            ));
+        }
+        if let Err(e) = self.stream.read_exact(&mut header[size..]) {
+            close_fds(&fds);
+            return Err(e);
+        }
+
+        match self.parse_message(&header, &fds) {
+            Ok(msg) => Ok(msg),
+            Err(e) => {
+                close_fds(&fds);
+                Err(e)
+            }
+        }
+    }
+
+    /// Save daemon `state` and file descriptors `fds` to the supervisor.
+    pub fn save(&mut self, state: Vec<u8>, fds: &[RawFd]) -> Result<()> {
+        self.send(&UpgradeMessage::SaveState(state))?;
+        if !fds.is_empty() {
+            self.send(&UpgradeMessage::Fds(fds.to_vec()))?;
+        }
+        match self.recv()? {
+            UpgradeMessage::Confirm => Ok(()),
+            msg => Err(invalid_data(format!(
+                "unexpected message {:?}, expect confirmation",
+                msg
+            ))),
+        }
+    }
+
+    /// Fetch daemon state and `fd_count` file descriptors from the supervisor.
+    ///
+    /// The caller should send [UpgradeMessage::Confirm] by [UpgradeChannel::confirm()] after
+    /// successfully restoring the state.
+    pub fn restore(&mut self, fd_count: usize) -> Result<(Vec<u8>, Vec<File>)> {
+        let state = match self.recv()? {
+            UpgradeMessage::SaveState(state) => state,
+            msg => {
+                if let UpgradeMessage::Fds(fds) = &msg {
+                    close_fds(fds);
+                }
+                return Err(invalid_data(format!(
+                    "unexpected message {:?}, expect daemon state",
+                    msg
+                )));
+            }
+        };
+        if fd_count == 0 {
+            return Ok((state, Vec::new()));
+        }
+
+        match self.recv()? {
+            UpgradeMessage::Fds(fds) => {
+                // Safe because we own the received file descriptors.
+                let files: Vec<File> = fds
+                    .into_iter()
+                    .map(|fd| unsafe { File::from_raw_fd(fd) })
+                    .collect();
+                if files.len() != fd_count {
+                    return Err(invalid_data(format!(
+                        "expect {} file descriptors, got {}",
+                        fd_count,
+                        files.len()
+                    )));
+                }
+                Ok((state, files))
+            }
+            msg => Err(invalid_data(format!(
+                "unexpected message {:?}, expect file descriptors",
+                msg
+            ))),
+        }
+    }
+
+    /// Acknowledge the peer that the state has been saved or restored.
+    pub fn confirm(&mut self) -> Result<()> {
+        self.send(&UpgradeMessage::Confirm)
+    }
+
+    fn parse_message(
+        &mut self,
+        header: &[u8; HEADER_SIZE],
+        fds: &[RawFd],
+    ) -> Result<UpgradeMessage> {
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        let kind = u16::from_le_bytes([header[6], header[7]]);
+        let size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if magic != UPGRADE_MAGIC {
+            return Err(invalid_data(format!(
+                "invalid upgrade message magic 0x{:x}",
+                magic
+            )));
+        }
+        if !(UPGRADE_PROTOCOL_MIN_VERSION..=UPGRADE_PROTOCOL_VERSION).contains(&version) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "unsupported upgrade protocol version {}, supported versions {}-{}",
+                    version, UPGRADE_PROTOCOL_MIN_VERSION, UPGRADE_PROTOCOL_VERSION
+                ),
+            ));
+        }
+        if size > MAX_UPGRADE_PAYLOAD {
+            return Err(invalid_data(format!(
+                "upgrade message payload is too big, {} bytes",
+                size
+            )));
+        }
+        let mut payload = vec![0u8; size];
+        self.stream.read_exact(&mut payload)?;
+        if kind != MSG_FDS && !fds.is_empty() {
+            return Err(invalid_data(format!(
+                "unexpected file descriptors with message type {}",
+                kind
+            )));
+        }
+
+        let msg = match kind {
+            MSG_SAVE_STATE => UpgradeMessage::SaveState(payload),
+            MSG_FDS => {
+                if payload.len() != size_of::<u32>() {
+                    return Err(invalid_data("invalid file descriptors message".to_string()));
+                }
+                let count = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                if count as usize != fds.len() {
+                    return Err(invalid_data(format!(
+                        "expect {} file descriptors, got {}",
+                        count,
+                        fds.len()
+                    )));
+                }
+                UpgradeMessage::Fds(fds.to_vec())
+            }
+            MSG_CONFIRM => UpgradeMessage::Confirm,
+            _ => {
+                return Err(invalid_data(format!(
+                    "unknown upgrade message type {}",
+                    kind
+                )))
+            }
+        };
+        // Reply with the lowest version supported by both sides.
+        if version < self.version {
+            self.version = version;
+        }
+
+        Ok(msg)
+    }
+
+    fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> Result<usize> {
+        let fds_size = std::mem::size_of_val(fds);
+        // Safe because it only calculates buffer size.
+        let cmsg_space = unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize;
+        let mut cmsg_buf = vec![0u64; (cmsg_space + 7) / 8];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // Safe because all fields of msghdr are initialized below.
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+
+        // Safe because the control message buffer is big enough to hold the file descriptors.
+        let ret = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+            std::ptr::copy_nonoverlapping(
+                fds.as_ptr() as *const u8,
+                libc::CMSG_DATA(cmsg),
+                fds_size,
+            );
+            libc::sendmsg(self.stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL)
+        };
+        if ret < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    fn recv_with_fds(&self, buf: &mut [u8]) -> Result<(usize, Vec<RawFd>)> {
+        let fds_size = MAX_UPGRADE_FDS * size_of::<RawFd>();
+        // Safe because it only calculates buffer size.
+        let cmsg_space = unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize;
+        let mut cmsg_buf = vec![0u64; (cmsg_space + 7) / 8];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // Safe because all fields of msghdr are initialized below.
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+
+        let ret = loop {
+            // Safe because buffers are valid and big enough.
+            let ret =
+                unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+            if ret < 0 {
+                let e = Error::last_os_error();
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            break ret as usize;
+        };
+
+        let mut fds = Vec::new();
+        // Safe because the control messages are filled by the kernel.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg);
+                    let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                    for idx in 0..len / size_of::<RawFd>() {
+                        let fd = std::ptr::read_unaligned((data as *const RawFd).add(idx));
+                        fds.push(fd);
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            close_fds(&fds);
+            return Err(invalid_data(
+                "too many file descriptors received".to_string(),
+            ));
+        }
+
+        Ok((ret, fds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_upgrade_channel_save_restore() {
+        let (daemon, supervisor) = UnixStream::pair().unwrap();
+        let mut daemon = UpgradeChannel::new(daemon);
+        let mut supervisor = UpgradeChannel::new(supervisor);
+        let tmpfile = TempFile::new().unwrap();
+        let file = tmpfile.as_file();
+
+        let fd = file.as_raw_fd();
+        let handle = std::thread::spawn(move || daemon.save(b"state".to_vec(), &[fd]));
+        assert_eq!(
+            supervisor.recv().unwrap(),
+            UpgradeMessage::SaveState(b"state".to_vec())
+        );
+        let fds = match supervisor.recv().unwrap() {
+            UpgradeMessage::Fds(fds) => fds,
+            msg => panic!("unexpected message {:?}", msg),
+        };
+        assert_eq!(fds.len(), 1);
+        supervisor.confirm().unwrap();
+        handle.join().unwrap().unwrap();
+
+        // Hand over the state and file descriptor to the new daemon.
+        let (daemon, supervisor_stream) = UnixStream::pair().unwrap();
+        let mut daemon = UpgradeChannel::new(daemon);
+        let mut supervisor = UpgradeChannel::new(supervisor_stream);
+        let handle = std::thread::spawn(move || {
+            let (state, files) = daemon.restore(1).unwrap();
+            daemon.confirm().unwrap();
+            (state, files)
+        });
+        supervisor
+            .send(&UpgradeMessage::SaveState(b"state".to_vec()))
+            .unwrap();
+        supervisor.send(&UpgradeMessage::Fds(fds.clone())).unwrap();
+        assert_eq!(supervisor.recv().unwrap(), UpgradeMessage::Confirm);
+        close_fds(&fds);
+
+        let (state, mut files) = handle.join().unwrap();
+        assert_eq!(state, b"state");
+        assert_eq!(files.len(), 1);
+        files[0].write_all(b"data").unwrap();
+        let mut file = file.try_clone().unwrap();
+        file.rewind().unwrap();
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "data");
+    }
+
+    #[test]
+    fn test_upgrade_channel_invalid_message() {
+        let (peer, stream) = UnixStream::pair().unwrap();
+        let mut peer = peer;
+        let mut channel = UpgradeChannel::new(stream);
+
+        // Invalid magic.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x1234_5678u32.to_le_bytes());
+        buf.extend_from_slice(&UPGRADE_PROTOCOL_VERSION.to_le_bytes());
+        buf.extend_from_slice(&MSG_CONFIRM.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        peer.write_all(&buf).unwrap();
+        assert_eq!(channel.recv().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Unsupported protocol version.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&UPGRADE_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&(UPGRADE_PROTOCOL_VERSION + 1).to_le_bytes());
+        buf.extend_from_slice(&MSG_CONFIRM.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        peer.write_all(&buf).unwrap();
+        assert_eq!(channel.recv().unwrap_err().kind(), ErrorKind::Unsupported);
+
+        // Unknown message type.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&UPGRADE_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&UPGRADE_PROTOCOL_VERSION.to_le_bytes());
+        buf.extend_from_slice(&100u16.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        peer.write_all(&buf).unwrap();
+        assert_eq!(channel.recv().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Unexpected message when restoring.
+        let mut sender = UpgradeChannel::new(peer.try_clone().unwrap());
+        sender.confirm().unwrap();
+        assert!(channel.restore(1).is_err());
+        assert!(channel.send(&UpgradeMessage::Fds(Vec::new())).is_err());
+
+        drop(sender);
+        drop(peer);
+        assert_eq!(channel.recv().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -7,10 +7,10 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io::{Read, Result};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{cmp, thread};
 
 use log::{max_level, Level};
 
@@ -23,12 +23,16 @@ use reqwest::{
     Method, StatusCode, Url,
 };
 
-use nydus_api::http::{MirrorConfig, OssConfig, ProxyConfig, RegistryConfig};
+use nydus_api::http::{MirrorConfig, OssConfig, ProxyConfig, RegistryConfig, S3Config};
+use nydus_utils::audit::{self, EventCategory};
+use nydus_utils::metrics::BackendMetrics;
 use url::ParseError;
 
 const HEADER_AUTHORIZATION: &str = "Authorization";
 
 const RATE_LIMITED_LOG_TIME: u8 = 2;
+/// Maximum factor to back off the interval to fall back to the primary server.
+const PRIMARY_RETRY_MAX_FACTOR: u32 = 16;
 
 thread_local! {
     pub static LAST_FALLBACK_AT: RefCell<SystemTime> = RefCell::new(UNIX_EPOCH);
@@ -59,6 +63,8 @@ pub(crate) struct ConnectionConfig {
     pub timeout: u32,
     pub connect_timeout: u32,
     pub retry_limit: u8,
+    pub failover_mirrors: Vec<MirrorConfig>,
+    pub primary_retry_interval: u64,
 }
 
 impl Default for ConnectionConfig {
@@ -70,6 +76,8 @@ impl Default for ConnectionConfig {
             timeout: 5,
             connect_timeout: 5,
             retry_limit: 0,
+            failover_mirrors: Vec::<MirrorConfig>::new(),
+            primary_retry_interval: 60,
         }
     }
 }
@@ -83,6 +91,21 @@ impl From<OssConfig> for ConnectionConfig {
             timeout: c.timeout,
             connect_timeout: c.connect_timeout,
             retry_limit: c.retry_limit,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<S3Config> for ConnectionConfig {
+    fn from(c: S3Config) -> ConnectionConfig {
+        // Requests are signed for the S3 endpoint, so they can't be redirected to mirrors.
+        ConnectionConfig {
+            proxy: c.proxy,
+            skip_verify: c.skip_verify,
+            timeout: c.timeout,
+            connect_timeout: c.connect_timeout,
+            retry_limit: c.retry_limit,
+            ..Default::default()
         }
     }
 }
@@ -96,6 +119,8 @@ impl From<RegistryConfig> for ConnectionConfig {
             timeout: c.timeout,
             connect_timeout: c.connect_timeout,
             retry_limit: c.retry_limit,
+            failover_mirrors: c.failover_mirrors,
+            primary_retry_interval: c.primary_retry_interval,
         }
     }
 }
@@ -202,6 +227,77 @@ impl Proxy {
     }
 }
 
+/// Health status of the primary server when failover mirrors are configured.
+#[derive(Debug)]
+struct PrimaryHealth {
+    // Number of consecutive failures when requesting the primary server.
+    failures: AtomicU32,
+    // Timestamp in milliseconds since UNIX epoch to fall back to the primary server.
+    retry_at: AtomicU64,
+    retry_interval: Duration,
+}
+
+impl PrimaryHealth {
+    fn new(retry_interval: u64) -> Self {
+        PrimaryHealth {
+            failures: AtomicU32::new(0),
+            retry_at: AtomicU64::new(0),
+            retry_interval: Duration::from_secs(retry_interval),
+        }
+    }
+
+    /// Check whether requests should be sent to the primary server.
+    ///
+    /// After failing over to mirrors, the primary server will be tried again periodically.
+    fn ok(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) == 0
+            || now_millis() >= self.retry_at.load(Ordering::Relaxed)
+    }
+
+    /// Mark the primary server as healthy, return true if it was failed.
+    fn set_ok(&self) -> bool {
+        if self.failures.swap(0, Ordering::Relaxed) != 0 {
+            info!("Primary server recovered, stop failing over to mirrors");
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Mark the primary server as failed, return true if it was healthy.
+    fn set_failed(&self) -> bool {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed);
+        let max = self.retry_interval.saturating_mul(PRIMARY_RETRY_MAX_FACTOR);
+        let delay = jittered_backoff(self.retry_interval, failures, max);
+        self.retry_at
+            .store(now_millis() + delay.as_millis() as u64, Ordering::Relaxed);
+        failures == 0
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Calculate exponential backoff delay for the `attempt`th retry, with random jitter.
+///
+/// The delay is randomly picked from [delay / 2, delay], so concurrent clients won't retry
+/// at the same time.
+pub(crate) fn jittered_backoff(base: Duration, attempt: u32, max: Duration) -> Duration {
+    let delay = cmp::min(base.saturating_mul(1 << cmp::min(attempt, 16)), max);
+    let half = delay.as_micros() as u64 / 2;
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or_default();
+    let jitter = if half == 0 { 0 } else { seed % (half + 1) };
+
+    Duration::from_micros(half + jitter)
+}
+
 /// Check whether the HTTP status code is a success result.
 pub(crate) fn is_success_status(status: StatusCode) -> bool {
     status >= StatusCode::OK && status < StatusCode::BAD_REQUEST
@@ -224,6 +320,9 @@ pub(crate) struct Connection {
     proxy: Option<Arc<Proxy>>,
     pub mirrors: Vec<Arc<Mirror>>,
     pub shutdown: AtomicBool,
+    failover_mirrors: Vec<Arc<Mirror>>,
+    primary: PrimaryHealth,
+    metrics: Option<Arc<BackendMetrics>>,
 }
 
 #[derive(Debug)]
@@ -239,6 +338,28 @@ pub(crate) struct Mirror {
 }
 
 impl Mirror {
+    fn new(config: &MirrorConfig) -> Self {
+        Mirror {
+            config: config.clone(),
+            status: AtomicBool::from(true),
+            failed_times: AtomicU8::from(0),
+            failure_limit: config.failure_limit,
+        }
+    }
+
+    /// Record a failed request, the mirror will be disabled when reaching the failure limit.
+    fn record_failure(&self) {
+        self.failed_times.fetch_add(1, Ordering::Relaxed);
+
+        if self.failed_times.load(Ordering::Relaxed) >= self.failure_limit {
+            warn!(
+                "reach to failure limit {}, disable mirror: {:?}",
+                self.failure_limit, self
+            );
+            self.status.store(false, Ordering::Relaxed);
+        }
+    }
+
     /// Convert original URL to mirror URL.
     fn mirror_url(&self, url: &str) -> ConnectionResult<Url> {
         let mirror_host = Url::parse(self.config.host.as_ref()).map_err(ConnectionError::Url)?;
@@ -259,7 +380,12 @@ impl Mirror {
 
 impl Connection {
     /// Create a new connection according to the configuration.
-    pub fn new(config: &ConnectionConfig) -> Result<Arc<Connection>> {
+    ///
+    /// The optional `metrics` is used to record mirror failover events.
+    pub fn new(
+        config: &ConnectionConfig,
+        metrics: Option<Arc<BackendMetrics>>,
+    ) -> Result<Arc<Connection>> {
         info!("backend config: {:?}", config);
         let client = Self::build_connection("", config)?;
 
@@ -283,12 +409,14 @@ impl Connection {
         let mut mirrors = Vec::new();
         for mirror_config in config.mirrors.iter() {
             if !mirror_config.host.is_empty() {
-                mirrors.push(Arc::new(Mirror {
-                    config: mirror_config.clone(),
-                    status: AtomicBool::from(true),
-                    failed_times: AtomicU8::from(0),
-                    failure_limit: mirror_config.failure_limit,
-                }));
+                mirrors.push(Arc::new(Mirror::new(mirror_config)));
+            }
+        }
+
+        let mut failover_mirrors = Vec::new();
+        for mirror_config in config.failover_mirrors.iter() {
+            if !mirror_config.host.is_empty() {
+                failover_mirrors.push(Arc::new(Mirror::new(mirror_config)));
             }
         }
 
@@ -297,13 +425,17 @@ impl Connection {
             proxy,
             mirrors,
             shutdown: AtomicBool::new(false),
+            failover_mirrors,
+            primary: PrimaryHealth::new(config.primary_retry_interval),
+            metrics,
         });
 
         // Start  proxy's health checking thread.
         connection.start_proxy_health_thread(config.connect_timeout as u64);
 
         // Start mirrors' health checking thread.
-        connection.start_mirrors_health_thread(config.timeout as u64);
+        Self::start_mirrors_health_thread(&connection.mirrors, config.timeout as u64);
+        Self::start_mirrors_health_thread(&connection.failover_mirrors, config.timeout as u64);
 
         Ok(connection)
     }
@@ -351,8 +483,8 @@ impl Connection {
         }
     }
 
-    fn start_mirrors_health_thread(&self, timeout: u64) {
-        for mirror in self.mirrors.iter() {
+    fn start_mirrors_health_thread(mirrors: &[Arc<Mirror>], timeout: u64) {
+        for mirror in mirrors.iter() {
             let mirror_cloned = mirror.clone();
             thread::spawn(move || {
                 let mirror_health_url = if mirror_cloned.config.ping_url.is_empty() {
@@ -405,6 +537,15 @@ impl Connection {
         self.shutdown.store(true, Ordering::Release);
     }
 
+    /// Reset failure states of the primary server and mirrors.
+    pub fn reset(&self) {
+        self.primary.set_ok();
+        for mirror in self.mirrors.iter().chain(self.failover_mirrors.iter()) {
+            mirror.failed_times.store(0, Ordering::Relaxed);
+            mirror.status.store(true, Ordering::Relaxed);
+        }
+    }
+
     /// If the auth_through is enable, all requests are send to the mirror server.
     /// If the auth_through disabled, e.g. P2P/Dragonfly, we try to avoid sending
     /// non-authorization request to the mirror server, which causes performance loss.
@@ -538,15 +679,7 @@ impl Connection {
                                 "request mirror server failed, mirror: {:?},  error: {:?}",
                                 mirror, err
                             );
-                            mirror.failed_times.fetch_add(1, Ordering::Relaxed);
-
-                            if mirror.failed_times.load(Ordering::Relaxed) >= mirror.failure_limit {
-                                warn!(
-                                    "reach to failure limit {}, disable mirror: {:?}",
-                                    mirror.failure_limit, mirror
-                                );
-                                mirror.status.store(false, Ordering::Relaxed);
-                            }
+                            mirror.record_failure();
                         }
                     }
                 }
@@ -560,6 +693,10 @@ impl Connection {
             }
         }
 
+        if !self.failover_mirrors.is_empty() {
+            return self.call_with_failover(method, url, &query, data, headers, catch_status);
+        }
+
         self.call_inner(
             &self.client,
             method,
@@ -572,6 +709,163 @@ impl Connection {
         )
     }
 
+    /// Record an audit event when failing over from the primary server to mirrors, with the
+    /// error of the primary server, or when falling back to the recovered primary server.
+    fn record_failover(&self, url: &str, result: std::result::Result<(), String>) {
+        let primary = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()));
+        let mirrors = self
+            .failover_mirrors
+            .iter()
+            .map(|m| m.config.host.as_str())
+            .collect::<Vec<_>>();
+        let detail = serde_json::json!({
+            "primary": primary,
+            "mirrors": mirrors,
+            "recovered": result.is_ok(),
+        });
+        audit::record(EventCategory::BackendFailover, None, detail, &result);
+    }
+
+    /// Request the primary server, and fail over to mirrors in order if the primary server fails
+    /// or responds with server errors.
+    ///
+    /// Once failed over, requests are sent to mirrors directly and the primary server is retried
+    /// periodically, with a jittered backoff interval if it's still unavailable.
+    fn call_with_failover<R: Read + Clone + Send + 'static>(
+        &self,
+        method: Method,
+        url: &str,
+        query: &Option<&[(&str, &str)]>,
+        data: Option<ReqBody<R>>,
+        headers: &mut HeaderMap,
+        catch_status: bool,
+    ) -> ConnectionResult<Response> {
+        let primary_ok = self.primary.ok();
+        let mut last_result = None;
+
+        if primary_ok {
+            let data_cloned = data.as_ref().cloned();
+            let result = self.call_inner(
+                &self.client,
+                method.clone(),
+                url,
+                query,
+                data_cloned,
+                headers,
+                false,
+                false,
+            );
+            let error = match result {
+                Ok(resp) if resp.status() < StatusCode::INTERNAL_SERVER_ERROR => {
+                    if self.primary.set_ok() {
+                        self.record_failover(url, Ok(()));
+                    }
+                    return respond(resp, catch_status);
+                }
+                Ok(ref resp) => {
+                    warn!(
+                        "primary server responds status {}, fail over to mirrors",
+                        resp.status()
+                    );
+                    format!("status {}", resp.status())
+                }
+                Err(ref err) => {
+                    warn!(
+                        "request primary server failed, {:?}, fail over to mirrors",
+                        err
+                    );
+                    format!("{:?}", err)
+                }
+            };
+            if self.primary.set_failed() {
+                self.record_failover(url, Err(error));
+            }
+            last_result = Some(result);
+        }
+
+        for mirror in self.failover_mirrors.iter() {
+            if !mirror.status.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            for (key, value) in mirror.config.headers.iter() {
+                headers.insert(
+                    HeaderName::from_str(key).unwrap(),
+                    HeaderValue::from_str(value).unwrap(),
+                );
+            }
+
+            let current_url = mirror.mirror_url(url)?;
+            debug!("failover mirror server url {}", current_url);
+            let result = self.call_inner(
+                &self.client,
+                method.clone(),
+                current_url.as_str(),
+                query,
+                data.as_ref().cloned(),
+                headers,
+                false,
+                false,
+            );
+
+            for (key, _) in mirror.config.headers.iter() {
+                headers.remove(HeaderName::from_str(key).unwrap());
+            }
+
+            match result {
+                Ok(resp) if resp.status() < StatusCode::INTERNAL_SERVER_ERROR => {
+                    mirror.failed_times.store(0, Ordering::Relaxed);
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.mirror_failed_over();
+                    }
+                    return respond(resp, catch_status);
+                }
+                Ok(ref resp) => {
+                    warn!(
+                        "failover mirror {} responds status {}",
+                        mirror.config.host,
+                        resp.status()
+                    );
+                    mirror.record_failure();
+                }
+                Err(ref err) => {
+                    warn!(
+                        "request failover mirror {} failed, {:?}",
+                        mirror.config.host, err
+                    );
+                    mirror.record_failure();
+                }
+            }
+            last_result = Some(result);
+        }
+
+        match last_result {
+            Some(result) => result.and_then(|resp| respond(resp, catch_status)),
+            // The primary server is in backoff state and all mirrors are unavailable,
+            // so try the primary server anyway.
+            None => {
+                let result = self.call_inner(
+                    &self.client,
+                    method,
+                    url,
+                    query,
+                    data,
+                    headers,
+                    false,
+                    false,
+                );
+                if let Ok(resp) = result.as_ref() {
+                    if resp.status() < StatusCode::INTERNAL_SERVER_ERROR && self.primary.set_ok() {
+                        self.record_failover(url, Ok(()));
+                    }
+                }
+                result.and_then(|resp| respond(resp, catch_status))
+            }
+        }
+    }
+
     fn build_connection(proxy: &str, config: &ConnectionConfig) -> Result<Client> {
         let connect_timeout = if config.connect_timeout != 0 {
             Some(Duration::from_secs(config.connect_timeout as u64))
@@ -701,6 +995,39 @@ mod tests {
         assert!(checker.ok());
     }
 
+    #[test]
+    fn test_jittered_backoff() {
+        let base = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+
+        for attempt in 0..8 {
+            let delay = jittered_backoff(base, attempt, max);
+            let full = cmp::min(base * (1 << attempt), max);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+        assert_eq!(
+            jittered_backoff(Duration::from_secs(0), 3, max),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn test_primary_health() {
+        let health = PrimaryHealth::new(60);
+        assert!(health.ok());
+        assert!(health.set_failed());
+        assert!(!health.ok());
+        assert!(!health.set_failed());
+        assert!(health.set_ok());
+        assert!(health.ok());
+        assert!(!health.set_ok());
+
+        // Retry the primary server immediately if the retry interval is zero.
+        let health = PrimaryHealth::new(0);
+        health.set_failed();
+        assert!(health.ok());
+    }
+
     #[test]
     fn test_is_success_status() {
         assert!(!is_success_status(StatusCode::CONTINUE));
@@ -9,6 +9,7 @@
 //! - [Registry](registry/struct.Registry.html): backend driver to access blobs on container image
 //!   registry.
 //! - [Oss](oss/struct.Oss.html): backend driver to access blobs on Oss(Object Storage System).
+//! - [S3](s3/struct.S3.html): backend driver to access blobs on S3 compatible object storage.
 //! - [LocalFs](localfs/struct.LocalFs.html): backend driver to access blobs on local file system.
 //!   The [LocalFs](localfs/struct.LocalFs.html) storage backend supports backend level data
 //!   prefetching, which is to load data into page cache.
@@ -17,11 +18,16 @@ use std::sync::Arc;
 
 use fuse_backend_rs::file_buf::FileVolatileSlice;
 use nydus_utils::metrics::{BackendMetrics, ERROR_HOLDER};
+use nydus_utils::trace;
 
 use crate::utils::{alloc_buf, copyv};
 use crate::StorageError;
 
-#[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
+#[cfg(any(
+    feature = "backend-oss",
+    feature = "backend-registry",
+    feature = "backend-s3"
+))]
 pub mod connection;
 #[cfg(feature = "backend-localfs")]
 pub mod localfs;
@@ -29,6 +35,8 @@ pub mod localfs;
 pub mod oss;
 #[cfg(feature = "backend-registry")]
 pub mod registry;
+#[cfg(feature = "backend-s3")]
+pub mod s3;
 
 /// Error codes related to storage backend operations.
 #[derive(Debug)]
@@ -46,6 +54,9 @@ pub enum BackendError {
     #[cfg(feature = "backend-oss")]
     /// Error from OSS storage backend.
     Oss(self::oss::OssError),
+    #[cfg(feature = "backend-s3")]
+    /// Error from S3 storage backend.
+    S3(self::s3::S3Error),
 }
 
 /// Specialized `Result` for storage backends.
@@ -74,11 +85,13 @@ pub trait BlobReader: Send + Sync {
     fn read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
         let mut retry_count = self.retry_limit();
         let begin_time = self.metrics().begin();
+        let trace_begin = trace::stage_begin();
 
         loop {
             match self.try_read(buf, offset) {
                 Ok(size) => {
                     self.metrics().end(&begin_time, buf.len(), false);
+                    trace::record_current("backend_read", trace_begin);
                     return Ok(size);
                 }
                 Err(err) => {
@@ -90,6 +103,7 @@ pub trait BlobReader: Send + Sync {
                         retry_count -= 1;
                     } else {
                         self.metrics().end(&begin_time, buf.len(), true);
+                        trace::record_current("backend_read", trace_begin);
                         ERROR_HOLDER
                             .lock()
                             .unwrap()
@@ -151,4 +165,8 @@ pub trait BlobBackend: Send + Sync {
 
     /// Get a blob reader object to access blod `blob_id`.
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>>;
+
+    /// Reset cached failure states, such as disabled mirrors and failover backoff, so requests
+    /// are sent to the storage backend as usual after it recovers from failures.
+    fn reset(&self) {}
 }
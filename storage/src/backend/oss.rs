@@ -222,7 +222,7 @@ impl Oss {
         let oss_config: OssConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
         let con_config: ConnectionConfig = oss_config.clone().into();
         let retry_limit = con_config.retry_limit;
-        let connection = Connection::new(&con_config)?;
+        let connection = Connection::new(&con_config, None)?;
         let state = Arc::new(OssState {
             scheme: oss_config.scheme,
             object_prefix: oss_config.object_prefix,
@@ -248,6 +248,10 @@ impl BlobBackend for Oss {
         self.connection.shutdown();
     }
 
+    fn reset(&self) {
+        self.connection.reset();
+    }
+
     fn metrics(&self) -> &BackendMetrics {
         // `metrics()` is only used for nydusd, which will always provide valid `blob_id`, thus
         // `self.metrics` has valid value.
@@ -5,12 +5,13 @@
 //! Storage backend driver to access blobs on container image registry.
 use std::collections::HashMap;
 use std::io::{Error, Read, Result};
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use arc_swap::ArcSwapOption;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use reqwest::blocking::Response;
 pub use reqwest::header::HeaderMap;
 use reqwest::header::{HeaderValue, CONTENT_LENGTH};
@@ -21,13 +22,24 @@ use nydus_api::http::RegistryConfig;
 use nydus_utils::metrics::BackendMetrics;
 
 use crate::backend::connection::{
-    is_success_status, respond, Connection, ConnectionConfig, ConnectionError, ReqBody,
+    is_success_status, jittered_backoff, respond, Connection, ConnectionConfig, ConnectionError,
+    ReqBody,
 };
 use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader};
 
 const REGISTRY_CLIENT_ID: &str = "nydus-registry-client";
 const HEADER_AUTHORIZATION: &str = "Authorization";
 const HEADER_WWW_AUTHENTICATE: &str = "www-authenticate";
+/// Maximum attempts to refresh an expired token.
+const TOKEN_REFRESH_ATTEMPTS: u32 = 3;
+/// Base delay between attempts to refresh token, which backs off exponentially with jitter.
+const TOKEN_REFRESH_DELAY: Duration = Duration::from_millis(200);
+const TOKEN_REFRESH_MAX_DELAY: Duration = Duration::from_secs(5);
+/// Interval to check changes of the credential file.
+#[cfg(not(test))]
+const AUTH_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+#[cfg(test)]
+const AUTH_FILE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
 
 const REDIRECTED_STATUS_CODE: [StatusCode; 2] = [
     StatusCode::MOVED_PERMANENTLY,
@@ -101,6 +113,11 @@ impl HashCache {
         let mut cached_guard = self.0.write().unwrap();
         cached_guard.remove(key);
     }
+
+    fn clear(&self) {
+        let mut cached_guard = self.0.write().unwrap();
+        cached_guard.clear();
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -134,16 +151,34 @@ enum Auth {
     Bearer(BearerAuth),
 }
 
+// Credential to access the registry, which may be swapped when reloading the credential file.
+#[derive(Default)]
+struct RegistryCredential {
+    // Base64 encoded registry auth
+    auth: Option<String>,
+    username: String,
+    password: String,
+}
+
+impl RegistryCredential {
+    fn new(auth: Option<String>) -> Result<Self> {
+        let (username, password) = Registry::get_authorization_info(&auth)?;
+        Ok(RegistryCredential {
+            auth,
+            username,
+            password,
+        })
+    }
+}
+
 struct RegistryState {
     // HTTP scheme like: https, http
     scheme: String,
     host: String,
     // Image repo name like: library/ubuntu
     repo: String,
-    // Base64 encoded registry auth
-    auth: Option<String>,
-    username: String,
-    password: String,
+    // Credential for new requests, in-flight requests keep using the credential they started with.
+    credential: ArcSwap<RegistryCredential>,
     // Retry limit for read operation
     retry_limit: u8,
     // Scheme specified for blob server
@@ -181,7 +216,12 @@ impl RegistryState {
     }
 
     /// Request registry authentication server to get bearer token
-    fn get_token(&self, auth: BearerAuth, connection: &Arc<Connection>) -> Result<String> {
+    fn get_token(
+        &self,
+        auth: BearerAuth,
+        credential: &RegistryCredential,
+        connection: &Arc<Connection>,
+    ) -> Result<String> {
         // The information needed for getting token needs to be placed both in
         // the query and in the body to be compatible with different registry
         // implementations, which have been tested on these platforms:
@@ -190,8 +230,8 @@ impl RegistryState {
             ("service", auth.service.as_str()),
             ("scope", auth.scope.as_str()),
             ("grant_type", "password"),
-            ("username", self.username.as_str()),
-            ("password", self.password.as_str()),
+            ("username", credential.username.as_str()),
+            ("password", credential.password.as_str()),
             ("client_id", REGISTRY_CLIENT_ID),
         ];
 
@@ -238,20 +278,69 @@ impl RegistryState {
         Ok(ret.token)
     }
 
-    fn get_auth_header(&self, auth: Auth, connection: &Arc<Connection>) -> Result<String> {
+    /// Refresh bearer token with the cached `www-authenticate` challenge.
+    ///
+    /// Return `None` if there's no cached bearer challenge or all attempts failed.
+    fn refresh_token(&self, connection: &Arc<Connection>) -> Option<String> {
+        let auth = self.cached_bearer_auth.load_full()?;
+        let credential = self.credential.load();
+
+        for attempt in 0..TOKEN_REFRESH_ATTEMPTS {
+            match self.get_token(auth.as_ref().clone(), &credential, connection) {
+                Ok(token) => return Some(format!("Bearer {}", token)),
+                Err(e) => {
+                    warn!(
+                        "failed to refresh registry token, attempt {}: {}",
+                        attempt + 1,
+                        e
+                    );
+                    if attempt + 1 < TOKEN_REFRESH_ATTEMPTS {
+                        thread::sleep(jittered_backoff(
+                            TOKEN_REFRESH_DELAY,
+                            attempt,
+                            TOKEN_REFRESH_MAX_DELAY,
+                        ));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn get_auth_header(
+        &self,
+        auth: Auth,
+        credential: &RegistryCredential,
+        connection: &Arc<Connection>,
+    ) -> Result<String> {
         match auth {
-            Auth::Basic(_) => self
+            Auth::Basic(_) => credential
                 .auth
                 .as_ref()
                 .map(|auth| format!("Basic {}", auth))
                 .ok_or_else(|| einval!("invalid auth config")),
             Auth::Bearer(auth) => {
-                let token = self.get_token(auth, connection)?;
+                let token = self.get_token(auth, credential, connection)?;
                 Ok(format!("Bearer {}", token))
             }
         }
     }
 
+    /// Swap in credential loaded from `content` of the credential file for subsequent requests.
+    ///
+    /// Cached authorization headers are dropped, so requests authenticate with the new credential.
+    fn update_credential(&self, content: &str) -> Result<()> {
+        let auth = trim(Some(content.to_string()))
+            .ok_or_else(|| einval!("empty registry credential file"))?;
+        let credential = RegistryCredential::new(Some(auth))?;
+        self.credential.store(Arc::new(credential));
+        self.cached_auth.set(&self.cached_auth.get(), String::new());
+        self.cached_bearer_auth.store(None);
+
+        Ok(())
+    }
+
     /// Parse `www-authenticate` response header respond from registry server
     /// The header format like: `Bearer realm="https://auth.my-registry.com/token",service="my-registry.com",scope="repository:test/repo:pull,push"`
     fn parse_auth(source: &HeaderValue, auth: &Option<String>) -> Option<Auth> {
@@ -349,6 +438,7 @@ impl RegistryReader {
         mut headers: HeaderMap,
         catch_status: bool,
     ) -> RegistryResult<Response> {
+        let credential = self.state.credential.load_full();
         // Try get authorization header from cache for this request
         let mut last_cached_auth = String::new();
         let cached_auth = self.state.cached_auth.get();
@@ -384,6 +474,38 @@ impl RegistryReader {
             .map_err(RegistryError::Request)?;
         if resp.status() == StatusCode::UNAUTHORIZED {
             if headers.contains_key(HEADER_AUTHORIZATION) {
+                // The cached token may have expired, refresh it with the cached `www-authenticate`
+                // challenge and retry the request once. Reuse the token if it has already been
+                // refreshed by other threads.
+                let current_cached_auth = self.state.cached_auth.get();
+                let auth_header = if current_cached_auth != last_cached_auth {
+                    Some(current_cached_auth)
+                } else {
+                    self.state
+                        .refresh_token(&self.connection)
+                        .map(|auth_header| {
+                            info!("Authorization token for registry has been refreshed.");
+                            self.metrics.auth_refreshed();
+                            auth_header
+                        })
+                };
+                if let Some(auth_header) = auth_header {
+                    headers.insert(
+                        HEADER_AUTHORIZATION,
+                        HeaderValue::from_str(auth_header.as_str()).unwrap(),
+                    );
+                    resp = self
+                        .connection
+                        .call::<&[u8]>(method.clone(), url, None, None, &mut headers, false, false)
+                        .map_err(RegistryError::Request)?;
+                    if resp.status() != StatusCode::UNAUTHORIZED {
+                        if is_success_status(resp.status()) {
+                            self.state.cached_auth.set(&last_cached_auth, auth_header);
+                        }
+                        return respond(resp, catch_status).map_err(RegistryError::Request);
+                    }
+                }
+
                 // If we request registry (harbor server) with expired authorization token,
                 // the `www-authenticate: Basic realm="harbor"` in response headers is not expected.
                 // Related code in harbor:
@@ -401,10 +523,10 @@ impl RegistryReader {
 
             if let Some(resp_auth_header) = resp.headers().get(HEADER_WWW_AUTHENTICATE) {
                 // Get token from registry authorization server
-                if let Some(auth) = RegistryState::parse_auth(resp_auth_header, &self.state.auth) {
+                if let Some(auth) = RegistryState::parse_auth(resp_auth_header, &credential.auth) {
                     let auth_header = self
                         .state
-                        .get_auth_header(auth, &self.connection)
+                        .get_auth_header(auth, &credential, &self.connection)
                         .map_err(|e| RegistryError::Common(e.to_string()))?;
 
                     headers.insert(
@@ -609,10 +731,32 @@ impl Registry {
         }
 
         let retry_limit = con_config.retry_limit;
-        let connection = Connection::new(&con_config)?;
-        let auth = trim(config.auth);
         let registry_token = trim(config.registry_token);
-        let (username, password) = Self::get_authorization_info(&auth)?;
+        let auth_file = trim(config.auth_file).map(PathBuf::from);
+        let mut auth_file_content = String::new();
+        let auth = match auth_file.as_ref() {
+            Some(path) => {
+                if config.auth.is_some() {
+                    return Err(einval!(
+                        "registry: auth and auth_file cannot be configured at the same time."
+                    ));
+                }
+                auth_file_content = std::fs::read_to_string(path).map_err(|e| {
+                    einval!(format!(
+                        "failed to read registry credential file {:?}, {}",
+                        path, e
+                    ))
+                })?;
+                trim(Some(auth_file_content.clone()))
+            }
+            None => trim(config.auth),
+        };
+        let credential = RegistryCredential::new(auth)?;
+        let metrics = BackendMetrics::new(id, "registry");
+        let connection = Connection::new(&con_config, Some(metrics.clone())).map_err(|e| {
+            metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+            e
+        })?;
         let cached_auth = if let Some(registry_token) = registry_token {
             // Store the registry bearer token to cached_auth, prefer to
             // use the token stored in cached_auth to request registry.
@@ -625,10 +769,8 @@ impl Registry {
             scheme: config.scheme,
             host: config.host,
             repo: config.repo,
-            auth,
+            credential: ArcSwap::new(Arc::new(credential)),
             cached_auth,
-            username,
-            password,
             retry_limit,
             blob_url_scheme: config.blob_url_scheme,
             blob_redirected_host: config.blob_redirected_host,
@@ -642,7 +784,7 @@ impl Registry {
         let registry = Registry {
             connection,
             state,
-            metrics: BackendMetrics::new(id, "registry"),
+            metrics,
         };
 
         for mirror in mirrors.iter() {
@@ -652,6 +794,9 @@ impl Registry {
                 break;
             }
         }
+        if let Some(path) = auth_file {
+            registry.start_auth_file_watcher(path, auth_file_content);
+        }
 
         Ok(registry)
     }
@@ -696,19 +841,12 @@ impl Registry {
                         if now_timestamp.as_secs() + refresh_check_internal + 20
                             >= *next_refresh_timestamp
                         {
-                            if let Some(cached_bearer_auth) =
-                                state.cached_bearer_auth.load().as_deref()
-                            {
-                                if let Ok(token) =
-                                    state.get_token(cached_bearer_auth.to_owned(), &conn)
-                                {
-                                    let new_cached_auth = format!("Bearer {}", token);
-                                    info!("Authorization token for registry has been refreshed.");
-                                    // Refresh authorization token
-                                    state
-                                        .cached_auth
-                                        .set(&state.cached_auth.get(), new_cached_auth);
-                                }
+                            if let Some(new_cached_auth) = state.refresh_token(&conn) {
+                                info!("Authorization token for registry has been refreshed.");
+                                // Refresh authorization token
+                                state
+                                    .cached_auth
+                                    .set(&state.cached_auth.get(), new_cached_auth);
                             }
                         }
                     }
@@ -724,6 +862,51 @@ impl Registry {
             }
         });
     }
+
+    /// Watch the credential file and reload credential on change.
+    ///
+    /// The current credential is kept if the file fails to be read or parsed.
+    fn start_auth_file_watcher(&self, path: PathBuf, content: String) {
+        let conn = self.connection.clone();
+        let state = self.state.clone();
+        let metrics = self.metrics.clone();
+        thread::spawn(move || {
+            let mut last_content = content;
+            let mut read_failed = false;
+            loop {
+                thread::sleep(AUTH_FILE_CHECK_INTERVAL);
+                if conn.shutdown.load(Ordering::Acquire) {
+                    break;
+                }
+
+                let content = match std::fs::read_to_string(&path) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        if !read_failed {
+                            error!("failed to read registry credential file {:?}, {}", path, e);
+                            read_failed = true;
+                        }
+                        continue;
+                    }
+                };
+                read_failed = false;
+                if content == last_content {
+                    continue;
+                }
+                match state.update_credential(&content) {
+                    Ok(()) => {
+                        metrics.credential_reloaded();
+                        info!("registry credential reloaded from {:?}", path);
+                    }
+                    Err(e) => error!(
+                        "failed to load registry credential from {:?}, keep the current one, {}",
+                        path, e
+                    ),
+                }
+                last_content = content;
+            }
+        });
+    }
 }
 
 impl BlobBackend for Registry {
@@ -731,6 +914,12 @@ impl BlobBackend for Registry {
         self.connection.shutdown();
     }
 
+    fn reset(&self) {
+        self.connection.reset();
+        // Redirected urls may have become stale during the outage.
+        self.state.cached_redirect.clear();
+    }
+
     fn metrics(&self) -> &BackendMetrics {
         &self.metrics
     }
@@ -769,6 +958,273 @@ fn trim(value: Option<String>) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize};
+    use std::sync::Mutex;
+
+    struct StubRequest {
+        server: String,
+        method: String,
+        path: String,
+        auth: Option<String>,
+    }
+
+    // (status, headers, body)
+    type StubResponse = (u16, Vec<(String, String)>, String);
+
+    /// Start a stub HTTP server which responds requests with `handler`, return the server url.
+    fn start_stub_server<F>(handler: F) -> String
+    where
+        F: Fn(&StubRequest) -> StubResponse + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        let server_cloned = server.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                if reader.read_line(&mut line).is_err() {
+                    continue;
+                }
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 2 {
+                    continue;
+                }
+
+                let mut req = StubRequest {
+                    server: server_cloned.clone(),
+                    method: parts[0].to_string(),
+                    path: parts[1].to_string(),
+                    auth: None,
+                };
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = header.split_once(':') {
+                        let value = value.trim().to_string();
+                        match key.to_lowercase().as_str() {
+                            "authorization" => req.auth = Some(value),
+                            "content-length" => content_length = value.parse().unwrap_or(0),
+                            _ => {}
+                        }
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                let _ = reader.read_exact(&mut body);
+
+                let (status, headers, body) = handler(&req);
+                let mut resp = format!("HTTP/1.1 {} Stub\r\nConnection: close\r\n", status);
+                for (key, value) in headers {
+                    resp += &format!("{}: {}\r\n", key, value);
+                }
+                resp += &format!("Content-Length: {}\r\n\r\n", body.len());
+                if req.method != "HEAD" {
+                    resp += &body;
+                }
+                let _ = stream.write_all(resp.as_bytes());
+            }
+        });
+
+        server
+    }
+
+    fn get_metric(metrics: &BackendMetrics, name: &str) -> u64 {
+        serde_json::to_value(metrics).unwrap()[name]
+            .as_u64()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_registry_auth_refresh() {
+        let valid_token = Arc::new(Mutex::new("token-1".to_string()));
+        let token_requests = Arc::new(AtomicUsize::new(0));
+        let (token, requests) = (valid_token.clone(), token_requests.clone());
+        let server = start_stub_server(move |req| {
+            let token = token.lock().unwrap().clone();
+            if req.path.starts_with("/token") {
+                requests.fetch_add(1, Ordering::Relaxed);
+                (200, vec![], format!("{{\"token\": \"{}\"}}", token))
+            } else if req.auth == Some(format!("Bearer {}", token)) {
+                (200, vec![], "x".repeat(16))
+            } else {
+                let challenge = format!(
+                    "Bearer realm=\"{}/token\",service=\"stub\",scope=\"repository:test/repo:pull\"",
+                    req.server
+                );
+                (
+                    401,
+                    vec![(HEADER_WWW_AUTHENTICATE.to_string(), challenge)],
+                    String::new(),
+                )
+            }
+        });
+
+        let config = serde_json::json!({
+            "scheme": "http",
+            "host": server.trim_start_matches("http://"),
+            "repo": "test/repo",
+            "registry_token": "expired-token",
+        });
+        let registry = Registry::new(config, Some("test_registry_auth_refresh")).unwrap();
+        let reader = registry.get_reader("blob").unwrap();
+
+        // No cached challenge for the initial token, go through the whole auth workflow.
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(token_requests.load(Ordering::Relaxed), 1);
+        assert_eq!(get_metric(registry.metrics(), "auth_refreshes"), 0);
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(token_requests.load(Ordering::Relaxed), 1);
+
+        // Expire the token, which should be refreshed with the cached challenge.
+        *valid_token.lock().unwrap() = "token-2".to_string();
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(token_requests.load(Ordering::Relaxed), 2);
+        assert_eq!(get_metric(registry.metrics(), "auth_refreshes"), 1);
+        assert_eq!(registry.state.cached_auth.get(), "Bearer token-2");
+    }
+
+    #[test]
+    fn test_registry_mirror_failover() {
+        let primary_status = Arc::new(AtomicU16::new(503));
+        let primary_requests = Arc::new(AtomicUsize::new(0));
+        let mirror_requests = Arc::new(AtomicUsize::new(0));
+
+        let (status, requests) = (primary_status.clone(), primary_requests.clone());
+        let primary = start_stub_server(move |_req| {
+            requests.fetch_add(1, Ordering::Relaxed);
+            (status.load(Ordering::Relaxed), vec![], "x".repeat(16))
+        });
+        let requests = mirror_requests.clone();
+        let mirror = start_stub_server(move |_req| {
+            requests.fetch_add(1, Ordering::Relaxed);
+            (200, vec![], "y".repeat(32))
+        });
+
+        let config = serde_json::json!({
+            "scheme": "http",
+            "host": primary.trim_start_matches("http://"),
+            "repo": "test/repo",
+            "failover_mirrors": [{ "host": mirror }],
+            "primary_retry_interval": 0,
+        });
+        let registry = Registry::new(config, Some("test_registry_mirror_failover")).unwrap();
+        let reader = registry.get_reader("blob").unwrap();
+
+        // Primary server is unavailable, fail over to the mirror.
+        assert_eq!(reader.blob_size().unwrap(), 32);
+        assert_eq!(primary_requests.load(Ordering::Relaxed), 1);
+        assert_eq!(mirror_requests.load(Ordering::Relaxed), 1);
+        assert_eq!(get_metric(registry.metrics(), "mirror_failovers"), 1);
+
+        // Fall back to the primary server once it recovers.
+        primary_status.store(200, Ordering::Relaxed);
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(primary_requests.load(Ordering::Relaxed), 2);
+        assert_eq!(mirror_requests.load(Ordering::Relaxed), 1);
+        assert_eq!(get_metric(registry.metrics(), "mirror_failovers"), 1);
+    }
+
+    #[test]
+    fn test_registry_auth_file_reload() {
+        let cred1 = base64::encode("user:password1");
+        let cred2 = base64::encode("user:password2");
+        let valid_creds = Arc::new(Mutex::new(vec![cred1.clone()]));
+        let creds = valid_creds.clone();
+        let server = start_stub_server(move |req| {
+            let valid = creds
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|c| req.auth == Some(format!("Basic {}", c)));
+            if valid {
+                (200, vec![], "x".repeat(16))
+            } else {
+                let challenge = "Basic realm=\"stub\"".to_string();
+                (
+                    401,
+                    vec![(HEADER_WWW_AUTHENTICATE.to_string(), challenge)],
+                    String::new(),
+                )
+            }
+        });
+
+        let auth_file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(auth_file.as_path(), format!("{}\n", cred1)).unwrap();
+        let config = serde_json::json!({
+            "scheme": "http",
+            "host": server.trim_start_matches("http://"),
+            "repo": "test/repo",
+            "auth_file": auth_file.as_path(),
+        });
+        let registry = Registry::new(config, Some("test_registry_auth_file_reload")).unwrap();
+        let reader = registry.get_reader("blob").unwrap();
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(get_metric(registry.metrics(), "credential_generation"), 0);
+
+        let wait_for_generation = |generation: u64| {
+            for _ in 0..100 {
+                if get_metric(registry.metrics(), "credential_generation") >= generation {
+                    return;
+                }
+                thread::sleep(AUTH_FILE_CHECK_INTERVAL);
+            }
+            panic!("credential file has not been reloaded");
+        };
+
+        // Rotate the credential while other threads keep reading.
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut workers = Vec::new();
+        for _ in 0..4 {
+            let reader = reader.clone();
+            let stop = stop.clone();
+            workers.push(thread::spawn(move || {
+                let mut count = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    assert_eq!(reader.blob_size().unwrap(), 16);
+                    count += 1;
+                }
+                count
+            }));
+        }
+        valid_creds.lock().unwrap().push(cred2.clone());
+        std::fs::write(auth_file.as_path(), &cred2).unwrap();
+        wait_for_generation(1);
+        valid_creds.lock().unwrap().retain(|c| c != &cred1);
+        thread::sleep(AUTH_FILE_CHECK_INTERVAL * 4);
+        stop.store(true, Ordering::Relaxed);
+        for worker in workers {
+            assert!(worker.join().unwrap() > 0);
+        }
+        assert_eq!(reader.blob_size().unwrap(), 16);
+        assert_eq!(registry.state.credential.load().auth, Some(cred2.clone()));
+        assert_eq!(registry.state.credential.load().password, "password2");
+
+        // Keep the current credential if the credential file is invalid.
+        std::fs::write(auth_file.as_path(), "invalid-credential").unwrap();
+        thread::sleep(AUTH_FILE_CHECK_INTERVAL * 4);
+        assert_eq!(get_metric(registry.metrics(), "credential_generation"), 1);
+        assert_eq!(registry.state.credential.load().auth, Some(cred2));
+        assert_eq!(reader.blob_size().unwrap(), 16);
+
+        let config = serde_json::json!({
+            "host": "127.0.0.1",
+            "repo": "test/repo",
+            "auth": cred1,
+            "auth_file": auth_file.as_path(),
+        });
+        assert!(Registry::new(config, Some("test_registry_auth_file_conflict")).is_err());
+        registry.shutdown();
+    }
 
     #[test]
     fn test_string_cache() {
@@ -801,9 +1257,11 @@ mod tests {
             scheme: "http".to_string(),
             host: "alibaba-inc.com".to_string(),
             repo: "nydus".to_string(),
-            auth: None,
-            username: "test".to_string(),
-            password: "password".to_string(),
+            credential: ArcSwap::new(Arc::new(RegistryCredential {
+                auth: None,
+                username: "test".to_string(),
+                password: "password".to_string(),
+            })),
             retry_limit: 5,
             blob_url_scheme: "https".to_string(),
             blob_redirected_host: "oss.alibaba-inc.com".to_string(),
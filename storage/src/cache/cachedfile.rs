@@ -11,27 +11,30 @@
 
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{ErrorKind, Read, Result};
+use std::io::{ErrorKind, IoSliceMut, Read, Result};
 use std::mem::ManuallyDrop;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use fuse_backend_rs::file_buf::FileVolatileSlice;
 use nix::sys::uio;
 use nydus_utils::compress::Decoder;
+use nydus_utils::crypt::{self, CipherKey};
 use nydus_utils::metrics::{BlobcacheMetrics, Metric};
 use nydus_utils::{compress, digest, FileRangeReader};
 use tokio::runtime::Runtime;
 
 use crate::backend::BlobReader;
 use crate::cache::state::ChunkMap;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::cache::uring::IoUringReader;
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncPrefetchMessage, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobIoMergeState};
 use crate::device::{
-    BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoRange, BlobIoSegment, BlobIoTag, BlobIoVec,
-    BlobObject, BlobPrefetchRequest,
+    BlobCachedFile, BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoRange, BlobIoSegment, BlobIoTag,
+    BlobIoVec, BlobObject, BlobPrefetchRequest,
 };
 use crate::meta::{BlobMetaChunk, BlobMetaInfo};
 use crate::utils::{alloc_buf, copyv, readv, MemSliceCursor};
@@ -40,10 +43,45 @@ use crate::{StorageError, StorageResult, RAFS_DEFAULT_CHUNK_SIZE, RAFS_MERGING_S
 const DOWNLOAD_META_RETRY_COUNT: u32 = 20;
 const DOWNLOAD_META_RETRY_DELAY: u64 = 500;
 
-#[derive(Default, Clone)]
+/// Bitmap to record chunks whose data in the cache file has been validated by digest value.
+///
+/// Data in the cache file doesn't change once the chunk is ready, so a chunk only needs to be
+/// validated once even if data validation gets enabled and disabled at runtime.
+pub(crate) struct ChunkValidationMap {
+    bits: Vec<AtomicU64>,
+}
+
+impl ChunkValidationMap {
+    pub(crate) fn new(chunk_count: u32) -> Self {
+        let count = (chunk_count as usize + 63) / 64;
+        let mut bits = Vec::with_capacity(count);
+        bits.resize_with(count, || AtomicU64::new(0));
+        ChunkValidationMap { bits }
+    }
+
+    fn is_validated(&self, chunk: &dyn BlobChunkInfo) -> bool {
+        let index = chunk.id() as usize;
+        match self.bits.get(index >> 6) {
+            Some(v) => v.load(Ordering::Acquire) & (1u64 << (index & 0x3f)) != 0,
+            None => false,
+        }
+    }
+
+    fn set_validated(&self, chunk: &dyn BlobChunkInfo) {
+        let index = chunk.id() as usize;
+        if let Some(v) = self.bits.get(index >> 6) {
+            v.fetch_or(1u64 << (index & 0x3f), Ordering::AcqRel);
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct FileCacheMeta {
     has_error: Arc<AtomicBool>,
     meta: Arc<Mutex<Option<Arc<BlobMetaInfo>>>>,
+    blob_file: String,
+    blob_info: Arc<BlobInfo>,
+    reader: Option<Arc<dyn BlobReader>>,
 }
 
 impl FileCacheMeta {
@@ -55,13 +93,22 @@ impl FileCacheMeta {
         let meta = FileCacheMeta {
             has_error: Arc::new(AtomicBool::new(false)),
             meta: Arc::new(Mutex::new(None)),
+            blob_file,
+            blob_info,
+            reader,
         };
-        let meta1 = meta.clone();
+        meta.load();
+
+        Ok(meta)
+    }
+
+    fn load(&self) {
+        let meta1 = self.clone();
 
         std::thread::spawn(move || {
             let mut retry = 0;
             while retry < DOWNLOAD_META_RETRY_COUNT {
-                match BlobMetaInfo::new(&blob_file, &blob_info, reader.as_ref()) {
+                match BlobMetaInfo::new(&meta1.blob_file, &meta1.blob_info, meta1.reader.as_ref()) {
                     Ok(m) => {
                         *meta1.meta.lock().unwrap() = Some(Arc::new(m));
                         return;
@@ -76,8 +123,18 @@ impl FileCacheMeta {
             warn!("failed to get blob.meta");
             meta1.has_error.store(true, Ordering::Release);
         });
+    }
 
-        Ok(meta)
+    /// Try to load the blob metadata again if it failed to load before.
+    pub(crate) fn reload(&self) {
+        if self
+            .has_error
+            .compare_exchange(true, false, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            info!("reload blob.meta for blob {}", self.blob_info.blob_id());
+            self.load();
+        }
     }
 
     pub(crate) fn get_blob_meta(&self) -> Option<Arc<BlobMetaInfo>> {
@@ -123,9 +180,14 @@ pub(crate) struct FileCacheEntry {
     // True if direct IO is enabled for the `self.file`, supported for fscache only.
     pub(crate) dio_enabled: bool,
     // Data from the file cache should be validated before use.
-    pub(crate) need_validation: bool,
+    pub(crate) need_validation: AtomicBool,
+    // Chunks whose data in the file cache has been validated.
+    pub(crate) validated_chunks: ChunkValidationMap,
     pub(crate) batch_size: u64,
     pub(crate) prefetch_config: Arc<AsyncPrefetchConfig>,
+    // Shared io_uring instance to read data from the file cache.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub(crate) io_uring: Option<Arc<IoUringReader>>,
 }
 
 impl FileCacheEntry {
@@ -140,6 +202,13 @@ impl FileCacheEntry {
         Ok(size)
     }
 
+    /// Reset error states cached by the entry after the storage backend recovers from failures.
+    pub(crate) fn reset_error_state(&self) {
+        if let Some(meta) = self.meta.as_ref() {
+            meta.reload();
+        }
+    }
+
     fn delay_persist_chunk_data(
         &self,
         chunk: Arc<dyn BlobChunkInfo>,
@@ -341,6 +410,27 @@ impl FileCacheEntry {
             Ok((start, end, size as usize))
         }
     }
+
+    // Merge chunks to prefetch into bigger requests and send them to the async workers.
+    fn prefetch_bios(&self, blob_cache: Arc<dyn BlobCache>, bios: &[BlobIoDesc], low: bool) {
+        let max_comp_size = self.prefetch_batch_size();
+        let mut bios = bios.to_vec();
+        bios.sort_by_key(|entry| entry.chunkinfo.compressed_offset());
+        self.metrics.prefetch_unmerged_chunks.add(bios.len() as u64);
+        BlobIoMergeState::merge_and_issue(
+            &bios,
+            max_comp_size,
+            max_comp_size as u64 >> RAFS_MERGING_SIZE_TO_GAP_SHIFT,
+            |req: BlobIoRange| {
+                let msg = AsyncPrefetchMessage::new_fs_prefetch(blob_cache.clone(), req);
+                let _ = if low {
+                    self.workers.send_low_priority_prefetch_message(msg)
+                } else {
+                    self.workers.send_prefetch_message(msg)
+                };
+            },
+        );
+    }
 }
 
 impl AsRawFd for FileCacheEntry {
@@ -370,6 +460,14 @@ impl BlobCache for FileCacheEntry {
         self.digester
     }
 
+    fn cipher(&self) -> crypt::Algorithm {
+        self.blob_info.cipher()
+    }
+
+    fn cipher_key(&self) -> Option<&Arc<CipherKey>> {
+        self.blob_info.cipher_key()
+    }
+
     fn is_legacy_stargz(&self) -> bool {
         self.is_legacy_stargz
     }
@@ -379,7 +477,15 @@ impl BlobCache for FileCacheEntry {
     }
 
     fn need_validation(&self) -> bool {
+        self.need_validation.load(Ordering::Relaxed)
+    }
+
+    fn set_validation(&self, enable: bool) {
+        // Data must always be validated if readiness of chunks isn't persisted.
+        let need_validation =
+            (enable || !self.is_direct_chunkmap) && !self.blob_info.is_foreign_layer();
         self.need_validation
+            .store(need_validation, Ordering::Relaxed);
     }
 
     fn reader(&self) -> &dyn BlobReader {
@@ -405,6 +511,16 @@ impl BlobCache for FileCacheEntry {
         }
     }
 
+    fn get_cached_file(&self, bios: &[BlobIoDesc]) -> Option<BlobCachedFile> {
+        // Data read from the cache file must be used as is, without decompression or validation.
+        if self.is_compressed || self.need_validation() || !self.is_direct_chunkmap {
+            return None;
+        }
+
+        get_ready_contiguous_range(self.chunk_map.as_ref(), bios)
+            .map(|(offset, size)| BlobCachedFile::new(self.file.clone(), offset, size))
+    }
+
     fn start_prefetch(&self) -> StorageResult<()> {
         self.prefetch_state.fetch_add(1, Ordering::Release);
         Ok(())
@@ -454,19 +570,17 @@ impl BlobCache for FileCacheEntry {
         }
 
         // Then handle fs prefetch
-        let max_comp_size = self.prefetch_batch_size();
-        let mut bios = bios.to_vec();
-        bios.sort_by_key(|entry| entry.chunkinfo.compressed_offset());
-        self.metrics.prefetch_unmerged_chunks.add(bios.len() as u64);
-        BlobIoMergeState::merge_and_issue(
-            &bios,
-            max_comp_size,
-            max_comp_size as u64 >> RAFS_MERGING_SIZE_TO_GAP_SHIFT,
-            |req: BlobIoRange| {
-                let msg = AsyncPrefetchMessage::new_fs_prefetch(blob_cache.clone(), req);
-                let _ = self.workers.send_prefetch_message(msg);
-            },
-        );
+        self.prefetch_bios(blob_cache, bios, false);
+
+        Ok(0)
+    }
+
+    fn prefetch_low_priority(
+        &self,
+        blob_cache: Arc<dyn BlobCache>,
+        bios: &[BlobIoDesc],
+    ) -> StorageResult<usize> {
+        self.prefetch_bios(blob_cache, bios, true);
 
         Ok(0)
     }
@@ -850,14 +964,20 @@ impl FileCacheEntry {
                 Ok(true) => true,
                 Ok(false) => false,
                 Err(StorageError::Timeout) => false, // Retry if waiting for inflight IO timeouts
+                Err(StorageError::FetchFailed) => {
+                    return Err(eio!("failed to read data from storage backend"))
+                }
                 Err(e) => return Err(einval!(e)),
             };
 
             // Directly read data from the file cache into the user buffer iff:
             // - the chunk is ready in the file cache
             // - the data in the file cache is uncompressed.
-            // - data validation is disabled
-            if is_ready && !self.is_compressed && !self.need_validation() {
+            // - data validation is disabled or the chunk has already been validated
+            if is_ready
+                && !self.is_compressed
+                && (!self.need_validation() || self.validated_chunks.is_validated(chunk.as_ref()))
+            {
                 // Internal IO should not be committed to local cache region, just
                 // commit this region without pushing any chunk to avoid discontinuous
                 // chunks in a region.
@@ -929,7 +1049,16 @@ impl FileCacheEntry {
         let mut iovec = cursor.consume(size);
 
         self.metrics.partial_hits.inc();
-        readv(self.file.as_raw_fd(), &mut iovec, offset)
+        self.read_cache_file(&mut iovec, offset)
+    }
+
+    // Read data from the file cache, by io_uring if available.
+    fn read_cache_file(&self, iovec: &mut [IoSliceMut], offset: u64) -> Result<usize> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(io_uring) = self.io_uring.as_ref() {
+            return io_uring.readv(self.file.as_raw_fd(), iovec, offset);
+        }
+        readv(self.file.as_raw_fd(), iovec, offset)
     }
 
     // Try to read data from blob cache and validate it, fallback to storage backend.
@@ -1018,7 +1147,7 @@ impl FileCacheEntry {
             )
             .map_err(|e| {
                 for c in &region.chunks {
-                    self.chunk_map.clear_pending(c.as_ref());
+                    self.chunk_map.clear_pending_on_error(c.as_ref());
                 }
                 e
             })?;
@@ -1104,7 +1233,7 @@ impl FileCacheEntry {
             let c = self
                 .read_chunk_from_backend(chunk.as_ref(), d.mut_slice())
                 .map_err(|e| {
-                    self.chunk_map.clear_pending(chunk.as_ref());
+                    self.chunk_map.clear_pending_on_error(chunk.as_ref());
                     e
                 })?;
             if self.is_compressed {
@@ -1147,6 +1276,11 @@ impl FileCacheEntry {
     }
 
     fn read_file_cache(&self, chunk: &dyn BlobChunkInfo, buffer: &mut [u8]) -> Result<()> {
+        if chunk.is_zeroed() {
+            buffer.fill(0);
+            return Ok(());
+        }
+
         if self.is_compressed {
             let offset = chunk.compressed_offset();
             let size = if self.is_legacy_stargz() {
@@ -1155,14 +1289,19 @@ impl FileCacheEntry {
                 chunk.compressed_size() as u64
             };
             let mut reader = FileRangeReader::new(&self.file, offset, size);
-            if self.compressor() == compress::Algorithm::Lz4Block {
+            // Block based algorithms have no stream decoder, decompress the whole chunk at once.
+            if matches!(
+                self.compressor(),
+                compress::Algorithm::Lz4Block | compress::Algorithm::Snappy
+            ) {
                 let mut buf = alloc_buf(size as usize);
                 reader.read_exact(&mut buf)?;
                 let size = compress::decompress(&buf, buffer, self.compressor)?;
                 if size != buffer.len() {
-                    return Err(einval!(
-                        "data size decoded by lz4_block doesn't match expected"
-                    ));
+                    return Err(einval!(format!(
+                        "data size decoded by {} doesn't match expected",
+                        self.compressor()
+                    )));
                 }
             } else {
                 let mut decoder = Decoder::new(reader, self.compressor())?;
@@ -1170,10 +1309,18 @@ impl FileCacheEntry {
             }
         } else {
             let offset = chunk.uncompressed_offset();
-            let size = chunk.uncompressed_size() as u64;
-            FileRangeReader::new(&self.file, offset, size).read_exact(buffer)?;
+            let size = self.read_cache_file(&mut [IoSliceMut::new(buffer)], offset)?;
+            if size != buffer.len() {
+                return Err(eio!("failed to read data from file cache"));
+            }
+        }
+        if !self.validated_chunks.is_validated(chunk) {
+            let need_validation = self.need_validation();
+            self.validate_chunk_data(chunk, buffer, need_validation)?;
+            if need_validation {
+                self.validated_chunks.set_validated(chunk);
+            }
         }
-        self.validate_chunk_data(chunk, buffer, false)?;
         Ok(())
     }
 
@@ -1427,9 +1574,126 @@ impl FileIoMergeState {
     }
 }
 
+/// Get the uncompressed blob range covered by `bios` if all chunks are ready and laid out
+/// contiguously in the uncompressed blob.
+fn get_ready_contiguous_range(chunk_map: &dyn ChunkMap, bios: &[BlobIoDesc]) -> Option<(u64, u64)> {
+    let first = bios.first()?;
+    let start = first.chunkinfo.uncompressed_offset() + first.offset as u64;
+    let mut end = start;
+
+    for bio in bios {
+        let chunk = &bio.chunkinfo;
+        if chunk.uncompressed_offset() + bio.offset as u64 != end
+            || !chunk_map.is_ready(chunk).unwrap_or(false)
+        {
+            return None;
+        }
+        end += bio.size as u64;
+    }
+
+    Some((start, end - start))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::state::DigestedChunkMap;
+    use crate::device::{BlobChunkFlags, BlobFeatures};
+    use crate::test::MockChunkInfo;
+    use nydus_utils::digest::RafsDigest;
+
+    #[test]
+    fn test_chunk_validation_map() {
+        let chunk = |index: u32| MockChunkInfo {
+            index,
+            ..Default::default()
+        };
+        let map = ChunkValidationMap::new(65);
+        assert_eq!(map.bits.len(), 2);
+        assert!(!map.is_validated(&chunk(0)));
+        map.set_validated(&chunk(0));
+        map.set_validated(&chunk(64));
+        assert!(map.is_validated(&chunk(0)));
+        assert!(!map.is_validated(&chunk(1)));
+        assert!(!map.is_validated(&chunk(63)));
+        assert!(map.is_validated(&chunk(64)));
+
+        // Out of range chunks are never treated as validated.
+        map.set_validated(&chunk(128));
+        assert!(!map.is_validated(&chunk(128)));
+        assert!(ChunkValidationMap::new(0).bits.is_empty());
+    }
+
+    #[test]
+    fn test_get_ready_contiguous_range() {
+        let blob_info = Arc::new(BlobInfo::new(
+            1,
+            "test1".to_owned(),
+            0x200000,
+            0x100000,
+            0x1000,
+            4,
+            BlobFeatures::V5_NO_EXT_BLOB_TABLE,
+        ));
+        let chunks = (0..4u64)
+            .map(|idx| {
+                Arc::new(MockChunkInfo {
+                    block_id: RafsDigest::from_buf(&[idx as u8], digest::Algorithm::Sha256),
+                    blob_index: 1,
+                    flags: BlobChunkFlags::empty(),
+                    compress_size: 0x800,
+                    uncompress_size: 0x1000,
+                    compress_offset: idx * 0x800,
+                    uncompress_offset: 0x2000 + idx * 0x1000,
+                    file_offset: idx * 0x1000,
+                    index: idx as u32,
+                    reserved: 0,
+                }) as Arc<dyn BlobChunkInfo>
+            })
+            .collect::<Vec<_>>();
+        let bios = |chunks: &[Arc<dyn BlobChunkInfo>], last_size: u32| {
+            chunks
+                .iter()
+                .enumerate()
+                .map(|(idx, c)| {
+                    let size = if idx == chunks.len() - 1 {
+                        last_size
+                    } else {
+                        0x1000
+                    };
+                    BlobIoDesc::new(blob_info.clone(), c.clone().into(), 0, size, false)
+                })
+                .collect::<Vec<_>>()
+        };
+        let chunk_map = DigestedChunkMap::new();
+
+        assert!(get_ready_contiguous_range(&chunk_map, &[]).is_none());
+
+        // Partially cached files are excluded.
+        for chunk in chunks.iter().take(3) {
+            chunk_map
+                .set_ready_and_clear_pending(chunk.as_ref())
+                .unwrap();
+        }
+        assert!(get_ready_contiguous_range(&chunk_map, &bios(&chunks, 0x800)).is_none());
+        assert_eq!(
+            get_ready_contiguous_range(&chunk_map, &bios(&chunks[..3], 0x800)),
+            Some((0x2000, 0x2800))
+        );
+
+        // Fully cached files are included.
+        chunk_map
+            .set_ready_and_clear_pending(chunks[3].as_ref())
+            .unwrap();
+        assert_eq!(
+            get_ready_contiguous_range(&chunk_map, &bios(&chunks, 0x800)),
+            Some((0x2000, 0x3800))
+        );
+
+        // Files with chunks out of order in the blob are excluded.
+        let reordered = vec![chunks[0].clone(), chunks[2].clone(), chunks[1].clone()];
+        assert!(get_ready_contiguous_range(&chunk_map, &bios(&reordered, 0x1000)).is_none());
+    }
 
     #[test]
     fn test_data_buffer() {
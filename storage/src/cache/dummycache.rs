@@ -24,6 +24,7 @@ use std::sync::Arc;
 
 use fuse_backend_rs::file_buf::FileVolatileSlice;
 use nydus_api::http::CacheConfig;
+use nydus_utils::crypt::{self, CipherKey};
 use nydus_utils::{compress, digest};
 
 use crate::backend::{BlobBackend, BlobReader};
@@ -40,8 +41,11 @@ struct DummyCache {
     reader: Arc<dyn BlobReader>,
     compressor: compress::Algorithm,
     digester: digest::Algorithm,
+    cipher: crypt::Algorithm,
+    cipher_key: Option<Arc<CipherKey>>,
+    is_foreign_layer: bool,
     is_legacy_stargz: bool,
-    need_validation: bool,
+    need_validation: AtomicBool,
 }
 
 impl BlobCache for DummyCache {
@@ -65,12 +69,25 @@ impl BlobCache for DummyCache {
         self.digester
     }
 
+    fn cipher(&self) -> crypt::Algorithm {
+        self.cipher
+    }
+
+    fn cipher_key(&self) -> Option<&Arc<CipherKey>> {
+        self.cipher_key.as_ref()
+    }
+
     fn is_legacy_stargz(&self) -> bool {
         self.is_legacy_stargz
     }
 
     fn need_validation(&self) -> bool {
+        self.need_validation.load(Ordering::Relaxed)
+    }
+
+    fn set_validation(&self, enable: bool) {
         self.need_validation
+            .store(enable && !self.is_foreign_layer, Ordering::Relaxed);
     }
 
     fn reader(&self) -> &dyn BlobReader {
@@ -216,8 +233,11 @@ impl BlobCacheMgr for DummyCacheMgr {
             reader,
             compressor: blob_info.compressor(),
             digester: blob_info.digester(),
+            cipher: blob_info.cipher(),
+            cipher_key: blob_info.cipher_key().cloned(),
+            is_foreign_layer: blob_info.is_foreign_layer(),
             is_legacy_stargz: blob_info.is_legacy_stargz(),
-            need_validation: self.need_validation && !blob_info.is_legacy_stargz(),
+            need_validation: AtomicBool::new(self.need_validation && !blob_info.is_foreign_layer()),
         }))
     }
 
@@ -229,3 +249,188 @@ impl Drop for DummyCacheMgr {
         self.destroy();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use nydus_utils::digest::RafsDigest;
+    use nydus_utils::metrics::BackendMetrics;
+
+    use super::*;
+    use crate::backend::BackendResult;
+    use crate::device::BlobChunkFlags;
+    use crate::test::MockChunkInfo;
+
+    struct CountingReader {
+        reads: AtomicUsize,
+        metrics: Arc<BackendMetrics>,
+    }
+
+    impl BlobReader for CountingReader {
+        fn blob_size(&self) -> BackendResult<u64> {
+            Ok(0x1000)
+        }
+
+        fn try_read(&self, buf: &mut [u8], _offset: u64) -> BackendResult<usize> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            buf.fill(0x5a);
+            Ok(buf.len())
+        }
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+    }
+
+    fn new_dummy_cache() -> (DummyCache, Arc<CountingReader>) {
+        let reader = Arc::new(CountingReader {
+            reads: AtomicUsize::new(0),
+            metrics: BackendMetrics::new("id", "mock"),
+        });
+        let cache = DummyCache {
+            blob_id: "blob".to_string(),
+            chunk_map: Arc::new(NoopChunkMap::new(false)),
+            reader: reader.clone(),
+            compressor: compress::Algorithm::None,
+            digester: digest::Algorithm::Blake3,
+            cipher: crypt::Algorithm::None,
+            cipher_key: None,
+            is_foreign_layer: false,
+            is_legacy_stargz: false,
+            need_validation: AtomicBool::new(false),
+        };
+
+        (cache, reader)
+    }
+
+    #[test]
+    fn test_read_zeroed_chunk() {
+        let (cache, reader) = new_dummy_cache();
+        let chunk = MockChunkInfo {
+            flags: BlobChunkFlags::ZEROED,
+            compress_offset: 0x800,
+            uncompress_size: 0x1000,
+            ..Default::default()
+        };
+
+        let mut buf = vec![0xffu8; 0x1000];
+        assert!(cache
+            .read_chunk_from_backend(&chunk, &mut buf)
+            .unwrap()
+            .is_none());
+        assert!(buf.iter().all(|v| *v == 0));
+        assert_eq!(reader.reads.load(Ordering::Relaxed), 0);
+
+        let mut buf = vec![0xffu8; 0x800];
+        assert!(cache.read_chunk_from_backend(&chunk, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_read_zeroed_chunks_in_batch() {
+        let (cache, reader) = new_dummy_cache();
+        let zeroed = Arc::new(MockChunkInfo {
+            flags: BlobChunkFlags::ZEROED,
+            compress_offset: 0x800,
+            uncompress_offset: 0x800,
+            uncompress_size: 0x1000,
+            index: 1,
+            ..Default::default()
+        }) as Arc<dyn BlobChunkInfo>;
+
+        let chunks = vec![zeroed.clone()];
+        let bufs = cache
+            .read_chunks_from_backend(0x800, 0, &chunks, false)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(bufs.len(), 1);
+        assert_eq!(bufs[0], vec![0u8; 0x1000]);
+        assert_eq!(reader.reads.load(Ordering::Relaxed), 0);
+
+        // Zeroed chunks don't contribute any data to ranges merged with normal chunks.
+        let normal = |index: u32, offset: u64| {
+            Arc::new(MockChunkInfo {
+                compress_offset: offset,
+                compress_size: 0x800,
+                uncompress_offset: offset,
+                uncompress_size: 0x800,
+                index,
+                ..Default::default()
+            }) as Arc<dyn BlobChunkInfo>
+        };
+        let chunks = vec![normal(0, 0), zeroed, normal(2, 0x800)];
+        let bufs = cache
+            .read_chunks_from_backend(0, 0x1000, &chunks, false)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(bufs.len(), 3);
+        assert_eq!(bufs[0], vec![0x5au8; 0x800]);
+        assert_eq!(bufs[1], vec![0u8; 0x1000]);
+        assert_eq!(bufs[2], vec![0x5au8; 0x800]);
+        assert_eq!(reader.reads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_toggle_validation_with_concurrent_reads() {
+        let (cache, _reader) = new_dummy_cache();
+        let cache = Arc::new(cache);
+        let good = Arc::new(MockChunkInfo {
+            block_id: RafsDigest::from_buf(&[0x5au8; 0x1000], digest::Algorithm::Blake3),
+            compress_size: 0x1000,
+            uncompress_size: 0x1000,
+            ..Default::default()
+        });
+        // Data from the backend doesn't match the default digest.
+        let bad = Arc::new(MockChunkInfo {
+            compress_size: 0x1000,
+            uncompress_size: 0x1000,
+            index: 1,
+            ..Default::default()
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+        for _ in 0..4 {
+            let (cache, good, bad, stop) = (cache.clone(), good.clone(), bad.clone(), stop.clone());
+            threads.push(std::thread::spawn(move || {
+                let mut buf = vec![0u8; 0x1000];
+                while !stop.load(Ordering::Relaxed) {
+                    cache
+                        .read_chunk_from_backend(good.as_ref(), &mut buf)
+                        .unwrap();
+                    if let Err(e) = cache.read_chunk_from_backend(bad.as_ref(), &mut buf) {
+                        assert_eq!(e.raw_os_error(), Some(libc::EIO));
+                    }
+                }
+            }));
+        }
+        for idx in 0..1000 {
+            cache.set_validation(idx % 2 == 0);
+            std::thread::yield_now();
+        }
+        stop.store(true, Ordering::Relaxed);
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let mut buf = vec![0u8; 0x1000];
+        cache.set_validation(true);
+        assert!(cache.need_validation());
+        assert!(cache
+            .read_chunk_from_backend(bad.as_ref(), &mut buf)
+            .is_err());
+        cache.set_validation(false);
+        assert!(!cache.need_validation());
+        assert!(cache
+            .read_chunk_from_backend(bad.as_ref(), &mut buf)
+            .is_ok());
+
+        // Data from foreign layers is never validated.
+        let (mut cache, _reader) = new_dummy_cache();
+        cache.is_foreign_layer = true;
+        cache.set_validation(true);
+        assert!(!cache.need_validation());
+    }
+}
@@ -15,8 +15,12 @@ use nydus_utils::metrics::BlobcacheMetrics;
 use tokio::runtime::Runtime;
 
 use crate::backend::BlobBackend;
-use crate::cache::cachedfile::{FileCacheEntry, FileCacheMeta};
-use crate::cache::state::{BlobStateMap, ChunkMap, DigestedChunkMap, IndexedChunkMap};
+use crate::cache::cachedfile::{ChunkValidationMap, FileCacheEntry, FileCacheMeta};
+use crate::cache::state::{
+    BlobStateMap, ChunkMap, DigestedChunkMap, IndexedChunkMap, InflightTracerRegistry,
+};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::cache::uring::{IoUringReader, IO_URING_DEFAULT_ENTRIES};
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobCacheMgr};
 use crate::device::{BlobFeatures, BlobInfo};
@@ -30,6 +34,7 @@ pub struct FileCacheMgr {
     blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
     backend: Arc<dyn BlobBackend>,
     metrics: Arc<BlobcacheMetrics>,
+    inflight_tracers: Arc<InflightTracerRegistry>,
     prefetch_config: Arc<AsyncPrefetchConfig>,
     runtime: Arc<Runtime>,
     worker_mgr: Arc<AsyncWorkerMgr>,
@@ -38,6 +43,8 @@ pub struct FileCacheMgr {
     disable_indexed_map: bool,
     is_compressed: bool,
     closed: Arc<AtomicBool>,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    io_uring: Option<Arc<IoUringReader>>,
 }
 
 impl FileCacheMgr {
@@ -46,6 +53,7 @@ impl FileCacheMgr {
         config: CacheConfig,
         backend: Arc<dyn BlobBackend>,
         runtime: Arc<Runtime>,
+        inflight_tracers: Arc<InflightTracerRegistry>,
         id: &str,
     ) -> Result<FileCacheMgr> {
         let blob_config: FileCacheConfig =
@@ -54,11 +62,28 @@ impl FileCacheMgr {
         let metrics = BlobcacheMetrics::new(id, work_dir);
         let prefetch_config: Arc<AsyncPrefetchConfig> = Arc::new(config.prefetch_config.into());
         let worker_mgr = AsyncWorkerMgr::new(metrics.clone(), prefetch_config.clone())?;
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let io_uring = if blob_config.enable_io_uring {
+            match IoUringReader::new(IO_URING_DEFAULT_ENTRIES) {
+                Ok(v) => Some(Arc::new(v)),
+                Err(e) => {
+                    warn!("io_uring is unsupported, fall back to preadv, {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        if blob_config.enable_io_uring {
+            warn!("io_uring support is not enabled at build time, fall back to preadv");
+        }
 
         Ok(FileCacheMgr {
             blobs: Arc::new(RwLock::new(HashMap::new())),
             backend,
             metrics,
+            inflight_tracers,
             prefetch_config,
             runtime,
             worker_mgr: Arc::new(worker_mgr),
@@ -67,6 +92,8 @@ impl FileCacheMgr {
             validate: config.cache_validate,
             is_compressed: config.cache_compressed,
             closed: Arc::new(AtomicBool::new(false)),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring,
         })
     }
 
@@ -149,6 +176,12 @@ impl BlobCacheMgr for FileCacheMgr {
         self.backend.as_ref()
     }
 
+    fn backend_recovered(&self) {
+        for entry in self.blobs.read().unwrap().values() {
+            entry.reset_error_state();
+        }
+    }
+
     fn get_blob_cache(&self, blob_info: &Arc<BlobInfo>) -> Result<Arc<dyn BlobCache>> {
         self.get_or_create_cache_entry(blob_info)
             .map(|v| v as Arc<dyn BlobCache>)
@@ -189,9 +222,14 @@ impl FileCacheEntry {
         let compressor = blob_info.compressor();
         let digester = blob_info.digester();
         let is_legacy_stargz = blob_info.is_legacy_stargz();
-        let is_compressed = mgr.is_compressed && compressor != compress::Algorithm::None;
+        // Encrypted chunk data is always cached in plaintext, after decryption and decompression.
+        let is_compressed = mgr.is_compressed
+            && compressor != compress::Algorithm::None
+            && !blob_info.is_encrypted();
         let is_zran = blob_info.meta_flags() & BLOB_META_FEATURE_ZRAN != 0;
-        let need_validation = (mgr.validate || !is_direct_chunkmap) && !is_legacy_stargz;
+        let need_validation =
+            (mgr.validate || !is_direct_chunkmap) && !blob_info.is_foreign_layer();
+        let chunk_count = blob_info.chunk_count();
         trace!(
             "filecache entry: compressed {}, direct {}, legacy_stargz {}, zran {}",
             mgr.is_compressed,
@@ -245,9 +283,12 @@ impl FileCacheEntry {
             is_legacy_stargz,
             is_zran,
             dio_enabled: false,
-            need_validation,
+            need_validation: AtomicBool::new(need_validation),
+            validated_chunks: ChunkValidationMap::new(chunk_count),
             batch_size: RAFS_DEFAULT_CHUNK_SIZE,
             prefetch_config,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring: mgr.io_uring.clone(),
         })
     }
 
@@ -267,11 +308,15 @@ impl FileCacheEntry {
             direct_chunkmap = false;
             Arc::new(BlobStateMap::from(DigestedChunkMap::new()))
         } else {
-            Arc::new(BlobStateMap::from(IndexedChunkMap::new(
-                blob_file,
-                blob_info.chunk_count(),
-                true,
-            )?))
+            // Share inflight fetches with other blob caches using the same cache file, which is
+            // identified by its canonical path in case the work directory is spelled differently.
+            let cache_file = std::fs::canonicalize(&mgr.work_dir)?.join(blob_info.blob_id());
+            Arc::new(BlobStateMap::new_shared(
+                IndexedChunkMap::new(blob_file, blob_info.chunk_count(), true)?,
+                &mgr.inflight_tracers,
+                &cache_file.to_string_lossy(),
+                mgr.metrics.clone(),
+            ))
         };
 
         Ok((chunk_map, direct_chunkmap))
@@ -303,10 +348,59 @@ pub mod blob_cache_tests {
     };
     */
 
+    use fuse_backend_rs::file_buf::FileVolatileSlice;
+    use nydus_utils::metrics::BackendMetrics;
     use vmm_sys_util::tempdir::TempDir;
     use vmm_sys_util::tempfile::TempFile;
 
     use super::*;
+    use crate::backend::{BackendError, BackendResult, BlobReader};
+    use crate::device::{BlobChunkFlags, BlobChunkInfo, BlobIoDesc, BlobIoVec};
+    use crate::factory::ASYNC_RUNTIME;
+    use crate::test::MockChunkInfo;
+
+    // Mock backend serving a blob of compressed data, which may be switched off.
+    struct CompressedBackend {
+        data: Arc<Vec<u8>>,
+        up: Arc<AtomicBool>,
+        metrics: Arc<BackendMetrics>,
+    }
+
+    impl BlobReader for CompressedBackend {
+        fn blob_size(&self) -> BackendResult<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+            if !self.up.load(Ordering::Relaxed) {
+                return Err(BackendError::Unsupported("backend is down".to_string()));
+            }
+            let offset = offset as usize;
+            let size = std::cmp::min(buf.len(), self.data.len() - offset);
+            buf[..size].copy_from_slice(&self.data[offset..offset + size]);
+            Ok(size)
+        }
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+    }
+
+    impl BlobBackend for CompressedBackend {
+        fn shutdown(&self) {}
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+
+        fn get_reader(&self, _blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+            Ok(Arc::new(CompressedBackend {
+                data: self.data.clone(),
+                up: self.up.clone(),
+                metrics: self.metrics.clone(),
+            }))
+        }
+    }
 
     #[test]
     fn test_blob_cache_config() {
@@ -338,6 +432,93 @@ pub mod blob_cache_tests {
         assert!(blob_config.get_work_dir().is_err());
     }
 
+    #[test]
+    fn test_read_snappy_chunk_from_compressed_cache() {
+        let tmp_dir = TempDir::new().unwrap();
+        let data: Vec<u8> = (0..0x1000u32).map(|v| (v / 16) as u8).collect();
+        let (compressed, is_compressed) =
+            compress::compress(&data, compress::Algorithm::Snappy).unwrap();
+        assert!(is_compressed);
+        let compressed = compressed.into_owned();
+        let compressed_size = compressed.len() as u32;
+
+        let up = Arc::new(AtomicBool::new(true));
+        let backend = Arc::new(CompressedBackend {
+            data: Arc::new(compressed),
+            up: up.clone(),
+            metrics: BackendMetrics::new("test_read_snappy_chunk", "mock"),
+        });
+        let config = CacheConfig {
+            cache_type: "blobcache".to_string(),
+            cache_compressed: true,
+            cache_config: serde_json::json!({ "work_dir": tmp_dir.as_path() }),
+            ..Default::default()
+        };
+        let mgr = FileCacheMgr::new(
+            config,
+            backend.clone(),
+            ASYNC_RUNTIME.clone(),
+            Arc::new(InflightTracerRegistry::default()),
+            "test_read_snappy_chunk",
+        )
+        .unwrap();
+        mgr.init().unwrap();
+
+        let mut blob_info = BlobInfo::new(
+            0,
+            "snappy-blob".to_string(),
+            0x1000,
+            compressed_size as u64,
+            0x1000,
+            1,
+            BlobFeatures::empty(),
+        );
+        blob_info.set_compressor(compress::Algorithm::Snappy);
+        let blob_info = Arc::new(blob_info);
+        let cache = mgr.get_blob_cache(&blob_info).unwrap();
+        let chunk: Arc<dyn BlobChunkInfo> = Arc::new(MockChunkInfo {
+            flags: BlobChunkFlags::COMPRESSED,
+            compress_size: compressed_size,
+            uncompress_size: 0x1000,
+            ..Default::default()
+        });
+
+        let read = |buf: &mut Vec<u8>| {
+            let mut iovec = BlobIoVec::new(blob_info.clone());
+            iovec.push(BlobIoDesc::new(
+                blob_info.clone(),
+                chunk.clone().into(),
+                0,
+                0x1000,
+                true,
+            ));
+            // Safe because the slice is within the buffer.
+            let slice = unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+            cache.read(&mut iovec, &[slice])
+        };
+
+        // Fetch the chunk from the backend and wait for it to be persisted into the cache file.
+        let mut buf = vec![0u8; 0x1000];
+        assert_eq!(read(&mut buf).unwrap(), 0x1000);
+        assert_eq!(buf, data);
+        for _ in 0..100 {
+            if cache.get_chunk_map().is_ready(chunk.as_ref()).unwrap() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(cache.get_chunk_map().is_ready(chunk.as_ref()).unwrap());
+
+        // Decompress the snappy chunk from the cache file with the backend switched off.
+        up.store(false, Ordering::Relaxed);
+        buf.fill(0);
+        assert_eq!(read(&mut buf).unwrap(), 0x1000);
+        assert_eq!(buf, data);
+
+        mgr.destroy();
+        backend.metrics.release().unwrap();
+    }
+
     /*
        #[test]
        fn test_add() {
@@ -13,7 +13,7 @@ use nydus_api::http::{CacheConfig, FsCacheConfig};
 use nydus_utils::metrics::BlobcacheMetrics;
 
 use crate::backend::BlobBackend;
-use crate::cache::cachedfile::{FileCacheEntry, FileCacheMeta};
+use crate::cache::cachedfile::{ChunkValidationMap, FileCacheEntry, FileCacheMeta};
 use crate::cache::state::{BlobStateMap, IndexedChunkMap};
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobCacheMgr};
@@ -153,6 +153,12 @@ impl BlobCacheMgr for FsCacheMgr {
         self.backend.as_ref()
     }
 
+    fn backend_recovered(&self) {
+        for entry in self.blobs.read().unwrap().values() {
+            entry.reset_error_state();
+        }
+    }
+
     fn get_blob_cache(&self, blob_info: &Arc<BlobInfo>) -> Result<Arc<dyn BlobCache>> {
         self.get_or_create_cache_entry(blob_info)
             .map(|v| v as Arc<dyn BlobCache>)
@@ -225,6 +231,7 @@ impl FileCacheEntry {
             ));
         };
         let is_zran = blob_info.meta_flags() & BLOB_META_FEATURE_ZRAN != 0;
+        let need_validation = mgr.need_validation && !blob_info.is_foreign_layer();
 
         Ok(FileCacheEntry {
             blob_info: blob_info.clone(),
@@ -247,9 +254,12 @@ impl FileCacheEntry {
             is_legacy_stargz: blob_info.is_legacy_stargz(),
             is_zran,
             dio_enabled: true,
-            need_validation: mgr.need_validation && !blob_info.is_legacy_stargz(),
+            need_validation: AtomicBool::new(need_validation),
+            validated_chunks: ChunkValidationMap::new(blob_info.chunk_count()),
             batch_size: RAFS_DEFAULT_CHUNK_SIZE,
             prefetch_config,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring: None,
         })
     }
 }
@@ -0,0 +1,866 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! An in-memory implementation of the [BlobCacheMgr](../trait.BlobCacheMgr.html) trait.
+//!
+//! The [MemoryCacheMgr](struct.MemoryCacheMgr.html) keeps decompressed chunk data in a
+//! size-bounded LRU cache in process memory instead of on local storage, which suits ephemeral
+//! workloads without a writable local disk or bottlenecked by local disk IO. All blobs managed by
+//! the same manager share one memory budget, and chunks warmed by data prefetching are accounted
+//! against the budget in the same way as chunks fetched on demand.
+//!
+//! Chunk data is validated against its digest when fetched from the storage backend if
+//! `digest_validate` is enabled, and then served from memory without validating again because the
+//! cached data never leaves the process. So digest validation only costs on cache misses.
+//!
+//! Eviction is an approximated LRU with second chance: readers only take a shared lock on one
+//! shard and bump an access stamp. Inserting a chunk pops at most one victim from the eviction
+//! queue, and a background thread evicts the remaining chunks over budget, so the read path never
+//! waits for eviction to finish.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Result;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+
+use fuse_backend_rs::file_buf::FileVolatileSlice;
+use nydus_api::http::{CacheConfig, MemoryCacheConfig};
+use nydus_utils::crypt::{self, CipherKey};
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+use nydus_utils::{compress, digest};
+
+use crate::backend::{BlobBackend, BlobReader};
+use crate::cache::cachedfile::FileCacheEntry;
+use crate::cache::state::{BlobStateMap, ChunkIndexGetter, ChunkMap};
+use crate::cache::worker::{AsyncPrefetchConfig, AsyncPrefetchMessage, AsyncWorkerMgr};
+use crate::cache::{BlobCache, BlobCacheMgr, BlobIoMergeState};
+use crate::device::{
+    BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoRange, BlobIoVec, BlobPrefetchRequest,
+};
+use crate::meta::BLOB_META_FEATURE_ZRAN;
+use crate::utils::{alloc_buf, copyv};
+use crate::{StorageError, StorageResult, RAFS_MERGING_SIZE_TO_GAP_SHIFT};
+
+/// Number of shards of the chunk LRU cache, to reduce lock contention.
+const MEMORY_CACHE_SHARDS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct ChunkKey {
+    // Per manager unique key for the blob.
+    blob: u32,
+    // Chunks with the same compressed offset share the same data within a blob.
+    offset: u64,
+}
+
+struct LruEntry {
+    data: Arc<Vec<u8>>,
+    generation: u64,
+    stamp: AtomicU64,
+}
+
+struct LruVictim {
+    key: ChunkKey,
+    generation: u64,
+    stamp: u64,
+}
+
+#[derive(Default)]
+struct EvictorState {
+    pending: bool,
+    stopped: bool,
+}
+
+/// A size-bounded concurrent LRU cache for decompressed chunk data.
+struct ChunkLru {
+    shards: Vec<RwLock<HashMap<ChunkKey, LruEntry>>>,
+    victims: Mutex<VecDeque<LruVictim>>,
+    evictor: Mutex<EvictorState>,
+    evictor_cond: Condvar,
+    clock: AtomicU64,
+    usage: AtomicU64,
+    capacity: u64,
+    metrics: Arc<BlobcacheMetrics>,
+}
+
+impl ChunkLru {
+    fn new(capacity: u64, metrics: Arc<BlobcacheMetrics>) -> Self {
+        let mut shards = Vec::with_capacity(MEMORY_CACHE_SHARDS);
+        for _ in 0..MEMORY_CACHE_SHARDS {
+            shards.push(RwLock::new(HashMap::new()));
+        }
+        metrics.memory_capacity.add(capacity);
+
+        ChunkLru {
+            shards,
+            victims: Mutex::new(VecDeque::new()),
+            evictor: Mutex::new(EvictorState::default()),
+            evictor_cond: Condvar::new(),
+            clock: AtomicU64::new(0),
+            usage: AtomicU64::new(0),
+            capacity,
+            metrics,
+        }
+    }
+
+    fn shard(&self, key: &ChunkKey) -> &RwLock<HashMap<ChunkKey, LruEntry>> {
+        let hash = key.offset.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ key.blob as u64;
+        &self.shards[(hash >> 32) as usize % MEMORY_CACHE_SHARDS]
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    fn contains(&self, key: &ChunkKey) -> bool {
+        self.shard(key).read().unwrap().contains_key(key)
+    }
+
+    fn get(&self, key: &ChunkKey) -> Option<Arc<Vec<u8>>> {
+        let shard = self.shard(key).read().unwrap();
+        shard.get(key).map(|entry| {
+            entry.stamp.store(self.tick(), Ordering::Relaxed);
+            entry.data.clone()
+        })
+    }
+
+    /// Insert chunk data into the cache, evicting least recently used chunks if over budget.
+    ///
+    /// At most one victim is handled inline, the background evictor is woken up to evict the rest.
+    ///
+    /// Return false if the chunk is too big to be cached.
+    fn insert(&self, key: ChunkKey, data: Arc<Vec<u8>>) -> bool {
+        let size = data.len() as u64;
+        if size > self.capacity {
+            return false;
+        }
+
+        let generation = self.tick();
+        {
+            let mut shard = self.shard(&key).write().unwrap();
+            if shard.contains_key(&key) {
+                return true;
+            }
+            shard.insert(
+                key,
+                LruEntry {
+                    data,
+                    generation,
+                    stamp: AtomicU64::new(generation),
+                },
+            );
+        }
+        self.usage.fetch_add(size, Ordering::AcqRel);
+        self.metrics.memory_usage.add(size);
+        self.metrics.entries_count.inc();
+        self.victims.lock().unwrap().push_back(LruVictim {
+            key,
+            generation,
+            stamp: generation,
+        });
+
+        if self.over_budget() {
+            self.evict_one();
+            if self.over_budget() {
+                let mut state = self.evictor.lock().unwrap();
+                state.pending = true;
+                self.evictor_cond.notify_one();
+            }
+        }
+
+        true
+    }
+
+    fn over_budget(&self) -> bool {
+        self.usage.load(Ordering::Acquire) > self.capacity
+    }
+
+    // Evict chunks until memory usage is within budget.
+    fn evict(&self) {
+        while self.over_budget() && self.evict_one() {}
+    }
+
+    // Pop one victim from the eviction queue and evict it. Chunks accessed since queued get a
+    // second chance and are queued again.
+    //
+    // Return false if the eviction queue is empty.
+    fn evict_one(&self) -> bool {
+        let victim = match self.victims.lock().unwrap().pop_front() {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let mut shard = self.shard(&victim.key).write().unwrap();
+        let stamp = match shard.get(&victim.key) {
+            Some(entry) if entry.generation == victim.generation => {
+                entry.stamp.load(Ordering::Relaxed)
+            }
+            // Stale victim of a chunk already evicted or purged.
+            _ => return true,
+        };
+        if stamp != victim.stamp {
+            drop(shard);
+            self.victims
+                .lock()
+                .unwrap()
+                .push_back(LruVictim { stamp, ..victim });
+        } else if let Some(entry) = shard.remove(&victim.key) {
+            drop(shard);
+            self.release(entry.data.len() as u64, 1);
+            self.metrics.evicted_chunks.inc();
+        }
+
+        true
+    }
+
+    /// Start the background thread to evict chunks over budget.
+    fn start_evictor(lru: Arc<ChunkLru>) -> Result<()> {
+        thread::Builder::new()
+            .name("nydus_memcache_evictor".to_string())
+            .spawn(move || {
+                while lru.wait_for_eviction() {
+                    lru.evict();
+                }
+                info!("storage: memory cache evictor exits.")
+            })
+            .map(|_| ())
+    }
+
+    fn stop_evictor(&self) {
+        let mut state = self.evictor.lock().unwrap();
+        state.stopped = true;
+        self.evictor_cond.notify_all();
+    }
+
+    // Wait until eviction is requested, return false if the evictor has been stopped.
+    fn wait_for_eviction(&self) -> bool {
+        let mut state = self.evictor.lock().unwrap();
+        while !state.pending && !state.stopped {
+            state = self.evictor_cond.wait(state).unwrap();
+        }
+        state.pending = false;
+        !state.stopped
+    }
+
+    /// Purge all cached chunks of a blob.
+    fn purge(&self, blob: u32) {
+        for shard in self.shards.iter() {
+            let mut size = 0;
+            let mut count = 0;
+            shard.write().unwrap().retain(|k, v| {
+                if k.blob == blob {
+                    size += v.data.len() as u64;
+                    count += 1;
+                }
+                k.blob != blob
+            });
+            if count > 0 {
+                self.release(size, count);
+            }
+        }
+        self.victims.lock().unwrap().retain(|v| v.key.blob != blob);
+    }
+
+    fn release(&self, size: u64, count: u64) {
+        self.usage.fetch_sub(size, Ordering::AcqRel);
+        self.metrics.memory_usage.sub(size);
+        self.metrics.entries_count.sub(count);
+    }
+}
+
+/// An implementation of [ChunkMap](../state/trait.ChunkMap.html) to track chunks cached in memory.
+///
+/// A chunk is ready if it's still in the LRU cache, so an evicted chunk becomes not ready again.
+struct MemoryChunkMap {
+    blob: u32,
+    lru: Arc<ChunkLru>,
+}
+
+impl ChunkMap for MemoryChunkMap {
+    fn is_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        Ok(self.lru.contains(&ChunkKey {
+            blob: self.blob,
+            offset: Self::get_index(chunk),
+        }))
+    }
+
+    fn set_ready_and_clear_pending(&self, _chunk: &dyn BlobChunkInfo) -> Result<()> {
+        // Chunk data has already been inserted into the LRU cache by the caller.
+        Ok(())
+    }
+}
+
+impl ChunkIndexGetter for MemoryChunkMap {
+    type Index = u64;
+
+    fn get_index(chunk: &dyn BlobChunkInfo) -> Self::Index {
+        chunk.compressed_offset()
+    }
+}
+
+/// An implementation of [BlobCache](../trait.BlobCache.html) to cache chunk data in memory.
+pub struct MemoryCacheEntry {
+    blob_info: Arc<BlobInfo>,
+    blob_key: u32,
+    chunk_map: Arc<dyn ChunkMap>,
+    lru: Arc<ChunkLru>,
+    metrics: Arc<BlobcacheMetrics>,
+    prefetch_config: Arc<AsyncPrefetchConfig>,
+    prefetch_state: AtomicU32,
+    reader: Arc<dyn BlobReader>,
+    workers: Arc<AsyncWorkerMgr>,
+
+    blob_compressed_size: u64,
+    compressor: compress::Algorithm,
+    digester: digest::Algorithm,
+    is_legacy_stargz: bool,
+    need_validation: AtomicBool,
+}
+
+impl MemoryCacheEntry {
+    fn chunk_key(&self, chunk: &dyn BlobChunkInfo) -> ChunkKey {
+        ChunkKey {
+            blob: self.blob_key,
+            offset: MemoryChunkMap::get_index(chunk),
+        }
+    }
+
+    fn prefetch_batch_size(&self) -> u64 {
+        if self.prefetch_config.merging_size < 0x2_0000 {
+            0x2_0000
+        } else {
+            self.prefetch_config.merging_size as u64
+        }
+    }
+
+    // Get decompressed data of the chunk, from the LRU cache or the storage backend.
+    fn get_chunk_data(&self, chunk: &dyn BlobChunkInfo) -> Result<Arc<Vec<u8>>> {
+        let key = self.chunk_key(chunk);
+        if let Some(data) = self.lru.get(&key) {
+            self.metrics.whole_hits.inc();
+            return Ok(data);
+        }
+
+        let pending = match self.chunk_map.check_ready_and_mark_pending(chunk) {
+            Ok(true) => {
+                if let Some(data) = self.lru.get(&key) {
+                    self.metrics.whole_hits.inc();
+                    return Ok(data);
+                }
+                // Evicted right after being fetched by another thread.
+                false
+            }
+            Ok(false) => true,
+            // Timed out waiting for another thread to fetch the chunk, try by ourselves.
+            Err(StorageError::Timeout) => false,
+            Err(e) => return Err(eio!(e)),
+        };
+
+        let mut buf = alloc_buf(chunk.uncompressed_size() as usize);
+        if let Err(e) = self.read_chunk_from_backend(chunk, &mut buf) {
+            if pending {
+                self.chunk_map.clear_pending(chunk);
+            }
+            return Err(e);
+        }
+
+        let data = Arc::new(buf);
+        self.lru.insert(key, data.clone());
+        if pending {
+            self.chunk_map.set_ready_and_clear_pending(chunk)?;
+        }
+
+        Ok(data)
+    }
+}
+
+impl BlobCache for MemoryCacheEntry {
+    fn blob_id(&self) -> &str {
+        self.blob_info.blob_id()
+    }
+
+    fn blob_uncompressed_size(&self) -> Result<u64> {
+        Ok(self.blob_info.uncompressed_size())
+    }
+
+    fn blob_compressed_size(&self) -> Result<u64> {
+        Ok(self.blob_compressed_size)
+    }
+
+    fn compressor(&self) -> compress::Algorithm {
+        self.compressor
+    }
+
+    fn digester(&self) -> digest::Algorithm {
+        self.digester
+    }
+
+    fn cipher(&self) -> crypt::Algorithm {
+        self.blob_info.cipher()
+    }
+
+    fn cipher_key(&self) -> Option<&Arc<CipherKey>> {
+        self.blob_info.cipher_key()
+    }
+
+    fn is_legacy_stargz(&self) -> bool {
+        self.is_legacy_stargz
+    }
+
+    fn need_validation(&self) -> bool {
+        self.need_validation.load(Ordering::Relaxed)
+    }
+
+    fn set_validation(&self, enable: bool) {
+        // Cached chunks have been validated when fetched from the backend if needed, so it only
+        // affects chunks fetched afterwards.
+        self.need_validation.store(
+            enable && !self.blob_info.is_foreign_layer(),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn reader(&self) -> &dyn BlobReader {
+        &*self.reader
+    }
+
+    fn get_chunk_map(&self) -> &Arc<dyn ChunkMap> {
+        &self.chunk_map
+    }
+
+    fn get_chunk_info(&self, _chunk_index: u32) -> Option<Arc<dyn BlobChunkInfo>> {
+        None
+    }
+
+    fn start_prefetch(&self) -> StorageResult<()> {
+        self.prefetch_state.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    fn stop_prefetch(&self) -> StorageResult<()> {
+        loop {
+            let val = self.prefetch_state.load(Ordering::Acquire);
+            if val > 0
+                && self
+                    .prefetch_state
+                    .compare_exchange(val, val - 1, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_err()
+            {
+                continue;
+            }
+
+            if val == 0 {
+                warn!("storage: inaccurate prefetch status");
+            }
+            if val == 0 || val == 1 {
+                self.workers
+                    .flush_pending_prefetch_requests(self.blob_info.blob_id());
+                return Ok(());
+            }
+        }
+    }
+
+    fn is_prefetch_active(&self) -> bool {
+        self.prefetch_state.load(Ordering::Acquire) > 0
+    }
+
+    fn prefetch(
+        &self,
+        blob_cache: Arc<dyn BlobCache>,
+        _prefetches: &[BlobPrefetchRequest],
+        bios: &[BlobIoDesc],
+    ) -> StorageResult<usize> {
+        // Blob level prefetch is not supported because there's no blob object to hold the data.
+        let max_comp_size = self.prefetch_batch_size();
+        let mut bios = bios.to_vec();
+        bios.sort_by_key(|entry| entry.chunkinfo.compressed_offset());
+        self.metrics.prefetch_unmerged_chunks.add(bios.len() as u64);
+        BlobIoMergeState::merge_and_issue(
+            &bios,
+            max_comp_size,
+            max_comp_size as u64 >> RAFS_MERGING_SIZE_TO_GAP_SHIFT,
+            |req: BlobIoRange| {
+                let msg = AsyncPrefetchMessage::new_fs_prefetch(blob_cache.clone(), req);
+                let _ = self.workers.send_prefetch_message(msg);
+            },
+        );
+
+        Ok(0)
+    }
+
+    fn prefetch_range(&self, range: &BlobIoRange) -> Result<usize> {
+        let mut ready = true;
+        for chunk in range.chunks.iter() {
+            if !self.chunk_map.is_ready_or_pending(chunk.as_ref())? {
+                ready = false;
+                break;
+            }
+        }
+        if ready {
+            return Ok(0);
+        }
+
+        let mut total_size = 0;
+        let bufs = self.read_chunks_from_backend(
+            range.blob_offset,
+            range.blob_size as usize,
+            &range.chunks,
+            true,
+        )?;
+        for (chunk, buf) in range.chunks.iter().zip(bufs) {
+            let buf = buf?;
+            total_size += buf.len();
+            self.lru
+                .insert(self.chunk_key(chunk.as_ref()), Arc::new(buf));
+        }
+
+        Ok(total_size)
+    }
+
+    fn read(&self, iovec: &mut BlobIoVec, buffers: &[FileVolatileSlice]) -> Result<usize> {
+        self.metrics.total.inc();
+
+        let bios = &iovec.bi_vec;
+        if iovec.size() == 0 || bios.is_empty() {
+            return Err(einval!("parameter `bios` is empty"));
+        }
+
+        let offset = bios[0].offset;
+        let mut user_size = 0;
+        let mut chunks = Vec::with_capacity(bios.len());
+        for bio in bios.iter() {
+            if bio.user_io {
+                chunks.push(self.get_chunk_data(&bio.chunkinfo)?);
+                // Even a merged IO can hardly reach u32::MAX. So this is safe
+                user_size += bio.size;
+            }
+        }
+
+        let bufs: Vec<&[u8]> = chunks.iter().map(|v| v.as_slice()).collect();
+        copyv(&bufs, buffers, offset as usize, user_size as usize, 0, 0)
+            .map(|(n, _)| n)
+            .map_err(|e| eother!(e))
+    }
+}
+
+/// An implementation of [BlobCacheMgr](../trait.BlobCacheMgr.html) to improve performance by
+/// caching uncompressed chunk data in memory.
+pub struct MemoryCacheMgr {
+    blobs: RwLock<HashMap<String, Arc<MemoryCacheEntry>>>,
+    backend: Arc<dyn BlobBackend>,
+    lru: Arc<ChunkLru>,
+    metrics: Arc<BlobcacheMetrics>,
+    prefetch_config: Arc<AsyncPrefetchConfig>,
+    worker_mgr: Arc<AsyncWorkerMgr>,
+    next_blob_key: AtomicU32,
+    validate: bool,
+    closed: AtomicBool,
+}
+
+impl MemoryCacheMgr {
+    /// Create a new instance of `MemoryCacheMgr`.
+    pub fn new(config: CacheConfig, backend: Arc<dyn BlobBackend>, id: &str) -> Result<Self> {
+        let blob_config: MemoryCacheConfig =
+            serde_json::from_value(config.cache_config).map_err(|e| einval!(e))?;
+        if blob_config.capacity == 0 {
+            return Err(einval!("capacity of memory cache must not be zero"));
+        }
+        let metrics = BlobcacheMetrics::new(id, "memory");
+        let lru = Arc::new(ChunkLru::new(blob_config.capacity, metrics.clone()));
+        let prefetch_config: Arc<AsyncPrefetchConfig> = Arc::new(config.prefetch_config.into());
+        let worker_mgr = AsyncWorkerMgr::new(metrics.clone(), prefetch_config.clone())?;
+
+        Ok(MemoryCacheMgr {
+            blobs: RwLock::new(HashMap::new()),
+            backend,
+            lru,
+            metrics,
+            prefetch_config,
+            worker_mgr: Arc::new(worker_mgr),
+            next_blob_key: AtomicU32::new(0),
+            validate: config.cache_validate,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Get bytes of chunk data cached in memory.
+    pub fn usage(&self) -> u64 {
+        self.lru.usage()
+    }
+
+    // Create a cache entry for the specified blob object if not present, otherwise return the
+    // existing one.
+    fn get_or_create_cache_entry(
+        &self,
+        blob_info: &Arc<BlobInfo>,
+    ) -> Result<Arc<MemoryCacheEntry>> {
+        if let Some(entry) = self.blobs.read().unwrap().get(blob_info.blob_id()) {
+            return Ok(entry.clone());
+        }
+        if blob_info.meta_flags() & BLOB_META_FEATURE_ZRAN != 0 {
+            return Err(einval!(
+                "MemoryCacheMgr doesn't support ZRan based RAFS data blobs"
+            ));
+        }
+
+        let reader = self
+            .backend
+            .get_reader(blob_info.blob_id())
+            .map_err(|_e| eio!("failed to get blob reader"))?;
+        let blob_compressed_size = FileCacheEntry::get_blob_size(&reader, blob_info)?;
+        let blob_key = self.next_blob_key.fetch_add(1, Ordering::Relaxed);
+        let chunk_map = MemoryChunkMap {
+            blob: blob_key,
+            lru: self.lru.clone(),
+        };
+        let is_legacy_stargz = blob_info.is_legacy_stargz();
+        let entry = Arc::new(MemoryCacheEntry {
+            blob_info: blob_info.clone(),
+            blob_key,
+            chunk_map: Arc::new(BlobStateMap::from(chunk_map)),
+            lru: self.lru.clone(),
+            metrics: self.metrics.clone(),
+            prefetch_config: self.prefetch_config.clone(),
+            prefetch_state: AtomicU32::new(0),
+            reader,
+            workers: self.worker_mgr.clone(),
+
+            blob_compressed_size,
+            compressor: blob_info.compressor(),
+            digester: blob_info.digester(),
+            is_legacy_stargz,
+            need_validation: AtomicBool::new(self.validate && !blob_info.is_foreign_layer()),
+        });
+
+        let mut guard = self.blobs.write().unwrap();
+        if let Some(entry) = guard.get(blob_info.blob_id()) {
+            Ok(entry.clone())
+        } else {
+            guard.insert(blob_info.blob_id().to_owned(), entry.clone());
+            Ok(entry)
+        }
+    }
+}
+
+impl BlobCacheMgr for MemoryCacheMgr {
+    fn init(&self) -> Result<()> {
+        ChunkLru::start_evictor(self.lru.clone())?;
+        AsyncWorkerMgr::start(self.worker_mgr.clone())
+    }
+
+    fn destroy(&self) {
+        if !self.closed.load(Ordering::Acquire) {
+            self.closed.store(true, Ordering::Release);
+            self.worker_mgr.stop();
+            self.lru.stop_evictor();
+            self.backend().shutdown();
+            self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+        }
+    }
+
+    fn gc(&self, id: Option<&str>) -> bool {
+        let mut reclaim = Vec::new();
+
+        if let Some(blob_id) = id {
+            reclaim.push(blob_id.to_string());
+        } else {
+            let guard = self.blobs.read().unwrap();
+            for (id, entry) in guard.iter() {
+                if Arc::strong_count(entry) == 1 {
+                    reclaim.push(id.to_owned());
+                }
+            }
+        }
+
+        for key in reclaim.iter() {
+            let mut guard = self.blobs.write().unwrap();
+            if let Some(entry) = guard.get(key) {
+                if Arc::strong_count(entry) == 1 {
+                    self.lru.purge(entry.blob_key);
+                    guard.remove(key);
+                }
+            }
+        }
+
+        self.blobs.read().unwrap().len() == 0
+    }
+
+    fn backend(&self) -> &(dyn BlobBackend) {
+        self.backend.as_ref()
+    }
+
+    fn get_blob_cache(&self, blob_info: &Arc<BlobInfo>) -> Result<Arc<dyn BlobCache>> {
+        self.get_or_create_cache_entry(blob_info)
+            .map(|v| v as Arc<dyn BlobCache>)
+    }
+
+    fn check_stat(&self) {}
+}
+
+impl Drop for MemoryCacheMgr {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{BlobChunkFlags, BlobFeatures};
+    use crate::test::{MockBackend, MockChunkInfo};
+    use nydus_utils::metrics::BackendMetrics;
+
+    fn new_chunk(index: u32, offset: u64, size: u32) -> Arc<dyn BlobChunkInfo> {
+        Arc::new(MockChunkInfo {
+            block_id: Default::default(),
+            blob_index: 0,
+            flags: BlobChunkFlags::empty(),
+            compress_size: size,
+            uncompress_size: size,
+            compress_offset: offset,
+            uncompress_offset: offset,
+            file_offset: offset,
+            index,
+            reserved: 0,
+        })
+    }
+
+    #[test]
+    fn test_chunk_lru() {
+        let metrics = BlobcacheMetrics::new("memcache-lru", "memory");
+        let lru = ChunkLru::new(0x3000, metrics.clone());
+        let key = |offset| ChunkKey { blob: 0, offset };
+
+        assert!(!lru.insert(key(0), Arc::new(vec![0u8; 0x4000])));
+        assert!(lru.insert(key(0), Arc::new(vec![0u8; 0x1000])));
+        assert!(lru.insert(key(0x1000), Arc::new(vec![1u8; 0x1000])));
+        assert!(lru.insert(key(0x2000), Arc::new(vec![2u8; 0x1000])));
+        assert_eq!(lru.usage(), 0x3000);
+
+        // Touch the first chunk so the second one becomes the least recently used.
+        assert_eq!(lru.get(&key(0)).unwrap()[0], 0);
+        // Inserting only gives the first chunk a second chance, and leaves eviction of the second
+        // one to the evictor.
+        assert!(lru.insert(key(0x3000), Arc::new(vec![3u8; 0x1000])));
+        assert_eq!(lru.usage(), 0x4000);
+        assert!(lru.contains(&key(0x1000)));
+        assert!(lru.wait_for_eviction());
+        lru.evict();
+        assert_eq!(lru.usage(), 0x3000);
+        assert!(lru.contains(&key(0)));
+        assert!(!lru.contains(&key(0x1000)));
+        assert!(lru.contains(&key(0x2000)));
+        assert!(lru.contains(&key(0x3000)));
+        assert_eq!(metrics.evicted_chunks.count(), 1);
+        assert_eq!(metrics.memory_usage.count(), 0x3000);
+        assert_eq!(metrics.entries_count.count(), 3);
+
+        lru.purge(0);
+        assert_eq!(lru.usage(), 0);
+        assert!(!lru.contains(&key(0)));
+        assert_eq!(metrics.entries_count.count(), 0);
+        lru.stop_evictor();
+        assert!(!lru.wait_for_eviction());
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_chunk_lru_evictor() {
+        let metrics = BlobcacheMetrics::new("memcache-evictor", "memory");
+        let lru = Arc::new(ChunkLru::new(0x2000, metrics.clone()));
+        let key = |offset| ChunkKey { blob: 0, offset };
+        ChunkLru::start_evictor(lru.clone()).unwrap();
+
+        for i in 0..4 {
+            assert!(lru.insert(key(i * 0x1000), Arc::new(vec![0u8; 0x1000])));
+            assert!(lru.get(&key(i * 0x1000)).is_some());
+        }
+        // Touched chunks get a second chance inline, so the evictor has to catch up.
+        for _ in 0..100 {
+            if lru.usage() <= 0x2000 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(lru.usage(), 0x2000);
+        assert_eq!(metrics.evicted_chunks.count(), 2);
+
+        lru.stop_evictor();
+        lru.purge(0);
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_memory_cache_read() {
+        let config = CacheConfig {
+            cache_type: "memory".to_string(),
+            cache_config: serde_json::json!({ "capacity": 0x2000 }),
+            cache_validate: false,
+            ..Default::default()
+        };
+        let backend = Arc::new(MockBackend {
+            metrics: BackendMetrics::new("memcache-read", "mock"),
+        });
+        let mgr = MemoryCacheMgr::new(config, backend, "memcache-read").unwrap();
+        mgr.init().unwrap();
+
+        let blob_info = Arc::new(BlobInfo::new(
+            0,
+            "memcache-blob".to_owned(),
+            0x4000,
+            0x4000,
+            0x1000,
+            4,
+            BlobFeatures::V5_NO_EXT_BLOB_TABLE,
+        ));
+        let cache = mgr.get_blob_cache(&blob_info).unwrap();
+        let chunks: Vec<_> = (0..3)
+            .map(|i| new_chunk(i, i as u64 * 0x1000, 0x1000))
+            .collect();
+
+        let mut buf = vec![0u8; 0x800];
+        for chunk in chunks.iter() {
+            let mut iovec = BlobIoVec::new(blob_info.clone());
+            iovec.push(BlobIoDesc {
+                blob: blob_info.clone(),
+                chunkinfo: chunk.clone().into(),
+                offset: 0x100,
+                size: 0x800,
+                user_io: true,
+            });
+            let slice = unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+            assert_eq!(cache.read(&mut iovec, &[slice]).unwrap(), 0x800);
+            assert_eq!(buf[0], 0);
+            assert_eq!(buf[1], 1);
+            assert!(cache.get_chunk_map().is_ready(chunk.as_ref()).unwrap());
+        }
+
+        // The first chunk has been evicted to keep memory usage within budget.
+        assert_eq!(mgr.usage(), 0x2000);
+        assert!(!cache.get_chunk_map().is_ready(chunks[0].as_ref()).unwrap());
+        assert_eq!(mgr.metrics.evicted_chunks.count(), 1);
+        assert_eq!(mgr.metrics.whole_hits.count(), 0);
+
+        let mut iovec = BlobIoVec::new(blob_info.clone());
+        iovec.push(BlobIoDesc {
+            blob: blob_info.clone(),
+            chunkinfo: chunks[2].clone().into(),
+            offset: 0,
+            size: 0x800,
+            user_io: true,
+        });
+        let slice = unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(cache.read(&mut iovec, &[slice]).unwrap(), 0x800);
+        assert_eq!(mgr.metrics.whole_hits.count(), 1);
+        assert_eq!(mgr.metrics.total.count(), 4);
+
+        drop(cache);
+        assert!(mgr.gc(None));
+        assert_eq!(mgr.usage(), 0);
+    }
+}
@@ -15,6 +15,8 @@
 //! - [DummyCacheMgr](dummycache/struct.DummyCacheMgr.html): a dummy implementation of
 //!   `BlobCacheMgr`, simply reporting each chunk as cached or not cached according to
 //!   configuration.
+//! - [MemoryCacheMgr](memcache/struct.MemoryCacheMgr.html): an implementation of `BlobCacheMgr`
+//!   caching decompressed chunk data in a size-bounded LRU cache in memory.
 
 use std::cmp;
 use std::io::Result;
@@ -23,12 +25,14 @@ use std::time::Instant;
 
 use fuse_backend_rs::file_buf::FileVolatileSlice;
 use nydus_utils::compress::zlib_random::ZranDecoder;
+use nydus_utils::crypt::{self, CipherKey};
 use nydus_utils::{compress, digest};
 
 use crate::backend::{BlobBackend, BlobReader};
 use crate::cache::state::ChunkMap;
 use crate::device::{
-    BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoRange, BlobIoVec, BlobObject, BlobPrefetchRequest,
+    BlobCachedFile, BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoRange, BlobIoVec, BlobObject,
+    BlobPrefetchRequest,
 };
 use crate::meta::BlobMetaInfo;
 use crate::utils::{alloc_buf, check_digest};
@@ -38,6 +42,9 @@ mod cachedfile;
 mod dummycache;
 mod filecache;
 mod fscache;
+mod memcache;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 mod worker;
 
 pub mod state;
@@ -45,6 +52,7 @@ pub mod state;
 pub use dummycache::DummyCacheMgr;
 pub use filecache::FileCacheMgr;
 pub use fscache::FsCacheMgr;
+pub use memcache::MemoryCacheMgr;
 
 /// Timeout in milli-seconds to retrieve blob data from backend storage.
 pub const SINGLE_INFLIGHT_WAIT_TIMEOUT: u64 = 2000;
@@ -150,6 +158,16 @@ pub trait BlobCache: Send + Sync {
     /// Get message digest algorithm to handle chunks in the blob.
     fn digester(&self) -> digest::Algorithm;
 
+    /// Get encryption algorithm to handle chunks in the blob.
+    fn cipher(&self) -> crypt::Algorithm {
+        crypt::Algorithm::None
+    }
+
+    /// Get the data key to decrypt chunks in the blob.
+    fn cipher_key(&self) -> Option<&Arc<CipherKey>> {
+        None
+    }
+
     /// Check whether the cache object is for an stargz image with legacy chunk format.
     fn is_legacy_stargz(&self) -> bool;
 
@@ -177,6 +195,11 @@ pub trait BlobCache: Send + Sync {
     /// Check whether need to validate the data chunk by digest value.
     fn need_validation(&self) -> bool;
 
+    /// Enable or disable validating data chunks by digest value at runtime.
+    ///
+    /// Data chunks from foreign layers are never validated.
+    fn set_validation(&self, enable: bool);
+
     /// Get the [BlobReader](../backend/trait.BlobReader.html) to read data from storage backend.
     fn reader(&self) -> &dyn BlobReader;
 
@@ -191,6 +214,14 @@ pub trait BlobCache: Send + Sync {
         None
     }
 
+    /// Get a `BlobCachedFile` object to directly read data covered by `bios` from the cache file.
+    ///
+    /// It's only available when data of all chunks is ready in the cache file in uncompressed
+    /// form and laid out contiguously.
+    fn get_cached_file(&self, _bios: &[BlobIoDesc]) -> Option<BlobCachedFile> {
+        None
+    }
+
     /// Enable prefetching blob data in background.
     ///
     /// It should be paired with stop_prefetch().
@@ -212,6 +243,16 @@ pub trait BlobCache: Send + Sync {
         bios: &[BlobIoDesc],
     ) -> StorageResult<usize>;
 
+    /// Start to prefetch data requested by applications in background, with lower priority than
+    /// other prefetch requests.
+    fn prefetch_low_priority(
+        &self,
+        cache: Arc<dyn BlobCache>,
+        bios: &[BlobIoDesc],
+    ) -> StorageResult<usize> {
+        self.prefetch(cache, &[], bios)
+    }
+
     /// Execute filesystem data prefetch.
     fn prefetch_range(&self, _range: &BlobIoRange) -> Result<usize> {
         Err(enosys!("doesn't support prefetch_range()"))
@@ -239,6 +280,18 @@ pub trait BlobCache: Send + Sync {
     where
         Self: Sized,
     {
+        // Zeroed chunks have no data in the data blob, so there's nothing to read from the
+        // backend if the range only covers zeroed chunks.
+        if blob_size == 0 {
+            let chunks = chunks.iter().map(|v| v.as_ref()).collect();
+            return Ok(ChunkDecompressState::new(
+                blob_offset,
+                self,
+                chunks,
+                Vec::new(),
+            ));
+        }
+
         // Read requested data from the backend by altogether.
         let mut c_buf = alloc_buf(blob_size);
         let start = Instant::now();
@@ -270,18 +323,27 @@ pub trait BlobCache: Send + Sync {
     ///
     /// The fetched chunk data may be compressed or not, which depends on chunk information from
     /// `chunk`.Moreover, chunk data from backend storage may be validated per user's configuration.
+    /// Zeroed chunks are filled with zero without accessing the backend.
     fn read_chunk_from_backend(
         &self,
         chunk: &dyn BlobChunkInfo,
         buffer: &mut [u8],
     ) -> Result<Option<Vec<u8>>> {
+        if chunk.is_zeroed() {
+            if buffer.len() != chunk.uncompressed_size() as usize {
+                return Err(eio!("uncompressed size and buffer size doesn't match"));
+            }
+            buffer.fill(0);
+            return Ok(None);
+        }
+
         let start = Instant::now();
         let offset = chunk.compressed_offset();
         let mut c_buf = None;
 
         if self.is_zran() {
             return Err(enosys!("read_chunk_from_backend"));
-        } else if chunk.is_compressed() {
+        } else if chunk.is_compressed() || !self.cipher().is_none() {
             let c_size = if self.is_legacy_stargz() {
                 self.get_legacy_stargz_size(offset, buffer.len())?
             } else {
@@ -295,7 +357,7 @@ pub trait BlobCache: Send + Sync {
             if size != raw_buffer.len() {
                 return Err(eio!("storage backend returns less data than requested"));
             }
-            self.decompress_chunk_data(&raw_buffer, buffer, true)?;
+            self.decompress_chunk_data(&raw_buffer, buffer, chunk.is_compressed())?;
             c_buf = Some(raw_buffer);
         } else {
             let size = self.reader().read(buffer, offset).map_err(|e| eio!(e))?;
@@ -324,6 +386,21 @@ pub trait BlobCache: Send + Sync {
         buffer: &mut [u8],
         is_compressed: bool,
     ) -> Result<()> {
+        let cipher = self.cipher();
+        let decrypted;
+        let raw_buffer = if cipher.is_none() {
+            raw_buffer
+        } else {
+            let key = self
+                .cipher_key()
+                .ok_or_else(|| eio!("no key available to decrypt chunk data"))?;
+            decrypted = cipher.decrypt(key, raw_buffer)?;
+            if !is_compressed && decrypted.len() != buffer.len() {
+                return Err(eother!("size of decrypted data doesn't match expected"));
+            }
+            &decrypted
+        };
+
         if is_compressed {
             let ret = compress::decompress(raw_buffer, buffer, self.compressor()).map_err(|e| {
                 error!("failed to decompress chunk: {}", e);
@@ -460,6 +537,10 @@ impl<'a, 'b> ChunkDecompressState<'a, 'b> {
             return Err(eio!(msg));
         }
 
+        if chunk.is_zeroed() {
+            return Ok(vec![0u8; d_size]);
+        }
+
         let offset_merged = (c_offset - self.blob_offset) as usize;
         let end_merged = offset_merged + c_size as usize;
         let buf = &self.c_buf[offset_merged..end_merged];
@@ -515,6 +596,9 @@ pub(crate) trait BlobCacheMgr: Send + Sync {
     /// Get the underlying `BlobBackend` object of the blob cache object.
     fn backend(&self) -> &(dyn BlobBackend);
 
+    /// Reset error states cached by blob cache objects after the storage backend recovers.
+    fn backend_recovered(&self) {}
+
     /// Get the blob cache to provide access to the `blob` object.
     fn get_blob_cache(&self, blob_info: &Arc<BlobInfo>) -> Result<Arc<dyn BlobCache>>;
 
@@ -8,9 +8,12 @@ use std::collections::HashMap;
 use std::fmt::Display;
 use std::hash::Hash;
 use std::io::Result;
-use std::sync::{Arc, Condvar, Mutex, WaitTimeoutResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, WaitTimeoutResult, Weak};
 use std::time::Duration;
 
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+
 use crate::cache::state::{BlobRangeMap, ChunkIndexGetter, ChunkMap, IndexedChunkMap, RangeMap};
 use crate::cache::SINGLE_INFLIGHT_WAIT_TIMEOUT;
 use crate::device::BlobChunkInfo;
@@ -20,18 +23,22 @@ use crate::{StorageError, StorageResult};
 enum Status {
     Inflight,
     Complete,
+    Failed,
 }
 
 struct Slot {
     state: Mutex<Status>,
     condvar: Condvar,
+    // Id of the `BlobStateMap` object issuing the backend IO.
+    owner: u64,
 }
 
 impl Slot {
-    fn new() -> Self {
+    fn new(owner: u64) -> Self {
         Slot {
             state: Mutex::new(Status::Inflight),
             condvar: Condvar::new(),
+            owner,
         }
     }
 
@@ -45,6 +52,11 @@ impl Slot {
         self.notify();
     }
 
+    fn fail(&self) {
+        *self.state.lock().unwrap() = Status::Failed;
+        self.notify();
+    }
+
     fn wait_for_inflight(&self, timeout: Duration) -> StorageResult<Status> {
         let mut state = self.state.lock().unwrap();
         let mut tor: WaitTimeoutResult;
@@ -63,6 +75,35 @@ impl Slot {
     }
 }
 
+type InflightTracer<I> = Mutex<HashMap<I, Arc<Slot>>>;
+
+static NEXT_STATE_MAP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Inflight chunk fetches shared by blob caches backed by the same cache file, keyed by the path
+/// of the cache file.
+///
+/// The registry is owned by the blob factory, so blob caches created by different blob cache
+/// managers, for mounts with different configurations, still share inflight fetches.
+#[derive(Default)]
+pub struct InflightTracerRegistry {
+    tracers: Mutex<HashMap<String, Weak<InflightTracer<u32>>>>,
+}
+
+impl InflightTracerRegistry {
+    fn get_or_create(&self, blob_file: &str) -> Arc<InflightTracer<u32>> {
+        let mut tracers = self.tracers.lock().unwrap();
+        match tracers.get(blob_file).and_then(|v| v.upgrade()) {
+            Some(v) => v,
+            None => {
+                tracers.retain(|_, v| v.strong_count() > 0);
+                let v = Arc::new(Mutex::new(HashMap::new()));
+                tracers.insert(blob_file.to_string(), Arc::downgrade(&v));
+                v
+            }
+        }
+    }
+}
+
 /// Adapter structure to enable concurrent chunk readiness manipulating based on a base [ChunkMap]
 /// object.
 ///
@@ -72,7 +113,9 @@ impl Slot {
 /// state manipulation.
 pub struct BlobStateMap<C, I> {
     c: C,
-    inflight_tracer: Mutex<HashMap<I, Arc<Slot>>>,
+    id: u64,
+    inflight_tracer: Arc<InflightTracer<I>>,
+    metrics: Option<Arc<BlobcacheMetrics>>,
 }
 
 impl<C, I> From<C> for BlobStateMap<C, I>
@@ -83,7 +126,9 @@ where
     fn from(c: C) -> Self {
         Self {
             c,
-            inflight_tracer: Mutex::new(HashMap::new()),
+            id: NEXT_STATE_MAP_ID.fetch_add(1, Ordering::Relaxed),
+            inflight_tracer: Arc::new(Mutex::new(HashMap::new())),
+            metrics: None,
         }
     }
 }
@@ -115,18 +160,34 @@ where
         if let Some(i) = guard.get(&index).cloned() {
             drop(guard);
             let result = i.wait_for_inflight(Duration::from_millis(SINGLE_INFLIGHT_WAIT_TIMEOUT));
-            if let Err(StorageError::Timeout) = result {
-                warn!(
-                    "Waiting for backend IO expires. chunk index {}, compressed offset {}",
-                    index,
-                    chunk.compressed_offset()
-                );
-
-                Err(StorageError::Timeout)
-            } else {
-                // Check if the chunk is ready in local cache again. It should be READY
-                // since wait_for_inflight must return OK in this branch by one more check.
-                self.check_ready_and_mark_pending(chunk)
+            match result {
+                Err(StorageError::Timeout) => {
+                    warn!(
+                        "Waiting for backend IO expires. chunk index {}, compressed offset {}",
+                        index,
+                        chunk.compressed_offset()
+                    );
+                    Err(StorageError::Timeout)
+                }
+                Ok(Status::Failed) => {
+                    warn!(
+                        "Waited backend IO failed. chunk index {}, compressed offset {}",
+                        index,
+                        chunk.compressed_offset()
+                    );
+                    Err(StorageError::FetchFailed)
+                }
+                _ => {
+                    // Only count fetches issued by other blob caches sharing the same blob.
+                    if i.owner != self.id {
+                        if let Some(metrics) = self.metrics.as_ref() {
+                            metrics.suppressed_fetches.inc();
+                        }
+                    }
+                    // Check if the chunk is ready in local cache again. It should be READY
+                    // since wait_for_inflight must return OK in this branch by one more check.
+                    self.check_ready_and_mark_pending(chunk)
+                }
             }
         } else {
             // Double check to close the window where prior slot was just removed after backend IO
@@ -134,7 +195,7 @@ where
             if self.c.is_ready(chunk).map_err(StorageError::CacheIndex)? {
                 ready = true;
             } else {
-                guard.insert(index, Arc::new(Slot::new()));
+                guard.insert(index, Arc::new(Slot::new(self.id)));
             }
             Ok(ready)
         }
@@ -154,6 +215,14 @@ where
         }
     }
 
+    fn clear_pending_on_error(&self, chunk: &dyn BlobChunkInfo) {
+        let index = C::get_index(chunk);
+        let mut guard = self.inflight_tracer.lock().unwrap();
+        if let Some(i) = guard.remove(&index) {
+            i.fail();
+        }
+    }
+
     fn is_persist(&self) -> bool {
         self.c.is_persist()
     }
@@ -200,7 +269,7 @@ impl RangeMap for BlobStateMap<IndexedChunkMap, u32> {
                 // Double check to close the window where prior slot was just removed after backend
                 // IO returned.
                 if !self.c.is_range_ready(*index, 1)? {
-                    guard.insert(*index, Arc::new(Slot::new()));
+                    guard.insert(*index, Arc::new(Slot::new(self.id)));
                     res.push(*index);
                 }
             }
@@ -292,7 +361,7 @@ impl RangeMap for BlobStateMap<BlobRangeMap, u64> {
                 // Double check to close the window where prior slot was just removed after backend
                 // IO returned.
                 if !self.c.is_range_ready(*index, 1)? {
-                    guard.insert(*index, Arc::new(Slot::new()));
+                    guard.insert(*index, Arc::new(Slot::new(self.id)));
                     res.push(*index);
                 }
             }
@@ -361,13 +430,38 @@ impl BlobStateMap<BlobRangeMap, u64> {
     pub fn from_range_map(map: BlobRangeMap) -> Self {
         Self {
             c: map,
-            inflight_tracer: Mutex::new(HashMap::new()),
+            id: NEXT_STATE_MAP_ID.fetch_add(1, Ordering::Relaxed),
+            inflight_tracer: Arc::new(Mutex::new(HashMap::new())),
+            metrics: None,
+        }
+    }
+}
+
+impl BlobStateMap<IndexedChunkMap, u32> {
+    /// Create a new instance of `BlobStateMap` sharing inflight state with other instances.
+    ///
+    /// All `BlobStateMap` objects created from the same `registry` for the same `blob_file` share
+    /// the same inflight tracer. So when multiple blob caches are backed by the same cache file,
+    /// a request for a chunk being fetched by another blob cache waits for the inflight fetch
+    /// instead of issuing a duplicated backend request.
+    pub fn new_shared(
+        map: IndexedChunkMap,
+        registry: &InflightTracerRegistry,
+        blob_file: &str,
+        metrics: Arc<BlobcacheMetrics>,
+    ) -> Self {
+        Self {
+            c: map,
+            id: NEXT_STATE_MAP_ID.fetch_add(1, Ordering::Relaxed),
+            inflight_tracer: registry.get_or_create(blob_file),
+            metrics: Some(metrics),
         }
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::thread;
     use std::time::Instant;
@@ -703,6 +797,151 @@ pub(crate) mod tests {
         t2.join().unwrap();
     }
 
+    #[test]
+    fn test_shared_inflight_tracer() {
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().to_str().unwrap().to_string();
+        let metrics = BlobcacheMetrics::new("test_shared_inflight_tracer", &path);
+        let chunk_count = 16;
+        let registry = InflightTracerRegistry::default();
+        // Two blob caches backed by the same cache file, as mounts sharing a blob do.
+        let maps = [
+            Arc::new(BlobStateMap::new_shared(
+                IndexedChunkMap::new(&path, chunk_count, true).unwrap(),
+                &registry,
+                &path,
+                metrics.clone(),
+            )),
+            Arc::new(BlobStateMap::new_shared(
+                IndexedChunkMap::new(&path, chunk_count, true).unwrap(),
+                &registry,
+                &path,
+                metrics.clone(),
+            )),
+        ];
+        assert!(Arc::ptr_eq(
+            &maps[0].inflight_tracer,
+            &maps[1].inflight_tracer
+        ));
+        // Blob caches backed by another cache file don't share inflight fetches.
+        let other_file = TempFile::new().unwrap();
+        let other_path = other_file.as_path().to_str().unwrap();
+        let other = BlobStateMap::new_shared(
+            IndexedChunkMap::new(other_path, chunk_count, true).unwrap(),
+            &registry,
+            other_path,
+            metrics.clone(),
+        );
+        assert!(!Arc::ptr_eq(
+            &maps[0].inflight_tracer,
+            &other.inflight_tracer
+        ));
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for idx in 0..8 {
+            let map = maps[idx % 2].clone();
+            let fetches = fetches.clone();
+            handles.push(thread::spawn(move || {
+                for index in 0..chunk_count {
+                    let mut chunk = MockChunkInfo::new();
+                    chunk.index = index;
+                    loop {
+                        match map.check_ready_and_mark_pending(&chunk) {
+                            Ok(true) => break,
+                            Ok(false) => {
+                                fetches.fetch_add(1, Ordering::SeqCst);
+                                thread::sleep(Duration::from_millis(10));
+                                map.set_ready_and_clear_pending(&chunk).unwrap();
+                                break;
+                            }
+                            Err(StorageError::Timeout) => continue,
+                            Err(e) => panic!("unexpected error {}", e),
+                        }
+                    }
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // Each chunk is fetched from the backend exactly once.
+        assert_eq!(fetches.load(Ordering::SeqCst), chunk_count as usize);
+        for map in maps.iter() {
+            assert!(map.inflight_tracer.lock().unwrap().is_empty());
+            for index in 0..chunk_count {
+                let mut chunk = MockChunkInfo::new();
+                chunk.index = index;
+                assert!(map.is_ready(&chunk).unwrap());
+            }
+        }
+        assert!(metrics.suppressed_fetches.count() <= 7 * chunk_count as u64);
+    }
+
+    #[test]
+    fn test_shared_inflight_tracer_error() {
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().to_str().unwrap().to_string();
+        let metrics = BlobcacheMetrics::new("test_shared_inflight_tracer_error", &path);
+        let registry = InflightTracerRegistry::default();
+        let map1 = Arc::new(BlobStateMap::new_shared(
+            IndexedChunkMap::new(&path, 10, true).unwrap(),
+            &registry,
+            &path,
+            metrics.clone(),
+        ));
+        let map2 = Arc::new(BlobStateMap::new_shared(
+            IndexedChunkMap::new(&path, 10, true).unwrap(),
+            &registry,
+            &path,
+            metrics.clone(),
+        ));
+        let mut chunk = MockChunkInfo::new();
+        chunk.index = 4;
+
+        // A failed fetch is propagated to requests waiting for it.
+        assert!(!map1.check_ready_and_mark_pending(&chunk).unwrap());
+        let map = map2.clone();
+        let t = thread::spawn(move || {
+            let mut chunk = MockChunkInfo::new();
+            chunk.index = 4;
+            map.check_ready_and_mark_pending(&chunk)
+        });
+        thread::sleep(Duration::from_millis(500));
+        map1.clear_pending_on_error(&chunk);
+        assert!(matches!(t.join().unwrap(), Err(StorageError::FetchFailed)));
+        assert_eq!(metrics.suppressed_fetches.count(), 0);
+
+        // A successful fetch is reused by requests waiting for it.
+        assert!(!map1.check_ready_and_mark_pending(&chunk).unwrap());
+        let map = map2.clone();
+        let t = thread::spawn(move || {
+            let mut chunk = MockChunkInfo::new();
+            chunk.index = 4;
+            map.check_ready_and_mark_pending(&chunk)
+        });
+        thread::sleep(Duration::from_millis(500));
+        map1.set_ready_and_clear_pending(&chunk).unwrap();
+        assert!(t.join().unwrap().unwrap());
+        assert_eq!(metrics.suppressed_fetches.count(), 1);
+        assert!(map2.inflight_tracer.lock().unwrap().is_empty());
+
+        // Waiting for a fetch issued by the same blob cache isn't a suppressed fetch.
+        chunk.index = 5;
+        assert!(!map1.check_ready_and_mark_pending(&chunk).unwrap());
+        let map = map1.clone();
+        let t = thread::spawn(move || {
+            let mut chunk = MockChunkInfo::new();
+            chunk.index = 5;
+            map.check_ready_and_mark_pending(&chunk)
+        });
+        thread::sleep(Duration::from_millis(500));
+        map1.set_ready_and_clear_pending(&chunk).unwrap();
+        assert!(t.join().unwrap().unwrap());
+        assert_eq!(metrics.suppressed_fetches.count(), 1);
+    }
+
     #[test]
     /// Case description:
     ///     Never invoke `set_ready` method, thus to let each caller of `has_ready` reach
@@ -38,7 +38,7 @@ use std::io::Result;
 use crate::device::BlobChunkInfo;
 use crate::StorageResult;
 
-pub use blob_state_map::BlobStateMap;
+pub use blob_state_map::{BlobStateMap, InflightTracerRegistry};
 pub use digested_chunk_map::DigestedChunkMap;
 pub use indexed_chunk_map::IndexedChunkMap;
 pub use noop_chunk_map::NoopChunkMap;
@@ -74,6 +74,7 @@ pub trait ChunkMap: Any + Send + Sync {
     ///
     /// The function returns:
     /// - `Err(Timeout)` waiting for inflight backend IO timeouts.
+    /// - `Err(FetchFailed)` the inflight backend IO being waited for failed.
     /// - `Ok(true)` if the the chunk is ready.
     /// - `Ok(false)` marks the chunk as pending, either set_ready_and_clear_pending() or
     ///   clear_pending() must be called to clear the pending state.
@@ -91,6 +92,13 @@ pub trait ChunkMap: Any + Send + Sync {
         panic!("no support of clear_pending()");
     }
 
+    /// Clear the pending state of the chunk after failing to fetch it from the backend.
+    ///
+    /// Requests waiting for the chunk get `Err(FetchFailed)` instead of issuing another fetch.
+    fn clear_pending_on_error(&self, chunk: &dyn BlobChunkInfo) {
+        self.clear_pending(chunk)
+    }
+
     /// Check whether the implementation supports state persistence.
     fn is_persist(&self) -> bool {
         false
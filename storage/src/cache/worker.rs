@@ -163,6 +163,20 @@ impl AsyncWorkerMgr {
         }
     }
 
+    /// Send an asynchronous service request message to the workers, which is handled after all
+    /// other pending messages.
+    pub fn send_low_priority_prefetch_message(
+        &self,
+        msg: AsyncPrefetchMessage,
+    ) -> std::result::Result<(), AsyncPrefetchMessage> {
+        if !self.prefetch_config.enable {
+            Err(msg)
+        } else {
+            self.prefetch_inflight.fetch_add(1, Ordering::Relaxed);
+            self.prefetch_channel.send_low_priority(msg)
+        }
+    }
+
     /// Flush pending prefetch requests associated with `blob_id`.
     pub fn flush_pending_prefetch_requests(&self, blob_id: &str) {
         self.prefetch_channel
@@ -424,14 +438,20 @@ mod tests {
         assert!(mgr
             .send_prefetch_message(AsyncPrefetchMessage::Ping)
             .is_ok());
+        assert!(mgr
+            .send_low_priority_prefetch_message(AsyncPrefetchMessage::Ping)
+            .is_ok());
         thread::sleep(Duration::from_secs(1));
-        assert_eq!(mgr.ping_requests.load(Ordering::Acquire), 5);
+        assert_eq!(mgr.ping_requests.load(Ordering::Acquire), 6);
         assert_eq!(mgr.workers.load(Ordering::Acquire), 2);
         mgr.stop();
         assert_eq!(mgr.workers.load(Ordering::Acquire), 0);
         assert!(mgr
             .send_prefetch_message(AsyncPrefetchMessage::Ping)
             .is_err());
+        assert!(mgr
+            .send_low_priority_prefetch_message(AsyncPrefetchMessage::Ping)
+            .is_err());
     }
 
     #[test]
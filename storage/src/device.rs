@@ -25,8 +25,10 @@ use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::fs::File;
 use std::io::{self, Error};
-use std::os::unix::io::AsRawFd;
-use std::sync::Arc;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use arc_swap::ArcSwap;
 use fuse_backend_rs::api::filesystem::ZeroCopyWriter;
@@ -35,17 +37,22 @@ use fuse_backend_rs::file_traits::FileReadWriteVolatile;
 
 use nydus_api::http::FactoryConfig;
 use nydus_utils::compress;
+use nydus_utils::crypt::{self, CipherKey, KEY_REF_LEN};
 use nydus_utils::digest::{self, RafsDigest};
+use nydus_utils::trace::{self, RequestTrace};
 
 use crate::cache::BlobCache;
 use crate::factory::BLOB_FACTORY;
-use crate::meta::BLOB_META_FEATURE_CHUNK_INFO_V2;
+use crate::meta::{BLOB_META_FEATURE_CHUNK_INFO_V2, BLOB_META_FEATURE_FOREIGN_LAYER};
+use crate::utils::{readv, MemSliceCursor};
 
 bitflags! {
     /// Features bits for blob management.
     pub struct BlobFeatures: u32 {
         /// Rafs V5 image without extended blob table.
         const V5_NO_EXT_BLOB_TABLE = 0x0000_0001;
+        /// Chunk data is referenced in place from a foreign (eStargz/zstd:chunked) layer blob.
+        const FOREIGN_LAYER = 0x0000_0002;
     }
 }
 
@@ -105,6 +112,15 @@ pub struct BlobInfo {
     /// V6: count of Zran context entries
     meta_ci_zran_count: u32,
 
+    /// V6: encryption algorithm for chunk data.
+    cipher: crypt::Algorithm,
+    /// V6: reference to the master key to wrap the data key.
+    cipher_key_ref: [u8; KEY_REF_LEN],
+    /// V6: data key wrapped by the master key.
+    cipher_wrapped_key: Vec<u8>,
+    /// V6: data key to decrypt chunk data, unwrapped with the master key provided at runtime.
+    cipher_key: Option<Arc<CipherKey>>,
+
     /// V6: support fs-cache mode
     fs_cache_file: Option<Arc<File>>,
 }
@@ -143,6 +159,11 @@ impl BlobInfo {
             meta_ci_zran_offset: 0,
             meta_ci_zran_size: 0,
 
+            cipher: crypt::Algorithm::None,
+            cipher_key_ref: [0u8; KEY_REF_LEN],
+            cipher_wrapped_key: Vec::new(),
+            cipher_key: None,
+
             fs_cache_file: None,
         };
 
@@ -226,6 +247,14 @@ impl BlobInfo {
         self.is_legacy_stargz
     }
 
+    /// Check whether chunk data is referenced in place from a foreign layer blob.
+    ///
+    /// Chunk digests of foreign layers are derived from the layer TOC instead of chunk content,
+    /// so chunk data fetched from foreign layers can't be validated by digest.
+    pub fn is_foreign_layer(&self) -> bool {
+        self.is_legacy_stargz || self.has_feature(BlobFeatures::FOREIGN_LAYER)
+    }
+
     /// Set metadata information for a blob.
     ///
     /// The compressed blobs are laid out as:
@@ -239,6 +268,9 @@ impl BlobInfo {
         compressor: u32,
     ) {
         self.meta_flags = flags;
+        if flags & BLOB_META_FEATURE_FOREIGN_LAYER != 0 {
+            self.blob_features |= BlobFeatures::FOREIGN_LAYER;
+        }
         self.meta_ci_compressor = compressor;
         self.meta_ci_offset = offset;
         self.meta_ci_compressed_size = compressed_size;
@@ -265,6 +297,8 @@ impl BlobInfo {
             compress::Algorithm::GZip
         } else if self.meta_ci_compressor == compress::Algorithm::Zstd as u32 {
             compress::Algorithm::Zstd
+        } else if self.meta_ci_compressor == compress::Algorithm::Snappy as u32 {
+            compress::Algorithm::Snappy
         } else {
             compress::Algorithm::None
         }
@@ -305,6 +339,48 @@ impl BlobInfo {
         self.meta_ci_compressed_size != 0 && self.meta_ci_uncompressed_size != 0
     }
 
+    /// Set encryption information for chunk data of the blob.
+    pub fn set_cipher_info(
+        &mut self,
+        cipher: crypt::Algorithm,
+        key_ref: [u8; KEY_REF_LEN],
+        wrapped_key: &[u8],
+    ) {
+        self.cipher = cipher;
+        self.cipher_key_ref = key_ref;
+        self.cipher_wrapped_key = wrapped_key.to_vec();
+    }
+
+    /// Get encryption algorithm for chunk data.
+    pub fn cipher(&self) -> crypt::Algorithm {
+        self.cipher
+    }
+
+    /// Check whether chunk data of the blob is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        !self.cipher.is_none()
+    }
+
+    /// Get reference to the master key to wrap the data key.
+    pub fn cipher_key_ref(&self) -> &[u8; KEY_REF_LEN] {
+        &self.cipher_key_ref
+    }
+
+    /// Get the data key wrapped by the master key.
+    pub fn cipher_wrapped_key(&self) -> &[u8] {
+        &self.cipher_wrapped_key
+    }
+
+    /// Set the data key to decrypt chunk data.
+    pub fn set_cipher_key(&mut self, key: Arc<CipherKey>) {
+        self.cipher_key = Some(key);
+    }
+
+    /// Get the data key to decrypt chunk data.
+    pub fn cipher_key(&self) -> Option<&Arc<CipherKey>> {
+        self.cipher_key.as_ref()
+    }
+
     /// Set the associated `File` object provided by Linux fscache subsystem.
     pub fn set_fscache_file(&mut self, file: Option<Arc<File>>) {
         self.fs_cache_file = file;
@@ -338,8 +414,8 @@ bitflags! {
     pub struct BlobChunkFlags: u32 {
         /// Chunk data is compressed.
         const COMPRESSED = 0x0000_0001;
-        /// Chunk is a hole, with all data as zero.
-        const _HOLECHUNK = 0x0000_0002;
+        /// Chunk data is all zero, and no data is stored in the data blob for it.
+        const ZEROED = 0x0000_0002;
     }
 }
 
@@ -401,6 +477,14 @@ pub trait BlobChunkInfo: Any + Sync + Send {
     /// data may be stored in the compressed data blob for those chunks.
     fn is_compressed(&self) -> bool;
 
+    /// Check whether the chunk data is all zero.
+    ///
+    /// There's no data stored in the compressed data blob for zeroed chunks, so they may be
+    /// served without accessing the storage backend.
+    fn is_zeroed(&self) -> bool {
+        false
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
@@ -450,6 +534,10 @@ impl BlobChunkInfo for BlobIoChunk {
         self.0.is_compressed()
     }
 
+    fn is_zeroed(&self) -> bool {
+        self.0.is_zeroed()
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -529,6 +617,8 @@ pub struct BlobIoVec {
     bi_size: u32,
     /// Array of blob IOs, these IOs should executed sequentially.
     pub(crate) bi_vec: Vec<BlobIoDesc>,
+    /// Tracing context of the request which the blob IOs belong to.
+    bi_trace: Option<Arc<RequestTrace>>,
 }
 
 impl BlobIoVec {
@@ -538,6 +628,7 @@ impl BlobIoVec {
             bi_blob,
             bi_size: 0,
             bi_vec: Vec::with_capacity(128),
+            bi_trace: None,
         }
     }
 
@@ -603,6 +694,16 @@ impl BlobIoVec {
     pub fn has_same_blob(&self, desc: &BlobIoVec) -> bool {
         self.bi_blob.blob_index() == desc.bi_blob.blob_index()
     }
+
+    /// Attach the tracing context of the request to the blob io vector.
+    pub fn set_trace(&mut self, trace: Option<Arc<RequestTrace>>) {
+        self.bi_trace = trace;
+    }
+
+    /// Get the tracing context attached to the blob io vector.
+    pub fn trace(&self) -> Option<&Arc<RequestTrace>> {
+        self.bi_trace.as_ref()
+    }
 }
 
 impl Debug for BlobIoVec {
@@ -820,14 +921,214 @@ pub trait BlobObject: AsRawFd {
     fn prefetch_chunks(&self, range: &BlobIoRange) -> io::Result<()>;
 }
 
-/// A wrapping object over an underlying [BlobCache] object.
+/// A file whose data is fully cached in a local cache file in uncompressed form.
 ///
-/// All blob Io requests are actually served by the underlying [BlobCache] object. The wrapper
+/// Data of the file is laid out contiguously in the cache file, so reads could be served by the
+/// cache file directly, bypassing the blob cache and storage backend.
+#[derive(Clone)]
+pub struct BlobCachedFile {
+    file: Arc<File>,
+    base: u64,
+    size: u64,
+}
+
+impl AsRawFd for BlobCachedFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl BlobCachedFile {
+    /// Create a new instance of `BlobCachedFile`, covering data in range [base, base + size) of
+    /// the cache file.
+    pub fn new(file: Arc<File>, base: u64, size: u64) -> Self {
+        BlobCachedFile { file, base, size }
+    }
+
+    /// Get size of the cached file data.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get offset of the cached file data in the cache file.
+    pub fn base_offset(&self) -> u64 {
+        self.base
+    }
+
+    /// Read file data in range [offset, offset + size) into the provided writer.
+    pub fn read_to(
+        &self,
+        w: &mut dyn ZeroCopyWriter,
+        offset: u64,
+        size: usize,
+    ) -> io::Result<usize> {
+        if offset >= self.size || size == 0 {
+            return Ok(0);
+        }
+        let size = std::cmp::min(size as u64, self.size - offset) as usize;
+        let mut f = BlobCachedFileReader { file: self };
+        w.write_from(&mut f, size, offset)
+    }
+}
+
+/// Access information about a blob managed by a [BlobDevice].
+#[derive(Clone, Debug, Default)]
+pub struct BlobAccessInfo {
+    /// Blob id.
+    pub blob_id: String,
+    /// Whether the blob has been registered, that is its [BlobCache] object has been created.
+    pub ready: bool,
+    /// Time of the first access to the blob, in seconds since the UNIX epoch.
+    pub first_access: Option<u64>,
+}
+
+/// Source of [BlobInfo] objects for blobs managed by a [BlobDevice].
+///
+/// A [BlobDevice] only asks for the [BlobInfo] object of a blob on first access to the blob, so
+/// sources may materialize [BlobInfo] objects lazily.
+pub trait BlobInfoSource: Send + Sync {
+    /// Get number of blobs.
+    fn blob_count(&self) -> usize;
+
+    /// Get id of the blob with index `blob_index`, without materializing its [BlobInfo] object.
+    fn blob_id(&self, blob_index: u32) -> Option<String>;
+
+    /// Get the [BlobInfo] object for the blob with index `blob_index`.
+    fn blob_info(&self, blob_index: u32) -> io::Result<Arc<BlobInfo>>;
+
+    /// Get reference to the key encrypting the blob with index `blob_index`.
+    ///
+    /// Return `None` if the blob is not encrypted. Sources materializing [BlobInfo] objects lazily
+    /// should override it to avoid materializing the [BlobInfo] object.
+    fn cipher_key_ref(&self, blob_index: u32) -> io::Result<Option<[u8; KEY_REF_LEN]>> {
+        let blob_info = self.blob_info(blob_index)?;
+        if blob_info.is_encrypted() {
+            Ok(Some(*blob_info.cipher_key_ref()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl BlobInfoSource for Vec<Arc<BlobInfo>> {
+    fn blob_count(&self) -> usize {
+        self.len()
+    }
+
+    fn blob_id(&self, blob_index: u32) -> Option<String> {
+        self.get(blob_index as usize)
+            .map(|bi| bi.blob_id().to_string())
+    }
+
+    fn blob_info(&self, blob_index: u32) -> io::Result<Arc<BlobInfo>> {
+        self.get(blob_index as usize)
+            .cloned()
+            .ok_or_else(|| einval!(format!("blob index {} is out of range", blob_index)))
+    }
+}
+
+/// Function to create a [BlobCache] object for a blob.
+type BlobCacheCreator = dyn Fn(&Arc<BlobInfo>) -> io::Result<Arc<dyn BlobCache>> + Send + Sync;
+
+/// Runtime states shared by all blobs of a [BlobDevice].
+#[derive(Default)]
+struct BlobDeviceState {
+    /// Whether background prefetch has been started.
+    prefetch: AtomicBool,
+    /// Whether to validate data chunks, set at runtime by [BlobDevice::set_validation()].
+    validation: Mutex<Option<bool>>,
+}
+
+/// A blob managed by a [BlobDevice].
+///
+/// The [BlobInfo] object and the [BlobCache] object, which connects to the storage backend, are
+/// created on first access to the blob, so untouched blobs cost nothing.
+struct BlobDeviceEntry {
+    blob_index: u32,
+    source: Arc<dyn BlobInfoSource>,
+    creator: Arc<BlobCacheCreator>,
+    cache: Mutex<Option<Arc<dyn BlobCache>>>,
+    first_access: AtomicU64,
+}
+
+impl BlobDeviceEntry {
+    fn new(
+        blob_index: u32,
+        source: Arc<dyn BlobInfoSource>,
+        creator: Arc<BlobCacheCreator>,
+        first_access: u64,
+    ) -> Self {
+        BlobDeviceEntry {
+            blob_index,
+            source,
+            creator,
+            cache: Mutex::new(None),
+            first_access: AtomicU64::new(first_access),
+        }
+    }
+
+    /// Get the [BlobCache] object for the blob, create it if it hasn't been created yet.
+    fn get_cache(&self, state: &BlobDeviceState) -> io::Result<Arc<dyn BlobCache>> {
+        let mut guard = self.cache.lock().unwrap();
+        if let Some(cache) = guard.as_ref() {
+            return Ok(cache.clone());
+        }
+
+        let blob_info = self.source.blob_info(self.blob_index)?;
+        let cache = (self.creator)(&blob_info)?;
+        if let Some(enable) = *state.validation.lock().unwrap() {
+            cache.set_validation(enable);
+        }
+        if state.prefetch.load(Ordering::Acquire) {
+            let _ = cache.start_prefetch();
+        }
+        if self.first_access.load(Ordering::Relaxed) == 0 {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(1);
+            self.first_access.store(now, Ordering::Relaxed);
+        }
+        *guard = Some(cache.clone());
+
+        Ok(cache)
+    }
+
+    /// Get the [BlobCache] object for the blob if it has been created.
+    fn get_ready_cache(&self) -> Option<Arc<dyn BlobCache>> {
+        self.cache.lock().unwrap().clone()
+    }
+
+    fn blob_id(&self) -> Option<String> {
+        self.source.blob_id(self.blob_index)
+    }
+
+    fn access_info(&self) -> BlobAccessInfo {
+        let first_access = self.first_access.load(Ordering::Relaxed);
+        BlobAccessInfo {
+            blob_id: self.blob_id().unwrap_or_default(),
+            ready: self.cache.lock().unwrap().is_some(),
+            first_access: if first_access == 0 {
+                None
+            } else {
+                Some(first_access)
+            },
+        }
+    }
+}
+
+/// A wrapping object over underlying [BlobCache] objects.
+///
+/// All blob Io requests are actually served by the underlying [BlobCache] objects. The wrapper
 /// provides an interface to dynamically switch underlying [BlobCache] objects.
+///
+/// A [BlobCache] object, and thus the connection to the storage backend, is only created on the
+/// first access to the corresponding blob.
 #[derive(Clone, Default)]
 pub struct BlobDevice {
-    blobs: Arc<ArcSwap<Vec<Arc<dyn BlobCache>>>>,
+    blobs: Arc<ArcSwap<Vec<Arc<BlobDeviceEntry>>>>,
     blob_count: usize,
+    state: Arc<BlobDeviceState>,
 }
 
 impl BlobDevice {
@@ -836,38 +1137,86 @@ impl BlobDevice {
         config: &Arc<FactoryConfig>,
         blob_infos: &[Arc<BlobInfo>],
     ) -> io::Result<BlobDevice> {
-        let mut blobs = Vec::with_capacity(blob_infos.len());
-        for blob_info in blob_infos.iter() {
-            let blob = BLOB_FACTORY.new_blob_cache(config, blob_info, blob_infos.len())?;
-            blobs.push(blob);
-        }
+        Self::with_source(config, Arc::new(blob_infos.to_vec()))
+    }
 
-        Ok(BlobDevice {
+    /// Create new blob device instance, getting [BlobInfo] objects from `source` on demand.
+    pub fn with_source(
+        config: &Arc<FactoryConfig>,
+        source: Arc<dyn BlobInfoSource>,
+    ) -> io::Result<BlobDevice> {
+        let creator = Self::new_creator(config, source.blob_count());
+        Ok(Self::with_creator(source, creator))
+    }
+
+    fn with_creator(source: Arc<dyn BlobInfoSource>, creator: Arc<BlobCacheCreator>) -> BlobDevice {
+        let blob_count = source.blob_count();
+        let blobs = (0..blob_count as u32)
+            .map(|idx| {
+                Arc::new(BlobDeviceEntry::new(
+                    idx,
+                    source.clone(),
+                    creator.clone(),
+                    0,
+                ))
+            })
+            .collect();
+
+        BlobDevice {
             blobs: Arc::new(ArcSwap::new(Arc::new(blobs))),
-            blob_count: blob_infos.len(),
+            blob_count,
+            state: Arc::new(BlobDeviceState::default()),
+        }
+    }
+
+    fn new_creator(config: &Arc<FactoryConfig>, blob_count: usize) -> Arc<BlobCacheCreator> {
+        let config = config.clone();
+        Arc::new(move |blob_info: &Arc<BlobInfo>| {
+            BLOB_FACTORY.new_blob_cache(&config, blob_info, blob_count)
         })
     }
 
     /// Update configuration and storage backends of the blob device.
     ///
     /// The `update()` method switch a new storage backend object according to the configuration
-    /// information passed in.
+    /// information passed in. Blobs which have been accessed are registered with the new storage
+    /// backend immediately, others are still registered on first access.
     pub fn update(
         &self,
         config: &Arc<FactoryConfig>,
         blob_infos: &[Arc<BlobInfo>],
         fs_prefetch: bool,
     ) -> io::Result<()> {
-        if self.blobs.load().len() != blob_infos.len() {
+        self.update_with_source(config, Arc::new(blob_infos.to_vec()), fs_prefetch)
+    }
+
+    /// Update configuration and storage backends of the blob device, getting [BlobInfo] objects
+    /// from `source` on demand.
+    pub fn update_with_source(
+        &self,
+        config: &Arc<FactoryConfig>,
+        source: Arc<dyn BlobInfoSource>,
+        fs_prefetch: bool,
+    ) -> io::Result<()> {
+        let blob_count = source.blob_count();
+        if self.blobs.load().len() != blob_count {
             return Err(einval!(
                 "number of blobs doesn't match when update 'BlobDevice' object"
             ));
         }
 
-        let mut blobs = Vec::with_capacity(blob_infos.len());
-        for blob_info in blob_infos.iter() {
-            let blob = BLOB_FACTORY.new_blob_cache(config, blob_info, blob_infos.len())?;
-            blobs.push(blob);
+        let creator = Self::new_creator(config, blob_count);
+        let old_blobs = self.blobs.load_full();
+        let mut blobs = Vec::with_capacity(blob_count);
+        for (idx, old) in old_blobs.iter().enumerate() {
+            let first_access = old.first_access.load(Ordering::Relaxed);
+            let entry =
+                BlobDeviceEntry::new(idx as u32, source.clone(), creator.clone(), first_access);
+            if old.get_ready_cache().is_some() {
+                let blob_info = source.blob_info(idx as u32)?;
+                *entry.cache.lock().unwrap() = Some((entry.creator)(&blob_info)?);
+            }
+            blobs.push(Arc::new(entry));
         }
 
         if fs_prefetch {
@@ -905,13 +1254,94 @@ impl BlobDevice {
             Err(einval!("BlobIoVec has out of range blob_index."))
         } else {
             let size = desc.bi_size;
+            let trace = desc.bi_trace.clone();
+            let _guard = trace.as_ref().map(|t| t.enter());
+            let begin = trace.as_ref().map(|_| Instant::now());
             let mut f = BlobDeviceIoVec::new(self, desc);
             // The `off` parameter to w.write_from() is actually ignored by
             // BlobV5IoVec::read_vectored_at_volatile()
-            w.write_from(&mut f, size as usize, 0)
+            let result = w.write_from(&mut f, size as usize, 0);
+            trace::record_current("blob_cache_read", begin);
+            result
         }
     }
 
+    /// Read a range of data from a data blob into the buffer.
+    ///
+    /// It's used by services without zero-copy writers, such as the NFS server.
+    pub fn read(&self, desc: &mut BlobIoVec, buf: &mut [u8]) -> io::Result<usize> {
+        if desc.bi_vec.is_empty() {
+            if desc.bi_size == 0 {
+                Ok(0)
+            } else {
+                Err(einval!("BlobIoVec size doesn't match."))
+            }
+        } else if desc.blob_index() as usize >= self.blob_count {
+            Err(einval!("BlobIoVec has out of range blob_index."))
+        } else if desc.bi_size as usize > buf.len() {
+            Err(einval!("buffer is too small for BlobIoVec."))
+        } else {
+            let trace = desc.bi_trace.clone();
+            let _guard = trace.as_ref().map(|t| t.enter());
+            let begin = trace.as_ref().map(|_| Instant::now());
+            // Safe because the slice is within the buffer.
+            let slice =
+                unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), desc.bi_size as usize) };
+            let result = match self.get_blob(desc.blob_index()) {
+                Some(blob) => blob.and_then(|blob| blob.read(desc, &[slice])),
+                None => Err(einval!("BlobIoVec has out of range blob_index.")),
+            };
+            trace::record_current("blob_cache_read", begin);
+            result
+        }
+    }
+
+    /// Get a `BlobCachedFile` object to read data covered by `desc` from the local cache file.
+    ///
+    /// Return None if data isn't fully ready in the cache file in uncompressed form.
+    pub fn get_cached_file(&self, desc: &BlobIoVec) -> Option<BlobCachedFile> {
+        if desc.bi_vec.is_empty() {
+            return None;
+        }
+        self.get_blob_by_iovec(desc)
+            .and_then(|blob| blob.get_cached_file(&desc.bi_vec))
+            .filter(|file| file.size() == desc.bi_size as u64)
+    }
+
+    /// Fetch chunks covered by `desc` into the local cache file, and get a `BlobCachedFile` object
+    /// to access data of each chunk.
+    ///
+    /// Missing chunks are fetched from the storage backend synchronously.
+    pub fn fetch_cached_chunks(&self, desc: &BlobIoVec) -> io::Result<Vec<BlobCachedFile>> {
+        let blob = self
+            .get_blob_by_iovec(desc)
+            .ok_or_else(|| einval!("BlobIoVec has out of range blob_index."))?;
+        let mut files = Vec::with_capacity(desc.bi_vec.len());
+
+        for bio in desc.bi_vec.iter() {
+            let bios = std::slice::from_ref(bio);
+            let file = match blob.get_cached_file(bios) {
+                Some(v) => v,
+                None => {
+                    let obj = blob.get_blob_object().ok_or_else(|| {
+                        enosys!("blob cache doesn't support direct access to cache file")
+                    })?;
+                    let chunk = &bio.chunkinfo;
+                    obj.fetch_range_uncompressed(
+                        chunk.uncompressed_offset(),
+                        chunk.uncompressed_size() as u64,
+                    )?;
+                    blob.get_cached_file(bios).ok_or_else(|| {
+                        eio!("chunk data is not available in uncompressed cache file")
+                    })?
+                }
+            };
+            files.push(file);
+        }
+
+        Ok(files)
+    }
+
     /// Try to prefetch specified blob data.
     pub fn prefetch(
         &self,
@@ -938,20 +1368,70 @@ impl BlobDevice {
         Ok(())
     }
 
+    /// Start to prefetch data requested by applications in background, with lower priority than
+    /// other prefetch requests.
+    pub fn prefetch_low_priority(&self, io_vecs: &[&BlobIoVec]) -> io::Result<()> {
+        for io_vec in io_vecs.iter() {
+            if let Some(blob) = self.get_blob_by_iovec(io_vec) {
+                // Prefetch errors are ignored.
+                let _ = blob
+                    .prefetch_low_priority(blob.clone(), &io_vec.bi_vec)
+                    .map_err(|e| {
+                        error!("failed to prefetch blob data, {}", e);
+                    });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Enable or disable validating data chunks by digest value at runtime.
+    ///
+    /// Blob cache objects may be shared with other blob devices using the same storage
+    /// configuration, so the change also applies to them.
+    pub fn set_validation(&self, enable: bool) {
+        *self.state.validation.lock().unwrap() = Some(enable);
+        for blob in self.blobs.load().iter() {
+            if let Some(cache) = blob.get_ready_cache() {
+                cache.set_validation(enable);
+            }
+        }
+    }
+
     /// Start the background blob data prefetch task.
     pub fn start_prefetch(&self) {
+        self.state.prefetch.store(true, Ordering::Release);
         for blob in self.blobs.load().iter() {
-            let _ = blob.start_prefetch();
+            if let Some(cache) = blob.get_ready_cache() {
+                let _ = cache.start_prefetch();
+            }
         }
     }
 
     /// Stop the background blob data prefetch task.
     pub fn stop_prefetch(&self) {
+        self.state.prefetch.store(false, Ordering::Release);
         for blob in self.blobs.load().iter() {
-            let _ = blob.stop_prefetch();
+            if let Some(cache) = blob.get_ready_cache() {
+                let _ = cache.stop_prefetch();
+            }
         }
     }
 
+    /// Check whether the blob with index `blob_index` has been registered by accessing it.
+    pub fn is_blob_ready(&self, blob_index: u32) -> bool {
+        self.blobs
+            .load()
+            .get(blob_index as usize)
+            .map(|blob| blob.get_ready_cache().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Get access information about all blobs of the blob device.
+    pub fn get_blob_access_info(&self) -> Vec<BlobAccessInfo> {
+        self.blobs.load().iter().map(|b| b.access_info()).collect()
+    }
+
     /// fetch specified blob data in a synchronous way.
     pub fn fetch_range_synchronous(&self, prefetches: &[BlobPrefetchRequest]) -> io::Result<()> {
         for req in prefetches {
@@ -1008,32 +1488,55 @@ impl BlobDevice {
 
     /// RAFS V6: create a `BlobIoChunk` for chunk with index `chunk_index`.
     pub fn create_io_chunk(&self, blob_index: u32, chunk_index: u32) -> Option<BlobIoChunk> {
+        match self.get_blob(blob_index)? {
+            Ok(blob) => blob.get_chunk_info(chunk_index).map(|v| v.into()),
+            Err(e) => {
+                error!("failed to get blob cache for blob {}, {}", blob_index, e);
+                None
+            }
+        }
+    }
+
+    /// Get the [BlobCache] object for blob `blob_index`, registering the blob on first access.
+    ///
+    /// Return None if `blob_index` is out of range.
+    fn get_blob(&self, blob_index: u32) -> Option<io::Result<Arc<dyn BlobCache>>> {
         if (blob_index as usize) < self.blob_count {
-            let state = self.blobs.load();
-            let blob = &state[blob_index as usize];
-            blob.get_chunk_info(chunk_index).map(|v| v.into())
+            let entry = self.blobs.load()[blob_index as usize].clone();
+            Some(entry.get_cache(&self.state))
         } else {
             None
         }
     }
 
     fn get_blob_by_iovec(&self, iovec: &BlobIoVec) -> Option<Arc<dyn BlobCache>> {
-        let blob_index = iovec.blob_index();
-        if (blob_index as usize) < self.blob_count {
-            return Some(self.blobs.load()[blob_index as usize].clone());
+        match self.get_blob(iovec.blob_index())? {
+            Ok(blob) => Some(blob),
+            Err(e) => {
+                error!(
+                    "failed to get blob cache for blob {}, {}",
+                    iovec.blob_index(),
+                    e
+                );
+                None
+            }
         }
-
-        None
     }
 
     fn get_blob_by_id(&self, blob_id: &str) -> Option<Arc<dyn BlobCache>> {
-        for blob in self.blobs.load().iter() {
-            if blob.blob_id() == blob_id {
-                return Some(blob.clone());
+        let entry = self
+            .blobs
+            .load()
+            .iter()
+            .find(|blob| blob.blob_id().as_deref() == Some(blob_id))?
+            .clone();
+        match entry.get_cache(&self.state) {
+            Ok(blob) => Some(blob),
+            Err(e) => {
+                error!("failed to get blob cache for blob {}, {}", blob_id, e);
+                None
             }
         }
-
-        None
     }
 }
 
@@ -1079,17 +1582,16 @@ impl FileReadWriteVolatile for BlobDeviceIoVec<'_> {
     ) -> Result<usize, Error> {
         // BlobDevice::read_to() has validated that all IOs are against a single blob.
         let index = self.iovec.blob_index();
-        let blobs = &self.dev.blobs.load();
 
-        if (index as usize) < blobs.len() {
-            blobs[index as usize].read(self.iovec, buffers)
-        } else {
-            let msg = format!(
-                "failed to get blob object for BlobIoVec, index {}, blob array len: {}",
-                index,
-                blobs.len()
-            );
-            Err(einval!(msg))
+        match self.dev.get_blob(index) {
+            Some(blob) => blob?.read(self.iovec, buffers),
+            None => {
+                let msg = format!(
+                    "failed to get blob object for BlobIoVec, index {}, blob array len: {}",
+                    index, self.dev.blob_count
+                );
+                Err(einval!(msg))
+            }
         }
     }
 
@@ -1102,6 +1604,49 @@ impl FileReadWriteVolatile for BlobDeviceIoVec<'_> {
     }
 }
 
+struct BlobCachedFileReader<'a> {
+    file: &'a BlobCachedFile,
+}
+
+impl FileReadWriteVolatile for BlobCachedFileReader<'_> {
+    fn read_volatile(&mut self, _slice: FileVolatileSlice) -> Result<usize, Error> {
+        Err(enosys!(
+            "BlobCachedFileReader only supports positioned reads"
+        ))
+    }
+
+    fn write_volatile(&mut self, _slice: FileVolatileSlice) -> Result<usize, Error> {
+        Err(enosys!("BlobCachedFileReader doesn't support writes"))
+    }
+
+    fn read_at_volatile(&mut self, slice: FileVolatileSlice, offset: u64) -> Result<usize, Error> {
+        self.read_vectored_at_volatile(&[slice], offset)
+    }
+
+    fn read_vectored_at_volatile(
+        &mut self,
+        buffers: &[FileVolatileSlice],
+        offset: u64,
+    ) -> Result<usize, Error> {
+        let size = buffers.iter().map(|b| b.len()).sum();
+        let mut cursor = MemSliceCursor::new(buffers);
+        let mut iovec = cursor.consume(size);
+        readv(
+            self.file.file.as_raw_fd(),
+            &mut iovec,
+            self.file.base + offset,
+        )
+    }
+
+    fn write_at_volatile(
+        &mut self,
+        _slice: FileVolatileSlice,
+        _offset: u64,
+    ) -> Result<usize, Error> {
+        Err(enosys!("BlobCachedFileReader doesn't support writes"))
+    }
+}
+
 /// Traits and Structs to support Rafs v5 image format.
 ///
 /// The Rafs v5 image format is designed with fused filesystem metadata and blob management
@@ -1136,8 +1681,15 @@ pub mod v5 {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use nydus_api::http::CacheConfig;
+    use nydus_utils::metrics::BackendMetrics;
+
     use super::*;
-    use crate::test::MockChunkInfo;
+    use crate::backend::{BackendResult, BlobBackend, BlobReader};
+    use crate::cache::{BlobCacheMgr, MemoryCacheMgr};
+    use crate::test::{MockBackend, MockChunkInfo};
 
     #[test]
     fn test_blob_io_chunk() {
@@ -1163,6 +1715,35 @@ mod tests {
         assert!(!iochunk.is_compressed());
     }
 
+    #[test]
+    fn test_blob_foreign_layer() {
+        let mut blob_info = BlobInfo::new(
+            1,
+            "test1".to_owned(),
+            0x200000,
+            0x100000,
+            0x100000,
+            512,
+            BlobFeatures::empty(),
+        );
+        blob_info.set_compressor(compress::Algorithm::Zstd);
+        assert!(!blob_info.is_legacy_stargz());
+        assert!(!blob_info.is_foreign_layer());
+
+        blob_info.set_blob_meta_info(
+            BLOB_META_FEATURE_CHUNK_INFO_V2 | BLOB_META_FEATURE_FOREIGN_LAYER,
+            0,
+            0,
+            0,
+            0,
+        );
+        assert!(blob_info.is_foreign_layer());
+        assert!(blob_info.has_feature(BlobFeatures::FOREIGN_LAYER));
+
+        blob_info.set_compressor(compress::Algorithm::GZip);
+        assert!(blob_info.is_foreign_layer());
+    }
+
     #[test]
     fn test_chunk_is_continuous() {
         let blob_info = Arc::new(BlobInfo::new(
@@ -1248,4 +1829,124 @@ mod tests {
         assert!(desc2.is_continuous(&desc3, 0x800));
         assert!(desc2.is_continuous(&desc3, 0x1000));
     }
+
+    struct CountingBackend {
+        inner: MockBackend,
+        readers: AtomicUsize,
+    }
+
+    impl BlobBackend for CountingBackend {
+        fn shutdown(&self) {}
+
+        fn metrics(&self) -> &BackendMetrics {
+            BlobBackend::metrics(&self.inner)
+        }
+
+        fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+            self.readers.fetch_add(1, Ordering::Relaxed);
+            self.inner.get_reader(blob_id)
+        }
+    }
+
+    #[test]
+    fn test_blob_device_lazy_registration() {
+        let backend = Arc::new(CountingBackend {
+            inner: MockBackend {
+                metrics: BackendMetrics::new("blob-device-lazy", "mock"),
+            },
+            readers: AtomicUsize::new(0),
+        });
+        let config = CacheConfig {
+            cache_type: "memory".to_string(),
+            cache_config: serde_json::json!({ "capacity": 0x2000 }),
+            cache_validate: false,
+            ..Default::default()
+        };
+        let mgr = MemoryCacheMgr::new(config, backend.clone(), "blob-device-lazy").unwrap();
+        mgr.init().unwrap();
+        let mgr = Arc::new(mgr);
+        let creator: Arc<BlobCacheCreator> =
+            Arc::new(move |blob_info: &Arc<BlobInfo>| mgr.get_blob_cache(blob_info));
+
+        let blob_infos: Vec<Arc<BlobInfo>> = (0..3)
+            .map(|idx| {
+                Arc::new(BlobInfo::new(
+                    idx,
+                    format!("blob-device-lazy-{}", idx),
+                    0x4000,
+                    0x4000,
+                    0x1000,
+                    4,
+                    BlobFeatures::V5_NO_EXT_BLOB_TABLE,
+                ))
+            })
+            .collect();
+        let device = BlobDevice::with_creator(Arc::new(blob_infos.clone()), creator);
+        device.set_validation(false);
+        device.start_prefetch();
+        device.stop_prefetch();
+        assert_eq!(backend.readers.load(Ordering::Relaxed), 0);
+        assert!(!device.is_blob_ready(1));
+
+        let chunk: Arc<dyn BlobChunkInfo> = Arc::new(MockChunkInfo {
+            block_id: Default::default(),
+            blob_index: 1,
+            flags: BlobChunkFlags::empty(),
+            compress_size: 0x1000,
+            uncompress_size: 0x1000,
+            compress_offset: 0,
+            uncompress_offset: 0,
+            file_offset: 0,
+            index: 0,
+            reserved: 0,
+        });
+        let mut buf = vec![0u8; 0x800];
+        for _ in 0..2 {
+            let mut iovec = BlobIoVec::new(blob_infos[1].clone());
+            iovec.push(BlobIoDesc {
+                blob: blob_infos[1].clone(),
+                chunkinfo: chunk.clone().into(),
+                offset: 0x100,
+                size: 0x800,
+                user_io: true,
+            });
+            assert_eq!(device.read(&mut iovec, &mut buf).unwrap(), 0x800);
+        }
+        assert_eq!(backend.readers.load(Ordering::Relaxed), 1);
+        assert!(!device.is_blob_ready(0));
+        assert!(device.is_blob_ready(1));
+        assert!(!device.is_blob_ready(2));
+        assert!(!device.is_blob_ready(3));
+
+        let infos = device.get_blob_access_info();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[1].blob_id, "blob-device-lazy-1");
+        assert!(infos[1].ready);
+        assert!(infos[1].first_access.is_some());
+        assert!(!infos[0].ready);
+        assert!(infos[0].first_access.is_none());
+        assert!(infos[2].first_access.is_none());
+    }
+
+    #[test]
+    fn test_blob_cached_file_reader() {
+        let tmp = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(tmp.as_path(), b"0123456789abcdef").unwrap();
+        let file = BlobCachedFile::new(Arc::new(tmp.into_file()), 4, 8);
+        let mut reader = BlobCachedFileReader { file: &file };
+
+        let mut buf = vec![0u8; 4];
+        let slice = unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(reader.read_at_volatile(slice, 2).unwrap(), 4);
+        assert_eq!(&buf, b"6789");
+
+        let enosys = Some(libc::ENOSYS);
+        let slice = unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+        let err = reader.read_volatile(slice).unwrap_err();
+        assert_eq!(err.raw_os_error(), enosys);
+        let err = reader.write_volatile(slice).unwrap_err();
+        assert_eq!(err.raw_os_error(), enosys);
+        let err = reader.write_at_volatile(slice, 0).unwrap_err();
+        assert_eq!(err.raw_os_error(), enosys);
+    }
 }
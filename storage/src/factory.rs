@@ -14,11 +14,14 @@ use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::io::Result as IOResult;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
 use std::time::Duration;
 
 use lazy_static::lazy_static;
-use nydus_api::http::{BackendConfig, FactoryConfig};
+use nydus_api::http::{BackendConfig, BackendHealthCheckConfig, FactoryConfig};
+use nydus_utils::audit::{self, EventCategory};
+use nydus_utils::compress;
 use tokio::runtime::{Builder, Runtime};
 use tokio::time;
 
@@ -28,8 +31,13 @@ use crate::backend::localfs;
 use crate::backend::oss;
 #[cfg(feature = "backend-registry")]
 use crate::backend::registry;
+#[cfg(feature = "backend-s3")]
+use crate::backend::s3;
 use crate::backend::BlobBackend;
-use crate::cache::{BlobCache, BlobCacheMgr, DummyCacheMgr, FileCacheMgr, FsCacheMgr};
+use crate::cache::state::InflightTracerRegistry;
+use crate::cache::{
+    BlobCache, BlobCacheMgr, DummyCacheMgr, FileCacheMgr, FsCacheMgr, MemoryCacheMgr,
+};
 use crate::device::BlobInfo;
 
 lazy_static! {
@@ -72,6 +80,8 @@ lazy_static::lazy_static! {
 pub struct BlobFactory {
     mgrs: Mutex<HashMap<BlobCacheMgrKey, Arc<dyn BlobCacheMgr>>>,
     mgr_checker_active: AtomicBool,
+    // Inflight chunk fetches shared by blob cache managers using the same cache files.
+    inflight_tracers: Arc<InflightTracerRegistry>,
 }
 
 impl BlobFactory {
@@ -80,6 +90,7 @@ impl BlobFactory {
         BlobFactory {
             mgrs: Mutex::new(HashMap::new()),
             mgr_checker_active: AtomicBool::new(false),
+            inflight_tracers: Arc::new(InflightTracerRegistry::default()),
         }
     }
 
@@ -107,6 +118,12 @@ impl BlobFactory {
         blob_info: &Arc<BlobInfo>,
         blobs_need: usize,
     ) -> IOResult<Arc<dyn BlobCache>> {
+        if blob_info.is_encrypted() && blob_info.cipher_key().is_none() {
+            return Err(einval!(format!(
+                "blob {} is encrypted, but no key is available to decrypt it",
+                blob_info.blob_id()
+            )));
+        }
         let key = BlobCacheMgrKey {
             config: config.clone(),
         };
@@ -115,6 +132,8 @@ impl BlobFactory {
         if let Some(mgr) = guard.get(&key) {
             return mgr.get_blob_cache(blob_info);
         }
+        let gzip_backend = compress::parse_gzip_backend(&config.cache.gzip_backend)?;
+        compress::set_gzip_backend(gzip_backend);
         let backend = Self::new_backend(key.config.backend.clone(), blob_info.blob_id())?;
         let mgr = match key.config.cache.cache_type.as_str() {
             "blobcache" => {
@@ -122,6 +141,7 @@ impl BlobFactory {
                     config.cache.clone(),
                     backend,
                     ASYNC_RUNTIME.clone(),
+                    self.inflight_tracers.clone(),
                     &config.id,
                 )?;
                 mgr.init()?;
@@ -138,12 +158,20 @@ impl BlobFactory {
                 mgr.init()?;
                 Arc::new(mgr) as Arc<dyn BlobCacheMgr>
             }
+            "memory" => {
+                let mgr = MemoryCacheMgr::new(config.cache.clone(), backend, &config.id)?;
+                mgr.init()?;
+                Arc::new(mgr) as Arc<dyn BlobCacheMgr>
+            }
             _ => {
                 let mgr = DummyCacheMgr::new(config.cache.clone(), backend, false)?;
                 mgr.init()?;
                 Arc::new(mgr) as Arc<dyn BlobCacheMgr>
             }
         };
+        if config.backend.health_check.interval > 0 {
+            BackendHealthChecker::start(&mgr, &config.backend.health_check, blob_info.blob_id())?;
+        }
 
         let mgr = guard.entry(key).or_insert_with(|| mgr);
 
@@ -177,12 +205,20 @@ impl BlobFactory {
             }
         }
 
+        let mut released = 0;
         for (key, mgr) in mgrs {
             let mut guard = self.mgrs.lock().unwrap();
             if mgr.gc(None) {
                 guard.remove(&key);
+                released += 1;
             }
         }
+
+        let detail = serde_json::json!({
+            "blob_id": victim.map(|(_, id)| id),
+            "released_managers": released,
+        });
+        audit::record::<_, ()>(EventCategory::CacheGc, None, detail, &Ok(()));
     }
 
     /// Create a storage backend for the blob with id `blob_id`.
@@ -202,6 +238,8 @@ impl BlobFactory {
                 config.backend_config,
                 Some(blob_id),
             )?)),
+            #[cfg(feature = "backend-s3")]
+            "s3" => Ok(Arc::new(s3::S3::new(config.backend_config, Some(blob_id))?)),
             #[cfg(feature = "backend-localfs")]
             "localfs" => Ok(Arc::new(localfs::LocalFs::new(
                 config.backend_config,
@@ -228,15 +266,300 @@ impl Default for BlobFactory {
     }
 }
 
+/// Health checker to periodically probe the storage backend of a blob cache manager.
+///
+/// Once the storage backend recovers from failures, failure states cached by the storage backend
+/// and blob cache objects are reset, so IO requests may succeed again without remounting.
+struct BackendHealthChecker {
+    mgr: Weak<dyn BlobCacheMgr>,
+    probe_object: String,
+    interval: Duration,
+}
+
+impl BackendHealthChecker {
+    /// Start a thread to check health of the storage backend, which exits once the blob cache
+    /// manager gets released.
+    fn start(
+        mgr: &Arc<dyn BlobCacheMgr>,
+        config: &BackendHealthCheckConfig,
+        blob_id: &str,
+    ) -> IOResult<()> {
+        let probe_object = if config.probe_object.is_empty() {
+            blob_id.to_string()
+        } else {
+            config.probe_object.clone()
+        };
+        let checker = BackendHealthChecker {
+            mgr: Arc::downgrade(mgr),
+            probe_object,
+            interval: Duration::from_secs(config.interval),
+        };
+
+        thread::Builder::new()
+            .name("backend-health".to_string())
+            .spawn(move || loop {
+                thread::sleep(checker.interval);
+                match checker.mgr.upgrade() {
+                    Some(mgr) => {
+                        Self::check(mgr.as_ref(), &checker.probe_object);
+                    }
+                    None => break,
+                }
+            })
+            .map(|_| ())
+    }
+
+    /// Probe the storage backend and handle health state changes, return whether it's healthy.
+    fn check(mgr: &dyn BlobCacheMgr, probe_object: &str) -> bool {
+        let backend = mgr.backend();
+        let result = backend
+            .get_reader(probe_object)
+            .and_then(|reader| reader.blob_size());
+        let healthy = result.is_ok();
+
+        if backend.metrics().set_healthy(healthy) {
+            match result {
+                Ok(_) => {
+                    info!("storage backend recovered, reset cached failure states");
+                    backend.reset();
+                    mgr.backend_recovered();
+                }
+                Err(e) => warn!(
+                    "storage backend becomes unhealthy when probing {}, {:?}",
+                    probe_object, e
+                ),
+            }
+        }
+
+        healthy
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::{BackendError, BackendResult, BlobReader};
+    use crate::device::{BlobChunkFlags, BlobFeatures};
+    use crate::test::MockChunkInfo;
+    use nydus_api::http::CacheConfig;
+    use nydus_utils::metrics::BackendMetrics;
+
+    // Mock backend with a circuit breaker, which rejects all reads after a failure until reset.
+    struct ToggleBackend {
+        up: Arc<AtomicBool>,
+        tripped: Arc<AtomicBool>,
+        metrics: Arc<BackendMetrics>,
+    }
+
+    impl BlobReader for ToggleBackend {
+        fn blob_size(&self) -> BackendResult<u64> {
+            if self.up.load(Ordering::Relaxed) {
+                Ok(0x1000)
+            } else {
+                Err(BackendError::Unsupported("backend is down".to_string()))
+            }
+        }
+
+        fn try_read(&self, buf: &mut [u8], _offset: u64) -> BackendResult<usize> {
+            if self.tripped.load(Ordering::Relaxed) || !self.up.load(Ordering::Relaxed) {
+                self.tripped.store(true, Ordering::Relaxed);
+                return Err(BackendError::Unsupported("backend is down".to_string()));
+            }
+            buf.fill(0x5a);
+            Ok(buf.len())
+        }
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+    }
+
+    impl BlobBackend for ToggleBackend {
+        fn shutdown(&self) {}
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+
+        fn get_reader(&self, _blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+            Ok(Arc::new(ToggleBackend {
+                up: self.up.clone(),
+                tripped: self.tripped.clone(),
+                metrics: self.metrics.clone(),
+            }))
+        }
+
+        fn reset(&self) {
+            self.tripped.store(false, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_backend_health_check() {
+        let up = Arc::new(AtomicBool::new(true));
+        let backend = Arc::new(ToggleBackend {
+            up: up.clone(),
+            tripped: Arc::new(AtomicBool::new(false)),
+            metrics: BackendMetrics::new("test_backend_health_check", "mock"),
+        });
+        let mgr = DummyCacheMgr::new(CacheConfig::default(), backend.clone(), false).unwrap();
+        let mgr = Arc::new(mgr) as Arc<dyn BlobCacheMgr>;
+        let blob_info = Arc::new(BlobInfo::new(
+            0,
+            "blob".to_string(),
+            0x1000,
+            0x1000,
+            0x1000,
+            1,
+            BlobFeatures::V5_NO_EXT_BLOB_TABLE,
+        ));
+        let cache = mgr.get_blob_cache(&blob_info).unwrap();
+        let chunk = MockChunkInfo {
+            flags: BlobChunkFlags::empty(),
+            compress_size: 0x1000,
+            uncompress_size: 0x1000,
+            ..Default::default()
+        };
+        let mut buf = vec![0u8; 0x1000];
+
+        assert!(BackendHealthChecker::check(mgr.as_ref(), "blob"));
+        assert!(backend.metrics.is_healthy());
+        cache.read_chunk_from_backend(&chunk, &mut buf).unwrap();
+
+        up.store(false, Ordering::Relaxed);
+        assert!(cache.read_chunk_from_backend(&chunk, &mut buf).is_err());
+        assert!(!BackendHealthChecker::check(mgr.as_ref(), "blob"));
+        assert!(!backend.metrics.is_healthy());
+
+        // Reads keep failing due to the cached failure state even if the backend is up again.
+        up.store(true, Ordering::Relaxed);
+        assert!(cache.read_chunk_from_backend(&chunk, &mut buf).is_err());
+        assert!(BackendHealthChecker::check(mgr.as_ref(), "blob"));
+        assert!(backend.metrics.is_healthy());
+        buf.fill(0);
+        cache.read_chunk_from_backend(&chunk, &mut buf).unwrap();
+        assert_eq!(buf, vec![0x5au8; 0x1000]);
+
+        backend.metrics.release().unwrap();
+    }
+
+    #[cfg(feature = "backend-localfs")]
+    #[test]
+    fn test_shared_cache_file_fetches() {
+        use crate::device::{BlobChunkInfo, BlobIoDesc, BlobIoVec};
+        use fuse_backend_rs::file_buf::FileVolatileSlice;
+        use vmm_sys_util::tempdir::TempDir;
+
+        let blob_dir = TempDir::new().unwrap();
+        let work_dir = TempDir::new().unwrap();
+        let blob_id = "shared-cache-file-blob";
+        let chunk_count = 32u32;
+        let data: Vec<u8> = (0..chunk_count * 0x1000)
+            .map(|v| (v / 0x1000) as u8)
+            .collect();
+        std::fs::write(blob_dir.as_path().join(blob_id), &data).unwrap();
+
+        // Two mounts with different configurations share the same cache file under `work_dir`.
+        let configs: Vec<Arc<FactoryConfig>> = ["mount-a", "mount-b"]
+            .iter()
+            .map(|id| {
+                Arc::new(FactoryConfig {
+                    id: id.to_string(),
+                    backend: BackendConfig {
+                        backend_type: "localfs".to_string(),
+                        backend_config: serde_json::json!({ "dir": blob_dir.as_path() }),
+                        ..Default::default()
+                    },
+                    cache: CacheConfig {
+                        cache_type: "blobcache".to_string(),
+                        cache_config: serde_json::json!({ "work_dir": work_dir.as_path() }),
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+        let blob_info = Arc::new(BlobInfo::new(
+            0,
+            blob_id.to_string(),
+            data.len() as u64,
+            data.len() as u64,
+            0x1000,
+            chunk_count,
+            BlobFeatures::empty(),
+        ));
+        let caches: Vec<Arc<dyn BlobCache>> = configs
+            .iter()
+            .map(|config| BLOB_FACTORY.new_blob_cache(config, &blob_info, 1).unwrap())
+            .collect();
+
+        let mut handles = Vec::new();
+        for idx in 0..8 {
+            let cache = caches[idx % 2].clone();
+            let blob_info = blob_info.clone();
+            let data = data.clone();
+            handles.push(thread::spawn(move || {
+                for index in 0..chunk_count {
+                    let chunk: Arc<dyn BlobChunkInfo> = Arc::new(MockChunkInfo {
+                        flags: BlobChunkFlags::empty(),
+                        compress_offset: index as u64 * 0x1000,
+                        compress_size: 0x1000,
+                        uncompress_offset: index as u64 * 0x1000,
+                        uncompress_size: 0x1000,
+                        index,
+                        ..Default::default()
+                    });
+                    let mut iovec = BlobIoVec::new(blob_info.clone());
+                    iovec.push(BlobIoDesc::new(
+                        blob_info.clone(),
+                        chunk.into(),
+                        0,
+                        0x1000,
+                        true,
+                    ));
+                    let mut buf = vec![0u8; 0x1000];
+                    // Safe because the slice is within the buffer.
+                    let slice =
+                        unsafe { FileVolatileSlice::from_raw_ptr(buf.as_mut_ptr(), buf.len()) };
+                    assert_eq!(cache.read(&mut iovec, &[slice]).unwrap(), 0x1000);
+                    let offset = index as usize * 0x1000;
+                    assert_eq!(buf, &data[offset..offset + 0x1000]);
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // Each chunk is fetched from the backend only once by the two blob cache managers.
+        let mut fetched = 0;
+        for config in configs.iter() {
+            let key = BlobCacheMgrKey {
+                config: config.clone(),
+            };
+            let mgr = BLOB_FACTORY
+                .mgrs
+                .lock()
+                .unwrap()
+                .get(&key)
+                .cloned()
+                .unwrap();
+            let metrics = serde_json::to_value(mgr.backend().metrics()).unwrap();
+            fetched += metrics["read_amount_total"].as_u64().unwrap();
+        }
+        assert_eq!(fetched, data.len() as u64);
+
+        drop(caches);
+        for config in configs.iter() {
+            BLOB_FACTORY.gc(Some((config, blob_id)));
+        }
+    }
 
     #[test]
     fn test_backend_config() {
         let config = BackendConfig {
             backend_type: "localfs".to_string(),
             backend_config: Default::default(),
+            ..Default::default()
         };
         let str_val = serde_json::to_string(&config).unwrap();
         let config2 = serde_json::from_str(&str_val).unwrap();
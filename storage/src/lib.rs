@@ -51,6 +51,7 @@ pub mod cache;
 pub mod device;
 pub mod factory;
 pub mod meta;
+pub mod prefetch_job;
 //pub mod remote;
 #[cfg(test)]
 pub(crate) mod test;
@@ -85,6 +86,7 @@ pub enum StorageError {
     MemOverflow,
     NotContinuous,
     CacheIndex(std::io::Error),
+    FetchFailed,
 }
 
 impl Display for StorageError {
@@ -96,6 +98,9 @@ impl Display for StorageError {
             StorageError::NotContinuous => write!(f, "address ranges are not continuous"),
             StorageError::VolatileSlice(e) => write!(f, "{}", e),
             StorageError::CacheIndex(e) => write!(f, "Wrong cache index {}", e),
+            StorageError::FetchFailed => {
+                write!(f, "inflight fetch from storage backend failed")
+            }
         }
     }
 }
@@ -82,8 +82,10 @@ pub const BLOB_META_FEATURE_SEPARATE: u32 = 0x2;
 pub const BLOB_META_FEATURE_CHUNK_INFO_V2: u32 = 0x4;
 /// Blob compression information data include context data for zlib random access.
 pub const BLOB_META_FEATURE_ZRAN: u32 = 0x8;
+/// Chunk data is referenced in place from a foreign (eStargz/zstd:chunked) layer blob.
+pub const BLOB_META_FEATURE_FOREIGN_LAYER: u32 = 0x10;
 /// All valid blob feature bits.
-pub const BLOB_META_FEATURE_MASK: u32 = 0xf;
+pub const BLOB_META_FEATURE_MASK: u32 = 0x1f;
 
 /// On disk format for blob meta data header, containing meta information for a data blob.
 #[repr(C)]
@@ -148,6 +150,8 @@ impl BlobMetaHeaderOndisk {
             compress::Algorithm::GZip
         } else if self.s_ci_compressor == compress::Algorithm::Zstd as u32 {
             compress::Algorithm::Zstd
+        } else if self.s_ci_compressor == compress::Algorithm::Snappy as u32 {
+            compress::Algorithm::Snappy
         } else {
             compress::Algorithm::None
         }
@@ -270,6 +274,20 @@ impl BlobMetaHeaderOndisk {
         }
     }
 
+    /// Check whether the chunk data is referenced from a foreign layer blob.
+    pub fn is_foreign_layer(&self) -> bool {
+        self.s_features & BLOB_META_FEATURE_FOREIGN_LAYER != 0
+    }
+
+    /// Set flag indicating whether the chunk data is referenced from a foreign layer blob.
+    pub fn set_foreign_layer(&mut self, enable: bool) {
+        if enable {
+            self.s_features |= BLOB_META_FEATURE_FOREIGN_LAYER;
+        } else {
+            self.s_features &= !BLOB_META_FEATURE_FOREIGN_LAYER;
+        }
+    }
+
     /// Get blob meta feature flags.
     pub fn meta_flags(&self) -> u32 {
         self.s_features
@@ -1470,6 +1488,9 @@ pub fn format_blob_meta_features(features: u32) -> String {
     if features & BLOB_META_FEATURE_ZRAN != 0 {
         output += "zran ";
     }
+    if features & BLOB_META_FEATURE_FOREIGN_LAYER != 0 {
+        output += "foreign-layer ";
+    }
     output.trim_end().to_string()
 }
 
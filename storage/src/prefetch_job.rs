@@ -0,0 +1,540 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Jobs to pull blob data into blob caches ahead of mounting filesystems.
+//!
+//! Container runtimes know data blobs of an image as soon as the image manifest is fetched, so
+//! they may start pulling blob data before filesystems get mounted. A prefetch job downloads
+//! compressed blob ranges into blob cache files through the blob cache layer, without loading
+//! RAFS metadata. Chunk states are tracked by the chunk map of the blob cache, so chunks already
+//! fetched or being fetched by overlapping jobs, or by a mounted filesystem sharing the same
+//! storage configuration, won't be downloaded again.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nydus_api::http::{
+    BackendConfig, BlobCacheEntryConfig, CacheConfig, FactoryConfig, PrefetchJobBlobConfig,
+};
+use nydus_utils::{compress, digest};
+
+use crate::device::{BlobFeatures, BlobInfo, BlobObject};
+use crate::factory::BLOB_FACTORY;
+
+/// Amount of compressed data fetched by each step of a prefetch job.
+pub const PREFETCH_JOB_BATCH_SIZE: u64 = 0x10_0000;
+/// Number of finished jobs kept for querying status.
+const MAX_FINISHED_JOBS: usize = 64;
+
+/// State of a prefetch job.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PrefetchJobState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+impl Display for PrefetchJobState {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let s = match self {
+            PrefetchJobState::Running => "running",
+            PrefetchJobState::Completed => "completed",
+            PrefetchJobState::Cancelled => "cancelled",
+            PrefetchJobState::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Status information about a prefetch job.
+#[derive(Clone, Debug)]
+pub struct PrefetchJobStatus {
+    pub id: u64,
+    pub state: PrefetchJobState,
+    /// Total bytes of compressed data to pull.
+    pub total_bytes: u64,
+    /// Bytes of compressed data already pulled, including data already cached.
+    pub fetched_bytes: u64,
+    /// Error message if the job failed.
+    pub error: Option<String>,
+}
+
+/// A data blob and compressed data ranges to pull by a prefetch job.
+pub struct PrefetchJobBlob {
+    blob_info: Arc<BlobInfo>,
+    ranges: Vec<(u64, u64)>,
+}
+
+impl PrefetchJobBlob {
+    /// Create a `PrefetchJobBlob` object from the configuration information.
+    pub fn from_config(config: &PrefetchJobBlobConfig) -> Result<Self> {
+        let mut blob_info = BlobInfo::new(
+            0,
+            config.blob_id.clone(),
+            config.uncompressed_size,
+            config.compressed_size,
+            config.chunk_size,
+            config.chunk_count,
+            BlobFeatures::empty(),
+        );
+        let compressor = compress::Algorithm::from_str(&config.compressor)
+            .map_err(|_| invalid_input(format!("invalid compressor {}", config.compressor)))?;
+        blob_info.set_compressor(compressor);
+        let digester = digest::Algorithm::from_str(&config.digester)
+            .map_err(|_| invalid_input(format!("invalid digester {}", config.digester)))?;
+        blob_info.set_digester(digester);
+        let meta_ci_compressor = compress::Algorithm::from_str(&config.meta_ci_compressor)
+            .map_err(|_| {
+                invalid_input(format!(
+                    "invalid meta_ci_compressor {}",
+                    config.meta_ci_compressor
+                ))
+            })?;
+        blob_info.set_blob_meta_info(
+            config.meta_flags,
+            config.meta_ci_offset,
+            config.meta_ci_compressed_size,
+            config.meta_ci_uncompressed_size,
+            meta_ci_compressor as u32,
+        );
+        if !blob_info.meta_ci_is_valid() {
+            return Err(invalid_input(format!(
+                "blob {} has no valid chunk information array",
+                config.blob_id
+            )));
+        }
+
+        let ranges = if config.ranges.is_empty() {
+            vec![(0, config.compressed_size)]
+        } else {
+            let mut ranges = Vec::with_capacity(config.ranges.len());
+            for r in config.ranges.iter() {
+                match r.offset.checked_add(r.size) {
+                    Some(end) if end <= config.compressed_size => ranges.push((r.offset, r.size)),
+                    _ => {
+                        return Err(invalid_input(format!(
+                            "invalid range {}/{} for blob {}",
+                            r.offset, r.size, config.blob_id
+                        )))
+                    }
+                }
+            }
+            ranges
+        };
+
+        Ok(PrefetchJobBlob {
+            blob_info: Arc::new(blob_info),
+            ranges,
+        })
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.ranges.iter().map(|(_, size)| size).sum()
+    }
+}
+
+fn invalid_input(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Generate a [FactoryConfig] object from the blob cache configuration of a prefetch job.
+pub fn prefetch_job_factory_config(config: &BlobCacheEntryConfig) -> FactoryConfig {
+    FactoryConfig {
+        id: config.id.clone(),
+        backend: BackendConfig {
+            backend_type: config.backend_type.clone(),
+            backend_config: config.backend_config.clone(),
+            ..Default::default()
+        },
+        cache: CacheConfig {
+            cache_type: config.cache_type.clone(),
+            cache_compressed: false,
+            cache_config: config.cache_config.clone(),
+            cache_validate: false,
+            prefetch_config: config.prefetch_config.clone(),
+            gzip_backend: String::new(),
+        },
+    }
+}
+
+/// A job to pull compressed data ranges of blobs into blob caches.
+pub struct PrefetchJob {
+    id: u64,
+    total_bytes: u64,
+    fetched_bytes: AtomicU64,
+    cancelled: AtomicBool,
+    state: Mutex<(PrefetchJobState, Option<String>)>,
+}
+
+impl PrefetchJob {
+    fn new(id: u64, total_bytes: u64) -> Self {
+        PrefetchJob {
+            id,
+            total_bytes,
+            fetched_bytes: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new((PrefetchJobState::Running, None)),
+        }
+    }
+
+    /// Get id of the job.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Request to cancel the job, which stops after finishing the data being fetched.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Get status information about the job.
+    pub fn status(&self) -> PrefetchJobStatus {
+        let state = self.state.lock().unwrap();
+        PrefetchJobStatus {
+            id: self.id,
+            state: state.0,
+            total_bytes: self.total_bytes,
+            fetched_bytes: self.fetched_bytes.load(Ordering::Acquire),
+            error: state.1.clone(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().0 != PrefetchJobState::Running
+    }
+
+    fn run(&self, config: &Arc<FactoryConfig>, blobs: &[PrefetchJobBlob]) {
+        let mut result = Ok(());
+        for blob in blobs {
+            result = BLOB_FACTORY
+                .new_blob_cache(config, &blob.blob_info, 1)
+                .and_then(|cache| match cache.get_blob_object() {
+                    Some(obj) => self.fetch_ranges(obj, &blob.ranges),
+                    None => Err(enosys!(
+                        "prefetch job: blob cache doesn't support fetching blob ranges"
+                    )),
+                });
+            if result.is_err() {
+                break;
+            }
+        }
+        self.finish(result);
+    }
+
+    fn fetch_ranges(&self, obj: &dyn BlobObject, ranges: &[(u64, u64)]) -> Result<()> {
+        for (offset, size) in ranges {
+            let end = offset + size;
+            let mut pos = *offset;
+            while pos < end {
+                if self.cancelled.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+                let len = std::cmp::min(end - pos, PREFETCH_JOB_BATCH_SIZE);
+                obj.fetch_range_compressed(pos, len)?;
+                self.fetched_bytes.fetch_add(len, Ordering::AcqRel);
+                pos += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&self, result: Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        *state = match result {
+            Err(e) => {
+                warn!("prefetch job {} failed, {}", self.id, e);
+                (PrefetchJobState::Failed, Some(e.to_string()))
+            }
+            Ok(()) if self.cancelled.load(Ordering::Acquire) => (PrefetchJobState::Cancelled, None),
+            Ok(()) => (PrefetchJobState::Completed, None),
+        };
+    }
+}
+
+/// Manager to start, query and cancel prefetch jobs.
+pub struct PrefetchJobMgr {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, Arc<PrefetchJob>>>,
+}
+
+impl Default for PrefetchJobMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrefetchJobMgr {
+    /// Create a new instance of `PrefetchJobMgr`.
+    pub fn new() -> Self {
+        PrefetchJobMgr {
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start a job to pull `blobs` into blob caches created with `config`, and return the job id.
+    pub fn start(&self, config: Arc<FactoryConfig>, blobs: Vec<PrefetchJobBlob>) -> Result<u64> {
+        let total_bytes = blobs.iter().map(|b| b.total_bytes()).sum();
+        let job = self.add_job(total_bytes);
+        let job2 = job.clone();
+        thread::Builder::new()
+            .name("prefetch_job".to_string())
+            .spawn(move || job2.run(&config, &blobs))
+            .map_err(|e| {
+                job.finish(Err(eother!(e)));
+                e
+            })?;
+
+        Ok(job.id())
+    }
+
+    /// Get status information about the job with `id`.
+    pub fn get_job_status(&self, id: u64) -> Option<PrefetchJobStatus> {
+        self.jobs.lock().unwrap().get(&id).map(|job| job.status())
+    }
+
+    /// Get status information about all jobs, sorted by job id.
+    pub fn get_all_job_status(&self) -> Vec<PrefetchJobStatus> {
+        let mut status = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.status())
+            .collect::<Vec<_>>();
+        status.sort_by_key(|s| s.id);
+        status
+    }
+
+    /// Cancel the job with `id`.
+    pub fn cancel(&self, id: u64) -> Result<()> {
+        match self.jobs.lock().unwrap().get(&id) {
+            Some(job) => {
+                job.cancel();
+                Ok(())
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("prefetch job {} not found", id),
+            )),
+        }
+    }
+
+    fn add_job(&self, total_bytes: u64) -> Arc<PrefetchJob> {
+        let id = self.next_id.fetch_add(1, Ordering::AcqRel);
+        let job = Arc::new(PrefetchJob::new(id, total_bytes));
+        let mut jobs = self.jobs.lock().unwrap();
+
+        // Forget the oldest finished jobs.
+        let mut finished = jobs
+            .values()
+            .filter(|job| job.is_finished())
+            .map(|job| job.id())
+            .collect::<Vec<_>>();
+        if finished.len() >= MAX_FINISHED_JOBS {
+            finished.sort_unstable();
+            for id in finished[..finished.len() + 1 - MAX_FINISHED_JOBS].iter() {
+                jobs.remove(id);
+            }
+        }
+        jobs.insert(id, job.clone());
+
+        job
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Default prefetch job manager.
+    pub static ref PREFETCH_JOB_MGR: PrefetchJobMgr = PrefetchJobMgr::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::state::{BlobStateMap, IndexedChunkMap, RangeMap};
+    use crate::device::BlobIoRange;
+    use nydus_api::http::BlobRange;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::sync::atomic::AtomicU32;
+    use vmm_sys_util::tempdir::TempDir;
+
+    const CHUNK_SIZE: u64 = 0x1000;
+    const CHUNK_COUNT: u32 = 1024;
+
+    // A blob object whose chunks are laid out continuously with fixed size, recording the number
+    // of times each chunk gets downloaded.
+    struct MockBlobObject {
+        chunk_map: BlobStateMap<IndexedChunkMap, u32>,
+        downloads: Vec<AtomicU32>,
+    }
+
+    impl MockBlobObject {
+        fn new(dir: &TempDir) -> Self {
+            let path = dir.as_path().join("blob1");
+            let chunk_map =
+                IndexedChunkMap::new(path.to_str().unwrap(), CHUNK_COUNT, false).unwrap();
+            MockBlobObject {
+                chunk_map: BlobStateMap::from(chunk_map),
+                downloads: (0..CHUNK_COUNT).map(|_| AtomicU32::new(0)).collect(),
+            }
+        }
+
+        fn fetch(&self, offset: u64, size: u64) -> Result<()> {
+            let start = (offset / CHUNK_SIZE) as u32;
+            let end = ((offset + size + CHUNK_SIZE - 1) / CHUNK_SIZE) as u32;
+            if let Some(pending) = self
+                .chunk_map
+                .check_range_ready_and_mark_pending(start, end - start)?
+            {
+                for idx in pending {
+                    thread::yield_now();
+                    self.downloads[idx as usize].fetch_add(1, Ordering::AcqRel);
+                    self.chunk_map.set_range_ready_and_clear_pending(idx, 1)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl AsRawFd for MockBlobObject {
+        fn as_raw_fd(&self) -> RawFd {
+            -1
+        }
+    }
+
+    impl BlobObject for MockBlobObject {
+        fn base_offset(&self) -> u64 {
+            0
+        }
+
+        fn is_all_data_ready(&self) -> bool {
+            self.chunk_map.is_range_all_ready()
+        }
+
+        fn fetch_range_compressed(&self, offset: u64, size: u64) -> Result<()> {
+            self.fetch(offset, size)
+        }
+
+        fn fetch_range_uncompressed(&self, offset: u64, size: u64) -> Result<()> {
+            self.fetch(offset, size)
+        }
+
+        fn prefetch_chunks(&self, _range: &BlobIoRange) -> Result<()> {
+            Err(enosys!())
+        }
+    }
+
+    #[test]
+    fn test_prefetch_job_overlapping_access() {
+        let dir = TempDir::new().unwrap();
+        let obj = Arc::new(MockBlobObject::new(&dir));
+        let blob_size = CHUNK_SIZE * CHUNK_COUNT as u64;
+        let mgr = PrefetchJobMgr::new();
+        let job1 = mgr.add_job(blob_size);
+        let job2 = mgr.add_job(blob_size / 2);
+
+        let mut threads = Vec::new();
+        let (o, j) = (obj.clone(), job1.clone());
+        threads.push(thread::spawn(move || {
+            let result = j.fetch_ranges(o.as_ref(), &[(0, blob_size)]);
+            j.finish(result);
+        }));
+        let (o, j) = (obj.clone(), job2.clone());
+        threads.push(thread::spawn(move || {
+            let result = j.fetch_ranges(o.as_ref(), &[(blob_size / 4, blob_size / 2)]);
+            j.finish(result);
+        }));
+        // Simulate read requests from a mounted filesystem.
+        let o = obj.clone();
+        threads.push(thread::spawn(move || {
+            for idx in (0..CHUNK_COUNT as u64).rev().step_by(3) {
+                o.fetch_range_uncompressed(idx * CHUNK_SIZE, CHUNK_SIZE)
+                    .unwrap();
+            }
+        }));
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert!(obj.is_all_data_ready());
+        for count in obj.downloads.iter() {
+            assert_eq!(count.load(Ordering::Acquire), 1);
+        }
+        let status = mgr.get_all_job_status();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[0].id, job1.id());
+        assert_eq!(status[0].state, PrefetchJobState::Completed);
+        assert_eq!(status[0].fetched_bytes, blob_size);
+        assert_eq!(status[1].state, PrefetchJobState::Completed);
+        assert_eq!(status[1].fetched_bytes, blob_size / 2);
+    }
+
+    #[test]
+    fn test_prefetch_job_cancel() {
+        let dir = TempDir::new().unwrap();
+        let obj = MockBlobObject::new(&dir);
+        let mgr = PrefetchJobMgr::new();
+        let job = mgr.add_job(CHUNK_SIZE * CHUNK_COUNT as u64);
+
+        mgr.cancel(job.id()).unwrap();
+        let result = job.fetch_ranges(&obj, &[(0, CHUNK_SIZE * CHUNK_COUNT as u64)]);
+        job.finish(result);
+        let status = mgr.get_job_status(job.id()).unwrap();
+        assert_eq!(status.state, PrefetchJobState::Cancelled);
+        assert_eq!(status.fetched_bytes, 0);
+        assert!(!obj.is_all_data_ready());
+
+        assert!(mgr.cancel(job.id() + 1).is_err());
+        assert!(mgr.get_job_status(job.id() + 1).is_none());
+    }
+
+    #[test]
+    fn test_prefetch_job_blob_config() {
+        let mut config = PrefetchJobBlobConfig {
+            blob_id: "blob1".to_string(),
+            compressed_size: 0x10000,
+            uncompressed_size: 0x20000,
+            chunk_size: 0x1000,
+            chunk_count: 32,
+            compressor: "zstd".to_string(),
+            digester: "blake3".to_string(),
+            meta_flags: 0,
+            meta_ci_offset: 0x10000,
+            meta_ci_compressed_size: 0x100,
+            meta_ci_uncompressed_size: 0x200,
+            meta_ci_compressor: "lz4_block".to_string(),
+            ranges: Vec::new(),
+        };
+        let blob = PrefetchJobBlob::from_config(&config).unwrap();
+        assert_eq!(blob.ranges, vec![(0, 0x10000)]);
+        assert_eq!(blob.total_bytes(), 0x10000);
+        assert_eq!(blob.blob_info.compressor(), compress::Algorithm::Zstd);
+
+        config.ranges = vec![
+            BlobRange {
+                offset: 0x1000,
+                size: 0x2000,
+            },
+            BlobRange {
+                offset: 0x8000,
+                size: 0x8000,
+            },
+        ];
+        let blob = PrefetchJobBlob::from_config(&config).unwrap();
+        assert_eq!(blob.total_bytes(), 0xa000);
+
+        config.ranges[1].size = 0x8001;
+        assert!(PrefetchJobBlob::from_config(&config).is_err());
+        config.ranges.clear();
+        config.compressor = "lz5".to_string();
+        assert!(PrefetchJobBlob::from_config(&config).is_err());
+    }
+}
@@ -86,6 +86,9 @@ impl BlobChunkInfo for MockChunkInfo {
     fn is_compressed(&self) -> bool {
         self.flags.contains(BlobChunkFlags::COMPRESSED)
     }
+    fn is_zeroed(&self) -> bool {
+        self.flags.contains(BlobChunkFlags::ZEROED)
+    }
 
     fn as_any(&self) -> &dyn Any {
         self
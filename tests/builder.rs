@@ -335,10 +335,32 @@ impl<'a> Builder<'a> {
         ).unwrap();
     }
 
+    pub fn build_estargz(&mut self, toc: &Path, blob_id: &str, blob_size: u64, chunk_size: u32) {
+        self.create_dir(&self.work_dir.join("cache"));
+
+        exec(
+            format!(
+                "{:?} create --source-type estargz --bootstrap {:?} --blob-meta {:?} --blob-id {} --blob-data-size {:x} --chunk-size 0x{:x} --fs-version 6 --log-level info {:?}",
+                self.builder,
+                self.work_dir.join(blob_id),
+                self.work_dir
+                    .join("cache")
+                    .join(format!("{}.blob.meta", blob_id)),
+                blob_id,
+                blob_size,
+                chunk_size,
+                toc,
+            )
+            .as_str(),
+            false,
+            b"",
+        ).unwrap();
+    }
+
     pub fn merge(&mut self, target: &Path, bootstraps: Vec<&str>) {
         exec(
             format!(
-                "{:?} merge --bootstrap {:?} --log-level info {}",
+                "{:?} merge --output {:?} --log-level info --parent {}",
                 self.builder,
                 target,
                 bootstraps.join(" "),
@@ -350,6 +372,96 @@ impl<'a> Builder<'a> {
         .unwrap();
     }
 
+    pub fn merge_layer_bootstraps(&mut self, target: &Path, bootstraps: Vec<&str>) {
+        exec(
+            format!(
+                "{:?} merge --bootstrap {:?} --log-level info --layer-bootstraps {}",
+                self.builder,
+                target,
+                bootstraps.join(","),
+            )
+            .as_str(),
+            false,
+            b"",
+        )
+        .unwrap();
+    }
+
+    /// Create three layers to be merged, and the expected content of the merged filesystem.
+    pub fn make_merge_layers(&mut self) {
+        let lower = self.work_dir.join("merge-lower");
+        let middle = self.work_dir.join("merge-middle");
+        let upper = self.work_dir.join("merge-upper");
+        let expected = self.work_dir.join("merge-expected");
+        for dir in [&lower, &middle, &upper, &expected] {
+            self.create_dir(&dir.join("sub"));
+        }
+
+        // Whiteouts in the lowest layer have nothing to hide.
+        self.create_file(&lower.join(".wh.ghost"), b"");
+        self.create_large_file(&lower.join("dup-lower"), 2);
+        self.create_file(&lower.join("lower-only"), b"lower:lower-only");
+        self.create_file(&lower.join("sub/removed"), b"lower:removed");
+        self.create_file(&lower.join("sub/kept"), b"lower:kept");
+
+        self.create_file(&middle.join("sub/.wh.removed"), b"");
+        self.create_file(&middle.join("middle-only"), b"middle:middle-only");
+
+        // Same data as `dup-lower`, so chunks are deduplicated when merging.
+        self.create_large_file(&upper.join("dup-upper"), 2);
+        self.create_file(&upper.join("upper-only"), b"upper:upper-only");
+        self.create_file(&upper.join("lower-only"), b"upper:lower-only");
+
+        self.create_large_file(&expected.join("dup-lower"), 2);
+        self.create_large_file(&expected.join("dup-upper"), 2);
+        self.create_file(&expected.join("lower-only"), b"upper:lower-only");
+        self.create_file(&expected.join("sub/kept"), b"lower:kept");
+        self.create_file(&expected.join("middle-only"), b"middle:middle-only");
+        self.create_file(&expected.join("upper-only"), b"upper:upper-only");
+    }
+
+    /// Build per layer bootstraps for layers created by `make_merge_layers()`.
+    ///
+    /// Bootstraps are named by blob ids of the layers as required by merge, and blob ids are
+    /// returned in order of lower to upper layers.
+    pub fn build_merge_layers(&mut self, rafs_version: &str) -> Vec<String> {
+        self.create_dir(&self.work_dir.join("blobs"));
+
+        let mut blob_ids = Vec::new();
+        for (layer, prefetch) in [
+            ("lower", &b"/dup-lower\n"[..]),
+            ("middle", b"/middle-only\n"),
+            ("upper", b"/dup-upper\n/upper-only\n"),
+        ] {
+            let bootstrap = self.work_dir.join(format!("bootstrap-merge-{}", layer));
+            let output_json = self.work_dir.join(format!("output-merge-{}.json", layer));
+            // Whiteout files are kept in per layer bootstraps with the "none" spec, and applied
+            // when merging.
+            exec(
+                format!(
+                    "{:?} create --bootstrap {:?} --blob-dir {:?} --prefetch-policy fs --output-json {:?} --log-level info --compressor lz4_block --whiteout-spec none --fs-version {} {:?}",
+                    self.builder,
+                    bootstrap,
+                    self.work_dir.join("blobs"),
+                    output_json,
+                    rafs_version,
+                    self.work_dir.join(format!("merge-{}", layer)),
+                )
+                .as_str(),
+                false,
+                prefetch,
+            ).unwrap();
+
+            let output: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(output_json).unwrap()).unwrap();
+            let blob_id = output["blobs"][0].as_str().unwrap().to_string();
+            self.copy_file(&bootstrap, &self.work_dir.join(&blob_id));
+            blob_ids.push(blob_id);
+        }
+
+        blob_ids
+    }
+
     pub fn build_special_files(&mut self) {
         let dir = self.work_dir.join("special_files");
         self.create_dir(&dir);
@@ -483,6 +595,231 @@ impl<'a> Builder<'a> {
         ).unwrap();
     }
 
+    pub fn pack_reproducible(&mut self, rafs_version: &str, name: &str) {
+        let blob_dir = self.work_dir.join(format!("blobs-{}", name));
+        self.create_dir(&blob_dir);
+
+        exec(
+            format!(
+                "SOURCE_DATE_EPOCH=1 {:?} create --bootstrap {:?} --blob-dir {:?} --log-level info --whiteout-spec none --fs-version {} --reproducible {:?}",
+                self.builder,
+                self.work_dir.join(format!("bootstrap-{}", name)),
+                blob_dir,
+                rafs_version,
+                self.work_dir.join("compress"),
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+    }
+
+    pub fn pack_threads(&mut self, rafs_version: &str, threads: usize, name: &str) {
+        let blob_dir = self.work_dir.join(format!("blobs-{}", name));
+        self.create_dir(&blob_dir);
+
+        exec(
+            format!(
+                "SOURCE_DATE_EPOCH=1 {:?} create --bootstrap {:?} --blob-dir {:?} --log-level info --whiteout-spec none --fs-version {} --reproducible --threads {} {:?}",
+                self.builder,
+                self.work_dir.join(format!("bootstrap-{}", name)),
+                blob_dir,
+                rafs_version,
+                threads,
+                self.work_dir.join("compress"),
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+    }
+
+    /// Build RAFS filesystem from a tar stream of `source`, piped through `filter` into stdin
+    /// of the builder, like a layer exported by `docker save`.
+    pub fn pack_tar_stream(
+        &mut self,
+        rafs_version: &str,
+        source: &str,
+        filter: &str,
+        bootstrap: &str,
+        parent_bootstrap: Option<&str>,
+    ) {
+        let blob_dir = self.work_dir.join("blobs");
+        self.create_dir(&blob_dir);
+        let parent = parent_bootstrap
+            .map(|v| format!("--parent-bootstrap {:?}", self.work_dir.join(v)))
+            .unwrap_or_default();
+
+        exec(
+            format!(
+                "tar --xattrs --format=pax -C {:?} -cf - . | {} | {:?} create --type targz-stream --bootstrap {:?} {} --blob-dir {:?} --log-level info --whiteout-spec {} --fs-version {} -",
+                self.work_dir.join(source),
+                filter,
+                self.builder,
+                self.work_dir.join(bootstrap),
+                parent,
+                blob_dir,
+                self.whiteout_spec,
+                rafs_version,
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+    }
+
+    pub fn pack_incremental(&mut self, rafs_version: &str) {
+        exec(
+            format!(
+                "{:?} create --bootstrap {:?} --base-bootstrap {:?} --verify-reused --blob-dir {:?} --log-level info --compressor lz4_block --whiteout-spec none --fs-version {} {:?}",
+                self.builder,
+                self.work_dir.join("bootstrap-incremental"),
+                self.work_dir.join("bootstrap"),
+                self.work_dir.join("blobs"),
+                rafs_version,
+                self.work_dir.join("compress"),
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+    }
+
+    pub fn compact_blobs(&mut self, bootstrap: &str, compressor: &str) -> String {
+        let output_json = self.work_dir.join("output-compact.json");
+        exec(
+            format!(
+                "{:?} compact --bootstrap {:?} --blob-dir {:?} --output-bootstrap {:?} --output-blob {:?} --compressor {} --output-json {:?} --log-level info",
+                self.builder,
+                self.work_dir.join(bootstrap),
+                self.work_dir.join("blobs"),
+                self.work_dir.join("bootstrap-compact"),
+                self.work_dir.join("blob-compact"),
+                compressor,
+                output_json,
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+
+        fs::read_to_string(output_json).unwrap()
+    }
+
+    pub fn pack_chunk_dict(&mut self, rafs_version: &str, dict: &str) -> String {
+        let output_json = self.work_dir.join("output-chunk-dict.json");
+        exec(
+            format!(
+                "{:?} create --bootstrap {:?} --blob-dir {:?} --chunk-dict bootstrap={:?} --output-json {:?} --log-level info --compressor lz4_block --whiteout-spec none --fs-version {} {:?}",
+                self.builder,
+                self.work_dir.join("bootstrap-chunk-dict"),
+                self.work_dir.join("blobs"),
+                self.work_dir.join(dict),
+                output_json,
+                rafs_version,
+                self.work_dir.join("compress"),
+            )
+            .as_str(),
+            false,
+            b""
+        ).unwrap();
+
+        fs::read_to_string(output_json).unwrap()
+    }
+
+    pub fn pack_chunking(&mut self, rafs_version: &str, chunking: &str) -> bool {
+        exec(
+            format!(
+                "{:?} create --bootstrap {:?} --blob-dir {:?} --log-level info --compressor lz4_block --whiteout-spec none --fs-version {} --chunking {} {:?}",
+                self.builder,
+                self.work_dir.join("bootstrap-chunking"),
+                self.work_dir.join("blobs"),
+                rafs_version,
+                chunking,
+                self.work_dir.join("compress"),
+            )
+            .as_str(),
+            false,
+            b""
+        ).is_ok()
+    }
+
+    pub fn check_compare(&mut self, bootstrap: &str) -> bool {
+        exec(
+            format!(
+                "{:?} check --compare {:?} --deep --blob-dir {:?} --log-level info {:?}",
+                self.builder,
+                self.work_dir.join("compress"),
+                self.work_dir.join("blobs"),
+                self.work_dir.join(bootstrap),
+            )
+            .as_str(),
+            true,
+            b"",
+        )
+        .is_ok()
+    }
+
+    pub fn pack_oci_layout(&self, bootstrap: &str) {
+        exec(
+            format!(
+                "{:?} pack --bootstrap {:?} --blob-dir {:?} --oci-layout {:?}",
+                self.builder,
+                self.work_dir.join(bootstrap),
+                self.work_dir.join("blobs"),
+                self.work_dir.join("oci-layout"),
+            )
+            .as_str(),
+            false,
+            b"",
+        )
+        .unwrap();
+    }
+
+    pub fn unpack_oci_layout(&self) {
+        exec(
+            format!(
+                "{:?} unpack --oci-layout {:?} --output {:?}",
+                self.builder,
+                self.work_dir.join("oci-layout"),
+                self.work_dir.join("oci-unpacked"),
+            )
+            .as_str(),
+            false,
+            b"",
+        )
+        .unwrap();
+    }
+
+    pub fn inspect_symlinks(&self, bootstrap: &str) -> String {
+        exec(
+            format!(
+                "{:?} inspect --symlinks {:?}",
+                self.builder,
+                self.work_dir.join(bootstrap),
+            )
+            .as_str(),
+            true,
+            b"",
+        )
+        .unwrap()
+    }
+
+    pub fn inspect_request(&self, bootstrap: &str, request: &str) -> String {
+        exec(
+            format!(
+                "{:?} inspect --bootstrap {:?} --request {:?}",
+                self.builder,
+                self.work_dir.join(bootstrap),
+                request,
+            )
+            .as_str(),
+            true,
+            b"",
+        )
+        .unwrap()
+    }
+
     pub fn make_pack(&mut self) {
         let dir = self.work_dir.join("compress");
         self.create_dir(&dir);
@@ -4,10 +4,13 @@
 
 use std::fs::{self, File};
 use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
 use std::thread::*;
 use std::time;
 
+use nydus::upgrade::{UpgradeChannel, UpgradeMessage};
 use nydus_rafs::metadata::RafsMode;
 use nydus_utils::exec;
 
@@ -128,6 +131,26 @@ impl Nydusd {
         self._start(false, bootstrap_name, mount_path)
     }
 
+    /// Start nydusd in upgrade mode, waiting for the supervisor to hand over the fuse session.
+    pub fn start_upgrade(&self, mount_path: &str) {
+        self._start(true, None, mount_path)
+    }
+
+    /// Send a request to the nydusd HTTP API server.
+    pub fn api(&self, method: &str, path: &str) -> std::io::Result<String> {
+        exec(
+            format!(
+                "curl -sf -X {} --unix-socket {:?} http://localhost/api/v1{}",
+                method,
+                self.work_dir.join(&self.api_sock),
+                path
+            )
+            .as_str(),
+            true,
+            b"",
+        )
+    }
+
     pub fn check(&self, expect_texture: &str, mount_path: &str) {
         let mount_path = self.work_dir.join(mount_path);
 
@@ -159,6 +182,20 @@ impl Nydusd {
         assert_eq!(ret.trim(), expected.trim());
     }
 
+    pub fn check_dir(&self, expect_dir: &str, mount_path: &str) {
+        exec(
+            format!(
+                "diff -r --no-dereference {:?} {:?}",
+                self.work_dir.join(expect_dir),
+                self.work_dir.join(mount_path)
+            )
+            .as_str(),
+            false,
+            b"",
+        )
+        .expect("content of mounted filesystem doesn't match");
+    }
+
     pub fn is_mounted(&self, mount_path: &str) -> bool {
         let ret = exec("cat /proc/mounts", true, b"").unwrap();
         for line in ret.split('\n') {
@@ -178,3 +215,54 @@ impl Nydusd {
         .unwrap();
     }
 }
+
+/// A minimal supervisor keeping daemon state and file descriptors across live upgrade.
+pub struct Supervisor {
+    listener: UnixListener,
+    state: Vec<u8>,
+    files: Vec<File>,
+}
+
+impl Supervisor {
+    pub fn new(work_dir: &Path) -> Self {
+        let path = work_dir.join("supervisor.sock");
+        let _ = fs::remove_file(&path);
+
+        Supervisor {
+            listener: UnixListener::bind(path).unwrap(),
+            state: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Accept a connection from the old daemon and receive its state.
+    pub fn save(&mut self) {
+        let (stream, _) = self.listener.accept().unwrap();
+        let mut channel = UpgradeChannel::new(stream);
+        match channel.recv().unwrap() {
+            UpgradeMessage::SaveState(state) => self.state = state,
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        if let UpgradeMessage::Fds(fds) = channel.recv().unwrap() {
+            self.files = fds
+                .into_iter()
+                .map(|fd| unsafe { File::from_raw_fd(fd) })
+                .collect();
+        }
+        channel.confirm().unwrap();
+    }
+
+    /// Accept a connection from the new daemon and hand over the saved state.
+    pub fn restore(&mut self) {
+        let (stream, _) = self.listener.accept().unwrap();
+        let mut channel = UpgradeChannel::new(stream);
+        channel
+            .send(&UpgradeMessage::SaveState(self.state.clone()))
+            .unwrap();
+        if !self.files.is_empty() {
+            let fds = self.files.iter().map(|f| f.as_raw_fd()).collect();
+            channel.send(&UpgradeMessage::Fds(fds)).unwrap();
+        }
+        assert_eq!(channel.recv().unwrap(), UpgradeMessage::Confirm);
+    }
+}
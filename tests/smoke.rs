@@ -6,8 +6,11 @@ extern crate log;
 
 use std::env::var;
 use std::fs::{self, File};
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use nydus_app::setup_logging;
 use nydus_utils::exec;
@@ -326,6 +329,241 @@ fn integration_test_stargz() {
     test_stargz("6");
 }
 
+#[test]
+fn integration_test_merge_layers() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_merge_layers();
+    let blob_ids = builder.build_merge_layers("6");
+    let layers = blob_ids
+        .iter()
+        .map(|id| work_dir.join(id).to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    builder.merge(
+        &work_dir.join("bootstrap-merged"),
+        layers.iter().map(|v| v.as_str()).collect(),
+    );
+
+    let output = builder.inspect_request("bootstrap-merged", "blobs");
+    let blobs: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        blobs
+            .iter()
+            .map(|b| b["blob_id"].clone())
+            .collect::<Vec<_>>(),
+        blob_ids
+    );
+
+    // Chunks of the upper layer refer to identical chunks of the lowest layer.
+    let output = builder.inspect_request("bootstrap-merged", "chunks /dup-lower");
+    let lower_chunks: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    let output = builder.inspect_request("bootstrap-merged", "chunks /dup-upper");
+    let upper_chunks: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(lower_chunks.len(), 2);
+    assert_eq!(upper_chunks.len(), 2);
+    for (lower, upper) in lower_chunks.iter().zip(upper_chunks.iter()) {
+        assert_eq!(upper["blob_id"], blob_ids[0]);
+        assert_eq!(upper["chunk_id"], lower["chunk_id"]);
+        assert_eq!(upper["file_offset"], lower["file_offset"]);
+        assert_eq!(upper["compressed_offset"], lower["compressed_offset"]);
+        assert_eq!(upper["decompressed_offset"], lower["decompressed_offset"]);
+    }
+
+    // Only prefetch entries of the uppermost layer are kept.
+    let output = builder.inspect_request("bootstrap-merged", "prefetch");
+    let prefetch: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    let mut paths = prefetch
+        .iter()
+        .map(|v| v["path"][0].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, vec!["/dup-upper", "/upper-only"]);
+
+    let nydusd = nydusd::new(
+        &work_dir,
+        false,
+        false,
+        "direct".parse().unwrap(),
+        "api.sock".into(),
+        true,
+    );
+    nydusd.start(Some("bootstrap-merged"), "mnt");
+    nydusd.check_dir("merge-expected", "mnt");
+    nydusd.umount("mnt");
+}
+
+#[test]
+fn integration_test_merge_layer_bootstraps() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_merge_layers();
+    let layers = builder
+        .build_merge_layers("6")
+        .iter()
+        .map(|id| work_dir.join(id).to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    builder.merge_layer_bootstraps(
+        &work_dir.join("bootstrap-merged"),
+        layers.iter().map(|v| v.as_str()).collect(),
+    );
+
+    let list_dir = |dir: &str| {
+        let output = builder.inspect_request("bootstrap-merged", &format!("ls {}", dir));
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+        let mut names = entries
+            .iter()
+            .map(|e| e["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    // Whiteouts of the lowest layer are dropped, `.wh.removed` of the middle layer removes
+    // `removed` of the lowest layer.
+    assert_eq!(
+        list_dir("/"),
+        vec![
+            "dup-lower",
+            "dup-upper",
+            "lower-only",
+            "middle-only",
+            "sub",
+            "upper-only"
+        ]
+    );
+    assert_eq!(list_dir("/sub"), vec!["kept"]);
+
+    let output = builder.inspect_request("bootstrap-merged", "stat /lower-only");
+    let stat: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(stat["size"], b"upper:lower-only".len());
+}
+
+fn test_estargz_foreign_layer() {
+    info!("\n\n==================== testing run: estargz foreign layer test");
+
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    // Blob id of the eStargz layer is the sha256 digest of the layer blob.
+    let blob_id = "5c9e1fd919ea442e732fda5fd49d3f09b87d73e80297b9b2c94d75ebd5ab9400";
+    let layer = Path::new("tests/texture/stargz/alpine-stargz.tar.gz");
+    let blob_size = fs::metadata(layer).unwrap().len();
+    fs::create_dir_all(work_dir.join("blobs")).unwrap();
+    fs::copy(layer, work_dir.join("blobs").join(blob_id)).unwrap();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.build_estargz(
+        Path::new("tests/texture/stargz/alpine-stargz.index.json"),
+        blob_id,
+        blob_size,
+        0x400000,
+    );
+
+    // Unpack the original layer, skipping eStargz specific entries, to verify file contents
+    // served from the foreign layer blob.
+    fs::create_dir_all(work_dir.join("expected")).unwrap();
+    exec(
+        format!(
+            "tar -xzf {:?} -C {:?} --exclude stargz.index.json --exclude .prefetch.landmark --exclude .no.prefetch.landmark",
+            layer,
+            work_dir.join("expected"),
+        )
+        .as_str(),
+        false,
+        b"",
+    )
+    .unwrap();
+
+    for cache in [false, true] {
+        let nydusd = nydusd::new(
+            &work_dir,
+            cache,
+            false,
+            "direct".parse().unwrap(),
+            "api.sock".into(),
+            false,
+        );
+        nydusd.start(Some(blob_id), "mnt");
+        nydusd.check_dir("expected", "mnt");
+        nydusd.umount("mnt");
+    }
+}
+
+#[test]
+fn integration_test_estargz_foreign_layer() {
+    test_estargz_foreign_layer();
+}
+
+fn test_zstd_chunked_foreign_layer() {
+    info!("\n\n==================== testing run: zstd:chunked foreign layer test");
+
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    // File chunks of the zstd:chunked layer are independent zstd frames of 4KiB data.
+    let blob_id = "d0f6cd940a5365e6dd494efd074c0815ae784c04d10fc573185a32cb2a250fc9";
+    let layer = Path::new("tests/texture/stargz/zstd-chunked.tar.zst");
+    let blob_size = fs::metadata(layer).unwrap().len();
+    fs::create_dir_all(work_dir.join("blobs")).unwrap();
+    fs::copy(layer, work_dir.join("blobs").join(blob_id)).unwrap();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.build_estargz(
+        Path::new("tests/texture/stargz/zstd-chunked.index.json"),
+        blob_id,
+        blob_size,
+        0x1000,
+    );
+    fs::copy(work_dir.join(blob_id), work_dir.join("bootstrap")).unwrap();
+    builder.unpack(&format!("blobs/{}", blob_id), "unpacked.tar");
+
+    // Files unpacked from the foreign layer blob should match the original layer.
+    for dir in ["expected", "actual"] {
+        fs::create_dir_all(work_dir.join(dir)).unwrap();
+    }
+    exec(
+        format!(
+            "zstd -d -c {:?} | tar -xf - -C {:?}",
+            layer,
+            work_dir.join("expected")
+        )
+        .as_str(),
+        false,
+        b"",
+    )
+    .unwrap();
+    exec(
+        format!(
+            "tar -xf {:?} -C {:?}",
+            work_dir.join("unpacked.tar"),
+            work_dir.join("actual")
+        )
+        .as_str(),
+        false,
+        b"",
+    )
+    .unwrap();
+    exec(
+        format!(
+            "diff -r --no-dereference {:?} {:?}",
+            work_dir.join("expected"),
+            work_dir.join("actual")
+        )
+        .as_str(),
+        false,
+        b"",
+    )
+    .unwrap();
+}
+
+#[test]
+fn integration_test_zstd_chunked_foreign_layer() {
+    test_zstd_chunked_foreign_layer();
+}
+
 #[test]
 fn integration_test_inline_directory() {
     test_inline("5");
@@ -400,6 +638,439 @@ fn test_unpack(work_dir: &Path, version: &str) {
     assert_eq!(ret.trim(), expected.trim());
 }
 
+#[test]
+fn integration_test_reproducible() {
+    test_reproducible("5");
+    test_reproducible("6");
+}
+
+fn test_reproducible(rafs_version: &str) {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack_reproducible(rafs_version, "1");
+    // Modification time should be clamped to SOURCE_DATE_EPOCH.
+    exec(
+        &format!("find {:?} -exec touch -h {{}} +", work_dir.join("compress")),
+        false,
+        b"",
+    )
+    .unwrap();
+    builder.pack_reproducible(rafs_version, "2");
+
+    let bootstrap1 = fs::read(work_dir.join("bootstrap-1")).unwrap();
+    let bootstrap2 = fs::read(work_dir.join("bootstrap-2")).unwrap();
+    assert_eq!(bootstrap1, bootstrap2);
+
+    let manifest1 = fs::read(work_dir.join("bootstrap-1.manifest.json")).unwrap();
+    let manifest2 = fs::read(work_dir.join("bootstrap-2.manifest.json")).unwrap();
+    assert_eq!(manifest1, manifest2);
+    let manifest: serde_json::Value = serde_json::from_slice(&manifest1).unwrap();
+    assert_eq!(manifest["config"]["source_date_epoch"], 1);
+    assert!(!manifest["files"].as_array().unwrap().is_empty());
+
+    let blobs1 = fs::read_dir(work_dir.join("blobs-1"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(blobs1.len(), 1);
+    let blob1 = fs::read(work_dir.join("blobs-1").join(&blobs1[0])).unwrap();
+    let blob2 = fs::read(work_dir.join("blobs-2").join(&blobs1[0])).unwrap();
+    assert_eq!(blob1, blob2);
+}
+
+#[test]
+fn integration_test_multithreaded_build() {
+    test_multithreaded_build("5");
+    test_multithreaded_build("6");
+}
+
+fn test_multithreaded_build(rafs_version: &str) {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack_threads(rafs_version, 1, "single");
+    builder.pack_threads(rafs_version, 4, "multi");
+
+    // Output of the multi-threaded pipeline should be identical to the single-threaded path.
+    let bootstrap1 = fs::read(work_dir.join("bootstrap-single")).unwrap();
+    let bootstrap2 = fs::read(work_dir.join("bootstrap-multi")).unwrap();
+    assert_eq!(bootstrap1, bootstrap2);
+
+    let blobs1 = fs::read_dir(work_dir.join("blobs-single"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(blobs1.len(), 1);
+    let blob1 = fs::read(work_dir.join("blobs-single").join(&blobs1[0])).unwrap();
+    let blob2 = fs::read(work_dir.join("blobs-multi").join(&blobs1[0])).unwrap();
+    assert_eq!(blob1, blob2);
+}
+
+/// Benchmark scaling of the multi-threaded build pipeline on a large fixture.
+///
+/// Run with `cargo test --test smoke -- --ignored bench_multithreaded_build --nocapture`, the
+/// fixture size in GiB may be set by `NYDUS_BENCH_FIXTURE_GB`.
+#[test]
+#[ignore]
+fn bench_multithreaded_build() {
+    let size_gb = var("NYDUS_BENCH_FIXTURE_GB")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(4);
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    // Generate semi-compressible data, so compression dominates the build time.
+    let mut data = vec![0u8; 0x100000];
+    for (idx, v) in data.iter_mut().enumerate() {
+        *v = (idx as u32).wrapping_mul(2654435761).rotate_right(13) as u8 & 0x3f;
+    }
+    for file in 0..size_gb {
+        let mut f = File::create(work_dir.join(format!("compress/large-{}", file))).unwrap();
+        for idx in 0..1024u32 {
+            data[..4].copy_from_slice(&(file as u32 * 1024 + idx).to_le_bytes());
+            f.write_all(&data).unwrap();
+        }
+    }
+
+    let threads = std::thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1);
+    let mut counts = vec![1, 2, 4, threads];
+    counts.retain(|v| *v <= threads);
+    counts.dedup();
+    let mut baseline = None;
+    for count in counts {
+        let start = std::time::Instant::now();
+        builder.pack_threads("6", count, &format!("bench-{}", count));
+        let elapsed = start.elapsed().as_secs_f64();
+        let baseline = *baseline.get_or_insert(elapsed);
+        println!(
+            "{} GiB with {} threads: {:.2}s, speedup {:.2}x",
+            size_gb,
+            count,
+            elapsed,
+            baseline / elapsed
+        );
+        fs::remove_dir_all(work_dir.join(format!("blobs-bench-{}", count))).unwrap();
+    }
+}
+
+#[test]
+fn integration_test_tar_stream() {
+    test_tar_stream("5", "gzip");
+    test_tar_stream("6", "gzip");
+    test_tar_stream("6", "zstd");
+    test_tar_stream("6", "cat");
+}
+
+fn test_tar_stream(rafs_version: &str, filter: &str) {
+    info!(
+        "\n\n==================== testing run: tar stream test, rafs_version={} filter={}",
+        rafs_version, filter
+    );
+
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack_tar_stream(rafs_version, "compress", filter, "bootstrap-lower", None);
+
+    let nydusd = nydusd::new(
+        &work_dir,
+        false,
+        false,
+        "direct".parse().unwrap(),
+        "api.sock".into(),
+        false,
+    );
+    nydusd.start(Some("bootstrap-lower"), "mnt");
+    nydusd.check_dir("compress", "mnt");
+    nydusd.umount("mnt");
+
+    // Build an upper layer removing `root-1` and adding `root-3`, whiteouts should be applied to
+    // the lower layer.
+    let upper = work_dir.join("upper");
+    fs::create_dir_all(&upper).unwrap();
+    fs::write(upper.join(".wh.root-1"), b"").unwrap();
+    fs::write(upper.join("root-3"), b"upper:root-3").unwrap();
+    builder.pack_tar_stream(
+        rafs_version,
+        "upper",
+        filter,
+        "bootstrap-upper",
+        Some("bootstrap-lower"),
+    );
+
+    exec(
+        &format!(
+            "cp -a {:?} {:?}",
+            work_dir.join("compress"),
+            work_dir.join("expected")
+        ),
+        false,
+        b"",
+    )
+    .unwrap();
+    fs::remove_file(work_dir.join("expected/root-1")).unwrap();
+    fs::write(work_dir.join("expected/root-3"), b"upper:root-3").unwrap();
+
+    nydusd.start(Some("bootstrap-upper"), "mnt");
+    nydusd.check_dir("expected", "mnt");
+    nydusd.umount("mnt");
+}
+
+#[test]
+fn integration_test_oci_layout() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", "6");
+    builder.pack_oci_layout("bootstrap");
+
+    let index: serde_json::Value =
+        serde_json::from_slice(&fs::read(work_dir.join("oci-layout/index.json")).unwrap()).unwrap();
+    let digest = index["manifests"][0]["digest"].as_str().unwrap();
+    let manifest_path = work_dir
+        .join("oci-layout/blobs/sha256")
+        .join(digest.strip_prefix("sha256:").unwrap());
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(manifest_path).unwrap()).unwrap();
+    let layers = manifest["layers"].as_array().unwrap();
+    let blobs = fs::read_dir(work_dir.join("blobs"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(blobs.len(), 1);
+    assert_eq!(layers.len(), 2);
+    assert_eq!(
+        layers[0]["mediaType"],
+        "application/vnd.oci.image.layer.nydus.blob.v1"
+    );
+    assert_eq!(layers[0]["digest"], format!("sha256:{}", blobs[0]));
+    assert_eq!(
+        layers[1]["annotations"]["containerd.io/snapshot/nydus-bootstrap"],
+        "true"
+    );
+    assert_eq!(
+        layers[1]["annotations"]["containerd.io/snapshot/nydus-fs-version"],
+        "6"
+    );
+
+    builder.unpack_oci_layout();
+    let unpacked = work_dir.join("oci-unpacked");
+    assert_eq!(
+        fs::read(unpacked.join("image.boot")).unwrap(),
+        fs::read(work_dir.join("bootstrap")).unwrap()
+    );
+    assert_eq!(
+        fs::read(unpacked.join(&blobs[0])).unwrap(),
+        fs::read(work_dir.join("blobs").join(&blobs[0])).unwrap()
+    );
+}
+
+#[test]
+fn integration_test_check_compare() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", "6");
+    assert!(builder.check_compare("bootstrap"));
+
+    // Modify file content without changing file size.
+    fs::write(work_dir.join("compress/root-1"), b"upper:root-1").unwrap();
+    assert!(!builder.check_compare("bootstrap"));
+}
+
+#[test]
+fn integration_test_inspect_symlinks() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    std::os::unix::fs::symlink("../non-existent", work_dir.join("compress/sub/dangling")).unwrap();
+    std::os::unix::fs::symlink("sub/more", work_dir.join("compress/more-symlink")).unwrap();
+    std::os::unix::fs::symlink(
+        "/more-symlink/more-sub/more-sub-1",
+        work_dir.join("compress/nested-symlink"),
+    )
+    .unwrap();
+    builder.pack("lz4_block", "6");
+
+    let output = builder.inspect_symlinks("bootstrap");
+    let report: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        report["symlinks"]["/sub/sub-root-large-symlink"],
+        "../root-large"
+    );
+    assert_eq!(report["symlinks"]["/sub/dangling"], "../non-existent");
+    assert_eq!(report["dangling"], serde_json::json!(["/sub/dangling"]));
+}
+
+#[test]
+fn integration_test_inspect_requests() {
+    test_inspect_requests("5");
+    test_inspect_requests("6");
+}
+
+fn test_inspect_requests(rafs_version: &str) {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", rafs_version);
+
+    let output = builder.inspect_request("bootstrap", "stat /sub/sub-1");
+    let stat: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(stat["name"], "sub-1");
+    assert_eq!(stat["size"], 11);
+
+    // Directory nlink is 2 plus the number of subdirectories.
+    for (dir, nlink) in [("/sub/more", 3), ("/sub/more/more-sub", 2)] {
+        let output = builder.inspect_request("bootstrap", &format!("stat {}", dir));
+        let stat: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(stat["nlink"], nlink);
+    }
+
+    let output = builder.inspect_request("bootstrap", "ls /sub");
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert!(entries
+        .iter()
+        .any(|e| e["name"] == "sub-1" && e["type"] == "-"));
+    assert!(entries
+        .iter()
+        .any(|e| e["name"] == "more" && e["type"] == "d"));
+
+    // The 13MB file is split into 1MB chunks.
+    let output = builder.inspect_request("bootstrap", "chunks /root-large");
+    let chunks: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(chunks.len(), 13);
+    let blobs = fs::read_dir(work_dir.join("blobs"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    let mut file_offset = 0;
+    for chunk in chunks.iter() {
+        assert_eq!(chunk["file_offset"], file_offset);
+        assert_eq!(chunk["blob_id"], blobs[0]);
+        file_offset += chunk["decompressed_size"].as_u64().unwrap();
+    }
+    assert_eq!(file_offset, 13 << 20);
+
+    let output = builder.inspect_request("bootstrap", "blobs");
+    let blob_infos: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(blob_infos.len(), 1);
+}
+
+#[test]
+fn integration_test_chunking() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    fs::create_dir_all(work_dir.join("blobs")).unwrap();
+    assert!(builder.pack_chunking("5", "cdc:4K-16K-64K"));
+    assert!(builder.check_compare("bootstrap-chunking"));
+
+    // RAFS v6 requires chunks of uniform size.
+    assert!(!builder.pack_chunking("6", "cdc:4K-16K-64K"));
+    assert!(builder.pack_chunking("6", "fixed:64K"));
+    assert!(builder.check_compare("bootstrap-chunking"));
+}
+
+#[test]
+fn integration_test_chunk_dict_dedup() {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", "5");
+
+    // All chunks are found in the chunk dict generated from the same source.
+    let output = builder.pack_chunk_dict("5", "bootstrap");
+    let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let stats = &output["chunk_dict"];
+    assert!(stats["deduplicated_chunks"].as_u64().unwrap() > 0);
+    assert!(stats["deduplicated_size"].as_u64().unwrap() > 0);
+    assert_eq!(stats["saved_percent"].as_f64().unwrap(), 100.0);
+}
+
+#[test]
+fn integration_test_incremental_build() {
+    test_incremental_build("5");
+    test_incremental_build("6");
+}
+
+fn test_incremental_build(rafs_version: &str) {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", rafs_version);
+    let blobs = fs::read_dir(work_dir.join("blobs")).unwrap().count();
+
+    fs::write(work_dir.join("compress/root-1"), b"upper:root-1").unwrap();
+    fs::write(work_dir.join("compress/sub/sub-3"), b"upper:sub-3").unwrap();
+    builder.pack_incremental(rafs_version);
+
+    // Only changed files are written into the new blob.
+    assert_eq!(
+        fs::read_dir(work_dir.join("blobs")).unwrap().count(),
+        blobs + 1
+    );
+    assert!(builder.check_compare("bootstrap-incremental"));
+}
+
+#[test]
+fn integration_test_compact_blobs() {
+    test_compact_blobs("5");
+    test_compact_blobs("6");
+}
+
+fn test_compact_blobs(rafs_version: &str) {
+    let tmp_dir = TempDir::new().unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.make_pack();
+    builder.pack("lz4_block", rafs_version);
+    fs::write(work_dir.join("compress/root-1"), b"upper:root-1").unwrap();
+    builder.pack_incremental(rafs_version);
+
+    let output = builder.compact_blobs("bootstrap-incremental", "zstd");
+    let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let blobs = output["blobs"].as_array().unwrap();
+    assert_eq!(blobs.len(), 1);
+
+    // Data of all files is read from the new blob only.
+    let blob_id = blobs[0].as_str().unwrap();
+    fs::remove_dir_all(work_dir.join("blobs")).unwrap();
+    fs::create_dir(work_dir.join("blobs")).unwrap();
+    fs::rename(
+        work_dir.join("blob-compact"),
+        work_dir.join("blobs").join(blob_id),
+    )
+    .unwrap();
+    assert!(builder.check_compare("bootstrap-compact"));
+}
+
 #[test]
 fn test_image_inspect() {
     let bootstrap_path = "./tests/texture/bootstrap/rafs-v5.boot";
@@ -407,3 +1078,98 @@ fn test_image_inspect() {
     test_image_inspect_cmd("prefetch", bootstrap_path);
     test_image_inspect_cmd("blobs", bootstrap_path);
 }
+
+#[test]
+fn integration_test_takeover() {
+    info!("\n\n==================== testing run: takeover test");
+
+    // Maximum time a request may be blocked when handing over the fuse session.
+    let max_gap = var("NYDUS_TAKEOVER_MAX_GAP_MS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(5000);
+    // Creating char device files with `mknod` needs a non-overlayfs work directory.
+    let tmp_dir_prefix =
+        std::env::var("TEST_WORKDIR_PREFIX").expect("Please specify `TEST_WORKDIR_PREFIX` env");
+    let tmp_dir =
+        TempDir::new_with_prefix(format!("{}/", tmp_dir_prefix.trim_end_matches('/'))).unwrap();
+    let work_dir = tmp_dir.as_path().to_path_buf();
+
+    let mut builder = builder::new(&work_dir, "oci");
+    builder.build_empty_file_with_prefetch("lz4_block", "6");
+    builder.make_lower();
+    builder.build_lower("lz4_block", "6");
+
+    let mut supervisor = nydusd::Supervisor::new(&work_dir);
+    let old_daemon = nydusd::new(
+        &work_dir,
+        false,
+        false,
+        "direct".parse().unwrap(),
+        "api-old.sock".into(),
+        false,
+    );
+    old_daemon.start(Some("bootstrap-lower"), "mnt");
+
+    // Keep sending requests to the filesystem during takeover.
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let stop = stop.clone();
+        let mnt = work_dir.join("mnt");
+        std::thread::spawn(move || -> std::result::Result<Duration, String> {
+            let mut max = Duration::default();
+            let mut idx = 0u64;
+            while !stop.load(Ordering::Acquire) {
+                let start = Instant::now();
+                // Lookup of a new name always reaches the daemon instead of the dentry cache.
+                idx += 1;
+                match fs::metadata(mnt.join(format!("non-existent-{}", idx))) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    r => return Err(format!("unexpected lookup result {:?}", r)),
+                }
+                let data = fs::read(mnt.join("root-1")).map_err(|e| e.to_string())?;
+                if data != b"lower:root-1" {
+                    return Err("file content mismatch".to_string());
+                }
+                max = max.max(start.elapsed());
+            }
+            Ok(max)
+        })
+    };
+
+    let handle = std::thread::spawn(move || {
+        supervisor.save();
+        supervisor
+    });
+    old_daemon.api("PUT", "/daemon/fuse/sendfd").unwrap();
+    let mut supervisor = handle.join().unwrap();
+
+    let new_daemon = nydusd::new(
+        &work_dir,
+        false,
+        false,
+        "direct".parse().unwrap(),
+        "api-new.sock".into(),
+        false,
+    );
+    new_daemon.start_upgrade("mnt");
+
+    // The old daemon may exit before sending back the response.
+    let _ = old_daemon.api("PUT", "/daemon/exit");
+    let handle = std::thread::spawn(move || supervisor.restore());
+    new_daemon.api("PUT", "/daemon/fuse/takeover").unwrap();
+    handle.join().unwrap();
+    new_daemon.api("PUT", "/daemon/start").unwrap();
+
+    std::thread::sleep(Duration::from_secs(1));
+    stop.store(true, Ordering::Release);
+    let max = reader.join().unwrap().unwrap();
+    assert!(
+        max <= Duration::from_millis(max_gap),
+        "requests blocked for {:?} during takeover",
+        max
+    );
+
+    new_daemon.check("directory/lower.result", "mnt");
+    new_daemon.umount("mnt");
+}
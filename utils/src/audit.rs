@@ -0,0 +1,315 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Audit trail of daemon events.
+//!
+//! Events such as mounts, umounts, configuration changes, prefetch jobs, cache garbage collection
+//! and storage backend failovers are recorded into an in-memory ring buffer. Each event gets an
+//! increasing sequence number, so consumers may fetch events incrementally by [query()]. Events
+//! may optionally be mirrored to a file in JSON-lines format, with size based rotation.
+//!
+//! Events are recorded no matter whether the triggering operation succeeds, and the error is
+//! captured if it fails.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// Default number of events kept in the ring buffer.
+pub const DEFAULT_CAPACITY: usize = 1024;
+/// Maximum number of events returned by a single [query()].
+pub const MAX_QUERY_EVENTS: usize = 256;
+
+/// Category of audit events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    /// A filesystem is mounted.
+    Mount,
+    /// A filesystem is umounted.
+    Umount,
+    /// A filesystem is remounted with a new bootstrap or configuration.
+    Remount,
+    /// Metadata of a RAFS filesystem is updated.
+    Update,
+    /// Daemon configuration is changed at runtime.
+    ConfigChange,
+    /// A prefetch job is finished.
+    Prefetch,
+    /// Unused blob caches are garbage collected.
+    CacheGc,
+    /// Requests to a storage backend fail over to mirrors.
+    BackendFailover,
+}
+
+/// An audit event.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEvent {
+    /// Sequence number of the event, starting from 1.
+    pub seq: u64,
+    /// Timestamp in milliseconds since UNIX epoch.
+    pub timestamp: u64,
+    pub category: EventCategory,
+    /// Mountpoint or id of the filesystem related to the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount_id: Option<String>,
+    /// Category specific details.
+    pub detail: Value,
+    /// Error message if the triggering operation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A page of audit events returned by [query()].
+#[derive(Debug, Serialize)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEvent>,
+    /// Sequence number to start the next query from.
+    pub next_seq: u64,
+}
+
+/// Configuration to mirror audit events to a file.
+#[derive(Clone, Debug)]
+pub struct AuditFileConfig {
+    pub path: PathBuf,
+    /// Rotate the file when it grows beyond the size in bytes, `0` to disable rotation.
+    pub rotation_size: u64,
+    /// Number of rotated files to keep, named as `<path>.1` to `<path>.<rotation_count>`.
+    pub rotation_count: usize,
+}
+
+struct AuditFile {
+    config: AuditFileConfig,
+    file: File,
+    size: u64,
+}
+
+impl AuditFile {
+    fn open(config: AuditFileConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditFile { config, file, size })
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        let rotation_size = self.config.rotation_size;
+        if rotation_size > 0 && self.size > 0 && self.size + line.len() as u64 > rotation_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let path = &self.config.path;
+        let count = self.config.rotation_count;
+        if count == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for idx in (1..count).rev() {
+                match fs::rename(rotated_path(path, idx), rotated_path(path, idx + 1)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, idx: usize) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(format!(".{}", idx));
+    PathBuf::from(p)
+}
+
+struct AuditLog {
+    events: VecDeque<AuditEvent>,
+    capacity: usize,
+    next_seq: u64,
+    file: Option<AuditFile>,
+}
+
+impl AuditLog {
+    fn push(&mut self, event: AuditEvent) {
+        if let Some(file) = self.file.as_mut() {
+            let result = serde_json::to_vec(&event)
+                .map_err(|e| eother!(e))
+                .and_then(|mut line| {
+                    line.push(b'\n');
+                    file.write(&line)
+                });
+            if let Err(e) = result {
+                error!(
+                    "failed to write audit event {} to {:?}, {}",
+                    event.seq, file.config.path, e
+                );
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        while self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+lazy_static! {
+    static ref AUDIT_LOG: Mutex<AuditLog> = Mutex::new(AuditLog {
+        events: VecDeque::new(),
+        capacity: DEFAULT_CAPACITY,
+        next_seq: 1,
+        file: None,
+    });
+}
+
+/// Configure size of the ring buffer and the optional file to mirror events to.
+///
+/// Events beyond the new `capacity` are dropped, oldest first.
+pub fn configure(capacity: usize, file: Option<AuditFileConfig>) -> Result<()> {
+    let file = file.map(AuditFile::open).transpose()?;
+    let mut log = AUDIT_LOG.lock().unwrap();
+    log.capacity = capacity;
+    while log.events.len() > capacity {
+        log.events.pop_front();
+    }
+    log.file = file;
+    Ok(())
+}
+
+/// Record an audit event, with error message of `result` captured if it's an error.
+pub fn record<T, E: Debug>(
+    category: EventCategory,
+    mount_id: Option<&str>,
+    detail: Value,
+    result: &std::result::Result<T, E>,
+) {
+    let error = result.as_ref().err().map(|e| format!("{:?}", e));
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let mut log = AUDIT_LOG.lock().unwrap();
+    let seq = log.next_seq;
+    log.next_seq += 1;
+    log.push(AuditEvent {
+        seq,
+        timestamp,
+        category,
+        mount_id: mount_id.map(|s| s.to_string()),
+        detail,
+        error,
+    });
+}
+
+/// Get recorded events with sequence number no less than `since`, at most [MAX_QUERY_EVENTS].
+///
+/// Events already dropped from the ring buffer are skipped silently, so the sequence number of
+/// the first returned event may be greater than `since`.
+pub fn query(since: u64) -> AuditEventPage {
+    let log = AUDIT_LOG.lock().unwrap();
+    let events: Vec<AuditEvent> = log
+        .events
+        .iter()
+        .filter(|e| e.seq >= since)
+        .take(MAX_QUERY_EVENTS)
+        .cloned()
+        .collect();
+    let next_seq = match events.last() {
+        Some(e) => e.seq + 1,
+        None => std::cmp::max(since, log.next_seq),
+    };
+    AuditEventPage { events, next_seq }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    // The audit log is global, so all cases are run by a single test to avoid interference.
+    #[test]
+    fn test_audit_log() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.as_path().join("events.log");
+        configure(
+            4,
+            Some(AuditFileConfig {
+                path: path.clone(),
+                rotation_size: 512,
+                rotation_count: 2,
+            }),
+        )
+        .unwrap();
+
+        let start = query(0).next_seq;
+        record::<(), String>(
+            EventCategory::Mount,
+            Some("/mnt"),
+            serde_json::json!({"source": "bootstrap"}),
+            &Ok(()),
+        );
+        record::<(), _>(
+            EventCategory::Umount,
+            Some("/mnt"),
+            Value::Null,
+            &Err(einval!("not mounted")),
+        );
+        let page = query(start);
+        assert_eq!(page.events.len(), 2);
+        assert_eq!(page.events[0].seq, start);
+        assert_eq!(page.events[0].category, EventCategory::Mount);
+        assert!(page.events[0].error.is_none());
+        assert_eq!(page.events[1].category, EventCategory::Umount);
+        assert!(page.events[1].error.is_some());
+        assert_eq!(page.next_seq, start + 2);
+        assert!(query(page.next_seq).events.is_empty());
+        assert_eq!(query(page.next_seq).next_seq, start + 2);
+
+        // Old events are dropped when the ring buffer is full.
+        for _ in 0..8 {
+            record::<(), String>(EventCategory::CacheGc, None, Value::Null, &Ok(()));
+        }
+        let page = query(start);
+        assert_eq!(page.events.len(), 4);
+        assert_eq!(page.events[0].seq, start + 6);
+        assert_eq!(page.next_seq, start + 10);
+
+        // Events are mirrored to the file, and the file gets rotated.
+        let line = serde_json::to_string(&page.events[0]).unwrap();
+        assert!(line.len() < 512);
+        let mut lines = 0;
+        for p in [path.clone(), rotated_path(&path, 1), rotated_path(&path, 2)].iter() {
+            if let Ok(content) = fs::read_to_string(p) {
+                assert!(content.len() <= 512);
+                for l in content.lines() {
+                    let v: Value = serde_json::from_str(l).unwrap();
+                    assert!(v["seq"].as_u64().unwrap() >= start);
+                    lines += 1;
+                }
+            }
+        }
+        assert!(rotated_path(&path, 1).exists());
+        assert!(!rotated_path(&path, 3).exists());
+        assert!(lines > 0 && lines <= 10);
+
+        configure(DEFAULT_CAPACITY, None).unwrap();
+    }
+}
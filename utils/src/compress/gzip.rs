@@ -0,0 +1,378 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Gzip decompression backends.
+//!
+//! Gzip compressed chunks may be decompressed by hardware accelerated libraries, such as ISA-L or
+//! zlib-ng, if they are enabled at compile time and supported by the CPU. The backend is selected
+//! once for the whole process, and the builtin implementation based on `flate2` is used as
+//! fallback.
+
+use std::fmt;
+use std::io::{Error, Read, Result};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const GZIP_BACKEND_UNSET: u8 = u8::MAX;
+
+static GZIP_BACKEND: AtomicU8 = AtomicU8::new(GZIP_BACKEND_UNSET);
+
+/// Backends to decompress gzip compressed data.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(u8)]
+pub enum GzipBackend {
+    /// Builtin implementation based on `flate2`.
+    Builtin = 0,
+    /// zlib-ng, enabled by the `gzip-zlib-ng` feature.
+    ZlibNg = 1,
+    /// Intel ISA-L, enabled by the `gzip-isal` feature.
+    Isal = 2,
+}
+
+impl GzipBackend {
+    /// All gzip decompression backends, in order of preference.
+    pub const ALL: [GzipBackend; 3] =
+        [GzipBackend::Isal, GzipBackend::ZlibNg, GzipBackend::Builtin];
+
+    /// Get name of the backend.
+    pub fn name(self) -> &'static str {
+        match self {
+            GzipBackend::Builtin => "builtin",
+            GzipBackend::ZlibNg => "zlib-ng",
+            GzipBackend::Isal => "isal",
+        }
+    }
+
+    /// Check whether the backend is enabled at compile time and supported by the CPU.
+    pub fn is_available(self) -> bool {
+        match self {
+            GzipBackend::Builtin => true,
+            GzipBackend::ZlibNg => cfg!(feature = "gzip-zlib-ng"),
+            GzipBackend::Isal => cfg!(feature = "gzip-isal") && isal_cpu_supported(),
+        }
+    }
+
+    /// Detect the preferred backend available on the host.
+    pub fn detect() -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|b| b.is_available())
+            .unwrap_or(GzipBackend::Builtin)
+    }
+
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(GzipBackend::Builtin),
+            1 => Some(GzipBackend::ZlibNg),
+            2 => Some(GzipBackend::Isal),
+            _ => None,
+        }
+    }
+}
+
+impl Default for GzipBackend {
+    fn default() -> Self {
+        GzipBackend::Builtin
+    }
+}
+
+impl fmt::Display for GzipBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for GzipBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "builtin" => Ok(GzipBackend::Builtin),
+            "zlib-ng" => Ok(GzipBackend::ZlibNg),
+            "isal" => Ok(GzipBackend::Isal),
+            _ => Err(einval!(
+                "gzip decompression backend should be auto, builtin, zlib-ng or isal"
+            )),
+        }
+    }
+}
+
+/// Parse gzip decompression backend from configuration, `None` for auto-detection.
+pub fn parse_gzip_backend(s: &str) -> Result<Option<GzipBackend>> {
+    match s {
+        "" | "auto" => Ok(None),
+        v => GzipBackend::from_str(v).map(Some),
+    }
+}
+
+/// Select the gzip decompression backend for the process, or auto-detect it if `backend` is
+/// `None`.
+///
+/// The backend may only be selected once, later selections are ignored. Unavailable backends
+/// fall back to the builtin implementation. The effective backend is returned.
+pub fn set_gzip_backend(backend: Option<GzipBackend>) -> GzipBackend {
+    let selected = match backend {
+        None => GzipBackend::detect(),
+        Some(b) if b.is_available() => b,
+        Some(b) => {
+            warn!(
+                "gzip decompression backend {} is unavailable, fall back to {}",
+                b,
+                GzipBackend::Builtin
+            );
+            GzipBackend::Builtin
+        }
+    };
+
+    match GZIP_BACKEND.compare_exchange(
+        GZIP_BACKEND_UNSET,
+        selected as u8,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    ) {
+        Ok(_) => {
+            info!("gzip decompression backend: {}", selected);
+            selected
+        }
+        Err(v) => {
+            let current = GzipBackend::from_u8(v).unwrap_or_default();
+            if backend.is_some() && current != selected {
+                warn!(
+                    "gzip decompression backend has been set to {}, ignore {}",
+                    current, selected
+                );
+            }
+            current
+        }
+    }
+}
+
+/// Get the gzip decompression backend of the process, auto-detected on first use if not set.
+pub fn gzip_backend() -> GzipBackend {
+    match GzipBackend::from_u8(GZIP_BACKEND.load(Ordering::Acquire)) {
+        Some(b) => b,
+        None => set_gzip_backend(None),
+    }
+}
+
+/// Decompress gzip compressed data to fill `dst` by the specified backend.
+///
+/// The gzip stream must decompress to exactly `dst.len()` bytes, otherwise an error is returned.
+/// Trailing data after the end of the gzip stream is ignored, so the compressed size may be
+/// estimated by `compute_compressed_gzip_size()`.
+pub fn decompress_with(src: &[u8], dst: &mut [u8], backend: GzipBackend) -> Result<usize> {
+    match backend {
+        GzipBackend::Builtin => builtin_decompress(src, dst),
+        #[cfg(feature = "gzip-zlib-ng")]
+        GzipBackend::ZlibNg => zlib_ng::decompress(src, dst),
+        #[cfg(feature = "gzip-isal")]
+        GzipBackend::Isal => isal_decompress(src, dst),
+        // Backends disabled at compile time fall back to the builtin implementation.
+        #[allow(unreachable_patterns)]
+        _ => builtin_decompress(src, dst),
+    }
+}
+
+fn builtin_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let mut gz = flate2::bufread::GzDecoder::new(src);
+    gz.read_exact(dst)?;
+    // Make sure the gzip stream ends here, which also verifies the gzip trailer.
+    let mut buf = [0u8; 1];
+    if gz.read(&mut buf)? != 0 {
+        return Err(eio!(format!(
+            "decompressed data is bigger than expected size {}",
+            dst.len()
+        )));
+    }
+    Ok(dst.len())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn isal_cpu_supported() -> bool {
+    is_x86_feature_detected!("sse4.1") && is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn isal_cpu_supported() -> bool {
+    std::arch::is_aarch64_feature_detected!("neon")
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn isal_cpu_supported() -> bool {
+    false
+}
+
+#[cfg(feature = "gzip-isal")]
+fn isal_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let size = isal::decompress_into(src, dst, isal::Codec::Gzip)
+        .map_err(|e| eio!(format!("isal: failed to decompress gzip data, {}", e)))?;
+    if size != dst.len() {
+        return Err(eio!(format!(
+            "isal: decompressed size {} doesn't match expected size {}",
+            size,
+            dst.len()
+        )));
+    }
+    Ok(size)
+}
+
+#[cfg(feature = "gzip-zlib-ng")]
+mod zlib_ng {
+    use std::io::Result;
+    use std::mem::size_of;
+    use std::os::raw::{c_int, c_void};
+    use std::ptr;
+
+    use libz_ng_sys::{inflate, inflateEnd, inflateInit2_, uInt, z_stream, zlibVersion};
+    use libz_ng_sys::{Z_FINISH, Z_OK, Z_STREAM_END};
+
+    // Accept gzip header only.
+    const GZIP_WINDOW_BITS: c_int = 15 + 16;
+
+    extern "C" fn zalloc(_opaque: *mut c_void, items: uInt, size: uInt) -> *mut c_void {
+        unsafe { libc::calloc(items as usize, size as usize) }
+    }
+
+    extern "C" fn zfree(_opaque: *mut c_void, address: *mut c_void) {
+        unsafe { libc::free(address) }
+    }
+
+    pub(super) fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+        if src.len() > uInt::MAX as usize || dst.len() > uInt::MAX as usize {
+            return Err(einval!("zlib-ng: buffer is too big"));
+        }
+
+        let mut stream = z_stream {
+            next_in: src.as_ptr() as *mut _,
+            avail_in: src.len() as uInt,
+            total_in: 0,
+            next_out: dst.as_mut_ptr(),
+            avail_out: dst.len() as uInt,
+            total_out: 0,
+            msg: ptr::null_mut(),
+            state: ptr::null_mut(),
+            zalloc,
+            zfree,
+            opaque: ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0,
+        };
+
+        // Safe because the stream is initialized and released in pair, and buffers referenced
+        // by the stream outlive it.
+        unsafe {
+            let ret = inflateInit2_(
+                &mut stream,
+                GZIP_WINDOW_BITS,
+                zlibVersion(),
+                size_of::<z_stream>() as c_int,
+            );
+            if ret != Z_OK {
+                return Err(eio!(format!("zlib-ng: failed to init inflate, {}", ret)));
+            }
+            let ret = inflate(&mut stream, Z_FINISH);
+            let avail_out = stream.avail_out;
+            inflateEnd(&mut stream);
+
+            // The gzip stream must end exactly when the output buffer is full.
+            match ret {
+                Z_STREAM_END if avail_out == 0 => Ok(dst.len()),
+                _ => Err(eio!(format!(
+                    "zlib-ng: failed to decompress gzip data, {}, {} bytes left",
+                    ret, avail_out
+                ))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // "hello, nydus\n" compressed by gzip(1).
+    const HELLO_GZ: [u8; 33] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0xd7, 0x51, 0xc8, 0xab, 0x4c, 0x29, 0x2d, 0xe6, 0x02, 0x00, 0x08, 0x51, 0x5b, 0x5c, 0x0d,
+        0x00, 0x00, 0x00,
+    ];
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(data).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn test_gzip_backend_parse() {
+        assert_eq!(parse_gzip_backend("").unwrap(), None);
+        assert_eq!(parse_gzip_backend("auto").unwrap(), None);
+        for backend in GzipBackend::ALL.iter() {
+            assert_eq!(parse_gzip_backend(backend.name()).unwrap(), Some(*backend));
+            assert_eq!(GzipBackend::from_u8(*backend as u8), Some(*backend));
+        }
+        assert!(parse_gzip_backend("zlib").is_err());
+        assert!(GzipBackend::Builtin.is_available());
+        assert!(GzipBackend::detect().is_available());
+    }
+
+    #[test]
+    fn test_gzip_backend_decompress() {
+        let mut vectors = vec![(b"hello, nydus\n".to_vec(), HELLO_GZ.to_vec())];
+        let zeros = vec![0u8; 0x10_0000];
+        vectors.push((zeros.clone(), gzip(&zeros)));
+        let text = (0..0x4000u32)
+            .flat_map(|v| format!("{:08x}", v.wrapping_mul(2654435761)).into_bytes())
+            .collect::<Vec<_>>();
+        vectors.push((text.clone(), gzip(&text)));
+
+        for backend in GzipBackend::ALL.iter().filter(|b| b.is_available()) {
+            for (data, compressed) in vectors.iter() {
+                let mut buf = vec![0u8; data.len()];
+                let size = decompress_with(compressed, &mut buf, *backend).unwrap();
+                assert_eq!(size, data.len());
+                assert_eq!(&buf, data, "backend {}", backend);
+
+                // Trailing data after the gzip stream is ignored.
+                let mut src = compressed.clone();
+                src.extend_from_slice(&[0u8; 128]);
+                let mut buf = vec![0u8; data.len()];
+                decompress_with(&src, &mut buf, *backend).unwrap();
+                assert_eq!(&buf, data, "backend {}", backend);
+
+                // Decompressed data doesn't fit into the buffer.
+                let mut buf = vec![0u8; data.len() - 1];
+                assert!(
+                    decompress_with(compressed, &mut buf, *backend).is_err(),
+                    "backend {}",
+                    backend
+                );
+                assert!(
+                    decompress_with(&src, &mut buf, *backend).is_err(),
+                    "backend {}",
+                    backend
+                );
+
+                // Truncated data can't fill the buffer.
+                let mut buf = vec![0u8; data.len()];
+                assert!(
+                    decompress_with(&compressed[..compressed.len() / 2], &mut buf, *backend)
+                        .is_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_gzip_backend_select() {
+        let backend = set_gzip_backend(Some(GzipBackend::Builtin));
+        assert_eq!(gzip_backend(), backend);
+        // The backend is selected once per process.
+        assert_eq!(set_gzip_backend(None), backend);
+        assert_eq!(set_gzip_backend(Some(GzipBackend::Isal)), backend);
+    }
+}
@@ -7,6 +7,12 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::io::{BufReader, Error, Read, Result, Write};
 use std::str::FromStr;
+use std::time::Instant;
+
+use crate::metrics::DecompressMetrics;
+
+mod gzip;
+pub use self::gzip::{gzip_backend, parse_gzip_backend, set_gzip_backend, GzipBackend};
 
 mod lz4_standard;
 use self::lz4_standard::*;
@@ -23,6 +29,7 @@ pub enum Algorithm {
     Lz4Block,
     GZip,
     Zstd,
+    Snappy,
 }
 
 impl Default for Algorithm {
@@ -46,7 +53,10 @@ impl FromStr for Algorithm {
             "lz4_block" => Ok(Self::Lz4Block),
             "gzip" => Ok(Self::GZip),
             "zstd" => Ok(Self::Zstd),
-            _ => Err(einval!("compression algorithm should be none or lz4_block")),
+            "snappy" => Ok(Self::Snappy),
+            _ => Err(einval!(
+                "compression algorithm should be none, lz4_block, gzip, zstd or snappy"
+            )),
         }
     }
 }
@@ -63,6 +73,8 @@ impl TryFrom<u32> for Algorithm {
             Ok(Algorithm::GZip)
         } else if value == Algorithm::Zstd as u32 {
             Ok(Algorithm::Zstd)
+        } else if value == Algorithm::Snappy as u32 {
+            Ok(Algorithm::Snappy)
         } else {
             Err(())
         }
@@ -81,6 +93,8 @@ impl TryFrom<u64> for Algorithm {
             Ok(Algorithm::GZip)
         } else if value == Algorithm::Zstd as u64 {
             Ok(Algorithm::Zstd)
+        } else if value == Algorithm::Snappy as u64 {
+            Ok(Algorithm::Snappy)
         } else {
             Err(())
         }
@@ -111,6 +125,7 @@ pub fn compress(src: &[u8], algorithm: Algorithm) -> Result<(Cow<[u8]>, bool)> {
             gz.finish()?
         }
         Algorithm::Zstd => zstd_compress(src)?,
+        Algorithm::Snappy => snappy_compress(src)?,
     };
 
     // Abandon compressed data when compression ratio greater than COMPRESSION_MINIMUM_RATIO
@@ -125,21 +140,34 @@ pub fn compress(src: &[u8], algorithm: Algorithm) -> Result<(Cow<[u8]>, bool)> {
 
 /// Decompress a source slice or file stream into destination slice, with provided compression algorithm.
 /// Use the file as decompress source if provided.
+///
+/// Gzip compressed data is decompressed by the gzip decompression backend of the process.
 pub fn decompress(src: &[u8], dst: &mut [u8], algorithm: Algorithm) -> Result<usize> {
-    match algorithm {
+    let begin = Instant::now();
+    let (decoder, ret) = match algorithm {
         Algorithm::None => {
             assert_eq!(src.len(), dst.len());
             dst.copy_from_slice(src);
-            Ok(dst.len())
+            return Ok(dst.len());
         }
-        Algorithm::Lz4Block => lz4_decompress(src, dst),
+        Algorithm::Lz4Block => ("lz4_block", lz4_decompress(src, dst)),
         Algorithm::GZip => {
-            let mut gz = flate2::bufread::GzDecoder::new(src);
-            gz.read_exact(dst)?;
-            Ok(dst.len())
+            let backend = gzip_backend();
+            let decoder = match backend {
+                GzipBackend::Builtin => "gzip-builtin",
+                GzipBackend::ZlibNg => "gzip-zlib-ng",
+                GzipBackend::Isal => "gzip-isal",
+            };
+            (decoder, gzip::decompress_with(src, dst, backend))
         }
-        Algorithm::Zstd => zstd::bulk::decompress_to_buffer(src, dst),
+        Algorithm::Zstd => ("zstd", zstd::bulk::decompress_to_buffer(src, dst)),
+        Algorithm::Snappy => ("snappy", snappy_decompress(src, dst)),
+    };
+
+    if let Ok(size) = ret {
+        DecompressMetrics::get(decoder).record(size, begin.elapsed());
     }
+    ret
 }
 
 /// Stream decoder for gzip/lz4/zstd.
@@ -156,8 +184,9 @@ impl<'a, R: Read> Decoder<'a, R> {
             Algorithm::GZip => {
                 Decoder::Gzip(flate2::bufread::GzDecoder::new(BufReader::new(reader)))
             }
-            Algorithm::Lz4Block => panic!("Decoder doesn't support lz4_block"),
+            Algorithm::Lz4Block => return Err(einval!("Decoder doesn't support lz4_block")),
             Algorithm::Zstd => Decoder::Zstd(zstd::stream::Decoder::new(reader)?),
+            Algorithm::Snappy => return Err(einval!("Decoder doesn't support snappy")),
         };
         Ok(decoder)
     }
@@ -245,6 +274,21 @@ fn zstd_compress(src: &[u8]) -> Result<Vec<u8>> {
     zstd::bulk::compress(src, zstd::DEFAULT_COMPRESSION_LEVEL)
 }
 
+// The Snappy framing format carries its own stream header and per-frame checksums, which are
+// unnecessary for chunks already framed by RAFS chunk information. So chunk data is compressed
+// by the raw (non-framed) Snappy API.
+fn snappy_compress(src: &[u8]) -> Result<Vec<u8>> {
+    snap::raw::Encoder::new()
+        .compress_vec(src)
+        .map_err(Error::from)
+}
+
+fn snappy_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    snap::raw::Decoder::new()
+        .decompress(src, dst)
+        .map_err(Error::from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -456,6 +500,84 @@ mod tests {
         assert_eq!(buf, decompressed);
     }
 
+    #[test]
+    fn test_snappy_compress_decompress() {
+        for size in [1usize, 16, 4095, 4096, 4097] {
+            let buf = vec![0x2u8; size];
+            let (compressed, is_compressed) = compress(&buf, Algorithm::Snappy).unwrap();
+            if size >= 4095 {
+                assert!(is_compressed);
+                // Raw snappy data starts with the uncompressed length instead of a stream header.
+                assert_eq!(snap::raw::decompress_len(&compressed).unwrap(), size);
+            }
+
+            let mut decompressed = vec![0; buf.len()];
+            let sz = snappy_decompress(&snappy_compress(&buf).unwrap(), &mut decompressed).unwrap();
+            assert_eq!(sz, size);
+            assert_eq!(buf, decompressed);
+        }
+
+        assert_eq!(Algorithm::from_str("snappy").unwrap(), Algorithm::Snappy);
+        assert_eq!(
+            Algorithm::try_from(Algorithm::Snappy as u32).unwrap(),
+            Algorithm::Snappy
+        );
+        let mut decompressed = vec![0; 16];
+        assert!(decompress(b"invalid data", &mut decompressed, Algorithm::Snappy).is_err());
+    }
+
+    // Benchmark comparing snappy and lz4_block on executables and text files, run with:
+    // cargo test --release bench_snappy_vs_lz4 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_snappy_vs_lz4() {
+        const CHUNK_SIZE: usize = 0x10_0000;
+
+        let mut data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        for entry in std::fs::read_dir(Path::new(root_dir).join("src")).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                data.extend_from_slice(&std::fs::read(path).unwrap());
+            }
+        }
+
+        for algo in [Algorithm::Lz4Block, Algorithm::Snappy] {
+            let mut compressed_size = 0;
+            let mut chunks = Vec::new();
+            let start = std::time::Instant::now();
+            for chunk in data.chunks(CHUNK_SIZE) {
+                let (buf, _) = compress(chunk, algo).unwrap();
+                compressed_size += buf.len();
+                chunks.push((buf.into_owned(), chunk.len()));
+            }
+            let compress_time = start.elapsed();
+
+            let start = std::time::Instant::now();
+            for _ in 0..10 {
+                for (buf, size) in chunks.iter() {
+                    let mut decompressed = vec![0u8; *size];
+                    if buf.len() == *size {
+                        decompressed.copy_from_slice(buf);
+                    } else {
+                        decompress(buf, &mut decompressed, algo).unwrap();
+                    }
+                }
+            }
+            let decompress_time = start.elapsed() / 10;
+
+            println!(
+                "{}: {} bytes compressed to {} bytes ({:.1}%), compress {:?}, decompress {:?}",
+                algo,
+                data.len(),
+                compressed_size,
+                compressed_size as f64 * 100.0 / data.len() as f64,
+                compress_time,
+                decompress_time
+            );
+        }
+    }
+
     #[test]
     fn test_new_decoder_none() {
         let buf = b"This is a test";
@@ -466,6 +588,13 @@ mod tests {
         assert_eq!(&buf2[0..14], buf.as_slice());
     }
 
+    #[test]
+    fn test_new_decoder_block_algorithms() {
+        let buf = b"This is a test";
+        assert!(Decoder::new(buf.as_slice(), Algorithm::Lz4Block).is_err());
+        assert!(Decoder::new(buf.as_slice(), Algorithm::Snappy).is_err());
+    }
+
     #[test]
     fn test_gzip_decoder() {
         let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
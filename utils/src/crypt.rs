@@ -0,0 +1,317 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encrypt and decrypt data chunks at rest.
+//!
+//! Each data chunk is encrypted with a per-image data key, after compression. The data key is
+//! wrapped by a master key and stored in the blob table, together with a reference to the master
+//! key, so the master key itself never lands in the image. An encrypted chunk is stored as
+//! `nonce || ciphertext || tag`, with a random nonce for each chunk.
+//!
+//! The AES256-GCM implementation is provided by OpenSSL, which is only available with the
+//! `encryption` feature. Without it, encryption algorithms and keys could still be handled, but
+//! encrypting, decrypting and generating keys fail with `ENOSYS`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Error, Result};
+use std::str::FromStr;
+
+#[cfg(feature = "encryption")]
+use openssl::rand::rand_bytes;
+#[cfg(feature = "encryption")]
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use sha2::{Digest, Sha256};
+
+/// Size of AES256-GCM keys.
+pub const AES256_GCM_KEY_LEN: usize = 32;
+/// Size of AES256-GCM nonces.
+pub const AES256_GCM_NONCE_LEN: usize = 12;
+/// Size of AES256-GCM authentication tags.
+pub const AES256_GCM_TAG_LEN: usize = 16;
+/// Size of the reference to a master key.
+pub const KEY_REF_LEN: usize = 8;
+/// Size of a data key wrapped by a master key.
+pub const WRAPPED_KEY_LEN: usize = AES256_GCM_NONCE_LEN + AES256_GCM_KEY_LEN + AES256_GCM_TAG_LEN;
+
+/// Supported encryption algorithms.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Algorithm {
+    None,
+    Aes256Gcm,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "aes256-gcm" => Ok(Self::Aes256Gcm),
+            _ => Err(einval!("encryption algorithm should be none or aes256-gcm")),
+        }
+    }
+}
+
+impl TryFrom<u32> for Algorithm {
+    type Error = ();
+
+    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
+        if value == Algorithm::None as u32 {
+            Ok(Algorithm::None)
+        } else if value == Algorithm::Aes256Gcm as u32 {
+            Ok(Algorithm::Aes256Gcm)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Algorithm {
+    /// Check whether the encryption algorithm is none.
+    pub fn is_none(self) -> bool {
+        self == Self::None
+    }
+
+    /// Get size of extra data added to each encrypted chunk.
+    pub fn overhead(self) -> usize {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Aes256Gcm => AES256_GCM_NONCE_LEN + AES256_GCM_TAG_LEN,
+        }
+    }
+
+    /// Encrypt data with the key.
+    pub fn encrypt(self, key: &CipherKey, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Algorithm::None => Ok(data.to_vec()),
+            Algorithm::Aes256Gcm => aes256_gcm_encrypt(key.as_bytes(), data),
+        }
+    }
+
+    /// Decrypt data with the key, and verify its integrity.
+    pub fn decrypt(self, key: &CipherKey, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Algorithm::None => Ok(data.to_vec()),
+            Algorithm::Aes256Gcm => aes256_gcm_decrypt(key.as_bytes(), data),
+        }
+    }
+}
+
+/// A secret key to encrypt/decrypt data, which won't be printed out.
+#[derive(Clone, Eq, PartialEq)]
+pub struct CipherKey(Vec<u8>);
+
+impl CipherKey {
+    /// Generate a random AES256 key.
+    pub fn generate() -> Result<Self> {
+        let mut key = vec![0u8; AES256_GCM_KEY_LEN];
+        random_bytes(&mut key)?;
+        Ok(CipherKey(key))
+    }
+
+    /// Create a key from raw bytes.
+    pub fn from_bytes(key: &[u8]) -> Result<Self> {
+        if key.len() != AES256_GCM_KEY_LEN {
+            return Err(einval!(format!(
+                "invalid key size {}, expect {}",
+                key.len(),
+                AES256_GCM_KEY_LEN
+            )));
+        }
+        Ok(CipherKey(key.to_vec()))
+    }
+
+    /// Create a key from a hex string.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(einval!("invalid hex string for key"));
+        }
+        let mut key = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            let v = u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| einval!("invalid hex string for key"))?;
+            key.push(v);
+        }
+        Self::from_bytes(&key)
+    }
+
+    /// Get raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for CipherKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CipherKey(<redacted>)")
+    }
+}
+
+/// Get the reference to a master key by its identifier.
+pub fn key_ref(key_id: &str) -> [u8; KEY_REF_LEN] {
+    let digest = Sha256::digest(key_id.as_bytes());
+    let mut key_ref = [0u8; KEY_REF_LEN];
+    key_ref.copy_from_slice(&digest[..KEY_REF_LEN]);
+    key_ref
+}
+
+/// Wrap a data key with a master key.
+pub fn wrap_key(master: &CipherKey, key: &CipherKey) -> Result<Vec<u8>> {
+    aes256_gcm_encrypt(master.as_bytes(), key.as_bytes())
+}
+
+/// Unwrap a data key with a master key.
+pub fn unwrap_key(master: &CipherKey, wrapped_key: &[u8]) -> Result<CipherKey> {
+    if wrapped_key.len() != WRAPPED_KEY_LEN {
+        return Err(einval!("invalid size of wrapped key"));
+    }
+    let key = aes256_gcm_decrypt(master.as_bytes(), wrapped_key)?;
+    CipherKey::from_bytes(&key)
+}
+
+#[cfg(feature = "encryption")]
+fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    rand_bytes(buf).map_err(|e| eother!(e))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn random_bytes(_buf: &mut [u8]) -> Result<()> {
+    Err(enosys!(
+        "nydus-utils is built without the encryption feature"
+    ))
+}
+
+#[cfg(feature = "encryption")]
+fn aes256_gcm_encrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; AES256_GCM_NONCE_LEN];
+    random_bytes(&mut nonce)?;
+    let mut tag = [0u8; AES256_GCM_TAG_LEN];
+    let encrypted = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        data,
+        &mut tag,
+    )
+    .map_err(|e| eother!(format!("failed to encrypt data, {}", e)))?;
+
+    let mut buf = Vec::with_capacity(nonce.len() + encrypted.len() + tag.len());
+    buf.extend_from_slice(&nonce);
+    buf.extend_from_slice(&encrypted);
+    buf.extend_from_slice(&tag);
+    Ok(buf)
+}
+
+#[cfg(feature = "encryption")]
+fn aes256_gcm_decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < AES256_GCM_NONCE_LEN + AES256_GCM_TAG_LEN {
+        return Err(einval!("encrypted data is too small"));
+    }
+    let (nonce, data) = data.split_at(AES256_GCM_NONCE_LEN);
+    let (data, tag) = data.split_at(data.len() - AES256_GCM_TAG_LEN);
+    decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), &[], data, tag)
+        .map_err(|_| eio!("failed to decrypt data, wrong key or corrupted data"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn aes256_gcm_encrypt(_key: &[u8], _data: &[u8]) -> Result<Vec<u8>> {
+    Err(enosys!(
+        "nydus-utils is built without the encryption feature"
+    ))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn aes256_gcm_decrypt(_key: &[u8], _data: &[u8]) -> Result<Vec<u8>> {
+    Err(enosys!(
+        "nydus-utils is built without the encryption feature"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher_key() {
+        let key = CipherKey::from_hex(&"0f".repeat(AES256_GCM_KEY_LEN)).unwrap();
+        assert_eq!(key.as_bytes(), &[0xfu8; AES256_GCM_KEY_LEN]);
+        assert_eq!(format!("{:?}", key), "CipherKey(<redacted>)");
+        assert!(CipherKey::from_hex("0f0f").is_err());
+        assert!(CipherKey::from_hex(&"zz".repeat(AES256_GCM_KEY_LEN)).is_err());
+        assert!(CipherKey::from_bytes(&[0u8; 16]).is_err());
+        assert_eq!(key_ref("kms://key1"), key_ref("kms://key1"));
+        assert_ne!(key_ref("kms://key1"), key_ref("kms://key2"));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypt_decrypt() {
+        let algo = Algorithm::Aes256Gcm;
+        let key = CipherKey::generate().unwrap();
+        let data = b"nydus encrypted chunk data";
+
+        let encrypted = algo.encrypt(&key, data).unwrap();
+        assert_eq!(encrypted.len(), data.len() + algo.overhead());
+        assert_ne!(&encrypted[AES256_GCM_NONCE_LEN..][..data.len()], &data[..]);
+        assert_eq!(algo.decrypt(&key, &encrypted).unwrap(), data);
+
+        let other = CipherKey::generate().unwrap();
+        assert!(algo.decrypt(&other, &encrypted).is_err());
+        let mut corrupted = encrypted.clone();
+        corrupted[AES256_GCM_NONCE_LEN] ^= 0x1;
+        assert!(algo.decrypt(&key, &corrupted).is_err());
+        assert!(algo.decrypt(&key, &encrypted[..8]).is_err());
+
+        assert_eq!(Algorithm::None.encrypt(&key, data).unwrap(), data);
+        assert_eq!(Algorithm::try_from(1u32).unwrap(), Algorithm::Aes256Gcm);
+        assert!(Algorithm::try_from(2u32).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_wrap_key() {
+        let master = CipherKey::generate().unwrap();
+        let key = CipherKey::generate().unwrap();
+        let wrapped = wrap_key(&master, &key).unwrap();
+        assert_eq!(wrapped.len(), WRAPPED_KEY_LEN);
+        assert_eq!(unwrap_key(&master, &wrapped).unwrap(), key);
+
+        let other = CipherKey::generate().unwrap();
+        assert!(unwrap_key(&other, &wrapped).is_err());
+        assert!(unwrap_key(&master, &wrapped[1..]).is_err());
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn test_encryption_unsupported() {
+        let key = CipherKey::from_bytes(&[0u8; AES256_GCM_KEY_LEN]).unwrap();
+        let data = b"nydus encrypted chunk data";
+        let enosys = |e: Error| e.raw_os_error() == Some(libc::ENOSYS);
+
+        assert!(enosys(CipherKey::generate().unwrap_err()));
+        assert!(enosys(
+            Algorithm::Aes256Gcm.encrypt(&key, data).unwrap_err()
+        ));
+        assert!(enosys(
+            Algorithm::Aes256Gcm.decrypt(&key, data).unwrap_err()
+        ));
+        assert!(enosys(wrap_key(&key, &key).unwrap_err()));
+        assert_eq!(Algorithm::None.decrypt(&key, data).unwrap(), data);
+    }
+}
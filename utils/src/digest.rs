@@ -4,7 +4,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Fast message digest algorithms for Rafs and Nydus, including Blake3 and SHA256.
+//!
+//! Besides the builtin algorithms defined by [Algorithm], more message digest algorithms may be
+//! provided by implementing the [DigestProvider] trait and registering them into a
+//! [DigestRegistry].
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::io::{Error, Read};
@@ -76,6 +81,95 @@ impl TryFrom<u64> for Algorithm {
     }
 }
 
+impl Algorithm {
+    /// Get the [DigestProvider] object for the builtin algorithm.
+    pub fn provider(self) -> &'static dyn DigestProvider {
+        match self {
+            Algorithm::Blake3 => &Algorithm::Blake3,
+            Algorithm::Sha256 => &Algorithm::Sha256,
+        }
+    }
+}
+
+/// Trait to provide a message digest algorithm.
+pub trait DigestProvider: Send + Sync {
+    /// Get name of the message digest algorithm.
+    fn name(&self) -> &'static str;
+
+    /// Compute message digest for the data.
+    fn digest(&self, data: &[u8]) -> RafsDigest;
+
+    /// Check whether the data matches the expected message digest.
+    fn verify(&self, data: &[u8], expected: &RafsDigest) -> bool {
+        &self.digest(data) == expected
+    }
+}
+
+impl DigestProvider for Algorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+
+    fn digest(&self, data: &[u8]) -> RafsDigest {
+        RafsDigest::from_buf(data, *self)
+    }
+}
+
+/// A registry to map algorithm names to message digest providers.
+///
+/// The builtin algorithms are always available, and third-party message digest algorithms may be
+/// registered by name.
+pub struct DigestRegistry {
+    providers: HashMap<&'static str, Box<dyn DigestProvider>>,
+}
+
+impl Default for DigestRegistry {
+    fn default() -> Self {
+        let mut registry = DigestRegistry {
+            providers: HashMap::new(),
+        };
+        for algo in [Algorithm::Blake3, Algorithm::Sha256] {
+            registry.providers.insert(algo.name(), Box::new(algo));
+        }
+        registry
+    }
+}
+
+impl DigestRegistry {
+    /// Create a registry with builtin message digest algorithms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a message digest provider, which must not conflict with registered ones.
+    pub fn register(&mut self, provider: Box<dyn DigestProvider>) -> std::io::Result<()> {
+        let name = provider.name();
+        if self.providers.contains_key(name) {
+            return Err(einval!(format!(
+                "digest algorithm {} has already been registered",
+                name
+            )));
+        }
+        self.providers.insert(name, provider);
+        Ok(())
+    }
+
+    /// Get the message digest provider by algorithm name.
+    pub fn get(&self, name: &str) -> Option<&dyn DigestProvider> {
+        self.providers.get(name).map(|p| p.as_ref())
+    }
+
+    /// Get names of all registered message digest algorithms.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self.providers.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+}
+
 pub trait DigestHasher {
     fn digest_update(&mut self, buf: &[u8]);
     fn digest_finalize(self) -> RafsDigest;
@@ -225,6 +319,53 @@ mod test {
         Algorithm::from_str("SHA256").unwrap_err();
     }
 
+    #[test]
+    fn test_digest_provider() {
+        let text = b"The quick brown fox jumps over the lazy dog";
+        let provider = Algorithm::Sha256.provider();
+        assert_eq!(provider.name(), "sha256");
+        let digest = provider.digest(text);
+        assert_eq!(digest, RafsDigest::from_buf(text, Algorithm::Sha256));
+        assert!(provider.verify(text, &digest));
+        assert!(!provider.verify(b"The quick brown fox", &digest));
+        assert_eq!(Algorithm::Blake3.provider().name(), "blake3");
+    }
+
+    struct XorDigest;
+
+    impl DigestProvider for XorDigest {
+        fn name(&self) -> &'static str {
+            "xor"
+        }
+
+        fn digest(&self, data: &[u8]) -> RafsDigest {
+            let mut digest = RafsDigest::default();
+            for (idx, v) in data.iter().enumerate() {
+                digest.data[idx % RAFS_DIGEST_LENGTH] ^= v;
+            }
+            digest
+        }
+    }
+
+    #[test]
+    fn test_digest_registry() {
+        let mut registry = DigestRegistry::new();
+        assert_eq!(registry.names(), vec!["blake3", "sha256"]);
+        assert!(registry.get("xor").is_none());
+        assert!(registry.register(Box::new(Algorithm::Sha256)).is_err());
+
+        registry.register(Box::new(XorDigest)).unwrap();
+        assert!(registry.register(Box::new(XorDigest)).is_err());
+        let provider = registry.get("xor").unwrap();
+        let digest = provider.digest(b"nydus");
+        assert!(provider.verify(b"nydus", &digest));
+        assert!(!provider.verify(b"rafs", &digest));
+        assert_eq!(
+            registry.get("blake3").unwrap().digest(b"nydus"),
+            RafsDigest::from_buf(b"nydus", Algorithm::Blake3)
+        );
+    }
+
     #[test]
     fn test_hash_from_buf() {
         let text = b"The quick brown fox jumps over the lazy dog";
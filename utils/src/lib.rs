@@ -22,14 +22,17 @@ pub use self::inode_bitmap::InodeBitmap;
 pub use self::types::*;
 
 pub mod async_helper;
+pub mod audit;
 pub mod compact;
 pub mod compress;
+pub mod crypt;
 pub mod digest;
 pub mod exec;
 pub mod filemap;
 pub mod inode_bitmap;
 pub mod metrics;
 pub mod mpmc;
+pub mod trace;
 pub mod types;
 
 /// Round up and divide the value `n` by `d`.
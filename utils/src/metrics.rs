@@ -8,9 +8,10 @@
 //! - Global error events of type [`ErrorHolder`]
 //! - Storage backend metrics of type ['BackendMetrics']
 //! - Blobcache metrics of type ['BlobcacheMetrics']
+//! - Decompression metrics of type ['DecompressMetrics']
 //! - Filesystem metrics of type ['FsIoStats`], supported by Rafs in fuse/virtiofs only.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::{Deref, Drop};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
@@ -106,6 +107,11 @@ lazy_static! {
         Default::default();
 }
 
+lazy_static! {
+    static ref DECOMPRESS_METRICS: RwLock<BTreeMap<&'static str, Arc<DecompressMetrics>>> =
+        Default::default();
+}
+
 lazy_static! {
     pub static ref ERROR_HOLDER: Arc<Mutex<ErrorHolder>> =
         Arc::new(Mutex::new(ErrorHolder::new(500, 50 * 1024)));
@@ -210,6 +216,16 @@ pub struct FsIoStats {
     fop_hits: [BasicMetric; StatsFop::Max as usize],
     // Counters for failed file operations.
     fop_errors: [BasicMetric; StatsFop::Max as usize],
+    // Counters for kernel cache invalidations sent after updating filesystem metadata.
+    invalidated_entries: BasicMetric,
+    invalidated_inodes: BasicMetric,
+    // Number of opened files classified as accessed sequentially or randomly for readahead.
+    sequential_files: BasicMetric,
+    random_files: BasicMetric,
+    // Bytes of file data prefetched on advice from applications, and advices dropped for
+    // exceeding the prefetch budget.
+    advised_prefetch_bytes: BasicMetric,
+    throttled_advices: BasicMetric,
 
     // Cumulative latency's life cycle is equivalent to Rafs, unlike incremental
     // latency which will be cleared each time dumped. Unit as micro-seconds.
@@ -352,6 +368,39 @@ impl FsIoStats {
         }
     }
 
+    /// Record number of kernel directory entry and inode cache invalidations sent.
+    pub fn record_invalidations(&self, entries: u64, inodes: u64) {
+        self.invalidated_entries.add(entries);
+        self.invalidated_inodes.add(inodes);
+    }
+
+    /// Count an opened file as accessed sequentially or randomly.
+    pub fn access_pattern_inc(&self, sequential: bool) {
+        if sequential {
+            self.sequential_files.inc();
+        } else {
+            self.random_files.inc();
+        }
+    }
+
+    /// Stop counting an opened file as accessed sequentially or randomly.
+    pub fn access_pattern_dec(&self, sequential: bool) {
+        if sequential {
+            self.sequential_files.dec();
+        } else {
+            self.random_files.dec();
+        }
+    }
+
+    /// Record an advice to prefetch `bytes` of file data, which may be throttled.
+    pub fn record_advice(&self, bytes: u64, throttled: bool) {
+        if throttled {
+            self.throttled_advices.inc();
+        } else {
+            self.advised_prefetch_bytes.add(bytes);
+        }
+    }
+
     /// Mark starting of filesystem operation.
     pub fn latency_start(&self) -> Option<SystemTime> {
         if !self.measure_latency.load(Ordering::Relaxed) {
@@ -559,6 +608,16 @@ pub fn export_blobcache_metrics(id: &Option<String>) -> IoStatsResult<String> {
     }
 }
 
+/// Export decompression metrics, together with the gzip decompression backend in use.
+pub fn export_decompress_metrics() -> IoStatsResult<String> {
+    let metrics = DECOMPRESS_METRICS.read().unwrap();
+    let output = serde_json::json!({
+        "gzip_backend": crate::compress::gzip_backend().name(),
+        "decoders": metrics.deref(),
+    });
+    serde_json::to_string(&output).map_err(MetricsError::Serialize)
+}
+
 /// Export global error events.
 pub fn export_events() -> IoStatsResult<String> {
     serde_json::to_string(ERROR_HOLDER.lock().unwrap().deref()).map_err(MetricsError::Serialize)
@@ -620,6 +679,16 @@ pub struct BackendMetrics {
     read_count_block_size_dist: [BasicMetric; BLOCK_READ_SIZES_MAX],
     // Categorize metrics as per their latency and request size
     read_latency_sizes_dist: [[BasicMetric; READ_LATENCY_RANGE_MAX]; BLOCK_READ_SIZES_MAX],
+    // Cumulative count of authorization token refreshes triggered by expired tokens
+    auth_refreshes: BasicMetric,
+    // Generation of the credential, increased each time the credential file is reloaded
+    credential_generation: BasicMetric,
+    // Cumulative count of requests served by failover mirrors instead of the primary server
+    mirror_failovers: BasicMetric,
+    // Whether the storage backend is healthy according to the latest health check
+    healthy: AtomicBool,
+    // Cumulative count of health state changes detected by health checks
+    health_state_changes: BasicMetric,
 }
 
 impl BackendMetrics {
@@ -628,6 +697,7 @@ impl BackendMetrics {
         let backend_metrics = Arc::new(Self {
             id: id.to_string(),
             backend_type: backend_type.to_string(),
+            healthy: AtomicBool::new(true),
             ..Default::default()
         });
 
@@ -674,6 +744,35 @@ impl BackendMetrics {
         }
     }
 
+    /// Record an authorization token refresh caused by an expired token.
+    pub fn auth_refreshed(&self) {
+        self.auth_refreshes.inc();
+    }
+
+    /// Record a credential reload from the credential file.
+    pub fn credential_reloaded(&self) {
+        self.credential_generation.inc();
+    }
+
+    /// Record a request served by failover mirror instead of the primary server.
+    pub fn mirror_failed_over(&self) {
+        self.mirror_failovers.inc();
+    }
+
+    /// Check whether the storage backend is healthy according to the latest health check.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Record result of a health check, and return whether the health state has changed.
+    pub fn set_healthy(&self, healthy: bool) -> bool {
+        let changed = self.healthy.swap(healthy, Ordering::Relaxed) != healthy;
+        if changed {
+            self.health_state_changes.inc();
+        }
+        changed
+    }
+
     fn export_metrics(&self) -> IoStatsResult<String> {
         serde_json::to_string(self).map_err(MetricsError::Serialize)
     }
@@ -702,6 +801,40 @@ fn saturating_duration_micros(d: &Duration) -> u64 {
     }
 }
 
+/// Throughput metrics of a decoder to decompress chunk data, such as "zstd" or "gzip-isal".
+#[derive(Debug, Default, Serialize)]
+pub struct DecompressMetrics {
+    // Cumulative count of decompressed chunks.
+    pub count: BasicMetric,
+    // Cumulative amount of decompressed data in unit of Byte.
+    pub amount_total: BasicMetric,
+    // Cumulative time spent on decompression in unit of microsecond.
+    pub cumulative_latency_micros_total: BasicMetric,
+}
+
+impl DecompressMetrics {
+    /// Get the [`DecompressMetrics`] object for decoder `name`, created on first use.
+    pub fn get(name: &'static str) -> Arc<Self> {
+        if let Some(m) = DECOMPRESS_METRICS.read().unwrap().get(name) {
+            return m.clone();
+        }
+        DECOMPRESS_METRICS
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// Record that `size` bytes of data have been decompressed in `elapsed` time.
+    pub fn record(&self, size: usize, elapsed: Duration) {
+        self.count.inc();
+        self.amount_total.add(size as u64);
+        self.cumulative_latency_micros_total
+            .add(elapsed.as_micros() as u64);
+    }
+}
+
 #[derive(Debug, Default, Serialize)]
 pub struct BlobcacheMetrics {
     #[serde(skip_serializing, skip_deserializing)]
@@ -728,6 +861,14 @@ pub struct BlobcacheMetrics {
     pub prefetch_unmerged_chunks: BasicMetric,
     pub buffered_backend_size: BasicMetric,
     pub data_all_ready: AtomicBool,
+    // Bytes of chunk data held by the in-memory blob cache, and its configured budget.
+    pub memory_usage: BasicMetric,
+    pub memory_capacity: BasicMetric,
+    // How many chunks have been evicted from the in-memory blob cache under memory pressure.
+    pub evicted_chunks: BasicMetric,
+    // How many backend fetches are avoided by waiting for an inflight fetch of the same chunk
+    // issued by another blob cache sharing the same cache file.
+    pub suppressed_fetches: BasicMetric,
 }
 
 impl BlobcacheMetrics {
@@ -812,4 +953,20 @@ mod tests {
         g.fop_update(StatsFop::Read, 2015520, true);
         assert_eq!(g.block_count_read[3].count(), 2);
     }
+
+    #[test]
+    fn test_decompress_metrics() {
+        let m = DecompressMetrics::get("test-decoder");
+        m.record(4096, Duration::from_micros(10));
+        m.record(8192, Duration::from_micros(20));
+        let m = DecompressMetrics::get("test-decoder");
+        assert_eq!(m.count.count(), 2);
+        assert_eq!(m.amount_total.count(), 12288);
+        assert_eq!(m.cumulative_latency_micros_total.count(), 30);
+
+        let output = export_decompress_metrics().unwrap();
+        let v: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert!(v["gzip_backend"].is_string());
+        assert_eq!(v["decoders"]["test-decoder"]["amount_total"], 12288);
+    }
 }
@@ -5,6 +5,7 @@
 //! Asynchronous Multi-Producer Multi-Consumer channel.
 //!
 //! This module provides an asynchronous multi-producer multi-consumer channel based on [tokio::sync::Notify].
+//! Messages sent with low priority are only received when there are no normal messages pending.
 
 use std::collections::VecDeque;
 use std::io::{Error, ErrorKind, Result};
@@ -17,6 +18,7 @@ pub struct Channel<T> {
     closed: AtomicBool,
     notifier: Notify,
     requests: Mutex<VecDeque<T>>,
+    low_priority_requests: Mutex<VecDeque<T>>,
 }
 
 impl<T> Default for Channel<T> {
@@ -32,6 +34,7 @@ impl<T> Channel<T> {
             closed: AtomicBool::new(false),
             notifier: Notify::new(),
             requests: Mutex::new(VecDeque::new()),
+            low_priority_requests: Mutex::new(VecDeque::new()),
         }
     }
 
@@ -54,9 +57,26 @@ impl<T> Channel<T> {
         }
     }
 
+    /// Send a message to the channel, which is received after all normal messages.
+    ///
+    /// The message object will be returned on error, to ease the lifecycle management.
+    pub fn send_low_priority(&self, msg: T) -> std::result::Result<(), T> {
+        if self.closed.load(Ordering::Acquire) {
+            Err(msg)
+        } else {
+            self.low_priority_requests.lock().unwrap().push_back(msg);
+            self.notifier.notify_one();
+            Ok(())
+        }
+    }
+
     /// Try to receive a message from the channel.
     pub fn try_recv(&self) -> Option<T> {
-        self.requests.lock().unwrap().pop_front()
+        // Always lock normal requests before low priority requests to avoid deadlock.
+        let mut requests = self.requests.lock().unwrap();
+        requests
+            .pop_front()
+            .or_else(|| self.low_priority_requests.lock().unwrap().pop_front())
     }
 
     /// Receive message from the channel in asynchronous mode.
@@ -91,7 +111,9 @@ impl<T> Channel<T> {
     where
         F: FnMut(&T) -> bool,
     {
-        self.requests.lock().unwrap().retain(|t| !f(t));
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|t| !f(t));
+        self.low_priority_requests.lock().unwrap().retain(|t| !f(t));
     }
 
     /// Lock the channel to block all queue operations.
@@ -123,12 +145,31 @@ mod tests {
         channel.send(2u32).unwrap_err();
     }
 
+    #[test]
+    fn test_low_priority_channel() {
+        let channel = Channel::new();
+
+        channel.send_low_priority(1u32).unwrap();
+        channel.send(2u32).unwrap();
+        channel.send_low_priority(3u32).unwrap();
+        channel.send(4u32).unwrap();
+        assert_eq!(channel.try_recv().unwrap(), 2);
+        assert_eq!(channel.try_recv().unwrap(), 4);
+        assert_eq!(channel.try_recv().unwrap(), 1);
+        assert_eq!(channel.try_recv().unwrap(), 3);
+        assert!(channel.try_recv().is_none());
+
+        channel.close();
+        channel.send_low_priority(5u32).unwrap_err();
+    }
+
     #[test]
     fn test_flush_channel() {
         let channel = Channel::new();
 
         channel.send(1u32).unwrap();
         channel.send(2u32).unwrap();
+        channel.send_low_priority(3u32).unwrap();
         channel.flush_pending_prefetch_requests(|_| true);
         assert!(channel.try_recv().is_none());
 
@@ -0,0 +1,201 @@
+// Copyright (C) 2023 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Request level tracing to diagnose slow IO requests.
+//!
+//! A tracing context with an unique id is created for each FUSE request when tracing is enabled,
+//! and then propagated to blob caches and storage backends. Time spent by each stage is recorded
+//! into the context, and a log record with target `nydusd::trace` is emitted when the request
+//! takes longer than the configured latency threshold.
+//!
+//! Tracing is disabled by default, and the overhead is a single atomic load when disabled.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Log target for request tracing records.
+pub const TRACE_LOG_TARGET: &str = "nydusd::trace";
+
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE_THRESHOLD_MS: AtomicU64 = AtomicU64::new(0);
+static NEXT_TRACE_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT_TRACE: RefCell<Option<Arc<RequestTrace>>> = RefCell::new(None);
+}
+
+/// Enable or disable request tracing.
+///
+/// Requests taking longer than `threshold_ms` milliseconds will be logged, and `0` means logging
+/// all traced requests.
+pub fn configure(enable: bool, threshold_ms: u64) {
+    TRACE_THRESHOLD_MS.store(threshold_ms, Ordering::Relaxed);
+    TRACE_ENABLED.store(enable, Ordering::Relaxed);
+}
+
+/// Get current tracing configuration as `(enabled, threshold_ms)`.
+pub fn config() -> (bool, u64) {
+    (
+        TRACE_ENABLED.load(Ordering::Relaxed),
+        TRACE_THRESHOLD_MS.load(Ordering::Relaxed),
+    )
+}
+
+/// Check whether request tracing is enabled.
+#[inline]
+pub fn is_enabled() -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Get a timestamp to measure a stage if request tracing is enabled.
+#[inline]
+pub fn stage_begin() -> Option<Instant> {
+    if is_enabled() {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Record time spent by `stage` into the tracing context of current thread.
+pub fn record_current(stage: &'static str, begin: Option<Instant>) {
+    if let Some(begin) = begin {
+        CURRENT_TRACE.with(|t| {
+            if let Some(trace) = t.borrow().as_ref() {
+                trace.record(stage, begin);
+            }
+        });
+    }
+}
+
+/// Tracing context for a request.
+///
+/// The request gets logged when the last reference to the tracing context is dropped.
+#[derive(Debug)]
+pub struct RequestTrace {
+    id: u64,
+    op: &'static str,
+    ino: u64,
+    start: Instant,
+    stages: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl RequestTrace {
+    /// Start tracing a request, return `None` if request tracing is disabled.
+    #[inline]
+    pub fn start(op: &'static str, ino: u64) -> Option<Arc<RequestTrace>> {
+        if !is_enabled() {
+            return None;
+        }
+
+        Some(Arc::new(RequestTrace {
+            id: NEXT_TRACE_ID.fetch_add(1, Ordering::Relaxed),
+            op,
+            ino,
+            start: Instant::now(),
+            stages: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Get the unique id of the request.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record time spent by `stage`, which started at `begin`.
+    pub fn record(&self, stage: &'static str, begin: Instant) {
+        self.stages.lock().unwrap().push((stage, begin.elapsed()));
+    }
+
+    /// Set the tracing context as the current context of the calling thread.
+    ///
+    /// The previous context is restored when the returned guard is dropped.
+    pub fn enter(self: &Arc<Self>) -> RequestTraceGuard {
+        let prev = CURRENT_TRACE.with(|t| t.borrow_mut().replace(self.clone()));
+        RequestTraceGuard { prev }
+    }
+
+    fn format(&self, elapsed: Duration) -> String {
+        let mut msg = format!(
+            "id={} op={} ino={} latency_us={} stages=",
+            self.id,
+            self.op,
+            self.ino,
+            elapsed.as_micros()
+        );
+        for (idx, (stage, duration)) in self.stages.lock().unwrap().iter().enumerate() {
+            if idx > 0 {
+                msg.push(',');
+            }
+            let _ = write!(msg, "{}:{}", stage, duration.as_micros());
+        }
+        msg
+    }
+}
+
+impl Drop for RequestTrace {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let threshold = TRACE_THRESHOLD_MS.load(Ordering::Relaxed);
+        if elapsed >= Duration::from_millis(threshold) {
+            info!(target: TRACE_LOG_TARGET, "{}", self.format(elapsed));
+        }
+    }
+}
+
+/// Guard object to restore the previous tracing context of current thread.
+pub struct RequestTraceGuard {
+    prev: Option<Arc<RequestTrace>>,
+}
+
+impl Drop for RequestTraceGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT_TRACE.with(|t| *t.borrow_mut() = prev);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_trace() {
+        configure(false, 10);
+        assert_eq!(config(), (false, 10));
+        assert!(RequestTrace::start("read", 1).is_none());
+        assert!(stage_begin().is_none());
+
+        configure(true, 0);
+        let t1 = RequestTrace::start("read", 1).unwrap();
+        let t2 = RequestTrace::start("read", 2).unwrap();
+        assert!(t2.id() > t1.id());
+
+        t1.record("alloc_bio_vecs", Instant::now());
+        {
+            let _guard = t1.enter();
+            {
+                let _guard = t2.enter();
+                record_current("cache_read", stage_begin());
+            }
+            record_current("backend_read", stage_begin());
+        }
+        record_current("backend_read", stage_begin());
+
+        let msg = t1.format(Duration::from_micros(5));
+        assert!(msg.starts_with(&format!(
+            "id={} op=read ino=1 latency_us=5 stages=",
+            t1.id()
+        )));
+        assert!(msg.contains("alloc_bio_vecs:"));
+        assert!(msg.contains(",backend_read:"));
+        assert!(!msg.contains("cache_read"));
+        assert_eq!(t2.stages.lock().unwrap().len(), 1);
+        assert!(CURRENT_TRACE.with(|t| t.borrow().is_none()));
+        configure(false, 0);
+    }
+}